@@ -0,0 +1,73 @@
+//! Benchmarks concurrent mem-table reads, with 8 reader threads scanning and getting keys while
+//! a writer inserts. Run with `cargo bench --bench memtable`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use featherdb::storage::kv::lsm_tree::memtable::MemTable;
+use featherdb::storage::kv::Range;
+
+const KEYS: u32 = 10_000;
+const READERS: usize = 8;
+const READS: u32 = 1_000;
+
+fn setup() -> Arc<MemTable> {
+    let memtable = Arc::new(MemTable::create());
+    for i in 0..KEYS {
+        memtable.set(&i.to_be_bytes(), vec![0; 64]);
+    }
+    memtable
+}
+
+/// Runs READERS threads doing READS reads each, optionally with a concurrent writer.
+fn read_concurrently(memtable: &Arc<MemTable>, with_writer: bool, read: fn(&MemTable, u32)) {
+    let done = Arc::new(AtomicBool::new(false));
+    let writer = with_writer.then(|| {
+        let (memtable, done) = (memtable.clone(), done.clone());
+        std::thread::spawn(move || {
+            let mut i = 0;
+            while !done.load(Ordering::Relaxed) {
+                memtable.set(&(i % KEYS).to_be_bytes(), vec![1; 64]);
+                i += 1;
+            }
+        })
+    });
+    let readers = (0..READERS)
+        .map(|_| {
+            let memtable = memtable.clone();
+            std::thread::spawn(move || (0..READS).for_each(|i| read(&memtable, i)))
+        })
+        .collect::<Vec<_>>();
+    readers.into_iter().for_each(|reader| reader.join().unwrap());
+    done.store(true, Ordering::Relaxed);
+    writer.into_iter().for_each(|writer| writer.join().unwrap());
+}
+
+fn get(memtable: &MemTable, i: u32) {
+    memtable.get(&(i * 7 % KEYS).to_be_bytes()).unwrap();
+}
+
+/// A short scan of 10 keys, which must not cost the whole range.
+fn scan(memtable: &MemTable, i: u32) {
+    let start = (i * 7 % KEYS).to_be_bytes().to_vec();
+    assert!(memtable.scan(Range::from(start..)).take(10).count() > 0);
+}
+
+fn bench_concurrent_reads(c: &mut Criterion) {
+    let memtable = setup();
+    let mut group = c.benchmark_group("memtable_concurrent_reads");
+    group.throughput(Throughput::Elements((READERS as u32 * READS) as u64));
+    for (name, read) in [("get", get as fn(&MemTable, u32)), ("scan", scan)] {
+        for with_writer in [false, true] {
+            let id = BenchmarkId::new(name, if with_writer { "writer" } else { "no_writer" });
+            group.bench_with_input(id, &with_writer, |b, &with_writer| {
+                b.iter(|| read_concurrently(&memtable, with_writer, read))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_concurrent_reads);
+criterion_main!(benches);
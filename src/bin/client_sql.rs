@@ -64,7 +64,7 @@ impl FeatherClient {
         while let Some(input) = self.prompt()? {
             match self.execute(&input).await {
                 Ok(_) => {},
-                err @ Err(Error::Internal(_)) => return err,
+                err @ Err(Error::Internal(_) | Error::IoError(_)) => return err,
                 Err(err) => {
                     let msg = err.to_string();
                     let chunks = msg.split(" ").collect::<Vec<_>>();
@@ -126,7 +126,14 @@ impl FeatherClient {
                     ResultSet::Update { count } => println!("  Updated {} rows", count),
                     ResultSet::CreateTable { name } => println!("  Created table {}", name),
                     ResultSet::DropTable { name } => println!("  Dropped table {}", name),
+                    ResultSet::AlterTable { name } => println!("  Altered table {}", name),
+                    ResultSet::Analyze { name, rows } => {
+                        println!("  Analyzed table {} ({} rows)", name, rows)
+                    }
+                    ResultSet::LoadExtension { name } => println!("  Loaded extension {}", name),
+                    ResultSet::DropExtension { name } => println!("  Dropped extension {}", name),
                     ResultSet::Explain(plan) => println!("{}", plan.to_string()),
+                    ResultSet::ExplainAnalyze(plan) => println!("{}", plan),
                     ResultSet::Query { columns, buffered_rows, .. } => {
                         if self.show_headers {
                             println!(
@@ -19,7 +19,7 @@ async fn main() -> Result<()> {
         .add_service(FeatherDbServer::new(server))
         .serve(config.serve_addr.parse()?)
         .await
-        .or_else(|e| Err(Error::Internal(format!("FeatherDB server failed: {:?}", e))))
+        .map_err(|e| Error::Internal(format!("FeatherDB server failed: {:?}", e)))
 }
 
 #[derive(Debug, Deserialize)]
@@ -1,6 +1,6 @@
 use featherdb::error::{Error, Result};
 use featherdb::proto::featherkv::FeatherKvServer;
-use featherdb::{concurrency, FeatherKV, sql, storage};
+use featherdb::{concurrency, raft, FeatherKV, sql, storage};
 use tempfile::tempdir;
 use tonic::transport::Server;
 use serde::Deserialize;
@@ -15,6 +15,9 @@ async fn main() -> Result<()> {
 
     let log_store: Box<dyn storage::log::LogStore> = match config.storage_log.as_str() {
         "memory" => Box::new(storage::log::Memory::new()),
+        "bitcask" => Box::new(storage::log::KvLog::open(Box::new(
+            storage::kv::BitcaskStore::open(std::path::Path::new(&config.data_dir).join("log"))?,
+        ))?),
         name => return Err(Error::Config(format!("Unknown log storage engine {}", name))),
     };
 
@@ -32,7 +35,19 @@ async fn main() -> Result<()> {
         )?
     );
 
-    let server = FeatherKV::new(config.id, config.peers.clone(), state, log_store).await?;
+    let raft_config = raft::RaftConfig {
+        follower: raft::FollowerConfig { proxy_requests: config.proxy_requests },
+        ..Default::default()
+    };
+    let server = FeatherKV::with_config(
+        config.id,
+        config.peers.clone(),
+        config.client_addrs.clone(),
+        state,
+        log_store,
+        raft_config,
+    )
+    .await?;
 
     println!("FeatherKV server listening on {}...", config.serve_addr.clone());
 
@@ -48,6 +63,8 @@ struct Config {
     id: u64,
     peers: Vec<String>,
     serve_addr: String,
+    proxy_requests: bool,
+    client_addrs: Vec<String>,
     // log_level: String,
     data_dir: String,
     // sync: bool,
@@ -61,6 +78,8 @@ impl Config {
             .set_default("id", 0)?
             .set_default("peers", Vec::<String>::new())?
             .set_default("serve_addr", String::new())?
+            .set_default("proxy_requests", true)?
+            .set_default("client_addrs", Vec::<String>::new())?
             // .set_default("log_level", "info")?
             .set_default("data_dir", "/var/lib/toydb")?
             // .set_default("sync", true)?
@@ -0,0 +1,78 @@
+pub mod pool;
+
+pub use pool::{ConnectionPool, PoolConfig, PooledConnection};
+
+use std::net::SocketAddr;
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::error::{Error, Result};
+use crate::server::{deserialize, serialize, ClientRequest, ClientResponse};
+use crate::sql::execution::ResultSet;
+
+/// A client for the TCP server, see server::tcp::Server. Requests are answered in order, and
+/// transactions begun by the client span its subsequent requests until committed or rolled
+/// back.
+pub struct Client {
+    framed: Framed<TcpStream, LengthDelimitedCodec>,
+}
+
+impl Client {
+    /// Connects to a server.
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        let socket = TcpStream::connect(addr).await?;
+        Ok(Self { framed: Framed::new(socket, LengthDelimitedCodec::new()) })
+    }
+
+    /// Sends a request to the server and waits for its response.
+    async fn call(&mut self, request: ClientRequest) -> Result<ClientResponse> {
+        self.framed.send(serialize(&request)?.into()).await?;
+        match self.framed.next().await {
+            Some(frame) => deserialize::<Result<ClientResponse>>(&frame?)?,
+            None => Err(Error::Internal("Server closed the connection".into())),
+        }
+    }
+
+    /// Sends a request expected to return a result set.
+    async fn query(&mut self, request: ClientRequest) -> Result<ResultSet> {
+        match self.call(request).await? {
+            ClientResponse::Query(result_set) => Ok(result_set),
+            response => Err(Error::Internal(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Executes an SQL statement.
+    pub async fn execute(&mut self, query: &str) -> Result<ResultSet> {
+        self.query(ClientRequest::Query(query.into())).await
+    }
+
+    /// Begins a read-write transaction.
+    pub async fn begin(&mut self) -> Result<ResultSet> {
+        self.query(ClientRequest::Begin).await
+    }
+
+    /// Commits the current transaction.
+    pub async fn commit(&mut self) -> Result<ResultSet> {
+        self.query(ClientRequest::Commit).await
+    }
+
+    /// Rolls back the current transaction.
+    pub async fn rollback(&mut self) -> Result<ResultSet> {
+        self.query(ClientRequest::Rollback).await
+    }
+
+    /// Lists the names of the tables.
+    pub async fn list_tables(&mut self) -> Result<Vec<String>> {
+        match self.call(ClientRequest::ListTables).await? {
+            ClientResponse::ListTables(names) => Ok(names),
+            response => Err(Error::Internal(format!("Unexpected response {:?}", response))),
+        }
+    }
+
+    /// Checks that the connection is alive, with a cheap read-only request.
+    pub async fn ping(&mut self) -> Result<()> {
+        self.list_tables().await.map(|_| ())
+    }
+}
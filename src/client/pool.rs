@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+use super::Client;
+use crate::error::{Error, Result};
+
+/// Connection pool configuration.
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// The maximum number of open connections, idle or in use.
+    pub max_connections: usize,
+    /// The number of idle connections kept open, even once they exceed the idle timeout.
+    pub min_idle: usize,
+    /// How long acquire() waits for a connection, including connecting to the server.
+    pub connection_timeout: Duration,
+    /// How long a connection may be idle before it's closed. Idle connections are also checked
+    /// every half of this, closing any that don't respond.
+    pub idle_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 10,
+            min_idle: 1,
+            connection_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// An idle connection, and when it was last released.
+struct Idle {
+    client: Client,
+    since: Instant,
+}
+
+/// The pool state, shared with its connections and the health check.
+struct Shared {
+    addr: SocketAddr,
+    config: PoolConfig,
+    /// Idle connections, most recently released last.
+    idle: Mutex<VecDeque<Idle>>,
+    /// Permits for connections in use, limiting them (and the idle ones) to max_connections.
+    permits: Arc<Semaphore>,
+}
+
+impl Shared {
+    /// The number of connections in use, including ones being connected or checked.
+    fn in_use(&self) -> usize {
+        self.config.max_connections - self.permits.available_permits()
+    }
+
+    /// The number of open connections.
+    fn size(&self) -> usize {
+        self.idle.lock().len() + self.in_use()
+    }
+
+    /// Closes idle connections that have timed out or don't respond to a ping, oldest first,
+    /// then opens connections until there are min_idle. Each connection is checked while
+    /// holding a permit, so acquirers don't open more than max_connections in the meanwhile.
+    async fn check(&self) {
+        let config = &self.config;
+        let mut checked = Vec::new();
+        let count = self.idle.lock().len();
+        for _ in 0..count {
+            let Ok(permit) = self.permits.clone().try_acquire_owned() else { break };
+            let Some(mut idle) = self.idle.lock().pop_front() else { break };
+            if idle.since.elapsed() >= config.idle_timeout && self.size() > config.min_idle {
+                continue;
+            }
+            let ping = tokio::time::timeout(config.connection_timeout, idle.client.ping());
+            if let Ok(Ok(())) = ping.await {
+                checked.push((idle, permit));
+            }
+        }
+        {
+            let mut idle = self.idle.lock();
+            for (connection, _) in checked.into_iter().rev() {
+                idle.push_front(connection);
+            }
+        }
+
+        while self.size() < config.min_idle {
+            let Ok(_permit) = self.permits.clone().try_acquire_owned() else { break };
+            let connect = Client::connect(self.addr);
+            match tokio::time::timeout(config.connection_timeout, connect).await {
+                Ok(Ok(client)) => {
+                    self.idle.lock().push_back(Idle { client, since: Instant::now() })
+                }
+                _ => break,
+            }
+        }
+    }
+}
+
+/// A pool of connections to a server, see Client. Connections are acquired from the pool, and
+/// returned to it when dropped. Up to max_connections are open at a time, and acquirers wait
+/// for one to be returned beyond that. A background task closes idle connections that have
+/// timed out or stopped responding, and keeps min_idle connections open.
+///
+/// Connections keep their server session, so callers must commit or roll back any transaction
+/// before returning a connection, or invalidate it instead.
+pub struct ConnectionPool {
+    shared: Arc<Shared>,
+    health_check: JoinHandle<()>,
+}
+
+impl ConnectionPool {
+    /// Creates a pool of connections to a server. Connections are opened on demand, and by
+    /// the health check, which starts right away and must run in a Tokio runtime.
+    pub fn new(addr: SocketAddr, config: PoolConfig) -> Result<Self> {
+        if config.max_connections == 0 {
+            return Err(Error::Config("max_connections must be at least 1".into()));
+        }
+        if config.min_idle > config.max_connections {
+            return Err(Error::Config("min_idle can't exceed max_connections".into()));
+        }
+        let interval = (config.idle_timeout / 2).max(Duration::from_millis(1));
+        let shared = Arc::new(Shared {
+            addr,
+            permits: Arc::new(Semaphore::new(config.max_connections)),
+            idle: Mutex::new(VecDeque::new()),
+            config,
+        });
+        let health_check = tokio::spawn(Self::health_check(Arc::downgrade(&shared), interval));
+        Ok(Self { shared, health_check })
+    }
+
+    /// Checks the idle connections periodically, until the pool and its connections are gone.
+    async fn health_check(shared: Weak<Shared>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            match shared.upgrade() {
+                Some(shared) => shared.check().await,
+                None => return,
+            }
+        }
+    }
+
+    /// Acquires a connection, reusing the most recently released idle connection if any, or
+    /// opening a new one. Errors if none is available within the connection timeout.
+    pub async fn acquire(&self) -> Result<PooledConnection> {
+        let shared = self.shared.clone();
+        let timeout = shared.config.connection_timeout;
+        let acquire = async move {
+            let permit = shared
+                .permits
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(|err| Error::Internal(err.to_string()))?;
+            let idle = shared.idle.lock().pop_back();
+            let client = match idle {
+                Some(idle) => idle.client,
+                None => Client::connect(shared.addr).await?,
+            };
+            Ok(PooledConnection { client: Some(client), shared, _permit: permit })
+        };
+        tokio::time::timeout(timeout, acquire).await.map_err(|_| {
+            Error::Internal(format!("Timed out acquiring a connection after {:?}", timeout))
+        })?
+    }
+
+    /// Returns the number of open connections, idle or in use.
+    pub fn size(&self) -> usize {
+        self.shared.size()
+    }
+
+    /// Returns the number of idle connections.
+    pub fn idle(&self) -> usize {
+        self.shared.idle.lock().len()
+    }
+}
+
+impl Drop for ConnectionPool {
+    fn drop(&mut self) {
+        self.health_check.abort();
+    }
+}
+
+/// A connection acquired from a pool, which is returned to the pool when dropped.
+pub struct PooledConnection {
+    /// The client, until it's returned or invalidated.
+    client: Option<Client>,
+    shared: Arc<Shared>,
+    /// The permit for the connection, released after the client is returned to the pool.
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    /// Closes the connection rather than returning it to the pool, e.g. after an error left
+    /// it in an unknown state.
+    pub fn invalidate(mut self) {
+        self.client = None;
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("connection already released")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("connection already released")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.shared.idle.lock().push_back(Idle { client, since: Instant::now() });
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod mvcc;
 pub mod tests;
 pub mod transaction;
+pub mod two_phase;
 
 pub use mvcc::MVCC;
 pub use transaction::Transaction;
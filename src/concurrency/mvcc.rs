@@ -5,7 +5,7 @@ use dashmap::DashMap;
 use parking_lot::RwLock;
 
 use crate::error::{Result, Error};
-use crate::storage::kv::KvStore;
+use crate::storage::kv::{KvStore, Range, WriteOp};
 use super::{Mode, Transaction};
 
 /// An MVCC-based transactional key-value store.
@@ -44,6 +44,42 @@ impl MVCC {
         Transaction::resume(self.store.clone(), id, self.lock_manager.clone())
     }
 
+    /// Removes obsolete versions below the given version, returning the number of versions
+    /// removed. Versions that an active transaction can still read are retained, but snapshot
+    /// transactions for a vacuumed version can no longer see its original state.
+    pub fn vacuum(&self, below: u64) -> Result<u64> {
+        super::transaction::vacuum(&self.store, below)
+    }
+
+    /// Dumps all raw key/value pairs of the underlying store, including versions, transaction
+    /// state and metadata.
+    pub fn snapshot(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let session = self.store.read();
+        session.scan(Range::from(..))?.collect()
+    }
+
+    /// Replaces the contents of the underlying store with a dump from snapshot(). Must not be
+    /// called while transactions are active.
+    pub fn restore(&self, pairs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        let session = self.store.write();
+        let mut ops = session
+            .scan(Range::from(..))?
+            .map(|r| r.map(|(key, _)| WriteOp::Delete { key }))
+            .collect::<Result<Vec<_>>>()?;
+        ops.extend(pairs.into_iter().map(|(key, value)| WriteOp::Put { key, value }));
+        session.write_batch(ops)
+    }
+
+    /// Returns the format version of the underlying store, see KvStore::store_version().
+    pub fn store_version(&self) -> Result<(u32, u32)> {
+        self.store.read().store_version()
+    }
+
+    /// Records the format version of the underlying store.
+    pub fn write_store_version(&self, major: u32, minor: u32) -> Result<()> {
+        self.store.write().write_store_version(major, minor)
+    }
+
     /// Fetches an unversioned metadata value
     pub fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         use super::transaction::MvccKey;
@@ -674,4 +674,114 @@ fn test_metadata() -> Result<()> {
     mvcc.set_metadata(b"foo", b"baz".to_vec())?;
     assert_eq!(Some(b"baz".to_vec()), mvcc.get_metadata(b"foo")?);
     Ok(())
-}
\ No newline at end of file
+}
+#[test]
+fn test_vacuum() -> Result<()> {
+    let (mvcc, _dir) = setup()?;
+
+    for value in [0x01, 0x02, 0x03] {
+        let txn = mvcc.begin()?;
+        txn.set(b"a", vec![value])?;
+        txn.set(b"b", vec![value])?;
+        txn.commit()?;
+    }
+    let txn = mvcc.begin()?;
+    txn.delete(b"b")?;
+    txn.set(b"c", vec![0x04])?;
+    txn.commit()?;
+
+    // Only the first versions of a and b are shadowed below 3.
+    assert_eq!(2, mvcc.vacuum(3)?);
+    let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+    assert_eq!(Some(vec![0x03]), txn.get(b"a")?);
+    assert_eq!(None, txn.get(b"b")?);
+    txn.commit()?;
+
+    // The remaining versions of a and b are shadowed, and b's deletion marker is removed too.
+    assert_eq!(4, mvcc.vacuum(10)?);
+    assert_eq!(0, mvcc.vacuum(10)?);
+    let txn = mvcc.begin_with_mode(Mode::ReadOnly)?;
+    assert_eq!(Some(vec![0x03]), txn.get(b"a")?);
+    assert_eq!(None, txn.get(b"b")?);
+    assert_eq!(
+        vec![(b"a".to_vec(), vec![0x03]), (b"c".to_vec(), vec![0x04])],
+        txn.scan(..)?.collect::<Result<Vec<_>>>()?
+    );
+    txn.commit()?;
+
+    // Vacuumed versions can't be rolled back.
+    let txn = mvcc.begin()?;
+    txn.set(b"a", vec![0x05])?;
+    txn.rollback()?;
+    assert_eq!(Some(vec![0x03]), mvcc.begin()?.get(b"a")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_vacuum_active_txns() -> Result<()> {
+    let (mvcc, _dir) = setup()?;
+
+    let txn = mvcc.begin()?;
+    txn.set(b"a", vec![0x01])?;
+    txn.commit()?;
+
+    // A txn that is active when t3 begins stays invisible to t3 after committing.
+    let t2 = mvcc.begin()?;
+    let t3 = mvcc.begin_with_mode(Mode::ReadOnly)?;
+    t2.set(b"a", vec![0x02])?;
+    t2.commit()?;
+
+    let txn = mvcc.begin()?;
+    txn.set(b"a", vec![0x04])?;
+    txn.commit()?;
+
+    // The horizon is lowered to t2, which t3 can't see, so nothing is removed.
+    assert_eq!(0, mvcc.vacuum(10)?);
+    assert_eq!(Some(vec![0x01]), t3.get(b"a")?);
+    t3.commit()?;
+
+    // A snapshot txn holds back the horizon at its version.
+    let snapshot = mvcc.begin_with_mode(Mode::Snapshot { version: 3 })?;
+    assert_eq!(Some(vec![0x01]), snapshot.get(b"a")?);
+    assert_eq!(0, mvcc.vacuum(10)?);
+    snapshot.commit()?;
+
+    assert_eq!(2, mvcc.vacuum(10)?);
+    assert_eq!(Some(vec![0x04]), mvcc.begin()?.get(b"a")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_restore() -> Result<()> {
+    let (mvcc, _dir) = setup()?;
+    let txn = mvcc.begin()?;
+    txn.set(b"a", vec![0x01])?;
+    txn.set(b"b", vec![0x02])?;
+    txn.commit()?;
+    let snapshot = mvcc.snapshot()?;
+
+    let txn = mvcc.begin()?;
+    txn.delete(b"a")?;
+    txn.set(b"c", vec![0x03])?;
+    txn.commit()?;
+
+    let (other, _other_dir) = setup()?;
+    let txn = other.begin()?;
+    txn.set(b"d", vec![0x04])?;
+    txn.commit()?;
+
+    // Restoring replaces the entire store, including the transaction state.
+    for mvcc in [&mvcc, &other] {
+        mvcc.restore(snapshot.clone())?;
+        let txn = mvcc.begin()?;
+        assert_eq!(2, txn.id());
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"b".to_vec(), vec![0x02])],
+            txn.scan(..)?.collect::<Result<Vec<_>>>()?
+        );
+        txn.commit()?;
+    }
+    Ok(())
+}
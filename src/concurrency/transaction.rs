@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
 use super::mvcc::LockManager;
-use crate::storage::kv::{KvStore, Range, KvScan};
+use crate::storage::kv::{KvStore, Range, KvScan, WriteOp};
 
 /// An MVCC transaction.
 pub struct Transaction {
@@ -281,6 +281,28 @@ impl Transaction {
         }
         self.scan(start..end)
     }
+
+    /// Fetches an unversioned metadata value, see MVCC::get_metadata().
+    pub fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.read().get(&MvccKey::Metadata(key.into()).encode())
+    }
+
+    /// Atomically replaces an unversioned metadata value if it has the expected value, see
+    /// KvStore::compare_and_swap(). Returns whether it was replaced. Since metadata isn't
+    /// versioned, the write is immediately visible to all transactions and isn't rolled back.
+    pub fn compare_and_swap_metadata(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        value: Vec<u8>,
+    ) -> Result<bool> {
+        if !self.mode.allows_write() {
+            return Err(Error::ReadOnly);
+        }
+        // The write lock makes the swap atomic even for stores using the default implementation.
+        let session = self.store.write();
+        session.compare_and_swap(&MvccKey::Metadata(key.into()).encode(), expected, value)
+    }
 }
 
 /// An MVCC transaction mode.
@@ -360,6 +382,74 @@ impl Snapshot {
     }
 }
 
+/// Garbage-collects versions below the given version that are shadowed by a newer version below
+/// it, along with trailing deletion markers and the update markers of the removed versions. The
+/// version is lowered to the oldest one that an active transaction may still read, so that
+/// vacuuming never changes what a running transaction sees. Returns the number of versions removed.
+pub(super) fn vacuum(store: &RwLock<Box<dyn KvStore>>, mut below: u64) -> Result<u64> {
+    let session = store.write();
+
+    // Every version below the horizon must be committed and visible to all active snapshots.
+    let mut scan = session.scan(Range::from(
+        MvccKey::TxnActive(0).encode()..MvccKey::TxnSnapshot(0).encode()
+    ))?;
+    while let Some((key, value)) = scan.next().transpose()? {
+        let id = match MvccKey::decode(&key)? {
+            MvccKey::TxnActive(id) => id,
+            k => return Err(Error::Internal(format!("Expected TxnActive, got {:?}", k))),
+        };
+        let version = match deserialize(&value)? {
+            Mode::Snapshot { version } => version,
+            _ => id,
+        };
+        let invisible: HashSet<u64> = match session.get(&MvccKey::TxnSnapshot(version).encode())? {
+            Some(ref v) => deserialize(v)?,
+            None => return Err(Error::Value(format!("Snapshot not found for version {}", version))),
+        };
+        below = invisible.into_iter().fold(below.min(version + 1), u64::min);
+    }
+    std::mem::drop(scan);
+
+    // Records are ordered by key and then version, so each key's versions below the horizon are
+    // contiguous. All but the latest are obsolete, and so is the latest if it is a deletion.
+    let mut ops = vec![];
+    let mut removed = 0;
+    let mut latest: Option<(Vec<u8>, u64, bool)> = None;
+    let mut remove = |key: Vec<u8>, version: u64, ops: &mut Vec<WriteOp>| {
+        ops.push(WriteOp::Delete { key: MvccKey::TxnUpdate(version, (&key).into()).encode() });
+        ops.push(WriteOp::Delete { key });
+        removed += 1;
+    };
+    let mut scan = session.scan(Range::from(MvccKey::Record(vec![].into(), 0).encode()..))?;
+    while let Some((key, value)) = scan.next().transpose()? {
+        let (record, version) = match MvccKey::decode(&key)? {
+            MvccKey::Record(record, version) => (record, version),
+            k => return Err(Error::Internal(format!("Expected Record, got {:?}", k))),
+        };
+        if version >= below {
+            continue;
+        }
+        let deleted = deserialize::<Option<Vec<u8>>>(&value)?.is_none();
+        if let Some((prev_key, prev_version, prev_deleted)) = latest.take() {
+            match MvccKey::decode(&prev_key)? {
+                MvccKey::Record(prev_record, _) if prev_record == record => {
+                    remove(prev_key, prev_version, &mut ops)
+                }
+                _ if prev_deleted => remove(prev_key, prev_version, &mut ops),
+                _ => {}
+            }
+        }
+        latest = Some((key, version, deleted));
+    }
+    std::mem::drop(scan);
+    if let Some((key, version, true)) = latest {
+        remove(key, version, &mut ops);
+    }
+
+    session.write_batch(ops)?;
+    Ok(removed)
+}
+
 /// MVCC keys. The encoding preserves the grouping and ordering of keys. 
 /// Uses a Cow since we want to take borrows when encoding and return owned when decoding.
 #[derive(Debug)]
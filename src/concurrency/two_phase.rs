@@ -0,0 +1,556 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::storage::kv::{KvStore, Range, WriteOp};
+use crate::storage::log::{self, LogStore};
+
+/// A shard ID, identifying the Raft group holding part of the data, as in VectorClock.
+pub type ShardId = String;
+
+/// A participant's vote in the prepare phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Vote {
+    Commit,
+    Abort,
+}
+
+/// The outcome of the prepare phase.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PrepareResult {
+    /// All participants voted to commit, so the transaction can be committed.
+    Prepared,
+    /// The given participant voted to abort or failed to prepare, so the transaction was
+    /// aborted.
+    Aborted(ShardId),
+}
+
+/// The state of a distributed transaction in the transaction log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxnState {
+    /// The participants are being prepared, and no decision has been made.
+    Preparing,
+    /// The transaction was decided to commit.
+    Committed,
+    /// The transaction was decided to abort.
+    Aborted,
+}
+
+/// A shard taking part in distributed transactions. A participant must persist a transaction's
+/// writes before voting to commit, such that it can still commit or abort it after a crash.
+/// commit() and abort() are retried during recovery, so they must be idempotent, and succeed
+/// for transactions the participant doesn't know.
+pub trait Participant: Send + Sync {
+    /// Prepares a transaction's writes, voting whether it can commit.
+    fn prepare(&self, txn_id: u64, ops: Vec<WriteOp>) -> Result<Vote>;
+    /// Applies a prepared transaction's writes.
+    fn commit(&self, txn_id: u64) -> Result<()>;
+    /// Discards a prepared transaction's writes.
+    fn abort(&self, txn_id: u64) -> Result<()>;
+}
+
+/// A transaction log record.
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    /// The transaction entered a state.
+    State { txn_id: u64, state: TxnState, participants: Vec<ShardId> },
+    /// All participants acknowledged the decision, so the transaction can be forgotten.
+    Finished { txn_id: u64 },
+}
+
+/// A durable log of distributed transaction states, kept in a log store such as the Raft log.
+/// Records are committed to the log before returning. Transactions are forgotten once all
+/// participants have acknowledged the decision.
+pub struct TransactionLog {
+    log: Box<dyn LogStore>,
+    /// The unfinished transactions, with their state and participants.
+    txns: BTreeMap<u64, (TxnState, Vec<ShardId>)>,
+}
+
+impl TransactionLog {
+    /// Opens a transaction log, replaying the records already in the log store.
+    pub fn open(log: Box<dyn LogStore>) -> Result<Self> {
+        let mut txns = BTreeMap::new();
+        for entry in log.scan(log::Range::from(..)) {
+            match bincode::deserialize(&entry?)? {
+                Record::State { txn_id, state, participants } => {
+                    txns.insert(txn_id, (state, participants));
+                }
+                Record::Finished { txn_id } => {
+                    txns.remove(&txn_id);
+                }
+            }
+        }
+        Ok(Self { log, txns })
+    }
+
+    /// Returns the state of an unfinished transaction, if any.
+    pub fn get(&self, txn_id: u64) -> Option<TxnState> {
+        self.txns.get(&txn_id).map(|(state, _)| *state)
+    }
+
+    /// Returns the unfinished transactions, with their state and participants.
+    pub fn unfinished(&self) -> Vec<(u64, TxnState, Vec<ShardId>)> {
+        self.txns.iter().map(|(id, (state, shards))| (*id, *state, shards.clone())).collect()
+    }
+
+    /// Records that a transaction started preparing the given participants.
+    pub fn preparing(&mut self, txn_id: u64, participants: Vec<ShardId>) -> Result<()> {
+        if self.txns.contains_key(&txn_id) {
+            return Err(Error::AlreadyExists(format!("Transaction {} already exists", txn_id)));
+        }
+        self.append(Record::State { txn_id, state: TxnState::Preparing, participants })
+    }
+
+    /// Records the decision for a preparing transaction. Recording the same decision again
+    /// does nothing.
+    pub fn decide(&mut self, txn_id: u64, decision: TxnState) -> Result<()> {
+        let (state, participants) = self
+            .txns
+            .get(&txn_id)
+            .ok_or_else(|| Error::NotFound(format!("Transaction {} not found", txn_id)))?;
+        match (*state, decision) {
+            (_, TxnState::Preparing) => {
+                Err(Error::Internal("Preparing is not a decision".into()))
+            }
+            (state, decision) if state == decision => Ok(()),
+            (TxnState::Preparing, decision) => {
+                let participants = participants.clone();
+                self.append(Record::State { txn_id, state: decision, participants })
+            }
+            (state, _) => {
+                Err(Error::Value(format!("Transaction {} is already {:?}", txn_id, state)))
+            }
+        }
+    }
+
+    /// Records that all participants acknowledged a transaction's decision.
+    pub fn finish(&mut self, txn_id: u64) -> Result<()> {
+        match self.get(txn_id) {
+            None => Ok(()),
+            Some(TxnState::Preparing) => {
+                Err(Error::Internal(format!("Transaction {} has no decision", txn_id)))
+            }
+            Some(_) => self.append(Record::Finished { txn_id }),
+        }
+    }
+
+    /// Appends and commits a record, and applies it.
+    fn append(&mut self, record: Record) -> Result<()> {
+        let index = self.log.append(bincode::serialize(&record)?)?;
+        self.log.commit(index)?;
+        match record {
+            Record::State { txn_id, state, participants } => {
+                self.txns.insert(txn_id, (state, participants));
+            }
+            Record::Finished { txn_id } => {
+                self.txns.remove(&txn_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A two-phase commit coordinator for transactions spanning several shards. Writes are staged
+/// per shard, then prepare() sends them to the participants, and commits or aborts once they
+/// all voted. The decision is recorded in the transaction log before any participant learns
+/// about it, so a decision is never lost or changed by a crash.
+///
+/// After a coordinator restart, recover() resolves the transactions left in doubt: those that
+/// were still preparing are aborted, and the phase-2 decision of the others is sent again.
+/// Participants that fail during phase 2 are also retried by recover().
+pub struct TwoPhaseCoordinator {
+    log: Mutex<TransactionLog>,
+    participants: HashMap<ShardId, Arc<dyn Participant>>,
+    /// Writes staged by transactions which haven't been prepared yet, by shard.
+    staged: Mutex<HashMap<u64, HashMap<ShardId, Vec<WriteOp>>>>,
+    /// Transactions prepared by this coordinator, which can be committed.
+    prepared: Mutex<HashSet<u64>>,
+}
+
+impl TwoPhaseCoordinator {
+    /// Creates a coordinator for the given shards, using a transaction log.
+    pub fn new(log: TransactionLog, participants: HashMap<ShardId, Arc<dyn Participant>>) -> Self {
+        Self {
+            log: Mutex::new(log),
+            participants,
+            staged: Mutex::new(HashMap::new()),
+            prepared: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Returns a participant, or errors if the shard is unknown.
+    fn participant(&self, shard: &str) -> Result<&Arc<dyn Participant>> {
+        self.participants
+            .get(shard)
+            .ok_or_else(|| Error::NotFound(format!("Unknown shard {}", shard)))
+    }
+
+    /// Stages writes to a shard, sent to it when the transaction is prepared.
+    pub fn stage(&self, txn_id: u64, shard: &str, ops: Vec<WriteOp>) -> Result<()> {
+        self.participant(shard)?;
+        if self.log.lock().get(txn_id).is_some() {
+            return Err(Error::Value(format!("Transaction {} is already prepared", txn_id)));
+        }
+        let mut staged = self.staged.lock();
+        staged.entry(txn_id).or_default().entry(shard.to_string()).or_default().extend(ops);
+        Ok(())
+    }
+
+    /// Prepares a transaction on the given participants, sending them the transaction's
+    /// staged writes, if any. If any participant votes to abort or fails, the transaction is
+    /// aborted on all of them.
+    pub fn prepare(&self, txn_id: u64, participants: Vec<ShardId>) -> Result<PrepareResult> {
+        for shard in &participants {
+            self.participant(shard)?;
+        }
+        let mut staged = self.staged.lock().remove(&txn_id).unwrap_or_default();
+        if let Some(shard) = staged.keys().find(|shard| !participants.contains(shard)) {
+            return Err(Error::Value(format!("Shard {} has writes but isn't a participant", shard)));
+        }
+        self.log.lock().preparing(txn_id, participants.clone())?;
+
+        for shard in &participants {
+            let ops = staged.remove(shard).unwrap_or_default();
+            let vote = self.participant(shard)?.prepare(txn_id, ops).unwrap_or_else(|err| {
+                ::log::warn!("Failed to prepare transaction {} on {}: {}", txn_id, shard, err);
+                Vote::Abort
+            });
+            if vote == Vote::Abort {
+                self.decide(txn_id, TxnState::Aborted)?;
+                return Ok(PrepareResult::Aborted(shard.clone()));
+            }
+        }
+        self.prepared.lock().insert(txn_id);
+        Ok(PrepareResult::Prepared)
+    }
+
+    /// Commits a prepared transaction. The transaction is committed once the decision is
+    /// recorded, even if a participant fails to apply it, which recover() then retries.
+    pub fn commit(&self, txn_id: u64) -> Result<()> {
+        if self.log.lock().get(txn_id) == Some(TxnState::Preparing)
+            && !self.prepared.lock().contains(&txn_id)
+        {
+            return Err(Error::Value(format!("Transaction {} is not prepared", txn_id)));
+        }
+        self.decide(txn_id, TxnState::Committed)
+    }
+
+    /// Aborts a transaction, discarding any staged or prepared writes.
+    pub fn abort(&self, txn_id: u64) -> Result<()> {
+        if self.staged.lock().remove(&txn_id).is_some() && self.log.lock().get(txn_id).is_none() {
+            return Ok(());
+        }
+        self.decide(txn_id, TxnState::Aborted)
+    }
+
+    /// Returns the state of an unfinished transaction, if any.
+    pub fn state(&self, txn_id: u64) -> Option<TxnState> {
+        self.log.lock().get(txn_id)
+    }
+
+    /// Returns the IDs of decided transactions which not all participants have acknowledged.
+    pub fn in_doubt(&self) -> Vec<u64> {
+        let unfinished = self.log.lock().unfinished();
+        unfinished.into_iter().filter(|(_, s, _)| *s != TxnState::Preparing).map(|t| t.0).collect()
+    }
+
+    /// Resolves the transactions left in doubt, e.g. after a restart. Transactions which were
+    /// preparing when the coordinator crashed are aborted, and the decision of the others is
+    /// sent to their participants again. Returns the number of transactions that were
+    /// resolved, i.e. acknowledged by all participants.
+    pub fn recover(&self) -> Result<usize> {
+        let unfinished = self.log.lock().unfinished();
+        let mut resolved = 0;
+        for (txn_id, state, _) in unfinished {
+            let decision = match state {
+                TxnState::Preparing if self.prepared.lock().contains(&txn_id) => continue,
+                TxnState::Preparing => TxnState::Aborted,
+                state => state,
+            };
+            self.decide(txn_id, decision)?;
+            if self.log.lock().get(txn_id).is_none() {
+                resolved += 1;
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Records a decision and sends it to the participants. The transaction is finished once
+    /// all participants have acknowledged it, otherwise it's left for recover().
+    fn decide(&self, txn_id: u64, decision: TxnState) -> Result<()> {
+        let participants = {
+            let mut log = self.log.lock();
+            log.decide(txn_id, decision)?;
+            log.txns[&txn_id].1.clone()
+        };
+        self.prepared.lock().remove(&txn_id);
+
+        let mut acknowledged = true;
+        for shard in &participants {
+            let participant = self.participant(shard)?;
+            let result = match decision {
+                TxnState::Committed => participant.commit(txn_id),
+                _ => participant.abort(txn_id),
+            };
+            if let Err(err) = result {
+                ::log::warn!(
+                    "Failed to send {:?} for transaction {} to {}: {}",
+                    decision,
+                    txn_id,
+                    shard,
+                    err
+                );
+                acknowledged = false;
+            }
+        }
+        if acknowledged {
+            self.log.lock().finish(txn_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// A participant applying transactions to a key-value store. Prepared writes are persisted in
+/// the store, and applied in one write batch on commit, which also removes them. A prepared
+/// transaction locks the keys it writes, so another transaction writing any of them votes to
+/// abort until it's committed or aborted.
+pub struct KvParticipant {
+    store: Arc<dyn KvStore>,
+    /// The prepared transactions and their writes, restored from the store on open.
+    prepared: Mutex<BTreeMap<u64, Vec<WriteOp>>>,
+}
+
+impl KvParticipant {
+    /// The key prefix of prepared transactions.
+    const PREPARED_PREFIX: &'static [u8] = b"2pc:prepared:";
+
+    /// Opens a participant on a store, restoring any prepared transactions.
+    pub fn open(store: Arc<dyn KvStore>) -> Result<Self> {
+        let mut prepared = BTreeMap::new();
+        for item in store.scan(Range::from_prefix(Self::PREPARED_PREFIX))? {
+            let (key, value) = item?;
+            let id = key[Self::PREPARED_PREFIX.len()..].try_into().map_err(|_| {
+                Error::Corruption(format!("Invalid prepared transaction key {:?}", key))
+            })?;
+            prepared.insert(u64::from_be_bytes(id), bincode::deserialize(&value)?);
+        }
+        Ok(Self { store, prepared: Mutex::new(prepared) })
+    }
+
+    /// Returns the key of a prepared transaction.
+    fn prepared_key(txn_id: u64) -> Vec<u8> {
+        [Self::PREPARED_PREFIX, &txn_id.to_be_bytes()].concat()
+    }
+
+    /// Returns the key written by an op.
+    fn op_key(op: &WriteOp) -> &[u8] {
+        match op {
+            WriteOp::Put { key, .. } | WriteOp::Delete { key } => key,
+        }
+    }
+
+    /// Returns the IDs of the prepared transactions.
+    pub fn prepared(&self) -> Vec<u64> {
+        self.prepared.lock().keys().copied().collect()
+    }
+}
+
+impl Participant for KvParticipant {
+    fn prepare(&self, txn_id: u64, ops: Vec<WriteOp>) -> Result<Vote> {
+        let mut prepared = self.prepared.lock();
+        if prepared.contains_key(&txn_id) {
+            return Ok(Vote::Commit);
+        }
+        let locked: HashSet<&[u8]> = prepared.values().flatten().map(Self::op_key).collect();
+        if ops.iter().any(|op| locked.contains(Self::op_key(op))) {
+            return Ok(Vote::Abort);
+        }
+        self.store.set(&Self::prepared_key(txn_id), bincode::serialize(&ops)?)?;
+        prepared.insert(txn_id, ops);
+        Ok(Vote::Commit)
+    }
+
+    fn commit(&self, txn_id: u64) -> Result<()> {
+        let mut prepared = self.prepared.lock();
+        if let Some(ops) = prepared.get(&txn_id) {
+            let mut batch = ops.clone();
+            batch.push(WriteOp::Delete { key: Self::prepared_key(txn_id) });
+            self.store.write_batch(batch)?;
+            prepared.remove(&txn_id);
+        }
+        Ok(())
+    }
+
+    fn abort(&self, txn_id: u64) -> Result<()> {
+        let mut prepared = self.prepared.lock();
+        if prepared.contains_key(&txn_id) {
+            self.store.delete(&Self::prepared_key(txn_id))?;
+            prepared.remove(&txn_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::StdBPlusTree;
+    use crate::storage::log::LogDemo;
+
+    /// A participant which can crash and restart, reopening its store.
+    struct Shard {
+        store: Arc<dyn KvStore>,
+        participant: Mutex<Option<KvParticipant>>,
+    }
+
+    impl Shard {
+        fn new() -> Result<Arc<Self>> {
+            let store: Arc<dyn KvStore> = Arc::new(StdBPlusTree::new());
+            let participant = Mutex::new(Some(KvParticipant::open(store.clone())?));
+            Ok(Arc::new(Self { store, participant }))
+        }
+
+        fn crash(&self) {
+            *self.participant.lock() = None;
+        }
+
+        fn restart(&self) -> Result<()> {
+            *self.participant.lock() = Some(KvParticipant::open(self.store.clone())?);
+            Ok(())
+        }
+
+        fn with<T>(&self, f: impl FnOnce(&KvParticipant) -> Result<T>) -> Result<T> {
+            match &*self.participant.lock() {
+                Some(participant) => f(participant),
+                None => Err(Error::Internal("Shard is down".into())),
+            }
+        }
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.store.get(key)
+        }
+    }
+
+    impl Participant for Shard {
+        fn prepare(&self, txn_id: u64, ops: Vec<WriteOp>) -> Result<Vote> {
+            self.with(|p| p.prepare(txn_id, ops))
+        }
+
+        fn commit(&self, txn_id: u64) -> Result<()> {
+            self.with(|p| p.commit(txn_id))
+        }
+
+        fn abort(&self, txn_id: u64) -> Result<()> {
+            self.with(|p| p.abort(txn_id))
+        }
+    }
+
+    fn put(key: &[u8], value: &[u8]) -> Vec<WriteOp> {
+        vec![WriteOp::Put { key: key.to_vec(), value: value.to_vec() }]
+    }
+
+    fn shards() -> Vec<ShardId> {
+        vec!["a".into(), "b".into()]
+    }
+
+    /// Sets up a coordinator for shards a and b, returning the log for restarts.
+    fn setup() -> Result<(TwoPhaseCoordinator, LogDemo, Arc<Shard>, Arc<Shard>)> {
+        let (a, b) = (Shard::new()?, Shard::new()?);
+        let log = LogDemo::new();
+        let coordinator = restart(&log, &a, &b)?;
+        Ok((coordinator, log, a, b))
+    }
+
+    /// Opens a coordinator from a log.
+    fn restart(log: &LogDemo, a: &Arc<Shard>, b: &Arc<Shard>) -> Result<TwoPhaseCoordinator> {
+        let participants: HashMap<ShardId, Arc<dyn Participant>> =
+            HashMap::from([("a".into(), a.clone() as _), ("b".into(), b.clone() as _)]);
+        Ok(TwoPhaseCoordinator::new(TransactionLog::open(Box::new(log.clone()))?, participants))
+    }
+
+    #[test]
+    fn commit_abort() -> Result<()> {
+        let (coordinator, _, a, b) = setup()?;
+        coordinator.stage(1, "a", put(b"x", b"1"))?;
+        coordinator.stage(1, "b", put(b"y", b"1"))?;
+        assert!(coordinator.stage(1, "c", vec![]).is_err());
+        assert_eq!(PrepareResult::Prepared, coordinator.prepare(1, shards())?);
+        assert_eq!(Some(TxnState::Preparing), coordinator.state(1));
+        assert_eq!(None, a.get(b"x")?);
+        coordinator.commit(1)?;
+        assert_eq!((Some(b"1".to_vec()), Some(b"1".to_vec())), (a.get(b"x")?, b.get(b"y")?));
+        assert_eq!(None, coordinator.state(1));
+
+        coordinator.stage(2, "a", put(b"x", b"3"))?;
+        assert_eq!(PrepareResult::Prepared, coordinator.prepare(2, shards())?);
+        coordinator.abort(2)?;
+        assert!(coordinator.commit(2).is_err());
+        assert_eq!(Some(b"1".to_vec()), a.get(b"x")?);
+        assert!(a.with(|p| Ok(p.prepared()))?.is_empty());
+
+        // A transaction writing a key locked by a prepared transaction is aborted everywhere.
+        coordinator.stage(4, "a", put(b"x", b"4"))?;
+        assert_eq!(PrepareResult::Prepared, coordinator.prepare(4, shards())?);
+        coordinator.stage(5, "b", put(b"z", b"5"))?;
+        coordinator.stage(5, "a", put(b"x", b"5"))?;
+        assert_eq!(PrepareResult::Aborted("a".into()), coordinator.prepare(5, shards())?);
+        assert!(b.with(|p| Ok(p.prepared()))?.contains(&4));
+        assert!(!b.with(|p| Ok(p.prepared()))?.contains(&5));
+        coordinator.commit(4)?;
+        assert_eq!((Some(b"4".to_vec()), None), (a.get(b"x")?, b.get(b"z")?));
+        Ok(())
+    }
+
+    #[test]
+    fn coordinator_crash_after_prepare() -> Result<()> {
+        let (coordinator, log, a, b) = setup()?;
+        coordinator.stage(1, "a", put(b"x", b"1"))?;
+        coordinator.stage(1, "b", put(b"y", b"1"))?;
+        assert_eq!(PrepareResult::Prepared, coordinator.prepare(1, shards())?);
+        assert_eq!(vec![1], a.with(|p| Ok(p.prepared()))?);
+
+        // Without a recorded decision, the restarted coordinator presumes an abort.
+        drop(coordinator);
+        let coordinator = restart(&log, &a, &b)?;
+        assert_eq!(Some(TxnState::Preparing), coordinator.state(1));
+        assert!(coordinator.commit(1).is_err());
+        assert_eq!(1, coordinator.recover()?);
+        assert_eq!(None, coordinator.state(1));
+        assert!(a.with(|p| Ok(p.prepared()))?.is_empty());
+        assert!(b.with(|p| Ok(p.prepared()))?.is_empty());
+        assert_eq!((None, None), (a.get(b"x")?, b.get(b"y")?));
+        Ok(())
+    }
+
+    #[test]
+    fn participant_crash_during_commit() -> Result<()> {
+        let (coordinator, log, a, b) = setup()?;
+        coordinator.stage(1, "a", put(b"x", b"1"))?;
+        coordinator.stage(1, "b", put(b"y", b"1"))?;
+        assert_eq!(PrepareResult::Prepared, coordinator.prepare(1, shards())?);
+
+        // The decision is durable even though b crashed, so the transaction is committed.
+        b.crash();
+        coordinator.commit(1)?;
+        assert_eq!(Some(b"1".to_vec()), a.get(b"x")?);
+        assert_eq!(None, b.get(b"y")?);
+        assert_eq!(vec![1], coordinator.in_doubt());
+        assert_eq!(0, coordinator.recover()?);
+
+        // The coordinator also crashes, and re-sends the decision once b is back.
+        drop(coordinator);
+        let coordinator = restart(&log, &a, &b)?;
+        assert_eq!(Some(TxnState::Committed), coordinator.state(1));
+        assert!(coordinator.abort(1).is_err());
+        b.restart()?;
+        assert_eq!(1, coordinator.recover()?);
+        assert_eq!(Some(b"1".to_vec()), b.get(b"y")?);
+        assert!(coordinator.in_doubt().is_empty());
+        assert!(b.with(|p| Ok(p.prepared()))?.is_empty());
+        Ok(())
+    }
+}
@@ -6,9 +6,13 @@
 //! u64:     Big-endian binary representation.
 //! i64:     Big-endian binary representation, with sign bit flipped.
 //! f64:     Big-endian binary representation, with sign bit flipped if +, all flipped if -.
+//! Decimal: The mantissa like i64 but as i128, followed by the scale as u32. Only decimals of the
+//!          same scale are ordered, which holds for the values of a column.
 //! Value:   Like above, with type prefix 0x00=Null 0x01=Boolean 0x02=Float 0x03=Integer 0x04=String
+//!          0x05=Decimal 0x06=Timestamp 0x07=Date. Timestamps are encoded like i64, and dates
+//!          like i64 widened from i32, preserving their chronological order.
 
-use crate::{error::{Error, Result}, sql::types::Value};
+use crate::{error::{Error, Result}, sql::types::{Decimal, Value}};
 // use crate::sql::types::Value;
 
 use std::convert::TryInto;
@@ -139,6 +143,27 @@ pub fn take_i64(bytes: &mut &[u8]) -> Result<i64> {
     Ok(n)
 }
 
+/// Encodes a decimal, see module docs for format.
+pub fn encode_decimal(d: &Decimal) -> [u8; 20] {
+    let mut bytes = [0; 20];
+    bytes[..16].copy_from_slice(&d.mantissa().to_be_bytes());
+    bytes[0] ^= 1 << 7;
+    bytes[16..].copy_from_slice(&d.scale().to_be_bytes());
+    bytes
+}
+
+/// Decodes a decimal from a slice and shrinks the slice.
+pub fn take_decimal(bytes: &mut &[u8]) -> Result<Decimal> {
+    if bytes.len() < 20 {
+        return Err(Error::Internal(format!("Unable to decode decimal from {} bytes", bytes.len())));
+    }
+    let mut mantissa: [u8; 16] = bytes[0..16].try_into()?;
+    mantissa[0] ^= 1 << 7;
+    let scale = u32::from_be_bytes(bytes[16..20].try_into()?);
+    *bytes = &bytes[20..];
+    Decimal::new(i128::from_be_bytes(mantissa), scale)
+}
+
 /// Encodes a string. Simply converts to a byte vector and encodes that.
 pub fn encode_string(string: &str) -> Vec<u8> {
     encode_bytes(string.as_bytes())
@@ -178,6 +203,9 @@ pub fn encode_value(value: &Value) -> Vec<u8> {
         Value::Float(f) => [&[0x02][..], &encode_f64(*f)].concat(),
         Value::Integer(i) => [&[0x03][..], &encode_i64(*i)].concat(),
         Value::String(s) => [&[0x04][..], &encode_string(s)].concat(),
+        Value::Decimal(d) => [&[0x05][..], &encode_decimal(d)].concat(),
+        Value::Timestamp(t) => [&[0x06][..], &encode_i64(*t)].concat(),
+        Value::Date(d) => [&[0x07][..], &encode_i64(*d as i64)].concat(),
     }
 }
 
@@ -189,6 +217,9 @@ pub fn take_value(bytes: &mut &[u8]) -> Result<Value> {
         0x02 => Ok(Value::Float(take_f64(bytes)?)),
         0x03 => Ok(Value::Integer(take_i64(bytes)?)),
         0x04 => Ok(Value::String(take_string(bytes)?)),
+        0x05 => Ok(Value::Decimal(take_decimal(bytes)?)),
+        0x06 => Ok(Value::Timestamp(take_i64(bytes)?)),
+        0x07 => Ok(Value::Date(take_i64(bytes)?.try_into()?)),
         n => Err(Error::Internal(format!("Invalid value prefix {:x?}", n))),
     }
 }
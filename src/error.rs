@@ -7,14 +7,25 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// RPC-Result returning Error
 pub type RpcResult<T> = std::result::Result<tonic::Response<T>, tonic::Status>;
 
-/// toyDB errors. All except Internal are considered user-facing.
+/// toyDB errors. All except Internal and IoError are considered user-facing.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum Error {
     Abort,
+    AlreadyExists(String),
     Config(String),
+    /// A row violates a table constraint, e.g. a CHECK or FOREIGN KEY constraint.
+    ConstraintViolation(String),
+    Corruption(String),
+    DivisionByZero,
+    IncompatibleFormatVersion(String),
     Internal(String),
+    /// An I/O error, e.g. from a failed disk read or write.
+    IoError(String),
+    NotFound(String),
     Parse(String),
     ReadOnly,
+    SchemaMismatch(String),
     Serialization,
     Value(String),
     NotLeader,
@@ -22,13 +33,31 @@ pub enum Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    /// Returns whether the error is transient, such that retrying the operation may succeed:
+    /// serialization failures from conflicting transactions, aborted operations, and requests to
+    /// a node which is not the Raft leader.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Serialization | Error::NotLeader | Error::Abort)
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> fmt::Result {
         match self {
-            Error::Config(s) | Error::Internal(s) | Error::Parse(s) | Error::Value(s) => {
-                write!(f, "{}", s)
-            }
+            Error::AlreadyExists(s)
+            | Error::Config(s)
+            | Error::ConstraintViolation(s)
+            | Error::Corruption(s)
+            | Error::IncompatibleFormatVersion(s)
+            | Error::Internal(s)
+            | Error::IoError(s)
+            | Error::NotFound(s)
+            | Error::Parse(s)
+            | Error::SchemaMismatch(s)
+            | Error::Value(s) => write!(f, "{}", s),
             Error::Abort => write!(f, "Operation aborted"),
+            Error::DivisionByZero => write!(f, "Division by zero"),
             Error::Serialization => write!(f, "Serialization failure, retry transaction"),
             Error::ReadOnly => write!(f, "Read-only transaction"),
             Error::NotLeader => write!(f, "Not leader"),
@@ -42,6 +71,12 @@ impl From<Box<bincode::ErrorKind>> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
 impl From<config::ConfigError> for Error {
     fn from(err: config::ConfigError) -> Self {
         Error::Config(err.to_string())
@@ -80,7 +115,7 @@ impl From<std::array::TryFromSliceError> for Error {
 
 impl From<std::io::Error> for Error {
     fn from(err: std::io::Error) -> Self {
-        Error::Internal(err.to_string())
+        Error::IoError(err.to_string())
     }
 }
 
@@ -102,6 +137,12 @@ impl From<std::num::ParseIntError> for Error {
     }
 }
 
+impl From<std::num::TryFromIntError> for Error {
+    fn from(err: std::num::TryFromIntError) -> Self {
+        Error::Internal(err.to_string())
+    }
+}
+
 impl From<std::string::FromUtf8Error> for Error {
     fn from(err: std::string::FromUtf8Error) -> Self {
         Error::Internal(err.to_string())
@@ -150,11 +191,21 @@ impl From<tonic::Status> for Error {
     fn from(err: tonic::Status) -> Self {
         let chunks = err.message().split(" ").collect::<Vec<_>>();
         match chunks[0] {
+            "[AlreadyExists]" => Error::AlreadyExists(chunks[1..].join(" ")),
             "[Config]" => Error::Config(chunks[1..].join(" ")),
+            "[ConstraintViolation]" => Error::ConstraintViolation(chunks[1..].join(" ")),
+            "[Corruption]" => Error::Corruption(chunks[1..].join(" ")),
+            "[IncompatibleFormatVersion]" => {
+                Error::IncompatibleFormatVersion(chunks[1..].join(" "))
+            }
             "[Internal]" => Error::Internal(chunks[1..].join(" ")),
+            "[IoError]" => Error::IoError(chunks[1..].join(" ")),
+            "[NotFound]" => Error::NotFound(chunks[1..].join(" ")),
             "[Parse]" => Error::Parse(chunks[1..].join(" ")),
+            "[SchemaMismatch]" => Error::SchemaMismatch(chunks[1..].join(" ")),
             "[Value]" => Error::Value(chunks[1..].join(" ")),
             "[Abort]" => Error::Abort,
+            "[DivisionByZero]" => Error::DivisionByZero,
             "[ReadOnly]" => Error::ReadOnly,
             "[Serialization]" => Error::Serialization,
             "[NotLeader]" => Error::NotLeader,
@@ -166,15 +217,84 @@ impl From<tonic::Status> for Error {
 impl From<Error> for tonic::Status {
     fn from(err: Error) -> Self {
         let msg = match err {
+            Error::AlreadyExists(s) => format!("[AlreadyExists] {}", s),
             Error::Config(s) => format!("[Config] {}", s),
+            Error::ConstraintViolation(s) => format!("[ConstraintViolation] {}", s),
+            Error::Corruption(s) => format!("[Corruption] {}", s),
+            Error::IncompatibleFormatVersion(s) => format!("[IncompatibleFormatVersion] {}", s),
             Error::Internal(s) => format!("[Internal] {}", s),
+            Error::IoError(s) => format!("[IoError] {}", s),
+            Error::NotFound(s) => format!("[NotFound] {}", s),
             Error::Parse(s) => format!("[Parse] {}", s),
+            Error::SchemaMismatch(s) => format!("[SchemaMismatch] {}", s),
             Error::Value(s) => format!("[Value] {}", s),
-            Error::Abort => format!("[Abort] Operation aborted"),
-            Error::ReadOnly => format!("[ReadOnly] Read-only transaction"),
-            Error::Serialization => format!("[Serialization] Serialization failure, retry transaction"),
-            Error::NotLeader => format!("[NotLeader] Not leader"),
+            Error::Abort => "[Abort] Operation aborted".to_string(),
+            Error::DivisionByZero => "[DivisionByZero] Division by zero".to_string(),
+            Error::ReadOnly => "[ReadOnly] Read-only transaction".to_string(),
+            Error::Serialization => {
+                "[Serialization] Serialization failure, retry transaction".to_string()
+            }
+            Error::NotLeader => "[NotLeader] Not leader".to_string(),
         };
         tonic::Status::internal(msg)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[allow(clippy::invalid_regex)] // the regex is invalid on purpose
+    fn from() {
+        let io = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof");
+        assert_eq!(Error::IoError("eof".into()), io.into());
+        assert!(matches!("x".parse::<i64>().map_err(Error::from), Err(Error::Parse(_))));
+        assert!(matches!("x".parse::<f64>().map_err(Error::from), Err(Error::Parse(_))));
+        assert!(matches!(u8::try_from(256_u64).map_err(Error::from), Err(Error::Internal(_))));
+        assert!(matches!(
+            String::from_utf8(vec![0xff]).map_err(Error::from),
+            Err(Error::Internal(_))
+        ));
+        assert!(matches!(
+            <[u8; 2]>::try_from(&[0_u8][..]).map_err(Error::from),
+            Err(Error::Internal(_))
+        ));
+        assert!(matches!(
+            bincode::deserialize::<String>(&[0xff]).map_err(Error::from),
+            Err(Error::Internal(_))
+        ));
+        assert!(matches!(regex::Regex::new("(").map_err(Error::from), Err(Error::Value(_))));
+    }
+
+    #[test]
+    fn status_roundtrip() {
+        for err in [
+            Error::Abort,
+            Error::AlreadyExists("key exists".into()),
+            Error::Config("bad config".into()),
+            Error::ConstraintViolation("bad row".into()),
+            Error::Corruption("CRC mismatch".into()),
+            Error::DivisionByZero,
+            Error::IncompatibleFormatVersion("v2".into()),
+            Error::Internal("boom".into()),
+            Error::IoError("disk full".into()),
+            Error::NotFound("no key".into()),
+            Error::Parse("bad input".into()),
+            Error::ReadOnly,
+            Error::SchemaMismatch("bad column".into()),
+            Error::Serialization,
+            Error::Value("bad value".into()),
+            Error::NotLeader,
+        ] {
+            assert_eq!(err, Error::from(tonic::Status::from(err.clone())));
+        }
+    }
+
+    #[test]
+    fn is_transient() {
+        assert!(Error::Serialization.is_transient());
+        assert!(Error::NotLeader.is_transient());
+        assert!(!Error::IoError("disk full".into()).is_transient());
+        assert!(!Error::NotFound("no key".into()).is_transient());
+    }
+}
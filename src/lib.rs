@@ -1,3 +1,4 @@
+pub mod client;
 pub mod concurrency;
 pub mod error;
 pub mod encoding;
@@ -4,23 +4,28 @@ use tonic::transport::Channel;
 use crate::error::{Result, Error};
 use crate::proto::featherkv::{ExecutionReply, ExecutionRequest};
 use crate::proto::featherkv::{FeatherKvClient, RegistrationRequest, RegistrationReply};
-use super::RpcStatus;
+use super::{RpcStatus, VectorClock};
 
 /// A Raft-based key-value client.
 #[derive(Clone)]
 pub struct Client {
     servers: Vec<FeatherKvClient<Channel>>,
+    /// The addresses of the servers, including leaders that the client was redirected to.
+    addrs: Vec<String>,
     session_id: u64,
     sequence_number: u64,
     last_leader: u64,
+    /// The clock that servers must have caught up with before serving queries, if any.
+    min_clock: Option<VectorClock>,
 }
 
 impl Client {
     /// Creates a new Raft client.
     pub async fn new(servers: Vec<String>) -> Result<Self> {
+        let addrs = servers;
         let servers = {
             let mut clients = Vec::new();
-            for addr in servers {
+            for addr in &addrs {
                 let addr = format!("http://{}", addr).to_string();
                 let client = FeatherKvClient::connect(addr).await.unwrap();
                 clients.push(client);
@@ -29,9 +34,11 @@ impl Client {
         };
         Ok(Self {
             servers,
+            addrs,
             session_id: 0,
             sequence_number: 1,
             last_leader: 0,
+            min_clock: None,
         })
     }
 
@@ -53,11 +60,12 @@ impl Client {
                         RpcStatus::SessionExpired => {
                             return Err(Error::Internal("Should not get SessionExpired".into()));
                         },
+                        RpcStatus::RedirectToLeader { leader } => { self.redirect(leader)?; },
                     }
                 },
 
-                // Timeout, retries the request.
-                Err(e) => { continue; },
+                // The server is unreachable or timed out, retries the request on the next one.
+                Err(e) => { self.next_server(); },
             }
         }
     }
@@ -69,6 +77,7 @@ impl Client {
                 session_id: self.session_id,
                 sequence_number: self.sequence_number,
                 operation: mutation.clone(),
+                min_clock: vec![],
             };
 
             match self.servers[self.last_leader as usize].mutate(execution_request).await {
@@ -83,11 +92,12 @@ impl Client {
                         },
                         RpcStatus::NotLeader => { continue; },
                         RpcStatus::SessionExpired => { self.register().await?; },
+                        RpcStatus::RedirectToLeader { leader } => { self.redirect(leader)?; },
                     }
                 },
 
-                // Timeout, retries the request.
-                Err(e) => { continue; },
+                // The server is unreachable or timed out, retries the request on the next one.
+                Err(e) => { self.next_server(); },
             }
         }
     }
@@ -99,6 +109,10 @@ impl Client {
                 session_id: self.session_id,
                 sequence_number: self.sequence_number,
                 operation: query.clone(),
+                min_clock: match &self.min_clock {
+                    Some(clock) => Self::serialize(clock)?,
+                    None => vec![],
+                },
             };
 
             match self.servers[self.last_leader as usize].query(execution_request).await {
@@ -113,15 +127,47 @@ impl Client {
                         },
                         RpcStatus::NotLeader => { continue; },
                         RpcStatus::SessionExpired => { self.register().await?; },
+                        RpcStatus::RedirectToLeader { leader } => { self.redirect(leader)?; },
                     }
                 },
 
-                // Timeout, retries the request.
-                Err(e) => { continue; },
+                // The server is unreachable or timed out, retries the request on the next one.
+                Err(e) => { self.next_server(); },
             }
         }
     }
 
+    /// Requires servers to have applied the entries in a clock before serving later queries,
+    /// e.g. so that a query observes writes made through another client. Servers which don't
+    /// catch up in time error the query, which is then retried on the next server.
+    pub fn set_min_clock(&mut self, clock: VectorClock) {
+        self.min_clock = Some(clock);
+    }
+
+    /// Sends the following requests to the next server, e.g. when the last leader has failed.
+    /// The next server redirects them to the new leader once one is elected.
+    fn next_server(&mut self) {
+        self.last_leader = (self.last_leader + 1) % self.servers.len() as u64;
+    }
+
+    /// Sends the following requests to the leader at the given address, connecting to it if it
+    /// isn't one of the known servers.
+    fn redirect(&mut self, leader: String) -> Result<()> {
+        let index = match self.addrs.iter().position(|addr| *addr == leader) {
+            Some(index) => index,
+            None => {
+                let channel = Channel::from_shared(format!("http://{}", leader))
+                    .map_err(|e| Error::Value(e.to_string()))?
+                    .connect_lazy();
+                self.servers.push(FeatherKvClient::new(channel));
+                self.addrs.push(leader);
+                self.servers.len() - 1
+            }
+        };
+        self.last_leader = index as u64;
+        Ok(())
+    }
+
     /// Serializes a value for the Raft client.
     fn serialize<V: Serialize>(value: &V) -> Result<Vec<u8>> {
         Ok(bincode::serialize(value)?)
@@ -131,4 +177,117 @@ impl Client {
     fn deserialize<'a, V: Deserialize<'a>>(bytes: &'a [u8]) -> Result<V> {
         Ok(bincode::deserialize(bytes)?)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+    use tonic::{Request, Response};
+
+    use crate::error::RpcResult;
+    use crate::proto::featherkv::{FeatherKv, FeatherKvServer};
+    use super::*;
+
+    /// A fake FeatherKV node which counts the requests it receives. The leader serves all
+    /// requests. A follower either redirects them to the leader, or replies like a proxying
+    /// follower that doesn't have the client's session.
+    struct FakeNode {
+        /// The leader's address, or None if this node is the leader.
+        leader: Option<String>,
+        redirect: bool,
+        requests: Arc<AtomicU64>,
+    }
+
+    impl FakeNode {
+        /// Returns the status for a request, given the status of a proxying follower.
+        fn status(&self, proxied: RpcStatus) -> Result<Vec<u8>> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            match &self.leader {
+                None => Client::serialize(&RpcStatus::Ok),
+                Some(leader) if self.redirect => {
+                    Client::serialize(&RpcStatus::RedirectToLeader { leader: leader.clone() })
+                }
+                Some(_) => Client::serialize(&proxied),
+            }
+        }
+
+        fn execute(&self) -> Result<ExecutionReply> {
+            Ok(ExecutionReply {
+                status: self.status(RpcStatus::SessionExpired)?,
+                response: Client::serialize(&Ok::<_, Error>(b"ok".to_vec()))?,
+                leader_hint: 1,
+            })
+        }
+    }
+
+    #[tonic::async_trait]
+    impl FeatherKv for FakeNode {
+        async fn register(&self, _: Request<RegistrationRequest>) -> RpcResult<RegistrationReply> {
+            let status = self.status(RpcStatus::NotLeader)?;
+            Ok(Response::new(RegistrationReply { status, session_id: 1, leader_hint: 1 }))
+        }
+
+        async fn mutate(&self, _: Request<ExecutionRequest>) -> RpcResult<ExecutionReply> {
+            Ok(Response::new(self.execute()?))
+        }
+
+        async fn query(&self, _: Request<ExecutionRequest>) -> RpcResult<ExecutionReply> {
+            Ok(Response::new(self.execute()?))
+        }
+    }
+
+    /// Serves a fake node on a local port, returning its address.
+    async fn serve(node: FakeNode) -> Result<String> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        tokio::spawn(
+            Server::builder()
+                .add_service(FeatherKvServer::new(node))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        Ok(addr)
+    }
+
+    /// Sends mutations to a follower (node 0) of a two-node cluster, returning the number of
+    /// requests the cluster received.
+    async fn round_trips(redirect: bool, mutations: u64) -> Result<u64> {
+        let requests = Arc::new(AtomicU64::new(0));
+        let leader = serve(FakeNode { leader: None, redirect, requests: requests.clone() }).await?;
+        let follower = serve(FakeNode {
+            leader: Some(leader.clone()),
+            redirect,
+            requests: requests.clone(),
+        })
+        .await?;
+
+        let mut client = Client::new(vec![follower, leader]).await?;
+        for _ in 0..mutations {
+            client.last_leader = 0;
+            assert_eq!(b"ok".to_vec(), client.mutate(vec![]).await?);
+        }
+        Ok(requests.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn redirect_to_leader() -> Result<()> {
+        // A proxying follower doesn't have the session, so each mutation re-registers it with
+        // the leader, while a redirect retries it against the leader straight away.
+        assert_eq!(30, round_trips(false, 10).await?);
+        assert_eq!(20, round_trips(true, 10).await?);
+
+        // Clients connect to leaders they don't know about.
+        let requests = Arc::new(AtomicU64::new(0));
+        let leader = serve(FakeNode { leader: None, redirect: true, requests: requests.clone() })
+            .await?;
+        let follower =
+            serve(FakeNode { leader: Some(leader.clone()), redirect: true, requests }).await?;
+        let mut client = Client::new(vec![follower]).await?;
+        assert_eq!(b"ok".to_vec(), client.mutate(vec![]).await?);
+        assert_eq!(vec![client.addrs[0].clone(), leader], client.addrs);
+        assert_eq!(1, client.last_leader);
+        Ok(())
+    }
+}
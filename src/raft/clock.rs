@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::error::{Error, Result};
+
+/// A vector clock over Raft groups, mapping each shard ID to the last log index applied on it.
+/// Used to track causal dependencies between reads and writes that span several shards.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorClock {
+    entries: HashMap<String, u64>,
+}
+
+impl VectorClock {
+    /// Creates an empty vector clock.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the applied index recorded for a shard, or 0 if the shard is unknown.
+    pub fn get(&self, shard_id: &str) -> u64 {
+        self.entries.get(shard_id).copied().unwrap_or(0)
+    }
+
+    /// Advances a shard's entry to an index. Entries never move backwards.
+    pub fn advance(&mut self, shard_id: &str, index: u64) {
+        let entry = self.entries.entry(shard_id.to_string()).or_insert(0);
+        *entry = std::cmp::max(*entry, index);
+    }
+
+    /// Returns whether every entry in this clock is at least as recent as in the other clock.
+    /// Shards missing from either clock count as index 0.
+    pub fn dominates(&self, other: &VectorClock) -> bool {
+        other.entries.iter().all(|(shard_id, &index)| self.get(shard_id) >= index)
+    }
+
+    /// Returns a new clock holding the latest index for each shard in either clock.
+    pub fn merge(&self, other: &VectorClock) -> VectorClock {
+        let mut merged = self.clone();
+        for (shard_id, &index) in other.entries.iter() {
+            merged.advance(shard_id, index);
+        }
+        merged
+    }
+}
+
+/// The applied index of the local replica of a shard. Reads carrying a client's min_clock wait
+/// on it until the replica has caught up, so they don't return state older than the client saw.
+#[derive(Debug)]
+pub struct ShardClock {
+    shard_id: String,
+    applied: AtomicU64,
+    notify: Notify,
+}
+
+impl ShardClock {
+    /// Creates a clock for a shard that hasn't applied any entries.
+    pub fn new(shard_id: impl Into<String>) -> Self {
+        Self { shard_id: shard_id.into(), applied: AtomicU64::new(0), notify: Notify::new() }
+    }
+
+    /// Records that the replica has applied entries up to an index, waking waiting reads.
+    pub fn applied(&self, index: u64) {
+        self.applied.fetch_max(index, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Returns a vector clock holding the replica's applied index.
+    pub fn clock(&self) -> VectorClock {
+        let mut clock = VectorClock::new();
+        clock.advance(&self.shard_id, self.applied.load(Ordering::SeqCst));
+        clock
+    }
+
+    /// Waits until the replica has applied the shard's index in min_clock. Errors if it hasn't
+    /// caught up within the timeout, e.g. because it's partitioned from the leader.
+    pub async fn wait(&self, min_clock: &VectorClock, timeout: Duration) -> Result<()> {
+        let min_index = min_clock.get(&self.shard_id);
+        let caught_up = async {
+            loop {
+                // Registers for notifications before checking, so an index applied in between
+                // isn't missed.
+                let notified = self.notify.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.applied.load(Ordering::SeqCst) >= min_index {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, caught_up).await.map_err(|_| {
+            Error::Internal(format!(
+                "Timed out after {:?} waiting for shard {} to apply index {}, at {}",
+                timeout,
+                self.shard_id,
+                min_index,
+                self.applied.load(Ordering::SeqCst)
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance() {
+        let mut clock = VectorClock::new();
+        assert_eq!(0, clock.get("a"));
+        clock.advance("a", 3);
+        assert_eq!(3, clock.get("a"));
+        clock.advance("a", 2);
+        assert_eq!(3, clock.get("a"));
+    }
+
+    #[test]
+    fn dominates() {
+        let mut a = VectorClock::new();
+        let mut b = VectorClock::new();
+        assert!(a.dominates(&b));
+
+        a.advance("x", 2);
+        b.advance("x", 1);
+        assert!(a.dominates(&b));
+        assert!(!b.dominates(&a));
+
+        b.advance("y", 1);
+        assert!(!a.dominates(&b));
+        assert!(!b.dominates(&a));
+    }
+
+    #[test]
+    fn merge() {
+        let mut a = VectorClock::new();
+        a.advance("x", 5);
+        a.advance("y", 1);
+        let mut b = VectorClock::new();
+        b.advance("y", 4);
+        b.advance("z", 2);
+
+        let merged = a.merge(&b);
+        assert_eq!(5, merged.get("x"));
+        assert_eq!(4, merged.get("y"));
+        assert_eq!(2, merged.get("z"));
+        assert!(merged.dominates(&a));
+        assert!(merged.dominates(&b));
+    }
+
+    #[tokio::test]
+    async fn wait_blocks_stale_reads() -> Result<()> {
+        let shard = ShardClock::new("x");
+        shard.applied(1);
+        let mut min_clock = VectorClock::new();
+        min_clock.advance("x", 2);
+        assert!(matches!(
+            shard.wait(&min_clock, Duration::from_millis(20)).await,
+            Err(Error::Internal(_))
+        ));
+
+        // Other shards' entries don't concern this replica.
+        let mut other = VectorClock::new();
+        other.advance("y", 5);
+        shard.wait(&other, Duration::from_millis(20)).await
+    }
+
+    #[tokio::test]
+    async fn wait_serves_after_catch_up() -> Result<()> {
+        let shard = std::sync::Arc::new(ShardClock::new("x"));
+        let mut min_clock = VectorClock::new();
+        min_clock.advance("x", 3);
+        let applier = shard.clone();
+        tokio::spawn(async move {
+            for index in 1..=3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                applier.applied(index);
+            }
+        });
+        shard.wait(&min_clock, Duration::from_secs(5)).await?;
+        assert!(shard.clock().dominates(&min_clock));
+        Ok(())
+    }
+}
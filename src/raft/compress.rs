@@ -0,0 +1,171 @@
+//! LZ4 block compression, used for large log entries. The compressed form is the uncompressed
+//! length as a little-endian u32, followed by an LZ4 block: a series of sequences, each a token
+//! byte holding the literal and match lengths, the literal bytes, and a match given by its u16
+//! offset back into the output. The last sequence only has literals.
+
+use crate::error::{Error, Result};
+
+/// The minimum length of a match.
+const MIN_MATCH: usize = 4;
+/// Matches must not start within this many bytes of the end of the input.
+const MATCH_START_LIMIT: usize = 12;
+/// The input must end with at least this many literals.
+const END_LITERALS: usize = 5;
+/// The maximum offset of a match.
+const MAX_OFFSET: usize = u16::MAX as usize;
+/// The number of bits in a match table hash.
+const HASH_BITS: u32 = 12;
+
+/// Compresses bytes.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(4 + input.len() / 2);
+    output.extend((input.len() as u32).to_le_bytes());
+
+    // The last position (plus one) at which each hashed 4-byte sequence was seen.
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let (mut anchor, mut pos) = (0, 0);
+    while pos + MATCH_START_LIMIT < input.len() {
+        let sequence = read_u32(input, pos);
+        let hash = (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+        let candidate = table[hash].checked_sub(1);
+        table[hash] = pos + 1;
+        match candidate {
+            Some(candidate)
+                if pos - candidate <= MAX_OFFSET && read_u32(input, candidate) == sequence =>
+            {
+                let mut len = MIN_MATCH;
+                while pos + len < input.len() - END_LITERALS
+                    && input[candidate + len] == input[pos + len]
+                {
+                    len += 1;
+                }
+                write_sequence(&mut output, &input[anchor..pos], Some((pos - candidate, len)));
+                pos += len;
+                anchor = pos;
+            }
+            _ => pos += 1,
+        }
+    }
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+/// Decompresses bytes compressed by compress().
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>> {
+    let invalid = || Error::Corruption("Invalid compressed data".into());
+    let size = input.get(..4).ok_or_else(invalid)?;
+    let size = u32::from_le_bytes(size.try_into()?) as usize;
+    let mut output = Vec::with_capacity(size.min(input.len() * 255));
+    let mut pos = 4;
+    while pos < input.len() {
+        let token = input[pos];
+        pos += 1;
+        let literals = read_length(input, &mut pos, token >> 4)?;
+        let end = pos.checked_add(literals).ok_or_else(invalid)?;
+        output.extend_from_slice(input.get(pos..end).ok_or_else(invalid)?);
+        pos = end;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = input.get(pos..pos + 2).ok_or_else(invalid)?;
+        let offset = u16::from_le_bytes(offset.try_into()?) as usize;
+        pos += 2;
+        let len = read_length(input, &mut pos, token & 0x0f)? + MIN_MATCH;
+        if offset == 0 || offset > output.len() || output.len() + len > size {
+            return Err(invalid());
+        }
+        // The match may overlap the bytes it copies, so copy them one at a time.
+        let start = output.len() - offset;
+        for i in start..start + len {
+            output.push(output[i]);
+        }
+    }
+    if output.len() != size {
+        return Err(invalid());
+    }
+    Ok(output)
+}
+
+/// Reads a little-endian u32 at a position.
+fn read_u32(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]])
+}
+
+/// Writes a sequence of literals, followed by a match of the given offset and length.
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], r#match: Option<(usize, usize)>) {
+    let match_len = r#match.map_or(0, |(_, len)| len - MIN_MATCH);
+    output.push(((literals.len().min(15) as u8) << 4) | match_len.min(15) as u8);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+    if let Some((offset, _)) = r#match {
+        output.extend((offset as u16).to_le_bytes());
+        write_length(output, match_len);
+    }
+}
+
+/// Writes the remainder of a length that doesn't fit in its 4-bit token field, as a series of
+/// bytes that are added up, terminated by a byte below 255.
+fn write_length(output: &mut Vec<u8>, len: usize) {
+    if len < 15 {
+        return;
+    }
+    let mut rest = len - 15;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+/// Reads a length given its 4-bit token field, see write_length().
+fn read_length(input: &[u8], pos: &mut usize, field: u8) -> Result<usize> {
+    let mut len = field as usize;
+    if field < 15 {
+        return Ok(len);
+    }
+    loop {
+        let byte = *input.get(*pos).ok_or_else(|| {
+            Error::Corruption("Invalid compressed data".into())
+        })?;
+        *pos += 1;
+        len += byte as usize;
+        if byte < 255 {
+            return Ok(len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let random: Vec<u8> = (0..5000).map(|_| rng.gen()).collect();
+        let text = "INSERT INTO movies VALUES (1, 'Stalker', 1979, 8.2); ".repeat(100);
+        let runs: Vec<u8> = (0..10_000).map(|i| (i / 1000) as u8).collect();
+        for input in [&b""[..], b"a", b"abcdabcdabcdabcdabcd", &random, text.as_bytes(), &runs] {
+            assert_eq!(input, decompress(&compress(input))?);
+        }
+
+        // Repetitive data compresses well, while random data grows slightly.
+        assert!(compress(text.as_bytes()).len() < text.len() / 20);
+        assert!(compress(&runs).len() < 200);
+        assert!(compress(&random).len() < random.len() + random.len() / 100 + 16);
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt() {
+        let compressed = compress("abcdefgh".repeat(10).as_bytes());
+        assert!(decompress(&compressed[..2]).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+        let mut bad_size = compressed.clone();
+        bad_size[0] += 1;
+        assert!(decompress(&bad_size).is_err());
+    }
+}
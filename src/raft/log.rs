@@ -3,7 +3,27 @@ use std::ops::RangeBounds;
 use serde::{Deserialize, Serialize};
 
 use crate::{storage::log::{LogStore, Range}, error::{Result, Error}};
-use super::Command;
+use super::{compress, Command, Membership};
+
+/// The header byte of an encoded entry holding the serialized entry.
+const PLAIN: u8 = 0;
+/// The header byte of an encoded entry holding the compressed serialized entry.
+const COMPRESSED: u8 = 1;
+
+/// Log settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    /// Whether to compress large entries, both when storing and replicating them.
+    pub compress: bool,
+    /// The serialized size above which entries are compressed, in bytes.
+    pub compression_threshold: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { compress: false, compression_threshold: 1024 }
+    }
+}
 
 /// A replicated log entry
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -16,6 +36,32 @@ pub struct Entry {
     pub command: Command,
 }
 
+impl Entry {
+    /// The size of the serialized entry, in bytes.
+    pub fn uncompressed_size(&self) -> usize {
+        bincode::serialized_size(self).map_or(0, |size| size as usize)
+    }
+
+    /// The size of the serialized entry once compressed, in bytes.
+    pub fn compressed_size(&self) -> usize {
+        bincode::serialize(self).map_or(0, |bytes| compress::compress(&bytes).len())
+    }
+}
+
+/// A snapshot of the state machine, replacing the log entries up to and including its last
+/// included entry.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The index of the last entry included in the snapshot.
+    pub last_index: u64,
+    /// The term of the last entry included in the snapshot.
+    pub last_term: u64,
+    /// The cluster configuration as of the last included entry.
+    pub membership: Membership,
+    /// The serialized state machine.
+    pub data: Vec<u8>,
+}
+
 pub type Scan<'a> = Box<dyn Iterator<Item = Result<Entry>> + 'a>;
 
 pub struct Log {
@@ -29,36 +75,71 @@ pub struct Log {
     pub(super) commit_index: u64,
     /// The term of the last committed entry.
     pub(super) commit_term: u64,
+    /// The index of the last entry included in the latest snapshot, or 0 if none.
+    pub(super) snapshot_index: u64,
+    /// The term of the last entry included in the latest snapshot, or 0 if none.
+    pub(super) snapshot_term: u64,
+    /// The log settings.
+    pub(super) config: LogConfig,
 }
 
 impl Log {
+    /// The store metadata key of the latest snapshot.
+    const SNAPSHOT_KEY: &'static [u8] = b"snapshot";
+    /// The store metadata key of the current term and vote.
+    const TERM_KEY: &'static [u8] = b"term";
+
     /// Creates a new log, using a LogStore for storage.
     pub fn new(store: Box<dyn LogStore>) -> Result<Log> {
+        Self::with_config(store, LogConfig::default())
+    }
+
+    /// Creates a new log with the given configuration. Entries already in the store are read
+    /// whether or not they were compressed.
+    pub fn with_config(store: Box<dyn LogStore>, config: LogConfig) -> Result<Log> {
+        let (snapshot_index, snapshot_term) = match store.get_metadata(Self::SNAPSHOT_KEY)? {
+            Some(bytes) => {
+                let snapshot: Snapshot = Self::deserialize(&bytes)?;
+                (snapshot.last_index, snapshot.last_term)
+            }
+            None => (0, 0),
+        };
         let (commit_index, commit_term) = match store.commit_index() {
             0 => (0, 0),
+            index if index == snapshot_index => (snapshot_index, snapshot_term),
             index => store
                 .get(index)?
-                .map(|v| Self::deserialize::<Entry>(&v))
+                .map(|v| Self::decode(&v))
                 .transpose()?
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal("Committed entry not found".into()))?,
         };
         let (last_index, last_term) = match store.len() {
             0 => (0, 0),
+            index if index == snapshot_index => (snapshot_index, snapshot_term),
             index => store
                 .get(index)?
-                .map(|v| Self::deserialize::<Entry>(&v))
+                .map(|v| Self::decode(&v))
                 .transpose()?
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal("Last entry not found".into()))?,
         };
-        Ok(Log { store, last_index, last_term, commit_index, commit_term })
+        Ok(Log {
+            store,
+            last_index,
+            last_term,
+            commit_index,
+            commit_term,
+            snapshot_index,
+            snapshot_term,
+            config,
+        })
     }
 
     /// Appends a command to the log, returning the entry.
     pub fn append(&mut self, term: u64, command: Command) -> Result<Entry> {
         let entry = Entry { index: self.last_index + 1, term, command };
-        self.store.append(Self::serialize(&entry)?)?;
+        self.store.append(self.encode(&entry)?)?;
         self.last_index = entry.index;
         self.last_term = entry.term;
         Ok(entry)
@@ -66,18 +147,77 @@ impl Log {
 
     /// Commits entries up to and including an index.
     pub fn commit(&mut self, index: u64) -> Result<u64> {
-        let entry = self
-            .get(index)?
+        let term = self
+            .term(index)?
             .ok_or_else(|| Error::Internal(format!("Entry {} not found", index)))?;
         self.store.commit(index)?;
-        self.commit_index = entry.index;
-        self.commit_term = entry.term;
+        self.commit_index = index;
+        self.commit_term = term;
         Ok(index)
     }
 
     /// Fetches an entry at an index.
     pub fn get(&self, index: u64) -> Result<Option<Entry>> {
-        self.store.get(index)?.map(|v| Self::deserialize(&v)).transpose()
+        self.store.get(index)?.map(|v| Self::decode(&v)).transpose()
+    }
+
+    /// Returns the term of the entry at an index, or None if it does not exist. This includes
+    /// the last entry of the latest snapshot, but not the other compacted entries.
+    pub fn term(&self, index: u64) -> Result<Option<u64>> {
+        match index {
+            0 => Ok(None),
+            i if i == self.snapshot_index => Ok(Some(self.snapshot_term)),
+            i => Ok(self.get(i)?.map(|e| e.term)),
+        }
+    }
+
+    /// Saves the current term and the vote cast in it, which must be durable before the node
+    /// acts on them.
+    pub fn save_term(&mut self, term: u64, voted_for: Option<u64>) -> Result<()> {
+        self.store.set_metadata(Self::TERM_KEY, Self::serialize(&(term, voted_for))?)
+    }
+
+    /// Loads the saved term and vote, or (0, None) if none were saved.
+    pub fn load_term(&self) -> Result<(u64, Option<u64>)> {
+        match self.store.get_metadata(Self::TERM_KEY)? {
+            Some(bytes) => Self::deserialize(&bytes),
+            None => Ok((0, None)),
+        }
+    }
+
+    /// Fetches the latest snapshot, if any.
+    pub fn snapshot(&self) -> Result<Option<Snapshot>> {
+        self.store.get_metadata(Self::SNAPSHOT_KEY)?.map(|v| Self::deserialize(&v)).transpose()
+    }
+
+    /// Stores a snapshot and discards the log entries it includes. If the log contains the
+    /// snapshot's last entry, the following entries are retained. Otherwise, the log is
+    /// replaced by the snapshot, e.g. when a lagging follower installs one from the leader.
+    /// Snapshots older than the latest one are ignored.
+    pub fn save_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        if snapshot.last_index <= self.snapshot_index {
+            return Ok(());
+        }
+        let (index, term) = (snapshot.last_index, snapshot.last_term);
+        let retain = self.term(index)? == Some(term);
+        self.store.set_metadata(Self::SNAPSHOT_KEY, Self::serialize(&snapshot)?)?;
+        if !retain {
+            // A mismatched entry at the snapshot index can't be committed, so neither can the
+            // entries following it.
+            if self.last_index > index {
+                self.store.truncate(index)?;
+            }
+            self.last_index = index;
+            self.last_term = term;
+        }
+        self.store.compact(index)?;
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        if self.commit_index < index {
+            self.commit_index = index;
+            self.commit_term = term;
+        }
+        Ok(())
     }
 
     /// Iterates over log entries
@@ -86,11 +226,42 @@ impl Log {
             self.store
                 .scan(Range::from(range))
                 .map(|r| 
-                    r.and_then(|v| Self::deserialize(&v))
+                    r.and_then(|v| Self::decode(&v))
                 )
         )
     }
 
+    /// Iterates over encoded log entries, as stored and replicated. See decode().
+    pub fn scan_encoded(
+        &self,
+        range: impl RangeBounds<u64>,
+    ) -> impl Iterator<Item = Result<Vec<u8>>> + '_ {
+        self.store.scan(Range::from(range))
+    }
+
+    /// Encodes an entry for storage and replication, as a header byte followed by the serialized
+    /// entry. If compression is enabled, entries above the threshold are compressed, unless that
+    /// doesn't make them smaller.
+    pub fn encode(&self, entry: &Entry) -> Result<Vec<u8>> {
+        let bytes = Self::serialize(entry)?;
+        if self.config.compress && bytes.len() > self.config.compression_threshold {
+            let compressed = compress::compress(&bytes);
+            if compressed.len() < bytes.len() {
+                return Ok([&[COMPRESSED], compressed.as_slice()].concat());
+            }
+        }
+        Ok([&[PLAIN], bytes.as_slice()].concat())
+    }
+
+    /// Decodes an entry encoded by encode().
+    pub fn decode(bytes: &[u8]) -> Result<Entry> {
+        match bytes.split_first() {
+            Some((&PLAIN, bytes)) => Self::deserialize(bytes),
+            Some((&COMPRESSED, bytes)) => Self::deserialize(&compress::decompress(bytes)?),
+            _ => Err(Error::Corruption("Invalid log entry header".into())),
+        }
+    }
+
     /// Splices a set of entries onto an offset. The entries must be contiguous, and the first entry
     /// must be at most `last_index + 1`. If an entry does not exist, append it. If an existing entry
     /// has a term mismatch, replace it and all following entries.
@@ -104,6 +275,10 @@ impl Log {
             }
         }
         for entry in entries {
+            // Entries included in the snapshot are committed, and thus match the leader's.
+            if entry.index <= self.snapshot_index {
+                continue;
+            }
             if let Some(ref current) = self.get(entry.index)? {
                 if current.term == entry.term {
                     continue;
@@ -120,10 +295,11 @@ impl Log {
     pub fn truncate(&mut self, index: u64) -> Result<u64> {
         let (index, term) = match self.store.truncate(index)? {
             0 => (0, 0),
+            i if i == self.snapshot_index => (self.snapshot_index, self.snapshot_term),
             i => self
                 .store
                 .get(i)?
-                .map(|v| Self::deserialize::<Entry>(&v))
+                .map(|v| Self::decode(&v))
                 .transpose()?
                 .map(|e| (e.index, e.term))
                 .ok_or_else(|| Error::Internal(format!("Entry {} not found", index)))?,
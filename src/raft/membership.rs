@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+
+use serde_derive::{Deserialize, Serialize};
+use tonic::transport::Channel;
+
+use crate::error::{Error, Result};
+use crate::proto::raft::raft_service_client::RaftServiceClient;
+
+/// A cluster configuration, i.e. the voting members of the cluster. A node's ID is its position
+/// in the address list, and removed nodes leave an empty address behind, such that node IDs are
+/// never reused.
+///
+/// Membership changes use joint consensus (Raft §6): the leader first appends a ConfigEntry with
+/// both the old and new configurations, during which elections and commits need a majority of
+/// each. Once that entry is committed, it appends a ConfigEntry with only the new configuration.
+/// Every node uses the latest configuration in its log, whether committed or not.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Membership {
+    /// The member addresses, or the new ones during joint consensus.
+    pub peers: Vec<String>,
+    /// The old member addresses during joint consensus, otherwise None.
+    pub old_peers: Option<Vec<String>>,
+    /// The index of the log entry that set the configuration, or 0 for the initial one.
+    pub index: u64,
+}
+
+impl Membership {
+    /// Creates an initial configuration with the given member addresses.
+    pub fn new(peers: Vec<String>) -> Self {
+        Self { peers, old_peers: None, index: 0 }
+    }
+
+    /// Creates the configuration set by a ConfigEntry at the given index. An entry without old
+    /// peers completes a membership change.
+    pub fn from_entry(index: u64, old_peers: Vec<String>, new_peers: Vec<String>) -> Self {
+        let old_peers = if old_peers.is_empty() { None } else { Some(old_peers) };
+        Self { peers: new_peers, old_peers, index }
+    }
+
+    /// Returns the node IDs in an address list.
+    fn ids(peers: &[String]) -> impl Iterator<Item = u64> + '_ {
+        peers.iter().enumerate().filter(|(_, addr)| !addr.is_empty()).map(|(id, _)| id as u64)
+    }
+
+    /// Returns the IDs of the voting members, including old members during joint consensus.
+    pub fn voters(&self) -> BTreeSet<u64> {
+        Self::ids(&self.peers).chain(self.old_peers.iter().flat_map(|p| Self::ids(p))).collect()
+    }
+
+    /// Returns whether a node is a voting member.
+    pub fn contains(&self, id: u64) -> bool {
+        self.voters().contains(&id)
+    }
+
+    /// Returns whether a membership change is in its joint consensus phase.
+    pub fn is_joint(&self) -> bool {
+        self.old_peers.is_some()
+    }
+
+    /// Returns the address of a voting member.
+    pub fn addr(&self, id: u64) -> Option<&str> {
+        [Some(&self.peers), self.old_peers.as_ref()]
+            .into_iter()
+            .flatten()
+            .find_map(|peers| peers.get(id as usize).filter(|a| !a.is_empty()))
+            .map(|addr| addr.as_str())
+    }
+
+    /// Returns whether the nodes for which `acked` is true form a majority of the members, or
+    /// of both the old and new members during joint consensus.
+    pub fn is_quorum(&self, acked: impl Fn(u64) -> bool) -> bool {
+        let majority = |peers: &[String]| {
+            let ids: Vec<u64> = Self::ids(peers).collect();
+            ids.iter().filter(|&&id| acked(id)).count() > ids.len() / 2
+        };
+        majority(&self.peers) && self.old_peers.as_ref().is_none_or(|old| majority(old))
+    }
+
+    /// Returns the new member addresses after adding a member, and the member's node ID.
+    pub fn with_peer(&self, addr: String) -> Result<(Vec<String>, u64)> {
+        if self.peers.contains(&addr) {
+            return Err(Error::Value(format!("Node {} is already a member", addr)));
+        }
+        let mut peers = self.peers.clone();
+        peers.push(addr);
+        Ok((peers, self.peers.len() as u64))
+    }
+
+    /// Returns the new member addresses after removing a member.
+    pub fn without_peer(&self, addr: &str) -> Result<Vec<String>> {
+        let mut peers = self.peers.clone();
+        match peers.iter().position(|a| a == addr) {
+            Some(id) => peers[id] = String::new(),
+            None => return Err(Error::Value(format!("Node {} is not a member", addr))),
+        }
+        Ok(peers)
+    }
+
+    /// Creates a client for a member, which connects on first use.
+    pub fn client(&self, id: u64) -> Result<RaftServiceClient<Channel>> {
+        let addr = self
+            .addr(id)
+            .ok_or_else(|| Error::Internal(format!("Node {} is not a member", id)))?;
+        Ok(RaftServiceClient::new(
+            Channel::from_shared(format!("http://{}", addr))
+                .map_err(|e| Error::Value(e.to_string()))?
+                .connect_lazy(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peers(addrs: &[&str]) -> Vec<String> {
+        addrs.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn joint_quorum() -> Result<()> {
+        let old = Membership::new(peers(&["a", "b", "c"]));
+        let (new, id) = old.with_peer("d".into())?;
+        assert_eq!(3, id);
+        let (new, id) = Membership::new(new).with_peer("e".into())?;
+        assert_eq!(4, id);
+        let joint = Membership::from_entry(5, old.peers.clone(), new.clone());
+        assert!(joint.is_joint());
+        assert_eq!((0..5).collect::<BTreeSet<_>>(), joint.voters());
+
+        // A majority of the new members isn't enough without a majority of the old ones.
+        assert!(joint.is_quorum(|id| id < 3));
+        assert!(!joint.is_quorum(|id| id >= 2));
+        assert!(!joint.is_quorum(|id| id < 2));
+        assert!(Membership::from_entry(6, vec![], new.clone()).is_quorum(|id| id >= 2));
+        Ok(())
+    }
+
+    #[test]
+    fn remove_keeps_ids() -> Result<()> {
+        let membership = Membership::new(peers(&["a", "b", "c", "d", "e"]));
+        let new = membership.without_peer("b")?;
+        assert_eq!(peers(&["a", "", "c", "d", "e"]), new);
+        let new = Membership::new(Membership::new(new).without_peer("e")?).without_peer("d")?;
+        assert_eq!(peers(&["a", "", "c", "", ""]), new);
+        let (readded, id) = Membership::new(new.clone()).with_peer("b".into())?;
+        assert_eq!((peers(&["a", "", "c", "", "", "b"]), 5), (readded, id));
+        assert!(membership.without_peer("f").is_err());
+        assert!(membership.with_peer("a".into()).is_err());
+
+        let joint = Membership::from_entry(1, membership.peers, new);
+        assert_eq!(Some("b"), joint.addr(1));
+        let new = Membership::from_entry(2, vec![], joint.peers);
+        assert_eq!(vec![0, 2], new.voters().into_iter().collect::<Vec<_>>());
+        assert_eq!(None, new.addr(1));
+        assert!(new.is_quorum(|id| id == 0 || id == 2));
+        assert!(!new.is_quorum(|id| id == 0));
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+
+/// The role of a Raft node, as reported in metrics. A pre-candidate reports as a candidate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RaftRole {
+    Leader,
+    Follower,
+    Candidate,
+}
+
+impl RaftRole {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Leader => "leader",
+            Self::Follower => "follower",
+            Self::Candidate => "candidate",
+        }
+    }
+}
+
+/// The replication state of a peer, as seen by the leader.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PeerMetrics {
+    /// The next index to replicate to the peer.
+    pub next_index: u64,
+    /// The last index known to be replicated on the peer.
+    pub match_index: u64,
+}
+
+/// A point-in-time view of a Raft node's state, for observability.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RaftMetrics {
+    pub current_term: u64,
+    pub role: RaftRole,
+    pub commit_index: u64,
+    /// The last index applied to the state machine.
+    pub last_applied: u64,
+    /// The leader this node knows of, if any; itself when leader.
+    pub leader_id: Option<u64>,
+    /// The replication state of each peer, by node ID. Only populated on the leader.
+    pub peer_states: HashMap<u64, PeerMetrics>,
+}
+
+/// The metrics of a Raft node, updated by the node as its state changes and read without taking
+/// the Raft lock. The scalar metrics are atomics; peer states are behind their own lock, which
+/// is only held to copy them.
+#[derive(Debug)]
+pub struct AtomicRaftMetrics {
+    current_term: AtomicU64,
+    role: AtomicU8,
+    commit_index: AtomicU64,
+    last_applied: AtomicU64,
+    /// The leader ID, or NO_LEADER.
+    leader_id: AtomicU64,
+    peer_states: Mutex<HashMap<u64, PeerMetrics>>,
+}
+
+impl AtomicRaftMetrics {
+    const NO_LEADER: u64 = u64::MAX;
+
+    pub fn new() -> Self {
+        Self {
+            current_term: AtomicU64::new(0),
+            role: AtomicU8::new(RaftRole::Follower as u8),
+            commit_index: AtomicU64::new(0),
+            last_applied: AtomicU64::new(0),
+            leader_id: AtomicU64::new(Self::NO_LEADER),
+            peer_states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Updates the node state. The applied index is tracked separately, see applied().
+    pub fn update(
+        &self,
+        current_term: u64,
+        role: RaftRole,
+        commit_index: u64,
+        leader_id: Option<u64>,
+        peer_states: HashMap<u64, PeerMetrics>,
+    ) {
+        self.current_term.store(current_term, Ordering::Relaxed);
+        self.role.store(role as u8, Ordering::Relaxed);
+        self.commit_index.store(commit_index, Ordering::Relaxed);
+        self.leader_id.store(leader_id.unwrap_or(Self::NO_LEADER), Ordering::Relaxed);
+        *self.peer_states.lock().unwrap() = peer_states;
+    }
+
+    /// Records that the state machine has applied entries up to an index.
+    pub fn applied(&self, index: u64) {
+        self.last_applied.fetch_max(index, Ordering::Relaxed);
+    }
+
+    /// Returns the current metrics.
+    pub fn load(&self) -> RaftMetrics {
+        let role = match self.role.load(Ordering::Relaxed) {
+            r if r == RaftRole::Leader as u8 => RaftRole::Leader,
+            r if r == RaftRole::Candidate as u8 => RaftRole::Candidate,
+            _ => RaftRole::Follower,
+        };
+        RaftMetrics {
+            current_term: self.current_term.load(Ordering::Relaxed),
+            role,
+            commit_index: self.commit_index.load(Ordering::Relaxed),
+            last_applied: self.last_applied.load(Ordering::Relaxed),
+            leader_id: match self.leader_id.load(Ordering::Relaxed) {
+                Self::NO_LEADER => None,
+                id => Some(id),
+            },
+            peer_states: self.peer_states.lock().unwrap().clone(),
+        }
+    }
+}
+
+impl Default for AtomicRaftMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Serializes metrics in the Prometheus text exposition format. The role is exported as one
+/// gauge per role, set to 1 for the current one, and peers are labeled by node ID.
+pub fn to_prometheus_text(m: &RaftMetrics) -> String {
+    let mut s = String::new();
+    let mut gauge = |name: &str, help: &str, samples: Vec<(String, u64)>| {
+        writeln!(s, "# HELP raft_{} {}", name, help).unwrap();
+        writeln!(s, "# TYPE raft_{} gauge", name).unwrap();
+        for (labels, value) in samples {
+            writeln!(s, "raft_{}{} {}", name, labels, value).unwrap();
+        }
+    };
+    gauge("current_term", "The current term.", vec![("".into(), m.current_term)]);
+    gauge(
+        "role",
+        "The role of the node.",
+        [RaftRole::Leader, RaftRole::Follower, RaftRole::Candidate]
+            .iter()
+            .map(|r| (format!("{{role=\"{}\"}}", r.as_str()), (*r == m.role) as u64))
+            .collect(),
+    );
+    gauge("commit_index", "The last committed log index.", vec![("".into(), m.commit_index)]);
+    gauge("last_applied", "The last applied log index.", vec![("".into(), m.last_applied)]);
+    gauge(
+        "leader_id",
+        "The ID of the known leader, if any.",
+        m.leader_id.into_iter().map(|id| ("".into(), id)).collect(),
+    );
+    let mut peers: Vec<_> = m.peer_states.iter().collect();
+    peers.sort_by_key(|(id, _)| **id);
+    gauge(
+        "peer_next_index",
+        "The next log index to replicate to a peer.",
+        peers.iter().map(|(id, p)| (format!("{{peer=\"{}\"}}", id), p.next_index)).collect(),
+    );
+    gauge(
+        "peer_match_index",
+        "The last log index known to be replicated on a peer.",
+        peers.iter().map(|(id, p)| (format!("{{peer=\"{}\"}}", id), p.match_index)).collect(),
+    );
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_text() {
+        let metrics = RaftMetrics {
+            current_term: 3,
+            role: RaftRole::Leader,
+            commit_index: 7,
+            last_applied: 6,
+            leader_id: Some(0),
+            peer_states: HashMap::from([
+                (2, PeerMetrics { next_index: 5, match_index: 4 }),
+                (1, PeerMetrics { next_index: 8, match_index: 7 }),
+            ]),
+        };
+        let text = to_prometheus_text(&metrics);
+        assert!(text.contains("# TYPE raft_current_term gauge\nraft_current_term 3\n"));
+        assert!(text.contains("raft_role{role=\"leader\"} 1\nraft_role{role=\"follower\"} 0\n"));
+        assert!(text.contains("raft_last_applied 6\n"));
+        assert!(text.contains("raft_leader_id 0\n"));
+        assert!(text.contains(
+            "raft_peer_match_index{peer=\"1\"} 7\nraft_peer_match_index{peer=\"2\"} 4\n"
+        ));
+
+        // Without a leader, the leader gauge has no samples.
+        let text = to_prometheus_text(&RaftMetrics { leader_id: None, ..metrics });
+        assert!(text.contains("# TYPE raft_leader_id gauge\n# HELP raft_peer_next_index"));
+    }
+}
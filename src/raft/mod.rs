@@ -2,35 +2,251 @@
 #![allow(unused_variables)]
 
 mod client;
+mod clock;
+mod compress;
 mod log;
+mod membership;
+mod metrics;
 mod node;
 mod server;
 mod state;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
 pub use self::client::Client;
+pub use self::clock::{ShardClock, VectorClock};
 pub use self::node::Node;
-pub use self::log::{Log, Entry};
-pub use self::state::{ApplyMsg, ApplyResult, Driver, State};
+pub use self::log::{Log, LogConfig, Entry, Snapshot};
+pub use self::membership::Membership;
+pub use self::metrics::{to_prometheus_text, AtomicRaftMetrics, PeerMetrics, RaftMetrics, RaftRole};
+pub use self::state::{
+    ApplyMsg, ApplyResult, ClientSession, CommitNotifier, Driver, KvState, State,
+};
 pub use self::server::{Command, FeatherKV, Session, RpcStatus, Task};
+pub(crate) use self::state::APPLIED_INDEX_KEY;
 
 use crate::error::{Result, Error, RpcResult};
-use crate::proto::raft::{RequestVoteArgs, RequestVoteReply, AppendEntriesArgs};
+use crate::proto::raft::{RequestVoteArgs, RequestVoteReply, AppendEntriesArgs, AppendEntriesReply};
+use crate::proto::raft::{InstallSnapshotArgs, InstallSnapshotReply, PreVoteArgs, PreVoteReply};
+use crate::proto::raft::TimeoutNowArgs;
 use crate::proto::raft::raft_service_client::RaftServiceClient;
 use crate::storage;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 use futures::Future;
-use futures::stream::FuturesUnordered;
-use rand::Rng;
-use tokio::sync::mpsc;
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::{broadcast, mpsc};
 use tonic::transport::Channel;
 
-/// The interval between leader heartbeats, in ticks.
+/// The default interval between leader heartbeats, in ticks.
 const HEARTBEAT_INTERVAL: u64 = 1;
-/// The minimum election timeout, in ticks.
+/// The default minimum election timeout, in ticks.
 const ELECTION_TIMEOUT_MIN: u64 = 8 * HEARTBEAT_INTERVAL;
-/// The maximum election timeout, in ticks.
+/// The default maximum election timeout, in ticks.
 const ELECTION_TIMEOUT_MAX: u64 = 15 * HEARTBEAT_INTERVAL;
+/// The maximum size of a snapshot chunk sent to a follower, in bytes.
+const SNAPSHOT_CHUNK_SIZE: usize = 1 << 20;
+/// The number of committed entries buffered for each subscriber, see
+/// Raft::subscribe_committed(). Subscribers falling further behind fail.
+const COMMITTED_CHANNEL_CAPACITY: usize = 4096;
+
+/// How the leader decides that a log entry is committed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplicationProtocol {
+    /// An entry commits once `min_replicas` nodes, counting the leader, have appended it. A
+    /// threshold of 0 falls back to a majority of the cluster.
+    Synchronous { min_replicas: usize },
+    /// An entry commits as soon as the leader has appended it locally. Followers catch up in the
+    /// background, so committed entries may be lost if the leader fails.
+    Asynchronous,
+}
+
+/// Leader replication settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LeaderConfig {
+    /// The maximum total size of the serialized entries in one AppendEntries request. A single
+    /// entry larger than this is still sent on its own.
+    pub max_batch_bytes: usize,
+    /// The maximum number of entries in one AppendEntries request, or 0 for no limit.
+    pub max_append_entries: usize,
+    /// The maximum number of AppendEntries requests in flight to a peer. Further batches are
+    /// sent as the peer acknowledges earlier ones.
+    pub pipeline_depth: usize,
+    /// The number of ticks between leader heartbeats.
+    pub heartbeat_interval_ticks: u64,
+}
+
+impl Default for LeaderConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_bytes: 1 << 20,
+            max_append_entries: 0,
+            pipeline_depth: 4,
+            heartbeat_interval_ticks: HEARTBEAT_INTERVAL,
+        }
+    }
+}
+
+/// Follower settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FollowerConfig {
+    /// Whether client requests sent to a follower are handled as if it were the leader, which
+    /// replies NotLeader or SessionExpired and leaves the client to find the leader. Otherwise a
+    /// follower which knows the leader redirects clients to it straight away.
+    pub proxy_requests: bool,
+}
+
+impl Default for FollowerConfig {
+    fn default() -> Self {
+        Self { proxy_requests: true }
+    }
+}
+
+/// Raft node configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RaftConfig {
+    /// The replication protocol used to commit entries.
+    pub replication_protocol: ReplicationProtocol,
+    /// The minimum election timeout, in ticks. Followers and candidates draw a random timeout
+    /// from election_timeout_min (inclusive) to election_timeout_max (exclusive), after which
+    /// they start an election if they haven't heard from a leader. A follower that heard from
+    /// a leader within the minimum timeout also rejects pre-votes.
+    pub election_timeout_min: u64,
+    /// The maximum election timeout, in ticks, see election_timeout_min. Leadership transfers
+    /// which haven't completed within it are abandoned.
+    pub election_timeout_max: u64,
+    /// The number of ticks a leader waits for a majority of peers to acknowledge it before
+    /// assuming it is partitioned and stepping down.
+    pub step_down_ticks: u64,
+    /// Whether the node is a non-voting observer. Observers replicate the log from the leader,
+    /// but never vote or campaign for leadership.
+    pub observer: bool,
+    /// The number of entries applied since the last snapshot after which the state machine is
+    /// snapshotted and the log compacted, or 0 to never take snapshots.
+    pub snapshot_threshold: u64,
+    /// Whether to run a pre-vote before each election. A node only increments its term and
+    /// campaigns once a majority of the cluster would vote for it, so a rejoining partitioned
+    /// node can't disrupt a healthy leader.
+    pub pre_vote: bool,
+    /// How the leader replicates entries to its peers.
+    pub leader: LeaderConfig,
+    /// How followers handle client requests.
+    pub follower: FollowerConfig,
+    /// How entries are stored and replicated.
+    pub log: LogConfig,
+    /// The number of log entries after which a client session without entries of its own is
+    /// expired, or 0 to never expire sessions.
+    pub session_timeout: u64,
+    /// The ID of the Raft group in vector clocks, see VectorClock.
+    pub shard_id: String,
+    /// How long a read waits for the node to apply the index required by its min_clock before
+    /// erroring, see ShardClock::wait().
+    pub min_clock_timeout: std::time::Duration,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self {
+            replication_protocol: ReplicationProtocol::Synchronous { min_replicas: 0 },
+            election_timeout_min: ELECTION_TIMEOUT_MIN,
+            election_timeout_max: ELECTION_TIMEOUT_MAX,
+            step_down_ticks: 2 * ELECTION_TIMEOUT_MAX,
+            observer: false,
+            snapshot_threshold: 10_000,
+            pre_vote: true,
+            leader: LeaderConfig::default(),
+            follower: FollowerConfig::default(),
+            log: LogConfig::default(),
+            session_timeout: 100_000,
+            shard_id: "0".into(),
+            min_clock_timeout: std::time::Duration::from_secs(5),
+        }
+    }
+}
+
+impl RaftConfig {
+    /// Returns a builder for a configuration, starting from the defaults.
+    pub fn builder() -> RaftConfigBuilder {
+        RaftConfigBuilder { config: RaftConfig::default() }
+    }
+
+    /// Checks that the election timeouts form a non-empty range, and that the minimum election
+    /// timeout spans at least two heartbeat intervals. Otherwise followers may time out between
+    /// heartbeats of a healthy leader, causing unnecessary elections.
+    pub fn validate(&self) -> Result<()> {
+        if self.election_timeout_min >= self.election_timeout_max {
+            return Err(Error::Config(format!(
+                "election_timeout_min ({}) must be less than election_timeout_max ({})",
+                self.election_timeout_min, self.election_timeout_max
+            )));
+        }
+        let heartbeat_interval = self.leader.heartbeat_interval_ticks;
+        if self.election_timeout_min < heartbeat_interval.saturating_mul(2) {
+            return Err(Error::Config(format!(
+                "election_timeout_min ({}) must be at least twice the heartbeat interval ({})",
+                self.election_timeout_min, heartbeat_interval
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Builds a validated Raft configuration, see RaftConfig::builder(). Settings not covered by
+/// the builder keep their defaults.
+#[derive(Clone, Debug)]
+pub struct RaftConfigBuilder {
+    config: RaftConfig,
+}
+
+impl RaftConfigBuilder {
+    /// Sets the minimum election timeout, in ticks.
+    pub fn election_timeout_min(mut self, ticks: u64) -> Self {
+        self.config.election_timeout_min = ticks;
+        self
+    }
+
+    /// Sets the maximum election timeout, in ticks.
+    pub fn election_timeout_max(mut self, ticks: u64) -> Self {
+        self.config.election_timeout_max = ticks;
+        self
+    }
+
+    /// Sets the interval between leader heartbeats, in ticks.
+    pub fn heartbeat_interval(mut self, ticks: u64) -> Self {
+        self.config.leader.heartbeat_interval_ticks = ticks;
+        self
+    }
+
+    /// Sets the maximum number of entries in one AppendEntries request, or 0 for no limit.
+    pub fn max_append_entries(mut self, entries: usize) -> Self {
+        self.config.leader.max_append_entries = entries;
+        self
+    }
+
+    /// Sets the number of applied entries after which a snapshot is taken, or 0 for never.
+    pub fn snapshot_threshold(mut self, entries: u64) -> Self {
+        self.config.snapshot_threshold = entries;
+        self
+    }
+
+    /// Builds the configuration, erroring if it's invalid, see RaftConfig::validate().
+    pub fn build(self) -> Result<RaftConfig> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+/// The replication status of a peer, as seen by the leader.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReplicationStatus {
+    /// A voting follower, with the last index known to be replicated on it.
+    Follower { match_index: u64 },
+    /// A non-voting observer, with the last index known to be replicated on it.
+    Observer { match_index: u64 },
+}
 
 /// The local Raft node state machine.
 pub enum Role {
@@ -42,77 +258,122 @@ pub enum Role {
         /// The timeout before triggering an election.
         leader_seen_timeout: u64,
     },
+    PreCandidate {
+        /// Ticks elapsed since pre-vote start.
+        election_ticks: u64,
+        /// Pre-vote timeout, in ticks.
+        election_timeout: u64,
+        /// Nodes which granted a pre-vote (including ourself), when driven by Raft::step().
+        votes: HashSet<u64>,
+    },
     Candidate {
         /// Ticks elapsed since election start.
         election_ticks: u64,
         /// Election timeout, in ticks.
         election_timeout: u64,
-        /// Votes received (including ourself).
-        votes: u64,
+        /// Nodes which granted a vote (including ourself), when driven by Raft::step().
+        votes: HashSet<u64>,
     },
     Leader {
         /// Number of ticks since last heartbeat.
         heartbeat_ticks: u64,
+        /// Number of ticks since becoming leader.
+        ticks: u64,
+        /// The tick at which each peer last acknowledged an AppendEntries.
+        last_heartbeat_ack_tick: HashMap<u64, u64>,
         /// The next index to replicate to a peer.
         next_index: HashMap<u64, u64>,
         /// The last index known to be replicated on a peer.
         match_index: HashMap<u64, u64>,
+        /// The last index sent to a peer in an unacknowledged request, if ahead of its next
+        /// index. Pipelined batches continue from here.
+        sent_index: HashMap<u64, u64>,
+        /// The number of AppendEntries requests in flight to a peer.
+        in_flight: HashMap<u64, usize>,
         /// The channel to send work to.
         work_txs: HashMap<u64, mpsc::UnboundedSender<u64>>,
+        /// Non-voting observers, which are replicated to but excluded from quorums.
+        observers: HashSet<u64>,
+        /// Peers which are being sent a snapshot, and are not sent entries meanwhile.
+        snapshot_transfers: HashSet<u64>,
+        /// The leadership transfer in progress, if any. No new commands are accepted meanwhile.
+        transfer: Option<LeadershipTransfer>,
+        /// The ID of the last read-only request, incrementing from 1.
+        read_seq: u64,
+        /// Read-only queries awaiting confirmation of leadership, by request ID, along with
+        /// their read index.
+        pending_reads: HashMap<u64, (Command, u64)>,
     },
 }
 
+/// A leadership transfer in progress on the leader.
+pub struct LeadershipTransfer {
+    /// The peer to transfer leadership to.
+    target: u64,
+    /// The leader tick at which the transfer started.
+    start_tick: u64,
+    /// Whether the target has been told to start an election.
+    timeout_sent: bool,
+}
+
 impl Role {
-    fn init_follower(leader: Option<u64>) -> Role {
-        Role::Follower {
-            leader: None,
-            leader_seen_ticks: 0,
-            leader_seen_timeout: rand::thread_rng().gen_range(
-                ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX
-            ),
-        }
+    fn init_follower(leader: Option<u64>, timeout: u64) -> Role {
+        Role::Follower { leader, leader_seen_ticks: 0, leader_seen_timeout: timeout }
     }
 
-    fn init_candidate() -> Role {
-        Role::Candidate {
+    fn init_pre_candidate(me: u64, timeout: u64) -> Role {
+        Role::PreCandidate {
             election_ticks: 0,
-            election_timeout: rand::thread_rng().gen_range(
-                ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX
-            ),
-            votes: 1,
+            election_timeout: timeout,
+            votes: HashSet::from([me]),
         }
     }
 
+    fn init_candidate(me: u64, timeout: u64) -> Role {
+        Role::Candidate { election_ticks: 0, election_timeout: timeout, votes: HashSet::from([me]) }
+    }
+
     fn init_leader(
         me: u64,
-        num_peers: usize,
+        voters: BTreeSet<u64>,
         last_index: u64,
         work_txs: HashMap<u64, mpsc::UnboundedSender<u64>>
     ) -> Role {
         let mut next_index = HashMap::new();
         let mut match_index = HashMap::new();
-        for i in 0..num_peers as u64 {
+        let mut last_heartbeat_ack_tick = HashMap::new();
+        for i in voters {
             if i == me {
                 continue;
             }
             next_index.insert(i, last_index + 1);
             match_index.insert(i, 0);
+            last_heartbeat_ack_tick.insert(i, 0);
         };
         Role::Leader {
             heartbeat_ticks: 0,
+            ticks: 0,
+            last_heartbeat_ack_tick,
             next_index,
             match_index,
+            sent_index: HashMap::new(),
+            in_flight: HashMap::new(),
             work_txs,
+            observers: HashSet::new(),
+            snapshot_transfers: HashSet::new(),
+            transfer: None,
+            read_seq: 0,
+            pending_reads: HashMap::new(),
         }
     }
 }
 
 /// A single Raft node.
 pub struct Raft {
-    peers: Vec<RaftServiceClient<Channel>>,
+    /// Clients for the voting members, by node ID.
+    peers: HashMap<u64, RaftServiceClient<Channel>>,
     apply_tx: mpsc::UnboundedSender<ApplyMsg>,
     me: u64,
-    // persister
 
     /// Persistent state on all servers:
     current_term: u64,
@@ -125,14 +386,78 @@ pub struct Raft {
 
     /// Volatile state as different roles:
     role: Role,
+
+    /// The latest cluster configuration in the log.
+    membership: Membership,
+    /// The configuration the node started with, used when the log has no configuration entries.
+    initial_membership: Membership,
+
+    config: RaftConfig,
+
+    /// Notified of entries as the leader commits them, if set.
+    notifier: Option<Box<dyn CommitNotifier>>,
+    /// Publishes committed entries to subscribers, see subscribe_committed().
+    committed_tx: broadcast::Sender<Entry>,
+
+    /// Clients for the observers added while leader, by node ID.
+    observer_peers: HashMap<u64, RaftServiceClient<Channel>>,
+
+    /// A snapshot being received from the leader, with the chunks received so far.
+    incoming_snapshot: Option<Snapshot>,
+
+    /// The node's metrics, updated on every state change.
+    metrics: Arc<AtomicRaftMetrics>,
+
+    /// Generates randomized election timeouts.
+    rng: SmallRng,
+}
+
+/// A message between Raft nodes, as exchanged by Raft::tick() and Raft::step(). Nodes served
+/// over gRPC send the same requests and replies as RPCs instead.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub from: u64,
+    pub to: u64,
+    pub event: Event,
+}
+
+/// The request or reply carried by a Message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    PreVote(PreVoteArgs),
+    PreVoteReply(PreVoteReply),
+    RequestVote(RequestVoteArgs),
+    RequestVoteReply(RequestVoteReply),
+    AppendEntries(AppendEntriesArgs),
+    AppendEntriesReply(AppendEntriesReply),
+}
+
+/// The result of Raft::tick_batch().
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchTickResult {
+    /// The messages sent during the ticks.
+    pub messages: Vec<Message>,
+    /// The number of ticks performed.
+    pub ticks_consumed: u64,
+    /// Whether the node changed role, which stopped the batch early.
+    pub transitioned: bool,
+}
+
+/// The action to take after advancing a node by a tick, see Raft::advance().
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TickAction {
+    Idle,
+    PreCampaign,
+    Campaign,
+    Heartbeat,
 }
 
 impl Raft {
     /// The service or tester wants to create a Raft server. The ports
     /// of all the Raft servers (including this one) are in `peers`. This
     /// server's port is `peers[me]`. All the servers' peers arrays
-    /// have the same order. `Persister` is a place for this server to
-    /// save its persistent state, and also initially holds the most
+    /// have the same order. `log_store` is where this server saves
+    /// its log and persistent state, and also initially holds the most
     /// recent saved state, if any. `apply_tx` is a channel on which the
     /// tester or service expects Raft to send `ApplyMsg` messages.
     /// This method must return quickly.
@@ -142,27 +467,189 @@ impl Raft {
         apply_tx: mpsc::UnboundedSender<ApplyMsg>,
         log_store: Box<dyn storage::log::LogStore>,
         // peers: Vec<RaftClient>,
-        // persister: Box<dyn Persister>,
     ) -> Result<Raft> {
-        let raft = Raft {
-            peers: vec![],
-            // persister,
+        Self::with_config(me, apply_tx, log_store, RaftConfig::default())
+    }
+
+    /// Creates a Raft server with the given configuration.
+    pub fn with_config(
+        me: u64,
+        apply_tx: mpsc::UnboundedSender<ApplyMsg>,
+        log_store: Box<dyn storage::log::LogStore>,
+        config: RaftConfig,
+    ) -> Result<Raft> {
+        // A slow heartbeat only causes unnecessary elections, so the node still runs with it,
+        // but an empty election timeout range leaves it unable to draw a timeout.
+        if let Err(err) = config.validate() {
+            if config.election_timeout_min >= config.election_timeout_max {
+                return Err(err);
+            }
+            ::log::warn!("Raft node {} is misconfigured: {}", me, err);
+        }
+        let mut rng = SmallRng::from_entropy();
+        let timeout = rng.gen_range(config.election_timeout_min..config.election_timeout_max);
+        let log = Log::with_config(log_store, config.log.clone())?;
+        let (current_term, voted_for) = log.load_term()?;
+        let mut raft = Raft {
+            peers: HashMap::new(),
             apply_tx,
             me,
 
-            current_term: 0,
-            voted_for: None,
-            log: Log::new(log_store)?,
+            current_term,
+            voted_for,
+            log,
 
             commit_index: 0,
             last_applied: 0,
 
-            role: Role::init_follower(None),
+            role: Role::init_follower(None, timeout),
+
+            membership: Membership::default(),
+            initial_membership: Membership::default(),
+
+            config,
+
+            notifier: None,
+            committed_tx: broadcast::channel(COMMITTED_CHANNEL_CAPACITY).0,
+
+            observer_peers: HashMap::new(),
+
+            incoming_snapshot: None,
+
+            metrics: Arc::new(AtomicRaftMetrics::new()),
+
+            rng,
         };
+        raft.refresh_membership()?;
 
         Ok(raft)
     }
 
+    /// Sets a notifier to be called synchronously for each entry the leader commits.
+    pub fn set_commit_notifier(&mut self, notifier: Box<dyn CommitNotifier>) {
+        self.notifier = Some(notifier);
+    }
+
+    /// Returns the committed entries between two indexes, inclusive. Errors if the range
+    /// includes uncommitted entries, or entries compacted into a snapshot.
+    pub fn read_committed_entries(&self, from_index: u64, to_index: u64) -> Result<Vec<Entry>> {
+        if to_index > self.commit_index {
+            return Err(Error::Value(format!(
+                "Entry {} is not committed, the commit index is {}",
+                to_index, self.commit_index
+            )));
+        }
+        if from_index > to_index {
+            return Ok(Vec::new());
+        }
+        if self.log.snapshot_index > 0 && from_index <= self.log.snapshot_index {
+            return Err(Error::Value(format!(
+                "Entry {} is compacted into the snapshot at {}",
+                from_index.max(1),
+                self.log.snapshot_index
+            )));
+        }
+        self.log.scan(from_index..=to_index).collect()
+    }
+
+    /// Streams committed entries in index order, starting at the given index: first those
+    /// already committed, then the others as they are committed. The stream fails if the
+    /// subscriber falls too far behind, and ends when the node is dropped. Entries included
+    /// in a snapshot installed from the leader are not streamed.
+    pub fn subscribe_committed(
+        &self,
+        from_index: u64,
+    ) -> Result<impl Stream<Item = Result<Entry>> + Send + 'static> {
+        let committed_rx = self.committed_tx.subscribe();
+        let entries = self.read_committed_entries(from_index, self.commit_index)?;
+        let next = self.commit_index.max(from_index.saturating_sub(1)) + 1;
+        let committed = futures::stream::unfold(Some(committed_rx), move |rx| async move {
+            let mut rx = rx?;
+            loop {
+                match rx.recv().await {
+                    Ok(entry) if entry.index < next => continue,
+                    Ok(entry) => return Some((Ok(entry), Some(rx))),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        let err = Error::Internal(format!("Subscriber lagged by {} entries", n));
+                        return Some((Err(err), None));
+                    }
+                }
+            }
+        });
+        Ok(futures::stream::iter(entries.into_iter().map(Ok)).chain(committed))
+    }
+
+    /// Publishes a committed entry to subscribers, if any.
+    fn publish_committed(&self, entry: &Entry) {
+        if self.committed_tx.receiver_count() > 0 {
+            // Sending only fails without subscribers.
+            let _ = self.committed_tx.send(entry.clone());
+        }
+    }
+
+    /// Adds a non-voting observer, which the leader replicates entries to via the given work
+    /// channel. Observers do not count towards commit or leadership quorums.
+    pub fn add_observer(
+        &mut self,
+        id: u64,
+        client: RaftServiceClient<Channel>,
+        work_tx: mpsc::UnboundedSender<u64>,
+    ) -> Result<()> {
+        if id == self.me || self.membership.contains(id) {
+            return Err(Error::Value(format!("Node {} is already a voting member", id)));
+        }
+        let last_index = self.log.last_index;
+        match self.role {
+            Role::Leader {
+                ref mut next_index,
+                ref mut match_index,
+                ref mut work_txs,
+                ref mut observers,
+                ..
+            } => {
+                if !observers.insert(id) {
+                    return Err(Error::Value(format!("Node {} is already an observer", id)));
+                }
+                next_index.insert(id, last_index + 1);
+                match_index.insert(id, 0);
+                work_txs.insert(id, work_tx.clone());
+            }
+            _ => return Err(Error::NotLeader),
+        }
+        self.observer_peers.insert(id, client);
+
+        // Catches the observer up on the existing log.
+        if last_index > 0 {
+            work_tx.send(last_index)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the replication status of each peer, or an empty map if not the leader.
+    pub fn replication_status(&self) -> HashMap<u64, ReplicationStatus> {
+        match self.role {
+            Role::Leader { ref match_index, ref observers, .. } => match_index
+                .iter()
+                .map(|(&id, &match_index)| match observers.contains(&id) {
+                    true => (id, ReplicationStatus::Observer { match_index }),
+                    false => (id, ReplicationStatus::Follower { match_index }),
+                })
+                .collect(),
+            _ => HashMap::new(),
+        }
+    }
+
+    /// Returns the client for a voting peer or observer.
+    fn client(&self, id: u64) -> Result<RaftServiceClient<Channel>> {
+        match self.observer_peers.get(&id) {
+            Some(client) => Ok(client.clone()),
+            None => self.peers.get(&id).cloned().ok_or_else(|| {
+                Error::Internal(format!("Unknown peer {}", id))
+            }),
+        }
+    }
+
     pub fn is_leader(&self) -> bool {
         match self.role {
             Role::Leader { .. } => true,
@@ -170,10 +657,23 @@ impl Raft {
         }
     }
 
+    /// Returns the leader that client requests should be redirected to, if this node is a
+    /// follower which knows the leader and doesn't proxy requests.
+    fn redirect_target(&self) -> Option<u64> {
+        match self.role {
+            Role::Follower { leader: Some(leader), .. }
+                if !self.config.follower.proxy_requests && leader != self.me =>
+            {
+                Some(leader)
+            }
+            _ => None,
+        }
+    }
+
     pub fn leader_id(&self) -> u64 {
         match self.role {
             Role::Leader { .. } => self.me,
-            Role::Candidate { .. } => self.me,
+            Role::PreCandidate { .. } | Role::Candidate { .. } => self.me,
             Role::Follower { leader, .. } => {
                 match leader {
                     Some(leader) => leader,
@@ -183,58 +683,73 @@ impl Raft {
         }
     }
 
-    /// Saves Raft's persistent state to stable storage,
-    /// where it can later be retrieved after a crash and restart.
+    /// Publishes the current state to the metrics. Called after every state change.
+    fn update_metrics(&self) {
+        let (role, leader_id) = match self.role {
+            Role::Leader { .. } => (RaftRole::Leader, Some(self.me)),
+            Role::Follower { leader, .. } => (RaftRole::Follower, leader),
+            Role::PreCandidate { .. } | Role::Candidate { .. } => (RaftRole::Candidate, None),
+        };
+        let peer_states = match self.role {
+            Role::Leader { ref next_index, ref match_index, .. } => next_index
+                .iter()
+                .map(|(&id, &next_index)| {
+                    let match_index = match_index.get(&id).copied().unwrap_or(0);
+                    (id, PeerMetrics { next_index, match_index })
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+        self.metrics.update(self.current_term, role, self.commit_index, leader_id, peer_states);
+    }
+
+    /// Saves the current term and vote to the log store, where they are restored from after a
+    /// crash and restart. Panics if they can't be saved, since the node could otherwise vote
+    /// twice in a term.
     fn persist(&mut self) {
-        // Your code here (2C).
-        // Example:
-        // labcodec::encode(&self.xxx, &mut data).unwrap();
-        // labcodec::encode(&self.yyy, &mut data).unwrap();
-        // self.persister.save_raft_state(data);
-    }
-
-    /// Restores previously persisted state.
-    fn restore(&mut self, data: &[u8]) {
-        if data.is_empty() {
-            // bootstrap without any state?
-        }
-        // Your code here (2C).
-        // Example:
-        // match labcodec::decode(data) {
-        //     Ok(o) => {
-        //         self.xxx = o.xxx;
-        //         self.yyy = o.yyy;
-        //     }
-        //     Err(e) => {
-        //         panic!("{:?}", e);
-        //     }
-        // }
+        if let Err(err) = self.log.save_term(self.current_term, self.voted_for) {
+            panic!("Failed to persist term {}: {}", self.current_term, err);
+        }
     }
 
     fn start(&mut self, command: Command) -> Result<(u64, u64)> {
+        if let Role::Leader { transfer: Some(_), .. } = self.role {
+            return Err(Error::NotLeader);
+        }
         let index = self.log.last_index + 1;
         let term = self.current_term;
         self.log.append(term, command.clone())?;
 
+        // A configuration takes effect as soon as it is appended, before it is committed.
+        if let Command::ConfigEntry { old_peers, new_peers } = command {
+            self.set_membership(Membership::from_entry(index, old_peers, new_peers))?;
+        }
+
         // If there is only one server, commits the log entry and apply it immediately.
-        if self.peers.len() == 1 {
-            self.commit_index = index;
+        if self.membership.is_quorum(|id| id == self.me) {
+            self.commit_to(index)?;
             self.last_applied = index;
-            self.apply_tx.send(ApplyMsg { log_index: index, command })?;
+            self.metrics.applied(index);
             return Ok((index, term));
         }
 
-        // Sends the log entry to the replicator worker for each peer.
-        for id in 0..self.peers.len() as u64 {
-            if id == self.me {
-                continue;
-            }
-            if let Role::Leader { ref work_txs, .. } = self.role {
-                let tx = work_txs.get(&id).unwrap();
-                tx.send(index)?;
-            } else {
-                return Err(Error::Internal(format!("{} is not leader", self.me)));
+        // Under asynchronous replication the local append is enough to commit.
+        if self.should_commit(index) {
+            self.commit_to(index)?;
+        }
+
+        // Sends the log entry to the replicator worker for each peer and observer.
+        if let Role::Leader { ref work_txs, ref observers, .. } = self.role {
+            for id in self.membership.voters().into_iter().chain(observers.iter().copied()) {
+                if id == self.me {
+                    continue;
+                }
+                if let Some(tx) = work_txs.get(&id) {
+                    tx.send(index)?;
+                }
             }
+        } else {
+            return Err(Error::Internal(format!("{} is not leader", self.me)));
         }
         
         Ok((index, term))
@@ -243,74 +758,2187 @@ impl Raft {
 
 /// State transition functions.
 impl Raft {
-    fn quorum(&self) -> u64 {
-        self.peers.len() as u64 / 2 + 1
+    /// Returns whether the entry at an index has been acknowledged by enough nodes to be
+    /// committed under the configured replication protocol. Only meaningful on the leader.
+    fn should_commit(&self, index: u64) -> bool {
+        if index == 0 || index > self.log.last_index {
+            return false;
+        }
+        let min_replicas = match self.config.replication_protocol {
+            ReplicationProtocol::Asynchronous => return true,
+            ReplicationProtocol::Synchronous { min_replicas } => min_replicas,
+        };
+        let Role::Leader { ref match_index, .. } = self.role else { return false };
+        let acked = |id| id == self.me || match_index.get(&id).is_some_and(|&i| i >= index);
+        match min_replicas {
+            // A majority of the members, or of both the old and new ones during a change.
+            0 => self.membership.is_quorum(acked),
+            n => self.membership.voters().into_iter().filter(|&id| acked(id)).count() >= n,
+        }
+    }
+
+    /// Commits entries up to and including an index, sending them to the state machine.
+    fn commit_to(&mut self, index: u64) -> Result<()> {
+        if index <= self.commit_index {
+            return Ok(());
+        }
+        let entries = self.log
+            .scan((self.commit_index + 1)..=index)
+            .collect::<Result<Vec<_>>>()?;
+        for entry in entries {
+            self.publish_committed(&entry);
+            let Entry { index, term, command } = entry;
+            if let Some(notifier) = &self.notifier {
+                notifier.on_commit(index, term, &command);
+            }
+            self.apply_tx.send(ApplyMsg::Entry { log_index: index, command })?;
+        }
+        self.commit_index = index;
+
+        // Once the joint configuration of a membership change is committed, the leader moves on
+        // to the new one. Once that is committed, a leader outside of it steps down.
+        if self.is_leader() && self.membership.index > 0 && self.membership.index <= index {
+            if self.membership.is_joint() {
+                let new_peers = self.membership.peers.clone();
+                self.start(Command::ConfigEntry { old_peers: vec![], new_peers })?;
+            } else if !self.membership.contains(self.me) {
+                self.step_down("removed from the cluster");
+            }
+        }
+        self.update_metrics();
+        Ok(())
     }
 
     pub fn become_follower(&mut self, term: u64, leader_id: Option<u64>) {
+        // A vote is only given once per term, so keep it when stepping down within a term.
+        if term > self.current_term {
+            self.voted_for = None;
+        }
         self.current_term = term;
-        self.voted_for = None;
-        self.role = Role::init_follower(leader_id);
+        self.role = Role::init_follower(leader_id, self.election_timeout());
         self.persist();
+        self.update_metrics();
+    }
+
+    /// Voluntarily steps down from leadership, becoming a leaderless follower in the same term.
+    pub fn step_down(&mut self, reason: &str) {
+        if self.is_leader() {
+            ::log::warn!("Leader {} stepping down in term {}: {}", self.me, self.current_term, reason);
+            self.become_follower(self.current_term, None);
+        }
+    }
+
+    /// Records that a peer acknowledged an AppendEntries sent during the given term.
+    fn record_ack(&mut self, peer: u64, term: u64) {
+        if term != self.current_term {
+            return;
+        }
+        if let Role::Leader { ticks, ref mut last_heartbeat_ack_tick, ref observers, .. } = self.role {
+            if !observers.contains(&peer) {
+                last_heartbeat_ack_tick.insert(peer, ticks);
+            }
+        }
+    }
+
+    /// Advances a leader by a tick, stepping down if a majority of the cluster (including
+    /// itself) has not acknowledged it within the step-down timeout. Returns true if it stepped
+    /// down.
+    fn tick_leader(&mut self) -> bool {
+        let acks = match self.role {
+            Role::Leader { ref mut ticks, ref last_heartbeat_ack_tick, ref mut transfer, .. } => {
+                *ticks += 1;
+                // Gives up on a leadership transfer that hasn't completed within an election
+                // timeout, e.g. because the target is down, and resumes accepting commands.
+                if let Some(LeadershipTransfer { target, start_tick, .. }) = *transfer {
+                    if *ticks - start_tick > self.config.election_timeout_max {
+                        ::log::warn!("Leadership transfer to {} timed out", target);
+                        *transfer = None;
+                    }
+                }
+                last_heartbeat_ack_tick
+                    .iter()
+                    .filter(|(_, &tick)| *ticks - tick <= self.config.step_down_ticks)
+                    .map(|(&id, _)| id)
+                    .chain(std::iter::once(self.me))
+                    .collect::<HashSet<_>>()
+            }
+            _ => return false,
+        };
+        if self.membership.is_quorum(|id| acks.contains(&id)) {
+            return false;
+        }
+        let (acks, size) = (acks.len(), self.membership.voters().len());
+        self.step_down(&format!("only {} of {} nodes acknowledged it recently", acks, size));
+        true
+    }
+
+    /// Advances the election timer of a follower or candidate by a tick, returning true if it
+    /// timed out and should start an election. Observers and nodes outside the cluster
+    /// configuration never start elections, and simply keep waiting for a leader.
+    fn tick_election(&mut self) -> bool {
+        if self.config.observer || !self.membership.contains(self.me) {
+            return false;
+        }
+        match self.role {
+            Role::Follower { ref mut leader_seen_ticks, leader_seen_timeout, .. } => {
+                *leader_seen_ticks += 1;
+                *leader_seen_ticks >= leader_seen_timeout
+            }
+            Role::PreCandidate { ref mut election_ticks, election_timeout, .. }
+            | Role::Candidate { ref mut election_ticks, election_timeout, .. } => {
+                *election_ticks += 1;
+                *election_ticks >= election_timeout
+            }
+            Role::Leader { .. } => false,
+        }
+    }
+
+    /// Returns a random election timeout, in ticks.
+    fn election_timeout(&mut self) -> u64 {
+        self.rng.gen_range(self.config.election_timeout_min..self.config.election_timeout_max)
+    }
+
+    /// Seeds the generator of randomized election timeouts, e.g. for deterministic simulations,
+    /// and redraws the current election timeout.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+        let timeout = self.election_timeout();
+        match self.role {
+            Role::Follower { ref mut leader_seen_timeout, .. } => *leader_seen_timeout = timeout,
+            Role::PreCandidate { ref mut election_timeout, .. }
+            | Role::Candidate { ref mut election_timeout, .. } => *election_timeout = timeout,
+            Role::Leader { .. } => {}
+        }
+    }
+
+    /// Advances the node's timers by a tick, returning the action to take: a follower or
+    /// candidate whose election timer expired starts a (pre-)election, and a leader that didn't
+    /// step down sends heartbeats every heartbeat interval.
+    fn advance(&mut self) -> TickAction {
+        if !self.is_leader() {
+            return match self.tick_election() {
+                false => TickAction::Idle,
+                true if self.config.pre_vote => TickAction::PreCampaign,
+                true => TickAction::Campaign,
+            };
+        }
+        if self.tick_leader() {
+            return TickAction::Idle;
+        }
+        let interval = self.config.leader.heartbeat_interval_ticks;
+        match self.role {
+            Role::Leader { ref mut heartbeat_ticks, .. } => {
+                *heartbeat_ticks += 1;
+                if *heartbeat_ticks < interval {
+                    return TickAction::Idle;
+                }
+                *heartbeat_ticks = 0;
+                TickAction::Heartbeat
+            }
+            _ => TickAction::Idle,
+        }
+    }
+
+    /// Handles a valid heartbeat (AppendEntries) from the leader of the given term, which must
+    /// not be below the current term. Steps down if necessary, and resets the election timer on
+    /// every heartbeat, not just the one that establishes leadership.
+    fn heartbeat(&mut self, term: u64, leader_id: u64) {
+        if term > self.current_term || !matches!(self.role, Role::Follower { .. }) {
+            self.become_follower(term, Some(leader_id));
+        }
+        if let Role::Follower { ref mut leader, ref mut leader_seen_ticks, .. } = self.role {
+            *leader_seen_ticks = 0;
+            *leader = Some(leader_id);
+        }
+        self.update_metrics();
+    }
+
+    /// Starts a pre-vote for the next term, without incrementing the current term or voting.
+    pub fn become_pre_candidate(&mut self) {
+        self.role = Role::init_pre_candidate(self.me, self.election_timeout());
+        self.update_metrics();
     }
 
     pub fn become_candidate(&mut self) {
         self.current_term += 1;
-        self.role = Role::init_candidate();
+        self.role = Role::init_candidate(self.me, self.election_timeout());
         self.voted_for = Some(self.me);
         self.persist();
+        self.update_metrics();
     }
 
     pub fn become_leader(&mut self, work_txs: HashMap<u64, mpsc::UnboundedSender<u64>>) {
+        // Observers are added per leadership, so drop any from a previous one.
+        self.observer_peers.clear();
         self.role = Role::init_leader(
             self.me,
-            self.peers.len(), 
+            self.membership.voters(),
             self.log.last_index,
             work_txs,
         );
         self.persist();
+        self.update_metrics();
     }
 
-    /// Solicits votes from other nodes.
-    pub fn solicit_votes(&self) -> 
-        FuturesUnordered<impl Future<Output = RpcResult<RequestVoteReply>>> {
+    /// Registers a read-only query using the ReadIndex protocol (Raft §6.4), returning its
+    /// request ID. The current commit index becomes its read index, and the query is served once
+    /// a quorum has acknowledged a heartbeat sent after it, confirming that this node was still
+    /// the leader. Until the leader has committed an entry from its own term its commit index
+    /// may be stale, so the query is appended to the log instead, returning None.
+    fn read_index(&mut self, command: Command) -> Result<Option<u64>> {
+        if !self.is_leader() {
+            return Err(Error::NotLeader);
+        }
+        if self.log.term(self.commit_index)? != Some(self.current_term) {
+            self.start(command)?;
+            return Ok(None);
+        }
+        let commit_index = self.commit_index;
+        let Role::Leader { ref mut read_seq, ref mut pending_reads, ref transfer, .. } = self.role
+        else {
+            return Err(Error::NotLeader);
+        };
+        if transfer.is_some() {
+            return Err(Error::NotLeader);
+        }
+        *read_seq += 1;
+        let request_id = *read_seq;
+        pending_reads.insert(request_id, (command, commit_index));
+
+        // If there is only one server, its leadership needs no confirmation.
+        if self.membership.is_quorum(|id| id == self.me) {
+            self.confirm_reads(self.current_term, request_id)?;
+        }
+        Ok(Some(request_id))
+    }
+
+    /// Returns the ID of the last read-only request, to be confirmed by the next heartbeat.
+    fn read_seq(&self) -> u64 {
+        match self.role {
+            Role::Leader { read_seq, .. } => read_seq,
+            _ => 0,
+        }
+    }
+
+    /// Confirms leadership for the read-only requests up to and including a request ID, once a
+    /// quorum has acknowledged a heartbeat sent in the given term after it. The queries are sent
+    /// to the state machine behind all entries up to their read index, so they are served once
+    /// those have been applied.
+    fn confirm_reads(&mut self, term: u64, request_id: u64) -> Result<()> {
+        if term != self.current_term {
+            return Ok(());
+        }
+        let Role::Leader { ref mut pending_reads, .. } = self.role else { return Ok(()) };
+        let mut confirmed: Vec<u64> =
+            pending_reads.keys().filter(|&&id| id <= request_id).copied().collect();
+        confirmed.sort_unstable();
+        for id in confirmed {
+            let (command, read_index) = pending_reads.remove(&id).unwrap();
+            self.apply_tx.send(ApplyMsg::Read { read_index, command })?;
+        }
+        Ok(())
+    }
+
+    /// Starts transferring leadership to a voting peer. Returns the TimeoutNow message to send
+    /// it if its log is already caught up, otherwise it is caught up first and transfer_ready()
+    /// returns the message once it acknowledges the last entry.
+    fn transfer_leadership(&mut self, target: u64) -> Result<Option<TimeoutNowArgs>> {
+        if target == self.me || !self.membership.contains(target) {
+            return Err(Error::Value(format!("Can't transfer leadership to node {}", target)));
+        }
+        let last_index = self.log.last_index;
+        match self.role {
+            Role::Leader { ticks, ref work_txs, ref mut transfer, .. } => {
+                *transfer =
+                    Some(LeadershipTransfer { target, start_tick: ticks, timeout_sent: false });
+                if let Some(work_tx) = work_txs.get(&target) {
+                    work_tx.send(last_index)?;
+                }
+            }
+            _ => return Err(Error::NotLeader),
+        }
+        Ok(self.transfer_ready(target))
+    }
+
+    /// Returns the TimeoutNow message for a peer if leadership is being transferred to it and
+    /// its log is caught up with ours. The message is only returned once per transfer.
+    fn transfer_ready(&mut self, peer: u64) -> Option<TimeoutNowArgs> {
+        match self.role {
+            Role::Leader { transfer: Some(ref mut transfer), ref match_index, .. }
+                if transfer.target == peer
+                    && !transfer.timeout_sent
+                    && match_index.get(&peer) == Some(&self.log.last_index) =>
+            {
+                transfer.timeout_sent = true;
+                Some(TimeoutNowArgs { term: self.current_term, leader_id: self.me })
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a TimeoutNow message from the leader, returning true if we should immediately
+    /// start an election, bypassing the election timer and pre-vote.
+    fn timeout_now(&mut self, args: TimeoutNowArgs) -> bool {
+        if args.term < self.current_term || self.config.observer {
+            return false;
+        }
+        self.heartbeat(args.term, args.leader_id);
+        true
+    }
+
+    /// Returns whether a log with the given last entry is at least as up-to-date as ours.
+    fn log_up_to_date(&self, last_index: u64, last_term: u64) -> bool {
+        (last_term, last_index) >= (self.log.last_term, self.log.last_index)
+    }
+
+    /// Returns whether we have heard from a valid leader within the minimum election timeout,
+    /// or are the leader ourself.
+    fn leader_seen_recently(&self) -> bool {
+        match self.role {
+            Role::Leader { .. } => true,
+            Role::Follower { leader: Some(_), leader_seen_ticks, .. } => {
+                leader_seen_ticks < self.config.election_timeout_min
+            }
+            _ => false,
+        }
+    }
+
+    /// Handles a pre-vote request. The pre-vote is granted if the candidate's prospective term
+    /// is ahead of ours, we haven't heard from a leader recently, and the candidate's log is at
+    /// least as up-to-date as ours. Unlike a real vote, it doesn't change our term or vote.
+    fn pre_vote(&self, args: PreVoteArgs) -> PreVoteReply {
+        let vote_granted = !self.config.observer
+            && args.term > self.current_term
+            && !self.leader_seen_recently()
+            && self.log_up_to_date(args.last_log_index, args.last_log_term);
+        PreVoteReply { term: self.current_term, vote_granted }
+    }
+
+    /// Handles a vote request. Steps down if the candidate's term is ahead of ours, and grants
+    /// the vote unless we're an observer or already voted for another candidate in the term.
+    fn request_vote(&mut self, args: RequestVoteArgs) -> RequestVoteReply {
+        if args.term < self.current_term {
+            return RequestVoteReply { term: self.current_term, vote_granted: false };
+        }
+        if args.term > self.current_term {
+            self.become_follower(args.term, None);
+        }
+
+        // Observers never vote.
+        if self.config.observer {
+            return RequestVoteReply { term: self.current_term, vote_granted: false };
+        }
+        if self.voted_for.is_some() && self.voted_for != Some(args.candidate_id) {
+            return RequestVoteReply { term: self.current_term, vote_granted: false };
+        }
+        self.voted_for = Some(args.candidate_id);
+        self.persist();
+        RequestVoteReply { term: self.current_term, vote_granted: true }
+    }
+
+    /// Handles an AppendEntries request from a leader: resets the election timer, appends the
+    /// entries if the log matches the leader's at the previous index, and applies the entries
+    /// the leader has committed.
+    fn append_entries(&mut self, args: AppendEntriesArgs) -> Result<AppendEntriesReply> {
+        if args.term < self.current_term {
+            return Ok(AppendEntriesReply { term: self.current_term, success: false });
+        }
+
+        self.heartbeat(args.term, args.leader_id);
+
+        // Entries up to the snapshot are committed, and thus match the leader's.
+        if args.prev_log_index > self.log.snapshot_index
+            && self.log.term(args.prev_log_index)? != Some(args.prev_log_term)
+        {
+            return Ok(AppendEntriesReply { term: self.current_term, success: false });
+        }
+
+        let entries: Vec<Entry> =
+            args.entries.iter().map(|e| Log::decode(e)).collect::<Result<_>>()?;
+        let config = entries.iter().any(|e| matches!(e.command, Command::ConfigEntry { .. }));
+        self.log.splice(entries)?;
+        if config || self.membership.index > self.log.last_index {
+            self.refresh_membership()?;
+        }
+
+        // Commits entries if necessary.
+        if args.leader_commit > self.commit_index {
+            let commit_index = std::cmp::min(args.leader_commit, self.log.last_index);
+            for index in (self.commit_index + 1)..=commit_index {
+                let entry = self.log.get(index)?
+                    .ok_or(Error::Internal(format!("Expected entry at index {}", index)))?;
+                self.publish_committed(&entry);
+                let Entry { index, command, .. } = entry;
+                self.apply_tx.send(ApplyMsg::Entry { log_index: index, command })?;
+            }
+            self.commit_index = commit_index;
+            self.log.commit(commit_index)?;
+            self.update_metrics();
+        }
+
+        Ok(AppendEntriesReply { term: self.current_term, success: true })
+    }
+
+    /// Solicits pre-votes for the next term from other nodes, returning the node IDs and their
+    /// replies.
+    pub fn solicit_pre_votes(&self) ->
+        FuturesUnordered<impl Future<Output = (u64, RpcResult<PreVoteReply>)>> {
         let futures = FuturesUnordered::new();
-        for i in 0..self.peers.len() {
-            if i as u64 == self.me {
+        for (&i, client) in &self.peers {
+            if i == self.me || !self.membership.contains(i) {
                 continue;
             }
-            let mut client = self.peers[i].clone();
-            let args = RequestVoteArgs {
-                term: self.current_term,
+            let mut client = client.clone();
+            let args = PreVoteArgs {
+                term: self.current_term + 1,
                 candidate_id: self.me,
-                last_log_index: 0,
-                last_log_term: 0,
+                last_log_index: self.log.last_index,
+                last_log_term: self.log.last_term,
             };
             futures.push(async move {
-                client.request_vote(args).await
+                (i, client.pre_vote(args).await)
             });
         }
         futures
     }
 
-    /// Sends heartbeats to other nodes.
-    pub fn send_heartbeats(&self) {
-        for i in 0..self.peers.len() {
-            if i as u64 == self.me {
+    /// Solicits votes from other nodes, returning the node IDs and their replies.
+    pub fn solicit_votes(&self) -> 
+        FuturesUnordered<impl Future<Output = (u64, RpcResult<RequestVoteReply>)>> {
+        let futures = FuturesUnordered::new();
+        for (&i, client) in &self.peers {
+            if i == self.me || !self.membership.contains(i) {
                 continue;
             }
-            let mut client = self.peers[i].clone();
-            let args = AppendEntriesArgs {
+            let mut client = client.clone();
+            let args = RequestVoteArgs {
                 term: self.current_term,
-                leader_id: self.me,
+                candidate_id: self.me,
+                last_log_index: 0,
+                last_log_term: 0,
+            };
+            futures.push(async move {
+                (i, client.request_vote(args).await)
+            });
+        }
+        futures
+    }
+
+    /// Prepares the next batch of entries to replicate to a peer, returning the AppendEntries
+    /// request and the index of its last entry. The batch continues after any batches in flight,
+    /// and is cut off before exceeding the maximum batch size or entry count. Returns None if the
+    /// peer has been sent all entries, the pipeline to it is full, or this node is no longer the
+    /// leader.
+    fn next_batch(&mut self, id: u64) -> Result<Option<(AppendEntriesArgs, u64)>> {
+        let LeaderConfig { max_batch_bytes, max_append_entries, pipeline_depth, .. } =
+            self.config.leader;
+        let Role::Leader { ref next_index, ref sent_index, ref in_flight, .. } = self.role else {
+            return Ok(None);
+        };
+        let next = next_index.get(&id).copied().unwrap_or(1);
+        let start = next.max(sent_index.get(&id).map_or(0, |i| i + 1));
+        if start > self.log.last_index || in_flight.get(&id).copied().unwrap_or(0) >= pipeline_depth
+        {
+            return Ok(None);
+        }
+
+        let (mut entries, mut size) = (Vec::new(), 0);
+        for entry in self.log.scan_encoded(start..=self.log.last_index) {
+            let entry = entry?;
+            if !entries.is_empty() && size + entry.len() > max_batch_bytes
+                || max_append_entries > 0 && entries.len() >= max_append_entries
+            {
+                break;
+            }
+            size += entry.len();
+            entries.push(entry);
+        }
+        let last_index = start + entries.len() as u64 - 1;
+        let args = AppendEntriesArgs {
+            term: self.current_term,
+            leader_id: self.me,
+            prev_log_index: start - 1,
+            prev_log_term: self.log.term(start - 1)?.unwrap_or(0),
+            entries,
+            leader_commit: self.commit_index,
+        };
+        if let Role::Leader { ref mut sent_index, ref mut in_flight, .. } = self.role {
+            sent_index.insert(id, last_index);
+            *in_flight.entry(id).or_default() += 1;
+        }
+        Ok(Some((args, last_index)))
+    }
+
+    /// Records that an AppendEntries batch to a peer completed, after its next and match index
+    /// have been updated. If it failed, the following batches are sent from the peer's next index
+    /// again. Returns whether the peer has not been sent all entries yet.
+    fn finish_batch(&mut self, id: u64, failed: bool) -> bool {
+        let last_index = self.log.last_index;
+        let Role::Leader { ref next_index, ref mut sent_index, ref mut in_flight, .. } = self.role
+        else {
+            return false;
+        };
+        if let Some(count) = in_flight.get_mut(&id) {
+            *count = count.saturating_sub(1);
+        }
+        let next = next_index.get(&id).copied().unwrap_or(1);
+        if failed {
+            sent_index.remove(&id);
+        }
+        sent_index.get(&id).map_or(next, |i| next.max(i + 1)) <= last_index
+    }
+
+    /// Records that a peer rejected an AppendEntries batch because its log doesn't contain the
+    /// batch's previous entry, so replication is retried from that entry. Several pipelined
+    /// batches may be rejected together, so the next index is only ever lowered to the earliest
+    /// rejected entry. Returns whether the peer has not been sent all entries yet, see
+    /// finish_batch().
+    fn reject_batch(&mut self, id: u64, prev_log_index: u64) -> bool {
+        if let Role::Leader { ref mut next_index, .. } = self.role {
+            next_index
+                .entry(id)
+                .and_modify(|index| *index = (*index).min(prev_log_index).max(1));
+        }
+        self.finish_batch(id, true)
+    }
+
+    /// Sends heartbeats to other nodes and observers, returning the peer IDs and their replies.
+    pub fn send_heartbeats(&self) ->
+        FuturesUnordered<impl Future<Output = (u64, RpcResult<AppendEntriesReply>)>> {
+        let futures = FuturesUnordered::new();
+        let peers = self.peers.iter().filter(|(&id, _)| self.membership.contains(id));
+        for (&i, client) in peers.chain(self.observer_peers.iter()) {
+            if i == self.me {
+                continue;
+            }
+            let mut client = client.clone();
+            let args = AppendEntriesArgs {
+                term: self.current_term,
+                leader_id: self.me,
                 prev_log_index: 0,
                 prev_log_term: 0,
                 entries: vec![],
                 leader_commit: self.commit_index,
             };
-            tokio::spawn(async move {
-                client.append_entries(args).await
+            futures.push(async move {
+                (i, client.append_entries(args).await)
+            });
+        }
+        futures
+    }
+}
+
+/// Message-driven functions, which run a node without gRPC by exchanging Messages, e.g. in
+/// deterministic simulations. They cover elections and heartbeats; entries are replicated by
+/// Node's replicators.
+impl Raft {
+    /// Advances the node by a tick, returning the messages to send.
+    pub fn tick(&mut self) -> Result<Vec<Message>> {
+        match self.advance() {
+            TickAction::Idle => Ok(vec![]),
+            TickAction::PreCampaign => {
+                self.become_pre_candidate();
+                let args = PreVoteArgs {
+                    term: self.current_term + 1,
+                    candidate_id: self.me,
+                    last_log_index: self.log.last_index,
+                    last_log_term: self.log.last_term,
+                };
+                Ok(self.broadcast(false, Event::PreVote(args)))
+            }
+            TickAction::Campaign => Ok(self.campaign_messages()),
+            TickAction::Heartbeat => Ok(self.heartbeat_messages()),
+        }
+    }
+
+    /// Advances the node by up to n ticks, stopping early once it changes role, e.g. when an
+    /// election times out or a leader steps down, so the caller can react to the transition.
+    pub fn tick_batch(&mut self, n: u64) -> Result<BatchTickResult> {
+        let mut result =
+            BatchTickResult { messages: vec![], ticks_consumed: 0, transitioned: false };
+        let role = std::mem::discriminant(&self.role);
+        while result.ticks_consumed < n {
+            result.messages.extend(self.tick()?);
+            result.ticks_consumed += 1;
+            if std::mem::discriminant(&self.role) != role {
+                result.transitioned = true;
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Handles a message from another node, returning the messages to send in response.
+    pub fn step(&mut self, msg: Message) -> Result<Vec<Message>> {
+        let reply = |event| Ok(vec![Message { from: msg.to, to: msg.from, event }]);
+        match msg.event {
+            Event::PreVote(args) => reply(Event::PreVoteReply(self.pre_vote(args))),
+            Event::RequestVote(args) => reply(Event::RequestVoteReply(self.request_vote(args))),
+            Event::AppendEntries(args) => {
+                reply(Event::AppendEntriesReply(self.append_entries(args)?))
+            }
+
+            Event::PreVoteReply(PreVoteReply { term, vote_granted }) => {
+                if !vote_granted {
+                    if term > self.current_term {
+                        self.become_follower(term, None);
+                    }
+                    return Ok(vec![]);
+                }
+                let Role::PreCandidate { ref mut votes, .. } = self.role else {
+                    return Ok(vec![]);
+                };
+                votes.insert(msg.from);
+                let votes = votes.clone();
+                if !self.membership.is_quorum(|id| votes.contains(&id)) {
+                    return Ok(vec![]);
+                }
+                Ok(self.campaign_messages())
+            }
+
+            Event::RequestVoteReply(RequestVoteReply { term, vote_granted }) => {
+                if term > self.current_term {
+                    self.become_follower(term, None);
+                    return Ok(vec![]);
+                }
+                if !vote_granted || term != self.current_term {
+                    return Ok(vec![]);
+                }
+                let Role::Candidate { ref mut votes, .. } = self.role else {
+                    return Ok(vec![]);
+                };
+                votes.insert(msg.from);
+                let votes = votes.clone();
+                if !self.membership.is_quorum(|id| votes.contains(&id)) {
+                    return Ok(vec![]);
+                }
+                self.become_leader(HashMap::new());
+                Ok(self.heartbeat_messages())
+            }
+
+            Event::AppendEntriesReply(AppendEntriesReply { term, success }) => {
+                if term > self.current_term {
+                    self.become_follower(term, None);
+                } else if success {
+                    self.record_ack(msg.from, term);
+                }
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Becomes a candidate for the next term, returning the vote requests to send. If there is
+    /// only one server, it becomes leader immediately and returns heartbeats instead.
+    fn campaign_messages(&mut self) -> Vec<Message> {
+        self.become_candidate();
+        if self.membership.is_quorum(|id| id == self.me) {
+            self.become_leader(HashMap::new());
+            return self.heartbeat_messages();
+        }
+        let args = RequestVoteArgs {
+            term: self.current_term,
+            candidate_id: self.me,
+            last_log_index: self.log.last_index,
+            last_log_term: self.log.last_term,
+        };
+        self.broadcast(false, Event::RequestVote(args))
+    }
+
+    /// Returns heartbeats for the other voters and observers.
+    fn heartbeat_messages(&self) -> Vec<Message> {
+        let args = AppendEntriesArgs {
+            term: self.current_term,
+            leader_id: self.me,
+            prev_log_index: 0,
+            prev_log_term: 0,
+            entries: vec![],
+            leader_commit: self.commit_index,
+        };
+        self.broadcast(true, Event::AppendEntries(args))
+    }
+
+    /// Returns a message for each other voter, and for a leader's observers if requested.
+    fn broadcast(&self, observers: bool, event: Event) -> Vec<Message> {
+        let mut to = self.membership.voters();
+        if let (true, Role::Leader { observers, .. }) = (observers, &self.role) {
+            to.extend(observers);
+        }
+        to.remove(&self.me);
+        to.into_iter().map(|id| Message { from: self.me, to: id, event: event.clone() }).collect()
+    }
+}
+
+/// Membership functions.
+impl Raft {
+    /// Sets the configuration the cluster started with. It is used until the log or snapshot
+    /// contains a configuration entry, so it doesn't override a membership change recovered
+    /// after a restart.
+    pub fn set_initial_membership(&mut self, peers: Vec<String>) -> Result<()> {
+        self.initial_membership = Membership::new(peers);
+        self.refresh_membership()?;
+        if self.membership.index == 0 {
+            self.set_membership(self.initial_membership.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Switches to a configuration, creating clients for new members.
+    fn set_membership(&mut self, membership: Membership) -> Result<()> {
+        for id in membership.voters() {
+            if id != self.me && !self.peers.contains_key(&id) {
+                self.peers.insert(id, membership.client(id)?);
+            }
+        }
+        self.membership = membership;
+        Ok(())
+    }
+
+    /// Switches to the latest configuration entry in the log, e.g. after a restart, appending
+    /// entries from the leader or truncating an uncommitted configuration entry.
+    fn refresh_membership(&mut self) -> Result<()> {
+        match self.membership_at(self.log.last_index)? {
+            Some(membership) => self.set_membership(membership),
+            // There is no configuration entry, and the initial configuration is still in use.
+            None if self.membership.index == 0 => Ok(()),
+            None => self.set_membership(self.initial_membership.clone()),
+        }
+    }
+
+    /// Returns the configuration as of the given log index: that of the latest configuration
+    /// entry up to it, or of the snapshot if the entry was compacted. Returns None if there is
+    /// no configuration entry, i.e. the initial configuration applies.
+    fn membership_at(&self, index: u64) -> Result<Option<Membership>> {
+        let mut membership = None;
+        for entry in self.log.scan(..=index) {
+            let entry = entry?;
+            if let Command::ConfigEntry { old_peers, new_peers } = entry.command {
+                membership = Some(Membership::from_entry(entry.index, old_peers, new_peers));
+            }
+        }
+        match (membership, self.log.snapshot()?) {
+            (Some(membership), _) => Ok(Some(membership)),
+            (None, Some(snapshot)) if snapshot.membership.index > 0 => {
+                Ok(Some(snapshot.membership))
+            }
+            (None, _) => Ok(None),
+        }
+    }
+
+    /// Proposes a membership change to the given member addresses via joint consensus, see
+    /// Membership. Only one change can be in progress at a time. Returns the work channels for
+    /// the added members, which the caller must spawn replicators for.
+    fn change_membership(
+        &mut self,
+        new_peers: Vec<String>,
+    ) -> Result<HashMap<u64, mpsc::UnboundedReceiver<u64>>> {
+        if !self.is_leader() {
+            return Err(Error::NotLeader);
+        }
+        if self.membership.is_joint() || self.membership.index > self.commit_index {
+            return Err(Error::Value("A membership change is already in progress".into()));
+        }
+        let voters = Membership::new(new_peers.clone()).voters();
+        if voters.is_empty() {
+            return Err(Error::Value("The cluster must have at least one member".into()));
+        }
+        let added: Vec<u64> = voters.difference(&self.membership.voters()).copied().collect();
+        if let Some(id) = added.iter().find(|id| self.observer_peers.contains_key(id)) {
+            return Err(Error::Value(format!("Node {} is already an observer", id)));
+        }
+
+        // New members usually start out with an empty log.
+        let mut work_rxs = HashMap::new();
+        if let Role::Leader {
+            ticks,
+            ref mut next_index,
+            ref mut match_index,
+            ref mut last_heartbeat_ack_tick,
+            ref mut work_txs,
+            ..
+        } = self.role
+        {
+            for id in added {
+                let (work_tx, work_rx) = mpsc::unbounded_channel();
+                next_index.insert(id, 1);
+                match_index.insert(id, 0);
+                last_heartbeat_ack_tick.insert(id, ticks);
+                work_txs.insert(id, work_tx);
+                work_rxs.insert(id, work_rx);
+            }
+        }
+        let old_peers = self.membership.peers.clone();
+        self.start(Command::ConfigEntry { old_peers, new_peers })?;
+        Ok(work_rxs)
+    }
+}
+
+/// Snapshot functions.
+impl Raft {
+    /// Returns whether the state machine should be snapshotted after applying an index.
+    fn snapshot_due(&self, applied_index: u64) -> bool {
+        self.config.snapshot_threshold > 0
+            && applied_index >= self.log.snapshot_index + self.config.snapshot_threshold
+    }
+
+    /// Stores a snapshot of the state machine taken at an applied index, and compacts the log
+    /// up to and including it.
+    fn create_snapshot(&mut self, index: u64, data: Vec<u8>) -> Result<Snapshot> {
+        if index > self.commit_index {
+            return Err(Error::Internal(format!(
+                "Can't snapshot uncommitted index {}, commit index is {}",
+                index, self.commit_index
+            )));
+        }
+        let last_term = self
+            .log
+            .term(index)?
+            .ok_or_else(|| Error::Internal(format!("Entry {} not found", index)))?;
+        let membership =
+            self.membership_at(index)?.unwrap_or_else(|| self.initial_membership.clone());
+        let snapshot = Snapshot { last_index: index, last_term, membership, data };
+        self.log.save_snapshot(snapshot.clone())?;
+        Ok(snapshot)
+    }
+
+    /// Splits the latest snapshot into InstallSnapshot chunks for a follower, or returns None if
+    /// there is no snapshot.
+    fn snapshot_chunks(&self) -> Result<Option<Vec<InstallSnapshotArgs>>> {
+        let snapshot = match self.log.snapshot()? {
+            Some(snapshot) => snapshot,
+            None => return Ok(None),
+        };
+        let mut chunks: Vec<_> = snapshot.data.chunks(SNAPSHOT_CHUNK_SIZE).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let count = chunks.len();
+        let membership = bincode::serialize(&snapshot.membership)?;
+        Ok(Some(
+            chunks
+                .into_iter()
+                .enumerate()
+                .map(|(i, data)| InstallSnapshotArgs {
+                    term: self.current_term,
+                    leader_id: self.me,
+                    last_included_index: snapshot.last_index,
+                    last_included_term: snapshot.last_term,
+                    offset: (i * SNAPSHOT_CHUNK_SIZE) as u64,
+                    data: data.to_vec(),
+                    done: i + 1 == count,
+                    // The configuration is only needed once, with the first chunk.
+                    membership: if i == 0 { membership.clone() } else { Vec::new() },
+                })
+                .collect(),
+        ))
+    }
+
+    /// Handles an InstallSnapshot chunk from the leader. Chunks must arrive in order, starting
+    /// at offset 0. Once the last chunk arrives, the snapshot replaces the log entries it
+    /// includes, and is sent to the state machine unless the entries have already been
+    /// committed.
+    fn install_snapshot(&mut self, args: InstallSnapshotArgs) -> Result<InstallSnapshotReply> {
+        if args.term < self.current_term {
+            return Ok(InstallSnapshotReply { term: self.current_term });
+        }
+        self.heartbeat(args.term, args.leader_id);
+
+        if args.offset == 0 {
+            self.incoming_snapshot = Some(Snapshot {
+                last_index: args.last_included_index,
+                last_term: args.last_included_term,
+                membership: bincode::deserialize(&args.membership)?,
+                data: Vec::new(),
             });
         }
+        match self.incoming_snapshot {
+            Some(ref mut snapshot)
+                if snapshot.last_index == args.last_included_index
+                    && snapshot.last_term == args.last_included_term
+                    && snapshot.data.len() as u64 == args.offset =>
+            {
+                snapshot.data.extend(args.data)
+            }
+            _ => {
+                self.incoming_snapshot = None;
+                return Err(Error::Internal(format!(
+                    "Unexpected snapshot chunk at offset {}",
+                    args.offset
+                )));
+            }
+        }
+
+        if args.done {
+            let snapshot = self.incoming_snapshot.take().unwrap();
+            if snapshot.last_index > self.commit_index {
+                self.log.save_snapshot(snapshot.clone())?;
+                self.refresh_membership()?;
+                self.commit_index = snapshot.last_index;
+                self.last_applied = snapshot.last_index;
+                self.update_metrics();
+                self.apply_tx.send(ApplyMsg::Snapshot(snapshot))?;
+            }
+        }
+        Ok(InstallSnapshotReply { term: self.current_term })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(replication_protocol: ReplicationProtocol) -> Result<(Raft, mpsc::UnboundedReceiver<ApplyMsg>)> {
+        let (apply_tx, apply_rx) = mpsc::unbounded_channel();
+        let mut raft = Raft::with_config(
+            0,
+            apply_tx,
+            Box::new(storage::log::Memory::new()),
+            RaftConfig { replication_protocol, ..Default::default() },
+        )?;
+        raft.membership = Membership::new(addrs(3));
+        raft.role = Role::init_leader(0, raft.membership.voters(), 0, HashMap::new());
+        Ok((raft, apply_rx))
+    }
+
+    fn follower(me: u64) -> Result<Raft> {
+        let (apply_tx, _) = mpsc::unbounded_channel();
+        let mut raft = Raft::new(me, apply_tx, Box::new(storage::log::Memory::new()))?;
+        raft.membership = Membership::new(addrs(3));
+        Ok(raft)
+    }
+
+    /// Returns a snapshot taken with the initial configuration.
+    fn new_snapshot(last_index: u64, last_term: u64, data: Vec<u8>) -> Snapshot {
+        Snapshot { last_index, last_term, membership: Membership::default(), data }
+    }
+
+    /// Returns the given number of member addresses, which are never actually connected to.
+    fn addrs(count: u16) -> Vec<String> {
+        (1..=count).map(|port| format!("127.0.0.1:{}", port)).collect()
+    }
+
+    #[test]
+    fn follower_times_out_without_heartbeats() -> Result<()> {
+        let mut raft = follower(1)?;
+        let mut ticks = 1;
+        while !raft.tick_election() {
+            ticks += 1;
+        }
+        assert!((ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX).contains(&ticks));
+        Ok(())
+    }
+
+    /// Captures warnings logged by any test, see captured_warnings().
+    struct CaptureLogger(std::sync::Mutex<Vec<String>>);
+
+    impl ::log::Log for CaptureLogger {
+        fn enabled(&self, metadata: &::log::Metadata) -> bool {
+            metadata.level() <= ::log::Level::Warn
+        }
+
+        fn log(&self, record: &::log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger(std::sync::Mutex::new(Vec::new()));
+
+    /// Installs the capturing logger if not yet installed, and returns the warnings captured
+    /// so far.
+    fn captured_warnings() -> Vec<String> {
+        if ::log::set_logger(&LOGGER).is_ok() {
+            ::log::set_max_level(::log::LevelFilter::Warn);
+        }
+        LOGGER.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn config_builder() -> Result<()> {
+        assert_eq!(RaftConfig::default(), RaftConfig::builder().build()?);
+        let config = RaftConfig::builder()
+            .election_timeout_min(20)
+            .election_timeout_max(40)
+            .heartbeat_interval(5)
+            .max_append_entries(64)
+            .snapshot_threshold(0)
+            .build()?;
+        assert_eq!((20, 40), (config.election_timeout_min, config.election_timeout_max));
+        let leader = &config.leader;
+        assert_eq!((5, 64), (leader.heartbeat_interval_ticks, leader.max_append_entries));
+        assert_eq!(0, config.snapshot_threshold);
+
+        // Followers time out within the configured election timeouts.
+        let (apply_tx, _) = mpsc::unbounded_channel();
+        let log_store = Box::new(storage::log::Memory::new());
+        let mut raft = Raft::with_config(1, apply_tx, log_store, config)?;
+        raft.membership = Membership::new(addrs(3));
+        let mut ticks = 1;
+        while !raft.tick_election() {
+            ticks += 1;
+        }
+        assert!((20..40).contains(&ticks));
+
+        let build = |min, max, heartbeat| {
+            let builder = RaftConfig::builder().election_timeout_min(min);
+            builder.election_timeout_max(max).heartbeat_interval(heartbeat).build()
+        };
+        assert_eq!(
+            Err(Error::Config(
+                "election_timeout_min (10) must be less than election_timeout_max (10)".into()
+            )),
+            build(10, 10, 1)
+        );
+        assert_eq!(
+            Err(Error::Config(
+                "election_timeout_min (9) must be at least twice the heartbeat interval (5)".into()
+            )),
+            build(9, 20, 5)
+        );
+        assert!(build(10, 11, 5).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn misconfigured_node_warns() -> Result<()> {
+        let message =
+            "Raft node 7 is misconfigured: election_timeout_min (8) must be at least twice the \
+             heartbeat interval (10)";
+        assert!(!captured_warnings().iter().any(|w| w == message));
+
+        // A heartbeat slower than the election timeout is logged, but the node still runs.
+        let mut config = RaftConfig::default();
+        config.leader.heartbeat_interval_ticks = 10;
+        let (apply_tx, _) = mpsc::unbounded_channel();
+        Raft::with_config(7, apply_tx.clone(), Box::new(storage::log::Memory::new()), config)?;
+        assert!(captured_warnings().iter().any(|w| w == message));
+
+        // An empty election timeout range is rejected.
+        let config = RaftConfig { election_timeout_max: 8, ..Default::default() };
+        assert!(matches!(
+            Raft::with_config(7, apply_tx, Box::new(storage::log::Memory::new()), config),
+            Err(Error::Config(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn follower_heartbeat_from_new_term() -> Result<()> {
+        let mut raft = follower(1)?;
+        raft.voted_for = Some(2);
+        raft.heartbeat(3, 2);
+        assert_eq!(3, raft.current_term);
+        assert_eq!(None, raft.voted_for);
+        assert_eq!(2, raft.leader_id());
+
+        // A candidate steps down on a heartbeat from its own term, keeping its vote.
+        raft.become_candidate();
+        raft.heartbeat(4, 0);
+        assert!(matches!(raft.role, Role::Follower { leader: Some(0), .. }));
+        assert_eq!(Some(1), raft.voted_for);
+        Ok(())
+    }
+
+    #[test]
+    fn restart_restores_term_and_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let open = || -> Result<Raft> {
+            let store = storage::kv::BitcaskStore::open(dir.path())?;
+            let log_store = storage::log::KvLog::open(Box::new(store))?;
+            Raft::new(1, mpsc::unbounded_channel().0, Box::new(log_store))
+        };
+        let mut raft = open()?;
+        raft.become_candidate();
+        raft.log.append(1, Command::Registration { session_id: 1 })?;
+        raft.log.append(1, Command::Registration { session_id: 2 })?;
+        raft.log.commit(1)?;
+
+        // The node crashes without flushing, and restarts with its vote and log intact.
+        drop(raft);
+        let raft = open()?;
+        assert_eq!((1, Some(1)), (raft.current_term, raft.voted_for));
+        assert_eq!((2, 1), (raft.log.last_index, raft.log.last_term));
+        assert_eq!((1, 1), (raft.log.commit_index, raft.log.commit_term));
+        assert_eq!(
+            Command::Registration { session_id: 2 },
+            raft.log.get(2)?.unwrap().command
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn steady_heartbeats_prevent_elections() -> Result<()> {
+        // A 3-node cluster where node 0 leads and heartbeats the followers every interval.
+        let mut followers = vec![follower(1)?, follower(2)?];
+        for raft in followers.iter_mut() {
+            raft.heartbeat(1, 0);
+        }
+        let mut heartbeat_ticks = 0;
+        for tick in 1..=10_000 {
+            for raft in followers.iter_mut() {
+                assert!(!raft.tick_election(), "spurious election at tick {}", tick);
+            }
+            heartbeat_ticks += 1;
+            if heartbeat_ticks >= HEARTBEAT_INTERVAL {
+                heartbeat_ticks = 0;
+                followers.iter_mut().for_each(|raft| raft.heartbeat(1, 0));
+            }
+        }
+        for raft in followers {
+            assert_eq!(1, raft.current_term);
+            assert_eq!(0, raft.leader_id());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn pre_vote_keeps_term() -> Result<()> {
+        let mut raft = follower(1)?;
+        raft.heartbeat(2, 0);
+        raft.voted_for = Some(0);
+        raft.become_pre_candidate();
+        assert_eq!(2, raft.current_term);
+        assert_eq!(Some(0), raft.voted_for);
+        assert_eq!(1, raft.leader_id());
+
+        // A pre-candidate retries after an election timeout, and steps down on a heartbeat.
+        let mut ticks = 1;
+        while !raft.tick_election() {
+            ticks += 1;
+        }
+        assert!((ELECTION_TIMEOUT_MIN..ELECTION_TIMEOUT_MAX).contains(&ticks));
+        raft.heartbeat(2, 0);
+        assert!(matches!(raft.role, Role::Follower { leader: Some(0), .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn pre_vote_granted() -> Result<()> {
+        let mut raft = follower(1)?;
+        raft.heartbeat(2, 0);
+        raft.log.append(1, Command::Registration { session_id: 1 })?;
+        raft.log.append(2, Command::Registration { session_id: 2 })?;
+        let args = |term, last_log_index, last_log_term| PreVoteArgs {
+            term,
+            candidate_id: 2,
+            last_log_index,
+            last_log_term,
+        };
+
+        // The leader was seen recently, so the candidate would disrupt it.
+        assert!(!raft.pre_vote(args(3, 2, 2)).vote_granted);
+        for _ in 0..ELECTION_TIMEOUT_MIN {
+            raft.tick_election();
+        }
+        let reply = raft.pre_vote(args(3, 2, 2));
+        assert_eq!(PreVoteReply { term: 2, vote_granted: true }, reply);
+        assert!(raft.pre_vote(args(3, 5, 2)).vote_granted);
+        assert!(raft.pre_vote(args(3, 1, 3)).vote_granted);
+
+        // The candidate's log or prospective term is behind ours.
+        assert!(!raft.pre_vote(args(3, 1, 2)).vote_granted);
+        assert!(!raft.pre_vote(args(3, 5, 1)).vote_granted);
+        assert!(!raft.pre_vote(args(2, 2, 2)).vote_granted);
+
+        // Granting a pre-vote doesn't change our state.
+        assert_eq!(2, raft.current_term);
+        assert_eq!(None, raft.voted_for);
+        assert!(matches!(raft.role, Role::Follower { leader: Some(0), .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn leader_rejects_pre_vote() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        raft.current_term = 1;
+        let args = PreVoteArgs { term: 2, candidate_id: 1, last_log_index: 5, last_log_term: 1 };
+        assert!(!raft.pre_vote(args.clone()).vote_granted);
+        raft.step_down("test");
+        assert!(raft.pre_vote(args).vote_granted);
+        Ok(())
+    }
+
+    #[test]
+    fn leader_steps_down_when_partitioned() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        let step_down_ticks = raft.config.step_down_ticks;
+        raft.current_term = 1;
+        for _ in 0..step_down_ticks {
+            assert!(!raft.tick_leader());
+        }
+        assert!(raft.tick_leader());
+        assert!(matches!(raft.role, Role::Follower { leader: None, .. }));
+        assert_eq!(1, raft.current_term);
+
+        // As a follower, it only campaigns after a regular election timeout, not immediately.
+        assert!(!raft.tick_leader());
+        assert!(!raft.tick_election());
+        Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn leader_stays_with_quorum() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        raft.current_term = 1;
+        for _ in 0..(10 * raft.config.step_down_ticks) {
+            assert!(!raft.tick_leader());
+            raft.record_ack(1, 1);
+            raft.record_ack(2, 0); // stale acks from an earlier term don't count
+        }
+        assert!(raft.is_leader());
+        Ok(())
+    }
+
+    /// Records the index and term of notified entries.
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<(u64, u64)>>>);
+
+    impl CommitNotifier for Recorder {
+        fn on_commit(&self, index: u64, term: u64, _: &Command) {
+            self.0.lock().unwrap().push((index, term));
+        }
+    }
+
+    #[test]
+    fn commit_notifier() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 2 })?;
+        let notified = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        raft.set_commit_notifier(Box::new(Recorder(notified.clone())));
+        let ack = |raft: &mut Raft, peer: u64, index: u64| {
+            if let Role::Leader { ref mut match_index, .. } = raft.role {
+                match_index.insert(peer, index);
+            }
+        };
+
+        raft.current_term = 1;
+        raft.start(Command::Registration { session_id: 1 })?;
+        let (index, _) = raft.start(Command::Registration { session_id: 2 })?;
+        assert!(notified.lock().unwrap().is_empty());
+        ack(&mut raft, 1, index);
+        assert!(raft.should_commit(index));
+        raft.commit_to(index)?;
+        raft.commit_to(index)?;
+        assert_eq!(vec![(1, 1), (2, 1)], *notified.lock().unwrap());
+
+        // Entries appended before losing leadership are notified once a new term's entry commits.
+        raft.start(Command::Registration { session_id: 3 })?;
+        raft.start(Command::Registration { session_id: 4 })?;
+        raft.step_down("election");
+        raft.current_term = 2;
+        let voters = raft.membership.voters();
+        raft.role = Role::init_leader(0, voters, raft.log.last_index, HashMap::new());
+        let (index, _) = raft.start(Command::Registration { session_id: 5 })?;
+        assert_eq!(2, notified.lock().unwrap().len());
+        ack(&mut raft, 2, index);
+        raft.commit_to(index)?;
+        assert_eq!(vec![(1, 1), (2, 1), (3, 1), (4, 1), (5, 2)], *notified.lock().unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn read_committed_entries() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 2 })?;
+        raft.current_term = 1;
+        for session_id in 1..=5 {
+            raft.start(Command::Registration { session_id })?;
+        }
+        raft.commit_to(3)?;
+        let indexes = |entries: Vec<Entry>| entries.iter().map(|e| e.index).collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 3], indexes(raft.read_committed_entries(1, 3)?));
+        assert_eq!(vec![2], indexes(raft.read_committed_entries(2, 2)?));
+        assert!(raft.read_committed_entries(3, 2)?.is_empty());
+        assert!(raft.read_committed_entries(2, 4).is_err());
+
+        // Entries compacted into a snapshot can't be read.
+        raft.log.save_snapshot(new_snapshot(2, 1, vec![]))?;
+        assert!(raft.read_committed_entries(2, 3).is_err());
+        assert_eq!(vec![3], indexes(raft.read_committed_entries(3, 3)?));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn subscribe_committed() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 2 })?;
+        raft.current_term = 1;
+        let stream = raft.subscribe_committed(1)?;
+        for session_id in 1..=1000 {
+            raft.start(Command::Registration { session_id })?;
+            if session_id % 100 == 0 {
+                raft.commit_to(session_id)?;
+            }
+        }
+        let entries = stream.take(1000).collect::<Vec<_>>().await.into_iter();
+        let entries = entries.map(|e| e.map(|e| (e.index, e.command))).collect::<Result<Vec<_>>>()?;
+        let expect: Vec<_> =
+            (1..=1000).map(|i| (i, Command::Registration { session_id: i })).collect();
+        assert_eq!(expect, entries);
+
+        // A later subscriber gets the committed entries from its start index, followed by new
+        // ones.
+        let stream = raft.subscribe_committed(998)?;
+        raft.start(Command::Registration { session_id: 1001 })?;
+        raft.commit_to(1001)?;
+        let entries = stream.take(4).collect::<Vec<_>>().await.into_iter();
+        let indexes = entries.map(|e| e.map(|e| e.index)).collect::<Result<Vec<_>>>()?;
+        assert_eq!(vec![998, 999, 1000, 1001], indexes);
+
+        // The stream ends when the node is dropped.
+        let stream = raft.subscribe_committed(1002)?;
+        drop(raft);
+        assert_eq!(0, stream.collect::<Vec<_>>().await.len());
+        Ok(())
+    }
+
+    #[test]
+    fn asynchronous_commits_on_append() -> Result<()> {
+        let (mut raft, mut apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        let (index, _) = raft.start(Command::Registration { session_id: 1 })?;
+        assert_eq!(index, raft.commit_index);
+        assert!(matches!(
+            apply_rx.try_recv().unwrap(),
+            ApplyMsg::Entry { log_index, .. } if log_index == index
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn synchronous_waits_for_acks() -> Result<()> {
+        let (mut raft, mut apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 3 })?;
+        let (index, _) = raft.start(Command::Registration { session_id: 1 })?;
+        assert_eq!(0, raft.commit_index);
+        assert!(apply_rx.try_recv().is_err());
+
+        if let Role::Leader { ref mut match_index, .. } = raft.role {
+            match_index.insert(1, index);
+        }
+        assert!(!raft.should_commit(index));
+        if let Role::Leader { ref mut match_index, .. } = raft.role {
+            match_index.insert(2, index);
+        }
+        assert!(raft.should_commit(index));
+        Ok(())
+    }
+
+    /// Acknowledges the leader's last entry from the given peers, committing it if possible.
+    fn ack_last(raft: &mut Raft, peers: &[u64]) -> Result<()> {
+        let index = raft.log.last_index;
+        if let Role::Leader { ref mut match_index, .. } = raft.role {
+            for &id in peers {
+                match_index.insert(id, index);
+            }
+        }
+        if raft.should_commit(index) {
+            raft.commit_to(index)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn batches_are_bounded_and_pipelined() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        for session_id in 1..=10 {
+            raft.start(Command::Registration { session_id })?;
+        }
+        let size = raft.log.scan_encoded(1..=1).next().unwrap()?.len();
+        raft.config.leader =
+            LeaderConfig { max_batch_bytes: 3 * size, pipeline_depth: 2, ..Default::default() };
+        let batch = |raft: &mut Raft| -> Result<Option<(u64, u64, usize)>> {
+            let batch = raft.next_batch(1)?;
+            Ok(batch.map(|(args, last)| (args.prev_log_index, last, args.entries.len())))
+        };
+        let ack = |raft: &mut Raft, index: u64| {
+            if let Role::Leader { ref mut next_index, ref mut match_index, .. } = raft.role {
+                next_index.insert(1, index + 1);
+                match_index.insert(1, index);
+            }
+        };
+
+        // Two batches are pipelined, the second continuing after the first.
+        assert_eq!(Some((0, 3, 3)), batch(&mut raft)?);
+        assert_eq!(Some((3, 6, 3)), batch(&mut raft)?);
+        assert_eq!(None, batch(&mut raft)?);
+
+        // Acknowledging a batch makes room for the next one.
+        ack(&mut raft, 3);
+        assert!(raft.finish_batch(1, false));
+        assert_eq!(Some((6, 9, 3)), batch(&mut raft)?);
+
+        // A failed batch restarts replication from the peer's next index.
+        assert!(raft.finish_batch(1, true));
+        assert_eq!(Some((3, 6, 3)), batch(&mut raft)?);
+        assert_eq!(None, batch(&mut raft)?);
+
+        // Draining the pipeline until the log is replicated leaves nothing to send.
+        ack(&mut raft, 6);
+        raft.finish_batch(1, false);
+        assert_eq!(Some((6, 9, 3)), batch(&mut raft)?);
+        ack(&mut raft, 9);
+        raft.finish_batch(1, false);
+        ack(&mut raft, 9);
+        assert!(raft.finish_batch(1, false));
+        assert_eq!(Some((9, 10, 1)), batch(&mut raft)?);
+        ack(&mut raft, 10);
+        assert!(!raft.finish_batch(1, false));
+        assert_eq!(None, batch(&mut raft)?);
+
+        // An entry larger than the batch size is sent on its own.
+        raft.config.leader.max_batch_bytes = 1;
+        raft.start(Command::Registration { session_id: 11 })?;
+        raft.start(Command::Registration { session_id: 12 })?;
+        assert_eq!(Some((10, 11, 1)), batch(&mut raft)?);
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_pipelined_batches() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        for session_id in 1..=13 {
+            raft.start(Command::Registration { session_id })?;
+        }
+        let size = raft.log.scan_encoded(1..=1).next().unwrap()?.len();
+        raft.config.leader =
+            LeaderConfig { max_batch_bytes: 3 * size, pipeline_depth: 4, ..Default::default() };
+        if let Role::Leader { ref mut next_index, .. } = raft.role {
+            next_index.insert(1, 4);
+        }
+        let next_index = |raft: &Raft| match raft.role {
+            Role::Leader { ref next_index, .. } => next_index[&1],
+            _ => panic!("not leader"),
+        };
+
+        // All four batches in flight are rejected, in any order. Replication is retried from the
+        // earliest rejected entry, rather than backing off once per rejection.
+        let mut batches = Vec::new();
+        while let Some((args, _)) = raft.next_batch(1)? {
+            batches.push(args.prev_log_index);
+        }
+        assert_eq!(vec![3, 6, 9, 12], batches);
+        for &prev_log_index in batches.iter().rev() {
+            raft.reject_batch(1, prev_log_index);
+        }
+        assert_eq!(3, next_index(&raft));
+        let (args, _) = raft.next_batch(1)?.unwrap();
+        assert_eq!(2, args.prev_log_index);
+
+        // The next index never drops below 1, however many batches are rejected.
+        for _ in 0..4 {
+            raft.reject_batch(1, 0);
+        }
+        assert_eq!(1, next_index(&raft));
+        let (args, _) = raft.next_batch(1)?.unwrap();
+        assert_eq!((0, 0), (args.prev_log_index, args.prev_log_term));
+        Ok(())
+    }
+
+    #[test]
+    fn batching_reduces_requests() -> Result<()> {
+        // Replicates 10,000 entries with batches of at most the given number of entries, by
+        // size and by count, returning the number of requests.
+        let requests = |max_size: usize, max_append_entries: usize| -> Result<usize> {
+            let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+            for session_id in 1..=10_000 {
+                raft.start(Command::Registration { session_id })?;
+            }
+            let size = raft.log.scan_encoded(1..=1).next().unwrap()?.len();
+            raft.config.leader.max_batch_bytes = max_size * size;
+            raft.config.leader.max_append_entries = max_append_entries;
+            let mut requests = 0;
+            while let Some((_, last_index)) = raft.next_batch(1)? {
+                if let Role::Leader { ref mut next_index, ref mut match_index, .. } = raft.role {
+                    next_index.insert(1, last_index + 1);
+                    match_index.insert(1, last_index);
+                }
+                raft.finish_batch(1, false);
+                requests += 1;
+            }
+            Ok(requests)
+        };
+        assert_eq!(100, requests(100, 0)?);
+        assert_eq!(200, requests(100, 50)?);
+        assert_eq!(40, requests(1000, 250)?);
+        Ok(())
+    }
+
+    #[test]
+    fn metrics_follow_role_changes() -> Result<()> {
+        let (apply_tx, _apply_rx) = mpsc::unbounded_channel();
+        let mut raft = Raft::new(1, apply_tx, Box::new(storage::log::Memory::new()))?;
+        raft.membership = Membership::new(addrs(3));
+        let metrics = raft.metrics.clone();
+        assert_eq!(RaftRole::Follower, metrics.load().role);
+        assert_eq!(None, metrics.load().leader_id);
+
+        raft.become_candidate();
+        let m = metrics.load();
+        assert_eq!((RaftRole::Candidate, 1, None), (m.role, m.current_term, m.leader_id));
+
+        raft.become_leader(HashMap::new());
+        let m = metrics.load();
+        assert_eq!((RaftRole::Leader, 1, Some(1)), (m.role, m.current_term, m.leader_id));
+        let peer = PeerMetrics { next_index: 1, match_index: 0 };
+        assert_eq!(HashMap::from([(0, peer), (2, peer)]), m.peer_states);
+
+        // Committing an entry updates the commit index, but not the applied index, which the
+        // state machine driver reports.
+        raft.start(Command::Registration { session_id: 1 })?;
+        ack_last(&mut raft, &[0])?;
+        let m = metrics.load();
+        assert_eq!((1, 0), (m.commit_index, m.last_applied));
+        raft.metrics.applied(1);
+        assert_eq!(1, metrics.load().last_applied);
+
+        raft.heartbeat(2, 2);
+        let m = metrics.load();
+        assert_eq!((RaftRole::Follower, 2, Some(2)), (m.role, m.current_term, m.leader_id));
+        assert!(m.peer_states.is_empty());
+        Ok(())
+    }
+
+    fn query(sequence_number: u64) -> Command {
+        Command::Query { session_id: 1, sequence_number, query: vec![] }
+    }
+
+    #[test]
+    fn read_index_waits_for_confirmation() -> Result<()> {
+        let (mut raft, mut apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 0 })?;
+        raft.current_term = 1;
+        raft.start(Command::Registration { session_id: 1 })?;
+        ack_last(&mut raft, &[1])?;
+        assert!(matches!(apply_rx.try_recv(), Ok(ApplyMsg::Entry { log_index: 1, .. })));
+
+        // Reads are not appended to the log, and wait for a heartbeat quorum.
+        assert_eq!(Some(1), raft.read_index(query(1))?);
+        assert_eq!(Some(2), raft.read_index(query(2))?);
+        assert_eq!(1, raft.log.last_index);
+        assert!(apply_rx.try_recv().is_err());
+
+        // A heartbeat sent before the second read only confirms the first, and one from a
+        // previous term confirms nothing.
+        raft.confirm_reads(0, 2)?;
+        assert!(apply_rx.try_recv().is_err());
+        raft.confirm_reads(1, 1)?;
+        assert!(matches!(
+            apply_rx.try_recv(),
+            Ok(ApplyMsg::Read { read_index: 1, command: Command::Query { sequence_number: 1, .. } })
+        ));
+        assert!(apply_rx.try_recv().is_err());
+        raft.confirm_reads(1, 2)?;
+        assert!(matches!(apply_rx.try_recv(), Ok(ApplyMsg::Read { read_index: 1, .. })));
+
+        // Reads are dropped on losing leadership.
+        raft.read_index(query(3))?;
+        raft.step_down("test");
+        raft.confirm_reads(1, 3)?;
+        assert!(apply_rx.try_recv().is_err());
+        assert_eq!(Err(Error::NotLeader), raft.read_index(query(3)));
+        Ok(())
+    }
+
+    #[test]
+    fn read_index_needs_commit_in_term() -> Result<()> {
+        let (mut raft, mut apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 0 })?;
+        raft.current_term = 1;
+        raft.start(Command::Registration { session_id: 1 })?;
+        ack_last(&mut raft, &[1])?;
+        raft.current_term = 2;
+        assert!(matches!(apply_rx.try_recv(), Ok(ApplyMsg::Entry { log_index: 1, .. })));
+
+        // The commit index may be stale, so the read goes through the log.
+        assert_eq!(None, raft.read_index(query(1))?);
+        assert_eq!(2, raft.log.last_index);
+        ack_last(&mut raft, &[1])?;
+        assert!(matches!(apply_rx.try_recv(), Ok(ApplyMsg::Entry { log_index: 2, .. })));
+        assert_eq!(Some(1), raft.read_index(query(2))?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn membership_grows_from_3_to_5() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 0 })?;
+        raft.current_term = 1;
+        let (new_peers, id) = raft.membership.with_peer("127.0.0.1:4".into())?;
+        assert_eq!(3, id);
+        let work_rxs = raft.change_membership(new_peers)?;
+        assert_eq!(vec![3], work_rxs.keys().copied().collect::<Vec<_>>());
+        assert!(raft.membership.is_joint());
+        assert_eq!(1, raft.membership.index);
+        assert!(raft.peers.contains_key(&3));
+
+        // The joint configuration needs a majority of both the old and new members.
+        assert!(raft.change_membership(addrs(5)).is_err());
+        ack_last(&mut raft, &[1])?;
+        assert_eq!(0, raft.commit_index);
+        ack_last(&mut raft, &[3])?;
+        assert_eq!(1, raft.commit_index);
+
+        // Committing it appends the new configuration, which commits with a new majority.
+        assert!(!raft.membership.is_joint());
+        assert_eq!(2, raft.membership.index);
+        assert!(raft.change_membership(addrs(5)).is_err());
+        ack_last(&mut raft, &[1])?;
+        assert_eq!(1, raft.commit_index);
+        ack_last(&mut raft, &[3])?;
+        assert_eq!(2, raft.commit_index);
+
+        let (new_peers, id) = raft.membership.with_peer("127.0.0.1:5".into())?;
+        assert_eq!(4, id);
+        let _work_rxs = raft.change_membership(new_peers)?;
+        ack_last(&mut raft, &[1, 3])?;
+        ack_last(&mut raft, &[1, 3])?;
+        assert_eq!(4, raft.commit_index);
+        assert_eq!(Membership::from_entry(4, vec![], addrs(5)), raft.membership);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn membership_shrinks_from_5_to_3() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 0 })?;
+        raft.current_term = 1;
+        raft.membership = Membership::new(addrs(5));
+        raft.role = Role::init_leader(0, raft.membership.voters(), 0, HashMap::new());
+
+        // Removing node 4 needs 3 of the old 5 nodes and 3 of the new 4.
+        let new_peers = raft.membership.without_peer("127.0.0.1:5")?;
+        assert!(raft.change_membership(new_peers)?.is_empty());
+        ack_last(&mut raft, &[4, 3])?;
+        assert_eq!(0, raft.commit_index);
+        ack_last(&mut raft, &[1])?;
+        assert_eq!(1, raft.commit_index);
+        ack_last(&mut raft, &[1, 2])?;
+        assert_eq!(2, raft.commit_index);
+        assert_eq!((0..4).collect::<BTreeSet<_>>(), raft.membership.voters());
+
+        // Removing the leader itself makes it step down once the new configuration commits,
+        // without counting its own acknowledgement.
+        let new_peers = raft.membership.without_peer("127.0.0.1:1")?;
+        raft.change_membership(new_peers)?;
+        ack_last(&mut raft, &[1, 2])?;
+        assert_eq!(3, raft.commit_index);
+        assert!(raft.is_leader());
+        ack_last(&mut raft, &[1])?;
+        assert_eq!(3, raft.commit_index);
+        ack_last(&mut raft, &[2])?;
+        assert_eq!(4, raft.commit_index);
+        assert!(!raft.is_leader());
+        assert_eq!(vec![1, 2, 3], raft.membership.voters().into_iter().collect::<Vec<_>>());
+        assert!(!raft.tick_election());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn membership_survives_restart() -> Result<()> {
+        let store = storage::log::LogDemo::new();
+        let (apply_tx, _apply_rx) = mpsc::unbounded_channel();
+        let restart = || -> Result<Raft> {
+            let mut raft = Raft::new(0, apply_tx.clone(), Box::new(store.clone()))?;
+            raft.set_initial_membership(addrs(3))?;
+            Ok(raft)
+        };
+        let mut raft = restart()?;
+        raft.current_term = 1;
+        raft.role = Role::init_leader(0, raft.membership.voters(), 0, HashMap::new());
+
+        // Add node 3 and remove node 1, committing both changes.
+        let (new_peers, _) = raft.membership.with_peer("127.0.0.1:4".into())?;
+        let _work_rxs = raft.change_membership(new_peers)?;
+        ack_last(&mut raft, &[1, 3])?;
+        ack_last(&mut raft, &[1, 3])?;
+        let new_peers = raft.membership.without_peer("127.0.0.1:2")?;
+        raft.change_membership(new_peers)?;
+        ack_last(&mut raft, &[2, 3])?;
+        ack_last(&mut raft, &[2, 3])?;
+        assert_eq!(4, raft.commit_index);
+        let membership = raft.membership.clone();
+        assert_eq!(vec![0, 2, 3], membership.voters().into_iter().collect::<Vec<_>>());
+
+        // The configuration is recovered from the log, rather than reset to the initial one.
+        drop(raft);
+        let mut raft = restart()?;
+        assert_eq!(membership, raft.membership);
+
+        // Once the configuration entries are compacted, it is recovered from the snapshot.
+        raft.commit_index = 4;
+        let snapshot = raft.create_snapshot(4, vec![])?;
+        assert_eq!(membership, snapshot.membership);
+        drop(raft);
+        let mut raft = restart()?;
+        assert_eq!(membership, raft.membership);
+
+        // And sent along with the snapshot to lagging followers.
+        raft.current_term = 1;
+        let mut follower = Raft::new(2, apply_tx.clone(), Box::new(storage::log::Memory::new()))?;
+        follower.set_initial_membership(addrs(3))?;
+        for chunk in raft.snapshot_chunks()?.unwrap() {
+            follower.install_snapshot(chunk)?;
+        }
+        assert_eq!(membership, follower.membership);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn follower_uses_latest_config_entry() -> Result<()> {
+        let mut raft = follower(1)?;
+        raft.set_initial_membership(addrs(3))?;
+        let (new_peers, _) = raft.membership.with_peer("127.0.0.1:4".into())?;
+        let entries = vec![
+            Entry { index: 1, term: 1, command: Command::Registration { session_id: 1 } },
+            Entry {
+                index: 2,
+                term: 1,
+                command: Command::ConfigEntry { old_peers: addrs(3), new_peers: new_peers.clone() },
+            },
+        ];
+        raft.log.splice(entries)?;
+        raft.refresh_membership()?;
+        assert_eq!(Membership::from_entry(2, addrs(3), new_peers), raft.membership);
+
+        // Truncating the uncommitted entry reverts to the previous configuration.
+        raft.log.truncate(1)?;
+        raft.refresh_membership()?;
+        assert_eq!(Membership::new(addrs(3)), raft.membership);
+        Ok(())
+    }
+
+    /// Returns a client for a peer that is never actually connected to.
+    fn lazy_client() -> RaftServiceClient<Channel> {
+        RaftServiceClient::new(Channel::from_static("http://127.0.0.1:1").connect_lazy())
+    }
+
+    /// Returns a leader of a 3-node cluster in term 1, and the work channel of peer 1.
+    fn transferring_leader() -> Result<(Raft, mpsc::UnboundedReceiver<u64>)> {
+        let (mut raft, _) = setup(ReplicationProtocol::Synchronous { min_replicas: 0 })?;
+        raft.peers = (0..3).map(|id| (id, lazy_client())).collect();
+        raft.current_term = 1;
+        let (work_tx, work_rx) = mpsc::unbounded_channel();
+        if let Role::Leader { ref mut work_txs, .. } = raft.role {
+            work_txs.insert(1, work_tx.clone());
+            work_txs.insert(2, work_tx);
+        }
+        Ok((raft, work_rx))
+    }
+
+    #[tokio::test]
+    async fn leadership_transfer() -> Result<()> {
+        let (mut raft, mut work_rx) = transferring_leader()?;
+        raft.start(Command::Registration { session_id: 1 })?;
+        while work_rx.try_recv().is_ok() {}
+
+        assert!(raft.transfer_leadership(0).is_err());
+        assert!(raft.transfer_leadership(3).is_err());
+
+        // The target is caught up first, and new commands are rejected meanwhile.
+        assert!(raft.transfer_leadership(1)?.is_none());
+        assert_eq!(Some(1), work_rx.try_recv().ok());
+        assert_eq!(Err(Error::NotLeader), raft.start(Command::Registration { session_id: 2 }));
+        assert!(raft.transfer_ready(1).is_none());
+        if let Role::Leader { ref mut match_index, .. } = raft.role {
+            match_index.insert(1, 1);
+        }
+        assert!(raft.transfer_ready(2).is_none());
+        assert_eq!(Some(TimeoutNowArgs { term: 1, leader_id: 0 }), raft.transfer_ready(1));
+        assert!(raft.transfer_ready(1).is_none());
+
+        // The target campaigns immediately, and the leader steps down on its higher term.
+        let mut target = follower(1)?;
+        assert!(target.timeout_now(TimeoutNowArgs { term: 1, leader_id: 0 }));
+        target.become_candidate();
+        assert!(!target.timeout_now(TimeoutNowArgs { term: 1, leader_id: 0 }));
+        raft.become_follower(target.current_term, None);
+        assert!(!raft.is_leader());
+        assert_eq!(2, raft.current_term);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leadership_transfer_times_out() -> Result<()> {
+        let (mut raft, _work_rx) = transferring_leader()?;
+        // The empty log is trivially caught up, but the target never campaigns.
+        assert!(raft.transfer_leadership(2)?.is_some());
+        for _ in 0..=ELECTION_TIMEOUT_MAX {
+            raft.record_ack(1, 1);
+            assert!(!raft.tick_leader());
+        }
+        assert!(raft.is_leader());
+        assert!(raft.start(Command::Registration { session_id: 1 }).is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn observer_receives_entries() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        raft.current_term = 1;
+        raft.start(Command::Registration { session_id: 1 })?;
+
+        // The observer is caught up on existing entries, and sent each new one.
+        let (work_tx, mut work_rx) = mpsc::unbounded_channel();
+        raft.add_observer(3, lazy_client(), work_tx)?;
+        assert_eq!(Some(1), work_rx.try_recv().ok());
+        let (index, _) = raft.start(Command::Registration { session_id: 2 })?;
+        assert_eq!(Some(index), work_rx.try_recv().ok());
+        assert_eq!(index, raft.commit_index);
+
+        assert_eq!(
+            Some(&ReplicationStatus::Observer { match_index: 0 }),
+            raft.replication_status().get(&3)
+        );
+        assert_eq!(
+            Some(&ReplicationStatus::Follower { match_index: 0 }),
+            raft.replication_status().get(&1)
+        );
+
+        let (work_tx, _) = mpsc::unbounded_channel();
+        assert!(raft.add_observer(3, lazy_client(), work_tx).is_err());
+        let (work_tx, _) = mpsc::unbounded_channel();
+        assert!(raft.add_observer(0, lazy_client(), work_tx).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn observer_excluded_from_votes_and_quorums() -> Result<()> {
+        let (mut raft, _apply_rx) = setup(ReplicationProtocol::Synchronous { min_replicas: 0 })?;
+        raft.peers = (0..3).map(|id| (id, lazy_client())).collect();
+        raft.current_term = 1;
+        let (work_tx, _work_rx) = mpsc::unbounded_channel();
+        raft.add_observer(3, lazy_client(), work_tx)?;
+
+        // Observers get heartbeats, but are never solicited for votes.
+        assert_eq!(3, raft.send_heartbeats().len());
+        assert_eq!(2, raft.solicit_votes().len());
+
+        // An observer ack doesn't commit an entry, but a voter ack does, as without observers.
+        raft.log.append(1, Command::Registration { session_id: 1 })?;
+        let index = raft.log.last_index;
+        if let Role::Leader { ref mut match_index, .. } = raft.role {
+            match_index.insert(3, index);
+        }
+        assert!(!raft.should_commit(index));
+        if let Role::Leader { ref mut match_index, .. } = raft.role {
+            match_index.insert(1, index);
+        }
+        assert!(raft.should_commit(index));
+
+        // Observer acks don't keep the leader from stepping down.
+        for _ in 0..=raft.config.step_down_ticks {
+            raft.tick_leader();
+            raft.record_ack(3, 1);
+        }
+        assert!(!raft.is_leader());
+        Ok(())
+    }
+
+    #[test]
+    fn observer_never_campaigns() -> Result<()> {
+        let (apply_tx, _) = mpsc::unbounded_channel();
+        let config = RaftConfig { observer: true, ..Default::default() };
+        let mut raft = Raft::with_config(1, apply_tx, Box::new(storage::log::Memory::new()), config)?;
+        for _ in 0..(10 * ELECTION_TIMEOUT_MAX) {
+            assert!(!raft.tick_election());
+        }
+        assert!(matches!(raft.role, Role::Follower { .. }));
+        Ok(())
+    }
+
+    /// Returns a leader with the given number of committed entries, and its apply channel.
+    fn leader_with_entries(count: u64) -> Result<(Raft, mpsc::UnboundedReceiver<ApplyMsg>)> {
+        let (mut raft, mut apply_rx) = setup(ReplicationProtocol::Asynchronous)?;
+        raft.current_term = 1;
+        for session_id in 0..count {
+            raft.start(Command::Registration { session_id })?;
+        }
+        while apply_rx.try_recv().is_ok() {}
+        Ok((raft, apply_rx))
+    }
+
+    /// Returns the indexes of the entries stored in a log.
+    fn indexes(raft: &Raft) -> Result<Vec<u64>> {
+        raft.log.scan(..).map(|r| r.map(|e| e.index)).collect()
+    }
+
+    #[test]
+    fn snapshot_compacts_log() -> Result<()> {
+        let (mut raft, _apply_rx) = leader_with_entries(5000)?;
+        assert!(!raft.snapshot_due(raft.config.snapshot_threshold - 1));
+        assert!(raft.snapshot_due(raft.config.snapshot_threshold));
+
+        assert!(raft.create_snapshot(5001, vec![]).is_err());
+        let snapshot = raft.create_snapshot(4000, vec![0x01])?;
+        assert_eq!(new_snapshot(4000, 1, vec![0x01]), snapshot);
+        assert_eq!(Some(snapshot), raft.log.snapshot()?);
+        assert_eq!((4000, 1), (raft.log.snapshot_index, raft.log.snapshot_term));
+        assert_eq!((5000, 1), (raft.log.last_index, raft.log.last_term));
+        assert_eq!(None, raft.log.get(4000)?);
+        assert_eq!(Some(1), raft.log.term(4000)?);
+        assert_eq!((4001..=5000).collect::<Vec<_>>(), indexes(&raft)?);
+
+        // The log keeps appending after the compacted entries.
+        let (index, _) = raft.start(Command::Registration { session_id: 0 })?;
+        assert_eq!(5001, index);
+        assert_eq!(Some(5001), raft.log.get(5001)?.map(|e| e.index));
+        Ok(())
+    }
+
+    #[test]
+    fn lagging_follower_installs_snapshot() -> Result<()> {
+        let (mut leader, _) = leader_with_entries(5000)?;
+        let data: Vec<u8> = (0..(2 * SNAPSHOT_CHUNK_SIZE + 10)).map(|i| i as u8).collect();
+        leader.create_snapshot(4000, data.clone())?;
+        let chunks = leader.snapshot_chunks()?.unwrap();
+        assert_eq!(vec![false, false, true], chunks.iter().map(|c| c.done).collect::<Vec<_>>());
+
+        // The follower only has the first few entries, from an older term.
+        let (apply_tx, mut apply_rx) = mpsc::unbounded_channel();
+        let mut follower = Raft::new(1, apply_tx, Box::new(storage::log::Memory::new()))?;
+        for session_id in 0..3 {
+            follower.log.append(0, Command::Registration { session_id })?;
+        }
+
+        // Chunks must arrive in order.
+        assert!(follower.install_snapshot(chunks[1].clone()).is_err());
+        for chunk in chunks.clone() {
+            assert_eq!(1, follower.install_snapshot(chunk)?.term);
+        }
+        assert_eq!(leader.me, follower.leader_id());
+        assert_eq!((4000, 1), (follower.log.last_index, follower.log.last_term));
+        assert_eq!(4000, follower.commit_index);
+        assert!(indexes(&follower)?.is_empty());
+        match apply_rx.try_recv() {
+            Ok(ApplyMsg::Snapshot(snapshot)) => {
+                assert_eq!((4000, 1), (snapshot.last_index, snapshot.last_term));
+                assert_eq!(data, snapshot.data);
+            }
+            msg => panic!("Unexpected apply message {:?}", msg),
+        }
+
+        // Replaying the snapshot is a noop, and stale terms are rejected.
+        for chunk in chunks.clone() {
+            follower.install_snapshot(chunk)?;
+        }
+        assert!(apply_rx.try_recv().is_err());
+        follower.current_term = 2;
+        assert_eq!(2, follower.install_snapshot(chunks[0].clone())?.term);
+        assert!(follower.incoming_snapshot.is_none());
+
+        // The follower can then append the remaining entries.
+        let entries = leader.log.scan(4001..).collect::<Result<Vec<_>>>()?;
+        assert_eq!(5000, follower.log.splice(entries)?);
+        assert_eq!(leader.log.get(5000)?, follower.log.get(5000)?);
+        Ok(())
+    }
+
+    /// Returns a mutation with a 10 KB payload, like a batch insert.
+    fn large_mutation(sequence_number: u64) -> Command {
+        let mutation = (0..300)
+            .map(|i| format!("INSERT INTO t VALUES ({}, 'row {}');", i, sequence_number))
+            .collect::<String>()
+            .into_bytes()[..10_000]
+            .to_vec();
+        Command::Mutation { session_id: 1, sequence_number, mutation }
+    }
+
+    #[test]
+    fn compressed_entries_survive_restart() -> Result<()> {
+        use storage::log::LogStore;
+        let store = storage::log::LogDemo::new();
+        let config = LogConfig { compress: true, ..Default::default() };
+        let mut log = Log::with_config(Box::new(store.clone()), config)?;
+        let mut entries = vec![
+            log.append(1, large_mutation(1))?,
+            log.append(1, Command::Registration { session_id: 2 })?,
+            log.append(2, large_mutation(2))?,
+        ];
+        assert!(entries[0].compressed_size() < entries[0].uncompressed_size() / 4);
+
+        // Entries appended without compression are mixed with the compressed ones.
+        log.config.compress = false;
+        entries.push(log.append(2, large_mutation(3))?);
+        log.commit(4)?;
+        let headers: Vec<u8> =
+            (1..=4).map(|i| Ok(store.get(i)?.unwrap()[0])).collect::<Result<_>>()?;
+        assert_eq!(vec![1, 0, 1, 0], headers);
+        assert!(store.get(1)?.unwrap().len() < store.get(4)?.unwrap().len() / 4);
+
+        // Both are read back after a restart, also when replicated.
+        let log = Log::new(Box::new(store.clone()))?;
+        assert_eq!((4, 2, 4), (log.last_index, log.last_term, log.commit_index));
+        assert_eq!(entries, log.scan(..).collect::<Result<Vec<_>>>()?);
+        assert_eq!(Some(entries[2].clone()), log.get(3)?);
+        let replicated: Vec<Entry> =
+            log.scan_encoded(..).map(|e| Log::decode(&e?)).collect::<Result<_>>()?;
+        assert_eq!(entries, replicated);
+        assert!(matches!(Log::decode(&[7, 0]), Err(Error::Corruption(_))));
+        Ok(())
+    }
+
+    /// Compares appending 100k entries with 10 KB payloads with and without compression. Run
+    /// with `cargo test --release compression_throughput -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn compression_throughput() -> Result<()> {
+        for compress in [false, true] {
+            let config = LogConfig { compress, ..Default::default() };
+            let mut log = Log::with_config(Box::new(storage::log::Memory::new()), config)?;
+            let start = std::time::Instant::now();
+            for i in 1..=100_000 {
+                log.append(1, large_mutation(i))?;
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "compress={}: {:.0} entries/s, {} MB stored",
+                compress,
+                100_000.0 / elapsed.as_secs_f64(),
+                log.store.size() >> 20,
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_retains_matching_entries() -> Result<()> {
+        let mut raft = follower(1)?;
+        for term in [1, 1, 2, 2] {
+            raft.log.append(term, Command::Registration { session_id: 1 })?;
+        }
+        raft.log.save_snapshot(new_snapshot(2, 1, vec![]))?;
+        assert_eq!((4, 2), (raft.log.last_index, raft.log.last_term));
+        assert_eq!((2, 1), (raft.log.commit_index, raft.log.commit_term));
+        assert_eq!(vec![3, 4], indexes(&raft)?);
+
+        // An older snapshot is ignored.
+        raft.log.save_snapshot(new_snapshot(1, 1, vec![]))?;
+        assert_eq!(2, raft.log.snapshot_index);
+
+        // A conflicting entry at the snapshot index discards the entries following it.
+        raft.log.save_snapshot(new_snapshot(3, 3, vec![]))?;
+        assert_eq!((3, 3), (raft.log.last_index, raft.log.last_term));
+        assert!(indexes(&raft)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn redirect_target() -> Result<()> {
+        let mut raft = follower(1)?;
+        assert_eq!(None, raft.redirect_target());
+        raft.heartbeat(1, 0);
+        assert_eq!(None, raft.redirect_target());
+
+        // Followers which don't proxy requests redirect them to the leader, once known.
+        raft.config.follower.proxy_requests = false;
+        assert_eq!(Some(0), raft.redirect_target());
+        raft.become_follower(2, None);
+        assert_eq!(None, raft.redirect_target());
+        raft.become_candidate();
+        assert_eq!(None, raft.redirect_target());
+        Ok(())
+    }
+
+    #[test]
+    fn tick_batch_stops_at_transition() -> Result<()> {
+        let mut raft = follower(1)?;
+        let result = raft.tick_batch(ELECTION_TIMEOUT_MIN - 1)?;
+        assert_eq!(ELECTION_TIMEOUT_MIN - 1, result.ticks_consumed);
+        assert!(!result.transitioned && result.messages.is_empty());
+
+        // The election timer expires within the batch, which stops at the pre-vote.
+        let result = raft.tick_batch(2 * ELECTION_TIMEOUT_MAX)?;
+        assert!(result.transitioned);
+        assert!(result.ticks_consumed <= ELECTION_TIMEOUT_MAX - ELECTION_TIMEOUT_MIN + 1);
+        assert!(matches!(raft.role, Role::PreCandidate { .. }));
+        assert_eq!(vec![0, 2], result.messages.iter().map(|m| m.to).collect::<Vec<_>>());
+        assert!(result.messages.iter().all(|m| matches!(
+            m.event,
+            Event::PreVote(PreVoteArgs { term: 1, candidate_id: 1, .. })
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn network_elects_single_leader() -> Result<()> {
+        let mut net = test_utils::TestNetwork::new(5, 1)?;
+        net.tick_until(10 * ELECTION_TIMEOUT_MAX, |net| net.leaders().len() == 1)?.unwrap();
+        let leader = net.leaders()[0];
+        let term = net.nodes[leader as usize].current_term;
+
+        // Heartbeats keep the leader in place.
+        for _ in 0..5 * ELECTION_TIMEOUT_MAX {
+            net.tick()?;
+        }
+        assert_eq!(vec![leader], net.leaders());
+        assert!(net.nodes.iter().all(|n| n.current_term == term && n.leader_id() == leader));
+        Ok(())
+    }
+
+    #[test]
+    fn network_partition_heals() -> Result<()> {
+        let mut net = test_utils::TestNetwork::new(5, 2)?;
+        net.tick_until(10 * ELECTION_TIMEOUT_MAX, |net| net.leaders().len() == 1)?.unwrap();
+        let old = net.leaders()[0];
+        let old_term = net.nodes[old as usize].current_term;
+
+        // Partitions the leader and one follower from the majority, which elects a new leader
+        // in a higher term. The old leader loses its quorum and steps down.
+        let minority = [old, (old + 1) % 5];
+        let majority: Vec<u64> = (0..5).filter(|id| !minority.contains(id)).collect();
+        net.policy.partition(&[&minority, &majority]);
+        net.tick_until(10 * ELECTION_TIMEOUT_MAX, |net| {
+            net.leaders().iter().any(|id| majority.contains(id))
+        })?
+        .unwrap();
+        let new = net.leaders().into_iter().find(|id| majority.contains(id)).unwrap();
+        assert!(net.nodes[new as usize].current_term > old_term);
+        net.tick_until(4 * ELECTION_TIMEOUT_MAX, |net| !net.nodes[old as usize].is_leader())?
+            .unwrap();
+
+        // Once healed, the minority follows the new leader.
+        net.policy.heal();
+        net.tick_until(4 * ELECTION_TIMEOUT_MAX, |net| {
+            net.nodes.iter().all(|n| n.leader_id() == new)
+        })?
+        .unwrap();
+        assert_eq!(vec![new], net.leaders());
+        Ok(())
+    }
+
+    #[test]
+    fn network_rejoin_without_disruption() -> Result<()> {
+        let mut net = test_utils::TestNetwork::new(3, 3)?;
+        net.tick_until(10 * ELECTION_TIMEOUT_MAX, |net| net.leaders().len() == 1)?.unwrap();
+        let leader = net.leaders()[0];
+        let term = net.nodes[leader as usize].current_term;
+
+        // An isolated follower keeps failing pre-votes, so its term doesn't grow.
+        let isolated = (leader + 1) % 3;
+        net.policy.isolate(isolated, 3);
+        for _ in 0..10 * ELECTION_TIMEOUT_MAX {
+            net.tick()?;
+        }
+        assert_eq!(term, net.nodes[isolated as usize].current_term);
+
+        // When it rejoins, the leader keeps its leadership and term.
+        net.policy.heal();
+        for _ in 0..5 * ELECTION_TIMEOUT_MAX {
+            net.tick()?;
+        }
+        assert_eq!(vec![leader], net.leaders());
+        assert!(net.nodes.iter().all(|n| n.current_term == term && n.leader_id() == leader));
+        Ok(())
+    }
+
+    #[test]
+    fn network_is_deterministic() -> Result<()> {
+        let run = |seed| -> Result<Vec<(u64, u64)>> {
+            let mut net = test_utils::TestNetwork::new(5, seed)?;
+            net.policy.drop_rate = 0.2;
+            net.policy.reorder = true;
+            let mut history = Vec::new();
+            for _ in 0..20 * ELECTION_TIMEOUT_MAX {
+                net.tick()?;
+                history.push((net.nodes[0].current_term, net.nodes[0].leader_id()));
+            }
+            assert!(!net.leaders().is_empty());
+            Ok(history)
+        };
+        assert_eq!(run(7)?, run(7)?);
+        Ok(())
+    }
+}
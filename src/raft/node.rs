@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -6,16 +6,21 @@ use futures::{stream::FuturesUnordered, Future};
 use rand::Rng;
 use tokio::sync::mpsc;
 use tokio_stream::StreamExt;
-use tonic::transport::Server;
+use tonic::transport::{Channel, Server};
 use tonic::{Response, Status, Request};
 
 use crate::error::{Result, Error, RpcResult};
 use crate::proto::raft::raft_service_client::RaftServiceClient;
 use crate::proto::raft::raft_service_server::{RaftService, RaftServiceServer};
-use crate::proto::raft::{RequestVoteReply, RequestVoteArgs, AppendEntriesArgs, AppendEntriesReply};
-use crate::server::{deserialize, serialize};
+use crate::proto::raft::{
+    RequestVoteReply, RequestVoteArgs, AppendEntriesArgs, AppendEntriesReply, InstallSnapshotArgs,
+    InstallSnapshotReply, PreVoteArgs, PreVoteReply, TimeoutNowArgs, TimeoutNowReply,
+};
 use crate::storage::log::LogStore;
-use super::{HEARTBEAT_INTERVAL, Raft, Role, ApplyMsg, Command, Entry};
+use super::{
+    Raft, RaftConfig, Role, ApplyMsg, AtomicRaftMetrics, Command,
+    Membership, RaftMetrics, ReplicationStatus, ShardClock, Snapshot, TickAction, VectorClock,
+};
 
 // An interceptor function. TODO: use layer instead.
 fn intercept(req: Request<()>) -> core::result::Result<Request<()>, Status> {
@@ -31,38 +36,40 @@ fn intercept(req: Request<()>) -> core::result::Result<Request<()>, Status> {
 pub struct Node {
     // Your code here.
     raft: Arc<Mutex<Raft>>,
+    /// The Raft node's metrics, readable without locking it.
+    metrics: Arc<AtomicRaftMetrics>,
+    /// The state machine's applied index, which reads with a min_clock wait on.
+    clock: Arc<ShardClock>,
 }
 
 impl Node {
-    /// Create a new raft service. TODO: Set up the raft server according to the config.
+    /// Wraps a Raft node.
+    fn from_raft(raft: Raft) -> Node {
+        let metrics = raft.metrics.clone();
+        let clock = Arc::new(ShardClock::new(raft.config.shard_id.clone()));
+        Node { raft: Arc::new(Mutex::new(raft)), metrics, clock }
+    }
+
+    /// Create a new raft service.
     pub async fn new(
         me: u64,
         peers: Vec<String>,
         apply_tx: mpsc::UnboundedSender<ApplyMsg>,
         log_store: Box<dyn LogStore>,
     ) -> Result<Node> {
-        let node = Node { raft: Arc::new(Mutex::new(Raft::new(
-            me,
-            apply_tx,
-            log_store,
-        )?)) };
-        let node_clone = node.clone();
-
-        let layer = tower::ServiceBuilder::new()
-            .layer(tonic::service::interceptor(intercept))
-            .into_inner();
+        Self::with_config(me, peers, apply_tx, log_store, RaftConfig::default()).await
+    }
 
-        let my_addr = peers[me as usize].parse()?;
-        tokio::spawn(async move {
-            match Server::builder()
-                .layer(layer)
-                .add_service(RaftServiceServer::new(node_clone))
-                .serve(my_addr)
-                .await {
-                    Ok(_) => { println!("Raft server built on addr {:?}", my_addr) },
-                    Err(err) => println!("Raft server failed on addr {:?}: {:?}", my_addr, err),
-                };
-        });
+    /// Create a new raft service with the given configuration.
+    pub async fn with_config(
+        me: u64,
+        peers: Vec<String>,
+        apply_tx: mpsc::UnboundedSender<ApplyMsg>,
+        log_store: Box<dyn LogStore>,
+        config: RaftConfig,
+    ) -> Result<Node> {
+        let node = Node::from_raft(Raft::with_config(me, apply_tx, log_store, config)?);
+        node.serve_rpc(&peers[me as usize])?;
 
         let mut conns = vec![false; peers.len()];
         'outer: loop {
@@ -82,15 +89,66 @@ impl Node {
                     Err(_) => { continue; },
                 };
                 let mut raft = node.raft.lock()?;
-                raft.peers.push(client);
+                raft.peers.insert(i as u64, client);
                 conns[i] = true;
             }
             tokio::time::sleep(Duration::from_millis(1000)).await;
         }
+        node.raft.lock()?.set_initial_membership(peers)?;
 
         Ok(node)
     }
 
+    /// Create a non-voting observer node serving on the given address. It does not connect to
+    /// any peers, and only receives entries once the leader adds it with `add_observer()`.
+    pub async fn new_observer(
+        me: u64,
+        addr: String,
+        apply_tx: mpsc::UnboundedSender<ApplyMsg>,
+        log_store: Box<dyn LogStore>,
+    ) -> Result<Node> {
+        let config = RaftConfig { observer: true, ..Default::default() };
+        let node = Node::from_raft(Raft::with_config(me, apply_tx, log_store, config)?);
+        node.serve_rpc(&addr)?;
+        Ok(node)
+    }
+
+    /// Create a node serving on the given address that is about to be added to an existing
+    /// cluster with `add_peer()`, using the node ID it returned. It does not connect to any
+    /// peers, and learns the cluster configuration from the leader.
+    pub async fn join(
+        me: u64,
+        addr: String,
+        apply_tx: mpsc::UnboundedSender<ApplyMsg>,
+        log_store: Box<dyn LogStore>,
+    ) -> Result<Node> {
+        let node = Node::from_raft(Raft::new(me, apply_tx, log_store)?);
+        node.serve_rpc(&addr)?;
+        Ok(node)
+    }
+
+    /// Spawns the Raft RPC server on the given address.
+    fn serve_rpc(&self, addr: &str) -> Result<()> {
+        let node_clone = self.clone();
+
+        let layer = tower::ServiceBuilder::new()
+            .layer(tonic::service::interceptor(intercept))
+            .into_inner();
+
+        let my_addr = addr.parse()?;
+        tokio::spawn(async move {
+            match Server::builder()
+                .layer(layer)
+                .add_service(RaftServiceServer::new(node_clone))
+                .serve(my_addr)
+                .await {
+                    Ok(_) => { println!("Raft server built on addr {:?}", my_addr) },
+                    Err(err) => println!("Raft server failed on addr {:?}: {:?}", my_addr, err),
+                };
+        });
+        Ok(())
+    }
+
     /// Start the Raft server. This method should not return until shutdown.
     pub async fn serve(self) -> Result<()> {
         loop {
@@ -120,6 +178,18 @@ impl Node {
         Ok((index, term))
     }
 
+    /// Serves a read-only query without appending it to the log, using the ReadIndex protocol.
+    /// If this server isn't the leader, returns [`Error::NotLeader`]. Otherwise a heartbeat is
+    /// sent to confirm its leadership, and the query is handed to the state machine once a
+    /// quorum acknowledges it. As with `start()`, the query may never be served.
+    pub fn read(&self, command: Command) -> Result<()> {
+        let mut raft = self.raft.lock()?;
+        if raft.read_index(command)?.is_some() {
+            Self::broadcast_heartbeats(self.raft.clone(), &raft);
+        }
+        Ok(())
+    }
+
     /// The current term of this peer.
     pub fn term(&self) -> Result<u64> {
         Ok(self.raft.lock()?.current_term)
@@ -140,80 +210,257 @@ impl Node {
         Ok(self.raft.lock()?.leader_id())
     }
 
+    /// The id of the leader that client requests should be redirected to, if any. See
+    /// FollowerConfig::proxy_requests.
+    pub fn redirect_target(&self) -> Result<Option<u64>> {
+        Ok(self.raft.lock()?.redirect_target())
+    }
+
+    /// Adds a non-voting observer at the given address. Only the leader can add observers, and
+    /// they must be added again after a leadership change.
+    pub fn add_observer(&self, node_id: u64, addr: String) -> Result<()> {
+        let client = RaftServiceClient::new(
+            Channel::from_shared(format!("http://{}", addr))
+                .map_err(|e| Error::Value(e.to_string()))?
+                .connect_lazy(),
+        );
+        let mut raft = self.raft.lock()?;
+        let (work_tx, work_rx) = mpsc::unbounded_channel();
+        raft.add_observer(node_id, client, work_tx)?;
+        tokio::spawn(Self::replicator(self.raft.clone(), work_rx, node_id));
+        Ok(())
+    }
+
+    /// Adds a voting member at the given address, returning its node ID. The new node should be
+    /// started with `Node::join()` using this ID. Only the leader can change the membership, and
+    /// only one change can be in progress at a time.
+    pub fn add_peer(&self, addr: String) -> Result<u64> {
+        let mut raft = self.raft.lock()?;
+        let (peers, id) = raft.membership.with_peer(addr)?;
+        self.change_membership(&mut raft, peers)?;
+        Ok(id)
+    }
+
+    /// Removes the voting member at the given address. If it is the leader itself, it steps
+    /// down once the change is committed.
+    pub fn remove_peer(&self, addr: String) -> Result<()> {
+        let mut raft = self.raft.lock()?;
+        let peers = raft.membership.without_peer(&addr)?;
+        self.change_membership(&mut raft, peers)
+    }
+
+    /// Proposes a membership change, and spawns replicators for any added members.
+    fn change_membership(&self, raft: &mut Raft, peers: Vec<String>) -> Result<()> {
+        for (id, work_rx) in raft.change_membership(peers)? {
+            tokio::spawn(Self::replicator(self.raft.clone(), work_rx, id));
+        }
+        Ok(())
+    }
+
+    /// The current cluster configuration.
+    pub fn membership(&self) -> Result<Membership> {
+        Ok(self.raft.lock()?.membership.clone())
+    }
+
+    /// The replication status of each peer, if this peer is the leader.
+    pub fn replication_status(&self) -> Result<HashMap<u64, ReplicationStatus>> {
+        Ok(self.raft.lock()?.replication_status())
+    }
+
+    /// Transfers leadership to the given voting peer, e.g. before shutting down. The peer is
+    /// first caught up on the log, and then told to start an election immediately. New commands
+    /// are rejected with [`Error::NotLeader`] meanwhile. The transfer is abandoned if it doesn't
+    /// complete within an election timeout.
+    pub fn transfer_leadership(&self, target: u64) -> Result<()> {
+        let mut raft = self.raft.lock()?;
+        if let Some(args) = raft.transfer_leadership(target)? {
+            Self::send_timeout_now(self.raft.clone(), &raft, target, args)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the node's current metrics, without waiting for the Raft node.
+    pub fn metrics(&self) -> RaftMetrics {
+        self.metrics.load()
+    }
+
+    /// Records that the state machine has applied entries up to an index.
+    pub fn applied(&self, index: u64) {
+        self.metrics.applied(index);
+        self.clock.applied(index);
+    }
+
+    /// Returns a vector clock holding the state machine's applied index.
+    pub fn clock(&self) -> VectorClock {
+        self.clock.clock()
+    }
+
+    /// Waits until the state machine has applied the index required by a client's min_clock,
+    /// erroring after the configured min_clock_timeout.
+    pub async fn wait_for_clock(&self, min_clock: &VectorClock) -> Result<()> {
+        let timeout = self.raft.lock()?.config.min_clock_timeout;
+        self.clock.wait(min_clock, timeout).await
+    }
+
+    /// Whether the state machine should be snapshotted after applying the given index.
+    pub fn snapshot_due(&self, applied_index: u64) -> Result<bool> {
+        Ok(self.raft.lock()?.snapshot_due(applied_index))
+    }
+
+    /// The number of log entries after which an idle client session expires, see RaftConfig.
+    pub fn session_timeout(&self) -> Result<u64> {
+        Ok(self.raft.lock()?.config.session_timeout)
+    }
+
+    /// Stores a snapshot of the state machine taken at the given applied index, and compacts the
+    /// log up to and including it.
+    pub fn create_snapshot(&self, index: u64, data: Vec<u8>) -> Result<Snapshot> {
+        self.raft.lock()?.create_snapshot(index, data)
+    }
+
     /// Tick the underlying Raft node to the next state.
     pub fn tick(&self) -> Result<()> {
         let mut raft = self.raft.lock()?;
+        match raft.advance() {
+            TickAction::Idle => {}
+            TickAction::PreCampaign => Self::pre_campaign(self.raft.clone(), &mut raft),
+            TickAction::Campaign => Self::campaign(self.raft.clone(), &mut raft),
+            TickAction::Heartbeat => Self::broadcast_heartbeats(self.raft.clone(), &raft),
+        }
+        Ok(())
+    }
 
-        match raft.role {
-            Role::Follower { ref mut leader_seen_ticks, leader_seen_timeout, .. } => {
-                *leader_seen_ticks += 1;
-                if *leader_seen_ticks >= leader_seen_timeout {
-                    raft.become_candidate();
-                    let request_vote_replies = raft.solicit_votes();
-
-                    let quorum = raft.quorum();
-                    let current_term = raft.current_term;
-                    let raft = self.raft.clone();
-                    tokio::spawn(async move {
-                        Self::count_votes(raft, quorum, current_term, request_vote_replies).await.unwrap();
-                    });
-                }
+    /// Sends heartbeats to all peers, and counts their acknowledgements in the background.
+    fn broadcast_heartbeats(arc_raft: Arc<Mutex<Raft>>, raft: &Raft) {
+        let heartbeat_replies = raft.send_heartbeats();
+        let membership = raft.membership.clone();
+        let (me, current_term, read_seq) = (raft.me, raft.current_term, raft.read_seq());
+        tokio::spawn(async move {
+            Self::count_acks(arc_raft, membership, me, current_term, read_seq, heartbeat_replies)
+                .await
+        });
+    }
+
+    /// Records heartbeat acknowledgements from peers, stepping down if a peer has a newer term.
+    /// Once a quorum has acknowledged, confirms the read-only requests up to `read_seq`.
+    async fn count_acks(
+        arc_raft: Arc<Mutex<Raft>>,
+        membership: Membership,
+        me: u64,
+        current_term: u64,
+        read_seq: u64,
+        mut heartbeat_replies: FuturesUnordered<impl Future<Output = (u64, RpcResult<AppendEntriesReply>)>>,
+    ) {
+        let mut acks = HashSet::from([me]);
+        let mut reads_confirmed = read_seq == 0;
+        while let Some((peer, reply)) = heartbeat_replies.next().await {
+            let Ok(reply) = reply else { continue };
+            let mut raft = arc_raft.lock().unwrap();
+            let term = reply.get_ref().term;
+            if term > raft.current_term {
+                raft.become_follower(term, None);
+                return;
             }
-            Role::Candidate {ref mut election_ticks, election_timeout, .. } => {
-                *election_ticks += 1;
-                if *election_ticks >= election_timeout {
-                    raft.become_candidate();
-                    let request_vote_replies = raft.solicit_votes();
-
-                    let quorum = raft.quorum();
-                    let current_term = raft.current_term;
-                    let raft = self.raft.clone();
-                    tokio::spawn(async move {
-                        Self::count_votes(raft, quorum, current_term, request_vote_replies).await.unwrap();
-                    });
-                }
+            raft.record_ack(peer, current_term);
+            acks.insert(peer);
+            if !reads_confirmed && membership.is_quorum(|id| acks.contains(&id)) {
+                raft.confirm_reads(current_term, read_seq).unwrap();
+                reads_confirmed = true;
             }
-            Role::Leader { ref mut heartbeat_ticks, .. } => {
-            *heartbeat_ticks += 1;
-                if *heartbeat_ticks >= HEARTBEAT_INTERVAL {
-                    *heartbeat_ticks = 0;
-                    raft.send_heartbeats();
+        }
+    }
+
+    /// Becomes a pre-candidate and solicits pre-votes, campaigning if a quorum grants them.
+    fn pre_campaign(arc_raft: Arc<Mutex<Raft>>, raft: &mut Raft) {
+        raft.become_pre_candidate();
+        let pre_vote_replies = raft.solicit_pre_votes();
+
+        let membership = raft.membership.clone();
+        let current_term = raft.current_term;
+        tokio::spawn(async move {
+            Self::count_pre_votes(arc_raft, membership, current_term, pre_vote_replies)
+                .await
+                .unwrap();
+        });
+    }
+
+    /// Becomes a candidate for the next term and solicits votes.
+    fn campaign(arc_raft: Arc<Mutex<Raft>>, raft: &mut Raft) {
+        raft.become_candidate();
+        let request_vote_replies = raft.solicit_votes();
+
+        let membership = raft.membership.clone();
+        let current_term = raft.current_term;
+        tokio::spawn(async move {
+            Self::count_votes(arc_raft, membership, current_term, request_vote_replies)
+                .await
+                .unwrap();
+        });
+    }
+
+    /// Counts the number of pre-votes for a pre-candidate, and starts a real election once a
+    /// quorum grants them, unless the node has moved on in the meantime.
+    async fn count_pre_votes(
+        arc_raft: Arc<Mutex<Raft>>,
+        membership: Membership,
+        current_term: u64,
+        mut pre_vote_replies: FuturesUnordered<impl
+            Future<Output = (u64, RpcResult<PreVoteReply>)>>,
+    ) -> Result<()> {
+        let mut votes = HashSet::from([arc_raft.lock()?.me]);
+        while !membership.is_quorum(|id| votes.contains(&id)) {
+            let Some((peer, res)) = pre_vote_replies.next().await else { return Ok(()) };
+            let (term, vote_granted) = match res {
+                Ok(res) => (res.get_ref().term, res.get_ref().vote_granted),
+                Err(_) => continue,
+            };
+            if vote_granted {
+                votes.insert(peer);
+            } else if term > current_term {
+                let mut raft = arc_raft.lock()?;
+                if term > raft.current_term {
+                    raft.become_follower(term, None);
                 }
+                return Ok(());
             }
         }
 
+        let mut raft = arc_raft.lock()?;
+        if matches!(raft.role, Role::PreCandidate { .. }) && raft.current_term == current_term {
+            Self::campaign(arc_raft.clone(), &mut raft);
+        }
         Ok(())
     }
 
     /// Counts the number of votes for a candidate.
     async fn count_votes(
         arc_raft: Arc<Mutex<Raft>>, 
-        quorum: u64,
+        membership: Membership,
         current_term: u64,
         mut request_vote_replies: FuturesUnordered<impl 
-            Future<Output = RpcResult<RequestVoteReply>>>,
+            Future<Output = (u64, RpcResult<RequestVoteReply>)>>,
     ) -> Result<()> {
-        let mut vote_count = 1;
+        let mut votes = HashSet::from([arc_raft.lock()?.me]);
         
         // If there is only myself in the cluster, we can become leader immediately.
-        if vote_count >= quorum {
+        if membership.is_quorum(|id| votes.contains(&id)) {
             let mut raft = arc_raft.lock()?;
             let work_txs = HashMap::new();
             raft.become_leader(work_txs);
             return Ok(());
         }
 
-        while let Some(res) = request_vote_replies.next().await {
+        while let Some((peer, res)) = request_vote_replies.next().await {
             let (term, vote_granted) = match res {
                 Ok(res) => (res.get_ref().term, res.get_ref().vote_granted),
                 Err(_) => continue,
             };
             if vote_granted {
-                vote_count += 1;
-                if vote_count >= quorum {
+                votes.insert(peer);
+                if membership.is_quorum(|id| votes.contains(&id)) {
                     let mut raft = arc_raft.lock()?;
                     let mut work_txs = HashMap::new();
-                    for id in 0..raft.peers.len() as u64 {
+                    for id in raft.membership.voters() {
                         if id == raft.me {
                             continue;
                         }
@@ -239,140 +486,215 @@ impl Node {
         id: u64
     ) -> Result<()> {
         while let Some(log_index) = work_rx.recv().await {
-            let raft = arc_raft.lock()?;
+            let mut raft = arc_raft.lock()?;
 
             if log_index < raft.log.last_index {
                 continue;
             }
 
-            if let Role::Leader { ref next_index, ref work_txs, .. } = raft.role {
-                let prev_log_index = next_index.get(&id).unwrap() - 1;
-                let prev_log_term = raft.log.get(prev_log_index)?.map_or(0, |e| e.term);
-                let entries = raft.log
-                    .scan((prev_log_index+1)..=log_index)
-                    .collect::<Result<Vec<_>>>()?
-                    .into_iter()
-                    .map(|e| serialize(&e))
-                    .collect::<Result<Vec<_>>>()?;
-
-                let args = AppendEntriesArgs {
-                    term: raft.current_term,
-                    leader_id: raft.me,
-                    prev_log_index,
-                    prev_log_term,
-                    entries,
-                    leader_commit: raft.commit_index,
-                };
+            // Entries the peer needs have been compacted, so send it the snapshot instead.
+            let snapshot_index = raft.log.snapshot_index;
+            if let Role::Leader { ref next_index, ref mut snapshot_transfers, .. } = raft.role {
+                if next_index[&id] <= snapshot_index {
+                    if snapshot_transfers.insert(id) {
+                        Self::send_snapshot(&raft, arc_raft.clone(), id, log_index)?;
+                    }
+                    continue;
+                }
+            }
 
-                let current_term = raft.current_term;
-                let work_tx = work_txs.get(&id).unwrap().clone();
-                let mut client = raft.peers[id as usize].clone();
-                let raft = arc_raft.clone();
-                tokio::spawn(async move {
-                    let (term, success) = match client.append_entries(args).await {
-                        Ok(res) => (res.get_ref().term, res.get_ref().success),
-                        Err(_) => {
-                            work_tx.send(log_index).unwrap();
-                            return;
-                        },
-                    };
-                    if term > current_term {
-                        raft.lock().unwrap().become_follower(term, None);
+            let Some((args, last_index)) = raft.next_batch(id)? else { continue };
+            let prev_log_index = args.prev_log_index;
+            let current_term = raft.current_term;
+            let work_tx = match raft.role {
+                Role::Leader { ref work_txs, .. } => work_txs.get(&id).unwrap().clone(),
+                _ => continue,
+            };
+            let mut client = raft.client(id)?;
+            let raft = arc_raft.clone();
+            let arc_raft = arc_raft.clone();
+            tokio::spawn(async move {
+                let reply = client.append_entries(args).await;
+                let mut raft = raft.lock().unwrap();
+                // The leader state has been reset since a later term began, so the reply is stale.
+                if raft.current_term != current_term {
+                    return;
+                }
+                let (term, success) = match reply {
+                    Ok(res) => (res.get_ref().term, res.get_ref().success),
+                    Err(_) => {
+                        if raft.finish_batch(id, true) {
+                            work_tx.send(raft.log.last_index).unwrap();
+                        }
                         return;
+                    },
+                };
+                if term > current_term {
+                    raft.become_follower(term, None);
+                    return;
+                }
+                if term < current_term {
+                    if raft.finish_batch(id, true) {
+                        work_tx.send(raft.log.last_index).unwrap();
                     }
-                    match success {
-                        true => {
-                            let mut raft = raft.lock().unwrap();
-                            let original_commit_index = raft.commit_index;
-                            if let Role::Leader { ref mut next_index, ref mut match_index, .. } = raft.role {
-                                if log_index > match_index[&id] {
-                                    next_index.entry(id).and_modify(|index| *index = log_index + 1);
-                                    match_index.entry(id).and_modify(|index| *index = log_index);
-                                }
+                    return;
+                }
+                match success {
+                    true => {
+                        raft.record_ack(id, current_term);
+                        let original_commit_index = raft.commit_index;
+                        if let Role::Leader { ref mut next_index, ref mut match_index, .. } = raft.role {
+                            if last_index > match_index[&id] {
+                                next_index.entry(id).and_modify(|index| *index = last_index + 1);
+                                match_index.entry(id).and_modify(|index| *index = last_index);
+                            }
 
-                                // Checks if there are entries ready to be committed.
-                                let mut match_indexes = match_index.values().cloned().collect::<Vec<_>>();
-                                match_indexes.push(raft.log.last_index);
-                                match_indexes.sort_unstable();
-                                let mut new_commit_index = match_indexes[raft.quorum() as usize - 1];
-                                while let Some(entry) = raft.log.get(new_commit_index).unwrap() {
-                                    if entry.term == raft.current_term {
-                                        break;
-                                    }
-                                    new_commit_index -= 1;
-                                }
-                                if new_commit_index > original_commit_index {
-                                    let entries = raft.log
-                                        .scan((original_commit_index+1)..=new_commit_index)
-                                        .collect::<Result<Vec<_>>>()
-                                        .unwrap();
-                                    for Entry { index, term, command } in entries {
-                                        let apply_msg = ApplyMsg {
-                                            log_index: index,
-                                            command,
-                                        };
-                                        raft.apply_tx.send(apply_msg).unwrap();
-                                    }
-                                    raft.commit_index = new_commit_index;
+                            // Checks if there are entries ready to be committed.
+                            let mut new_commit_index = raft.log.last_index;
+                            while new_commit_index > original_commit_index {
+                                let ready = raft.should_commit(new_commit_index) && raft.log
+                                    .get(new_commit_index)
+                                    .unwrap()
+                                    .is_some_and(|e| e.term == raft.current_term);
+                                if ready {
+                                    break;
                                 }
-                                
+                                new_commit_index -= 1;
                             }
-                        },
+                            raft.commit_to(new_commit_index).unwrap();
+                        }
+                        raft.update_metrics();
 
-                        false => {
-                            let mut raft = raft.lock().unwrap();
-                            if let Role::Leader { ref mut next_index, .. } = raft.role {
-                                next_index.entry(id).and_modify(|index| *index -= 1);
-                            }
-                            work_tx.send(log_index).unwrap();
-                        },
-                    }
-                });
-            }
+                        // Sends the next batch, if the peer isn't caught up yet.
+                        if raft.finish_batch(id, false) {
+                            work_tx.send(raft.log.last_index).unwrap();
+                        }
+
+                        // Hands over leadership once the transfer target is caught up.
+                        if let Some(args) = raft.transfer_ready(id) {
+                            Self::send_timeout_now(arc_raft, &raft, id, args).unwrap();
+                        }
+                    },
+
+                    false => {
+                        let more = raft.reject_batch(id, prev_log_index);
+                        raft.update_metrics();
+                        if more {
+                            work_tx.send(raft.log.last_index).unwrap();
+                        }
+                    },
+                }
+            });
         }
 
         Ok(())
     }
+
+    /// Tells the target of a leadership transfer to start an election, stepping down once it
+    /// has moved on to a later term.
+    fn send_timeout_now(
+        arc_raft: Arc<Mutex<Raft>>,
+        raft: &Raft,
+        target: u64,
+        args: TimeoutNowArgs,
+    ) -> Result<()> {
+        let mut client = raft.client(target)?;
+        tokio::spawn(async move {
+            let Ok(reply) = client.timeout_now(args).await else { return };
+            let term = reply.get_ref().term;
+            let mut raft = arc_raft.lock().unwrap();
+            if term > raft.current_term {
+                raft.become_follower(term, None);
+            }
+        });
+        Ok(())
+    }
+
+    /// Streams the latest snapshot to a peer in chunks, then resumes replication after it.
+    fn send_snapshot(
+        raft: &Raft,
+        arc_raft: Arc<Mutex<Raft>>,
+        id: u64,
+        log_index: u64,
+    ) -> Result<()> {
+        let Role::Leader { ref work_txs, .. } = raft.role else { return Ok(()) };
+        let chunks = raft
+            .snapshot_chunks()?
+            .ok_or_else(|| Error::Internal("No snapshot to send".into()))?;
+        let snapshot_index = raft.log.snapshot_index;
+        let current_term = raft.current_term;
+        let work_tx = work_txs.get(&id).unwrap().clone();
+        let mut client = raft.client(id)?;
+        tokio::spawn(async move {
+            let mut term = current_term;
+            let mut sent = true;
+            for chunk in chunks {
+                match client.install_snapshot(chunk).await {
+                    Ok(res) => term = res.get_ref().term,
+                    Err(_) => sent = false,
+                }
+                if !sent || term > current_term {
+                    break;
+                }
+            }
+            let mut raft = arc_raft.lock().unwrap();
+            if term > current_term {
+                raft.become_follower(term, None);
+                return;
+            }
+            if let Role::Leader {
+                ref mut next_index,
+                ref mut match_index,
+                ref mut snapshot_transfers,
+                ..
+            } = raft.role
+            {
+                snapshot_transfers.remove(&id);
+                if sent && snapshot_index > match_index[&id] {
+                    next_index.insert(id, snapshot_index + 1);
+                    match_index.insert(id, snapshot_index);
+                }
+            }
+            if sent {
+                raft.record_ack(id, current_term);
+            }
+            raft.update_metrics();
+            work_tx.send(log_index).unwrap();
+        });
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
 impl RaftService for Node {
+    /// PreVote RPC handler.
+    async fn pre_vote(
+        &self,
+        request: Request<PreVoteArgs>,
+    ) -> RpcResult<PreVoteReply> {
+        let raft = self.raft.lock().unwrap();
+        Ok(Response::new(raft.pre_vote(request.into_inner())))
+    }
+
+    /// TimeoutNow RPC handler.
+    async fn timeout_now(
+        &self,
+        request: Request<TimeoutNowArgs>,
+    ) -> RpcResult<TimeoutNowReply> {
+        let mut raft = self.raft.lock().unwrap();
+        if raft.timeout_now(request.into_inner()) {
+            Self::campaign(self.raft.clone(), &mut raft);
+        }
+        Ok(Response::new(TimeoutNowReply { term: raft.current_term }))
+    }
+
     /// RequestVote RPC handler.
     async fn request_vote(
         &self,
         request: Request<RequestVoteArgs>,
     ) -> RpcResult<RequestVoteReply> {
         let mut raft = self.raft.lock().unwrap();
-        let args = request.into_inner();
-
-        if args.term < raft.current_term {
-            let reply = RequestVoteReply {
-                term: raft.current_term,
-                vote_granted: false
-            };
-            return Ok(Response::new(reply));
-        }
-
-        if args.term > raft.current_term {
-            raft.become_follower(args.term, None);
-        }
-
-        if raft.voted_for.is_none() || raft.voted_for == Some(args.candidate_id) {
-            raft.voted_for = Some(args.candidate_id);
-            raft.persist();
-        } else {
-            let reply = RequestVoteReply {
-                term: raft.current_term,
-                vote_granted: false
-            };
-            return Ok(Response::new(reply));
-        }
-
-        let reply = RequestVoteReply {
-            term: raft.current_term,
-            vote_granted: true
-        };
-        Ok(Response::new(reply))
+        Ok(Response::new(raft.request_vote(request.into_inner())))
     }
 
     /// AppendEntries RPC handler.
@@ -381,61 +703,15 @@ impl RaftService for Node {
         request: Request<AppendEntriesArgs>,
     ) -> RpcResult<AppendEntriesReply> {
         let mut raft = self.raft.lock().unwrap();
-        let args = request.into_inner();
-
-        if args.term < raft.current_term {
-            let reply = AppendEntriesReply {
-                term: raft.current_term,
-                success: false
-            };
-            return Ok(Response::new(reply));
-        }
-
-        if let Role::Candidate { .. } | Role::Leader { .. } = raft.role {
-            raft.become_follower(args.term, None);
-        }
-
-        if let Role::Follower { ref mut leader, ref mut leader_seen_ticks, .. } = raft.role {
-            *leader_seen_ticks = 0;
-            *leader = Some(args.leader_id);
-        }
-
-        if args.prev_log_index != 0 && 
-            raft.log.get(args.prev_log_index)?.map_or(true, |e| e.term != args.prev_log_term) {
-            let reply = AppendEntriesReply {
-                term: raft.current_term,
-                success: false
-            };
-            return Ok(Response::new(reply));
-        }
-
-        raft.log.splice(args.entries.iter().map(|e| deserialize(e)).collect::<Result<_>>()?)?;
-
-        // Commits entries if necessary.
-        if args.leader_commit > raft.commit_index {
-            let commit_index = std::cmp::min(args.leader_commit, raft.log.last_index);
-            for index in (raft.commit_index + 1)..=commit_index {
-                let Entry {index, term, command} = raft.log.get(index)?
-                    .ok_or(Error::Internal(format!("Expected entry at index {}", index)))?;
-                let apply_msg = ApplyMsg {
-                    log_index: index,
-                    command,
-                };
-                match raft.apply_tx.send(apply_msg) {
-                    Ok(_) => {},
-                    Err(e) => {
-                        return Err(Status::internal(format!("Failed to send apply msg: {}", e)));
-                    },
-                }
-            }
-            raft.commit_index = commit_index;
-            raft.log.commit(commit_index)?;
-        }
+        Ok(Response::new(raft.append_entries(request.into_inner())?))
+    }
 
-        let reply = AppendEntriesReply {
-            term: raft.current_term,
-            success: true
-        };
-        Ok(Response::new(reply))
+    /// InstallSnapshot RPC handler.
+    async fn install_snapshot(
+        &self,
+        request: Request<InstallSnapshotArgs>,
+    ) -> RpcResult<InstallSnapshotReply> {
+        let mut raft = self.raft.lock().unwrap();
+        Ok(Response::new(raft.install_snapshot(request.into_inner())?))
     }
 }
\ No newline at end of file
@@ -9,7 +9,7 @@ use crate::error::{Result, RpcResult, Error};
 use crate::proto::featherkv::{FeatherKv, RegistrationRequest, RegistrationReply, ExecutionReply, ExecutionRequest};
 use crate::sql::engine;
 use crate::storage::log::LogStore;
-use super::{Node, Driver, State, ApplyResult};
+use super::{Node, Driver, State, ApplyResult, RaftConfig};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 /// A Raft session command.
@@ -27,6 +27,12 @@ pub enum Command {
     Registration {
         session_id: u64,
     },
+    /// A cluster membership change, see Membership. Handled by Raft itself rather than the
+    /// state machine.
+    ConfigEntry {
+        old_peers: Vec<String>,
+        new_peers: Vec<String>,
+    },
 }
 
 impl std::fmt::Display for Command {
@@ -35,20 +41,37 @@ impl std::fmt::Display for Command {
             Command::Mutation { session_id, sequence_number, mutation } => {
                 write!(
                     f,
-                    "Mutation {{ session_id: {}, sequence_number: {}, mutation: {} }}",
-                    session_id, sequence_number, FeatherKV::deserialize::<engine::raft::Mutation>(&mutation).unwrap(),
-                )
+                    "Mutation {{ session_id: {}, sequence_number: {}, mutation: ",
+                    session_id, sequence_number,
+                )?;
+                // The state machine need not be the SQL engine, e.g. KvState, so other
+                // payloads are shown as bytes.
+                match FeatherKV::deserialize::<engine::raft::Mutation>(mutation) {
+                    Ok(mutation) => write!(f, "{} }}", mutation),
+                    Err(_) => write!(f, "{:x?} }}", mutation),
+                }
             },
             Command::Query { session_id, sequence_number, query } => {
                 write!(
                     f,
-                    "Query {{ session_id: {}, sequence_number: {}, query: {} }}",
-                    session_id, sequence_number, FeatherKV::deserialize::<engine::raft::Query>(&query).unwrap(),
-                )
+                    "Query {{ session_id: {}, sequence_number: {}, query: ",
+                    session_id, sequence_number,
+                )?;
+                match FeatherKV::deserialize::<engine::raft::Query>(query) {
+                    Ok(query) => write!(f, "{} }}", query),
+                    Err(_) => write!(f, "{:x?} }}", query),
+                }
             },
             Command::Registration { session_id } => {
                 write!(f, "Registration {{ session_id: {} }}", session_id)
             },
+            Command::ConfigEntry { old_peers, new_peers } => {
+                write!(
+                    f,
+                    "ConfigEntry {{ old_peers: {:?}, new_peers: {:?} }}",
+                    old_peers, new_peers,
+                )
+            },
         }
     }
 }
@@ -58,6 +81,9 @@ pub enum RpcStatus {
     Ok,
     NotLeader,
     SessionExpired,
+    /// The node is a follower that redirects requests, and the client should retry them against
+    /// the leader at the given address.
+    RedirectToLeader { leader: String },
 }
 
 /// A Raft-based FeatherKV.
@@ -70,6 +96,9 @@ pub struct FeatherKV {
     registration_status: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<mpsc::UnboundedSender<Task>>>>>,
     /// The sending channels of the ongoing sessions.
     session_txs: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Task>>>>,
+    /// The client-facing addresses of all nodes, by node ID, which followers redirect clients
+    /// to. Empty if unknown, in which case requests are never redirected.
+    client_addrs: Vec<String>,
 }
 
 impl FeatherKV {
@@ -80,11 +109,25 @@ impl FeatherKV {
         peers: Vec<String>,
         state: Box<dyn State>,
         log_store: Box<dyn LogStore>,
+    ) -> Result<Self> {
+        Self::with_config(me, peers, vec![], state, log_store, RaftConfig::default()).await
+    }
+
+    /// Creates a new Raft FeatherKV with the given configuration. `client_addrs` are the
+    /// client-facing addresses of all nodes, by node ID, which followers redirect clients to
+    /// unless they proxy requests (see FollowerConfig).
+    pub async fn with_config(
+        me: u64,
+        peers: Vec<String>,
+        client_addrs: Vec<String>,
+        state: Box<dyn State>,
+        log_store: Box<dyn LogStore>,
+        config: RaftConfig,
     ) -> Result<Self> {
         let (apply_tx, apply_rx) = mpsc::unbounded_channel();
         let registration_status = Arc::new(Mutex::new(HashMap::new()));
 
-        let node = Node::new(me, peers, apply_tx, log_store).await?;
+        let node = Node::with_config(me, peers, apply_tx, log_store, config).await?;
         let driver = Driver::new(node.clone(), state, apply_rx, registration_status.clone());
 
         tokio::spawn(driver.drive());
@@ -95,9 +138,22 @@ impl FeatherKV {
             next_session_id: Arc::new(Mutex::new(1)),
             registration_status,
             session_txs: Arc::new(Mutex::new(HashMap::new())),
+            client_addrs,
         })
     }
 
+    /// Returns the status redirecting a client to the leader, if this node is a follower that
+    /// redirects requests and knows the leader's address.
+    fn redirect(&self) -> Result<Option<Vec<u8>>> {
+        let Some(leader) = self.node.redirect_target()? else { return Ok(None) };
+        match self.client_addrs.get(leader as usize) {
+            Some(leader) => {
+                Ok(Some(Self::serialize(&RpcStatus::RedirectToLeader { leader: leader.clone() })?))
+            }
+            None => Ok(None),
+        }
+    }
+
     /// Serializes a value for the Raft FeatherKV.
     fn serialize<V: Serialize>(value: &V) -> Result<Vec<u8>> {
         Ok(bincode::serialize(value)?)
@@ -112,6 +168,12 @@ impl FeatherKV {
 #[tonic::async_trait]
 impl FeatherKv for FeatherKV {
     async fn register(&self, _request: Request<RegistrationRequest>) -> RpcResult<RegistrationReply> {
+        if let Some(status) = self.redirect()? {
+            let reply =
+                RegistrationReply { status, session_id: 0, leader_hint: self.node.leader_id()? };
+            return Ok(Response::new(reply));
+        }
+
         let not_leader_reply = RegistrationReply {
             status: Self::serialize(&RpcStatus::NotLeader)?,
             session_id: 0,
@@ -174,7 +236,13 @@ impl FeatherKv for FeatherKV {
     }
 
     async fn mutate(&self, request: Request<ExecutionRequest>) -> RpcResult<ExecutionReply> {
-        let ExecutionRequest { session_id, sequence_number, operation } = request.into_inner();
+        if let Some(status) = self.redirect()? {
+            let reply =
+                ExecutionReply { status, response: vec![], leader_hint: self.node.leader_id()? };
+            return Ok(Response::new(reply));
+        }
+
+        let ExecutionRequest { session_id, sequence_number, operation, .. } = request.into_inner();
         let (reply_tx, reply_rx) = oneshot::channel();
         let task = Task {
             reply_tx,
@@ -188,11 +256,11 @@ impl FeatherKv for FeatherKV {
         {
             // Sends the task to the corresponding session.
             let mut session_txs = self.session_txs.lock().unwrap();
-            match session_txs.get_mut(&session_id) {
-                Some(session_tx) => {
-                    session_tx.send(task).unwrap();
-                },
-                None => {
+            // The session task exits once the session has expired.
+            match session_txs.get_mut(&session_id).map(|session_tx| session_tx.send(task)) {
+                Some(Ok(())) => {},
+                Some(Err(_)) | None => {
+                    session_txs.remove(&session_id);
                     let reply = ExecutionReply {
                         status: Self::serialize(&RpcStatus::SessionExpired)?,
                         response: vec![],
@@ -209,7 +277,20 @@ impl FeatherKv for FeatherKV {
     }
 
     async fn query(&self, request: Request<ExecutionRequest>) -> RpcResult<ExecutionReply> {
-        let ExecutionRequest { session_id, sequence_number, operation } = request.into_inner();
+        if let Some(status) = self.redirect()? {
+            let reply =
+                ExecutionReply { status, response: vec![], leader_hint: self.node.leader_id()? };
+            return Ok(Response::new(reply));
+        }
+
+        let ExecutionRequest { session_id, sequence_number, operation, min_clock } =
+            request.into_inner();
+
+        // Waits until the node has applied the entries the client has observed, such that the
+        // read isn't served from stale state.
+        if !min_clock.is_empty() {
+            self.node.wait_for_clock(&Self::deserialize(&min_clock)?).await?;
+        }
         let (reply_tx, reply_rx) = oneshot::channel();
         let task = Task {
             reply_tx,
@@ -223,11 +304,11 @@ impl FeatherKv for FeatherKV {
         {
             // Sends the task to the corresponding session.
             let mut session_txs = self.session_txs.lock().unwrap();
-            match session_txs.get_mut(&session_id) {
-                Some(session_tx) => {
-                    session_tx.send(task).unwrap();
-                },
-                None => {
+            // The session task exits once the session has expired.
+            match session_txs.get_mut(&session_id).map(|session_tx| session_tx.send(task)) {
+                Some(Ok(())) => {},
+                Some(Err(_)) | None => {
+                    session_txs.remove(&session_id);
                     let reply = ExecutionReply {
                         status: Self::serialize(&RpcStatus::SessionExpired)?,
                         response: vec![],
@@ -318,7 +399,7 @@ impl Session {
 
                     // Starts the command. If the node has lost leadership, replies `NotLeader`.
                     // Returns other errors to the client as internal errors.
-                    match self.node.start(command.clone()) {
+                    match self.submit(command.clone()) {
                         Err(Error::NotLeader) => {
                             reply_tx.send(not_leader_reply).unwrap();
                             break;
@@ -330,7 +411,22 @@ impl Session {
                     // Waits for the replica group to apply the execution command. Retries if timeout.
                     loop {
                         tokio::select! {
-                            Some(apply_result) = self.result_rx.recv() => {
+                            apply_result = self.result_rx.recv() => {
+                                // The driver drops the session's result channel when the
+                                // session expires.
+                                let apply_result = match apply_result {
+                                    Some(apply_result) => apply_result,
+                                    None => {
+                                        let reply = ExecutionReply {
+                                            status: Self::serialize(&RpcStatus::SessionExpired)?,
+                                            response: vec![],
+                                            leader_hint: self.node.leader_id()?,
+                                        };
+                                        reply_tx.send(reply).unwrap();
+                                        return Ok(());
+                                    }
+                                };
+
                                 // Discards the result if it is outdated.
                                 if apply_result.sequence_number < sequence_number {
                                     continue;
@@ -351,7 +447,7 @@ impl Session {
                             },
 
                             _ = tokio::time::sleep(tokio::time::Duration::from_secs(1)) => {
-                                match self.node.start(command.clone()) {
+                                match self.submit(command.clone()) {
                                     Err(Error::NotLeader) => {
                                         reply_tx.send(not_leader_reply).unwrap();
                                         break;
@@ -364,9 +460,9 @@ impl Session {
                     }
                 },
 
-                Command::Registration { .. } => {
+                Command::Registration { .. } | Command::ConfigEntry { .. } => {
                     return Err(Error::Internal(format!(
-                        "Unexpected registration command {:?}",
+                        "Unexpected command {:?}",
                         command,
                     )));
                 }
@@ -376,6 +472,15 @@ impl Session {
         Ok(())
     }
 
+    /// Submits a command to the Raft node. Queries are served by the leader via ReadIndex,
+    /// while mutations are appended to the log.
+    fn submit(&self, command: Command) -> Result<()> {
+        match command {
+            Command::Query { .. } => self.node.read(command),
+            command => self.node.start(command).map(|_| ()),
+        }
+    }
+
     /// Serializes a value for the Raft session.
     fn serialize<V: Serialize>(value: &V) -> Result<Vec<u8>> {
         Ok(bincode::serialize(value)?)
@@ -1,12 +1,14 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
+use serde::{Deserialize, Serialize};
 use tokio::sync::{mpsc};
 use tokio_stream::StreamExt;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
 use crate::error::{Result, Error};
-use super::{Command, Node, Session, Task};
+use crate::storage::kv::{KvStore, Range, WriteOp};
+use super::{Command, Node, Session, Snapshot, Task};
 
 /// A Raft-managed state machine.
 pub trait State: Send + Sync {
@@ -19,13 +21,109 @@ pub trait State: Send + Sync {
 
     /// Queries the state machine. All errors are propagated to the caller.
     fn query(&self, query: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Serializes the state machine as of the last applied index, for a Raft snapshot.
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Replaces the state machine with a snapshot taken at the given index.
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()>;
+}
+
+/// The reserved key under which KvState stores its applied index.
+pub(crate) const APPLIED_INDEX_KEY: &[u8] = b"\x00raft\x00applied_index";
+
+/// A state machine backed by a key-value store. Mutations are bincode-encoded batches of
+/// WriteOps, and queries bincode-encoded keys returning the encoded value, if any. The applied
+/// index is written in the same batch as each mutation, such that a durable store resumes
+/// applying from where it left off after a crash.
+pub struct KvState {
+    store: Box<dyn KvStore>,
+    applied_index: u64,
+}
+
+impl KvState {
+    /// Creates a state machine on top of a store, recovering its applied index.
+    pub fn new(store: Box<dyn KvStore>) -> Result<Self> {
+        let applied_index = match store.get(APPLIED_INDEX_KEY)? {
+            Some(bytes) => bincode::deserialize(&bytes)?,
+            None => 0,
+        };
+        Ok(Self { store, applied_index })
+    }
+
+    /// Returns the write storing the applied index.
+    fn applied_index_op(index: u64) -> Result<WriteOp> {
+        Ok(WriteOp::Put { key: APPLIED_INDEX_KEY.to_vec(), value: bincode::serialize(&index)? })
+    }
+}
+
+impl State for KvState {
+    fn applied_index(&self) -> u64 {
+        self.applied_index
+    }
+
+    fn mutate(&mut self, index: u64, mutation: Vec<u8>) -> Result<Vec<u8>> {
+        let mut ops: Vec<WriteOp> = bincode::deserialize(&mutation)?;
+        let reserved = ops.iter().any(|op| match op {
+            WriteOp::Put { key, .. } | WriteOp::Delete { key } => key == APPLIED_INDEX_KEY,
+        });
+        // Invalid mutations are still applied as no-ops, advancing the applied index.
+        let result = match reserved {
+            true => {
+                ops.clear();
+                Err(Error::Value("Can't write the reserved applied index key".into()))
+            }
+            false => Ok(Vec::new()),
+        };
+        ops.push(Self::applied_index_op(index)?);
+        self.store.write_batch(ops)?;
+        self.applied_index = index;
+        result
+    }
+
+    fn query(&self, query: Vec<u8>) -> Result<Vec<u8>> {
+        let key: Vec<u8> = bincode::deserialize(&query)?;
+        Ok(bincode::serialize(&self.store.get(&key)?)?)
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let pairs = self
+            .store
+            .scan(Range::from(..))?
+            .filter(|item| !matches!(item, Ok((key, _)) if key == APPLIED_INDEX_KEY))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(bincode::serialize(&pairs)?)
+    }
+
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        let pairs: Vec<(Vec<u8>, Vec<u8>)> = bincode::deserialize(&snapshot)?;
+        self.store.delete_range(Range::from(..))?;
+        let mut ops: Vec<_> =
+            pairs.into_iter().map(|(key, value)| WriteOp::Put { key, value }).collect();
+        ops.push(Self::applied_index_op(index)?);
+        self.store.write_batch(ops)?;
+        self.applied_index = index;
+        Ok(())
+    }
+}
+
+/// Notified synchronously as the leader commits log entries, before they are applied to the state
+/// machine, e.g. to respond to the client that issued a command without waiting for the driver.
+pub trait CommitNotifier: Send {
+    /// Called exactly once for each committed entry, in log order.
+    fn on_commit(&self, index: u64, term: u64, command: &Command);
 }
 
 /// A Raft state machine apply message.
 #[derive(Debug)]
-pub struct ApplyMsg {
-    pub log_index: u64,
-    pub command: Command,
+pub enum ApplyMsg {
+    /// A committed log entry to apply.
+    Entry { log_index: u64, command: Command },
+    /// A snapshot received from the leader, which replaces the state machine.
+    Snapshot(Snapshot),
+    /// A read-only query confirmed by the leader, which is served without being appended to the
+    /// log. All entries up to its read index are sent before it.
+    Read { read_index: u64, command: Command },
 }
 
 pub struct ApplyResult {
@@ -33,14 +131,69 @@ pub struct ApplyResult {
     pub result: Result<Vec<u8>>,
 }
 
-/// The meta-info for a client session.
-pub struct SesstionMeta {
+/// The deduplication state of a client session. Clients number their commands and retry a
+/// command with the same sequence number until they get a response, so a command may be
+/// committed more than once. Only the first is applied, and retries return its stored result.
+pub struct ClientSession {
     pub session_id: u64,
     pub last_applied_sequence_number: u64,
     pub stored_result: Option<Result<Vec<u8>>>,
+    /// The index of the session's last log entry, used to expire idle sessions.
+    pub last_active_index: u64,
     pub result_tx: mpsc::UnboundedSender<ApplyResult>,
 }
 
+impl ClientSession {
+    /// Applies a command with the given sequence number, unless it was already applied, returning
+    /// its result.
+    pub fn apply<F>(&mut self, sequence_number: u64, f: F) -> Result<Result<Vec<u8>>>
+    where
+        F: FnOnce() -> Result<Vec<u8>>,
+    {
+        match sequence_number.cmp(&self.last_applied_sequence_number) {
+            // If the command has already been applied, returns the stored result.
+            std::cmp::Ordering::Equal => self.stored_result.clone().ok_or_else(|| {
+                Error::Internal(format!("No stored result for session {}", self.session_id))
+            }),
+            // If the command has not been applied, applies it and stores the result.
+            std::cmp::Ordering::Greater => {
+                let result = f();
+                self.stored_result = Some(result.clone());
+                self.last_applied_sequence_number = sequence_number;
+                Ok(result)
+            }
+            std::cmp::Ordering::Less => Err(Error::Internal(format!(
+                "Sequence number {} is smaller than last applied sequence number {}",
+                sequence_number, self.last_applied_sequence_number,
+            ))),
+        }
+    }
+
+    /// Whether the session has had no log entries for more than timeout entries as of the given
+    /// index. A timeout of 0 never expires sessions.
+    pub fn expired(&self, index: u64, timeout: u64) -> bool {
+        timeout > 0 && index.saturating_sub(self.last_active_index) > timeout
+    }
+}
+
+/// The state machine and session state included in a Raft snapshot.
+#[derive(Serialize, Deserialize)]
+struct SnapshotData {
+    /// The serialized state machine.
+    state: Vec<u8>,
+    /// The state of each session.
+    sessions: Vec<SessionSnapshot>,
+}
+
+/// The state of a session included in a Raft snapshot.
+#[derive(Serialize, Deserialize)]
+struct SessionSnapshot {
+    session_id: u64,
+    last_applied_sequence_number: u64,
+    stored_result: Option<Result<Vec<u8>>>,
+    last_active_index: u64,
+}
+
 /// Drives a state machine, taking operations from `apply_rx` and sending results via `dispatcher_tx`.
 pub struct Driver {
     /// The underlying Raft node.
@@ -52,7 +205,7 @@ pub struct Driver {
     /// The channel to send registration results to.
     registration_status: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<mpsc::UnboundedSender<Task>>>>>,
     /// The ongoing sessions.
-    sessions: HashMap<u64, SesstionMeta>,
+    sessions: HashMap<u64, ClientSession>,
 }
 
 impl Driver {
@@ -75,8 +228,21 @@ impl Driver {
     /// Drives a state machine.
     pub async fn drive(mut self) -> Result<()> {
         while let Some(msg) = self.apply_rx.next().await {
-            println!("Applying cmd {}: {}", msg.log_index, msg.command);
-            if let Err(e) = self.execute(msg) {
+            let result = match msg {
+                ApplyMsg::Entry { log_index, command } => {
+                    println!("Applying cmd {}: {}", log_index, command);
+                    self.execute(log_index, command)
+                }
+                ApplyMsg::Snapshot(snapshot) => {
+                    println!("Restoring snapshot at {}", snapshot.last_index);
+                    self.restore_snapshot(snapshot)
+                }
+                ApplyMsg::Read { read_index, command } => {
+                    println!("Reading at {}: {}", read_index, command);
+                    self.apply(read_index, command)
+                }
+            };
+            if let Err(e) = result {
                 println!("Error applying: {:?}", e);
                 return Err(e);
             }
@@ -84,88 +250,110 @@ impl Driver {
         Ok(())
     }
 
-    /// Executes a state machine apply message.
-    fn execute(&mut self, apply_msg: ApplyMsg) -> Result<()> {
-        let ApplyMsg { log_index, command } = apply_msg;
+    /// Snapshots the state machine and sessions as of the given applied index, and hands the
+    /// snapshot to the Raft node to compact its log.
+    pub fn create_snapshot(&mut self, index: u64) -> Result<Snapshot> {
+        let data = SnapshotData {
+            state: self.state.snapshot()?,
+            sessions: self
+                .sessions
+                .values()
+                .map(|s| SessionSnapshot {
+                    session_id: s.session_id,
+                    last_applied_sequence_number: s.last_applied_sequence_number,
+                    stored_result: s.stored_result.clone(),
+                    last_active_index: s.last_active_index,
+                })
+                .collect(),
+        };
+        self.node.create_snapshot(index, bincode::serialize(&data)?)
+    }
+
+    /// Replaces the state machine and sessions with a snapshot from the leader.
+    fn restore_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        let data: SnapshotData = bincode::deserialize(&snapshot.data)?;
+        self.state.restore(snapshot.last_index, data.state)?;
+        self.node.applied(snapshot.last_index);
+        for session in data.sessions {
+            if !self.sessions.contains_key(&session.session_id) {
+                self.register_session(session.session_id);
+            }
+            let session_meta = self.sessions.get_mut(&session.session_id).unwrap();
+            session_meta.last_applied_sequence_number = session.last_applied_sequence_number;
+            session_meta.stored_result = session.stored_result;
+            session_meta.last_active_index = session.last_active_index;
+        }
+        Ok(())
+    }
+
+    /// Records a new session and spawns it, returning the channel to send it tasks. Overwrites
+    /// the existing session if any.
+    fn register_session(&mut self, session_id: u64) -> mpsc::UnboundedSender<Task> {
+        let (task_tx, task_rx) = mpsc::unbounded_channel();
+        let (result_tx, result_rx) = mpsc::unbounded_channel();
+        self.sessions.insert(session_id, ClientSession {
+            session_id,
+            last_applied_sequence_number: 0,
+            stored_result: None,
+            last_active_index: 0,
+            result_tx,
+        });
+        tokio::spawn(Session::new(self.node.clone(), session_id, task_rx, result_rx).serve());
+        task_tx
+    }
+
+    /// Executes a committed command, snapshotting the state machine afterwards if due. Sessions
+    /// idle for longer than the session timeout are expired, which is deterministic since the
+    /// timeout is measured in log entries.
+    fn execute(&mut self, log_index: u64, command: Command) -> Result<()> {
+        let session_id = match command {
+            Command::Mutation { session_id, .. } | Command::Registration { session_id } => {
+                Some(session_id)
+            }
+            _ => None,
+        };
+        self.apply(log_index, command)?;
+        if let Some(session) = session_id.and_then(|id| self.sessions.get_mut(&id)) {
+            session.last_active_index = log_index;
+        }
+        let timeout = self.node.session_timeout()?;
+        self.sessions.retain(|_, session| !session.expired(log_index, timeout));
+        self.node.applied(log_index);
+        if self.node.snapshot_due(log_index)? {
+            self.create_snapshot(log_index)?;
+        }
+        Ok(())
+    }
+
+    /// Applies a committed command to the state machine. Commands of expired sessions are
+    /// ignored, and their session tasks notice the expiry when their result channel closes.
+    fn apply(&mut self, log_index: u64, command: Command) -> Result<()> {
         match command {
             Command::Mutation { session_id, sequence_number, mutation } => {
-                let session_meta = self.sessions.get_mut(&session_id)
-                    .ok_or_else(|| Error::Internal(format!("Session {} not found", session_id)))?;
-
-                let result = 
-                    // If the operation has already been applied, returns the stored result.
-                    if session_meta.last_applied_sequence_number == sequence_number {
-                        session_meta.stored_result
-                            .clone()
-                            .ok_or_else(|| Error::Internal(format!(
-                                "No stored result for session {}",
-                                session_id,
-                            )))?
-                    }
-                    // If the operation has not been applied, applies it and stores the result.
-                    else if session_meta.last_applied_sequence_number < sequence_number {
-                        let result = self.state.mutate(log_index, mutation);
-                        session_meta.stored_result = Some(result.clone());
-                        session_meta.last_applied_sequence_number = sequence_number;
-                        result
-                    }
-                    // If the sequence number is smaller, returns an error.
-                    else {
-                        return Err(Error::Internal(format!(
-                            "Sequence number {} is smaller than last applied sequence number {}",
-                            sequence_number,
-                            session_meta.last_applied_sequence_number,
-                        )));
-                    };
+                let session = match self.sessions.get_mut(&session_id) {
+                    Some(session) => session,
+                    None => return Ok(()),
+                };
+                let state = &mut self.state;
+                let result = session.apply(sequence_number, || state.mutate(log_index, mutation))?;
 
                 // If the server is the leader, sends the result to the corresponding session.
                 if self.node.is_leader()? {
-                    let apply_result = ApplyResult {
-                        sequence_number,
-                        result,
-                    };
-                    session_meta.result_tx.send(apply_result)?;
+                    session.result_tx.send(ApplyResult { sequence_number, result })?;
                 }
             },
 
-            // TODO: Currently identical to Mutation; could be refactored for better performance.
             Command::Query { session_id, sequence_number, query } => {
-                let session_meta = self.sessions.get_mut(&session_id)
-                    .ok_or_else(|| Error::Internal(format!("Session {} not found", session_id)))?;
-
-                let result = 
-                    // If the operation has already been applied, returns the stored result.
-                    if session_meta.last_applied_sequence_number == sequence_number {
-                        session_meta.stored_result
-                            .clone()
-                            .ok_or_else(|| Error::Internal(format!(
-                                "No stored result for session {}",
-                                session_id,
-                            )))?
-                    }
-                    // If the operation has not been applied, applies it and stores the result.
-                    else if session_meta.last_applied_sequence_number < sequence_number {
-                        let result = self.state.query(query);
-                        session_meta.stored_result = Some(result.clone());
-                        session_meta.last_applied_sequence_number = sequence_number;
-                        result
-                    }
-                    // If the sequence number is smaller, returns an error.
-                    else {
-                        return Err(Error::Internal(format!(
-                            "Sequence number {} is smaller than last applied sequence number {}",
-                            sequence_number,
-                            session_meta.last_applied_sequence_number,
-                        )));
-                    };
+                let session = match self.sessions.get_mut(&session_id) {
+                    Some(session) => session,
+                    None => return Ok(()),
+                };
+                let state = &self.state;
+                let result = session.apply(sequence_number, || state.query(query))?;
 
                 // If the server is the leader, sends the result to the corresponding session.
                 if self.node.is_leader()? {
-                    let apply_result = ApplyResult {
-                        sequence_number,
-                        result,
-                    };
-                    session_meta.result_tx.send(apply_result)?;
+                    session.result_tx.send(ApplyResult { sequence_number, result })?;
                 }
             },
 
@@ -175,19 +363,8 @@ impl Driver {
                 //     return Ok(());
                 // }
                 
-                // Records the meta-data of the session. Ovewrites the existing session if any.
-                let (task_tx, task_rx) = mpsc::unbounded_channel();
-                let (result_tx, result_rx) = mpsc::unbounded_channel();
-                self.sessions.insert(session_id, SesstionMeta {
-                    session_id,
-                    last_applied_sequence_number: 0,
-                    stored_result: None,
-                    result_tx,
-                });
-
-                // Spawns a new session.
-                let node = self.node.clone();
-                tokio::spawn(Session::new(node, session_id, task_rx, result_rx).serve());
+                // Records the meta-data of the session and spawns it.
+                let task_tx = self.register_session(session_id);
 
                 // Notifies the server that the session has been registered.
                 if self.node.is_leader()? {
@@ -197,8 +374,121 @@ impl Driver {
                     registration_tx.send(task_tx).unwrap();
                 }
             },
+
+            // Membership changes only concern Raft itself.
+            Command::ConfigEntry { .. } => {},
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::{StdBPlusTree, WalStore};
+
+    fn put(key: &[u8], value: &[u8]) -> WriteOp {
+        WriteOp::Put { key: key.to_vec(), value: value.to_vec() }
+    }
+
+    fn mutate(state: &mut KvState, index: u64, ops: Vec<WriteOp>) -> Result<Vec<u8>> {
+        state.mutate(index, bincode::serialize(&ops)?)
+    }
+
+    fn get(state: &KvState, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(bincode::deserialize(&state.query(bincode::serialize(&key.to_vec())?)?)?)
+    }
+
+    #[test]
+    fn kv_state() -> Result<()> {
+        let mut state = KvState::new(Box::new(StdBPlusTree::new()))?;
+        assert_eq!(0, state.applied_index());
+
+        mutate(&mut state, 1, vec![put(b"a", b"1"), put(b"b", b"2")])?;
+        mutate(&mut state, 3, vec![WriteOp::Delete { key: b"a".to_vec() }, put(b"c", b"3")])?;
+        assert_eq!(3, state.applied_index());
+        assert_eq!(None, get(&state, b"a")?);
+        assert_eq!(Some(b"2".to_vec()), get(&state, b"b")?);
+
+        // Writes to the reserved key fail, but still advance the applied index.
+        assert!(mutate(&mut state, 4, vec![put(b"d", b"4"), put(APPLIED_INDEX_KEY, b"")]).is_err());
+        assert_eq!(4, state.applied_index());
+        assert_eq!(None, get(&state, b"d")?);
+        Ok(())
+    }
+
+    #[test]
+    fn client_session_dedup() -> Result<()> {
+        let (result_tx, _result_rx) = mpsc::unbounded_channel();
+        let mut session = ClientSession {
+            session_id: 1,
+            last_applied_sequence_number: 0,
+            stored_result: None,
+            last_active_index: 0,
+            result_tx,
+        };
+        let mut state = KvState::new(Box::new(StdBPlusTree::new()))?;
+        let ops = bincode::serialize(&vec![put(b"a", b"1")])?;
+        let applied = std::cell::Cell::new(0);
+        let mut apply = |session: &mut ClientSession, index, sequence_number| {
+            session.apply(sequence_number, || {
+                applied.set(applied.get() + 1);
+                state.mutate(index, ops.clone())
+            })
+        };
+
+        // A retried command returns the original result without being applied again.
+        assert_eq!(Ok(vec![]), apply(&mut session, 1, 1)?);
+        assert_eq!(Ok(vec![]), apply(&mut session, 2, 1)?);
+        assert_eq!(1, applied.get());
+        assert_eq!(Ok(vec![]), apply(&mut session, 3, 2)?);
+        assert_eq!(2, applied.get());
+        assert!(apply(&mut session, 4, 1).is_err());
+        assert_eq!(2, applied.get());
+
+        // Sessions expire once idle for more than the timeout, unless it is 0.
+        session.last_active_index = 3;
+        assert!(!session.expired(13, 10));
+        assert!(session.expired(14, 10));
+        assert!(!session.expired(14, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn kv_state_crash_recovery() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("follower.wal");
+        let mut leader = KvState::new(Box::new(StdBPlusTree::new()))?;
+        let mut follower = KvState::new(Box::new(WalStore::open(StdBPlusTree::new(), &path)?))?;
+        for index in 1..=2 {
+            let ops = vec![put(format!("k{}", index).as_bytes(), b"v")];
+            mutate(&mut leader, index, ops.clone())?;
+            mutate(&mut follower, index, ops)?;
         }
 
+        // The follower crashes, and recovers its state and applied index from its log.
+        drop(follower);
+        let mut follower = KvState::new(Box::new(WalStore::open(StdBPlusTree::new(), &path)?))?;
+        assert_eq!(2, follower.applied_index());
+        assert_eq!(Some(b"v".to_vec()), get(&follower, b"k2")?);
+
+        // Meanwhile, the leader applied more entries and compacted its log, so the follower
+        // catches up by restoring a snapshot.
+        mutate(&mut leader, 3, vec![WriteOp::Delete { key: b"k1".to_vec() }])?;
+        mutate(&mut leader, 4, vec![put(b"k4", b"v")])?;
+        follower.restore(4, leader.snapshot()?)?;
+        assert_eq!(4, follower.applied_index());
+        assert_eq!(leader.snapshot()?, follower.snapshot()?);
+        assert_eq!(None, get(&follower, b"k1")?);
+
+        // The follower continues applying entries after the snapshot, also across restarts.
+        mutate(&mut follower, 5, vec![put(b"k5", b"v")])?;
+        drop(follower);
+        let follower = KvState::new(Box::new(WalStore::open(StdBPlusTree::new(), &path)?))?;
+        assert_eq!(5, follower.applied_index());
+        assert_eq!(Some(b"v".to_vec()), get(&follower, b"k4")?);
+        assert_eq!(Some(b"v".to_vec()), get(&follower, b"k5")?);
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,135 @@
+use std::collections::{HashSet, VecDeque};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::mpsc;
+
+use crate::error::Result;
+use crate::storage;
+use super::{ApplyMsg, Membership, Message, Raft};
+
+/// Faults injected by a TestNetwork into the messages it delivers.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkPolicy {
+    /// Directed links (from, to) whose messages are dropped.
+    pub cut: HashSet<(u64, u64)>,
+    /// The probability of dropping any other message.
+    pub drop_rate: f64,
+    /// Whether to deliver messages in random order, rather than in the order they were sent.
+    pub reorder: bool,
+}
+
+impl NetworkPolicy {
+    /// Cuts all links between a node and the other nodes of a cluster, in both directions.
+    pub fn isolate(&mut self, id: u64, size: u64) {
+        self.partition(&[&[id], &(0..size).filter(|&i| i != id).collect::<Vec<_>>()]);
+    }
+
+    /// Cuts all links between nodes in different groups, in both directions.
+    pub fn partition(&mut self, groups: &[&[u64]]) {
+        for (i, a) in groups.iter().enumerate() {
+            for b in groups.iter().skip(i + 1) {
+                for &from in a.iter() {
+                    for &to in b.iter() {
+                        self.cut.extend([(from, to), (to, from)]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Restores all cut links.
+    pub fn heal(&mut self) {
+        self.cut.clear();
+    }
+}
+
+/// A simulated cluster of Raft nodes, which exchange Messages through an in-memory queue
+/// rather than gRPC. Timeouts, message drops and reordering are all driven by the given seed,
+/// so a run is reproducible.
+pub struct TestNetwork {
+    pub nodes: Vec<Raft>,
+    pub policy: NetworkPolicy,
+    /// Messages in flight.
+    queue: VecDeque<Message>,
+    rng: StdRng,
+    /// Receivers for the nodes' applied entries, kept alive to not fail sends.
+    _apply_rxs: Vec<mpsc::UnboundedReceiver<ApplyMsg>>,
+}
+
+impl TestNetwork {
+    /// Creates a cluster of the given size, with all nodes as leaderless followers.
+    pub fn new(size: u64, seed: u64) -> Result<Self> {
+        let addrs = (1..=size).map(|port| format!("127.0.0.1:{}", port)).collect();
+        let membership = Membership::new(addrs);
+        let (mut nodes, mut apply_rxs) = (Vec::new(), Vec::new());
+        for id in 0..size {
+            let (apply_tx, apply_rx) = mpsc::unbounded_channel();
+            let mut raft = Raft::new(id, apply_tx, Box::new(storage::log::Memory::new()))?;
+            raft.membership = membership.clone();
+            raft.seed(seed.wrapping_add(id));
+            nodes.push(raft);
+            apply_rxs.push(apply_rx);
+        }
+        Ok(Self {
+            nodes,
+            policy: NetworkPolicy::default(),
+            queue: VecDeque::new(),
+            rng: StdRng::seed_from_u64(seed),
+            _apply_rxs: apply_rxs,
+        })
+    }
+
+    /// Sends messages through the network, dropping or reordering them as per the policy.
+    pub fn send(&mut self, msgs: Vec<Message>) {
+        for msg in msgs {
+            if self.policy.cut.contains(&(msg.from, msg.to))
+                || (self.policy.drop_rate > 0.0 && self.rng.gen_bool(self.policy.drop_rate))
+            {
+                continue;
+            }
+            match self.policy.reorder {
+                true => {
+                    let i = self.rng.gen_range(0..=self.queue.len());
+                    self.queue.insert(i, msg);
+                }
+                false => self.queue.push_back(msg),
+            }
+        }
+    }
+
+    /// Delivers messages until none are in flight, including the responses they trigger.
+    pub fn deliver(&mut self) -> Result<()> {
+        while let Some(msg) = self.queue.pop_front() {
+            let msgs = self.nodes[msg.to as usize].step(msg)?;
+            self.send(msgs);
+        }
+        Ok(())
+    }
+
+    /// Ticks every node once, and delivers the resulting messages.
+    pub fn tick(&mut self) -> Result<()> {
+        for i in 0..self.nodes.len() {
+            let msgs = self.nodes[i].tick()?;
+            self.send(msgs);
+        }
+        self.deliver()
+    }
+
+    /// Ticks until the condition holds, up to the given number of ticks. Returns the number of
+    /// ticks taken, or None if the condition never held.
+    pub fn tick_until(&mut self, max: u64, cond: impl Fn(&Self) -> bool) -> Result<Option<u64>> {
+        for ticks in 1..=max {
+            self.tick()?;
+            if cond(self) {
+                return Ok(Some(ticks));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the IDs of the nodes that consider themselves leader.
+    pub fn leaders(&self) -> Vec<u64> {
+        self.nodes.iter().filter(|n| n.is_leader()).map(|n| n.me).collect()
+    }
+}
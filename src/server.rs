@@ -1,3 +1,7 @@
+mod tcp;
+
+pub use tcp::Server;
+
 use std::collections::HashMap;
 use std::sync::{Mutex, Arc};
 
@@ -17,6 +21,12 @@ use crate::sql::types::Row;
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ClientRequest {
     Query(String),
+    /// Begins a read-write transaction, like the BEGIN statement.
+    Begin,
+    /// Commits the current transaction, like the COMMIT statement.
+    Commit,
+    /// Rolls back the current transaction, like the ROLLBACK statement.
+    Rollback,
     GetTable(String),
     ListTables,
     Status,
@@ -170,36 +180,47 @@ impl Session {
     }
 
     fn execute(&mut self, client_request: ClientRequest) -> Result<ClientResponse> {
-        Ok(match client_request {
-            ClientRequest::Query(query) => {
-                let result_set = self.sql.execute(&query)?;
-                ClientResponse::Query(result_set)
-            },
-            ClientRequest::GetTable(table) => {
-                let table = self.sql.with_txn(
-                    Mode::ReadOnly,
-                    |txn| {
-                        txn.assert_read_table(&table)
-                    }
-                )?;
-                ClientResponse::GetTable(table)
-            },
-            ClientRequest::ListTables => {
-                let result = self.sql.with_txn(
-                    Mode::ReadOnly,
-                    |txn| {
-                        Ok(txn.scan_tables()?.map(|t| t.name).collect())
-                    }
-                )?;
-                ClientResponse::ListTables(result)
-            },
-            ClientRequest::Status => {
-                todo!()
-            },
-        })
+        execute_request(&self.sql, client_request)
     }
 }
 
+/// Executes a client request in an SQL session.
+pub fn execute_request<E: SqlEngine + 'static>(
+    sql: &SqlSession<E>,
+    client_request: ClientRequest,
+) -> Result<ClientResponse> {
+    Ok(match client_request {
+        ClientRequest::Query(query) => {
+            let result_set = sql.execute(&query)?;
+            ClientResponse::Query(result_set)
+        },
+        ClientRequest::Begin => ClientResponse::Query(sql.execute("BEGIN")?),
+        ClientRequest::Commit => ClientResponse::Query(sql.execute("COMMIT")?),
+        ClientRequest::Rollback => ClientResponse::Query(sql.execute("ROLLBACK")?),
+        ClientRequest::GetTable(table) => {
+            let table = sql.with_txn(
+                Mode::ReadOnly,
+                |txn| {
+                    txn.assert_read_table(&table)
+                }
+            )?;
+            ClientResponse::GetTable(table)
+        },
+        ClientRequest::ListTables => {
+            let result = sql.with_txn(
+                Mode::ReadOnly,
+                |txn| {
+                    Ok(txn.scan_tables()?.map(|t| t.name).collect())
+                }
+            )?;
+            ClientResponse::ListTables(result)
+        },
+        ClientRequest::Status => {
+            return Err(Error::Value("Status requests are not supported yet".into()))
+        },
+    })
+}
+
 /// Serializes RPC arguments.
 pub fn serialize<V: Serialize>(value: &V) -> crate::error::Result<Vec<u8>> {
     Ok(bincode::serialize(value)?)
@@ -0,0 +1,58 @@
+use std::net::SocketAddr;
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::error::Result;
+use crate::sql::engine::{SqlEngine, SqlSession};
+use super::{deserialize, execute_request, serialize, ClientRequest};
+
+/// An SQL server accepting client connections over TCP. Messages in both directions are framed
+/// as a 4-byte big-endian length followed by the bincode-encoded payload: a ClientRequest from
+/// the client, answered by a Result<ClientResponse>. Each connection has its own SQL session,
+/// so a transaction spans the requests made on its connection.
+pub struct Server<E: SqlEngine> {
+    engine: E,
+    listener: TcpListener,
+}
+
+impl<E> Server<E>
+where
+    E: SqlEngine + Send + Sync + 'static,
+    E::EngineTxn: Send,
+{
+    /// Creates a server for an engine, listening on the given address.
+    pub async fn bind(engine: E, addr: impl ToSocketAddrs) -> Result<Self> {
+        Ok(Self { engine, listener: TcpListener::bind(addr).await? })
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Serves client connections, spawning a task for each.
+    pub async fn serve(self) -> Result<()> {
+        loop {
+            let (socket, addr) = self.listener.accept().await?;
+            let session = self.engine.session()?;
+            tokio::spawn(async move {
+                if let Err(err) = Self::serve_connection(session, socket).await {
+                    ::log::error!("Error serving client {}: {}", addr, err);
+                }
+            });
+        }
+    }
+
+    /// Serves a client connection until the client disconnects.
+    async fn serve_connection(session: SqlSession<E>, socket: TcpStream) -> Result<()> {
+        let mut framed = Framed::new(socket, LengthDelimitedCodec::new());
+        while let Some(frame) = framed.next().await {
+            let request: ClientRequest = deserialize(&frame?)?;
+            let response = tokio::task::block_in_place(|| execute_request(&session, request));
+            framed.send(serialize(&response)?.into()).await?;
+        }
+        Ok(())
+    }
+}
@@ -1,13 +1,16 @@
 #![allow(dead_code)]
 use std::borrow::Cow;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Serialize, Deserialize};
 
 use crate::concurrency::{MVCC, Transaction, Mode};
 use crate::error::{Error, Result};
-use crate::sql::schema::{Catalog, Table, Tables};
-use crate::sql::types::{Row, Value, Expression};
+use crate::sql::schema::{
+    migration_plan, Catalog, ColumnStats, FkAction, IndexStats, MigrationStep, Table, TableStats,
+    Tables,
+};
+use crate::sql::types::{DataType, Row, Value, Expression};
 use super::{SqlTxn, SqlEngine, RowScan, IndexScan};
 
 
@@ -19,11 +22,74 @@ pub struct KvSqlEngine {
 }
 
 impl KvSqlEngine {
+    /// The (major, minor) store format version written by this code. Stores written with a
+    /// different major version can't be opened, while minor versions are compatible.
+    pub const STORE_VERSION: (u32, u32) = (1, 1);
+
     /// Creates a new SQL engine.
     pub fn new(kv: MVCC) -> Self {
         Self { kv }
     }
 
+    /// Opens an SQL engine on a possibly existing store, after checking its format version. A
+    /// fresh store is initialized with the current version, and an older minor version is
+    /// upgraded in place. Errors with IncompatibleFormatVersion on a major version mismatch.
+    /// The sequences of SERIAL columns are then verified, see verify_serials().
+    pub fn open(kv: MVCC) -> Result<Self> {
+        let engine = Self::open_replica(kv)?;
+        engine.verify_serials()?;
+        Ok(engine)
+    }
+
+    /// Opens an SQL engine for a Raft state machine, like open() but without verifying the
+    /// sequences. That runs a transaction outside of the Raft log, which would make transaction
+    /// IDs diverge between replicas.
+    pub(super) fn open_replica(kv: MVCC) -> Result<Self> {
+        let (major, minor) = Self::STORE_VERSION;
+        match kv.store_version()? {
+            (0, 0) => kv.write_store_version(major, minor)?,
+            (m, n) if m == major && n < minor => kv.write_store_version(major, minor)?,
+            (m, _) if m == major => {}
+            (m, n) => {
+                return Err(Error::IncompatibleFormatVersion(format!(
+                    "Store has format version {}.{}, expected {}.x",
+                    m, n, major
+                )))
+            }
+        }
+        Ok(Self::new(kv))
+    }
+
+    /// Verifies that the sequence of each SERIAL column is past the column's largest value, such
+    /// that it won't assign existing values. Sequences that are behind, e.g. because their rows
+    /// were restored without them, are advanced with a warning.
+    fn verify_serials(&self) -> Result<()> {
+        let txn = self.begin(Mode::ReadWrite)?;
+        for table in txn.scan_tables()? {
+            for (i, column) in table.columns.iter().enumerate() {
+                if column.datatype != DataType::Serial {
+                    continue;
+                }
+                let mut max = None;
+                let mut scan = txn.scan(&table.name, None)?;
+                while let Some(row) = scan.next().transpose()? {
+                    if let Value::Integer(value) = row[i] {
+                        max = max.max(Some(value));
+                    }
+                }
+                let Some(max) = max else { continue };
+                let next = txn.advance_serial(&table.name, &column.name, max)?;
+                if next <= max {
+                    ::log::warn!(
+                        "Sequence of column {}.{} was at {}, advanced past existing value {}",
+                        table.name, column.name, next, max
+                    );
+                }
+            }
+        }
+        txn.commit()
+    }
+
     /// Fetches an unversioned metadata value.
     pub fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.kv.get_metadata(key)
@@ -68,6 +134,209 @@ impl KvSqlTxn {
         Self { txn }
     }
 
+    /// Returns the unversioned metadata key of a SERIAL column's sequence.
+    fn serial_key(table: &str, column: &str) -> Vec<u8> {
+        format!("\x00serial\x00{}\x00{}", table, column).into_bytes()
+    }
+
+    /// Atomically updates a SERIAL column's sequence, i.e. the next value to assign, by applying
+    /// the given function to it, retrying if it was updated concurrently. Returns the previous
+    /// value, which is 1 for a sequence that was never updated.
+    fn update_serial(
+        &self,
+        table: &str,
+        column: &str,
+        f: impl Fn(i64) -> Result<i64>,
+    ) -> Result<i64> {
+        let key = Self::serial_key(table, column);
+        loop {
+            let current = self.txn.get_metadata(&key)?;
+            let next = current.as_deref().map(deserialize).transpose()?.unwrap_or(1);
+            let new = f(next)?;
+            if new == next
+                || self.txn.compare_and_swap_metadata(&key, current.as_deref(), serialize(&new)?)?
+            {
+                return Ok(next);
+            }
+        }
+    }
+
+    /// Advances a SERIAL column's sequence past a value, if it isn't already. Returns the
+    /// previous next value.
+    fn advance_serial(&self, table: &str, column: &str, value: i64) -> Result<i64> {
+        self.update_serial(table, column, |next| Ok(next.max(value.saturating_add(1))))
+    }
+
+    /// Asserts that a table column is a SERIAL column.
+    fn assert_serial(&self, table: &str, column: &str) -> Result<()> {
+        match self.assert_read_table(table)?.get_column(column)?.datatype {
+            DataType::Serial => Ok(()),
+            _ => Err(Error::Value(format!("Column {} in table {} is not serial", column, table))),
+        }
+    }
+
+    /// Finds the rows referencing a primary key of a table, other than the row itself, as
+    /// (table, column index, action, row key) tuples. The action is the foreign key's ON DELETE
+    /// action, or its ON UPDATE action if the key is updated. Errors if the key is referenced by
+    /// a RESTRICT foreign key. If it's referenced by a NO ACTION foreign key, a check that it's
+    /// no longer referenced is deferred until commit.
+    fn referencing_rows(
+        &self,
+        table: &Table,
+        id: &Value,
+        update: bool,
+    ) -> Result<Vec<(Table, usize, FkAction, Value)>> {
+        let mut referencing = Vec::new();
+        let mut deferred = false;
+        for (t, references) in self.table_references(&table.name, true)? {
+            let t = self.assert_read_table(&t)?;
+            let references = references
+                .into_iter()
+                .map(|(c, on_delete, on_update)| {
+                    Ok((t.get_column_index(&c)?, c, if update { on_update } else { on_delete }))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let mut scan = self.scan(&t.name, None)?;
+            while let Some(row) = scan.next().transpose()? {
+                // The key may be referenced by another row in the same table, but not by the
+                // row itself.
+                let key = t.get_row_key(&row)?;
+                for (i, c, action) in &references {
+                    if row[*i] != *id || (t.name == table.name && &key == id) {
+                        continue;
+                    }
+                    match action {
+                        FkAction::NoAction => deferred = true,
+                        FkAction::Restrict if update => {
+                            return Err(Error::ConstraintViolation(format!(
+                                "Cannot update key {} of table {} because it is referenced by column {} in table {}",
+                                id.to_sql_literal(), table.name, c, t.name
+                            )))
+                        }
+                        FkAction::Restrict => {
+                            return Err(Error::ConstraintViolation(format!(
+                                "Cannot delete row {} from table {} because it is referenced by column {} in table {}",
+                                id.to_sql_literal(), table.name, c, t.name
+                            )))
+                        }
+                        action => referencing.push((t.clone(), *i, *action, key.clone())),
+                    }
+                }
+            }
+        }
+        if deferred {
+            self.defer(DeferredCheck::Referenced((&table.name).into(), id.into()))?;
+        }
+        Ok(referencing)
+    }
+
+    /// Queues a foreign key check until the transaction commits. Checks are stored as keys
+    /// written by the transaction, since Raft transactions are resumed for each mutation.
+    fn defer(&self, check: DeferredCheck) -> Result<()> {
+        self.txn.set(&SqlKey::Deferred(self.txn.id(), Some(check)).encode(), vec![])
+    }
+
+    /// Runs and removes the foreign key checks deferred until commit: the references of inserted
+    /// and updated rows must exist, and removed keys must no longer be referenced. Rows and keys
+    /// since removed or recreated are skipped.
+    fn check_deferred(&mut self) -> Result<()> {
+        let keys = self
+            .txn
+            .scan_prefix(&SqlKey::Deferred(self.txn.id(), None).encode())?
+            .map(|r| r.map(|(key, _)| key))
+            .collect::<Result<Vec<_>>>()?;
+        for key in keys {
+            match SqlKey::decode(&key)? {
+                SqlKey::Deferred(_, Some(DeferredCheck::References(table, id))) => {
+                    if let Some(row) = self.read(&table, &id)? {
+                        self.assert_read_table(&table)?.validate_references(&row, self)?;
+                    }
+                }
+                SqlKey::Deferred(_, Some(DeferredCheck::Referenced(table, id))) => {
+                    if self.read_table(&table)?.is_some() && self.read(&table, &id)?.is_none() {
+                        self.assert_unreferenced(&table, &id)?;
+                    }
+                }
+                _ => return Err(Error::Internal("Invalid deferred check key".into())),
+            }
+            self.txn.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Errors if a primary key of a table is referenced by any row.
+    fn assert_unreferenced(&self, table: &str, id: &Value) -> Result<()> {
+        for (t, references) in self.table_references(table, true)? {
+            let t = self.assert_read_table(&t)?;
+            for (column, _, _) in references {
+                let i = t.get_column_index(&column)?;
+                let mut scan = self.scan(&t.name, None)?;
+                while let Some(row) = scan.next().transpose()? {
+                    if row[i] == *id {
+                        return Err(Error::ConstraintViolation(format!(
+                            "Key {} of table {} is still referenced by column {} in table {}",
+                            id.to_sql_literal(),
+                            table,
+                            column,
+                            t.name
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies foreign key actions to the rows found by referencing_rows(), once the referenced
+    /// key has been deleted, or updated to the given new key. Rows already deleted or changed
+    /// by an earlier action are skipped, which ends cascades through circular references.
+    fn apply_references(
+        &mut self,
+        references: Vec<(Table, usize, FkAction, Value)>,
+        id: &Value,
+        new_id: Option<&Value>,
+    ) -> Result<()> {
+        for (table, i, action, key) in references {
+            let mut row = match self.read(&table.name, &key)? {
+                Some(row) if row[i] == *id => row,
+                _ => continue,
+            };
+            row[i] = match (action, new_id) {
+                (FkAction::Cascade, Some(new_id)) => new_id.clone(),
+                (FkAction::Cascade, None) => {
+                    self.delete(&table.name, &key)?;
+                    continue;
+                }
+                (FkAction::SetNull, _) => Value::Null,
+                (FkAction::SetDefault, _) => {
+                    table.columns[i].default.clone().unwrap_or(Value::Null)
+                }
+                (FkAction::NoAction | FkAction::Restrict, _) => continue,
+            };
+            self.update(&table.name, &key, row)?;
+        }
+        Ok(())
+    }
+
+    /// Removes a row and its index entries, without checking references to it.
+    fn remove_row(&mut self, table: &Table, id: &Value) -> Result<()> {
+        let indexes: Vec<_> =
+            table.columns.iter().enumerate().filter(|(_, c)| c.is_indexed).collect();
+        if !indexes.is_empty() {
+            if let Some(row) = self.read(&table.name, id)? {
+                for (i, column) in indexes {
+                    if !column.indexes(&row)? {
+                        continue;
+                    }
+                    let mut index = self.load_index(&table.name, &column.name, &row[i])?;
+                    index.remove(id);
+                    self.save_index(&table.name, &column.name, &row[i], index)?;
+                }
+            }
+        }
+        self.txn.delete(&SqlKey::Row((&table.name).into(), Some(id.into())).encode())
+    }
+
     /// Loads an index entry. TODO: ????
     fn load_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
         Ok(self
@@ -97,7 +366,12 @@ impl SqlTxn for KvSqlTxn {
         self.txn.mode()
     }
 
-    fn commit(self) -> Result<()> {
+    fn commit(mut self) -> Result<()> {
+        // A failed deferred check aborts the transaction.
+        if let Err(err) = self.check_deferred() {
+            self.txn.rollback()?;
+            return Err(err);
+        }
         self.txn.commit()
     }
 
@@ -107,21 +381,36 @@ impl SqlTxn for KvSqlTxn {
 
     fn create(&mut self, table: &str, row: Row) -> Result<()> {
         let table = self.assert_read_table(table)?;
+        let mut row = table.coerce_row(row)?;
+        table.assign_serials(&mut row, self)?;
+        // Given SERIAL values advance the sequence, so it won't assign them again.
+        for (column, value) in table.columns.iter().zip(row.iter()) {
+            if let (DataType::Serial, Value::Integer(value)) = (&column.datatype, value) {
+                self.advance_serial(&table.name, &column.name, *value)?;
+            }
+        }
         table.validate_row(&row, self)?;
         let id = table.get_row_key(&row)?;
         if self.read(&table.name, &id)?.is_some() {
             return Err(Error::Value(format!(
                 "Primary key {} already exists for table {}",
-                id, table.name
+                id.to_sql_literal(),
+                table.name
             )));
         }
+        if !table.foreign_keys().is_empty() {
+            self.defer(DeferredCheck::References((&table.name).into(), (&id).into()))?;
+        }
         self.txn.set(
             &SqlKey::Row(Cow::Borrowed(&table.name), Some(Cow::Borrowed(&id))).encode(),
-            serialize(&row)?,
+            serialize(&table.encode_row(row.clone()))?,
         )?;
         
         // Update indexes
-        for (i, column) in table.columns.iter().enumerate().filter(|(_, c)| c.is_indexed) {
+        for (i, column) in table.columns.iter().enumerate() {
+            if !column.indexes(&row)? {
+                continue;
+            }
             let mut indexes = self.load_index(&table.name, &column.name, &row[i])?;
             indexes.insert(id.clone());
             self.save_index(&table.name, &column.name, &row[i], indexes)?;
@@ -131,21 +420,29 @@ impl SqlTxn for KvSqlTxn {
     }
 
     fn read(&self, table: &str, id: &Value) -> Result<Option<Row>> {
+        let table = match self.read_table(table)? {
+            Some(table) => table,
+            None => return Ok(None),
+        };
         self.txn
-            .get(&SqlKey::Row(table.into(), Some(id.into())).encode())?
-            .map(|v| deserialize(&v))
+            .get(&SqlKey::Row((&table.name).into(), Some(id.into())).encode())?
+            .map(|v| deserialize(&v).map(|row| table.decode_row(row)))
             .transpose()
     }
 
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()> {
         let table = self.assert_read_table(table)?;
-
-        // If the primary key changes, we need to delete the old row and create a new one.
-        // Otherwise, we can just update the existing row.
-        if id != &table.get_row_key(&row)? {
-            self.delete(&table.name, id)?;
+        let row = table.coerce_row(row)?;
+
+        // If the primary key changes, we need to delete the old row and create a new one, and
+        // then apply the ON UPDATE actions of rows referencing the old key. Otherwise, we can
+        // just update the existing row.
+        let new_id = table.get_row_key(&row)?;
+        if id != &new_id {
+            let references = self.referencing_rows(&table, id, true)?;
+            self.remove_row(&table, id)?;
             self.create(&table.name, row)?;
-            return Ok(());
+            return self.apply_references(references, id, Some(&new_id));
         }
 
         // Update indexes.
@@ -155,12 +452,14 @@ impl SqlTxn for KvSqlTxn {
                 Error::Value(format!("Row {} does not exist in table {}", id, &table.name))
             )?;
             for (i, column) in indexes {
-                // TODO: why twice?
-                if old_row[i] != row[i] {
+                // Partial indexes may gain or lose the row even if the value is unchanged.
+                let (was_indexed, is_indexed) = (column.indexes(&old_row)?, column.indexes(&row)?);
+                if was_indexed && (!is_indexed || old_row[i] != row[i]) {
                     let mut index = self.load_index(&table.name, &column.name, &old_row[i])?;
                     index.remove(id);
                     self.save_index(&table.name, &column.name, &old_row[i], index)?;
-                    
+                }
+                if is_indexed && (!was_indexed || old_row[i] != row[i]) {
                     let mut index = self.load_index(&table.name, &column.name, &row[i])?;
                     index.insert(id.clone());
                     self.save_index(&table.name, &column.name, &row[i], index)?;
@@ -169,48 +468,18 @@ impl SqlTxn for KvSqlTxn {
         }
 
         table.validate_row(&row, self)?;
-        self.txn.set(&SqlKey::Row(table.name.into(), Some(id.into())).encode(), serialize(&row)?)
+        if !table.foreign_keys().is_empty() {
+            self.defer(DeferredCheck::References((&table.name).into(), id.into()))?;
+        }
+        let key = SqlKey::Row((&table.name).into(), Some(id.into())).encode();
+        self.txn.set(&key, serialize(&table.encode_row(row))?)
     }
 
     fn delete(&mut self, table: &str, id: &Value) -> Result<()> {
         let table = self.assert_read_table(table)?;
-
-        // Check if the value of the to-be-deleted primary key is being referenced.
-        for (t, cs) in self.table_references(&table.name, true)? {
-            let t = self.assert_read_table(&t)?;
-            let cs = cs
-                .into_iter()
-                .map(|c| Ok((t.get_column_index(&c)?, c)))
-                .collect::<Result<Vec<_>>>()?;
-            let mut scan = self.scan(&t.name, None)?;
-            while let Some(row) = scan.next().transpose()? {
-                // There are two invalid deleting senarios:
-                // 1. PK's value is being referenced in another table;
-                // 2. PK's value is being referenced in the current table, and is not in the same row to be deleted.
-                for (i, c) in &cs {
-                    if row[*i] == *id && (t.name != table.name || id != &table.get_row_key(&row)?) {
-                        return Err(Error::Value(format!(
-                            "Cannot delete row {} from table {} because it is referenced by column {} in table {}",
-                            id, table.name, c, t.name
-                        )));
-                    }
-                }
-            }
-        }
-
-        // Delete indexes.
-        let indexes: Vec<_> = table.columns.iter().enumerate().filter(|(_, c)| c.is_indexed).collect();
-        if !indexes.is_empty() {
-            if let Some(row) = self.read(&table.name, id)? {
-                for (i, column) in indexes {
-                    let mut index = self.load_index(&table.name, &column.name, &row[i])?;
-                    index.remove(id);
-                    self.save_index(&table.name, &column.name, &row[i], index)?;
-                }
-            }
-        }
-
-        self.txn.delete(&SqlKey::Row(table.name.into(), Some(id.into())).encode())
+        let references = self.referencing_rows(&table, id, false)?;
+        self.remove_row(&table, id)?;
+        self.apply_references(references, id, None)
     }
 
     fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
@@ -226,13 +495,15 @@ impl SqlTxn for KvSqlTxn {
             self.txn
                 .scan_prefix(&SqlKey::Row((&table.name).into(), None).encode())?
                 .map(|r| r.and_then(|(_, v)| deserialize(&v)))
+                .map(move |r| r.map(|row| table.decode_row(row)))
                 .filter_map(move |r| match r {
                     Ok(row) => match &filter {
                         Some(filter) => match filter.evaluate(Some(&row)) {
                             Ok(Value::Boolean(true)) => Some(Ok(row)),
                             Ok(Value::Boolean(false)) | Ok(Value::Null) => None,
                             Ok(v) => Some(Err(Error::Value(format!(
-                                "Filter returned {}, expected boolean", v
+                                "Filter returned {}, expected boolean",
+                                v.to_sql_literal()
                             )))),
                             Err(err) => Some(Err(err)),
                         },
@@ -266,6 +537,21 @@ impl SqlTxn for KvSqlTxn {
                 })
         ))
     }
+
+    fn next_serial(&mut self, table: &str, column: &str) -> Result<i64> {
+        self.assert_serial(table, column)?;
+        self.update_serial(table, column, |next| {
+            next.checked_add(1).ok_or_else(|| {
+                Error::Value(format!("Sequence of {}.{} is exhausted", table, column))
+            })
+        })
+    }
+
+    fn reset_serial(&mut self, table: &str, column: &str, next: i64) -> Result<()> {
+        self.assert_serial(table, column)?;
+        self.update_serial(table, column, |_| Ok(next))?;
+        Ok(())
+    }
 }
 
 impl Catalog for KvSqlTxn {
@@ -274,7 +560,70 @@ impl Catalog for KvSqlTxn {
             return Err(Error::Value(format!("Table {} already exists", table.name)));
         }
         table.validate(self)?;
-        self.txn.set(&SqlKey::Table(Some((&table.name).into())).encode(), serialize(&table)?)
+        self.txn.set(&SqlKey::Table(Some((&table.name).into())).encode(), serialize(&table)?)?;
+
+        // Restart the sequences left behind by a dropped table of the same name.
+        for column in table.columns.iter().filter(|c| c.datatype == DataType::Serial) {
+            self.reset_serial(&table.name, &column.name, 1)?;
+        }
+        Ok(())
+    }
+
+    fn update_table(&mut self, table: Table) -> Result<()> {
+        let old = self.assert_read_table(&table.name)?;
+        table.validate(self)?;
+        self.txn.set(&SqlKey::Table(Some((&table.name).into())).encode(), serialize(&table)?)?;
+
+        // Column statistics are by position, so discard them if the columns changed.
+        if old.columns.iter().map(|c| &c.name).ne(table.columns.iter().map(|c| &c.name)) {
+            self.txn.delete(&SqlKey::Stats((&table.name).into()).encode())?;
+        }
+
+        // Remove the indexes of dropped columns.
+        for step in migration_plan(&old, &table) {
+            if let MigrationStep::DropColumn(column) = step {
+                let prefix = SqlKey::Index((&table.name).into(), (&column).into(), None).encode();
+                let keys = self
+                    .txn
+                    .scan_prefix(&prefix)?
+                    .map(|r| r.map(|(k, _)| k))
+                    .collect::<Result<Vec<_>>>()?;
+                for key in keys {
+                    self.txn.delete(&key)?;
+                }
+            }
+        }
+
+        // Rebuild the indexes of columns whose index definition changed.
+        for (i, column) in table.columns.iter().enumerate() {
+            let unchanged = old.columns.iter().any(|c| {
+                c.name == column.name
+                    && c.is_indexed == column.is_indexed
+                    && c.index_predicate == column.index_predicate
+            });
+            if unchanged {
+                continue;
+            }
+            let keys = self
+                .txn
+                .scan_prefix(&SqlKey::Index((&table.name).into(), (&column.name).into(), None).encode())?
+                .map(|r| r.map(|(k, _)| k))
+                .collect::<Result<Vec<_>>>()?;
+            for key in keys {
+                self.txn.delete(&key)?;
+            }
+            let mut entries: HashMap<Value, HashSet<Value>> = HashMap::new();
+            let mut scan = self.scan(&table.name, None)?;
+            while let Some(row) = scan.next().transpose()? {
+                if column.indexes(&row)? {
+                    entries.entry(row[i].clone()).or_default().insert(table.get_row_key(&row)?);
+                }
+            }
+            for (value, index) in entries {
+                self.save_index(&table.name, &column.name, &value, index)?;
+            }
+        }
+        Ok(())
     }
 
     fn read_table(&self, table: &str) -> Result<Option<Table>> {
@@ -286,13 +635,14 @@ impl Catalog for KvSqlTxn {
         if let Some((t, cs)) = self.table_references(&table.name, false)?.first() {
             return Err(Error::Value(format!(
                 "Cannot delete table {} because it is referenced by table {} column {}",
-                table.name, t, cs[0]
+                table.name, t, cs[0].0
             )));
         }
         let mut scan = self.scan(&table.name, None)?;
         while let Some(row) = scan.next().transpose()? {
             self.delete(&table.name, &table.get_row_key(&row)?)?;
         }
+        self.txn.delete(&SqlKey::Stats((&table.name).into()).encode())?;
         self.txn.delete(&SqlKey::Table(Some(table.name.into())).encode())
     }
 
@@ -305,6 +655,52 @@ impl Catalog for KvSqlTxn {
                 .into_iter()
         ))
     }
+
+    fn analyze(&mut self, table: &str) -> Result<TableStats> {
+        let table = self.assert_read_table(table)?;
+        let mut stats = TableStats::default();
+        let mut counts: Vec<HashMap<Value, u64>> = vec![HashMap::new(); table.columns.len()];
+        let mut bytes = 0;
+        for item in self.txn.scan_prefix(&SqlKey::Row((&table.name).into(), None).encode())? {
+            let (_, value) = item?;
+            bytes += value.len() as u64;
+            let row = table.decode_row(deserialize(&value)?);
+            for (counts, value) in counts.iter_mut().zip(row) {
+                *counts.entry(value).or_default() += 1;
+            }
+            stats.rows += 1;
+        }
+        stats.avg_row_bytes = bytes.checked_div(stats.rows).unwrap_or(0);
+        stats.columns =
+            counts.into_iter().map(|counts| ColumnStats::from_counts(counts, stats.rows)).collect();
+        for column in table.columns.iter().filter(|c| c.is_indexed) {
+            let mut index = IndexStats::default();
+            for item in self.scan_index(&table.name, &column.name)? {
+                index.entries += item?.1.len() as u64;
+                index.distinct += 1;
+            }
+            stats.indexes.insert(column.name.clone(), index);
+        }
+        self.txn.set(&SqlKey::Stats((&table.name).into()).encode(), serialize(&stats)?)?;
+        Ok(stats)
+    }
+
+    fn get_stats(&self, table: &str) -> Result<Option<TableStats>> {
+        self.txn.get(&SqlKey::Stats(table.into()).encode())?.map(|v| deserialize(&v)).transpose()
+    }
+
+    fn row_count(&self, table: &str) -> Result<u64> {
+        if let Some(stats) = self.get_stats(table)? {
+            return Ok(stats.rows);
+        }
+        let table = self.assert_read_table(table)?;
+        let mut rows = 0;
+        for item in self.txn.scan_prefix(&SqlKey::Row((&table.name).into(), None).encode())? {
+            item?;
+            rows += 1;
+        }
+        Ok(rows)
+    }
 }
 
 /// Encodes SQL keys, using an order-preserving encoding - see kv::encoding for details. Options can
@@ -318,6 +714,18 @@ enum SqlKey<'a> {
     Index(Cow<'a, str>, Cow<'a, str>, Option<Cow<'a, Value>>),
     /// A key for a row identified by table name and row primary key
     Row(Cow<'a, str>, Option<Cow<'a, Value>>),
+    /// A key for the statistics of a table, see Catalog::analyze()
+    Stats(Cow<'a, str>),
+    /// A key for a foreign key check deferred until commit, by transaction ID
+    Deferred(u64, Option<DeferredCheck<'a>>),
+}
+
+/// A foreign key check deferred until the transaction commits, see KvSqlTxn::check_deferred().
+enum DeferredCheck<'a> {
+    /// The references of a row, by table name and primary key, must exist.
+    References(Cow<'a, str>, Cow<'a, Value>),
+    /// A removed primary key of a table must no longer be referenced.
+    Referenced(Cow<'a, str>, Cow<'a, Value>),
 }
 
 impl<'a> SqlKey<'a> {
@@ -341,6 +749,22 @@ impl<'a> SqlKey<'a> {
             Self::Row(table, Some(pk)) => {
                 [&[0x03][..], &encode_string(&table), &encode_value(&pk)].concat()
             }
+            Self::Stats(table) => [&[0x04][..], &encode_string(&table)].concat(),
+            Self::Deferred(txn_id, None) => [&[0x05][..], &encode_u64(txn_id)].concat(),
+            Self::Deferred(txn_id, Some(check)) => {
+                let (kind, table, id) = match check {
+                    DeferredCheck::References(table, id) => (0x00, table, id),
+                    DeferredCheck::Referenced(table, id) => (0x01, table, id),
+                };
+                [
+                    &[0x05][..],
+                    &encode_u64(txn_id),
+                    &[kind],
+                    &encode_string(&table),
+                    &encode_value(&id),
+                ]
+                .concat()
+            }
         }
     }
 
@@ -356,6 +780,17 @@ impl<'a> SqlKey<'a> {
                 Some(take_value(bytes)?.into()),
             ),
             0x03 => Self::Row(take_string(bytes)?.into(), Some(take_value(bytes)?.into())),
+            0x04 => Self::Stats(take_string(bytes)?.into()),
+            0x05 => {
+                let txn_id = take_u64(bytes)?;
+                let kind = take_byte(bytes)?;
+                let (table, id) = (take_string(bytes)?.into(), take_value(bytes)?.into());
+                Self::Deferred(txn_id, Some(match kind {
+                    0x00 => DeferredCheck::References(table, id),
+                    0x01 => DeferredCheck::Referenced(table, id),
+                    b => return Err(Error::Internal(format!("Unknown deferred check {:x?}", b))),
+                }))
+            }
             b => return Err(Error::Internal(format!("Unknown SQL key prefix {:x?}", b))),
         };
         if !bytes.is_empty() {
@@ -364,3 +799,193 @@ impl<'a> SqlKey<'a> {
         Ok(key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::StdBPlusTree;
+
+    #[test]
+    fn open_checks_store_version() -> Result<()> {
+        let (major, minor) = KvSqlEngine::STORE_VERSION;
+        let kv = MVCC::new(Box::new(StdBPlusTree::new()), false);
+        KvSqlEngine::open(kv.clone())?;
+        assert_eq!((major, minor), kv.store_version()?);
+
+        kv.write_store_version(major, minor + 1)?;
+        KvSqlEngine::open(kv.clone())?;
+        assert_eq!((major, minor + 1), kv.store_version()?);
+
+        kv.write_store_version(major + 1, 0)?;
+        assert!(matches!(KvSqlEngine::open(kv), Err(Error::IncompatibleFormatVersion(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn alter_table_columns() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let session = engine.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, a INTEGER, b STRING)")?;
+        session.execute("INSERT INTO t VALUES (1, 10, 'x')")?;
+        let old = engine.begin(Mode::ReadOnly)?.assert_read_table("t")?;
+        session.execute("ALTER TABLE t DROP COLUMN a")?;
+        session.execute("ALTER TABLE t ADD COLUMN c BOOLEAN DEFAULT TRUE")?;
+        session.execute("INSERT INTO t VALUES (2, 'y', FALSE)")?;
+
+        // Stored rows keep the dropped column's slot, and old rows lack the added column.
+        let txn = engine.begin(Mode::ReadOnly)?;
+        let table = txn.assert_read_table("t")?;
+        assert_eq!(2, table.schema_version);
+        assert_eq!(vec![1], table.dropped_columns);
+        let stored = |id| -> Result<Row> {
+            let key = SqlKey::Row("t".into(), Some(Value::Integer(id).into())).encode();
+            deserialize(&txn.txn.get(&key)?.unwrap())
+        };
+        assert_eq!(
+            vec![Value::Integer(1), Value::Integer(10), Value::String("x".into())],
+            stored(1)?
+        );
+        assert_eq!(
+            vec![Value::Integer(2), Value::Null, Value::String("y".into()), Value::Boolean(false)],
+            stored(2)?
+        );
+        let rows = txn.scan("t", None)?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            vec![
+                vec![Value::Integer(1), Value::String("x".into()), Value::Boolean(true)],
+                vec![Value::Integer(2), Value::String("y".into()), Value::Boolean(false)],
+            ],
+            rows
+        );
+        assert_eq!(Some(rows[0].clone()), txn.read("t", &Value::Integer(1))?);
+
+        assert_eq!(
+            vec![
+                MigrationStep::DropColumn("a".into()),
+                MigrationStep::AddColumn(table.columns[2].clone()),
+            ],
+            migration_plan(&old, &table)
+        );
+        Ok(())
+    }
+
+    /// Inserts rows from 50 threads concurrently without giving their SERIAL primary key, and
+    /// checks that they were all assigned distinct values.
+    #[test]
+    fn foreign_keys_deferred() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let session = engine.session()?;
+        session.execute("CREATE TABLE a (id INTEGER PRIMARY KEY, b_id INTEGER NOT NULL)")?;
+        session
+            .execute("CREATE TABLE b (id INTEGER PRIMARY KEY, a_id INTEGER NOT NULL REFERENCES a)")?;
+        session.execute("ALTER TABLE a ADD CONSTRAINT fk FOREIGN KEY (b_id) REFERENCES b")?;
+        session.execute(
+            "CREATE TABLE c (id INTEGER PRIMARY KEY, a_id INTEGER REFERENCES a ON DELETE RESTRICT)",
+        )?;
+        let count = |table: &str| -> Result<usize> {
+            Ok(engine.begin(Mode::ReadOnly)?.scan(table, None)?.count())
+        };
+
+        // Circular references are checked at commit, once both rows exist.
+        session.execute("BEGIN")?;
+        session.execute("INSERT INTO a VALUES (1, 1)")?;
+        session.execute("INSERT INTO b VALUES (1, 1)")?;
+        session.execute("COMMIT")?;
+        assert_eq!((1, 1), (count("a")?, count("b")?));
+
+        // A NO ACTION key can be removed if its references are changed later in the transaction.
+        session.execute("BEGIN")?;
+        session.execute("INSERT INTO a VALUES (2, 1)")?;
+        session.execute("DELETE FROM a WHERE id = 1")?;
+        session.execute("UPDATE b SET a_id = 2")?;
+        session.execute("COMMIT")?;
+        assert_eq!((1, 1), (count("a")?, count("b")?));
+
+        // A failed check at commit aborts the transaction.
+        session.execute("BEGIN")?;
+        session.execute("INSERT INTO b VALUES (2, 2)")?;
+        session.execute("DELETE FROM a WHERE id = 2")?;
+        assert!(matches!(session.execute("COMMIT"), Err(Error::ConstraintViolation(_))));
+        assert!(matches!(session.execute("ROLLBACK"), Err(Error::Value(_))));
+        assert_eq!((1, 1), (count("a")?, count("b")?));
+
+        // RESTRICT keys error immediately.
+        session.execute("INSERT INTO c VALUES (1, 2)")?;
+        session.execute("BEGIN")?;
+        assert!(matches!(
+            session.execute("DELETE FROM a WHERE id = 2"),
+            Err(Error::ConstraintViolation(_))
+        ));
+        session.execute("ROLLBACK")?;
+
+        // Checks are stored under the transaction, and removed before it commits.
+        let txn = engine.begin(Mode::ReadWrite)?;
+        txn.defer(DeferredCheck::Referenced("a".into(), Cow::Owned(Value::Integer(9))))?;
+        let prefix = SqlKey::Deferred(txn.id(), None).encode();
+        assert_eq!(1, txn.txn.scan_prefix(&prefix)?.count());
+        let id = txn.id();
+        txn.commit()?;
+        let txn = engine.begin(Mode::ReadOnly)?;
+        assert_eq!(0, txn.txn.scan_prefix(&SqlKey::Deferred(id, None).encode())?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn serial_concurrent() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        engine.session()?.execute("CREATE TABLE t (id SERIAL PRIMARY KEY, thread INTEGER)")?;
+        let threads: Vec<_> = (0..50)
+            .map(|thread| {
+                let engine = engine.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    let session = engine.session()?;
+                    for _ in 0..10 {
+                        session.execute(&format!("INSERT INTO t (thread) VALUES ({})", thread))?;
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap()?;
+        }
+
+        let txn = engine.begin(Mode::ReadOnly)?;
+        let mut ids = HashSet::new();
+        for row in txn.scan("t", None)? {
+            assert!(ids.insert(row?[0].clone()), "duplicate id");
+        }
+        assert_eq!((1..=500).map(Value::Integer).collect::<HashSet<_>>(), ids);
+        Ok(())
+    }
+
+    #[test]
+    fn serial_sequences() -> Result<()> {
+        let kv = MVCC::new(Box::new(StdBPlusTree::new()), false);
+        let engine = KvSqlEngine::open(kv.clone())?;
+        let session = engine.session()?;
+        session.execute("CREATE TABLE t (id SERIAL PRIMARY KEY, value INTEGER)")?;
+        let insert = |session: &super::super::SqlSession<KvSqlEngine>| -> Result<Value> {
+            session.execute("INSERT INTO t (value) VALUES (0)")?;
+            session.execute("SELECT MAX(id) FROM t")?.into_value()
+        };
+
+        // Sequence values are not reused after a rollback.
+        session.execute("BEGIN")?;
+        assert_eq!(Value::Integer(1), insert(&session)?);
+        session.execute("ROLLBACK")?;
+        assert_eq!(Value::Integer(2), insert(&session)?);
+
+        // Opening the engine advances sequences which are behind the existing values.
+        session.execute("INSERT INTO t VALUES (5, 0)")?;
+        session.execute("ALTER TABLE t ALTER COLUMN id RESET SEQUENCE TO 3")?;
+        let session = KvSqlEngine::open(kv)?.session()?;
+        assert_eq!(Value::Integer(6), insert(&session)?);
+
+        // A table of the same name restarts the sequence.
+        session.execute("DROP TABLE t")?;
+        session.execute("CREATE TABLE t (id SERIAL PRIMARY KEY, value INTEGER)")?;
+        assert_eq!(Value::Integer(1), insert(&session)?);
+        Ok(())
+    }
+}
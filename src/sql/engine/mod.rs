@@ -9,7 +9,7 @@ pub use kv::KvSqlEngine;
 pub use raft::{RaftSqlEngine, StateMachine};
 pub use crate::concurrency::Mode;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use parking_lot::Mutex;
 
@@ -31,7 +31,11 @@ pub trait SqlEngine: Clone {
 
     /// Begins a session for executing individual statements
     fn session(&self) -> Result<SqlSession<Self>> {
-        Ok(SqlSession { engine: self.clone(), txn: Arc::new(Mutex::new(None)) })
+        Ok(SqlSession {
+            engine: self.clone(),
+            txn: Arc::new(Mutex::new(None)),
+            prepared: Arc::new(Mutex::new(PreparedStatements::default())),
+        })
     }
 
     /// Resumes an active transaction with the given ID
@@ -63,6 +67,12 @@ pub trait SqlTxn: Catalog {
     fn scan_index(&self, table: &str, column: &str) -> Result<IndexScan>;
     /// Updates a table row
     fn update(&mut self, table: &str, id: &Value, row: Row) -> Result<()>;
+
+    /// Returns the next value of a SERIAL column's sequence, advancing it. Sequences aren't
+    /// transactional, so a value is never returned twice, even if the transaction rolls back.
+    fn next_serial(&mut self, table: &str, column: &str) -> Result<i64>;
+    /// Sets the next value of a SERIAL column's sequence.
+    fn reset_serial(&mut self, table: &str, column: &str, next: i64) -> Result<()>;
 }
 
 /// An SQL session, which handles transaction control and simplified query execution
@@ -71,6 +81,26 @@ pub struct SqlSession<E: SqlEngine> {
     engine: E,
     /// The current session transaction, if any
     txn: Arc<Mutex<Option<E::EngineTxn>>>,
+    /// The session's prepared statements
+    prepared: Arc<Mutex<PreparedStatements>>,
+}
+
+/// A prepared statement, which is parsed and planned once and can then be executed repeatedly
+/// with different parameter values. The plan is optimized after binding the parameters, such
+/// that e.g. index lookups can use their values.
+#[derive(Clone, Debug)]
+pub struct PreparedStatement {
+    pub id: u64,
+    plan: Plan,
+    /// The number of parameters, i.e. the highest parameter number.
+    pub param_count: u64,
+}
+
+/// A session's prepared statements, by ID.
+#[derive(Default)]
+struct PreparedStatements {
+    next_id: u64,
+    statements: HashMap<u64, PreparedStatement>,
 }
 
 impl <E: SqlEngine + 'static> SqlSession<E> {
@@ -135,26 +165,108 @@ impl <E: SqlEngine + 'static> SqlSession<E> {
                 Ok(ResultSet::Rollback { id })
             },
 
-            statement if guard.is_some() => {
-                Plan::build(statement, guard.as_mut().unwrap())?
-                    .execute(guard.as_mut().unwrap())
+            ast::Statement::Explain { statement, analyze: false } => match guard.as_mut() {
+                Some(txn) => Ok(Plan::build(*statement, txn)?.optimize(txn)?.explain()),
+                None => {
+                    let mut txn = self.engine.begin(Mode::ReadOnly)?;
+                    let result = Plan::build(*statement, &mut txn)
+                        .and_then(|plan| plan.optimize(&mut txn));
+                    txn.rollback()?;
+                    Ok(result?.explain())
+                }
             },
+
             statement => {
-                let mut txn = self.engine.begin(Mode::ReadWrite)?;
-                match Plan::build(statement, &mut txn)?.execute(&mut txn) {
-                    Ok(result) => {
-                        txn.commit()?;
-                        Ok(result)
-                    },
-                    Err(err) => {
-                        txn.rollback()?;
-                        Err(err)
-                    }
-                }
+                self.execute_in_txn(&mut guard, |txn| Self::execute_statement(statement, txn))
             },
+        }
+    }
+
+    /// Prepares a statement for repeated execution, returning its ID. The statement may contain
+    /// numbered parameters $1, $2, etc., whose values are given when executing it. The statement
+    /// is planned against the current schema.
+    pub fn prepare(&self, query: &str) -> Result<u64> {
+        let statement = match Parser::new(query).parse()? {
+            ast::Statement::Begin { .. }
+            | ast::Statement::Commit
+            | ast::Statement::Rollback
+            | ast::Statement::Explain { .. } => {
+                return Err(Error::Value(
+                    "Can't prepare transaction control or EXPLAIN statements".into(),
+                ))
+            }
+            statement => statement,
+        };
+        let plan = self.with_txn(Mode::ReadOnly, |txn| Plan::build(statement, txn))?;
+        let param_count = plan.parameter_count()?;
+        let mut prepared = self.prepared.lock();
+        prepared.next_id += 1;
+        let id = prepared.next_id;
+        prepared.statements.insert(id, PreparedStatement { id, plan, param_count });
+        Ok(id)
+    }
+
+    /// Executes a prepared statement with the given parameter values, managing transaction
+    /// status like execute().
+    pub fn execute_prepared(&self, id: u64, params: Vec<Value>) -> Result<ResultSet> {
+        let statement = match self.prepared.lock().statements.get(&id) {
+            Some(statement) => statement.clone(),
+            None => return Err(Error::Value(format!("Prepared statement {} not found", id))),
+        };
+        if params.len() as u64 != statement.param_count {
+            return Err(Error::Value(format!(
+                "Prepared statement {} takes {} parameters, given {}",
+                id,
+                statement.param_count,
+                params.len()
+            )));
+        }
+        let plan = statement.plan.bind(&params)?;
+        let mut guard = self.txn.lock();
+        self.execute_in_txn(&mut guard, |txn| plan.optimize(txn)?.execute(txn))
+    }
+
+    /// Removes a prepared statement.
+    pub fn deallocate(&self, id: u64) -> Result<()> {
+        match self.prepared.lock().statements.remove(&id) {
+            Some(_) => Ok(()),
+            None => Err(Error::Value(format!("Prepared statement {} not found", id))),
+        }
+    }
 
-            #[allow(unreachable_patterns)]
-            _ => unreachable!()
+    /// Runs a closure in the session's transaction if any, or else in a new read-write
+    /// transaction which is committed if the closure succeeds and rolled back otherwise.
+    fn execute_in_txn<F>(&self, guard: &mut Option<E::EngineTxn>, func: F) -> Result<ResultSet>
+    where
+        F: FnOnce(&mut E::EngineTxn) -> Result<ResultSet>,
+    {
+        if let Some(txn) = guard.as_mut() {
+            return func(txn);
+        }
+        let mut txn = self.engine.begin(Mode::ReadWrite)?;
+        match func(&mut txn) {
+            Ok(result) => {
+                txn.commit()?;
+                Ok(result)
+            }
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Plans and executes a statement in a transaction. EXPLAIN ANALYZE statements are executed
+    /// as well, returning the plan annotated with execution statistics.
+    pub(crate) fn execute_statement(
+        statement: ast::Statement,
+        txn: &mut E::EngineTxn,
+    ) -> Result<ResultSet> {
+        match statement {
+            ast::Statement::Explain { statement, analyze: true } => {
+                Plan::build(*statement, txn)?.optimize(txn)?.analyze(txn)
+            }
+            statement => Plan::build(statement, txn)?.optimize(txn)?.execute(txn),
         }
     }
 
@@ -183,4 +295,59 @@ impl <E: SqlEngine + 'static> SqlSession<E> {
 pub type RowScan = Box<dyn DoubleEndedIterator<Item = Result<Row>> + Send>;
 
 /// An index scan iterator
-pub type IndexScan = Box<dyn DoubleEndedIterator<Item = Result<(Value, HashSet<Value>)>> + Send>;
\ No newline at end of file
+pub type IndexScan = Box<dyn DoubleEndedIterator<Item = Result<(Value, HashSet<Value>)>> + Send>;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::MVCC;
+    use crate::storage::kv::StdBPlusTree;
+
+    #[test]
+    fn prepared_statements() -> Result<()> {
+        use Value::Integer;
+        let string = |s: &str| Value::String(s.to_string());
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let session = engine.session()?;
+        session.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value STRING INDEX)")?;
+
+        let insert = session.prepare("INSERT INTO t VALUES ($1, $2)")?;
+        for (id, value) in [(1, "a"), (2, "b"), (3, "a")] {
+            session.execute_prepared(insert, vec![Integer(id), string(value)])?;
+        }
+
+        // Parameters are bound in subqueries too, and a parameter may be used several times.
+        let select = session.prepare(
+            "SELECT id FROM t WHERE value = $1 AND id IN (SELECT id FROM t WHERE id >= $2) \
+             OR id = $2 * 10 ORDER BY id",
+        )?;
+        let ids = |params: Vec<Value>| -> Result<Vec<Row>> {
+            match session.execute_prepared(select, params)? {
+                ResultSet::Query { buffered_rows, .. } => buffered_rows,
+                result => panic!("unexpected result {:?}", result),
+            }
+        };
+        assert_eq!(vec![vec![Integer(1)], vec![Integer(3)]], ids(vec![string("a"), Integer(1)])?);
+        assert_eq!(vec![vec![Integer(3)]], ids(vec![string("a"), Integer(2)])?);
+        assert_eq!(Vec::<Row>::new(), ids(vec![string("b"), Integer(3)])?);
+
+        // The parameter count must match.
+        assert!(session.execute_prepared(select, vec![string("a")]).is_err());
+
+        // Prepared statements run in the session's transaction.
+        session.execute("BEGIN")?;
+        session.execute_prepared(insert, vec![Integer(4), string("c")])?;
+        session.execute("ROLLBACK")?;
+        assert_eq!(Integer(3), session.execute("SELECT COUNT(*) FROM t")?.into_value()?);
+
+        // Deallocated statements can't be executed.
+        session.deallocate(insert)?;
+        assert!(session.execute_prepared(insert, vec![Integer(5), string("d")]).is_err());
+        assert!(session.deallocate(insert).is_err());
+
+        // Transaction control can't be prepared, and unbound parameters can't be executed.
+        assert!(session.prepare("BEGIN").is_err());
+        assert!(session.execute("SELECT $1").and_then(|r| r.into_value()).is_err());
+        assert!(session.prepare("SELECT $0").is_err());
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use crate::concurrency::MVCC;
 use crate::error::{Result, Error};
 use crate::raft;
-use crate::sql::schema::{Catalog, Table, Tables};
+use crate::sql::schema::{Catalog, Table, TableStats, Tables};
 use crate::sql::types::{Row, Value, Expression};
 use super::{SqlEngine, Mode, SqlTxn, RowScan, IndexScan};
 
@@ -25,11 +25,19 @@ pub(crate) enum Mutation {
     Delete { txn_id: u64, table: String, id: Value },
     /// Updates a row
     Update { txn_id: u64, table: String, id: Value, row: Row },
+    /// Returns the next value of a SERIAL column's sequence, advancing it
+    NextSerial { txn_id: u64, table: String, column: String },
+    /// Sets the next value of a SERIAL column's sequence
+    ResetSerial { txn_id: u64, table: String, column: String, next: i64 },
 
     /// Creates a table
     CreateTable { txn_id: u64, schema: Table },
     /// Deletes a table
     DeleteTable { txn_id: u64, table: String },
+    /// Replaces a table schema
+    UpdateTable { txn_id: u64, schema: Table },
+    /// Computes and stores a table's statistics
+    Analyze { txn_id: u64, table: String },
 }
 
 impl std::fmt::Display for Mutation {
@@ -41,8 +49,12 @@ impl std::fmt::Display for Mutation {
             Mutation::Create { txn_id, table, row } => write!(f, "CREATE"),
             Mutation::Delete { txn_id, table, id } => write!(f, "DELETE"),
             Mutation::Update { txn_id, table, id, row } => write!(f, "UPDATE"),
+            Mutation::NextSerial { txn_id, table, column } => write!(f, "NEXT SERIAL"),
+            Mutation::ResetSerial { txn_id, table, column, next } => write!(f, "RESET SERIAL"),
             Mutation::CreateTable { txn_id, schema } => write!(f, "CREATE TABLE"),
             Mutation::DeleteTable { txn_id, table } => write!(f, "DELETE TABLE"),
+            Mutation::UpdateTable { txn_id, schema } => write!(f, "UPDATE TABLE"),
+            Mutation::Analyze { txn_id, table } => write!(f, "ANALYZE"),
         }
     }
 }
@@ -68,6 +80,8 @@ pub(crate) enum Query {
     ScanTables { txn_id: u64 },
     /// Reads a table
     ReadTable { txn_id: u64, table: String },
+    /// Reads a table's stored statistics
+    GetStats { txn_id: u64, table: String },
 }
 
 impl std::fmt::Display for Query {
@@ -81,6 +95,7 @@ impl std::fmt::Display for Query {
             Query::ScanIndex { txn_id, table, column } => write!(f, "SCAN INDEX"),
             Query::ScanTables { txn_id } => write!(f, "SCAN TABLES"),
             Query::ReadTable { txn_id, table } => write!(f, "READ TABLE"),
+            Query::GetStats { txn_id, table } => write!(f, "GET STATS"),
         }
     }
 }
@@ -226,6 +241,27 @@ impl SqlTxn for RaftSqlTxn {
         )?)
     }
 
+    fn next_serial(&mut self, table: &str, column: &str) -> Result<i64> {
+        RaftSqlEngine::deserialize(&self.mutate(
+            Mutation::NextSerial {
+                txn_id: self.id,
+                table: table.to_string(),
+                column: column.to_string(),
+            }
+        )?)
+    }
+
+    fn reset_serial(&mut self, table: &str, column: &str, next: i64) -> Result<()> {
+        RaftSqlEngine::deserialize(&self.mutate(
+            Mutation::ResetSerial {
+                txn_id: self.id,
+                table: table.to_string(),
+                column: column.to_string(),
+                next,
+            }
+        )?)
+    }
+
     fn read_index(&self, table: &str, column: &str, value: &Value) -> Result<HashSet<Value>> {
         RaftSqlEngine::deserialize(&self.query(
             Query::ReadIndex {
@@ -294,6 +330,15 @@ impl Catalog for RaftSqlTxn {
         )?)
     }
 
+    fn update_table(&mut self, table: Table) -> Result<()> {
+        RaftSqlEngine::deserialize(&self.mutate(
+            Mutation::UpdateTable {
+                txn_id: self.id,
+                schema: table,
+            }
+        )?)
+    }
+
     fn scan_tables(&self) -> Result<Tables> {
         Ok(Box::new(
             RaftSqlEngine::deserialize::<Vec<_>>(&self.query(
@@ -304,6 +349,36 @@ impl Catalog for RaftSqlTxn {
             .into_iter()
         ))
     }
+
+    fn analyze(&mut self, table: &str) -> Result<TableStats> {
+        RaftSqlEngine::deserialize(&self.mutate(
+            Mutation::Analyze {
+                txn_id: self.id,
+                table: table.to_string(),
+            }
+        )?)
+    }
+
+    fn get_stats(&self, table: &str) -> Result<Option<TableStats>> {
+        RaftSqlEngine::deserialize(&self.query(
+            Query::GetStats {
+                txn_id: self.id,
+                table: table.to_string(),
+            }
+        )?)
+    }
+
+    fn row_count(&self, table: &str) -> Result<u64> {
+        if let Some(stats) = self.get_stats(table)? {
+            return Ok(stats.rows);
+        }
+        let mut rows = 0;
+        for row in SqlTxn::scan(self, table, None)? {
+            row?;
+            rows += 1;
+        }
+        Ok(rows)
+    }
 }
 
 /// The Raft state machine for the Raft-based SQL engine, using a KV SQL engine
@@ -316,7 +391,7 @@ pub struct StateMachine {
 
 impl StateMachine {
     pub fn new(store: MVCC) -> Result<Self> {
-        let engine = super::KvSqlEngine::new(store);
+        let engine = super::KvSqlEngine::open_replica(store)?;
         let applied_index = engine
             .get_metadata(b"applied_index")?
             .map(|bytes| RaftSqlEngine::deserialize(&bytes))
@@ -340,6 +415,12 @@ impl StateMachine {
             Mutation::Update { txn_id, table, id, row } => {
                 RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.update(&table, &id, row)?)
             }
+            Mutation::NextSerial { txn_id, table, column } => {
+                RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.next_serial(&table, &column)?)
+            }
+            Mutation::ResetSerial { txn_id, table, column, next } => RaftSqlEngine::serialize(
+                &self.engine.resume(txn_id)?.reset_serial(&table, &column, next)?,
+            ),
 
             Mutation::CreateTable { txn_id, schema } => {
                 RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.create_table(schema)?)
@@ -347,6 +428,12 @@ impl StateMachine {
             Mutation::DeleteTable { txn_id, table } => {
                 RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.delete_table(&table)?)
             }
+            Mutation::UpdateTable { txn_id, schema } => {
+                RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.update_table(schema)?)
+            }
+            Mutation::Analyze { txn_id, table } => {
+                RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.analyze(&table)?)
+            }
         }
     }
 }
@@ -360,7 +447,7 @@ impl raft::State for StateMachine {
         // We don't check that index == applied_index + 1, since the Raft log commits no-op
         // entries during leader election which we need to ignore.
         match self.apply(RaftSqlEngine::deserialize(&mutation)?) {
-            error @ Err(Error::Internal(_)) => error,
+            error @ Err(Error::Internal(_) | Error::IoError(_)) => error,
             result => {
                 self.engine.set_metadata(b"applied_index", RaftSqlEngine::serialize(&(index))?)?;
                 self.applied_index = index;
@@ -369,6 +456,17 @@ impl raft::State for StateMachine {
         }
     }
 
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        RaftSqlEngine::serialize(&self.engine.kv.snapshot()?)
+    }
+
+    fn restore(&mut self, index: u64, snapshot: Vec<u8>) -> Result<()> {
+        self.engine.kv.restore(RaftSqlEngine::deserialize(&snapshot)?)?;
+        self.engine.set_metadata(b"applied_index", RaftSqlEngine::serialize(&index)?)?;
+        self.applied_index = index;
+        Ok(())
+    }
+
     fn query(&self, query: Vec<u8>) -> Result<Vec<u8>> {
         match RaftSqlEngine::deserialize(&query)? {
             Query::Resume(id) => {
@@ -400,6 +498,9 @@ impl raft::State for StateMachine {
             Query::ScanTables { txn_id } => {
                 RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.scan_tables()?.collect::<Vec<_>>())
             },
+            Query::GetStats { txn_id, table } => {
+                RaftSqlEngine::serialize(&self.engine.resume(txn_id)?.get_stats(&table)?)
+            },
         }
     }
 }
\ No newline at end of file
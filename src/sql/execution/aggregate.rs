@@ -0,0 +1,377 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::sql::engine::SqlTxn;
+use crate::sql::plan::AggregateExpr;
+use crate::sql::types::{Expression, ResColumn, Row, Value};
+use super::{Executor, ResultSet};
+
+/// An aggregate function, which accumulates the rows of a group and then yields a value.
+pub trait Aggregator: Send {
+    /// Accumulates a row.
+    fn accumulate(&mut self, row: &Row) -> Result<()>;
+
+    /// Returns the aggregate of the accumulated rows.
+    fn finalize(&self) -> Result<Value>;
+}
+
+impl dyn Aggregator {
+    /// Creates an aggregator for an aggregate expression.
+    pub fn new(aggregate: &AggregateExpr) -> Box<dyn Aggregator> {
+        match aggregate.clone() {
+            AggregateExpr::Count(expr) => Box::new(CountAggregator::new(*expr)),
+            AggregateExpr::Sum(expr) => Box::new(SumAggregator::new(*expr)),
+            AggregateExpr::Min(expr) => Box::new(MinMaxAggregator::new(*expr, Ordering::Less)),
+            AggregateExpr::Max(expr) => Box::new(MinMaxAggregator::new(*expr, Ordering::Greater)),
+            AggregateExpr::Avg(expr) => Box::new(AvgAggregator::new(*expr)),
+        }
+    }
+}
+
+/// Counts the non-null values of an expression.
+pub struct CountAggregator {
+    expr: Expression,
+    count: i64,
+}
+
+impl CountAggregator {
+    pub fn new(expr: Expression) -> Self {
+        Self { expr, count: 0 }
+    }
+}
+
+impl Aggregator for CountAggregator {
+    fn accumulate(&mut self, row: &Row) -> Result<()> {
+        if self.expr.evaluate(Some(row))? != Value::Null {
+            self.count += 1;
+        }
+        Ok(())
+    }
+
+    fn finalize(&self) -> Result<Value> {
+        Ok(Value::Integer(self.count))
+    }
+}
+
+/// Sums the non-null values of an expression, using the type promotion of addition, i.e. the
+/// sum of integers and floats is a float. The sum of no values is null.
+pub struct SumAggregator {
+    expr: Expression,
+    sum: Value,
+}
+
+impl SumAggregator {
+    pub fn new(expr: Expression) -> Self {
+        Self { expr, sum: Value::Null }
+    }
+}
+
+impl Aggregator for SumAggregator {
+    fn accumulate(&mut self, row: &Row) -> Result<()> {
+        let value = self.expr.evaluate(Some(row))?;
+        self.sum = match (std::mem::replace(&mut self.sum, Value::Null), value) {
+            (sum, Value::Null) => sum,
+            (Value::Null, value @ (Value::Integer(_) | Value::Float(_) | Value::Decimal(_))) => {
+                value
+            }
+            (Value::Null, value) => {
+                return Err(Error::Value(format!("Can't sum {}", value.to_sql_literal())));
+            }
+            (sum, value) => Expression::Add(
+                Box::new(Expression::Constant(sum)),
+                Box::new(Expression::Constant(value)),
+            )
+            .evaluate(None)?,
+        };
+        Ok(())
+    }
+
+    fn finalize(&self) -> Result<Value> {
+        Ok(self.sum.clone())
+    }
+}
+
+/// Finds the smallest or largest non-null value of an expression, or null if there are none.
+/// Expressions cast to an enum compare by declaration order, see DataType::compare().
+pub struct MinMaxAggregator {
+    expr: Expression,
+    /// The ordering of a new value relative to the current one which replaces it.
+    replace: Ordering,
+    value: Value,
+}
+
+impl MinMaxAggregator {
+    pub fn new(expr: Expression, replace: Ordering) -> Self {
+        Self { expr, replace, value: Value::Null }
+    }
+}
+
+impl Aggregator for MinMaxAggregator {
+    fn accumulate(&mut self, row: &Row) -> Result<()> {
+        let value = self.expr.evaluate(Some(row))?;
+        if value == Value::Null {
+            return Ok(());
+        }
+        if self.value == Value::Null {
+            self.value = value;
+            return Ok(());
+        }
+        let ordering = match self.expr.enum_type() {
+            Some(datatype) => datatype.compare(&value, &self.value),
+            None => value.partial_cmp(&self.value),
+        };
+        match ordering {
+            Some(ordering) if ordering == self.replace => self.value = value,
+            Some(_) => {}
+            None => {
+                return Err(Error::Value(format!(
+                    "Can't compare {} and {}",
+                    value.to_sql_literal(),
+                    self.value.to_sql_literal()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&self) -> Result<Value> {
+        Ok(self.value.clone())
+    }
+}
+
+/// Averages the non-null values of an expression. The average of integers is a float, and the
+/// average of no values is null.
+pub struct AvgAggregator {
+    sum: SumAggregator,
+    count: CountAggregator,
+}
+
+impl AvgAggregator {
+    pub fn new(expr: Expression) -> Self {
+        Self { sum: SumAggregator::new(expr.clone()), count: CountAggregator::new(expr) }
+    }
+}
+
+impl Aggregator for AvgAggregator {
+    fn accumulate(&mut self, row: &Row) -> Result<()> {
+        self.sum.accumulate(row)?;
+        self.count.accumulate(row)
+    }
+
+    fn finalize(&self) -> Result<Value> {
+        let count = self.count.count;
+        Ok(match self.sum.finalize()? {
+            Value::Null => Value::Null,
+            Value::Integer(sum) => Value::Float(sum as f64 / count as f64),
+            Value::Float(sum) => Value::Float(sum / count as f64),
+            sum => Expression::Divide(
+                Box::new(Expression::Constant(sum)),
+                Box::new(Expression::Constant(Value::Integer(count))),
+            )
+            .evaluate(None)?,
+        })
+    }
+}
+
+/// An aggregation executor, which groups the source rows by the group_by expressions and runs
+/// the aggregators for each group. Groups are emitted in order of first appearance, each as the
+/// aggregates followed by the group values. Without group_by expressions all rows form a single
+/// group, which is emitted even if there are no rows. Group columns are named by the given
+/// labels, and aggregate columns by their function names.
+pub struct AggregationExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    group_by: Vec<Expression>,
+    group_labels: Vec<String>,
+    aggregates: Vec<AggregateExpr>,
+}
+
+impl<T: SqlTxn> AggregationExec<T> {
+    pub fn new(
+        source: Box<dyn Executor<T>>,
+        group_by: Vec<Expression>,
+        group_labels: Vec<String>,
+        aggregates: Vec<AggregateExpr>,
+    ) -> Box<Self> {
+        Box::new(Self { source, group_by, group_labels, aggregates })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for AggregationExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let buffered_rows = match self.source.execute(txn)? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows?,
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+        let new_group = || self.aggregates.iter().map(<dyn Aggregator>::new).collect::<Vec<_>>();
+
+        let mut index: HashMap<Row, usize> = HashMap::new();
+        let mut groups: Vec<(Row, Vec<Box<dyn Aggregator>>)> = Vec::new();
+        if self.group_by.is_empty() {
+            index.insert(Row::new(), 0);
+            groups.push((Row::new(), new_group()));
+        }
+        for row in buffered_rows {
+            let key = self
+                .group_by
+                .iter()
+                .map(|expr| expr.evaluate(Some(&row)))
+                .collect::<Result<Row>>()?;
+            let i = match index.get(&key) {
+                Some(i) => *i,
+                None => {
+                    index.insert(key.clone(), groups.len());
+                    groups.push((key, new_group()));
+                    groups.len() - 1
+                }
+            };
+            for aggregator in groups[i].1.iter_mut() {
+                aggregator.accumulate(&row)?;
+            }
+        }
+
+        let columns = self
+            .aggregates
+            .iter()
+            .map(|aggregate| ResColumn { name: Some(aggregate.function_name().into()) })
+            .chain(self.group_labels.iter().map(|label| ResColumn { name: Some(label.clone()) }))
+            .collect();
+        Ok(ResultSet::Query {
+            columns,
+            buffered_rows: groups
+                .into_iter()
+                .map(|(key, aggregators)| {
+                    let mut row = aggregators
+                        .iter()
+                        .map(|aggregator| aggregator.finalize())
+                        .collect::<Result<Row>>()?;
+                    row.extend(key);
+                    Ok(row)
+                })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::{Mode, MVCC};
+    use crate::sql::engine::{KvSqlEngine, SqlEngine};
+    use crate::storage::kv::StdBPlusTree;
+    use super::*;
+
+    /// A source executor returning fixed rows.
+    struct RowsExec(Vec<Row>);
+
+    impl<T: SqlTxn> Executor<T> for RowsExec {
+        fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+            Ok(ResultSet::Query {
+                columns: vec![ResColumn { name: None }, ResColumn { name: None }],
+                buffered_rows: Ok(self.0),
+            })
+        }
+    }
+
+    fn field(i: usize) -> Box<Expression> {
+        Box::new(Expression::Field(i, None))
+    }
+
+    fn aggregate(
+        rows: Vec<Row>,
+        group_by: Vec<Expression>,
+        aggregates: Vec<AggregateExpr>,
+    ) -> Result<Vec<Row>> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let labels = group_by.iter().map(|e| e.to_column_name()).collect();
+        let exec = AggregationExec::new(Box::new(RowsExec(rows)), group_by, labels, aggregates);
+        match exec.execute(&mut txn)? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows,
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+
+    fn all_aggregates(i: usize) -> Vec<AggregateExpr> {
+        vec![
+            AggregateExpr::Count(Box::new(Expression::Constant(Value::Boolean(true)))),
+            AggregateExpr::Count(field(i)),
+            AggregateExpr::Sum(field(i)),
+            AggregateExpr::Min(field(i)),
+            AggregateExpr::Max(field(i)),
+            AggregateExpr::Avg(field(i)),
+        ]
+    }
+
+    #[test]
+    fn test_aggregate_empty() -> Result<()> {
+        // Without grouping, empty input yields a single row, and with grouping no rows.
+        use Value::*;
+        assert_eq!(
+            vec![vec![Integer(0), Integer(0), Null, Null, Null, Null]],
+            aggregate(vec![], vec![], all_aggregates(1))?
+        );
+        assert!(aggregate(vec![], vec![*field(0)], all_aggregates(1))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_single_group() -> Result<()> {
+        use Value::*;
+        let rows = vec![
+            vec![String("a".into()), Integer(3)],
+            vec![String("b".into()), Null],
+            vec![String("c".into()), Integer(-1)],
+            vec![String("d".into()), Integer(4)],
+        ];
+        assert_eq!(
+            vec![vec![Integer(4), Integer(3), Integer(6), Integer(-1), Integer(4), Float(2.0)]],
+            aggregate(rows, vec![], all_aggregates(1))?
+        );
+
+        // A group of nulls only counts the rows.
+        let rows = vec![vec![Null, Null], vec![Null, Null]];
+        assert_eq!(
+            vec![vec![Integer(2), Integer(0), Null, Null, Null, Null]],
+            aggregate(rows, vec![], all_aggregates(1))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_groups() -> Result<()> {
+        use Value::*;
+        let rows = vec![
+            vec![String("b".into()), Integer(1)],
+            vec![Null, Integer(2)],
+            vec![String("a".into()), Integer(3)],
+            vec![String("b".into()), Integer(4)],
+            vec![Null, Null],
+        ];
+        let aggregates = vec![AggregateExpr::Count(field(1)), AggregateExpr::Sum(field(1))];
+        assert_eq!(
+            vec![
+                vec![Integer(2), Integer(5), String("b".into())],
+                vec![Integer(1), Integer(2), Null],
+                vec![Integer(1), Integer(3), String("a".into())],
+            ],
+            aggregate(rows, vec![*field(0)], aggregates)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_sum_promotion() -> Result<()> {
+        use Value::*;
+        let sum = |values: Vec<Value>| -> Result<Value> {
+            let rows = values.into_iter().map(|v| vec![Null, v]).collect();
+            Ok(aggregate(rows, vec![], vec![AggregateExpr::Sum(field(1))])?.remove(0).remove(0))
+        };
+        assert_eq!(Integer(3), sum(vec![Integer(1), Null, Integer(2)])?);
+        assert_eq!(Float(3.5), sum(vec![Integer(1), Float(0.5), Integer(2)])?);
+        assert_eq!(Float(3.5), sum(vec![Float(0.5), Integer(1), Integer(2)])?);
+        assert_eq!(Null, sum(vec![Null, Null])?);
+        assert!(sum(vec![Integer(i64::MAX), Integer(1)]).is_err());
+        assert!(sum(vec![String("a".into())]).is_err());
+        Ok(())
+    }
+}
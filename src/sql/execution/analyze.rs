@@ -0,0 +1,183 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::sql::engine::SqlTxn;
+use crate::sql::plan::Node;
+use super::{Executor, ResultSet};
+
+/// Execution statistics of a plan node, as recorded by EXPLAIN ANALYZE.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NodeStats {
+    /// The number of rows emitted by the node, or affected by a mutation.
+    pub rows: u64,
+    /// The wall time spent executing the node, including its sources.
+    pub elapsed: Duration,
+}
+
+/// Collects execution statistics of an analyzed plan's nodes, by their pre-order position in
+/// the plan tree. Nodes that are never executed, like lateral join subqueries which are planned
+/// per row, have no statistics.
+#[derive(Clone, Default)]
+pub struct Analyzer {
+    stats: Rc<RefCell<Vec<Option<NodeStats>>>>,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates a statistics slot for the next node in pre-order, returning its position.
+    pub(super) fn allocate(&self) -> usize {
+        let mut stats = self.stats.borrow_mut();
+        stats.push(None);
+        stats.len() - 1
+    }
+
+    /// Skips the slots of a subtree that is not built into executors.
+    pub(super) fn skip(&self, node: &Node) {
+        let count = Self::format(node).len();
+        self.stats.borrow_mut().extend(std::iter::repeat_n(None, count));
+    }
+
+    /// Renders the plan with each node annotated with its statistics, one line per node.
+    pub fn render(&self, node: &Node) -> String {
+        let stats = self.stats.borrow();
+        Self::format(node)
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| match stats.get(i).copied().flatten() {
+                Some(NodeStats { rows, elapsed }) => {
+                    format!("{} (rows={} time={:.3}ms)", line, rows, elapsed.as_secs_f64() * 1e3)
+                }
+                None => format!("{} (never executed)", line),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Formats a plan, returning its lines. Node::format() renders one line per node, in
+    /// pre-order.
+    fn format(node: &Node) -> Vec<String> {
+        node.to_string().lines().map(String::from).collect()
+    }
+}
+
+/// An executor wrapper which records the statistics of the wrapped executor.
+pub struct TimingExec<T: SqlTxn> {
+    inner: Box<dyn Executor<T>>,
+    analyzer: Analyzer,
+    slot: usize,
+}
+
+impl<T: SqlTxn> TimingExec<T> {
+    pub fn new(inner: Box<dyn Executor<T>>, analyzer: Analyzer, slot: usize) -> Box<Self> {
+        Box::new(Self { inner, analyzer, slot })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for TimingExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let start = Instant::now();
+        let result = self.inner.execute(txn)?;
+        let elapsed = start.elapsed();
+        let rows = match &result {
+            ResultSet::Query { buffered_rows: Ok(rows), .. } => rows.len() as u64,
+            ResultSet::Create { count }
+            | ResultSet::Update { count }
+            | ResultSet::Delete { count } => *count,
+            _ => 0,
+        };
+        self.analyzer.stats.borrow_mut()[self.slot] = Some(NodeStats { rows, elapsed });
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::MVCC;
+    use crate::sql::engine::{KvSqlEngine, SqlEngine, SqlSession};
+    use crate::sql::types::Value;
+    use crate::storage::kv::StdBPlusTree;
+    use super::*;
+
+    /// Runs an EXPLAIN ANALYZE query, returning the rendered lines with their timings removed.
+    fn analyze(session: &SqlSession<KvSqlEngine>, query: &str) -> Result<Vec<String>> {
+        match session.execute(&format!("EXPLAIN ANALYZE {}", query))? {
+            ResultSet::ExplainAnalyze(plan) => Ok(plan
+                .lines()
+                .map(|line| match line.find(" time=") {
+                    Some(i) => format!("{})", &line[..i]),
+                    None => line.to_string(),
+                })
+                .collect()),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn explain_analyze() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let session = engine.session()?;
+        session.execute("CREATE TABLE a (id INTEGER PRIMARY KEY, value INTEGER)")?;
+        session.execute("CREATE TABLE b (id INTEGER PRIMARY KEY, a_id INTEGER)")?;
+        session.execute("INSERT INTO a VALUES (1, 10), (2, 20), (3, 30), (4, 40)")?;
+        session.execute("INSERT INTO b VALUES (1, 1), (2, 1), (3, 3)")?;
+
+        // Scans, filters, sorts and joins are annotated with the rows they emitted. The
+        // projection qualifies the ambiguous id columns.
+        assert_eq!(
+            vec![
+                "Projection: a.id, a.value, b.id, b.a_id (rows=1)",
+                "└─ Order: a.value desc (rows=1)",
+                "   └─ HashJoin: inner on a.id = b.a_id (rows=1)",
+                "      ├─ Scan: a (a.value > 15) (rows=3)",
+                "      └─ Scan: b (rows=3)",
+            ],
+            analyze(
+                &session,
+                "SELECT * FROM a JOIN b ON a.id = b.a_id WHERE a.value > 15 ORDER BY a.value DESC",
+            )?
+        );
+        assert_eq!(
+            vec![
+                "Projection: a.id, a.value, b.id, b.a_id (rows=6)",
+                "└─ NestedLoopJoin: inner on a.id > b.id (rows=6)",
+                "   ├─ Scan: a (rows=4)",
+                "   └─ Scan: b (rows=3)",
+            ],
+            analyze(&session, "SELECT * FROM a, b WHERE a.id > b.id")?
+        );
+        assert_eq!(
+            vec![
+                "Filter: #1 > 1 (rows=1)",
+                "└─ Projection: b.a_id, #0 (rows=2)",
+                "   └─ Aggregation: count(TRUE) group by a_id (rows=2)",
+                "      └─ Scan: b (rows=3)",
+            ],
+            analyze(&session, "SELECT a_id, COUNT(*) FROM b GROUP BY a_id HAVING COUNT(*) > 1")?
+        );
+
+        // Mutations are executed, and report the affected rows.
+        assert_eq!(
+            vec!["Delete: b (rows=2)", "└─ Scan: b (a_id = 1) (rows=2)"],
+            analyze(&session, "DELETE FROM b WHERE a_id = 1")?
+        );
+        assert_eq!(Value::Integer(1), session.execute("SELECT COUNT(*) FROM b")?.into_value()?);
+
+        // Lateral join subqueries are planned per row, and have no statistics.
+        let lines = analyze(
+            &session,
+            "SELECT * FROM a JOIN LATERAL (SELECT * FROM b WHERE b.a_id = a.id) s",
+        )?;
+        assert_eq!("└─ LateralJoin: inner (rows=1)", lines[1]);
+        assert_eq!("   ├─ Scan: a (rows=4)", lines[2]);
+        assert!(lines.len() > 3);
+        assert!(lines[3..].iter().all(|line| line.ends_with("(never executed)")));
+
+        assert!(session.execute("EXPLAIN ANALYZE BEGIN").is_err());
+        Ok(())
+    }
+}
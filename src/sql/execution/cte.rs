@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::{Error, Result};
+use crate::sql::engine::SqlTxn;
+use crate::sql::types::Columns;
+use super::{EphemeralTable, Executor, ResultSet};
+
+/// The rows of a materialized common table expression, shared between all of its references in a
+/// query. It is empty until the first reference executes, and freed when the last one is dropped.
+pub type CteCache = Rc<RefCell<Option<(Columns, EphemeralTable)>>>;
+
+/// A materialized CTE executor. The first reference to execute runs the source and stores its
+/// rows in an ephemeral table; later references scan the stored rows instead.
+pub struct MaterializedCteExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    cache: CteCache,
+}
+
+impl<T: SqlTxn> MaterializedCteExec<T> {
+    pub fn new(source: Box<dyn Executor<T>>, cache: CteCache) -> Box<Self> {
+        Box::new(Self { source, cache })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for MaterializedCteExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut cache = self.cache.borrow_mut();
+        if cache.is_none() {
+            match self.source.execute(txn)? {
+                ResultSet::Query { columns, buffered_rows } => {
+                    let mut table = EphemeralTable::new();
+                    for row in buffered_rows? {
+                        table.write(row)?;
+                    }
+                    *cache = Some((columns, table));
+                }
+                r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+            }
+        }
+        let (columns, table) = cache.as_ref().unwrap();
+        Ok(ResultSet::Query {
+            columns: columns.clone(),
+            buffered_rows: table.scan(..)?.collect::<Result<_>>(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use crate::concurrency::{Mode, MVCC};
+    use crate::sql::engine::{KvSqlEngine, SqlEngine};
+    use crate::sql::types::{ResColumn, Value};
+    use crate::storage::kv::StdBPlusTree;
+    use super::*;
+
+    /// A source executor which counts how many times it is executed.
+    struct CountingExec {
+        calls: Rc<Cell<usize>>,
+    }
+
+    impl<T: SqlTxn> Executor<T> for CountingExec {
+        fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(ResultSet::Query {
+                columns: vec![ResColumn { name: Some("a".into()) }],
+                buffered_rows: Ok(vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]),
+            })
+        }
+    }
+
+    #[test]
+    fn test_materialized_cte_executes_once() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+
+        let calls = Rc::new(Cell::new(0));
+        let cache = CteCache::default();
+        let weak = Rc::downgrade(&cache);
+        let source = || Box::new(CountingExec { calls: calls.clone() });
+        let first = MaterializedCteExec::new(source(), cache.clone());
+        let second = MaterializedCteExec::new(source(), cache);
+
+        let result = first.execute(&mut txn)?;
+        assert_eq!(result, second.execute(&mut txn)?);
+        assert_eq!(1, calls.get());
+        match result {
+            ResultSet::Query { buffered_rows, .. } => {
+                assert_eq!(vec![vec![Value::Integer(1)], vec![Value::Integer(2)]], buffered_rows?)
+            }
+            r => panic!("Unexpected result {:?}", r),
+        }
+
+        // Both references have been consumed, so the materialized rows are freed.
+        assert!(weak.upgrade().is_none());
+        Ok(())
+    }
+}
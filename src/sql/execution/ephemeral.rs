@@ -0,0 +1,183 @@
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use tempfile::TempDir;
+
+use crate::encoding::encode_u64;
+use crate::error::Result;
+use crate::server::{deserialize, serialize};
+use crate::sql::types::{Row, Rows};
+use crate::storage::kv::{KvStore, LsmStorage, Range};
+
+/// The default number of rows kept in memory before spilling to disk.
+const DEFAULT_SPILL_THRESHOLD: usize = 1024;
+
+/// A scratch table for query intermediates, such as sorted or hashed rows. It is never persisted:
+/// rows are kept in memory until they exceed the spill threshold, after which they are moved to an
+/// LSM store in a temporary directory that is removed when the table is dropped.
+pub struct EphemeralTable {
+    /// Rows buffered in memory, until spilled.
+    memory: Vec<Row>,
+    /// The spill store, if spilled. Declared before its directory so that it is dropped first.
+    disk: Option<LsmStorage>,
+    /// The temporary directory backing the spill store.
+    dir: Option<TempDir>,
+    /// The number of rows held in memory (or in the spill store's memtable) before spilling.
+    threshold: usize,
+    /// The number of rows written to the spill store since its last flush.
+    unflushed: usize,
+    /// The total number of rows.
+    len: u64,
+}
+
+impl EphemeralTable {
+    /// Creates a new ephemeral table with the default spill threshold.
+    pub fn new() -> Self {
+        Self::with_threshold(DEFAULT_SPILL_THRESHOLD)
+    }
+
+    /// Creates a new ephemeral table which spills to disk after the given number of rows.
+    pub fn with_threshold(threshold: usize) -> Self {
+        Self { memory: Vec::new(), disk: None, dir: None, threshold, unflushed: 0, len: 0 }
+    }
+
+    /// Returns the number of rows in the table.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the table has no rows.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the temporary directory if the table has spilled to disk.
+    pub fn spill_path(&self) -> Option<&Path> {
+        self.dir.as_ref().map(|dir| dir.path())
+    }
+
+    /// Appends a row to the table, spilling to disk if it grows past the threshold.
+    pub fn write(&mut self, row: Row) -> Result<()> {
+        if self.disk.is_none() {
+            self.memory.push(row);
+            self.len += 1;
+            if self.memory.len() > self.threshold {
+                self.spill()?;
+            }
+            return Ok(());
+        }
+        self.write_disk(self.len, row)?;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Iterates over a range of row positions, in insertion order.
+    pub fn scan(&self, range: impl RangeBounds<u64>) -> Result<Rows> {
+        if let Some(disk) = &self.disk {
+            let start = match range.start_bound() {
+                Bound::Included(i) => Bound::Included(encode_u64(*i).to_vec()),
+                Bound::Excluded(i) => Bound::Excluded(encode_u64(*i).to_vec()),
+                Bound::Unbounded => Bound::Unbounded,
+            };
+            let end = match range.end_bound() {
+                Bound::Included(i) => Bound::Included(encode_u64(*i).to_vec()),
+                Bound::Excluded(i) => Bound::Excluded(encode_u64(*i).to_vec()),
+                Bound::Unbounded => Bound::Unbounded,
+            };
+            return Ok(Box::new(
+                disk.scan(Range::from((start, end)))?
+                    .map(|r| r.and_then(|(_, v)| deserialize(&v))),
+            ));
+        }
+
+        let len = self.memory.len();
+        let start = match range.start_bound() {
+            Bound::Included(i) => *i as usize,
+            Bound::Excluded(i) => *i as usize + 1,
+            Bound::Unbounded => 0,
+        }
+        .min(len);
+        let end = match range.end_bound() {
+            Bound::Included(i) => *i as usize + 1,
+            Bound::Excluded(i) => *i as usize,
+            Bound::Unbounded => len,
+        }
+        .clamp(start, len);
+        Ok(Box::new(Vec::from(&self.memory[start..end]).into_iter().map(Ok)))
+    }
+
+    /// Moves the in-memory rows into a spill store in a new temporary directory.
+    fn spill(&mut self) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        self.disk = Some(LsmStorage::open(dir.path())?);
+        self.dir = Some(dir);
+        for (i, row) in std::mem::take(&mut self.memory).into_iter().enumerate() {
+            self.write_disk(i as u64, row)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a row to the spill store, flushing the memtable whenever it reaches the threshold.
+    fn write_disk(&mut self, index: u64, row: Row) -> Result<()> {
+        let disk = self.disk.as_ref().expect("ephemeral table has not spilled");
+        disk.set(&encode_u64(index), serialize(&row)?)?;
+        self.unflushed += 1;
+        if self.unflushed >= self.threshold {
+            disk.flush()?;
+            self.unflushed = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Default for EphemeralTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::types::Value;
+
+    fn row(i: i64) -> Row {
+        vec![Value::Integer(i), Value::String(format!("row {}", i))]
+    }
+
+    #[test]
+    fn test_ephemeral_table_in_memory() -> Result<()> {
+        let mut table = EphemeralTable::with_threshold(10);
+        for i in 0..10 {
+            table.write(row(i))?;
+        }
+        assert!(table.spill_path().is_none());
+        assert_eq!(10, table.len());
+        assert_eq!(
+            (2..5).map(row).collect::<Vec<_>>(),
+            table.scan(2..5)?.collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ephemeral_table_spill() -> Result<()> {
+        let mut table = EphemeralTable::with_threshold(10);
+        for i in 0..100 {
+            table.write(row(i))?;
+        }
+        let path = table.spill_path().expect("table should have spilled").to_path_buf();
+        assert!(std::fs::read_dir(&path)?.next().is_some());
+        assert_eq!(100, table.len());
+
+        assert_eq!((0..100).map(row).collect::<Vec<_>>(), table.scan(..)?.collect::<Result<Vec<_>>>()?);
+        assert_eq!(
+            (5..=95).map(row).collect::<Vec<_>>(),
+            table.scan(5..=95)?.collect::<Result<Vec<_>>>()?
+        );
+
+        drop(table);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
 use crate::error::{Result, Error};
 use crate::sql::engine::SqlTxn;
+use crate::sql::plan::Node;
 use crate::sql::schema::Table;
-use crate::sql::types::{Expression, ResColumn, Row, Value, Rows};
+use crate::sql::types::{stable_hash, Columns, Expression, ResColumn, Row, Value, Rows};
+use super::peek::IntoPeekable;
+use super::subquery::execute_bound;
 use super::{Executor, ResultSet};
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 /// A nested loop join executor, which checks each row in the left source against every row in
@@ -135,7 +139,7 @@ impl NestedLoopRows {
                     value => {
                         return Err(Error::Value(format!(
                             "Join predicate returned {}, expected boolean",
-                            value
+                            value.to_sql_literal()
                         )))
                     }
                 }
@@ -153,4 +157,398 @@ impl Iterator for NestedLoopRows {
     fn next(&mut self) -> Option<Self::Item> {
         self.try_next().transpose()
     }
-}
\ No newline at end of file
+}
+
+/// A hash join executor, which joins the left and right rows whose join fields are equal. It
+/// hashes the smaller side by its join field and probes the hash with the other side, or always
+/// hashes the right side for outer joins, so that unmatched left rows can be padded with NULLs.
+/// It falls back to a sort-merge join when the hashed side exceeds MAX_BUILD_ROWS, or to a nested
+/// loop join when the join fields have different datatypes that may still compare equal, e.g.
+/// integers and floats.
+pub struct HashJoinExec<T: SqlTxn> {
+    left: Box<dyn Executor<T>>,
+    left_field: usize,
+    right: Box<dyn Executor<T>>,
+    right_field: usize,
+    outer: bool,
+}
+
+/// The maximum number of rows to hash before falling back to a sort-merge join.
+const MAX_BUILD_ROWS: usize = 1 << 20;
+
+impl<T: SqlTxn> HashJoinExec<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        left_field: usize,
+        right: Box<dyn Executor<T>>,
+        right_field: usize,
+        outer: bool,
+    ) -> Box<Self> {
+        Box::new(Self { left, left_field, right, right_field, outer })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for HashJoinExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match (self.left.execute(txn)?, self.right.execute(txn)?) {
+            (
+                ResultSet::Query { mut columns, buffered_rows },
+                ResultSet::Query { columns: right_columns, buffered_rows: right_buffered_rows },
+            ) => {
+                let join = HashJoin {
+                    left_width: columns.len(),
+                    left_field: self.left_field,
+                    right_width: right_columns.len(),
+                    right_field: self.right_field,
+                    outer: self.outer,
+                    max_build_rows: MAX_BUILD_ROWS,
+                };
+                columns.extend(right_columns);
+                let buffered_rows = match (buffered_rows, right_buffered_rows) {
+                    (Ok(left), Ok(right)) => join.join(left, right),
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                };
+                Ok(ResultSet::Query { columns, buffered_rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set".into())),
+        }
+    }
+}
+
+/// The parameters of a hash join, separate from the executor for testing.
+struct HashJoin {
+    left_width: usize,
+    left_field: usize,
+    right_width: usize,
+    right_field: usize,
+    outer: bool,
+    max_build_rows: usize,
+}
+
+impl HashJoin {
+    /// Joins the left and right rows, in left row order for outer joins.
+    fn join(&self, left: Vec<Row>, right: Vec<Row>) -> Result<Vec<Row>> {
+        let build_left = !self.outer && left.len() < right.len();
+        let build_size = if build_left { left.len() } else { right.len() };
+        let comparable = self.comparable(&left, &right);
+        if build_size > self.max_build_rows && comparable {
+            let join = SortMergeJoin {
+                left_field: self.left_field,
+                right_width: self.right_width,
+                right_field: self.right_field,
+                outer: self.outer,
+            };
+            return join.join(left, right);
+        }
+        if !comparable {
+            let predicate = Expression::Equal(
+                Expression::Field(self.left_field, None).into(),
+                Expression::Field(self.left_width + self.right_field, None).into(),
+            );
+            return NestedLoopRows::new(
+                Box::new(left.into_iter().map(Ok)),
+                right,
+                self.right_width,
+                Some(predicate),
+                self.outer,
+            )
+            .collect();
+        }
+
+        let mut rows = Vec::new();
+        if build_left {
+            let hash = Self::build(left, self.left_field)?;
+            for right_row in right {
+                let Some(key) = Self::key(&right_row, self.right_field)? else { continue };
+                for left_row in Self::probe(&hash, key, self.left_field) {
+                    let mut row = left_row.clone();
+                    row.extend(right_row.iter().cloned());
+                    rows.push(row);
+                }
+            }
+        } else {
+            let hash = Self::build(right, self.right_field)?;
+            for left_row in left {
+                let mut matched = false;
+                if let Some(key) = Self::key(&left_row, self.left_field)? {
+                    for right_row in Self::probe(&hash, key, self.right_field) {
+                        let mut row = left_row.clone();
+                        row.extend(right_row.iter().cloned());
+                        rows.push(row);
+                        matched = true;
+                    }
+                }
+                if self.outer && !matched {
+                    let mut row = left_row;
+                    row.extend(std::iter::repeat_n(Value::Null, self.right_width));
+                    rows.push(row);
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Returns whether all non-NULL join values have the same datatype, such that they are
+    /// equal exactly when their hash keys are.
+    fn comparable(&self, left: &[Row], right: &[Row]) -> bool {
+        let left = left.iter().filter_map(|row| row.get(self.left_field));
+        let right = right.iter().filter_map(|row| row.get(self.right_field));
+        let mut kinds = left
+            .chain(right)
+            .filter(|v| *v != &Value::Null)
+            .map(std::mem::discriminant);
+        match kinds.next() {
+            Some(kind) => kinds.all(|k| k == kind),
+            None => true,
+        }
+    }
+
+    /// Returns a row's join field, or None if it is NULL and can't match.
+    fn key(row: &[Value], field: usize) -> Result<Option<&Value>> {
+        match row.get(field) {
+            Some(Value::Null) => Ok(None),
+            Some(value) => Ok(Some(value)),
+            None => Err(Error::Internal(format!("Join field #{} not found", field))),
+        }
+    }
+
+    /// Hashes rows by the stable hash of their join field, skipping NULLs.
+    fn build(rows: Vec<Row>, field: usize) -> Result<HashMap<u64, Vec<Row>>> {
+        let mut hash: HashMap<u64, Vec<Row>> = HashMap::new();
+        for row in rows {
+            if let Some(key) = Self::key(&row, field)? {
+                hash.entry(stable_hash(key)).or_default().push(row);
+            }
+        }
+        Ok(hash)
+    }
+
+    /// Returns the hashed rows whose join field equals the key, skipping hash collisions.
+    fn probe<'a>(
+        hash: &'a HashMap<u64, Vec<Row>>,
+        key: &'a Value,
+        field: usize,
+    ) -> impl Iterator<Item = &'a Row> {
+        hash.get(&stable_hash(key)).into_iter().flatten().filter(move |row| row[field] == *key)
+    }
+}
+
+/// A sort-merge join of the left and right rows whose join fields are equal, used instead of a
+/// hash join when the hashed side would be too large. Both sides are sorted by their join field,
+/// and each left row is joined with the run of right rows having the same key, looking ahead in
+/// the right rows to find where the run ends. The join fields must have the same datatype.
+struct SortMergeJoin {
+    left_field: usize,
+    right_width: usize,
+    right_field: usize,
+    outer: bool,
+}
+
+impl SortMergeJoin {
+    /// Joins the left and right rows, in left row order, with matching right rows in right row
+    /// order.
+    fn join(&self, left: Vec<Row>, right: Vec<Row>) -> Result<Vec<Row>> {
+        let mut left = left
+            .into_iter()
+            .enumerate()
+            .map(|(i, row)| Ok((HashJoin::key(&row, self.left_field)?.cloned(), i, row)))
+            .collect::<Result<Vec<_>>>()?;
+        left.sort_by(|(a, ..), (b, ..)| Self::compare(a.as_ref(), b.as_ref()));
+        let mut right = right
+            .into_iter()
+            .map(|row| Ok((HashJoin::key(&row, self.right_field)?.cloned(), row)))
+            .filter(|r| !matches!(r, Ok((None, _))))
+            .collect::<Result<Vec<_>>>()?;
+        right.sort_by(|(a, _), (b, _)| Self::compare(a.as_ref(), b.as_ref()));
+        let mut right = right.into_iter().map(|(_, row)| Ok(row)).into_peekable();
+
+        // The run of right rows matching the previous left key, and the joined rows by left index.
+        let mut run: Option<(Value, Vec<Row>)> = None;
+        let mut rows = Vec::new();
+        for (key, i, left_row) in left {
+            let matches = match key {
+                Some(key) => {
+                    if run.as_ref().map(|(k, _)| k) != Some(&key) {
+                        let field = self.right_field;
+                        let cmp = |row: &Row| Self::compare(Some(&row[field]), Some(&key));
+                        let mut matched = Vec::new();
+                        while let Some(row) = right.next_if(|row| cmp(row).is_le()).transpose()? {
+                            if cmp(&row).is_eq() {
+                                matched.push(row);
+                            }
+                        }
+                        run = Some((key, matched));
+                    }
+                    run.as_ref().map(|(_, rows)| rows.as_slice()).unwrap_or_default()
+                }
+                None => &[],
+            };
+            for right_row in matches {
+                let mut row = left_row.clone();
+                row.extend(right_row.iter().cloned());
+                rows.push((i, row));
+            }
+            if self.outer && matches.is_empty() {
+                let mut row = left_row;
+                row.extend(std::iter::repeat_n(Value::Null, self.right_width));
+                rows.push((i, row));
+            }
+        }
+        rows.sort_by_key(|(i, _)| *i);
+        Ok(rows.into_iter().map(|(_, row)| row).collect())
+    }
+
+    /// Compares join keys, with NULLs first. Keys have the same datatype, so they're ordered,
+    /// with floats in their total order such that NaNs can be sorted too.
+    fn compare(a: Option<&Value>, b: Option<&Value>) -> Ordering {
+        match (a, b) {
+            (Some(Value::Float(a)), Some(Value::Float(b))) if a == b => Ordering::Equal,
+            (Some(Value::Float(a)), Some(Value::Float(b))) => a.total_cmp(b),
+            (Some(a), Some(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (a, b) => a.is_some().cmp(&b.is_some()),
+        }
+    }
+}
+
+/// A lateral join executor, which executes the right-hand plan once for each left row, binding
+/// any outer fields in the plan to the left row's values.
+pub struct LateralJoinExec<T: SqlTxn> {
+    left: Box<dyn Executor<T>>,
+    right: Node,
+    predicate: Option<Expression>,
+    outer: bool,
+}
+
+impl<T: SqlTxn + 'static> LateralJoinExec<T> {
+    pub fn new(
+        left: Box<dyn Executor<T>>,
+        right: Node,
+        predicate: Option<Expression>,
+        outer: bool,
+    ) -> Box<Self> {
+        Box::new(Self { left, right, predicate, outer })
+    }
+}
+
+impl<T: SqlTxn + 'static> Executor<T> for LateralJoinExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let (mut columns, left_rows) = match self.left.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => (columns, buffered_rows?),
+            _ => return Err(Error::Internal("Unexpected result set".into())),
+        };
+        let left_width = columns.len();
+
+        let mut right_columns = None;
+        let mut rows = Vec::new();
+        for left_row in left_rows {
+            let (right_cols, right_rows) = execute_bound(self.right.clone(), txn, &left_row)?;
+            let right_width = right_cols.len();
+            right_columns.get_or_insert(right_cols);
+            let mut hit = false;
+            for right_row in right_rows {
+                let mut row = left_row.clone();
+                row.extend(right_row);
+                if let Some(predicate) = &self.predicate {
+                    match predicate.evaluate(Some(&row))? {
+                        Value::Boolean(true) => {}
+                        Value::Boolean(false) | Value::Null => continue,
+                        value => {
+                            return Err(Error::Value(format!(
+                                "Join predicate returned {}, expected boolean",
+                                value.to_sql_literal()
+                            )))
+                        }
+                    }
+                }
+                hit = true;
+                rows.push(row);
+            }
+            if self.outer && !hit {
+                let mut row = left_row;
+                row.extend(std::iter::repeat_n(Value::Null, right_width));
+                rows.push(row);
+            }
+        }
+
+        // Without any left rows, execute the right-hand plan with NULL outer fields to find its
+        // columns.
+        let right_columns = match right_columns {
+            Some(right_columns) => right_columns,
+            None => {
+                execute_bound(self.right, txn, &vec![Value::Null; left_width])?.0
+            }
+        };
+        columns.extend(right_columns);
+        Ok(ResultSet::Query { columns, buffered_rows: Ok(rows) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(left: Vec<Row>, right: Vec<Row>, outer: bool, max_build_rows: usize) -> Vec<Row> {
+        let join = HashJoin {
+            left_width: 2,
+            left_field: 1,
+            right_width: 2,
+            right_field: 0,
+            outer,
+            max_build_rows,
+        };
+        join.join(left, right).unwrap()
+    }
+
+    #[test]
+    fn test_hash_join_fallback() {
+        use Value::*;
+        let left = vec![
+            vec![Integer(1), Float(-0.0)],
+            vec![Integer(2), Null],
+            vec![Integer(3), Float(2.0)],
+        ];
+        let right = vec![vec![Float(0.0), String("a".into())], vec![Null, String("b".into())]];
+        for outer in [false, true] {
+            let hashed = join(left.clone(), right.clone(), outer, MAX_BUILD_ROWS);
+            assert_eq!(hashed, join(left.clone(), right.clone(), outer, 0));
+            assert_eq!(if outer { 3 } else { 1 }, hashed.len());
+            assert_eq!(vec![Integer(1), Float(-0.0), Float(0.0), String("a".into())], hashed[0]);
+        }
+
+        // Integers equal floats, but hash differently, so mixed types use a nested loop join.
+        let right = vec![vec![Integer(2), String("c".into())]];
+        assert_eq!(
+            vec![vec![Integer(3), Float(2.0), Integer(2), String("c".into())]],
+            join(left, right, false, MAX_BUILD_ROWS),
+        );
+    }
+
+    #[test]
+    fn test_sort_merge_join() {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let mut key = || match rng.gen_range(0..12) {
+            0 => Value::Null,
+            k => Value::Integer(k),
+        };
+        let left: Vec<Row> = (0..200).map(|i| vec![Value::Integer(i), key()]).collect();
+        let right: Vec<Row> = (0..100).map(|i| vec![key(), Value::Integer(i)]).collect();
+        for outer in [false, true] {
+            // Joins on left.1 = right.0, like join() does.
+            let predicate = Expression::Equal(
+                Expression::Field(1, None).into(),
+                Expression::Field(2, None).into(),
+            );
+            let expect = NestedLoopRows::new(
+                Box::new(left.clone().into_iter().map(Ok)),
+                right.clone(),
+                2,
+                Some(predicate),
+                outer,
+            )
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+            assert_eq!(expect, join(left.clone(), right.clone(), outer, 0));
+        }
+    }
+}
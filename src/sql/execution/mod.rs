@@ -2,22 +2,44 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+mod aggregate;
+mod analyze;
+mod cte;
+mod ephemeral;
 mod join;
 mod mutation;
+mod peek;
 mod query;
 mod schema;
 mod source;
+mod subquery;
+mod window;
+
+use std::collections::HashMap;
 
 use derivative::Derivative;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::concurrency::Mode;
 use crate::error::{Result, Error};
-use self::join::NestedLoopJoinExec;
-use self::mutation::{InsertExec, UpdateExec, DeleteExec};
-use self::query::FilterExec;
-use self::schema::{CreateTableExec, DropTableExec};
-use self::source::{KeyLookupExec, Scan};
+use self::aggregate::AggregationExec;
+pub use self::analyze::{Analyzer, NodeStats};
+use self::analyze::TimingExec;
+use self::cte::{CteCache, MaterializedCteExec};
+pub use self::ephemeral::EphemeralTable;
+use self::join::{HashJoinExec, LateralJoinExec, NestedLoopJoinExec};
+use self::mutation::{InsertExec, InsertSelectExec, UpdateExec, DeleteExec, SelectIntoExec};
+pub use self::mutation::{InsertConflictAction, Returning};
+pub use self::peek::{IntoPeekable, PeekableRows};
+use self::query::{DistinctExec, FilterExec, LimitExec, OrderExec, ProjectionExec, SampleExec};
+use self::schema::{
+    AddColumnExec, AddConstraintExec, AddEnumValueExec, AnalyzeExec, CreateIndexExec,
+    CreateMaterializedViewExec, CreateTableExec, DropColumnExec, DropConstraintExec,
+    DropExtensionExec, DropMaterializedViewExec, DropTableExec, LoadExtensionExec,
+    RefreshMaterializedViewExec, ResetSequenceExec,
+};
+use self::source::{IndexLookupExec, KeyLookupExec, NothingExec, Scan};
+use self::window::WindowExec;
 
 use super::engine::SqlTxn;
 use super::plan::Node;
@@ -32,30 +54,156 @@ pub trait Executor<T: SqlTxn> {
 impl<T: SqlTxn + 'static> dyn Executor<T> {
     /// Builds an executor for a plan node, consuming it.
     pub fn build(node: Node) -> Box<dyn Executor<T>> {
+        Self::build_with(node, &mut HashMap::new(), None)
+    }
+
+    /// Builds an executor for a plan node, consuming it, which records the statistics of every
+    /// node in the analyzer.
+    pub fn build_analyzed(node: Node, analyzer: &Analyzer) -> Box<dyn Executor<T>> {
+        Self::build_with(node, &mut HashMap::new(), Some(analyzer))
+    }
+
+    /// Builds an executor for a plan node, sharing materialized CTEs by name, and wrapping it
+    /// to record its statistics if analyzed.
+    fn build_with(
+        node: Node,
+        ctes: &mut HashMap<String, CteCache>,
+        analyzer: Option<&Analyzer>,
+    ) -> Box<dyn Executor<T>> {
+        match analyzer {
+            Some(analyzer) => {
+                let slot = analyzer.allocate();
+                let exec = Self::build_node(node, ctes, Some(analyzer));
+                TimingExec::new(exec, analyzer.clone(), slot)
+            }
+            None => Self::build_node(node, ctes, None),
+        }
+    }
+
+    /// Builds an executor for a single plan node, building its sources with build_with(). Plan
+    /// subtrees that are executed separately are skipped by the analyzer.
+    fn build_node(
+        node: Node,
+        ctes: &mut HashMap<String, CteCache>,
+        analyzer: Option<&Analyzer>,
+    ) -> Box<dyn Executor<T>> {
         match node {
             Node::CreateTable { schema } => CreateTableExec::new(schema),
             Node::DropTable { table } => DropTableExec::new(table),
+            Node::Analyze { table } => AnalyzeExec::new(table),
+            Node::LoadExtension { path } => LoadExtensionExec::new(path),
+            Node::DropExtension { name } => DropExtensionExec::new(name),
+            Node::CreateMaterializedView { name, datatypes, source, query } => {
+                if let Some(analyzer) = analyzer {
+                    analyzer.skip(&source);
+                }
+                CreateMaterializedViewExec::new(name, datatypes, *source, query)
+            },
+            Node::DropMaterializedView { view } => DropMaterializedViewExec::new(view),
+            Node::RefreshMaterializedView { view } => RefreshMaterializedViewExec::new(view),
+            Node::ResetSequence { table, column, value } => {
+                ResetSequenceExec::new(table, column, value)
+            }
+            Node::AddEnumValue { table, column, value } => {
+                AddEnumValueExec::new(table, column, value)
+            }
+            Node::AddConstraint { table, name, constraint } => {
+                AddConstraintExec::new(table, name, constraint)
+            }
+            Node::DropConstraint { table, name } => DropConstraintExec::new(table, name),
+            Node::AddColumn { table, column } => AddColumnExec::new(table, column),
+            Node::DropColumn { table, column } => DropColumnExec::new(table, column),
+            Node::CreateIndex { table, column, predicate } => {
+                CreateIndexExec::new(table, column, predicate)
+            }
 
-            Node::Insert { table, columns, expression } => {
-                InsertExec::new(table, columns, expression)
+            Node::Insert { table, columns, expression, on_conflict, returning } => {
+                InsertExec::new(table, columns, expression, on_conflict, returning)
+            }
+            Node::InsertSelect { table, columns, source, on_conflict, returning } => {
+                InsertSelectExec::new(
+                    table,
+                    columns,
+                    Self::build_with(*source, ctes, analyzer),
+                    on_conflict,
+                    returning,
+                )
+            }
+            Node::SelectInto { table, primary_key, datatypes, source } => {
+                let source = Self::build_with(*source, ctes, analyzer);
+                SelectIntoExec::new(table, primary_key, datatypes, source)
             },
             Node::KeyLookup { table, alias, keys } => {
                 KeyLookupExec::new(table, keys)
+            }
+            Node::IndexLookup { table, alias, column, values } => {
+                IndexLookupExec::new(table, column, values)
             },
-            Node::Update { table, source, expressions } => UpdateExec::new(
+            Node::Update { table, source, expressions, returning } => UpdateExec::new(
                 table,
-                Self::build(*source),
+                Self::build_with(*source, ctes, analyzer),
                 expressions.into_iter().map(|(i, _, e)| (i, e)).collect(),
+                returning,
             ),
-            Node::Delete { table, source } => DeleteExec::new(table, Self::build(*source)),
+            Node::Delete { table, source, returning } => {
+                DeleteExec::new(table, Self::build_with(*source, ctes, analyzer), returning)
+            }
 
             Node::Scan { table, filter, alias: _ } => Scan::new(table, filter),
-            Node::Filter { source, predicate } => FilterExec::new(Self::build(*source), predicate),
-            Node::Projection { source, expressions } => todo!(),
+            Node::Filter { source, predicate } => {
+                FilterExec::new(Self::build_with(*source, ctes, analyzer), predicate)
+            },
+            Node::MaterializedCte { name, source } => {
+                let cache = ctes.entry(name).or_default().clone();
+                MaterializedCteExec::new(Self::build_with(*source, ctes, analyzer), cache)
+            },
+            Node::Projection { source, expressions } => {
+                ProjectionExec::new(Self::build_with(*source, ctes, analyzer), expressions)
+            },
+            Node::Distinct { source } => {
+                DistinctExec::new(Self::build_with(*source, ctes, analyzer))
+            }
+            Node::Order { source, orders } => {
+                OrderExec::new(Self::build_with(*source, ctes, analyzer), orders)
+            },
+            Node::Limit { source, limit, offset } => {
+                LimitExec::new(Self::build_with(*source, ctes, analyzer), limit, offset)
+            },
+            Node::Sample { source, probability, seed } => {
+                SampleExec::new(Self::build_with(*source, ctes, analyzer), probability, seed)
+            },
             Node::NestedLoopJoin { left, left_size, right, predicate, outer } => {
-                NestedLoopJoinExec::new(Self::build(*left), Self::build(*right), predicate, outer)
+                NestedLoopJoinExec::new(
+                    Self::build_with(*left, ctes, analyzer),
+                    Self::build_with(*right, ctes, analyzer),
+                    predicate,
+                    outer,
+                )
             },
-            Node::Nothing => todo!(),
+            Node::HashJoin { left, left_field, right, right_field, outer } => {
+                HashJoinExec::new(
+                    Self::build_with(*left, ctes, analyzer),
+                    left_field.0,
+                    Self::build_with(*right, ctes, analyzer),
+                    right_field.0,
+                    outer,
+                )
+            },
+            Node::LateralJoin { left, right, predicate, outer } => {
+                let left = Self::build_with(*left, ctes, analyzer);
+                if let Some(analyzer) = analyzer {
+                    analyzer.skip(&right);
+                }
+                LateralJoinExec::new(left, *right, predicate, outer)
+            },
+            Node::Aggregation { source, group_by, group_labels, aggregates } => {
+                let source = Self::build_with(*source, ctes, analyzer);
+                AggregationExec::new(source, group_by, group_labels, aggregates)
+            },
+            Node::Window { source, functions } => {
+                WindowExec::new(Self::build_with(*source, ctes, analyzer), functions)
+            },
+            Node::Nothing => NothingExec::new(),
         }
     }
 }
@@ -91,9 +239,19 @@ pub enum ResultSet {
     CreateTable { name: String },
     /// Table dropped
     DropTable { name: String },
+    /// Table altered
+    AlterTable { name: String },
+    /// Table statistics computed, over the given number of rows
+    Analyze { name: String, rows: u64 },
+    /// Extension loaded
+    LoadExtension { name: String },
+    /// Extension unloaded
+    DropExtension { name: String },
 
     /// Explain result
     Explain(Node),
+    /// Explain analyze result, i.e. the rendered plan annotated with execution statistics
+    ExplainAnalyze(String),
 }
 
 impl ResultSet {
@@ -104,18 +262,16 @@ impl ResultSet {
 
     /// Converts the ResultSet into a row, or errors if not a query result with rows.
     pub fn into_row(self) -> Result<Row> {
-        todo!()
-        // if let ResultSet::Query { mut rows, .. } = self {
-        //     rows.next().transpose()?.ok_or_else(|| Error::Value("No rows returned".into()))
-        // } else {
-        //     Err(Error::Value(format!("Not a query result: {:?}", self)))
-        // }
+        if let ResultSet::Query { buffered_rows, .. } = self {
+            buffered_rows?.into_iter().next().ok_or_else(|| Error::Value("No rows returned".into()))
+        } else {
+            Err(Error::Value(format!("Not a query result: {:?}", self)))
+        }
     }
 
-    /// Converts the ResultSet into a value, if possible. TODO: Read.
+    /// Converts the ResultSet into a value, if possible.
     pub fn into_value(self) -> Result<Value> {
-        todo!()
-        // self.into_row()?.into_iter().next().ok_or_else(|| Error::Value("No value returned".into()))
+        self.into_row()?.into_iter().next().ok_or_else(|| Error::Value("No value returned".into()))
     }
 }
 
@@ -132,6 +288,7 @@ mod tests {
             datatype: DataType::Integer,
             default,
             is_indexed: false,
+            index_predicate: None,
             is_nullable: false,
             is_primary_key,
             is_unique: true,
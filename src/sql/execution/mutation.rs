@@ -1,22 +1,128 @@
 use std::collections::{HashSet, HashMap};
 
+use serde_derive::{Deserialize, Serialize};
+
 use crate::error::{Result, Error};
 use crate::sql::engine::SqlTxn;
-use crate::sql::schema::Table;
-use crate::sql::types::{Expression, Value, Row};
+use crate::sql::schema::{Column, Table};
+use crate::sql::types::{DataType, Decimal, Expression, ResColumn, Value, Row};
+use super::query::ProjectionExec;
+use super::schema::CreateTableExec;
+use super::source::ValuesExec;
 use super::{Executor, ResultSet};
 
+/// The action taken when an inserted row conflicts with an existing primary key or unique value.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InsertConflictAction {
+    /// Fail the insert with an error.
+    Error,
+    /// Skip the conflicting row and continue with the next one.
+    DoNothing,
+    /// Update the existing row with the given column values, evaluated against the existing
+    /// row followed by the would-be-inserted row.
+    DoUpdate(Vec<(usize, Option<String>, Expression)>),
+}
+
+impl InsertConflictAction {
+    /// Transforms the DO UPDATE expressions, see Expression::transform().
+    pub fn transform<B, A>(self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        Ok(match self {
+            Self::DoUpdate(set) => Self::DoUpdate(
+                set.into_iter()
+                    .map(|(i, l, e)| Ok((i, l, e.transform(before, after)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            action => action,
+        })
+    }
+}
+
+/// The expressions and labels of a mutation's RETURNING clause. They are evaluated against each
+/// affected row as written, or as it was before deletion, followed by the OLD and NEW rows.
+pub type Returning = Vec<(Expression, Option<String>)>;
+
+/// Collects the rows affected by a mutation for its RETURNING clause, if any. The OLD and NEW
+/// rows are the row before and after the mutation, and are all NULL for inserted and deleted
+/// rows respectively.
+struct AffectedRows {
+    table: Table,
+    returning: Option<Returning>,
+    rows: Vec<Row>,
+}
+
+impl AffectedRows {
+    fn new(table: &Table, returning: Option<Returning>) -> Self {
+        Self { table: table.clone(), returning, rows: Vec::new() }
+    }
+
+    /// Records an affected row, given the row before and after the mutation.
+    fn add(&mut self, old: Option<&[Value]>, new: Option<&[Value]>) -> Result<()> {
+        if self.returning.is_none() {
+            return Ok(());
+        }
+        let nulls = || vec![Value::Null; self.table.columns.len()];
+        let old = old.map(|row| row.to_vec());
+        let new = new.map(|row| self.table.coerce_row(row.to_vec())).transpose()?;
+        let mut row = new.clone().or_else(|| old.clone()).unwrap_or_else(nulls);
+        row.extend(old.unwrap_or_else(nulls));
+        row.extend(new.unwrap_or_else(nulls));
+        self.rows.push(row);
+        Ok(())
+    }
+
+    /// Returns the RETURNING rows, or the given result if there is no RETURNING clause.
+    fn finish<T: SqlTxn + 'static>(self, txn: &mut T, result: ResultSet) -> Result<ResultSet> {
+        let Some(returning) = self.returning else { return Ok(result) };
+        let names = self.table.columns.iter().map(|c| ResColumn { name: Some(c.name.clone()) });
+        let columns = names.clone().chain(names.clone()).chain(names).collect();
+        ProjectionExec::new(ValuesExec::new(columns, self.rows), returning).execute(txn)
+    }
+}
+
 /// An INSERT executor
 pub struct InsertExec {
     table: String,
     columns: Vec<String>,
     rows: Vec<Vec<Expression>>,
+    on_conflict: InsertConflictAction,
+    returning: Option<Returning>,
 }
 
 impl InsertExec {
     /// Creates a new INSERT executor.
-    pub fn new(table: String, columns: Vec<String>, rows: Vec<Vec<Expression>>) -> Box<Self> {
-        Box::new(Self { table, columns, rows })
+    pub fn new(
+        table: String,
+        columns: Vec<String>,
+        rows: Vec<Vec<Expression>>,
+        on_conflict: InsertConflictAction,
+        returning: Option<Returning>,
+    ) -> Box<Self> {
+        Box::new(Self { table, columns, rows, on_conflict, returning })
+    }
+
+    /// Returns the existing row conflicting with a row's primary key or unique values, if any.
+    /// This only checks for key existence, leaving other validation to the insert itself.
+    fn conflict<T: SqlTxn>(table: &Table, row: &Row, txn: &mut T) -> Result<Option<Row>> {
+        let id = table.get_row_key(row)?;
+        if let Some(existing) = txn.read(&table.name, &id)? {
+            return Ok(Some(existing));
+        }
+        for (i, column) in table.columns.iter().enumerate() {
+            if !column.is_unique || column.is_primary_key || row[i] == Value::Null {
+                continue;
+            }
+            let mut scan = txn.scan(&table.name, None)?;
+            while let Some(existing) = scan.next().transpose()? {
+                if existing.get(i) == Some(&row[i]) {
+                    return Ok(Some(existing));
+                }
+            }
+        }
+        Ok(None)
     }
 
     /// Builds a row from a set of column names and values, padding it with default values.
@@ -33,14 +139,9 @@ impl InsertExec {
         }
         let mut row = Row::new();
         for column in table.columns.iter() {
-            if let Some(value) = inputs.get(&column.name) {
-                row.push(value.clone());
-            } else if let Some(default) = &column.default {
-                row.push(default.clone());
-            } else {
-                return Err(Error::Value(format!(
-                    "Column {} not given and has no default value", column.name
-                )));
+            match inputs.get(&column.name) {
+                Some(value) => row.push(value.clone()),
+                None => row.push(column.omitted_value()?),
             }
         }
         Ok(row)
@@ -49,21 +150,16 @@ impl InsertExec {
     /// Pads a row with default values where possible.
     fn pad_row(table: &Table, mut row: Vec<Value>) -> Result<Row> {
         for column in table.columns.iter().skip(row.len()) {
-            if let Some(default) = &column.default {
-                row.push(default.clone());
-            } else {
-                return Err(Error::Value(format!(
-                    "Column {} not given and has no default value", column.name
-                )));
-            }
+            row.push(column.omitted_value()?);
         }
         Ok(row)
     }
 }
 
-impl<T: SqlTxn> Executor<T> for InsertExec {
+impl<T: SqlTxn + 'static> Executor<T> for InsertExec {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.assert_read_table(&self.table)?;
+        let mut affected = AffectedRows::new(&table, self.returning);
         let mut count = 0;
         for expressions in self.rows {
             let mut row = expressions
@@ -74,10 +170,71 @@ impl<T: SqlTxn> Executor<T> for InsertExec {
                 true => row = Self::pad_row(&table, row)?,
                 false => row = Self::build_row(&table, row, &self.columns)?,
             };
+            // Assign SERIAL values up front, for conflict checks and RETURNING.
+            table.assign_serials(&mut row, txn)?;
+            if self.on_conflict != InsertConflictAction::Error {
+                // Coerce the row up front, so it compares equal to stored rows.
+                row = table.coerce_row(row)?;
+                match (&self.on_conflict, Self::conflict(&table, &row, txn)?) {
+                    (InsertConflictAction::DoNothing, Some(_)) => continue,
+                    (InsertConflictAction::DoUpdate(set), Some(existing)) => {
+                        let id = table.get_row_key(&existing)?;
+                        let mut new = existing.clone();
+                        let mut env = existing;
+                        env.extend(row);
+                        for (field, _, expr) in set {
+                            new[*field] = expr.evaluate(Some(&env))?;
+                        }
+                        affected.add(Some(&env[..table.columns.len()]), Some(&new))?;
+                        txn.update(&table.name, &id, new)?;
+                        count += 1;
+                        continue;
+                    }
+                    _ => {}
+                }
+            }
+            affected.add(None, Some(&row))?;
             txn.create(&table.name, row)?;
             count += 1;
         }
-        Ok(ResultSet::Create { count })
+        affected.finish(txn, ResultSet::Create { count })
+    }
+}
+
+/// An INSERT executor for the rows of a source query. The source rows are all buffered before
+/// inserting any of them, so a query reading the target table doesn't see its own inserts.
+pub struct InsertSelectExec<T: SqlTxn> {
+    table: String,
+    columns: Vec<String>,
+    source: Box<dyn Executor<T>>,
+    on_conflict: InsertConflictAction,
+    returning: Option<Returning>,
+}
+
+impl<T: SqlTxn> InsertSelectExec<T> {
+    pub fn new(
+        table: String,
+        columns: Vec<String>,
+        source: Box<dyn Executor<T>>,
+        on_conflict: InsertConflictAction,
+        returning: Option<Returning>,
+    ) -> Box<Self> {
+        Box::new(Self { table, columns, source, on_conflict, returning })
+    }
+}
+
+impl<T: SqlTxn + 'static> Executor<T> for InsertSelectExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let rows = match self.source.execute(txn)? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows?,
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+        let rows = rows
+            .into_iter()
+            .map(|row| row.into_iter().map(Expression::Constant).collect())
+            .collect();
+        InsertExec::new(self.table, self.columns, rows, self.on_conflict, self.returning)
+            .execute(txn)
     }
 }
 
@@ -86,6 +243,7 @@ pub struct UpdateExec<T: SqlTxn> {
     table: String,
     source: Box<dyn Executor<T>>,
     expressions: Vec<(usize, Expression)>,
+    returning: Option<Returning>,
 }
 
 impl<T: SqlTxn> UpdateExec<T> {
@@ -93,16 +251,18 @@ impl<T: SqlTxn> UpdateExec<T> {
         table: String,
         source: Box<dyn Executor<T>>,
         expressions: Vec<(usize, Expression)>,
+        returning: Option<Returning>,
     ) -> Box<Self> {
-        Box::new(Self { table, source, expressions })
+        Box::new(Self { table, source, expressions, returning })
     }
 }
 
-impl<T: SqlTxn> Executor<T> for UpdateExec<T> {
+impl<T: SqlTxn + 'static> Executor<T> for UpdateExec<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
             ResultSet::Query { buffered_rows, .. } => {
                 let table = txn.assert_read_table(&self.table)?;
+                let mut affected = AffectedRows::new(&table, self.returning);
 
                 // The iterator will see our changes, such that the same item may be iterated over
                 // multiple times. We keep track of the primary keys here to avoid that, althought
@@ -122,10 +282,11 @@ impl<T: SqlTxn> Executor<T> for UpdateExec<T> {
                     for (field, expr) in &self.expressions {
                         new[*field] = expr.evaluate(Some(&row))?;
                     }
+                    affected.add(Some(&row), Some(&new))?;
                     txn.update(&table.name, &id, new)?;
                     updated.insert(id);
                 }
-                Ok(ResultSet::Update { count: updated.len() as u64 })
+                affected.finish(txn, ResultSet::Update { count: updated.len() as u64 })
             },
             
             r => Err(Error::Internal(format!("Unexpected response {:?}", r))),
@@ -137,33 +298,125 @@ impl<T: SqlTxn> Executor<T> for UpdateExec<T> {
 pub struct DeleteExec<T: SqlTxn> {
     table: String,
     source: Box<dyn Executor<T>>,
+    returning: Option<Returning>,
 }
 
 impl<T: SqlTxn> DeleteExec<T> {
-    pub fn new(table: String, source: Box<dyn Executor<T>>) -> Box<Self> {
-        Box::new(Self { table, source })
+    pub fn new(
+        table: String,
+        source: Box<dyn Executor<T>>,
+        returning: Option<Returning>,
+    ) -> Box<Self> {
+        Box::new(Self { table, source, returning })
     }
 }
 
-impl<T: SqlTxn> Executor<T> for DeleteExec<T> {
+impl<T: SqlTxn + 'static> Executor<T> for DeleteExec<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let table = txn.assert_read_table(&self.table)?;
+        let mut affected = AffectedRows::new(&table, self.returning);
         let mut count = 0;
         match self.source.execute(txn)? {
             ResultSet::Query { buffered_rows, .. } => {
                 let mut rows = buffered_rows?.into_iter();
                 while let Some(row) = rows.next() {
                     txn.delete(&table.name, &table.get_row_key(&row)?)?;
+                    affected.add(Some(&row), None)?;
                     count += 1;
                 }
-                Ok(ResultSet::Delete { count })
+                affected.finish(txn, ResultSet::Delete { count })
             },
             r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
         }
     }
 }
 
+/// A SELECT INTO executor, which creates a table and inserts the source rows into it. The given
+/// primary key column, or else the first column, becomes the primary key, and all other columns
+/// are nullable.
+pub struct SelectIntoExec<T: SqlTxn> {
+    table: String,
+    primary_key: Option<String>,
+    datatypes: Vec<Option<DataType>>,
+    source: Box<dyn Executor<T>>,
+}
 
+impl<T: SqlTxn> SelectIntoExec<T> {
+    pub fn new(
+        table: String,
+        primary_key: Option<String>,
+        datatypes: Vec<Option<DataType>>,
+        source: Box<dyn Executor<T>>,
+    ) -> Box<Self> {
+        Box::new(Self { table, primary_key, datatypes, source })
+    }
+}
+
+impl<T: SqlTxn + 'static> Executor<T> for SelectIntoExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let (columns, rows) = match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => (columns, buffered_rows?),
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+
+        let key = match &self.primary_key {
+            Some(key) => columns.iter().position(|c| c.name.as_ref() == Some(key)).ok_or_else(
+                || Error::Value(format!("SELECT INTO has no primary key column {}", key)),
+            )?,
+            None => 0,
+        };
+
+        // Columns without a planned datatype take it from the first non-null value.
+        let mut names = HashSet::new();
+        let columns = columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let name = column.name.ok_or_else(|| {
+                    Error::Value(format!("Column #{} in SELECT INTO has no name", i))
+                })?;
+                if !names.insert(name.clone()) {
+                    return Err(Error::Value(format!("Duplicate column {} in SELECT INTO", name)));
+                }
+                let datatype = self
+                    .datatypes
+                    .get(i)
+                    .cloned()
+                    .flatten()
+                    .or_else(|| rows.iter().find_map(|row| row.get(i).and_then(|v| v.datatype())))
+                    .ok_or_else(|| Error::Value(format!("Can't infer datatype of column {}", name)))?;
+                // A decimal value's precision only covers itself, so allow any precision.
+                let datatype = match datatype {
+                    DataType::Decimal(_, scale) => DataType::Decimal(Decimal::MAX_PRECISION, scale),
+                    datatype => datatype,
+                };
+                Ok(Column {
+                    name,
+                    datatype,
+                    is_primary_key: i == key,
+                    is_nullable: i != key,
+                    default: if i == key { None } else { Some(Value::Null) },
+                    is_unique: i == key,
+                    references: None,
+                    is_indexed: false,
+                    index_predicate: None,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        CreateTableExec::new(Table::new(self.table.clone(), columns)?).execute(txn)?;
+        InsertExec::new(
+            self.table,
+            Vec::new(),
+            rows.into_iter()
+                .map(|row| row.into_iter().map(Expression::Constant).collect())
+                .collect(),
+            InsertConflictAction::Error,
+            None,
+        )
+        .execute(txn)
+    }
+}
 
 #[cfg(test)]
 mod tests {
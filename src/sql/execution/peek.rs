@@ -0,0 +1,112 @@
+use crate::error::Result;
+use crate::sql::types::{Row, Rows};
+
+/// A row iterator which can look at the next row without consuming it, by buffering at most
+/// one row from the inner iterator.
+pub struct PeekableRows {
+    rows: Rows,
+    /// The peeked item, if any. Some(None) means the inner iterator is exhausted.
+    peeked: Option<Option<Result<Row>>>,
+}
+
+impl PeekableRows {
+    /// Wraps a row iterator.
+    pub fn new(rows: Rows) -> Self {
+        Self { rows, peeked: None }
+    }
+
+    /// Returns the next item without consuming it.
+    pub fn peek(&mut self) -> Option<&Result<Row>> {
+        let rows = &mut self.rows;
+        self.peeked.get_or_insert_with(|| rows.next()).as_ref()
+    }
+
+    /// Consumes and returns the next row if it satisfies the predicate. Errors are always
+    /// returned, so they aren't hidden by a failed predicate.
+    pub fn next_if(&mut self, predicate: impl FnOnce(&Row) -> bool) -> Option<Result<Row>> {
+        match self.peek()? {
+            Ok(row) if !predicate(row) => None,
+            _ => self.next(),
+        }
+    }
+}
+
+impl Iterator for PeekableRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.peeked.take() {
+            Some(item) => item,
+            None => self.rows.next(),
+        }
+    }
+}
+
+/// Wraps row iterators as PeekableRows.
+pub trait IntoPeekable {
+    fn into_peekable(self) -> PeekableRows;
+}
+
+impl<I> IntoPeekable for I
+where
+    I: Iterator<Item = Result<Row>> + Send + 'static,
+{
+    fn into_peekable(self) -> PeekableRows {
+        PeekableRows::new(Box::new(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::sql::types::Value;
+
+    fn rows(items: Vec<Result<i64>>) -> PeekableRows {
+        items.into_iter().map(|item| item.map(|i| vec![Value::Integer(i)])).into_peekable()
+    }
+
+    fn row(i: i64) -> Row {
+        vec![Value::Integer(i)]
+    }
+
+    #[test]
+    fn peek() {
+        let mut rows = rows(vec![Ok(1), Ok(2)]);
+        assert_eq!(Some(&Ok(row(1))), rows.peek());
+        assert_eq!(Some(&Ok(row(1))), rows.peek());
+        assert_eq!(Some(Ok(row(1))), rows.next());
+        assert_eq!(Some(Ok(row(2))), rows.next());
+
+        // Peeking past the end keeps returning None.
+        assert_eq!(None, rows.peek());
+        assert_eq!(None, rows.peek());
+        assert_eq!(None, rows.next());
+    }
+
+    #[test]
+    fn next_if() {
+        let mut rows = rows(vec![Ok(1), Ok(2), Ok(3)]);
+        let below = |n| move |row: &Row| row[0] < Value::Integer(n);
+        assert_eq!(None, rows.next_if(below(1)));
+        assert_eq!(Some(&Ok(row(1))), rows.peek());
+        assert_eq!(Some(Ok(row(1))), rows.next_if(below(3)));
+        assert_eq!(Some(Ok(row(2))), rows.next_if(below(3)));
+        assert_eq!(None, rows.next_if(below(3)));
+        assert_eq!(vec![row(3)], rows.collect::<Result<Vec<_>>>().unwrap());
+    }
+
+    #[test]
+    fn errors() {
+        let err = Error::Internal("boom".into());
+        let mut rows = rows(vec![Ok(1), Err(err.clone()), Ok(3)]);
+        assert_eq!(Some(Ok(row(1))), rows.next());
+        assert_eq!(Some(&Err(err.clone())), rows.peek());
+        assert_eq!(Some(Err(err.clone())), rows.next_if(|_| false));
+        assert_eq!(Some(Ok(row(3))), rows.next());
+        assert_eq!(None, rows.next());
+
+        let rows = self::rows(vec![Ok(1), Err(err.clone())]);
+        assert_eq!(Err(err), rows.collect::<Result<Vec<_>>>());
+    }
+}
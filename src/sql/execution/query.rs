@@ -1,7 +1,14 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use rand::{Rng, SeedableRng};
+
 use crate::error::{Result, Error};
 use crate::sql::engine::SqlTxn;
+use crate::sql::plan::SortOrder;
 use crate::sql::schema::Table;
-use crate::sql::types::{Expression, ResColumn, Row, Value};
+use crate::sql::types::{stable_hash_row, Columns, Expression, ResColumn, Row, Value};
+use super::subquery::SubqueryEvaluator;
 use super::{Executor, ResultSet};
 
 /// A filter executor
@@ -16,12 +23,12 @@ impl<T: SqlTxn> FilterExec<T> {
     }
 }
 
-impl<T: SqlTxn> Executor<T> for FilterExec<T> {
+impl<T: SqlTxn + 'static> Executor<T> for FilterExec<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         match self.source.execute(txn)? {
             ResultSet::Query { columns, buffered_rows } => {
                 let predicate = self.predicate;
-                let predicate_buffered = predicate.clone();
+                let mut predicate_buffered = SubqueryEvaluator::new(predicate.clone());
                 Ok(ResultSet::Query {
                     columns,
                     // rows: Box::new(rows.filter_map(move |r| {
@@ -38,12 +45,13 @@ impl<T: SqlTxn> Executor<T> for FilterExec<T> {
                     // })),
                     buffered_rows: buffered_rows?
                         .into_iter()
-                        .filter_map(|row| match predicate_buffered.evaluate(Some(&row)) {
+                        .filter_map(|row| match predicate_buffered.evaluate(txn, &row) {
                             Ok(Value::Boolean(true)) => Ok(Some(row)),
                             Ok(Value::Boolean(false)) => Ok(None),
                             Ok(Value::Null) => Ok(None),
                             Ok(value) => Err(Error::Value(format!(
-                                "Filter returned {}, expected boolean", value
+                                "Filter returned {}, expected boolean",
+                                value.to_sql_literal()
                             ))),
                             Err(e) => Err(e),
                         }.transpose())
@@ -53,4 +61,369 @@ impl<T: SqlTxn> Executor<T> for FilterExec<T> {
             _ => Err(Error::Internal("Unexpected result".into()))
         }
     }
-}
\ No newline at end of file
+}
+
+/// A projection executor, which evaluates the given expressions for each source row. Columns
+/// are named by their label, or by the source column name for unlabeled field references. The
+/// planner labels computed expressions, see Planner::label_projection().
+pub struct ProjectionExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    expressions: Vec<(Expression, Option<String>)>,
+}
+
+impl<T: SqlTxn> ProjectionExec<T> {
+    pub fn new(
+        source: Box<dyn Executor<T>>,
+        expressions: Vec<(Expression, Option<String>)>,
+    ) -> Box<Self> {
+        Box::new(Self { source, expressions })
+    }
+}
+
+impl<T: SqlTxn + 'static> Executor<T> for ProjectionExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => {
+                let (expressions, labels): (Vec<Expression>, Vec<Option<String>>) =
+                    self.expressions.into_iter().unzip();
+                let columns = expressions
+                    .iter()
+                    .zip(labels)
+                    .map(|(expr, label)| match (expr, label) {
+                        (_, Some(label)) => ResColumn { name: Some(label) },
+                        (Expression::Field(i, _), None) => {
+                            columns.get(*i).cloned().unwrap_or(ResColumn { name: None })
+                        }
+                        (_, None) => ResColumn { name: None },
+                    })
+                    .collect();
+                let mut expressions: Vec<_> =
+                    expressions.into_iter().map(SubqueryEvaluator::new).collect();
+                Ok(ResultSet::Query {
+                    columns,
+                    buffered_rows: buffered_rows.and_then(|rows| {
+                        rows.into_iter()
+                            .map(|row| {
+                                expressions.iter_mut().map(|e| e.evaluate(txn, &row)).collect()
+                            })
+                            .collect()
+                    }),
+                })
+            }
+            _ => Err(Error::Internal("Unexpected result".into())),
+        }
+    }
+}
+
+/// A Bernoulli sample executor, which includes each source row independently with the given
+/// probability. A fixed seed makes the sample repeatable.
+pub struct SampleExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    probability: f64,
+    seed: Option<u64>,
+}
+
+impl<T: SqlTxn> SampleExec<T> {
+    pub fn new(source: Box<dyn Executor<T>>, probability: f64, seed: Option<u64>) -> Box<Self> {
+        Box::new(Self { source, probability, seed })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for SampleExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => {
+                let mut rng = match self.seed {
+                    Some(seed) => rand::rngs::StdRng::seed_from_u64(seed),
+                    None => rand::rngs::StdRng::from_entropy(),
+                };
+                let probability = self.probability;
+                Ok(ResultSet::Query {
+                    columns,
+                    buffered_rows: buffered_rows
+                        .map(|rows| rows.into_iter().filter(|_| rng.gen_bool(probability)).collect()),
+                })
+            },
+            _ => Err(Error::Internal("Unexpected result".into()))
+        }
+    }
+}
+
+/// A distinct executor, which removes duplicate source rows. Rows are deduplicated with a hash
+/// set in order of first appearance, or for more than MAX_HASH_ROWS rows by sorting them in place
+/// and removing adjacent duplicates, to avoid allocating a hash set of that size. Rows compare
+/// like their values, so e.g. NULLs are equal to each other but NaNs are not.
+pub struct DistinctExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+}
+
+/// The maximum number of rows to deduplicate with a hash set, rather than by sorting.
+const MAX_HASH_ROWS: usize = 1 << 20;
+
+impl<T: SqlTxn> DistinctExec<T> {
+    pub fn new(source: Box<dyn Executor<T>>) -> Box<Self> {
+        Box::new(Self { source })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for DistinctExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => Ok(ResultSet::Query {
+                columns,
+                buffered_rows: buffered_rows.map(|rows| distinct(rows, MAX_HASH_ROWS)),
+            }),
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+}
+
+/// Removes duplicate rows, hashing at most max_hash_rows rows.
+fn distinct(mut rows: Vec<Row>, max_hash_rows: usize) -> Vec<Row> {
+    if rows.len() > max_hash_rows {
+        rows.sort_unstable_by(|a, b| {
+            for (a, b) in a.iter().zip(b) {
+                let ordering = distinct_cmp(a, b);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            a.len().cmp(&b.len())
+        });
+        rows.dedup();
+        return rows;
+    }
+    let keep: Vec<bool> = {
+        let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+        (0..rows.len())
+            .map(|i| {
+                let indexes = seen.entry(stable_hash_row(&rows[i])).or_default();
+                let duplicate = indexes.iter().any(|j| rows[*j] == rows[i]);
+                if !duplicate {
+                    indexes.push(i);
+                }
+                !duplicate
+            })
+            .collect()
+    };
+    let mut keep = keep.into_iter();
+    rows.retain(|_| keep.next().unwrap_or(false));
+    rows
+}
+
+/// Orders values such that equal values are adjacent: by type, then by value. Zeroes of either
+/// sign are equal, and NaNs sort after other floats.
+fn distinct_cmp(a: &Value, b: &Value) -> Ordering {
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) => 2,
+            Value::Float(_) => 3,
+            Value::Decimal(_) => 4,
+            Value::String(_) => 5,
+            Value::Timestamp(_) => 6,
+            Value::Date(_) => 7,
+        }
+    }
+    match (a, b) {
+        (Value::Float(a), Value::Float(b)) => {
+            a.partial_cmp(b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+        }
+        (a, b) => rank(a).cmp(&rank(b)).then_with(|| a.partial_cmp(b).unwrap_or(Ordering::Equal)),
+    }
+}
+
+/// An order executor, which buffers the source rows and stably sorts them by the given
+/// expressions. Nulls sort last in ascending order and first in descending order, and values
+/// which can't be compared (e.g. of different types) are considered equal.
+pub struct OrderExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    orders: Vec<(Expression, SortOrder)>,
+}
+
+impl<T: SqlTxn> OrderExec<T> {
+    pub fn new(source: Box<dyn Executor<T>>, orders: Vec<(Expression, SortOrder)>) -> Box<Self> {
+        Box::new(Self { source, orders })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for OrderExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => {
+                // Evaluate the sort keys up front, so that errors can be returned.
+                let mut keyed = buffered_rows?
+                    .into_iter()
+                    .map(|row| {
+                        let keys = self
+                            .orders
+                            .iter()
+                            .map(|(expr, _)| expr.evaluate(Some(&row)))
+                            .collect::<Result<Vec<_>>>()?;
+                        Ok((keys, row))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                keyed.sort_by(|(a, _), (b, _)| compare_sort_keys(a, b, &self.orders));
+                Ok(ResultSet::Query {
+                    columns,
+                    buffered_rows: Ok(keyed.into_iter().map(|(_, row)| row).collect()),
+                })
+            },
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+}
+
+/// Compares two rows' sort keys, evaluated from the given order expressions. See OrderExec for
+/// the ordering of nulls and incomparable values. Keys cast to an enum sort by declaration order.
+pub(super) fn compare_sort_keys(
+    a: &[Value],
+    b: &[Value],
+    orders: &[(Expression, SortOrder)],
+) -> Ordering {
+    for ((a, b), (expr, order)) in a.iter().zip(b).zip(orders) {
+        let ordering = match (a, b) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Greater,
+            (_, Value::Null) => Ordering::Less,
+            (a, b) => match expr.enum_type() {
+                Some(datatype) => datatype.compare(a, b),
+                None => a.sql_cmp(b).ok().flatten(),
+            }
+            .unwrap_or(Ordering::Equal),
+        };
+        let ordering = match order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// A limit executor, which skips the first offset source rows and emits at most limit rows.
+pub struct LimitExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    limit: Option<u64>,
+    offset: u64,
+}
+
+impl<T: SqlTxn> LimitExec<T> {
+    pub fn new(source: Box<dyn Executor<T>>, limit: Option<u64>, offset: u64) -> Box<Self> {
+        Box::new(Self { source, limit, offset })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for LimitExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => {
+                let limit = self.limit.map_or(usize::MAX, |limit| limit as usize);
+                let offset = self.offset as usize;
+                Ok(ResultSet::Query {
+                    columns,
+                    buffered_rows: buffered_rows
+                        .map(|rows| rows.into_iter().skip(offset).take(limit).collect()),
+                })
+            },
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::{Mode, MVCC};
+    use crate::sql::engine::{KvSqlEngine, SqlEngine};
+    use crate::storage::kv::StdBPlusTree;
+    use super::*;
+
+    /// A source executor returning a fixed number of rows.
+    struct RowsExec(i64);
+
+    impl<T: SqlTxn> Executor<T> for RowsExec {
+        fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+            Ok(ResultSet::Query {
+                columns: vec![ResColumn { name: Some("id".into()) }],
+                buffered_rows: Ok((0..self.0).map(|i| vec![Value::Integer(i)]).collect()),
+            })
+        }
+    }
+
+    fn sample(rows: i64, probability: f64, seed: Option<u64>) -> Result<Vec<Row>> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        match SampleExec::new(Box::new(RowsExec(rows)), probability, seed).execute(&mut txn)? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows,
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+
+    #[test]
+    fn test_distinct() {
+        use Value::*;
+        for max_hash_rows in [usize::MAX, 0] {
+            let mut rows = distinct(Vec::new(), max_hash_rows);
+            assert!(rows.is_empty());
+            rows = distinct(vec![vec![Null, Null]], max_hash_rows);
+            assert_eq!(vec![vec![Null, Null]], rows);
+            rows = distinct(vec![vec![Null, Null], vec![Null, Null]], max_hash_rows);
+            assert_eq!(vec![vec![Null, Null]], rows);
+
+            // Zeroes are equal regardless of sign, NaNs are never equal, and values of
+            // different types are distinct.
+            rows = distinct(
+                vec![
+                    vec![Float(0.0), Integer(1)],
+                    vec![Float(f64::NAN), Integer(1)],
+                    vec![Float(-0.0), Integer(1)],
+                    vec![Float(f64::NAN), Integer(1)],
+                    vec![Float(0.0), Float(1.0)],
+                    vec![Float(-0.0), Integer(1)],
+                ],
+                max_hash_rows,
+            );
+            assert_eq!(4, rows.len());
+            let nans = rows.iter().filter(|row| matches!(row[0], Float(f) if f.is_nan()));
+            assert_eq!(2, nans.count());
+        }
+
+        // The hash set keeps rows in order of first appearance.
+        let rows = vec![vec![Integer(2)], vec![Integer(1)], vec![Integer(2)], vec![Null]];
+        assert_eq!(vec![vec![Integer(2)], vec![Integer(1)], vec![Null]], distinct(rows, 4));
+    }
+
+    #[test]
+    fn test_distinct_large() {
+        let rows: Vec<Row> =
+            (0..1_000_000).map(|i| vec![Value::Integer(i % 1000), Value::Null]).collect();
+        let hashed = distinct(rows.clone(), MAX_HASH_ROWS);
+        let mut sorted = distinct(rows, 0);
+        assert_eq!(1000, hashed.len());
+        sorted.sort_by_key(|row| match row[0] {
+            Value::Integer(i) => i,
+            _ => 0,
+        });
+        assert_eq!(hashed, sorted);
+    }
+
+    #[test]
+    fn test_sample_executor_fraction() -> Result<()> {
+        assert_eq!(1000, sample(1000, 1.0, None)?.len());
+        assert!(sample(1000, 0.0, None)?.is_empty());
+
+        let sampled = sample(100_000, 0.3, Some(1))?.len() as f64 / 100_000.0;
+        assert!((sampled - 0.3).abs() < 0.05, "sampled fraction {}", sampled);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sample_executor_repeatable() -> Result<()> {
+        assert_eq!(sample(1000, 0.5, Some(7))?, sample(1000, 0.5, Some(7))?);
+        assert_ne!(sample(1000, 0.5, Some(7))?, sample(1000, 0.5, Some(8))?);
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::sql::engine::SqlTxn;
-use crate::sql::schema::Table;
+use crate::sql::extension::FunctionRegistry;
+use crate::sql::parser::Parser;
+use crate::sql::plan::{Node, Plan};
+use crate::sql::schema::{Column, Constraint, Table};
+use crate::sql::types::{DataType, Expression};
+use super::mutation::SelectIntoExec;
 use super::{Executor, ResultSet};
 
 /// A CREATE TABLE executor
@@ -35,7 +40,373 @@ impl DropTableExec {
 
 impl<T: SqlTxn> Executor<T> for DropTableExec {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if txn.assert_read_table(&self.table)?.is_view() {
+            return Err(Error::Value(format!(
+                "{} is a materialized view, use DROP MATERIALIZED VIEW",
+                self.table
+            )));
+        }
         txn.delete_table(&self.table)?;
         Ok(ResultSet::DropTable { name: self.table })
     }
-}
\ No newline at end of file
+}
+
+/// An ANALYZE executor, which computes and stores a table's statistics.
+pub struct AnalyzeExec {
+    table: String,
+}
+
+impl AnalyzeExec {
+    pub fn new(table: String) -> Box<Self> {
+        Box::new(Self { table })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for AnalyzeExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let stats = txn.analyze(&self.table)?;
+        Ok(ResultSet::Analyze { name: self.table, rows: stats.rows })
+    }
+}
+
+/// A LOAD EXTENSION executor, which loads a native extension into the process-wide function
+/// registry. This isn't transactional, and takes effect immediately.
+pub struct LoadExtensionExec {
+    path: String,
+}
+
+impl LoadExtensionExec {
+    pub fn new(path: String) -> Box<Self> {
+        Box::new(Self { path })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for LoadExtensionExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let name = FunctionRegistry::global().write().load(&self.path)?;
+        Ok(ResultSet::LoadExtension { name })
+    }
+}
+
+/// A DROP EXTENSION executor, which unregisters an extension's functions and unloads it.
+pub struct DropExtensionExec {
+    name: String,
+}
+
+impl DropExtensionExec {
+    pub fn new(name: String) -> Box<Self> {
+        Box::new(Self { name })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for DropExtensionExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        FunctionRegistry::global().write().unload(&self.name)?;
+        Ok(ResultSet::DropExtension { name: self.name })
+    }
+}
+
+/// A CREATE MATERIALIZED VIEW executor. The query rows are stored like SELECT INTO, and the
+/// query text is recorded in the table schema for later refreshes.
+pub struct CreateMaterializedViewExec {
+    name: String,
+    datatypes: Vec<Option<DataType>>,
+    source: Node,
+    query: String,
+}
+
+impl CreateMaterializedViewExec {
+    pub fn new(
+        name: String,
+        datatypes: Vec<Option<DataType>>,
+        source: Node,
+        query: String,
+    ) -> Box<Self> {
+        Box::new(Self { name, datatypes, source, query })
+    }
+}
+
+impl<T: SqlTxn + 'static> Executor<T> for CreateMaterializedViewExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let source = <dyn Executor<T>>::build(self.source);
+        SelectIntoExec::new(self.name.clone(), None, self.datatypes, source).execute(txn)?;
+        let mut table = txn.assert_read_table(&self.name)?;
+        table.view = Some(self.query);
+        txn.update_table(table)?;
+        Ok(ResultSet::CreateTable { name: self.name })
+    }
+}
+
+/// A DROP MATERIALIZED VIEW executor
+pub struct DropMaterializedViewExec {
+    view: String,
+}
+
+impl DropMaterializedViewExec {
+    pub fn new(view: String) -> Box<Self> {
+        Box::new(Self { view })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for DropMaterializedViewExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if !txn.assert_read_table(&self.view)?.is_view() {
+            return Err(Error::Value(format!("{} is not a materialized view", self.view)));
+        }
+        txn.delete_table(&self.view)?;
+        Ok(ResultSet::DropTable { name: self.view })
+    }
+}
+
+/// A REFRESH MATERIALIZED VIEW executor, which replaces the stored rows with the result of
+/// re-running the view's query. The query is planned against the current schemas, so it sees
+/// columns added or dropped since the view was created. Returns the number of rows stored.
+pub struct RefreshMaterializedViewExec {
+    view: String,
+}
+
+impl RefreshMaterializedViewExec {
+    pub fn new(view: String) -> Box<Self> {
+        Box::new(Self { view })
+    }
+}
+
+impl<T: SqlTxn + 'static> Executor<T> for RefreshMaterializedViewExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.assert_read_table(&self.view)?;
+        let query = match &table.view {
+            Some(query) => Parser::new(query).parse()?,
+            None => {
+                return Err(Error::Value(format!("{} is not a materialized view", self.view)))
+            }
+        };
+        let rows = match Plan::build(query, txn)?.optimize(txn)?.execute(txn)? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows?,
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+
+        let existing = txn.scan(&table.name, None)?.collect::<Result<Vec<_>>>()?;
+        for row in existing {
+            txn.delete(&table.name, &table.get_row_key(&row)?)?;
+        }
+        let count = rows.len() as u64;
+        for row in rows {
+            txn.create(&table.name, row)?;
+        }
+        Ok(ResultSet::Create { count })
+    }
+}
+
+/// An executor which adds a variant to an enum column. Existing rows are unaffected, since
+/// variants are only ever added.
+pub struct AddEnumValueExec {
+    table: String,
+    column: String,
+    value: String,
+}
+
+impl AddEnumValueExec {
+    pub fn new(table: String, column: String, value: String) -> Box<Self> {
+        Box::new(Self { table, column, value })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for AddEnumValueExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.assert_read_table(&self.table)?;
+        let index = table.get_column_index(&self.column)?;
+        match &mut table.columns[index].datatype {
+            DataType::Enum(variants) if variants.contains(&self.value) => {
+                return Err(Error::Value(format!(
+                    "Enum column {} already has variant '{}'",
+                    self.column, self.value
+                )))
+            }
+            DataType::Enum(variants) => variants.push(self.value),
+            datatype => {
+                return Err(Error::Value(format!(
+                    "Can't add variant to {} column {}",
+                    datatype, self.column
+                )))
+            }
+        }
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
+
+/// An executor which sets the next value of a SERIAL column's sequence. Like all sequence
+/// updates, this takes effect immediately and isn't undone if the transaction rolls back.
+pub struct ResetSequenceExec {
+    table: String,
+    column: String,
+    value: i64,
+}
+
+impl ResetSequenceExec {
+    pub fn new(table: String, column: String, value: i64) -> Box<Self> {
+        Box::new(Self { table, column, value })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for ResetSequenceExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        txn.reset_serial(&self.table, &self.column, self.value)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
+
+/// An executor which adds a named constraint to a table, after checking that the existing rows
+/// satisfy it.
+pub struct AddConstraintExec {
+    table: String,
+    name: String,
+    constraint: Constraint,
+}
+
+impl AddConstraintExec {
+    pub fn new(table: String, name: String, constraint: Constraint) -> Box<Self> {
+        Box::new(Self { table, name, constraint })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for AddConstraintExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.assert_read_table(&self.table)?;
+        if table.named_constraints.iter().any(|(name, _)| name == &self.name) {
+            return Err(Error::Value(format!(
+                "Constraint {} already exists in table {}",
+                self.name, self.table
+            )));
+        }
+        self.constraint.validate(&self.name, &table, txn)?;
+        table.check_constraint(&self.name, &self.constraint, txn)?;
+        table.named_constraints.push((self.name, self.constraint));
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
+
+/// An executor which drops a named constraint from a table.
+pub struct DropConstraintExec {
+    table: String,
+    name: String,
+}
+
+impl DropConstraintExec {
+    pub fn new(table: String, name: String) -> Box<Self> {
+        Box::new(Self { table, name })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for DropConstraintExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.assert_read_table(&self.table)?;
+        let index = table
+            .named_constraints
+            .iter()
+            .position(|(name, _)| name == &self.name)
+            .ok_or_else(|| {
+                Error::Value(format!(
+                    "Constraint {} does not exist in table {}",
+                    self.name, self.table
+                ))
+            })?;
+        table.named_constraints.remove(index);
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
+
+/// An executor which adds a column to a table. Existing rows aren't rewritten, but take the
+/// column's default value when read.
+pub struct AddColumnExec {
+    table: String,
+    column: Column,
+}
+
+impl AddColumnExec {
+    pub fn new(table: String, column: Column) -> Box<Self> {
+        Box::new(Self { table, column })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for AddColumnExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.assert_read_table(&self.table)?;
+        if table.is_view() {
+            return Err(Error::Value(format!(
+                "Can't add column to materialized view {}",
+                self.table
+            )));
+        }
+        table.add_column(self.column)?;
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
+
+/// An executor which drops a column from a table. Its values remain in stored rows until they
+/// are rewritten, but are skipped when read.
+pub struct DropColumnExec {
+    table: String,
+    column: String,
+}
+
+impl DropColumnExec {
+    pub fn new(table: String, column: String) -> Box<Self> {
+        Box::new(Self { table, column })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for DropColumnExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.assert_read_table(&self.table)?;
+        if table.is_view() {
+            return Err(Error::Value(format!(
+                "Can't drop column from materialized view {}",
+                self.table
+            )));
+        }
+        table.drop_column(&self.column)?;
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
+
+/// A CREATE INDEX executor. Partial indexes only contain rows matching the predicate.
+pub struct CreateIndexExec {
+    table: String,
+    column: String,
+    predicate: Option<Expression>,
+}
+
+impl CreateIndexExec {
+    pub fn new(table: String, column: String, predicate: Option<Expression>) -> Box<Self> {
+        Box::new(Self { table, column, predicate })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for CreateIndexExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.assert_read_table(&self.table)?;
+        let index = table.get_column_index(&self.column)?;
+        let column = &mut table.columns[index];
+        if column.is_primary_key {
+            return Err(Error::Value(format!(
+                "Can't index primary key column {}",
+                self.column
+            )));
+        }
+        if column.is_indexed {
+            return Err(Error::Value(format!(
+                "Column {} in table {} is already indexed",
+                self.column, self.table
+            )));
+        }
+        column.is_indexed = true;
+        column.index_predicate = self.predicate;
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { name: self.table })
+    }
+}
@@ -1,6 +1,6 @@
 use crate::error::Result;
 use crate::sql::engine::SqlTxn;
-use crate::sql::types::{ResColumn, Expression, Row, Value};
+use crate::sql::types::{Columns, ResColumn, Expression, Row, Value};
 use super::{Executor, ResultSet};
 
 use std::collections::HashSet;
@@ -29,6 +29,39 @@ impl<T: SqlTxn> Executor<T> for Scan {
     }
 }
 
+/// An executor producing a single empty row, as the source of a SELECT without a FROM clause
+pub struct NothingExec;
+
+impl NothingExec {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for NothingExec {
+    fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+        Ok(ResultSet::Query { columns: Vec::new(), buffered_rows: Ok(vec![Row::new()]) })
+    }
+}
+
+/// An executor producing the given rows, such as the rows affected by a mutation
+pub struct ValuesExec {
+    columns: Columns,
+    rows: Vec<Row>,
+}
+
+impl ValuesExec {
+    pub fn new(columns: Columns, rows: Vec<Row>) -> Box<Self> {
+        Box::new(Self { columns, rows })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for ValuesExec {
+    fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+        Ok(ResultSet::Query { columns: self.columns, buffered_rows: Ok(self.rows) })
+    }
+}
+
 /// A primary key loop-up executor
 pub struct KeyLookupExec {
     table: String,
@@ -58,4 +91,39 @@ impl<T: SqlTxn> Executor<T> for KeyLookupExec {
             buffered_rows: rows,
         })
     }
-}
\ No newline at end of file
+}
+
+/// A secondary index lookup executor
+pub struct IndexLookupExec {
+    table: String,
+    column: String,
+    values: Vec<Value>,
+}
+
+impl IndexLookupExec {
+    pub fn new(table: String, column: String, values: Vec<Value>) -> Box<Self> {
+        Box::new(Self { table, column, values })
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for IndexLookupExec {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.assert_read_table(&self.table)?;
+
+        // Look up the keys in primary key order, since index entries are unordered sets.
+        let mut keys = Vec::new();
+        for value in &self.values {
+            keys.extend(txn.read_index(&table.name, &self.column, value)?);
+        }
+        keys.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let rows = keys
+            .into_iter()
+            .filter_map(|key| txn.read(&table.name, &key).transpose())
+            .collect::<Result<Vec<Row>>>();
+
+        Ok(ResultSet::Query {
+            columns: table.columns.iter().map(|c| ResColumn { name: Some(c.name.clone()) }).collect(),
+            buffered_rows: rows,
+        })
+    }
+}
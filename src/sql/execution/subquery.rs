@@ -0,0 +1,158 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::sql::engine::SqlTxn;
+use crate::sql::plan::Node;
+use crate::sql::types::{Columns, Expression, Row, Value};
+use super::{Executor, ResultSet};
+
+/// Executes a plan with its outer fields bound to the values of the given row, returning its
+/// columns and rows. Used for the right side of lateral joins, and for correlated subqueries.
+pub fn execute_bound<T: SqlTxn + 'static>(
+    node: Node,
+    txn: &mut T,
+    outer_row: &[Value],
+) -> Result<(Columns, Vec<Row>)> {
+    let node = node.transform(&Ok, &|n| {
+        n.transform_expressions(
+            &|e| match e {
+                Expression::Outer(i, _) => match outer_row.get(i) {
+                    Some(value) => Ok(Expression::Constant(value.clone())),
+                    None => Err(Error::Internal(format!("Outer field #{} not found", i))),
+                },
+                e => Ok(e),
+            },
+            &Ok,
+        )
+    })?;
+    match <dyn Executor<T>>::build(node).execute(txn)? {
+        ResultSet::Query { columns, buffered_rows } => Ok((columns, buffered_rows?)),
+        _ => Err(Error::Internal("Unexpected result set".into())),
+    }
+}
+
+/// Evaluates an expression containing subqueries. Before the expression is evaluated for a row,
+/// its subqueries are executed and replaced by constants holding their results. Uncorrelated
+/// subqueries, which don't reference outer fields, are only executed once and their rows cached,
+/// while correlated subqueries are executed for every row with the outer fields bound to it.
+pub struct SubqueryEvaluator {
+    expr: Expression,
+    has_subqueries: bool,
+    /// The rows of each subquery by its position in the expression, or None if correlated.
+    cache: HashMap<usize, Option<Vec<Row>>>,
+}
+
+impl SubqueryEvaluator {
+    pub fn new(expr: Expression) -> Self {
+        let has_subqueries = expr.contains_subquery();
+        Self { expr, has_subqueries, cache: HashMap::new() }
+    }
+
+    /// Evaluates the expression for a row.
+    pub fn evaluate<T: SqlTxn + 'static>(&mut self, txn: &mut T, row: &Row) -> Result<Value> {
+        if !self.has_subqueries {
+            return self.expr.evaluate(Some(row));
+        }
+        // The transform closures can't borrow mutably, and subqueries are visited in the same
+        // order for every row, so their position identifies them.
+        let txn = RefCell::new(txn);
+        let cache = RefCell::new(&mut self.cache);
+        let position = Cell::new(0);
+        let expr = self.expr.clone().transform(&Ok, &|e| {
+            // Scalar subqueries fetch a second row only to detect that there is more than one.
+            let (subquery, limit) = match &e {
+                Expression::ScalarSubquery(subquery) => (subquery, Some(2)),
+                Expression::InSubquery { subquery, .. } => (subquery, None),
+                Expression::ExistsSubquery(subquery) => (subquery, Some(1)),
+                _ => return Ok(e),
+            };
+            let index = position.replace(position.get() + 1);
+            let mut txn = txn.borrow_mut();
+            let mut cache = cache.borrow_mut();
+            if let Entry::Vacant(entry) = cache.entry(index) {
+                entry.insert(match Self::is_correlated(subquery)? {
+                    true => None,
+                    false => Some(Self::execute(subquery, limit, *txn, &[])?),
+                });
+            }
+            let correlated_rows;
+            let rows = match &cache[&index] {
+                Some(rows) => rows,
+                None => {
+                    correlated_rows = Self::execute(subquery, limit, *txn, row)?;
+                    &correlated_rows
+                }
+            };
+            Ok(Expression::Constant(match &e {
+                Expression::ScalarSubquery(_) => match rows.as_slice() {
+                    [] => Value::Null,
+                    [row] => row.first().cloned().unwrap_or(Value::Null),
+                    _ => return Err(Error::Value("Subquery returned more than one row".into())),
+                },
+                Expression::InSubquery { value, negated, .. } => {
+                    match (Self::contains(value.evaluate(Some(row))?, rows)?, negated) {
+                        (Value::Boolean(found), true) => Value::Boolean(!found),
+                        (result, _) => result,
+                    }
+                }
+                _ => Value::Boolean(!rows.is_empty()),
+            }))
+        })?;
+        expr.evaluate(Some(row))
+    }
+
+    /// Returns whether a subquery references outer fields. Nested subqueries can only reference
+    /// the subquery's own fields, and are not considered.
+    fn is_correlated(subquery: &Node) -> Result<bool> {
+        let correlated = Cell::new(false);
+        subquery.clone().transform(&Ok, &|n| {
+            n.transform_expressions(
+                &|e| {
+                    if let Expression::Outer(..) = e {
+                        correlated.set(true)
+                    }
+                    Ok(e)
+                },
+                &Ok,
+            )
+        })?;
+        Ok(correlated.get())
+    }
+
+    /// Executes a subquery for an outer row, returning at most limit rows.
+    fn execute<T: SqlTxn + 'static>(
+        subquery: &Node,
+        limit: Option<u64>,
+        txn: &mut T,
+        row: &[Value],
+    ) -> Result<Vec<Row>> {
+        let node = match limit {
+            Some(limit) => {
+                Node::Limit { source: Box::new(subquery.clone()), limit: Some(limit), offset: 0 }
+            }
+            None => subquery.clone(),
+        };
+        Ok(execute_bound(node, txn, row)?.1)
+    }
+
+    /// Returns whether the first column of any row equals the value: true if one does, NULL if
+    /// none does but a comparison was NULL, and false otherwise, as for ORed equalities.
+    fn contains(value: Value, rows: &[Row]) -> Result<Value> {
+        let mut result = Value::Boolean(false);
+        for row in rows {
+            let candidate = row.first().cloned().unwrap_or(Value::Null);
+            let equal = Expression::Equal(
+                Box::new(Expression::Constant(value.clone())),
+                Box::new(Expression::Constant(candidate)),
+            );
+            match equal.evaluate(None)? {
+                Value::Boolean(true) => return Ok(Value::Boolean(true)),
+                Value::Null => result = Value::Null,
+                _ => {}
+            }
+        }
+        Ok(result)
+    }
+}
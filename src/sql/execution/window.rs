@@ -0,0 +1,244 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::sql::engine::SqlTxn;
+use crate::sql::plan::{WindowFunc, WindowFunctionExpr};
+use crate::sql::types::{ResColumn, Row, Value};
+use super::query::compare_sort_keys;
+use super::{Executor, ResultSet};
+
+/// A window executor, which buffers the source rows and computes each window function over
+/// the rows of their partitions. Partitions are sorted stably by the function's order
+/// expressions, ordering values like OrderExec, and rows with equal order values are peers
+/// for RANK and DENSE_RANK. The source rows are emitted in their original order, each followed
+/// by its window function values.
+pub struct WindowExec<T: SqlTxn> {
+    source: Box<dyn Executor<T>>,
+    functions: Vec<WindowFunctionExpr>,
+}
+
+impl<T: SqlTxn> WindowExec<T> {
+    pub fn new(source: Box<dyn Executor<T>>, functions: Vec<WindowFunctionExpr>) -> Box<Self> {
+        Box::new(Self { source, functions })
+    }
+
+    /// Computes a window function for each row.
+    fn compute(function: &WindowFunctionExpr, rows: &[Row]) -> Result<Vec<Value>> {
+        // Group the rows into partitions, in order of first appearance, along with their sort
+        // keys.
+        let mut index: HashMap<Row, usize> = HashMap::new();
+        let mut partitions: Vec<Vec<(Row, usize)>> = Vec::new();
+        for (i, row) in rows.iter().enumerate() {
+            let key = function
+                .partition_by
+                .iter()
+                .map(|expr| expr.evaluate(Some(row)))
+                .collect::<Result<Row>>()?;
+            let keys = function
+                .order_by
+                .iter()
+                .map(|(expr, _)| expr.evaluate(Some(row)))
+                .collect::<Result<Row>>()?;
+            let p = *index.entry(key).or_insert_with(|| {
+                partitions.push(Vec::new());
+                partitions.len() - 1
+            });
+            partitions[p].push((keys, i));
+        }
+
+        let mut values = vec![Value::Null; rows.len()];
+        for mut partition in partitions {
+            partition.sort_by(|(a, _), (b, _)| compare_sort_keys(a, b, &function.order_by));
+            match &function.func {
+                WindowFunc::RowNumber => {
+                    for (n, (_, i)) in partition.iter().enumerate() {
+                        values[*i] = Value::Integer(n as i64 + 1);
+                    }
+                }
+                WindowFunc::Rank | WindowFunc::DenseRank => {
+                    let (mut rank, mut dense_rank) = (0, 0);
+                    for n in 0..partition.len() {
+                        let (keys, i) = &partition[n];
+                        if n == 0
+                            || compare_sort_keys(&partition[n - 1].0, keys, &function.order_by)
+                                != Ordering::Equal
+                        {
+                            rank = n as i64 + 1;
+                            dense_rank += 1;
+                        }
+                        values[*i] = Value::Integer(match function.func {
+                            WindowFunc::Rank => rank,
+                            _ => dense_rank,
+                        });
+                    }
+                }
+                WindowFunc::Lag(expr, offset) | WindowFunc::Lead(expr, offset) => {
+                    let offset = match function.func {
+                        WindowFunc::Lag(..) => offset.checked_neg(),
+                        _ => Some(*offset),
+                    };
+                    for (n, (_, i)) in partition.iter().enumerate() {
+                        let target = offset
+                            .and_then(|offset| (n as i64).checked_add(offset))
+                            .filter(|t| (0..partition.len() as i64).contains(t));
+                        values[*i] = match target {
+                            Some(t) => expr.evaluate(Some(&rows[partition[t as usize].1]))?,
+                            None => Value::Null,
+                        };
+                    }
+                }
+            }
+        }
+        Ok(values)
+    }
+}
+
+impl<T: SqlTxn> Executor<T> for WindowExec<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let (mut columns, mut rows) = match self.source.execute(txn)? {
+            ResultSet::Query { columns, buffered_rows } => (columns, buffered_rows?),
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+        let values = self
+            .functions
+            .iter()
+            .map(|function| Self::compute(function, &rows))
+            .collect::<Result<Vec<_>>>()?;
+        for function_values in values {
+            for (row, value) in rows.iter_mut().zip(function_values) {
+                row.push(value);
+            }
+        }
+        columns.extend(
+            self.functions
+                .iter()
+                .map(|function| ResColumn { name: Some(function.func.function_name().into()) }),
+        );
+        Ok(ResultSet::Query { columns, buffered_rows: Ok(rows) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::{Mode, MVCC};
+    use crate::sql::engine::{KvSqlEngine, SqlEngine};
+    use crate::sql::plan::SortOrder;
+    use crate::sql::types::Expression;
+    use crate::storage::kv::StdBPlusTree;
+    use super::*;
+
+    /// A source executor returning fixed rows.
+    struct RowsExec(Vec<Row>);
+
+    impl<T: SqlTxn> Executor<T> for RowsExec {
+        fn execute(self: Box<Self>, _: &mut T) -> Result<ResultSet> {
+            Ok(ResultSet::Query {
+                columns: vec![ResColumn { name: None }, ResColumn { name: None }],
+                buffered_rows: Ok(self.0),
+            })
+        }
+    }
+
+    fn field(i: usize) -> Expression {
+        Expression::Field(i, None)
+    }
+
+    /// Runs the window functions over (group, value) rows, returning the window values.
+    fn window(rows: Vec<(&str, i64)>, functions: Vec<WindowFunctionExpr>) -> Result<Vec<Row>> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let rows = rows
+            .into_iter()
+            .map(|(group, value)| vec![Value::String(group.into()), Value::Integer(value)])
+            .collect();
+        let exec = WindowExec::new(Box::new(RowsExec(rows)), functions);
+        match exec.execute(&mut txn)? {
+            ResultSet::Query { buffered_rows, .. } => {
+                Ok(buffered_rows?.into_iter().map(|row| row[2..].to_vec()).collect())
+            }
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+
+    /// A window function partitioned by group and ordered by value.
+    fn by_group(func: WindowFunc, order: SortOrder) -> WindowFunctionExpr {
+        WindowFunctionExpr { func, partition_by: vec![field(0)], order_by: vec![(field(1), order)] }
+    }
+
+    #[test]
+    fn partitions() -> Result<()> {
+        use Value::*;
+        let rows = vec![("a", 3), ("b", 5), ("a", 1), ("a", 3), ("b", 4), ("a", 7)];
+        let functions = vec![
+            by_group(WindowFunc::RowNumber, SortOrder::Asc),
+            by_group(WindowFunc::Rank, SortOrder::Asc),
+            by_group(WindowFunc::DenseRank, SortOrder::Asc),
+            by_group(WindowFunc::RowNumber, SortOrder::Desc),
+        ];
+        // Rows keep their source order, and peers keep theirs in the stable sort.
+        assert_eq!(
+            vec![
+                vec![Integer(2), Integer(2), Integer(2), Integer(2)],
+                vec![Integer(2), Integer(2), Integer(2), Integer(1)],
+                vec![Integer(1), Integer(1), Integer(1), Integer(4)],
+                vec![Integer(3), Integer(2), Integer(2), Integer(3)],
+                vec![Integer(1), Integer(1), Integer(1), Integer(2)],
+                vec![Integer(4), Integer(4), Integer(3), Integer(1)],
+            ],
+            window(rows.clone(), functions)?
+        );
+
+        // Without PARTITION BY all rows form one partition, and without ORDER BY all rows are
+        // peers.
+        let row_number = WindowFunctionExpr {
+            func: WindowFunc::RowNumber,
+            partition_by: vec![],
+            order_by: vec![(field(1), SortOrder::Asc)],
+        };
+        let rank =
+            WindowFunctionExpr { func: WindowFunc::Rank, partition_by: vec![], order_by: vec![] };
+        assert_eq!(
+            vec![
+                vec![Integer(2), Integer(1)],
+                vec![Integer(5), Integer(1)],
+                vec![Integer(1), Integer(1)],
+                vec![Integer(3), Integer(1)],
+                vec![Integer(4), Integer(1)],
+                vec![Integer(6), Integer(1)],
+            ],
+            window(rows, vec![row_number, rank])?
+        );
+        assert!(window(vec![], vec![by_group(WindowFunc::Rank, SortOrder::Asc)])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn lag_lead() -> Result<()> {
+        use Value::*;
+        let rows = vec![("a", 1), ("a", 2), ("b", 10), ("a", 3)];
+        let lag = |offset| by_group(WindowFunc::Lag(field(1).into(), offset), SortOrder::Asc);
+        let lead = |offset| by_group(WindowFunc::Lead(field(1).into(), offset), SortOrder::Asc);
+        assert_eq!(
+            vec![
+                vec![Null, Integer(2), Null, Integer(1)],
+                vec![Integer(1), Integer(3), Null, Integer(2)],
+                vec![Null, Null, Null, Integer(10)],
+                vec![Integer(2), Null, Integer(1), Integer(3)],
+            ],
+            window(rows.clone(), vec![lag(1), lead(1), lag(2), lag(0)])?
+        );
+
+        // Offsets past the partition yield NULL, and negative offsets look the other way.
+        assert_eq!(
+            vec![
+                vec![Null, Null, Integer(2), Null, Null],
+                vec![Null, Null, Integer(3), Integer(1), Null],
+                vec![Null, Null, Null, Null, Null],
+                vec![Null, Null, Null, Integer(2), Null],
+            ],
+            window(rows, vec![lag(3), lead(i64::MAX), lag(-1), lead(-1), lag(i64::MIN)])?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+//! Native extensions, i.e. dynamic libraries adding scalar functions callable from SQL.
+//!
+//! LOAD EXTENSION 'path' opens the library and calls its gymxdb_plugin_init symbol, which
+//! returns a PluginMeta describing the extension and its functions. The functions are
+//! registered in the process-wide FunctionRegistry, where the planner resolves calls to
+//! functions it doesn't know, until DROP EXTENSION name unregisters them and unloads the
+//! library. Extensions are local to the process executing the statement, and are not
+//! replicated to other Raft nodes.
+//!
+//! Opening libraries requires the extensions feature, which enables the libloading dependency.
+//! Without it, LOAD EXTENSION errors.
+//!
+//! The interface uses the C ABI, so plugins may be built with any compiler. Function arguments
+//! and results are passed as PluginValues, which support BOOLEAN, INTEGER and FLOAT values.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::ffi::{c_char, CStr};
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+
+use crate::error::{Error, Result};
+use super::types::{DataType, Value};
+
+/// The symbol an extension library exports its init function as.
+pub const PLUGIN_INIT_SYMBOL: &[u8] = b"gymxdb_plugin_init";
+
+/// An extension's init function. The returned metadata, and the strings and arrays it points
+/// to, must remain valid until the library is unloaded.
+pub type PluginInit = unsafe extern "C" fn() -> *const PluginMeta;
+
+/// An extension function, called with a pointer to its arguments and their number, which
+/// match the declared argument types.
+pub type PluginFn = unsafe extern "C" fn(args: *const PluginValue, len: usize) -> PluginValue;
+
+/// Metadata describing an extension, returned by its init function.
+#[repr(C)]
+pub struct PluginMeta {
+    /// The extension name, as given to DROP EXTENSION.
+    pub name: *const c_char,
+    pub version: *const c_char,
+    pub functions: *const PluginFunction,
+    pub functions_len: usize,
+}
+
+/// A function provided by an extension.
+#[repr(C)]
+pub struct PluginFunction {
+    /// The function name, as called from SQL. Unquoted SQL identifiers are lowercase.
+    pub name: *const c_char,
+    pub arg_types: *const PluginType,
+    pub arg_types_len: usize,
+    pub return_type: PluginType,
+    pub function: PluginFn,
+}
+
+/// The datatype of an extension function argument or result.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PluginType {
+    Boolean,
+    Integer,
+    Float,
+}
+
+impl From<PluginType> for DataType {
+    fn from(datatype: PluginType) -> Self {
+        match datatype {
+            PluginType::Boolean => Self::Boolean,
+            PluginType::Integer => Self::Integer,
+            PluginType::Float => Self::Float,
+        }
+    }
+}
+
+/// A value passed to or returned from an extension function. Only the field of its datatype is
+/// used, unless it is NULL.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PluginValue {
+    pub is_null: bool,
+    pub boolean: bool,
+    pub integer: i64,
+    pub float: f64,
+}
+
+/// A function registered by an extension.
+#[derive(Debug)]
+pub struct Function {
+    pub arg_types: Vec<DataType>,
+    pub return_type: DataType,
+    function: PluginFn,
+}
+
+/// A loaded extension and its registered functions.
+struct Extension {
+    functions: Vec<String>,
+    /// The library handle, which keeps the library loaded until the extension is dropped.
+    _library: Box<dyn Any + Send + Sync>,
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<FunctionRegistry> = RwLock::new(FunctionRegistry::default());
+}
+
+/// A registry of extension functions. Functions are called with the registry read-locked, so
+/// a library can't be unloaded while one of its functions is running.
+#[derive(Default)]
+pub struct FunctionRegistry {
+    extensions: HashMap<String, Extension>,
+    functions: HashMap<String, Function>,
+}
+
+impl FunctionRegistry {
+    /// Returns the process-wide registry.
+    pub fn global() -> &'static RwLock<FunctionRegistry> {
+        &REGISTRY
+    }
+
+    /// Fetches a registered function.
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// Calls a registered function with the given arguments. Integers are converted for FLOAT
+    /// arguments, and NULLs are passed as is.
+    pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let function = self
+            .functions
+            .get(name)
+            .ok_or_else(|| Error::Value(format!("Unknown function {}", name)))?;
+        if args.len() != function.arg_types.len() {
+            return Err(Error::Value(format!(
+                "Function {} takes {} arguments, given {}",
+                name,
+                function.arg_types.len(),
+                args.len()
+            )));
+        }
+        let args = args
+            .into_iter()
+            .zip(&function.arg_types)
+            .map(|(value, datatype)| {
+                Ok(match (value, datatype) {
+                    (Value::Null, _) => PluginValue { is_null: true, ..Default::default() },
+                    (Value::Boolean(b), DataType::Boolean) => {
+                        PluginValue { boolean: b, ..Default::default() }
+                    }
+                    (Value::Integer(i), DataType::Integer) => {
+                        PluginValue { integer: i, ..Default::default() }
+                    }
+                    (Value::Integer(i), DataType::Float) => {
+                        PluginValue { float: i as f64, ..Default::default() }
+                    }
+                    (Value::Float(f), DataType::Float) => {
+                        PluginValue { float: f, ..Default::default() }
+                    }
+                    (value, datatype) => {
+                        return Err(Error::Value(format!(
+                            "Function {} expects {} arguments, given {}",
+                            name,
+                            datatype,
+                            value.to_sql_literal()
+                        )))
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // SAFETY: the arguments match the declared types, and the library stays loaded while
+        // the registry is borrowed.
+        let result = unsafe { (function.function)(args.as_ptr(), args.len()) };
+        Ok(match function.return_type {
+            _ if result.is_null => Value::Null,
+            DataType::Boolean => Value::Boolean(result.boolean),
+            DataType::Integer => Value::Integer(result.integer),
+            _ => Value::Float(result.float),
+        })
+    }
+
+    /// Loads an extension library and registers its functions, returning the extension name.
+    #[cfg(feature = "extensions")]
+    pub fn load(&mut self, path: &str) -> Result<String> {
+        let error = |err: libloading::Error| {
+            Error::Value(format!("Can't load extension {}: {}", path, err))
+        };
+        // SAFETY: opening the library runs its initializers, and its init function is trusted
+        // to return valid metadata, like any native code the database is asked to run.
+        unsafe {
+            let library = libloading::Library::new(path).map_err(error)?;
+            let meta = library.get::<PluginInit>(PLUGIN_INIT_SYMBOL).map_err(error)?();
+            if meta.is_null() {
+                return Err(Error::Value(format!("Extension {} returned no metadata", path)));
+            }
+            self.register(&*meta, Box::new(library))
+        }
+    }
+
+    /// Loading extensions requires the extensions feature.
+    #[cfg(not(feature = "extensions"))]
+    pub fn load(&mut self, path: &str) -> Result<String> {
+        Err(Error::Value(format!(
+            "Can't load extension {}: built without the extensions feature",
+            path
+        )))
+    }
+
+    /// Registers an extension's functions, given its metadata and the library handle keeping
+    /// it loaded. Errors if the extension or any of its functions already exist.
+    ///
+    /// # Safety
+    ///
+    /// The metadata must be valid as described for PluginInit.
+    pub unsafe fn register(
+        &mut self,
+        meta: &PluginMeta,
+        library: Box<dyn Any + Send + Sync>,
+    ) -> Result<String> {
+        let string = |s: *const c_char| match s.is_null() {
+            true => Err(Error::Value("Extension metadata has a null string".into())),
+            false => Ok(CStr::from_ptr(s).to_string_lossy().into_owned()),
+        };
+        let name = string(meta.name)?;
+        if self.extensions.contains_key(&name) {
+            return Err(Error::Value(format!("Extension {} is already loaded", name)));
+        }
+        let mut functions = HashMap::new();
+        let descriptors = match meta.functions_len {
+            0 => &[][..],
+            len => std::slice::from_raw_parts(meta.functions, len),
+        };
+        for descriptor in descriptors {
+            let function_name = string(descriptor.name)?;
+            if self.functions.contains_key(&function_name)
+                || functions.contains_key(&function_name)
+            {
+                return Err(Error::Value(format!("Function {} already exists", function_name)));
+            }
+            let arg_types = match descriptor.arg_types_len {
+                0 => &[][..],
+                len => std::slice::from_raw_parts(descriptor.arg_types, len),
+            };
+            functions.insert(
+                function_name,
+                Function {
+                    arg_types: arg_types.iter().map(|t| (*t).into()).collect(),
+                    return_type: descriptor.return_type.into(),
+                    function: descriptor.function,
+                },
+            );
+        }
+        let extension =
+            Extension { functions: functions.keys().cloned().collect(), _library: library };
+        self.functions.extend(functions);
+        self.extensions.insert(name.clone(), extension);
+        Ok(name)
+    }
+
+    /// Unregisters an extension's functions and unloads its library.
+    pub fn unload(&mut self, name: &str) -> Result<()> {
+        let extension = self
+            .extensions
+            .remove(name)
+            .ok_or_else(|| Error::Value(format!("Extension {} does not exist", name)))?;
+        for function in &extension.functions {
+            self.functions.remove(function);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::MVCC;
+    use crate::sql::engine::{KvSqlEngine, SqlEngine as _};
+    use crate::storage::kv::StdBPlusTree;
+
+    unsafe extern "C" fn triple(args: *const PluginValue, len: usize) -> PluginValue {
+        let arg = std::slice::from_raw_parts(args, len)[0];
+        PluginValue { integer: arg.integer * 3, ..arg }
+    }
+
+    #[test]
+    fn register() -> Result<()> {
+        let mut registry = FunctionRegistry::default();
+        let arg_types = [PluginType::Integer];
+        let function = PluginFunction {
+            name: c"triple".as_ptr(),
+            arg_types: arg_types.as_ptr(),
+            arg_types_len: arg_types.len(),
+            return_type: PluginType::Integer,
+            function: triple,
+        };
+        let meta = PluginMeta {
+            name: c"triple_ext".as_ptr(),
+            version: c"0.1.0".as_ptr(),
+            functions: &function,
+            functions_len: 1,
+        };
+        assert_eq!("triple_ext", unsafe { registry.register(&meta, Box::new(())) }?);
+        assert_eq!(vec![DataType::Integer], registry.get("triple").unwrap().arg_types);
+
+        // Integers are passed as is, and NULLs as NULL.
+        assert_eq!(Value::Integer(21), registry.call("triple", vec![Value::Integer(7)])?);
+        assert_eq!(Value::Null, registry.call("triple", vec![Value::Null])?);
+        assert!(registry.call("triple", vec![Value::Float(1.0)]).is_err());
+        assert!(registry.call("triple", vec![]).is_err());
+
+        // Extensions and functions can't be registered twice.
+        assert!(unsafe { registry.register(&meta, Box::new(())) }.is_err());
+
+        // Unloading unregisters the functions.
+        registry.unload("triple_ext")?;
+        assert!(registry.get("triple").is_none());
+        assert!(registry.call("triple", vec![Value::Integer(7)]).is_err());
+        assert!(registry.unload("triple_ext").is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(not(feature = "extensions"))]
+    fn load_disabled() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let session = engine.session()?;
+        assert_eq!(
+            Err(Error::Value(
+                "Can't load extension /tmp/plugin.so: built without the extensions feature".into()
+            )),
+            session.execute("LOAD EXTENSION '/tmp/plugin.so'")
+        );
+        Ok(())
+    }
+
+    /// Compiles the double extension in tests/extension, loads it, and calls its function.
+    #[test]
+    #[cfg(feature = "extensions")]
+    fn load() -> Result<()> {
+        use crate::sql::execution::ResultSet;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("libdouble.so");
+        let status = std::process::Command::new(std::env::var("RUSTC").unwrap_or("rustc".into()))
+            .args(["--crate-type", "cdylib", "--edition", "2021", "-o"])
+            .arg(&path)
+            .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/extension/double.rs"))
+            .status()?;
+        assert!(status.success());
+
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let session = engine.session()?;
+        assert!(session.execute("SELECT \"double\"(21)").is_err());
+        assert_eq!(
+            ResultSet::LoadExtension { name: "double_ext".into() },
+            session.execute(&format!("LOAD EXTENSION '{}'", path.display()))?
+        );
+        assert_eq!(Value::Integer(42), session.execute("SELECT \"double\"(21)")?.into_value()?);
+        assert_eq!(Value::Null, session.execute("SELECT \"double\"(NULL)")?.into_value()?);
+        assert!(session.execute("SELECT \"double\"(1, 2)").is_err());
+        assert!(session.execute(&format!("LOAD EXTENSION '{}'", path.display())).is_err());
+
+        assert_eq!(
+            ResultSet::DropExtension { name: "double_ext".into() },
+            session.execute("DROP EXTENSION double_ext")?
+        );
+        assert!(session.execute("SELECT \"double\"(21)").is_err());
+        Ok(())
+    }
+}
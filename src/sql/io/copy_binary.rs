@@ -0,0 +1,343 @@
+//! A binary format for copying rows between databases.
+//!
+//! File:   Magic bytes "GYMXCOPY", u64 format version, then a length-prefixed header and a
+//!         sequence of length-prefixed rows until the end of the file.
+//! Header: The bincode-serialized column schemas, see CopyColumn.
+//! Row:    The concatenated encoding::encode_value() of each value.
+//!
+//! All lengths are u64 in big-endian form, like encoding::encode_u64(). Frames may be at most
+//! CopyBinaryFormat::MAX_FRAME_SIZE bytes long.
+
+use std::io::{ErrorKind, Read, Write};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::encoding;
+use crate::error::{Error, Result};
+use crate::sql::engine::{Mode, SqlEngine, SqlTxn};
+use crate::sql::execution::ResultSet;
+use crate::sql::schema::{Catalog, Table};
+use crate::sql::types::{DataType, Row};
+
+/// A copy schema column, describing the values stored in a column of the copied rows
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CopyColumn {
+    /// The column name, if any
+    pub name: Option<String>,
+    /// The column datatype, or None if unknown (e.g. all values are NULL)
+    pub datatype: Option<DataType>,
+}
+
+/// A binary copy format, holding the column schemas of the copied rows
+#[derive(Clone, Debug, PartialEq)]
+pub struct CopyBinaryFormat {
+    pub columns: Vec<CopyColumn>,
+}
+
+impl CopyBinaryFormat {
+    /// The magic bytes at the start of a copy file.
+    pub const MAGIC: &'static [u8; 8] = b"GYMXCOPY";
+    /// The current format version.
+    pub const VERSION: u64 = 1;
+    /// The maximum size of a header or row frame, to reject corrupt lengths.
+    pub const MAX_FRAME_SIZE: u64 = 64 << 20;
+
+    /// Creates a copy format for rows with the given columns.
+    pub fn new(columns: Vec<CopyColumn>) -> Self {
+        Self { columns }
+    }
+
+    /// Writes the file header.
+    pub fn write_header<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(Self::MAGIC)?;
+        writer.write_all(&encoding::encode_u64(Self::VERSION))?;
+        Self::write_frame(writer, &bincode::serialize(&self.columns)?)
+    }
+
+    /// Reads the file header.
+    pub fn read_header<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != Self::MAGIC {
+            return Err(Error::Value("Not a binary copy file".into()));
+        }
+        let mut version = [0; 8];
+        reader.read_exact(&mut version)?;
+        match encoding::decode_u64(version) {
+            Self::VERSION => {}
+            v => return Err(Error::Value(format!("Unsupported binary copy version {}", v))),
+        }
+        match Self::read_frame(reader)? {
+            Some(header) => Ok(Self::new(bincode::deserialize(&header)?)),
+            None => Err(Error::Value("Missing binary copy header".into())),
+        }
+    }
+
+    /// Writes a row.
+    pub fn write_row<W: Write>(&self, writer: &mut W, row: &Row) -> Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(Error::Value(format!(
+                "Row has {} columns, expected {}",
+                row.len(),
+                self.columns.len()
+            )));
+        }
+        Self::write_frame(writer, &row.iter().flat_map(encoding::encode_value).collect::<Vec<_>>())
+    }
+
+    /// Reads the next row, or None at the end of the file.
+    pub fn read_row<R: Read>(&self, reader: &mut R) -> Result<Option<Row>> {
+        let frame = match Self::read_frame(reader)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let mut bytes = &frame[..];
+        let mut row = Vec::with_capacity(self.columns.len());
+        while !bytes.is_empty() {
+            row.push(encoding::take_value(&mut bytes)?);
+        }
+        if row.len() != self.columns.len() {
+            return Err(Error::Value(format!(
+                "Row has {} columns, expected {}",
+                row.len(),
+                self.columns.len()
+            )));
+        }
+        Ok(Some(row))
+    }
+
+    /// Verifies that the copied rows can be loaded into the given table.
+    pub fn verify(&self, table: &Table) -> Result<()> {
+        if self.columns.len() != table.columns.len() {
+            return Err(Error::SchemaMismatch(format!(
+                "Copy has {} columns, but table {} has {}",
+                self.columns.len(),
+                table.name,
+                table.columns.len()
+            )));
+        }
+        for (source, target) in self.columns.iter().zip(table.columns.iter()) {
+            if let Some(name) = &source.name {
+                if name != &target.name {
+                    return Err(Error::SchemaMismatch(format!(
+                        "Copy column {} does not match column {} of table {}",
+                        name, target.name, table.name
+                    )));
+                }
+            }
+            match (&source.datatype, &target.datatype) {
+                (None, _) => {}
+                (Some(datatype), target_datatype)
+                    if datatype.value_type() == target_datatype.value_type() => {}
+                (Some(DataType::String), DataType::Enum(_)) => {}
+                (Some(DataType::Integer | DataType::Decimal(..)), DataType::Decimal(..)) => {}
+                (Some(datatype), target_datatype) => {
+                    return Err(Error::SchemaMismatch(format!(
+                        "Can't load {} values into {} column {} of table {}",
+                        datatype, target_datatype, target.name, table.name
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a length-prefixed frame.
+    fn write_frame<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<()> {
+        writer.write_all(&encoding::encode_u64(bytes.len() as u64))?;
+        writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    /// Reads a length-prefixed frame, or None if the reader is at the end of the file. The frame
+    /// is read incrementally, so a corrupt length can't allocate more than the file contains.
+    fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+        let mut length = [0; 8];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        let length = encoding::decode_u64(length);
+        if length > Self::MAX_FRAME_SIZE {
+            return Err(Error::Corruption(format!(
+                "Binary copy frame of {} bytes exceeds maximum of {}",
+                length,
+                Self::MAX_FRAME_SIZE
+            )));
+        }
+        let mut bytes = Vec::new();
+        reader.take(length).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != length {
+            return Err(Error::Corruption("Truncated binary copy frame".into()));
+        }
+        Ok(Some(bytes))
+    }
+}
+
+/// Runs a query and writes its result rows to the writer in the binary copy format. Column
+/// datatypes are taken from the first non-null value in each column. Returns the number of
+/// rows written.
+pub fn copy_binary_to<E, W>(engine: &E, query: &str, writer: &mut W) -> Result<u64>
+where
+    E: SqlEngine + 'static,
+    W: Write,
+{
+    let (columns, rows) = match engine.session()?.execute(query)? {
+        ResultSet::Query { columns, buffered_rows } => (columns, buffered_rows?),
+        result => return Err(Error::Value(format!("Not a query result: {:?}", result))),
+    };
+    let format = CopyBinaryFormat::new(
+        columns
+            .into_iter()
+            .enumerate()
+            .map(|(i, column)| CopyColumn {
+                name: column.name,
+                datatype: rows.iter().find_map(|row| row.get(i).and_then(|v| v.datatype())),
+            })
+            .collect(),
+    );
+    format.write_header(writer)?;
+    for row in &rows {
+        format.write_row(writer, row)?;
+    }
+    writer.flush()?;
+    Ok(rows.len() as u64)
+}
+
+/// Loads rows in the binary copy format from the reader into an existing table, in a single
+/// transaction. The copy schema is verified against the table before any rows are loaded.
+/// Returns the number of rows loaded.
+pub fn copy_binary_from<E, R>(engine: &E, table: &str, reader: &mut R) -> Result<u64>
+where
+    E: SqlEngine,
+    R: Read,
+{
+    let format = CopyBinaryFormat::read_header(reader)?;
+    let mut txn = engine.begin(Mode::ReadWrite)?;
+    let result = (|| {
+        format.verify(&txn.assert_read_table(table)?)?;
+        let mut count = 0;
+        while let Some(row) = format.read_row(reader)? {
+            txn.create(table, row)?;
+            count += 1;
+        }
+        Ok(count)
+    })();
+    match result {
+        Ok(count) => {
+            txn.commit()?;
+            Ok(count)
+        }
+        Err(err) => {
+            txn.rollback()?;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::MVCC;
+    use crate::sql::engine::KvSqlEngine;
+    use crate::sql::types::Value;
+    use crate::storage::kv::StdBPlusTree;
+
+    const SCHEMA: &str = "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING, value FLOAT)";
+
+    fn setup(rows: i64) -> Result<KvSqlEngine> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        engine.session()?.execute(SCHEMA)?;
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        for i in 0..rows {
+            let name = if i % 7 == 0 { Value::Null } else { Value::String(format!("name{}", i)) };
+            txn.create("test", vec![Value::Integer(i), name, Value::Float(i as f64 / 4.0)])?;
+        }
+        txn.commit()?;
+        Ok(engine)
+    }
+
+    /// Returns the row count and the sums of the id and value columns of the test table.
+    fn checksum(engine: &KvSqlEngine) -> Result<(u64, i64, f64)> {
+        let rows = match engine.session()?.execute("SELECT * FROM test")? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows?,
+            r => return Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        };
+        let mut checksum = (0, 0, 0.0);
+        for row in rows {
+            match (&row[0], &row[2]) {
+                (Value::Integer(id), Value::Float(value)) => {
+                    checksum = (checksum.0 + 1, checksum.1 + id, checksum.2 + value)
+                }
+                _ => return Err(Error::Internal(format!("Unexpected row {:?}", row))),
+            }
+        }
+        Ok(checksum)
+    }
+
+    #[test]
+    fn test_copy_binary_roundtrip() -> Result<()> {
+        let source = setup(50_000)?;
+        let mut buffer = Vec::new();
+        assert_eq!(50_000, copy_binary_to(&source, "SELECT * FROM test", &mut buffer)?);
+
+        let target = setup(0)?;
+        assert_eq!(50_000, copy_binary_from(&target, "test", &mut &buffer[..])?);
+        assert_eq!((50_000, 1_249_975_000, 312_493_750.0), checksum(&target)?);
+        assert_eq!(checksum(&source)?, checksum(&target)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_binary_schema_mismatch() -> Result<()> {
+        let source = setup(10)?;
+        let target = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        target.session()?.execute("CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING, value INTEGER)")?;
+
+        let mut buffer = Vec::new();
+        copy_binary_to(&source, "SELECT * FROM test", &mut buffer)?;
+        assert!(matches!(
+            copy_binary_from(&target, "test", &mut &buffer[..]),
+            Err(Error::SchemaMismatch(_))
+        ));
+
+        buffer.clear();
+        copy_binary_to(&source, "SELECT id, name FROM test", &mut buffer)?;
+        assert!(matches!(
+            copy_binary_from(&target, "test", &mut &buffer[..]),
+            Err(Error::SchemaMismatch(_))
+        ));
+        assert_eq!(0, target.begin(Mode::ReadOnly)?.scan("test", None)?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_binary_corrupt_frame() -> Result<()> {
+        let source = setup(10)?;
+        let mut buffer = Vec::new();
+        copy_binary_to(&source, "SELECT * FROM test", &mut buffer)?;
+        let header_len = {
+            let mut reader = &buffer[..];
+            CopyBinaryFormat::read_header(&mut reader)?;
+            buffer.len() - reader.len()
+        };
+
+        // A row frame claiming a huge length is rejected without allocating it.
+        let mut corrupt = buffer[..header_len].to_vec();
+        corrupt.extend(encoding::encode_u64(u64::MAX));
+        let target = setup(0)?;
+        assert!(matches!(
+            copy_binary_from(&target, "test", &mut &corrupt[..]),
+            Err(Error::Corruption(_))
+        ));
+
+        // As is a frame cut short by the end of the file.
+        assert!(matches!(
+            copy_binary_from(&target, "test", &mut &buffer[..buffer.len() - 1]),
+            Err(Error::Corruption(_))
+        ));
+        assert_eq!(0, target.begin(Mode::ReadOnly)?.scan("test", None)?.count());
+        Ok(())
+    }
+}
@@ -0,0 +1,473 @@
+//! CSV import and export of table rows.
+//!
+//! Records are separated by \n or \r\n, and fields by the delimiter. Fields containing the
+//! delimiter, quotes or line breaks are quoted with ", doubling any quotes inside them. NULL is
+//! written as the null string, unquoted; a quoted field is never NULL, so strings equal to the
+//! null string are quoted. Values are written with Display, and parsed by casting the field to
+//! the column datatype, as in CAST('field' AS datatype).
+
+use std::io::{Read, Write};
+
+use crate::error::{Error, Result};
+use crate::sql::engine::{Mode, SqlEngine, SqlTxn};
+use crate::sql::schema::{Catalog, Table};
+use crate::sql::types::{Row, Rows, Value};
+
+/// Imports CSV rows into an existing table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvImporter {
+    /// The table to import into.
+    pub table: String,
+    /// The field delimiter.
+    pub delimiter: char,
+    /// Whether the first record names the columns of the following ones. Columns missing from
+    /// the header take their default value. Without a header, records hold all columns in
+    /// table order.
+    pub has_header: bool,
+    /// The unquoted field representing NULL.
+    pub null_string: String,
+}
+
+impl CsvImporter {
+    /// Creates an importer for comma-separated records with a header and empty NULLs.
+    pub fn new(table: &str) -> Self {
+        Self { table: table.into(), delimiter: ',', has_header: true, null_string: String::new() }
+    }
+
+    /// Imports CSV records from the reader into the table, in a single transaction. Returns
+    /// the number of rows imported.
+    pub fn import<E: SqlEngine, R: Read>(&self, mut reader: R, engine: &E) -> Result<u64> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let input = std::str::from_utf8(&bytes).map_err(|err| {
+            let line = bytes[..err.valid_up_to()].iter().filter(|b| **b == b'\n').count() + 1;
+            Error::Parse(format!("Invalid UTF-8 in CSV on line {}", line))
+        })?;
+        let mut records = CsvParser::new(input, self.delimiter);
+
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let result = (|| {
+            let table = txn.assert_read_table(&self.table)?;
+            let columns = match self.has_header {
+                true => match records.next().transpose()? {
+                    Some((_, header)) => self.map_header(&table, header)?,
+                    None => return Ok(0),
+                },
+                false => (0..table.columns.len()).map(Some).collect(),
+            };
+            let mut count = 0;
+            while let Some((line, record)) = records.next().transpose()? {
+                let row = self.build_row(&table, &columns, record).map_err(|err| match err {
+                    Error::Value(msg) => Error::Value(format!("{} on line {}", msg, line)),
+                    err => err,
+                })?;
+                txn.create(&table.name, row)?;
+                count += 1;
+            }
+            Ok(count)
+        })();
+        match result {
+            Ok(count) => {
+                txn.commit()?;
+                Ok(count)
+            }
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Maps each table column to its field index in the records, given the header.
+    fn map_header(&self, table: &Table, header: Vec<Field>) -> Result<Vec<Option<usize>>> {
+        let mut columns = vec![None; table.columns.len()];
+        for (i, field) in header.into_iter().enumerate() {
+            let column = table.get_column_index(&field.text).map_err(|_| {
+                Error::SchemaMismatch(format!(
+                    "CSV column {} does not exist in table {}",
+                    field.text, table.name
+                ))
+            })?;
+            if columns[column].replace(i).is_some() {
+                return Err(Error::SchemaMismatch(format!(
+                    "CSV column {} given multiple times",
+                    field.text
+                )));
+            }
+        }
+        Ok(columns)
+    }
+
+    /// Builds a table row from a record.
+    fn build_row(
+        &self,
+        table: &Table,
+        columns: &[Option<usize>],
+        record: Vec<Field>,
+    ) -> Result<Row> {
+        let width = columns.iter().flatten().count();
+        if record.len() != width {
+            return Err(Error::Value(format!(
+                "Record has {} fields, expected {}",
+                record.len(),
+                width
+            )));
+        }
+        let mut record: Vec<Option<Field>> = record.into_iter().map(Some).collect();
+        table
+            .columns
+            .iter()
+            .zip(columns)
+            .map(|(column, field)| match field.and_then(|i| record[i].take()) {
+                Some(Field { text, quoted: false }) if text == self.null_string => Ok(Value::Null),
+                Some(Field { text, .. }) => column.datatype.cast(Value::String(text)),
+                None => column.omitted_value(),
+            })
+            .collect()
+    }
+}
+
+/// Exports rows as CSV.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvExporter {
+    /// The field delimiter.
+    pub delimiter: char,
+    /// Whether to write a header record with the column names.
+    pub has_header: bool,
+    /// The unquoted field representing NULL.
+    pub null_string: String,
+}
+
+impl Default for CsvExporter {
+    fn default() -> Self {
+        Self { delimiter: ',', has_header: true, null_string: String::new() }
+    }
+}
+
+impl CsvExporter {
+    /// Writes rows of a table to the writer as CSV, after a header if enabled. Returns the
+    /// number of rows written.
+    pub fn export<W: Write>(&self, rows: Rows, table: &Table, mut writer: W) -> Result<u64> {
+        if self.has_header {
+            let header = table.columns.iter().map(|c| self.quote(&c.name, false)).collect();
+            self.write_record(&mut writer, header)?;
+        }
+        let mut count = 0;
+        for row in rows {
+            let row = row?;
+            if row.len() != table.columns.len() {
+                return Err(Error::Value(format!(
+                    "Row has {} columns, expected {}",
+                    row.len(),
+                    table.columns.len()
+                )));
+            }
+            let record = row
+                .iter()
+                .map(|value| match value {
+                    Value::Null => self.null_string.clone(),
+                    value => self.quote(&value.to_string(), true),
+                })
+                .collect();
+            self.write_record(&mut writer, record)?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Quotes a field if necessary. Value fields equal to the null string are also quoted,
+    /// to tell them apart from NULL.
+    fn quote(&self, text: &str, is_value: bool) -> String {
+        let special = |c: char| c == self.delimiter || c == '"' || c == '\n' || c == '\r';
+        if text.contains(special) || (is_value && text == self.null_string) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Writes a record of quoted fields.
+    fn write_record<W: Write>(&self, writer: &mut W, fields: Vec<String>) -> Result<()> {
+        writer.write_all(fields.join(&self.delimiter.to_string()).as_bytes())?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// A parsed CSV field.
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+/// Parses CSV records, yielding each with its line number.
+struct CsvParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    delimiter: char,
+    line: usize,
+}
+
+impl<'a> CsvParser<'a> {
+    fn new(input: &'a str, delimiter: char) -> Self {
+        Self { chars: input.chars().peekable(), delimiter, line: 1 }
+    }
+
+    /// Consumes a line break if next, returning true if it did.
+    fn next_line_break(&mut self) -> bool {
+        match self.chars.peek() {
+            Some('\n') => {
+                self.chars.next();
+            }
+            Some('\r') => {
+                self.chars.next();
+                self.chars.next_if_eq(&'\n');
+            }
+            _ => return false,
+        }
+        self.line += 1;
+        true
+    }
+
+    /// Parses a field, up to the following delimiter or line break.
+    fn parse_field(&mut self) -> Result<Field> {
+        if self.chars.next_if_eq(&'"').is_none() {
+            let mut text = String::new();
+            let delimiter = self.delimiter;
+            let unquoted = |c: &char| *c != delimiter && *c != '\n' && *c != '\r';
+            while let Some(c) = self.chars.next_if(unquoted) {
+                if c == '"' {
+                    let line = self.line;
+                    return Err(Error::Parse(format!("Unexpected quote in CSV on line {}", line)));
+                }
+                text.push(c);
+            }
+            return Ok(Field { text, quoted: false });
+        }
+        let start = self.line;
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') if self.chars.next_if_eq(&'"').is_some() => text.push('"'),
+                Some('"') => break,
+                Some(c) => {
+                    if c == '\n' || (c == '\r' && self.chars.peek() != Some(&'\n')) {
+                        self.line += 1;
+                    }
+                    text.push(c)
+                }
+                None => {
+                    return Err(Error::Parse(format!("Unterminated quote in CSV on line {}", start)))
+                }
+            }
+        }
+        match self.chars.peek() {
+            None | Some('\n' | '\r') => {}
+            Some(c) if *c == self.delimiter => {}
+            Some(_) => {
+                return Err(Error::Parse(format!(
+                    "Unexpected character after quoted field in CSV on line {}",
+                    self.line
+                )))
+            }
+        }
+        Ok(Field { text, quoted: true })
+    }
+
+    /// Parses a record.
+    fn parse_record(&mut self) -> Result<Vec<Field>> {
+        let mut record = vec![self.parse_field()?];
+        while self.chars.next_if_eq(&self.delimiter).is_some() {
+            record.push(self.parse_field()?);
+        }
+        self.next_line_break();
+        Ok(record)
+    }
+}
+
+impl<'a> Iterator for CsvParser<'a> {
+    type Item = Result<(usize, Vec<Field>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Blank lines are skipped.
+        while self.next_line_break() {}
+        self.chars.peek()?;
+        let line = self.line;
+        Some(self.parse_record().map(|record| (line, record)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::MVCC;
+    use crate::sql::engine::KvSqlEngine;
+    use crate::sql::execution::ResultSet;
+    use crate::sql::types::datetime;
+    use crate::storage::kv::StdBPlusTree;
+
+    const SCHEMA: &str = "CREATE TABLE test (
+        id INTEGER PRIMARY KEY,
+        name STRING,
+        score FLOAT,
+        price DECIMAL(10, 2),
+        active BOOLEAN,
+        created TIMESTAMP,
+        day DATE DEFAULT NULL
+    )";
+
+    fn setup() -> Result<KvSqlEngine> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        engine.session()?.execute(SCHEMA)?;
+        Ok(engine)
+    }
+
+    fn rows(engine: &KvSqlEngine) -> Result<Vec<Row>> {
+        match engine.session()?.execute("SELECT * FROM test")? {
+            ResultSet::Query { buffered_rows, .. } => buffered_rows,
+            r => Err(Error::Internal(format!("Unexpected result {:?}", r))),
+        }
+    }
+
+    fn export(engine: &KvSqlEngine, exporter: &CsvExporter) -> Result<String> {
+        let txn = engine.begin(Mode::ReadOnly)?;
+        let table = txn.assert_read_table("test")?;
+        let mut buffer = Vec::new();
+        exporter.export(txn.scan("test", None)?, &table, &mut buffer)?;
+        Ok(String::from_utf8(buffer).unwrap())
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let source = setup()?;
+        let mut txn = source.begin(Mode::ReadWrite)?;
+        for i in 0..10_000i64 {
+            let name = match i % 5 {
+                0 => Value::Null,
+                1 => Value::String(String::new()),
+                2 => Value::String(format!("a, \"quoted\"\nname {}", i)),
+                _ => Value::String(format!("name{}", i)),
+            };
+            txn.create(
+                "test",
+                vec![
+                    Value::Integer(i),
+                    name,
+                    if i % 7 == 0 { Value::Null } else { Value::Float(i as f64 / 3.0) },
+                    Value::Decimal(format!("{}.{:02}", i, i % 100).parse()?),
+                    Value::Boolean(i % 2 == 0),
+                    Value::Timestamp(i * 1_000_003),
+                    if i % 3 == 0 { Value::Null } else { Value::Date(i as i32 - 5000) },
+                ],
+            )?;
+        }
+        txn.commit()?;
+
+        for delimiter in [',', '\t'] {
+            let exporter = CsvExporter { delimiter, ..Default::default() };
+            let csv = export(&source, &exporter)?;
+            let target = setup()?;
+            let importer = CsvImporter { delimiter, ..CsvImporter::new("test") };
+            assert_eq!(10_000, importer.import(csv.as_bytes(), &target)?);
+            let (expect, actual) = (rows(&source)?, rows(&target)?);
+            assert_eq!(expect.len(), actual.len());
+            for (expect, actual) in expect.iter().zip(actual.iter()) {
+                assert_eq!(expect, actual);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn import() -> Result<()> {
+        let engine = setup()?;
+        let csv = "price,id,name,score,active,created\n\
+                   1.5,1,\"a,b\",2.5,true,2024-01-02 03:04:05\r\n\
+                   \n\
+                   NULL,2,\"say \"\"hi\"\"\",NULL,false,NULL\n\
+                   0,3,,3,TRUE,\"2024-01-02\"";
+        let importer = CsvImporter { null_string: "NULL".into(), ..CsvImporter::new("test") };
+        assert_eq!(3, importer.import(csv.as_bytes(), &engine)?);
+        let rows = rows(&engine)?;
+        assert_eq!(Value::String("a,b".into()), rows[0][1]);
+        assert_eq!(Value::Decimal("1.50".parse()?), rows[0][3]);
+        let created = datetime::parse_timestamp("2024-01-02T03:04:05Z")?;
+        assert_eq!(Value::Timestamp(created), rows[0][5]);
+        assert_eq!(Value::Null, rows[0][6]);
+        assert_eq!(Value::String("say \"hi\"".into()), rows[1][1]);
+        assert_eq!(vec![Value::Null; 2], vec![rows[1][2].clone(), rows[1][5].clone()]);
+        // With a NULL string, empty fields are empty strings.
+        assert_eq!(Value::String("".into()), rows[2][1]);
+        assert_eq!(Value::Timestamp(datetime::parse_timestamp("2024-01-02")?), rows[2][5]);
+        Ok(())
+    }
+
+    #[test]
+    fn import_errors() -> Result<()> {
+        let engine = setup()?;
+        let import = |csv: &[u8]| CsvImporter::new("test").import(csv, &engine);
+        let header = "id,name,score,price,active,created\n";
+        let check = |csv: &str, err: Error| assert_eq!(Err(err), import(csv.as_bytes()));
+
+        check(
+            "id,missing\n",
+            Error::SchemaMismatch("CSV column missing does not exist in table test".into()),
+        );
+        check("id,id\n", Error::SchemaMismatch("CSV column id given multiple times".into()));
+        check(
+            &format!("{}1,\"a,2,3,true,\n", header),
+            Error::Parse("Unterminated quote in CSV on line 2".into()),
+        );
+        check(
+            &format!("{}1,\"a\"b,2,3,true,\n", header),
+            Error::Parse("Unexpected character after quoted field in CSV on line 2".into()),
+        );
+        check(
+            &format!("{}1,a\"b,2,3,true,\n", header),
+            Error::Parse("Unexpected quote in CSV on line 2".into()),
+        );
+        check(
+            &format!("{}1,a,2,3,true,\n2,b,2\n", header),
+            Error::Value("Record has 3 fields, expected 6 on line 3".into()),
+        );
+        check(
+            &format!("{}1,a,x,3,true,\n", header),
+            Error::Value("Can't cast 'x' to FLOAT on line 2".into()),
+        );
+        check(
+            "name\na\n",
+            Error::Value("Column id not given and has no default value on line 2".into()),
+        );
+        assert_eq!(
+            Err(Error::Parse("Invalid UTF-8 in CSV on line 3".into())),
+            import(&[header.as_bytes(), b"1,a,2,3,true,\n2,\xff,2,3,true,\n"].concat())
+        );
+
+        // Failed imports don't load any rows.
+        assert!(rows(&engine)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn export_quoting() -> Result<()> {
+        let engine = setup()?;
+        engine.session()?.execute(
+            "INSERT INTO test VALUES (1, 'a,b', NULL, 1.5, TRUE, NULL, NULL), \
+             (2, '', 0.5, 2, FALSE, NULL, NULL), (3, 'NA', 1, 3, NULL, NULL, NULL)",
+        )?;
+        assert_eq!(
+            "id,name,score,price,active,created,day\n\
+             1,\"a,b\",,1.50,TRUE,,\n\
+             2,\"\",0.5,2.00,FALSE,,\n\
+             3,NA,1,3.00,,,\n",
+            export(&engine, &CsvExporter::default())?
+        );
+        let exporter = CsvExporter { delimiter: ';', has_header: false, null_string: "NA".into() };
+        assert_eq!(
+            "1;a,b;NA;1.50;TRUE;NA;NA\n2;;0.5;2.00;FALSE;NA;NA\n3;\"NA\";1;3.00;NA;NA;NA\n",
+            export(&engine, &exporter)?
+        );
+        Ok(())
+    }
+}
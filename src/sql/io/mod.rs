@@ -0,0 +1,5 @@
+// The SQL I/O module moves table data in and out of the database.
+mod copy_binary;
+pub use copy_binary::{copy_binary_from, copy_binary_to, CopyBinaryFormat, CopyColumn};
+mod csv;
+pub use csv::{CsvExporter, CsvImporter};
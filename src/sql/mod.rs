@@ -1,6 +1,9 @@
 pub mod engine;
 pub mod execution;
+pub mod extension;
+pub mod io;
 pub mod parser;
 pub mod plan;
 pub mod schema;
+pub mod transaction;
 pub mod types;
@@ -1,5 +1,7 @@
 use std::collections::BTreeMap;
 
+use crate::error::Result;
+use crate::sql::schema::FkAction;
 use crate::sql::types::DataType;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -10,19 +12,93 @@ pub enum Statement {
     },
     Commit,
     Rollback,
+    /// Explains the plan of a statement. When analyzed, the statement is also executed, and
+    /// the plan annotated with execution statistics.
+    Explain {
+        statement: Box<Statement>,
+        analyze: bool,
+    },
 
     CreateTable {
         name: String,
         columns: Vec<Column>,
+        /// Table constraints, and their names if given.
+        constraints: Vec<(Option<String>, Constraint)>,
     },
     DropTable(String),
+    /// Computes and stores a table's statistics.
+    Analyze(String),
+    /// Creates a materialized view, storing the query's rows in a table of the same name.
+    CreateMaterializedView {
+        name: String,
+        query: Box<Statement>,
+        /// The query's SQL text, stored for re-planning on refresh.
+        sql: String,
+    },
+    DropMaterializedView(String),
+    /// Replaces the rows of a materialized view by re-running its query.
+    RefreshMaterializedView(String),
+    /// Adds a variant to an enum column.
+    AddEnumValue {
+        table: String,
+        column: String,
+        value: String,
+    },
+    /// Sets the next value of a SERIAL column's sequence.
+    ResetSequence {
+        table: String,
+        column: String,
+        value: Expression,
+    },
+    /// Indexes a column, optionally only for rows matching a predicate.
+    CreateIndex {
+        table: String,
+        column: String,
+        predicate: Option<Expression>,
+    },
+    /// Adds a named constraint to a table.
+    AddConstraint {
+        table: String,
+        name: String,
+        constraint: Constraint,
+    },
+    /// Drops a named constraint from a table.
+    DropConstraint {
+        table: String,
+        name: String,
+    },
+    /// Adds a column to a table.
+    AddColumn {
+        table: String,
+        column: Column,
+    },
+    /// Drops a column from a table.
+    DropColumn {
+        table: String,
+        column: String,
+    },
+    /// Loads a native extension from a dynamic library path, see sql::extension.
+    LoadExtension(String),
+    /// Unloads a native extension by name.
+    DropExtension(String),
 
     Insert {
         table: String,
         columns: Option<Vec<String>>,
         values: Vec<Vec<Expression>>,
+        on_conflict: Option<OnConflict>,
+        returning: Option<Returning>,
+    },
+    /// Inserts the result rows of a query into an existing table.
+    InsertSelect {
+        table: String,
+        columns: Option<Vec<String>>,
+        query: Box<Statement>,
+        on_conflict: Option<OnConflict>,
+        returning: Option<Returning>,
     },
     Select {
+        distinct: bool,
         select: Vec<(Expression, Option<String>)>,
         from: Vec<FromItem>,
         r#where: Option<Expression>,
@@ -32,17 +108,59 @@ pub enum Statement {
         offset: Option<Expression>,
         limit: Option<Expression>,
     },
+    /// A SELECT query whose result rows are stored in a new table, keyed by the given column or
+    /// else the first one.
+    SelectInto {
+        table: String,
+        primary_key: Option<String>,
+        query: Box<Statement>,
+    },
     Update {
         table: String,
         set: BTreeMap<String, Expression>,
         r#where: Option<Expression>,
+        returning: Option<Returning>,
     },
     Delete {
         table: String,
         r#where: Option<Expression>,
+        returning: Option<Returning>,
+    },
+
+    /// A query preceded by common table expressions, visible to the query by name.
+    With {
+        ctes: Vec<(String, Statement)>,
+        query: Box<Statement>,
     },
 }
 
+/// A table constraint
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constraint {
+    Check(Expression),
+    Unique(String),
+    ForeignKey {
+        column: String,
+        references: String,
+        referenced_column: Option<String>,
+        on_delete: FkAction,
+        on_update: FkAction,
+    },
+}
+
+/// The RETURNING clause of a mutation, with the same expressions and labels as a SELECT clause.
+/// It is empty for RETURNING *.
+pub type Returning = Vec<(Expression, Option<String>)>;
+
+/// An INSERT conflict action
+#[derive(Clone, Debug, PartialEq)]
+pub enum OnConflict {
+    DoNothing,
+    /// Updates the conflicting row with the given column values, which may refer to the
+    /// would-be-inserted row as EXCLUDED.
+    DoUpdate(BTreeMap<String, Expression>),
+}
+
 /// A FROM item
 #[derive(Clone, Debug, PartialEq)]
 pub enum FromItem {
@@ -56,6 +174,17 @@ pub enum FromItem {
         r#type: JoinType,
         predicate: Option<Expression>,
     },
+    /// A subquery, which must be labelled by an alias.
+    Subquery {
+        query: Box<Statement>,
+        alias: String,
+    },
+    /// A Bernoulli sample of a table, including each row with the given percent probability.
+    Sample {
+        source: Box<FromItem>,
+        percent: Expression,
+        seed: Option<Expression>,
+    },
 }
 
 /// A JOIN type
@@ -65,6 +194,11 @@ pub enum JoinType {
     Inner,
     Left,
     Right,
+    /// A join whose right-hand subquery is evaluated once per left-hand row, and may refer to
+    /// the left-hand columns.
+    Lateral,
+    /// A lateral join which pads left-hand rows with NULLs when the subquery returns no rows.
+    LeftLateral,
 }
 
 /// A column
@@ -78,6 +212,9 @@ pub struct Column {
     pub is_unique: bool,
     pub is_indexed: bool,
     pub references: Option<String>,
+    pub on_delete: FkAction,
+    pub on_update: FkAction,
+    pub checks: Vec<Expression>,
 }
 
 /// Sort orders
@@ -94,7 +231,144 @@ pub enum Expression {
     Column(usize), // only used during plan building to break off expression subtrees
     Literal(Literal),
     Function(String, Vec<Expression>),
+    Window(Box<Window>),
     Operation(Operation),
+    Cast(Box<Expression>, DataType),
+    /// A scalar subquery, which must return a single column and at most one row.
+    Subquery(Box<Statement>),
+    /// Whether a subquery returns any rows.
+    Exists(Box<Statement>),
+    /// A numbered statement parameter, bound to a value when executing a prepared statement.
+    Parameter(u64),
+}
+
+impl Expression {
+    /// Transforms the expression tree by applying closures before and after descending.
+    pub fn transform<B, A>(mut self, before: &mut B, after: &mut A) -> Result<Self>
+    where
+        B: FnMut(Self) -> Result<Self>,
+        A: FnMut(Self) -> Result<Self>,
+    {
+        use Operation::*;
+        self = before(self)?;
+        let children: Vec<&mut Box<Expression>> = match &mut self {
+            Self::Operation(op) => match op {
+                And(lhs, rhs)
+                | Or(lhs, rhs)
+                | Equal(lhs, rhs)
+                | GreaterThan(lhs, rhs)
+                | GreaterThanOrEqual(lhs, rhs)
+                | LessThan(lhs, rhs)
+                | LessThanOrEqual(lhs, rhs)
+                | NotEqual(lhs, rhs)
+                | Add(lhs, rhs)
+                | Concat(lhs, rhs)
+                | Divide(lhs, rhs)
+                | Exponentiate(lhs, rhs)
+                | Modulo(lhs, rhs)
+                | Multiply(lhs, rhs)
+                | Subtract(lhs, rhs) => vec![lhs, rhs],
+                Not(expr) | IsNull(expr) | Assert(expr) | Factorial(expr) | Negate(expr) => {
+                    vec![expr]
+                }
+                Like { value, pattern, escape, .. } => {
+                    [value, pattern].into_iter().chain(escape.as_mut()).collect()
+                }
+                In { value, .. } => vec![value],
+            },
+            Self::Cast(expr, _) => vec![expr],
+            Self::Function(_, args) => {
+                for arg in args.iter_mut() {
+                    let expr = std::mem::replace(arg, Literal::Null.into());
+                    *arg = expr.transform(before, after)?;
+                }
+                vec![]
+            }
+            Self::Window(window) => {
+                let Window { args, partition_by, order, .. } = window.as_mut();
+                let exprs = args
+                    .iter_mut()
+                    .chain(partition_by)
+                    .chain(order.iter_mut().map(|(expr, _)| expr));
+                for expr in exprs {
+                    let e = std::mem::replace(expr, Literal::Null.into());
+                    *expr = e.transform(before, after)?;
+                }
+                vec![]
+            }
+            // Subqueries have their own scope, and are not descended into.
+            Self::Field(..)
+            | Self::Column(_)
+            | Self::Literal(_)
+            | Self::Subquery(_)
+            | Self::Exists(_)
+            | Self::Parameter(_) => vec![],
+        };
+        for child in children {
+            let expr = std::mem::replace(child.as_mut(), Literal::Null.into());
+            *child.as_mut() = expr.transform(before, after)?;
+        }
+        after(self)
+    }
+
+    /// Returns whether the expression tree contains an expression matching the predicate.
+    pub fn contains(&self, predicate: &impl Fn(&Expression) -> bool) -> bool {
+        use Operation::*;
+        predicate(self)
+            || match self {
+                Self::Operation(op) => match op {
+                    And(lhs, rhs)
+                    | Or(lhs, rhs)
+                    | Equal(lhs, rhs)
+                    | GreaterThan(lhs, rhs)
+                    | GreaterThanOrEqual(lhs, rhs)
+                    | LessThan(lhs, rhs)
+                    | LessThanOrEqual(lhs, rhs)
+                    | NotEqual(lhs, rhs)
+                    | Add(lhs, rhs)
+                    | Concat(lhs, rhs)
+                    | Divide(lhs, rhs)
+                    | Exponentiate(lhs, rhs)
+                    | Modulo(lhs, rhs)
+                    | Multiply(lhs, rhs)
+                    | Subtract(lhs, rhs) => lhs.contains(predicate) || rhs.contains(predicate),
+                    Not(expr) | IsNull(expr) | Assert(expr) | Factorial(expr) | Negate(expr) => {
+                        expr.contains(predicate)
+                    }
+                    Like { value, pattern, escape, .. } => {
+                        value.contains(predicate)
+                            || pattern.contains(predicate)
+                            || escape.iter().any(|e| e.contains(predicate))
+                    }
+                    In { value, .. } => value.contains(predicate),
+                },
+                Self::Cast(expr, _) => expr.contains(predicate),
+                Self::Function(_, args) => args.iter().any(|arg| arg.contains(predicate)),
+                Self::Window(window) => window.children().any(|expr| expr.contains(predicate)),
+                Self::Field(..)
+                | Self::Column(_)
+                | Self::Literal(_)
+                | Self::Subquery(_)
+                | Self::Exists(_)
+                | Self::Parameter(_) => false,
+            }
+    }
+}
+
+/// A window function call, computed over the rows of its partition in the given order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Window {
+    pub function: String,
+    pub args: Vec<Expression>,
+    pub partition_by: Vec<Expression>,
+    pub order: Vec<(Expression, Order)>,
+}
+
+impl Window {
+    /// Iterates over the argument, partition and order expressions.
+    pub fn children(&self) -> impl Iterator<Item = &Expression> {
+        self.args.iter().chain(&self.partition_by).chain(self.order.iter().map(|(expr, _)| expr))
+    }
 }
 
 impl From<Literal> for Expression {
@@ -148,5 +422,18 @@ pub enum Operation {
     Subtract(Box<Expression>, Box<Expression>),
 
     // String operators
-    Like(Box<Expression>, Box<Expression>),
+    Concat(Box<Expression>, Box<Expression>),
+    Like {
+        value: Box<Expression>,
+        pattern: Box<Expression>,
+        escape: Option<Box<Expression>>,
+        case_insensitive: bool,
+    },
+
+    // Subquery operators
+    In {
+        value: Box<Expression>,
+        subquery: Box<Statement>,
+        negated: bool,
+    },
 }
\ No newline at end of file
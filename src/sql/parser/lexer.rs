@@ -15,11 +15,14 @@ pub enum Token {
     Identifier(String),
     Keyword(Keyword),
     Symbol(Symbol),
+    /// A numbered statement parameter, e.g. $1.
+    Parameter(u64),
 }
 
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.write_str(match self {
+            Token::Parameter(n) => return write!(f, "${}", n),
             Token::Number(n) => n,
             Token::String(s) => s,
             Token::Identifier(s) => s,
@@ -38,28 +41,51 @@ impl From<Keyword> for Token {
 /// Lexer keywords
 #[derive(Clone, Debug, PartialEq)]
 pub enum Keyword {
+    Action,
+    Add,
+    Alter,
+    Analyze,
     And,
     As,
     Asc,
+    AutoIncrement,
     Begin,
+    Bernoulli,
     Bool,
     Boolean,
     By,
+    Cascade,
+    Cast,
     Char,
+    Check,
+    Column,
     Commit,
+    Conflict,
+    Constraint,
     Create,
     Cross,
+    Date,
+    Decimal,
     Default,
     Delete,
     Desc,
+    Distinct,
+    Do,
     Double,
     Drop,
+    Enum,
+    Escape,
+    Exists,
     Explain,
+    Extension,
     False,
     Float,
+    Foreign,
     From,
     Group,
     Having,
+    ILike,
+    In,
     Index,
     Infinity,
     Inner,
@@ -70,12 +96,18 @@ pub enum Keyword {
     Is,
     Join,
     Key,
+    Lateral,
     Left,
     Like,
     Limit,
+    Load,
+    Materialized,
     NaN,
+    No,
     Not,
+    Nothing,
     Null,
+    Numeric,
     Of,
     Offset,
     On,
@@ -83,53 +115,90 @@ pub enum Keyword {
     Or,
     Order,
     Outer,
+    Over,
+    Partition,
     Primary,
     Read,
     References,
+    Refresh,
+    Repeatable,
+    Reset,
+    Restrict,
+    Returning,
     Right,
     Rollback,
     Select,
+    Sequence,
+    Serial,
     Set,
     String,
     System,
     Table,
+    Tablesample,
     Text,
     Time,
+    Timestamp,
+    To,
     Transaction,
     True,
     Unique,
     Update,
     Values,
     Varchar,
+    View,
     Where,
+    With,
     Write,
 }
 
 impl Keyword {
     pub fn from_str(ident: &str) -> Option<Self> {
         Some(match ident.to_uppercase().as_ref() {
+            "ACTION" => Self::Action,
+            "ADD" => Self::Add,
+            "ALTER" => Self::Alter,
             "AS" => Self::As,
             "ASC" => Self::Asc,
+            "ANALYZE" => Self::Analyze,
+            "AUTO_INCREMENT" => Self::AutoIncrement,
             "AND" => Self::And,
             "BEGIN" => Self::Begin,
+            "BERNOULLI" => Self::Bernoulli,
             "BOOL" => Self::Bool,
             "BOOLEAN" => Self::Boolean,
             "BY" => Self::By,
+            "CASCADE" => Self::Cascade,
+            "CAST" => Self::Cast,
             "CHAR" => Self::Char,
+            "CHECK" => Self::Check,
+            "COLUMN" => Self::Column,
             "COMMIT" => Self::Commit,
+            "CONFLICT" => Self::Conflict,
+            "CONSTRAINT" => Self::Constraint,
             "CREATE" => Self::Create,
             "CROSS" => Self::Cross,
+            "DATE" => Self::Date,
+            "DECIMAL" => Self::Decimal,
             "DEFAULT" => Self::Default,
             "DELETE" => Self::Delete,
             "DESC" => Self::Desc,
+            "DISTINCT" => Self::Distinct,
+            "DO" => Self::Do,
             "DOUBLE" => Self::Double,
             "DROP" => Self::Drop,
+            "ENUM" => Self::Enum,
+            "ESCAPE" => Self::Escape,
+            "EXISTS" => Self::Exists,
             "EXPLAIN" => Self::Explain,
+            "EXTENSION" => Self::Extension,
             "FALSE" => Self::False,
             "FLOAT" => Self::Float,
+            "FOREIGN" => Self::Foreign,
             "FROM" => Self::From,
             "GROUP" => Self::Group,
             "HAVING" => Self::Having,
+            "ILIKE" => Self::ILike,
+            "IN" => Self::In,
             "INDEX" => Self::Index,
             "INFINITY" => Self::Infinity,
             "INNER" => Self::Inner,
@@ -140,12 +209,18 @@ impl Keyword {
             "IS" => Self::Is,
             "JOIN" => Self::Join,
             "KEY" => Self::Key,
+            "LATERAL" => Self::Lateral,
             "LEFT" => Self::Left,
             "LIKE" => Self::Like,
             "LIMIT" => Self::Limit,
+            "LOAD" => Self::Load,
+            "MATERIALIZED" => Self::Materialized,
             "NAN" => Self::NaN,
+            "NO" => Self::No,
             "NOT" => Self::Not,
+            "NOTHING" => Self::Nothing,
             "NULL" => Self::Null,
+            "NUMERIC" => Self::Numeric,
             "OF" => Self::Of,
             "OFFSET" => Self::Offset,
             "ON" => Self::On,
@@ -153,25 +228,39 @@ impl Keyword {
             "OR" => Self::Or,
             "ORDER" => Self::Order,
             "OUTER" => Self::Outer,
+            "OVER" => Self::Over,
+            "PARTITION" => Self::Partition,
             "PRIMARY" => Self::Primary,
             "READ" => Self::Read,
             "REFERENCES" => Self::References,
+            "REFRESH" => Self::Refresh,
+            "REPEATABLE" => Self::Repeatable,
+            "RESET" => Self::Reset,
+            "RESTRICT" => Self::Restrict,
+            "RETURNING" => Self::Returning,
             "RIGHT" => Self::Right,
             "ROLLBACK" => Self::Rollback,
             "SELECT" => Self::Select,
+            "SEQUENCE" => Self::Sequence,
+            "SERIAL" => Self::Serial,
             "SET" => Self::Set,
             "STRING" => Self::String,
             "SYSTEM" => Self::System,
             "TABLE" => Self::Table,
+            "TABLESAMPLE" => Self::Tablesample,
             "TEXT" => Self::Text,
             "TIME" => Self::Time,
+            "TIMESTAMP" => Self::Timestamp,
+            "TO" => Self::To,
             "TRANSACTION" => Self::Transaction,
             "TRUE" => Self::True,
             "UNIQUE" => Self::Unique,
             "UPDATE" => Self::Update,
             "VALUES" => Self::Values,
             "VARCHAR" => Self::Varchar,
+            "VIEW" => Self::View,
             "WHERE" => Self::Where,
+            "WITH" => Self::With,
             "WRITE" => Self::Write,
             _ => return None,
         })
@@ -179,28 +268,51 @@ impl Keyword {
 
     pub fn to_str(&self) -> &str {
         match self {
+            Self::Action => "ACTION",
+            Self::Add => "ADD",
+            Self::Alter => "ALTER",
             Self::As => "AS",
             Self::Asc => "ASC",
+            Self::Analyze => "ANALYZE",
+            Self::AutoIncrement => "AUTO_INCREMENT",
             Self::And => "AND",
             Self::Begin => "BEGIN",
+            Self::Bernoulli => "BERNOULLI",
             Self::Bool => "BOOL",
             Self::Boolean => "BOOLEAN",
             Self::By => "BY",
+            Self::Cascade => "CASCADE",
+            Self::Cast => "CAST",
             Self::Char => "CHAR",
+            Self::Check => "CHECK",
+            Self::Column => "COLUMN",
             Self::Commit => "COMMIT",
+            Self::Conflict => "CONFLICT",
+            Self::Constraint => "CONSTRAINT",
             Self::Create => "CREATE",
             Self::Cross => "CROSS",
+            Self::Date => "DATE",
+            Self::Decimal => "DECIMAL",
             Self::Default => "DEFAULT",
             Self::Delete => "DELETE",
             Self::Desc => "DESC",
+            Self::Distinct => "DISTINCT",
+            Self::Do => "DO",
             Self::Double => "DOUBLE",
             Self::Drop => "DROP",
+            Self::Enum => "ENUM",
+            Self::Escape => "ESCAPE",
+            Self::Exists => "EXISTS",
             Self::Explain => "EXPLAIN",
+            Self::Extension => "EXTENSION",
             Self::False => "FALSE",
             Self::Float => "FLOAT",
+            Self::Foreign => "FOREIGN",
             Self::From => "FROM",
             Self::Group => "GROUP",
             Self::Having => "HAVING",
+            Self::ILike => "ILIKE",
+            Self::In => "IN",
             Self::Index => "INDEX",
             Self::Infinity => "INFINITY",
             Self::Inner => "INNER",
@@ -211,12 +323,18 @@ impl Keyword {
             Self::Is => "IS",
             Self::Join => "JOIN",
             Self::Key => "KEY",
+            Self::Lateral => "LATERAL",
             Self::Left => "LEFT",
             Self::Like => "LIKE",
             Self::Limit => "LIMIT",
+            Self::Load => "LOAD",
+            Self::Materialized => "MATERIALIZED",
             Self::NaN => "NAN",
+            Self::No => "NO",
             Self::Not => "NOT",
+            Self::Nothing => "NOTHING",
             Self::Null => "NULL",
+            Self::Numeric => "NUMERIC",
             Self::Of => "OF",
             Self::Offset => "OFFSET",
             Self::On => "ON",
@@ -224,25 +342,39 @@ impl Keyword {
             Self::Outer => "OUTER",
             Self::Or => "OR",
             Self::Order => "ORDER",
+            Self::Over => "OVER",
+            Self::Partition => "PARTITION",
             Self::Primary => "PRIMARY",
             Self::Read => "READ",
             Self::References => "REFERENCES",
+            Self::Refresh => "REFRESH",
+            Self::Repeatable => "REPEATABLE",
+            Self::Reset => "RESET",
+            Self::Restrict => "RESTRICT",
+            Self::Returning => "RETURNING",
             Self::Right => "RIGHT",
             Self::Rollback => "ROLLBACK",
             Self::Select => "SELECT",
+            Self::Sequence => "SEQUENCE",
+            Self::Serial => "SERIAL",
             Self::Set => "SET",
             Self::String => "STRING",
             Self::System => "SYSTEM",
             Self::Table => "TABLE",
+            Self::Tablesample => "TABLESAMPLE",
             Self::Text => "TEXT",
             Self::Time => "TIME",
+            Self::Timestamp => "TIMESTAMP",
+            Self::To => "TO",
             Self::Transaction => "TRANSACTION",
             Self::True => "TRUE",
             Self::Unique => "UNIQUE",
             Self::Update => "UPDATE",
             Self::Values => "VALUES",
             Self::Varchar => "VARCHAR",
+            Self::View => "VIEW",
             Self::Where => "WHERE",
+            Self::With => "WITH",
             Self::Write => "WRITE",
         }
     }
@@ -271,6 +403,8 @@ pub enum Symbol {
     Caret,
     Percent,
     Exclamation,
+    Pipe,
+    Concat,
     NotEqual,
     Question,
     OpenParen,
@@ -296,6 +430,8 @@ impl Symbol {
             Self::Caret => "^",
             Self::Percent => "%",
             Self::Exclamation => "!",
+            Self::Pipe => "|",
+            Self::Concat => "||",
             Self::NotEqual => "!=",
             Self::Question => "?",
             Self::OpenParen => "(",
@@ -315,6 +451,10 @@ impl std::fmt::Display for Symbol {
 /// A lexer that tokenizes an input string as an iterator.
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
+    /// The byte offset of the next character.
+    position: usize,
+    /// The byte offset of the last scanned token, or of the end of input.
+    offset: usize,
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -332,7 +472,19 @@ impl<'a> Iterator for Lexer<'a> {
 impl<'a> Lexer<'a> {
     /// Creates a new lexer for the given input string.
     pub fn new(input: &'a str) -> Lexer<'a> {
-        Lexer { iter: input.chars().peekable() }
+        Lexer { iter: input.chars().peekable(), position: 0, offset: 0 }
+    }
+
+    /// Returns the byte offset of the last scanned token, for error reporting.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Grabs the next character, advancing the position.
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.iter.next()?;
+        self.position += c.len_utf8();
+        Some(c)
     }
 
     /// Consumes any whitespace characters.
@@ -346,7 +498,7 @@ impl<'a> Lexer<'a> {
         F: Fn(char) -> bool,
     {
         self.iter.peek().filter(|&&c| predicate(c))?;
-        self.iter.next()
+        self.next_char()
     }
 
     /// Grabs the next single-character token if the tokenizer function returns one.
@@ -355,7 +507,7 @@ impl<'a> Lexer<'a> {
         F: Fn(char) -> Option<Token>,
     {
         let token = self.iter.peek().and_then(|&c| tokenizer(c))?;
-        self.iter.next();
+        self.next_char();
         Some(token)
     }
 
@@ -374,9 +526,11 @@ impl<'a> Lexer<'a> {
     /// Scans the input for the next token if any, ignoring leading whitespace.
     fn scan(&mut self) -> Result<Option<Token>> {
         self.consume_whitespace();
+        self.offset = self.position;
         match self.iter.peek() {
             Some('\'') => self.scan_string(),
             Some('"') => self.scan_identifier_quoted(),
+            Some('$') => self.scan_parameter(),
             Some(c) if c.is_digit(10) => Ok(self.scan_number()),
             Some(c) if c.is_alphabetic() => Ok(self.scan_word()),
             Some(_) => Ok(self.scan_symbol()),
@@ -402,7 +556,7 @@ impl<'a> Lexer<'a> {
         }
         let mut identifier = String::new();
         loop {
-            match self.iter.next() {
+            match self.next_char() {
                 Some('"') if self.next_if(|c| c == '"').is_some() => identifier.push('"'),
                 Some('"') => break,
                 Some(c) => identifier.push(c),
@@ -412,6 +566,17 @@ impl<'a> Lexer<'a> {
         Ok(Some(Token::Identifier(identifier)))
     }
 
+    /// Scans the input for the next parameter token, if any, numbered from 1.
+    fn scan_parameter(&mut self) -> Result<Option<Token>> {
+        if self.next_if(|c| c == '$').is_none() {
+            return Ok(None);
+        }
+        match self.next_while(|c| c.is_ascii_digit()).map(|n| n.parse::<u64>()) {
+            Some(Ok(n)) if n > 0 => Ok(Some(Token::Parameter(n))),
+            _ => Err(Error::Parse("Expected parameter number after $".into())),
+        }
+    }
+
     /// Scans the input for the next number token, if any.
     fn scan_number(&mut self) -> Option<Token> {
         let mut number = self.next_while(|c| c.is_digit(10))?;
@@ -436,7 +601,7 @@ impl<'a> Lexer<'a> {
         }
         let mut str = String::new();
         loop {
-            match self.iter.next() {
+            match self.next_char() {
                 Some('\'') if self.next_if(|c| c == '\'').is_some() => str.push('\''),
                 Some('\'') => break,
                 Some(c) => str.push(c),
@@ -460,6 +625,7 @@ impl<'a> Lexer<'a> {
             '^' => Some(Token::Symbol(Symbol::Caret)),
             '%' => Some(Token::Symbol(Symbol::Percent)),
             '!' => Some(Token::Symbol(Symbol::Exclamation)),
+            '|' => Some(Token::Symbol(Symbol::Pipe)),
             '?' => Some(Token::Symbol(Symbol::Question)),
             '(' => Some(Token::Symbol(Symbol::OpenParen)),
             ')' => Some(Token::Symbol(Symbol::CloseParen)),
@@ -480,6 +646,9 @@ impl<'a> Lexer<'a> {
             Token::Symbol(Symbol::LessThan) if self.next_if(|c| c == '>').is_some() => {
                 Token::Symbol(Symbol::LessOrGreaterThan)
             },
+            Token::Symbol(Symbol::Pipe) if self.next_if(|c| c == '|').is_some() => {
+                Token::Symbol(Symbol::Concat)
+            },
             _ => token,
         })
     }
@@ -12,31 +12,48 @@ use std::collections::BTreeMap;
 pub use lexer::{Keyword, Symbol, Lexer, Token};
 
 use crate::error::{Result, Error};
-use super::types::DataType;
+use super::schema::FkAction;
+use super::types::{DataType, Decimal};
 
 
 /// An SQL parser
 pub struct Parser<'a> {
-    lexer: std::iter::Peekable<Lexer<'a>>,
+    /// The input string, for recording the text of view queries.
+    input: &'a str,
+    lexer: Lexer<'a>,
+    /// The next lexer result, if peeked.
+    peeked: Option<Option<Result<Token>>>,
 }
 
 impl<'a> Parser<'a> {
     /// Creates a new parser for the given string input.
     pub fn new(query: &str) -> Parser {
-        Parser { lexer: Lexer::new(query).peekable() }
+        Parser { input: query, lexer: Lexer::new(query), peeked: None }
     }
 
-    /// Parses an SQL query.
+    /// Parses an SQL query. Parse errors are annotated with the byte offset of the last token
+    /// read, which is usually the offending token, or of the end of input.
     pub fn parse(&mut self) -> Result<ast::Statement> {
-        let statement = self.parse_statement()?;
-        self.next_if_token(Token::Symbol(lexer::Symbol::Semicolon));
-        self.next_expect(None)?;
-        Ok(statement)
+        let result = self.parse_statement().and_then(|statement| {
+            self.next_if_token(Token::Symbol(lexer::Symbol::Semicolon));
+            self.next_expect(None)?;
+            Ok(statement)
+        });
+        result.map_err(|err| match err {
+            Error::Parse(message) => {
+                Error::Parse(format!("{} at byte {}", message, self.lexer.offset()))
+            }
+            err => err,
+        })
     }
 
     /// Grabs the next lexer token, or throws an error if none is found.
     fn next(&mut self) -> Result<Token> {
-        self.lexer.next().unwrap_or_else(|| Err(Error::Parse("Unexpected end of input".into())))
+        let next = match self.peeked.take() {
+            Some(next) => next,
+            None => self.lexer.next(),
+        };
+        next.unwrap_or_else(|| Err(Error::Parse("Unexpected end of input".into())))
     }
 
     /// Grabs the next lexer token, and returns it if it was expected or otherwise throws an error.
@@ -104,11 +121,23 @@ impl<'a> Parser<'a> {
         self.next_if(|t| *t == token)
     }
 
+    /// Peeks the next lexer token, and errors if it is not the expected token.
+    fn peek_expect(&mut self, expected: Token) -> Result<()> {
+        match self.peek()? {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(Error::Parse(format!("Expected token {}, found {}", expected, token))),
+            None => Err(Error::Parse("Unexpected end of input".into())),
+        }
+    }
+
     /// Peeks the next lexer token if any, but converts it from
     /// Option<Result<Token>> to Result<Option<Token>> which is
     /// more convenient to work with (the Iterator trait requires Option<T>).
     fn peek(&mut self) -> Result<Option<Token>> {
-        self.lexer.peek().cloned().transpose()
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next());
+        }
+        self.peeked.clone().flatten().transpose()
     }
 
     /// Parses an SQL statement.
@@ -120,13 +149,28 @@ impl<'a> Parser<'a> {
 
             Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Drop)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Alter)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Refresh)) => self.parse_ddl(),
 
             Some(Token::Keyword(Keyword::Insert)) => self.parse_statement_insert(),
             Some(Token::Keyword(Keyword::Select)) => self.parse_statement_select(),
             Some(Token::Keyword(Keyword::Update)) => self.parse_statement_update(),
             Some(Token::Keyword(Keyword::Delete)) => self.parse_statement_delete(),
+            Some(Token::Keyword(Keyword::With)) => self.parse_statement_with(),
 
             Some(Token::Keyword(Keyword::Explain)) => self.parse_statement_explain(),
+            Some(Token::Keyword(Keyword::Analyze)) => {
+                self.next()?;
+                Ok(ast::Statement::Analyze(self.next_identifier()?))
+            }
+            Some(Token::Keyword(Keyword::Load)) => {
+                self.next()?;
+                self.next_expect(Some(Keyword::Extension.into()))?;
+                match self.next()? {
+                    Token::String(path) => Ok(ast::Statement::LoadExtension(path)),
+                    token => Err(Error::Parse(format!("Expected extension path, got {}", token))),
+                }
+            }
 
             Some(token) => Err(Error::Parse(format!("Unexpected token {}", token))),
             None => Err(Error::Parse("Unexpected end of input".into())),
@@ -170,10 +214,28 @@ impl<'a> Parser<'a> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Materialized) => self.parse_ddl_create_materialized_view(),
+                Token::Keyword(Keyword::Index) => self.parse_ddl_create_index(),
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             Token::Keyword(Keyword::Drop) => match self.next()? {
                 Token::Keyword(Keyword::Table) => self.parse_ddl_drop_table(),
+                Token::Keyword(Keyword::Materialized) => {
+                    self.next_expect(Some(Keyword::View.into()))?;
+                    Ok(ast::Statement::DropMaterializedView(self.next_identifier()?))
+                }
+                Token::Keyword(Keyword::Extension) => {
+                    Ok(ast::Statement::DropExtension(self.next_identifier()?))
+                }
+                token => Err(Error::Parse(format!("Unexpected token {}", token))),
+            },
+            Token::Keyword(Keyword::Refresh) => {
+                self.next_expect(Some(Keyword::Materialized.into()))?;
+                self.next_expect(Some(Keyword::View.into()))?;
+                Ok(ast::Statement::RefreshMaterializedView(self.next_identifier()?))
+            }
+            Token::Keyword(Keyword::Alter) => match self.next()? {
+                Token::Keyword(Keyword::Table) => self.parse_ddl_alter_table(),
                 token => Err(Error::Parse(format!("Unexpected token {}", token))),
             },
             token => Err(Error::Parse(format!("Unexpected token {}", token))),
@@ -181,18 +243,65 @@ impl<'a> Parser<'a> {
     }
 
     /// Parses a CREATE TABLE DDL statement. The CREATE TABLE prefix has already been consumed.
+    /// Column specifications may be mixed with table constraints, optionally named as in
+    /// CONSTRAINT name CHECK (expr).
     fn parse_ddl_create_table(&mut self) -> Result<ast::Statement> {
         let name = self.next_identifier()?;
         self.next_expect(Some(Token::Symbol(lexer::Symbol::OpenParen)))?;
         let mut columns = vec![];
+        let mut constraints = vec![];
         loop {
-            columns.push(self.parse_ddl_columnspec()?);
+            match self.peek()? {
+                Some(Token::Keyword(Keyword::Constraint)) => {
+                    self.next()?;
+                    let name = self.next_identifier()?;
+                    constraints.push((Some(name), self.parse_ddl_constraint()?));
+                }
+                Some(Token::Keyword(Keyword::Check | Keyword::Unique | Keyword::Foreign)) => {
+                    constraints.push((None, self.parse_ddl_constraint()?));
+                }
+                _ => columns.push(self.parse_ddl_columnspec()?),
+            }
             if self.next_if_token(Token::Symbol(lexer::Symbol::Comma)).is_none() {
                 break;
             }
         }
         self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
-        Ok(ast::Statement::CreateTable { name, columns })
+        Ok(ast::Statement::CreateTable { name, columns, constraints })
+    }
+
+    /// Parses a CREATE MATERIALIZED VIEW DDL statement. The CREATE MATERIALIZED prefix has
+    /// already been consumed. The query is the rest of the statement, so its text runs to the
+    /// end of input.
+    fn parse_ddl_create_materialized_view(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::View.into()))?;
+        let name = self.next_identifier()?;
+        self.next_expect(Some(Keyword::As.into()))?;
+        match self.peek()? {
+            Some(Token::Keyword(Keyword::Select)) => {}
+            Some(token) => return Err(Error::Parse(format!("Unexpected token {}", token))),
+            None => return Err(Error::Parse("Unexpected end of input".into())),
+        }
+        let sql = self.input[self.lexer.offset()..].trim_end().trim_end_matches(';').trim_end();
+        Ok(ast::Statement::CreateMaterializedView {
+            name,
+            query: Box::new(self.parse_statement_select()?),
+            sql: sql.to_string(),
+        })
+    }
+
+    /// Parses a CREATE INDEX DDL statement, as CREATE INDEX [name] ON table (column) [WHERE
+    /// predicate]. The CREATE INDEX prefix has already been consumed. Indexes are identified by
+    /// their column, so the name is only accepted for compatibility.
+    fn parse_ddl_create_index(&mut self) -> Result<ast::Statement> {
+        if self.next_if_token(Keyword::On.into()).is_none() {
+            self.next_identifier()?;
+            self.next_expect(Some(Keyword::On.into()))?;
+        }
+        let table = self.next_identifier()?;
+        let column = self.parse_ddl_constraint_column()?;
+        let predicate = self.parse_clause_where()?;
+        Ok(ast::Statement::CreateIndex { table, column, predicate })
     }
 
     /// Parses a DROP TABLE DDL statement. The DROP TABLE prefix has already been consumed.
@@ -200,29 +309,222 @@ impl<'a> Parser<'a> {
         Ok(ast::Statement::DropTable(self.next_identifier()?))
     }
 
+    /// Parses an ALTER TABLE DDL statement. The ALTER TABLE prefix has already been consumed.
+    /// Supports adding enum variants, as ALTER TABLE table ALTER [COLUMN] column ADD 'variant',
+    /// resetting sequences, as ALTER TABLE table ALTER [COLUMN] column RESET SEQUENCE TO value,
+    /// adding or dropping named constraints, as ALTER TABLE table ADD CONSTRAINT name ... and
+    /// ALTER TABLE table DROP CONSTRAINT name, and adding or dropping columns, as ALTER TABLE
+    /// table ADD [COLUMN] columnspec and ALTER TABLE table DROP [COLUMN] column.
+    fn parse_ddl_alter_table(&mut self) -> Result<ast::Statement> {
+        let table = self.next_identifier()?;
+        match self.next()? {
+            Token::Keyword(Keyword::Alter) => {
+                self.next_if_token(Keyword::Column.into());
+                let column = self.next_identifier()?;
+                if self.next_if_token(Keyword::Reset.into()).is_some() {
+                    self.next_expect(Some(Keyword::Sequence.into()))?;
+                    self.next_expect(Some(Keyword::To.into()))?;
+                    let value = self.parse_expression(0)?;
+                    return Ok(ast::Statement::ResetSequence { table, column, value });
+                }
+                self.next_expect(Some(Keyword::Add.into()))?;
+                match self.next()? {
+                    Token::String(value) => Ok(ast::Statement::AddEnumValue { table, column, value }),
+                    token => Err(Error::Parse(format!("Expected string, found {}", token))),
+                }
+            }
+            Token::Keyword(Keyword::Add) => {
+                if self.next_if_token(Keyword::Constraint.into()).is_none() {
+                    self.next_if_token(Keyword::Column.into());
+                    let column = self.parse_ddl_columnspec()?;
+                    return Ok(ast::Statement::AddColumn { table, column });
+                }
+                let name = self.next_identifier()?;
+                let constraint = self.parse_ddl_constraint()?;
+                Ok(ast::Statement::AddConstraint { table, name, constraint })
+            }
+            Token::Keyword(Keyword::Drop) => {
+                if self.next_if_token(Keyword::Constraint.into()).is_none() {
+                    self.next_if_token(Keyword::Column.into());
+                    let column = self.next_identifier()?;
+                    return Ok(ast::Statement::DropColumn { table, column });
+                }
+                let name = self.next_identifier()?;
+                Ok(ast::Statement::DropConstraint { table, name })
+            }
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
+    /// Parses a table constraint, i.e. CHECK (expr), UNIQUE (column), or FOREIGN KEY (column)
+    /// REFERENCES table [(column)] [ON DELETE action] [ON UPDATE action].
+    fn parse_ddl_constraint(&mut self) -> Result<ast::Constraint> {
+        match self.next()? {
+            Token::Keyword(Keyword::Check) => Ok(ast::Constraint::Check(self.parse_ddl_check()?)),
+            Token::Keyword(Keyword::Unique) => {
+                Ok(ast::Constraint::Unique(self.parse_ddl_constraint_column()?))
+            }
+            Token::Keyword(Keyword::Foreign) => {
+                self.next_expect(Some(Keyword::Key.into()))?;
+                let column = self.parse_ddl_constraint_column()?;
+                self.next_expect(Some(Keyword::References.into()))?;
+                let references = self.next_identifier()?;
+                let referenced_column = match self.peek()? {
+                    Some(Token::Symbol(lexer::Symbol::OpenParen)) => {
+                        Some(self.parse_ddl_constraint_column()?)
+                    }
+                    _ => None,
+                };
+                let (on_delete, on_update) = self.parse_ddl_references_actions()?;
+                Ok(ast::Constraint::ForeignKey {
+                    column,
+                    references,
+                    referenced_column,
+                    on_delete,
+                    on_update,
+                })
+            }
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
+    }
+
+    /// Parses the ON DELETE and ON UPDATE actions of a foreign key, in any order. Omitted
+    /// actions are NO ACTION.
+    fn parse_ddl_references_actions(&mut self) -> Result<(FkAction, FkAction)> {
+        let (mut on_delete, mut on_update) = (FkAction::NoAction, FkAction::NoAction);
+        while self.next_if_token(Keyword::On.into()).is_some() {
+            let action = match self.next()? {
+                Token::Keyword(Keyword::Delete) => &mut on_delete,
+                Token::Keyword(Keyword::Update) => &mut on_update,
+                token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+            };
+            *action = match self.next()? {
+                Token::Keyword(Keyword::Cascade) => FkAction::Cascade,
+                Token::Keyword(Keyword::Restrict) => FkAction::Restrict,
+                Token::Keyword(Keyword::No) => {
+                    self.next_expect(Some(Keyword::Action.into()))?;
+                    FkAction::NoAction
+                }
+                Token::Keyword(Keyword::Set) => match self.next()? {
+                    Token::Keyword(Keyword::Null) => FkAction::SetNull,
+                    Token::Keyword(Keyword::Default) => FkAction::SetDefault,
+                    token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+                },
+                token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+            };
+        }
+        Ok((on_delete, on_update))
+    }
+
+    /// Parses a parenthesized check expression, e.g. (value > 0).
+    fn parse_ddl_check(&mut self) -> Result<ast::Expression> {
+        self.next_expect(Some(Token::Symbol(lexer::Symbol::OpenParen)))?;
+        let expr = self.parse_expression(0)?;
+        self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
+        Ok(expr)
+    }
+
+    /// Parses a parenthesized constraint column, e.g. (id).
+    fn parse_ddl_constraint_column(&mut self) -> Result<String> {
+        self.next_expect(Some(Token::Symbol(lexer::Symbol::OpenParen)))?;
+        let column = self.next_identifier()?;
+        self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
+        Ok(column)
+    }
+
+    /// Parses the optional precision and scale of a decimal datatype, e.g. (10, 2). The DECIMAL
+    /// keyword has already been consumed. Defaults to the maximum precision and a scale of 0.
+    fn parse_ddl_decimal_params(&mut self) -> Result<DataType> {
+        let (mut precision, mut scale) = (Decimal::MAX_PRECISION, 0);
+        if self.next_if_token(Token::Symbol(lexer::Symbol::OpenParen)).is_some() {
+            precision = self.next_ddl_decimal_param()?;
+            if self.next_if_token(Token::Symbol(lexer::Symbol::Comma)).is_some() {
+                scale = self.next_ddl_decimal_param()?;
+            }
+            self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
+        }
+        if precision == 0 || precision > Decimal::MAX_PRECISION {
+            return Err(Error::Parse(format!(
+                "Decimal precision must be between 1 and {}, found {}",
+                Decimal::MAX_PRECISION,
+                precision
+            )));
+        }
+        if scale > precision {
+            return Err(Error::Parse(format!(
+                "Decimal scale {} can't exceed precision {}",
+                scale, precision
+            )));
+        }
+        Ok(DataType::Decimal(precision, scale))
+    }
+
+    /// Parses a decimal precision or scale.
+    fn next_ddl_decimal_param(&mut self) -> Result<u32> {
+        match self.next()? {
+            Token::Number(n) => n
+                .parse()
+                .map_err(|_| Error::Parse(format!("Invalid decimal precision or scale {}", n))),
+            token => Err(Error::Parse(format!("Expected number, found {}", token))),
+        }
+    }
+
+    /// Parses the variants of an enum datatype, e.g. ('a', 'b'). The ENUM keyword has already
+    /// been consumed.
+    fn parse_ddl_enum_variants(&mut self) -> Result<Vec<String>> {
+        self.next_expect(Some(Token::Symbol(lexer::Symbol::OpenParen)))?;
+        let mut variants = Vec::new();
+        loop {
+            match self.next()? {
+                Token::String(variant) => variants.push(variant),
+                token => return Err(Error::Parse(format!("Expected string, found {}", token))),
+            }
+            match self.next()? {
+                Token::Symbol(lexer::Symbol::CloseParen) => break,
+                Token::Symbol(lexer::Symbol::Comma) => continue,
+                token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+            }
+        }
+        Ok(variants)
+    }
+
+    /// Parses a datatype
+    fn parse_ddl_datatype(&mut self) -> Result<DataType> {
+        Ok(match self.next()? {
+            Token::Keyword(Keyword::Bool) => DataType::Boolean,
+            Token::Keyword(Keyword::Boolean) => DataType::Boolean,
+            Token::Keyword(Keyword::Char) => DataType::String,
+            Token::Keyword(Keyword::Date) => DataType::Date,
+            Token::Keyword(Keyword::Decimal) => self.parse_ddl_decimal_params()?,
+            Token::Keyword(Keyword::Double) => DataType::Float,
+            Token::Keyword(Keyword::Enum) => DataType::Enum(self.parse_ddl_enum_variants()?),
+            Token::Keyword(Keyword::Float) => DataType::Float,
+            Token::Keyword(Keyword::Int) => DataType::Integer,
+            Token::Keyword(Keyword::Integer) => DataType::Integer,
+            Token::Keyword(Keyword::Numeric) => self.parse_ddl_decimal_params()?,
+            Token::Keyword(Keyword::Serial) => DataType::Serial,
+            Token::Keyword(Keyword::String) => DataType::String,
+            Token::Keyword(Keyword::Text) => DataType::String,
+            Token::Keyword(Keyword::Timestamp) => DataType::Timestamp,
+            Token::Keyword(Keyword::Varchar) => DataType::String,
+            token => return Err(Error::Parse(format!("Unexpected token {}", token))),
+        })
+    }
+
     /// Parses a column specification
     fn parse_ddl_columnspec(&mut self) -> Result<ast::Column> {
         let mut column = ast::Column {
             name: self.next_identifier()?,
-            datatype: match self.next()? {
-                Token::Keyword(Keyword::Bool) => DataType::Boolean,
-                Token::Keyword(Keyword::Boolean) => DataType::Boolean,
-                Token::Keyword(Keyword::Char) => DataType::String,
-                Token::Keyword(Keyword::Double) => DataType::Float,
-                Token::Keyword(Keyword::Float) => DataType::Float,
-                Token::Keyword(Keyword::Int) => DataType::Integer,
-                Token::Keyword(Keyword::Integer) => DataType::Integer,
-                Token::Keyword(Keyword::String) => DataType::String,
-                Token::Keyword(Keyword::Text) => DataType::String,
-                Token::Keyword(Keyword::Varchar) => DataType::String,
-                token => return Err(Error::Parse(format!("Unexpected token {}", token))),
-            },
+            datatype: self.parse_ddl_datatype()?,
             is_primary_key: false,
             is_nullable: None,
             default: None,
             is_unique: false,
             is_indexed: false,
             references: None,
+            on_delete: FkAction::NoAction,
+            on_update: FkAction::NoAction,
+            checks: Vec::new(),
         };
         while let Some(Token::Keyword(keyword)) = self.next_if_keyword() {
             match keyword {
@@ -252,7 +554,20 @@ impl<'a> Parser<'a> {
                 Keyword::Default => column.default = Some(self.parse_expression(0)?),
                 Keyword::Unique => column.is_unique = true,
                 Keyword::Index => column.is_indexed = true,
-                Keyword::References => column.references = Some(self.next_identifier()?),
+                Keyword::References => {
+                    column.references = Some(self.next_identifier()?);
+                    (column.on_delete, column.on_update) = self.parse_ddl_references_actions()?;
+                }
+                Keyword::Check => column.checks.push(self.parse_ddl_check()?),
+                Keyword::AutoIncrement => match column.datatype {
+                    DataType::Integer | DataType::Serial => column.datatype = DataType::Serial,
+                    ref datatype => {
+                        return Err(Error::Parse(format!(
+                            "AUTO_INCREMENT is only supported for INTEGER columns, found {}",
+                            datatype
+                        )))
+                    }
+                },
                 keyword => return Err(Error::Parse(format!("Unexpected keyword {}", keyword))),
             }
         }
@@ -281,6 +596,25 @@ impl<'a> Parser<'a> {
             None => None,
         };
 
+        let query = match self.peek()? {
+            Some(Token::Keyword(Keyword::Select)) => Some(self.parse_statement_select()?),
+            Some(Token::Keyword(Keyword::With)) => Some(self.parse_statement_with()?),
+            _ => None,
+        };
+        if let Some(query) = query {
+            if let ast::Statement::SelectInto { .. } = query {
+                return Err(Error::Parse("Unexpected INTO in INSERT query".into()));
+            }
+            let on_conflict = self.parse_clause_on_conflict()?;
+            return Ok(ast::Statement::InsertSelect {
+                table,
+                columns,
+                query: Box::new(query),
+                on_conflict,
+                returning: self.parse_clause_returning()?,
+            });
+        }
+
         self.next_expect(Some(Keyword::Values.into()))?;
         let mut values = vec![];
         loop {
@@ -300,13 +634,49 @@ impl<'a> Parser<'a> {
             }
         }
 
-        Ok(ast::Statement::Insert { table, columns, values })
+        let on_conflict = self.parse_clause_on_conflict()?;
+        let returning = self.parse_clause_returning()?;
+        Ok(ast::Statement::Insert { table, columns, values, on_conflict, returning })
+    }
+
+    /// Parses an optional ON CONFLICT clause of an INSERT statement.
+    fn parse_clause_on_conflict(&mut self) -> Result<Option<ast::OnConflict>> {
+        if self.next_if_token(Keyword::On.into()).is_none() {
+            return Ok(None);
+        }
+        self.next_expect(Some(Keyword::Conflict.into()))?;
+        self.next_expect(Some(Keyword::Do.into()))?;
+        match self.next()? {
+            Token::Keyword(Keyword::Nothing) => Ok(Some(ast::OnConflict::DoNothing)),
+            Token::Keyword(Keyword::Update) => {
+                Ok(Some(ast::OnConflict::DoUpdate(self.parse_clause_set()?)))
+            }
+            token => Err(Error::Parse(format!("Unexpected token {}", token))),
+        }
     }
 
     /// Parses a SELECT statement. TODO: Read all the clauses parsing.
     fn parse_statement_select(&mut self) -> Result<ast::Statement> {
-        Ok(ast::Statement::Select {
-            select: self.parse_clause_select()?,
+        self.next_expect(Some(Keyword::Select.into()))?;
+        let distinct = self.next_if_token(Keyword::Distinct.into()).is_some();
+        let select = self.parse_clause_select()?;
+        let into = match self.next_if_token(Keyword::Into.into()) {
+            Some(_) => {
+                let table = self.next_identifier()?;
+                let primary_key = match self.next_if_token(Keyword::Primary.into()) {
+                    Some(_) => {
+                        self.next_expect(Some(Keyword::Key.into()))?;
+                        Some(self.next_identifier()?)
+                    }
+                    None => None,
+                };
+                Some((table, primary_key))
+            }
+            None => None,
+        };
+        let query = ast::Statement::Select {
+            distinct,
+            select,
             from: self.parse_clause_from()?,
             r#where: self.parse_clause_where()?,
             group_by: self.parse_clause_group_by()?,
@@ -322,6 +692,12 @@ impl<'a> Parser<'a> {
             } else {
                 None
             },
+        };
+        Ok(match into {
+            Some((table, primary_key)) => {
+                ast::Statement::SelectInto { table, primary_key, query: Box::new(query) }
+            }
+            None => query,
         })
     }
 
@@ -329,8 +705,15 @@ impl<'a> Parser<'a> {
     fn parse_statement_update(&mut self) -> Result<ast::Statement> {
         self.next_expect(Some(Keyword::Update.into()))?;
         let table = self.next_identifier()?;
-        self.next_expect(Some(Keyword::Set.into()))?;
+        let set = self.parse_clause_set()?;
+        let r#where = self.parse_clause_where()?;
+        let returning = self.parse_clause_returning()?;
+        Ok(ast::Statement::Update { table, set, r#where, returning })
+    }
 
+    /// Parses a SET clause of column assignments.
+    fn parse_clause_set(&mut self) -> Result<BTreeMap<String, ast::Expression>> {
+        self.next_expect(Some(Keyword::Set.into()))?;
         let mut set = BTreeMap::new();
         loop {
             let column = self.next_identifier()?;
@@ -344,8 +727,7 @@ impl<'a> Parser<'a> {
                 break;
             }
         }
-
-        Ok(ast::Statement::Update { table, set, r#where: self.parse_clause_where()? })
+        Ok(set)
     }
 
     /// Parses a DELETE statement.
@@ -353,20 +735,60 @@ impl<'a> Parser<'a> {
         self.next_expect(Some(Keyword::Delete.into()))?;
         self.next_expect(Some(Keyword::From.into()))?;
         let table = self.next_identifier()?;
-        Ok(ast::Statement::Delete { table, r#where: self.parse_clause_where()? })
+        let r#where = self.parse_clause_where()?;
+        Ok(ast::Statement::Delete { table, r#where, returning: self.parse_clause_returning()? })
+    }
+
+    /// Parses an optional RETURNING clause of a mutation.
+    fn parse_clause_returning(&mut self) -> Result<Option<ast::Returning>> {
+        match self.next_if_token(Keyword::Returning.into()) {
+            Some(_) => Ok(Some(self.parse_clause_select()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Parses a WITH statement, i.e. a SELECT preceded by common table expressions.
+    fn parse_statement_with(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Some(Keyword::With.into()))?;
+        let mut ctes: Vec<(String, ast::Statement)> = vec![];
+        loop {
+            let name = self.next_identifier()?;
+            if ctes.iter().any(|(n, _)| *n == name) {
+                return Err(Error::Value(format!("Duplicate common table expression {}", name)));
+            }
+            self.next_expect(Some(Keyword::As.into()))?;
+            self.next_expect(Some(Token::Symbol(Symbol::OpenParen)))?;
+            self.peek_expect(Keyword::Select.into())?;
+            let statement = self.parse_statement_select()?;
+            self.next_expect(Some(Token::Symbol(Symbol::CloseParen)))?;
+            ctes.push((name, statement));
+            if self.next_if_token(Token::Symbol(Symbol::Comma)).is_none() {
+                break;
+            }
+        }
+        self.peek_expect(Keyword::Select.into())?;
+        Ok(ast::Statement::With { ctes, query: Box::new(self.parse_statement_select()?) })
     }
 
-    /// TODO: Parses an EXPLAIN statement.
+    /// Parses an EXPLAIN [ANALYZE] statement, which can't be nested or explain transaction
+    /// control.
     fn parse_statement_explain(&mut self) -> Result<ast::Statement> {
-        todo!()
+        self.next_expect(Some(Keyword::Explain.into()))?;
+        let analyze = self.next_if_token(Keyword::Analyze.into()).is_some();
+        match self.peek()? {
+            Some(Token::Keyword(
+                keyword @ (Keyword::Explain | Keyword::Begin | Keyword::Commit | Keyword::Rollback),
+            )) => Err(Error::Parse(format!("Can't explain {} statements", keyword))),
+            _ => {
+                let statement = Box::new(self.parse_statement()?);
+                Ok(ast::Statement::Explain { statement, analyze })
+            }
+        }
     }
 
-    /// Parses a select clause
+    /// Parses the expressions of a select clause, following SELECT [DISTINCT].
     fn parse_clause_select(&mut self) -> Result<Vec<(ast::Expression, Option<String>)>> {
         let mut select = Vec::new();
-        if self.next_if_token(Keyword::Select.into()).is_none() {
-            return Ok(select);
-        }
         loop {
             if self.next_if_token(Token::Symbol(Symbol::Asterisk)).is_some() && select.is_empty() {
                 break;
@@ -401,6 +823,15 @@ impl<'a> Parser<'a> {
                 let right = Box::new(self.parse_clause_from_item()?);
                 let predicate = match &jointype {
                     ast::JoinType::Cross => None,
+                    ast::JoinType::Lateral | ast::JoinType::LeftLateral => {
+                        if !matches!(*right, ast::FromItem::Subquery { .. }) {
+                            return Err(Error::Parse("LATERAL join requires a subquery".into()));
+                        }
+                        match self.next_if_token(Keyword::On.into()) {
+                            Some(_) => Some(self.parse_expression(0)?),
+                            None => None,
+                        }
+                    }
                     _ => {
                         self.next_expect(Some(Keyword::On.into()))?;
                         Some(self.parse_expression(0)?)
@@ -419,11 +850,37 @@ impl<'a> Parser<'a> {
 
     /// Parses a from clause item
     fn parse_clause_from_item(&mut self) -> Result<ast::FromItem> {
-        self.parse_clause_from_table()
+        let item = self.parse_clause_from_table()?;
+        if self.next_if_token(Keyword::Tablesample.into()).is_none() {
+            return Ok(item);
+        }
+        self.next_expect(Some(Keyword::Bernoulli.into()))?;
+        self.next_expect(Some(Token::Symbol(Symbol::OpenParen)))?;
+        let percent = self.parse_expression(0)?;
+        self.next_expect(Some(Token::Symbol(Symbol::CloseParen)))?;
+        let seed = if self.next_if_token(Keyword::Repeatable.into()).is_some() {
+            self.next_expect(Some(Token::Symbol(Symbol::OpenParen)))?;
+            let seed = self.parse_expression(0)?;
+            self.next_expect(Some(Token::Symbol(Symbol::CloseParen)))?;
+            Some(seed)
+        } else {
+            None
+        };
+        Ok(ast::FromItem::Sample { source: Box::new(item), percent, seed })
     }
 
-    // Parses a from clause table
+    // Parses a from clause table or subquery
     fn parse_clause_from_table(&mut self) -> Result<ast::FromItem> {
+        if self.next_if_token(Token::Symbol(Symbol::OpenParen)).is_some() {
+            let query = Box::new(self.parse_statement_select()?);
+            self.next_expect(Some(Token::Symbol(Symbol::CloseParen)))?;
+            self.next_if_token(Keyword::As.into());
+            let alias = match self.peek()? {
+                Some(Token::Identifier(_)) => self.next_identifier()?,
+                _ => return Err(Error::Parse("Subquery in FROM must have an alias".into())),
+            };
+            return Ok(ast::FromItem::Subquery { query, alias });
+        }
         let name = self.next_identifier()?;
         let alias = if self.next_if_token(Keyword::As.into()).is_some() {
             Some(self.next_identifier()?)
@@ -437,24 +894,32 @@ impl<'a> Parser<'a> {
 
     // Parses a from clause join type
     fn parse_clause_from_jointype(&mut self) -> Result<Option<ast::JoinType>> {
-        if self.next_if_token(Keyword::Cross.into()).is_some() {
+        let jointype = if self.next_if_token(Keyword::Cross.into()).is_some() {
             self.next_expect(Some(Keyword::Join.into()))?;
-            Ok(Some(ast::JoinType::Cross))
+            ast::JoinType::Cross
         } else if self.next_if_token(Keyword::Inner.into()).is_some() {
             self.next_expect(Some(Keyword::Join.into()))?;
-            Ok(Some(ast::JoinType::Inner))
+            ast::JoinType::Inner
         } else if self.next_if_token(Keyword::Join.into()).is_some() {
-            Ok(Some(ast::JoinType::Inner))
+            ast::JoinType::Inner
         } else if self.next_if_token(Keyword::Left.into()).is_some() {
             self.next_if_token(Keyword::Outer.into());
             self.next_expect(Some(Keyword::Join.into()))?;
-            Ok(Some(ast::JoinType::Left))
+            ast::JoinType::Left
         } else if self.next_if_token(Keyword::Right.into()).is_some() {
             self.next_if_token(Keyword::Outer.into());
             self.next_expect(Some(Keyword::Join.into()))?;
-            Ok(Some(ast::JoinType::Right))
+            ast::JoinType::Right
         } else {
-            Ok(None)
+            return Ok(None);
+        };
+        if self.next_if_token(Keyword::Lateral.into()).is_none() {
+            return Ok(Some(jointype));
+        }
+        match jointype {
+            ast::JoinType::Cross | ast::JoinType::Inner => Ok(Some(ast::JoinType::Lateral)),
+            ast::JoinType::Left => Ok(Some(ast::JoinType::LeftLateral)),
+            _ => Err(Error::Parse("RIGHT JOIN LATERAL is not supported".into())),
         }
     }
 
@@ -507,6 +972,28 @@ impl<'a> Parser<'a> {
         Ok(orders)
     }
 
+    /// Parses the window of a window function call, following OVER.
+    fn parse_window(
+        &mut self,
+        function: String,
+        args: Vec<ast::Expression>,
+    ) -> Result<ast::Expression> {
+        self.next_expect(Some(Token::Symbol(Symbol::OpenParen)))?;
+        let mut partition_by = Vec::new();
+        if self.next_if_token(Keyword::Partition.into()).is_some() {
+            self.next_expect(Some(Keyword::By.into()))?;
+            loop {
+                partition_by.push(self.parse_expression(0)?);
+                if self.next_if_token(Token::Symbol(Symbol::Comma)).is_none() {
+                    break;
+                }
+            }
+        }
+        let order = self.parse_clause_order()?;
+        self.next_expect(Some(Token::Symbol(Symbol::CloseParen)))?;
+        Ok(ast::Expression::Window(Box::new(ast::Window { function, args, partition_by, order })))
+    }
+
     /// Parses a WHERE clause.
     fn parse_clause_where(&mut self) -> Result<Option<ast::Expression>> {
         if self.next_if_token(Keyword::Where.into()).is_none() {
@@ -527,16 +1014,42 @@ impl<'a> Parser<'a> {
             lhs = postfix.build(lhs)
         }
         while let Some(infix) = self.next_if_operator::<InfixOperator>(min_prec)? {
-            lhs = infix.build(lhs, self.parse_expression(infix.precedence() + infix.associativity())?)
+            let precedence = infix.precedence() + infix.associativity();
+            lhs = infix.build(lhs, self.parse_expression(precedence)?)?;
+            // LIKE patterns may be followed by an ESCAPE character.
+            if let ast::Expression::Operation(ast::Operation::Like { escape, .. }) = &mut lhs {
+                if self.next_if_token(Keyword::Escape.into()).is_some() {
+                    *escape = Some(Box::new(self.parse_expression(precedence)?));
+                }
+            }
         }
         Ok(lhs)
     }
 
+    /// Parses a parenthesized subquery, after the opening parenthesis.
+    fn parse_subquery(&mut self) -> Result<ast::Statement> {
+        let query = match self.parse_statement_select()? {
+            query @ ast::Statement::Select { .. } => query,
+            _ => return Err(Error::Parse("Subqueries can't select into a table".into())),
+        };
+        self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
+        Ok(query)
+    }
+
     /// Parses an expression atom
     fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
             Token::Identifier(id) => {
-                if self.next_if_token(Token::Symbol(lexer::Symbol::OpenParen)).is_some() {
+                if id == "extract"
+                    && self.next_if_token(Token::Symbol(lexer::Symbol::OpenParen)).is_some()
+                {
+                    // EXTRACT(field FROM expr) is passed the field name as a string argument.
+                    let field = self.next_identifier()?;
+                    self.next_expect(Some(Token::Keyword(Keyword::From)))?;
+                    let expr = self.parse_expression(0)?;
+                    self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
+                    ast::Expression::Function(id, vec![ast::Literal::String(field).into(), expr])
+                } else if self.next_if_token(Token::Symbol(lexer::Symbol::OpenParen)).is_some() {
                     let mut args = vec![];
                     while self.next_if_token(Token::Symbol(lexer::Symbol::CloseParen)).is_none() {
                         if !args.is_empty() {
@@ -551,7 +1064,10 @@ impl<'a> Parser<'a> {
                             args.push(self.parse_expression(0)?);
                         }
                     }
-                    ast::Expression::Function(id, args)
+                    match self.next_if_token(Keyword::Over.into()) {
+                        Some(_) => self.parse_window(id, args)?,
+                        None => ast::Expression::Function(id, args),
+                    }
                 } else {
                     let mut relation = None;
                     let mut field = id;
@@ -570,11 +1086,28 @@ impl<'a> Parser<'a> {
                 }
             },
             Token::Symbol(lexer::Symbol::OpenParen) => {
+                if self.peek()? == Some(Keyword::Select.into()) {
+                    ast::Expression::Subquery(Box::new(self.parse_subquery()?))
+                } else {
+                    let expr = self.parse_expression(0)?;
+                    self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
+                    expr
+                }
+            },
+            Token::Keyword(Keyword::Exists) => {
+                self.next_expect(Some(Token::Symbol(lexer::Symbol::OpenParen)))?;
+                ast::Expression::Exists(Box::new(self.parse_subquery()?))
+            },
+            Token::Keyword(Keyword::Cast) => {
+                self.next_expect(Some(Token::Symbol(lexer::Symbol::OpenParen)))?;
                 let expr = self.parse_expression(0)?;
+                self.next_expect(Some(Keyword::As.into()))?;
+                let datatype = self.parse_ddl_datatype()?;
                 self.next_expect(Some(Token::Symbol(lexer::Symbol::CloseParen)))?;
-                expr
+                ast::Expression::Cast(Box::new(expr), datatype)
             },
             Token::String(s) => ast::Literal::String(s).into(),
+            Token::Parameter(n) => ast::Expression::Parameter(n),
             Token::Keyword(Keyword::False) => ast::Literal::Boolean(false).into(),
             Token::Keyword(Keyword::Infinity) => ast::Literal::Float(std::f64::INFINITY).into(),
             Token::Keyword(Keyword::NaN) => ast::Literal::Float(std::f64::NAN).into(),
@@ -636,18 +1169,21 @@ impl Operator for PrefixOperator {
     }
 
     fn precedence(&self) -> u8 {
-        9
+        10
     }
 }
 
 enum InfixOperator {
     Add,
     And,
+    Concat,
     Divide,
     Equal,
     Exponentiate,
     GreaterThan,
     GreaterThanOrEqual,
+    ILike,
+    In { negated: bool },
     LessThan,
     LessThanOrEqual,
     Like,
@@ -659,26 +1195,40 @@ enum InfixOperator {
 }
 
 impl InfixOperator {
-    fn build(&self, lhs: ast::Expression, rhs: ast::Expression) -> ast::Expression {
+    fn build(&self, lhs: ast::Expression, rhs: ast::Expression) -> Result<ast::Expression> {
         let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
-        match self {
+        Ok(match self {
             Self::Add => ast::Operation::Add(lhs, rhs),
             Self::And => ast::Operation::And(lhs, rhs),
+            Self::Concat => ast::Operation::Concat(lhs, rhs),
             Self::Divide => ast::Operation::Divide(lhs, rhs),
             Self::Equal => ast::Operation::Equal(lhs, rhs),
             Self::Exponentiate => ast::Operation::Exponentiate(lhs, rhs),
             Self::GreaterThan => ast::Operation::GreaterThan(lhs, rhs),
             Self::GreaterThanOrEqual => ast::Operation::GreaterThanOrEqual(lhs, rhs),
+            Self::In { negated } => match *rhs {
+                ast::Expression::Subquery(subquery) => {
+                    ast::Operation::In { value: lhs, subquery, negated: *negated }
+                }
+                rhs => {
+                    return Err(Error::Parse(format!("Expected subquery after IN, found {:?}", rhs)))
+                }
+            },
             Self::LessThan => ast::Operation::LessThan(lhs, rhs),
             Self::LessThanOrEqual => ast::Operation::LessThanOrEqual(lhs, rhs),
-            Self::Like => ast::Operation::Like(lhs, rhs),
+            Self::ILike | Self::Like => ast::Operation::Like {
+                value: lhs,
+                pattern: rhs,
+                escape: None,
+                case_insensitive: matches!(self, Self::ILike),
+            },
             Self::Modulo => ast::Operation::Modulo(lhs, rhs),
             Self::Multiply => ast::Operation::Multiply(lhs, rhs),
             Self::NotEqual => ast::Operation::NotEqual(lhs, rhs),
             Self::Or => ast::Operation::Or(lhs, rhs),
             Self::Subtract => ast::Operation::Subtract(lhs, rhs),
         }
-        .into()
+        .into())
     }
 }
 
@@ -686,10 +1236,14 @@ impl Operator for InfixOperator {
     fn from(token: &Token) -> Option<Self> {
         Some(match token {
             Token::Keyword(Keyword::And) => Self::And,
+            Token::Keyword(Keyword::ILike) => Self::ILike,
+            Token::Keyword(Keyword::In) => Self::In { negated: false },
             Token::Keyword(Keyword::Like) => Self::Like,
+            Token::Keyword(Keyword::Not) => Self::In { negated: true },
             Token::Keyword(Keyword::Or) => Self::Or,
             Token::Symbol(lexer::Symbol::Asterisk) => Self::Multiply,
             Token::Symbol(lexer::Symbol::Caret) => Self::Exponentiate,
+            Token::Symbol(lexer::Symbol::Concat) => Self::Concat,
             Token::Symbol(lexer::Symbol::Equal) => Self::Equal,
             Token::Symbol(lexer::Symbol::GreaterThan) => Self::GreaterThan,
             Token::Symbol(lexer::Symbol::GreaterThanOrEqual) => Self::GreaterThanOrEqual,
@@ -705,7 +1259,11 @@ impl Operator for InfixOperator {
         })
     }
 
-    fn augment(self, _parser: &mut Parser) -> Result<Self> {
+    fn augment(self, parser: &mut Parser) -> Result<Self> {
+        // NOT is only an infix operator as part of NOT IN.
+        if let Self::In { negated: true } = self {
+            parser.next_expect(Some(Keyword::In.into()))?;
+        }
         Ok(self)
     }
 
@@ -720,14 +1278,15 @@ impl Operator for InfixOperator {
         match self {
             Self::Or => 1,
             Self::And => 2,
-            Self::Equal | Self::NotEqual | Self::Like => 3,
+            Self::Equal | Self::NotEqual | Self::Like | Self::ILike | Self::In { .. } => 3,
             Self::GreaterThan
             | Self::GreaterThanOrEqual
             | Self::LessThan
             | Self::LessThanOrEqual => 4,
-            Self::Add | Self::Subtract => 5,
-            Self::Multiply | Self::Divide | Self::Modulo => 6,
-            Self::Exponentiate => 7,
+            Self::Concat => 5,
+            Self::Add | Self::Subtract => 6,
+            Self::Multiply | Self::Divide | Self::Modulo => 7,
+            Self::Exponentiate => 8,
         }
     }
 }
@@ -783,7 +1342,7 @@ impl Operator for PostfixOperator {
     }
 
     fn precedence(&self) -> u8 {
-        8
+        9
     }
 }
 
@@ -7,20 +7,21 @@ mod optimizer;
 mod planner;
 use planner::Planner;
 
+use std::cell::Cell;
 use std::fmt::{self, Display};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use self::optimizer::Optimizer;
 
 use super::engine::SqlTxn;
-use super::execution::{Executor, ResultSet};
+use super::execution::{Analyzer, Executor, InsertConflictAction, ResultSet, Returning};
 use super::parser::ast;
-use super::schema::{Table, Catalog};
-use super::types::{Expression, Value};
+use super::schema::{Catalog, Column, Constraint, Table};
+use super::types::{DataType, Expression, Value};
 
 /// A query plan
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Plan(Node);
 
 impl Plan {
@@ -31,15 +32,96 @@ impl Plan {
 
     /// Optimizes the plan, consuming it and returning a new plan.
     pub fn optimize<C: Catalog>(self, catalog: &mut C) -> Result<Self> {
-        let mut root = self.0;
+        Ok(Plan(Self::optimize_node(self.0, catalog)?))
+    }
+
+    /// Optimizes a plan node, including the plans of subqueries in its expressions.
+    fn optimize_node<C: Catalog>(node: Node, catalog: &C) -> Result<Node> {
+        let mut root = node.transform(&Ok, &|n| {
+            n.transform_expressions(&Ok, &|e| match e {
+                Expression::ScalarSubquery(subquery) => Ok(Expression::ScalarSubquery(
+                    Self::optimize_node(*subquery, catalog)?.into(),
+                )),
+                Expression::InSubquery { value, subquery, negated } => {
+                    let subquery = Self::optimize_node(*subquery, catalog)?.into();
+                    Ok(Expression::InSubquery { value, subquery, negated })
+                }
+                Expression::ExistsSubquery(subquery) => Ok(Expression::ExistsSubquery(
+                    Self::optimize_node(*subquery, catalog)?.into(),
+                )),
+                e => Ok(e),
+            })
+        })?;
         root = optimizer::ConstantFolder.optimize(root)?;
-        Ok(Plan(root))
+        root = optimizer::FilterPushdown.optimize(root)?;
+        root = optimizer::IndexSelection::new(catalog).optimize(root)?;
+        root = optimizer::NoopCleaner.optimize(root)?;
+        root = optimizer::JoinType.optimize(root)?;
+        Ok(root)
+    }
+
+    /// Returns the number of parameters of the plan, i.e. the highest parameter number.
+    pub fn parameter_count(&self) -> Result<u64> {
+        let count = Cell::new(0);
+        Self::transform_parameters(self.0.clone(), &|n| {
+            count.set(count.get().max(n));
+            Ok(Expression::Parameter(n))
+        })?;
+        Ok(count.get())
+    }
+
+    /// Binds the plan's parameters to the given values, numbered from 1, consuming it.
+    pub fn bind(self, params: &[Value]) -> Result<Self> {
+        let node = Self::transform_parameters(self.0, &|n| match params.get(n as usize - 1) {
+            Some(value) => Ok(Expression::Constant(value.clone())),
+            None => Err(Error::Value(format!("No value given for parameter ${}", n))),
+        })?;
+        Ok(Plan(node))
+    }
+
+    /// Replaces the parameters of a plan node, including those in subqueries.
+    fn transform_parameters(node: Node, f: &dyn Fn(u64) -> Result<Expression>) -> Result<Node> {
+        let subquery = |node: Box<Node>| Self::transform_parameters(*node, f).map(Box::new);
+        node.transform(&Ok, &|n| {
+            n.transform_expressions(
+                &|e| match e {
+                    Expression::Parameter(n) => f(n),
+                    Expression::ScalarSubquery(node) => {
+                        Ok(Expression::ScalarSubquery(subquery(node)?))
+                    }
+                    Expression::InSubquery { value, subquery: node, negated } => {
+                        Ok(Expression::InSubquery { value, subquery: subquery(node)?, negated })
+                    }
+                    Expression::ExistsSubquery(node) => {
+                        Ok(Expression::ExistsSubquery(subquery(node)?))
+                    }
+                    e => Ok(e),
+                },
+                &Ok,
+            )
+        })
+    }
+
+    /// Explains the plan, consuming it and returning a result set with its root node.
+    pub fn explain(self) -> ResultSet {
+        ResultSet::Explain(self.0)
     }
 
     /// Executes the plan, consuming it and returning a result set.
     pub fn execute<T: SqlTxn + 'static>(self, txn: &mut T) -> Result<ResultSet> {
         <dyn Executor<T>>::build(self.0).execute(txn)
     }
+
+    /// Executes the plan while recording statistics for each node, consuming it and returning
+    /// a result set with the plan annotated with them. The rows are discarded.
+    pub fn analyze<T: SqlTxn + 'static>(self, txn: &mut T) -> Result<ResultSet> {
+        let analyzer = Analyzer::new();
+        let result = <dyn Executor<T>>::build_analyzed(self.0.clone(), &analyzer).execute(txn)?;
+        if let ResultSet::Query { buffered_rows: Err(err), .. } = result {
+            return Err(err);
+        }
+        Ok(ResultSet::ExplainAnalyze(analyzer.render(&self.0)))
+    }
 }
 
 impl Display for Plan {
@@ -53,25 +135,61 @@ impl Display for Plan {
 pub enum Node {
     CreateTable { schema: Table },
     DropTable { table: String },
+    Analyze { table: String },
+    LoadExtension { path: String },
+    DropExtension { name: String },
+    CreateMaterializedView {
+        name: String,
+        datatypes: Vec<Option<DataType>>,
+        source: Box<Node>,
+        query: String,
+    },
+    DropMaterializedView { view: String },
+    RefreshMaterializedView { view: String },
+    AddEnumValue { table: String, column: String, value: String },
+    ResetSequence { table: String, column: String, value: i64 },
+    AddConstraint { table: String, name: String, constraint: Constraint },
+    CreateIndex { table: String, column: String, predicate: Option<Expression> },
+    DropConstraint { table: String, name: String },
+    AddColumn { table: String, column: Column },
+    DropColumn { table: String, column: String },
 
     Insert {
         table: String,
         columns: Vec<String>,
         expression: Vec<Vec<Expression>>,
+        on_conflict: InsertConflictAction,
+        returning: Option<Returning>,
+    },
+    /// Inserts the rows of a source query into the given columns of a table.
+    InsertSelect {
+        table: String,
+        columns: Vec<String>,
+        source: Box<Node>,
+        on_conflict: InsertConflictAction,
+        returning: Option<Returning>,
     },
     KeyLookup {
         table: String,
         alias: Option<String>,
         keys: Vec<Value>,
     },
+    IndexLookup {
+        table: String,
+        alias: Option<String>,
+        column: String,
+        values: Vec<Value>,
+    },
     Update {
         table: String,
         source: Box<Node>,
         expressions: Vec<(usize, Option<String>, Expression)>,
+        returning: Option<Returning>,
     },
     Delete {
         table: String,
         source: Box<Node>,
+        returning: Option<Returning>,
     },
 
     Scan {
@@ -83,10 +201,42 @@ pub enum Node {
         source: Box<Node>,
         predicate: Expression,
     },
+    MaterializedCte {
+        name: String,
+        source: Box<Node>,
+    },
     Projection {
         source: Box<Node>,
         expressions: Vec<(Expression, Option<String>)>,
     },
+    /// Removes duplicate source rows, keeping the first occurrence of each.
+    Distinct {
+        source: Box<Node>,
+    },
+    /// Sorts the source rows by the given expressions, in order of precedence.
+    Order {
+        source: Box<Node>,
+        orders: Vec<(Expression, SortOrder)>,
+    },
+    /// Skips the first offset source rows, then emits at most limit rows.
+    Limit {
+        source: Box<Node>,
+        limit: Option<u64>,
+        offset: u64,
+    },
+    Sample {
+        source: Box<Node>,
+        probability: f64,
+        seed: Option<u64>,
+    },
+    /// Creates a table from the source rows. Column datatypes are inferred at planning time
+    /// where possible, and otherwise from the rows themselves.
+    SelectInto {
+        table: String,
+        primary_key: Option<String>,
+        datatypes: Vec<Option<DataType>>,
+        source: Box<Node>,
+    },
     NestedLoopJoin {
         left: Box<Node>,
         left_size: usize,
@@ -94,6 +244,35 @@ pub enum Node {
         predicate: Option<Expression>,
         outer: bool,
     },
+    /// Joins the left and right rows whose given fields are equal, by hashing one side.
+    HashJoin {
+        left: Box<Node>,
+        left_field: (usize, Option<(Option<String>, String)>),
+        right: Box<Node>,
+        right_field: (usize, Option<(Option<String>, String)>),
+        outer: bool,
+    },
+    LateralJoin {
+        left: Box<Node>,
+        right: Box<Node>,
+        predicate: Option<Expression>,
+        outer: bool,
+    },
+    /// Groups the source rows by the group_by expressions and computes the aggregates for each
+    /// group. Each output row contains the aggregates followed by the group values, whose
+    /// columns are named by group_labels.
+    Aggregation {
+        source: Box<Node>,
+        group_by: Vec<Expression>,
+        group_labels: Vec<String>,
+        aggregates: Vec<AggregateExpr>,
+    },
+    /// Computes window functions over the source rows, emitting each source row in order
+    /// followed by its window function values.
+    Window {
+        source: Box<Node>,
+        functions: Vec<WindowFunctionExpr>,
+    },
     Nothing,
 }
 
@@ -106,33 +285,55 @@ impl Node {
     {
         self = before(self)?;
         self = match self {
-            n @ Self::CreateTable { .. }
+            n @ Self::AddColumn { .. }
+            | n @ Self::AddConstraint { .. }
+            | n @ Self::AddEnumValue { .. }
+            | n @ Self::CreateIndex { .. }
+            | n @ Self::CreateMaterializedView { .. }
+            | n @ Self::CreateTable { .. }
+            | n @ Self::DropColumn { .. }
+            | n @ Self::DropConstraint { .. }
+            | n @ Self::DropMaterializedView { .. }
             | n @ Self::DropTable { .. }
-            // | n @ Self::IndexLookup { .. }
+            | n @ Self::Analyze { .. }
+            | n @ Self::DropExtension { .. }
+            | n @ Self::LoadExtension { .. }
+            | n @ Self::IndexLookup { .. }
             | n @ Self::Insert { .. }
             | n @ Self::KeyLookup { .. }
             | n @ Self::Nothing
+            | n @ Self::RefreshMaterializedView { .. }
+            | n @ Self::ResetSequence { .. }
             | n @ Self::Scan { .. } => n,
 
-            // Self::Aggregation { source, aggregates } => {
-            //     Self::Aggregation { source: source.transform(before, after)?.into(), aggregates }
-            // },
-            Self::Delete { table, source } => {
-                Self::Delete { table, source: source.transform(before, after)?.into() }
+            Self::Aggregation { source, group_by, group_labels, aggregates } => Self::Aggregation {
+                source: source.transform(before, after)?.into(),
+                group_by,
+                group_labels,
+                aggregates,
+            },
+            Self::Delete { table, source, returning } => {
+                Self::Delete { table, source: source.transform(before, after)?.into(), returning }
+            },
+            Self::Distinct { source } => {
+                Self::Distinct { source: source.transform(before, after)?.into() }
             },
             Self::Filter { source, predicate } => {
                 Self::Filter { source: source.transform(before, after)?.into(), predicate }
             },
-            // Self::HashJoin { left, left_field, right, right_field, outer } => Self::HashJoin {
-            //     left: left.transform(before, after)?.into(),
-            //     left_field,
-            //     right: right.transform(before, after)?.into(),
-            //     right_field,
-            //     outer,
-            // },
-            // Self::Limit { source, limit } => {
-            //     Self::Limit { source: source.transform(before, after)?.into(), limit }
-            // },
+            Self::MaterializedCte { name, source } => {
+                Self::MaterializedCte { name, source: source.transform(before, after)?.into() }
+            },
+            Self::HashJoin { left, left_field, right, right_field, outer } => Self::HashJoin {
+                left: left.transform(before, after)?.into(),
+                left_field,
+                right: right.transform(before, after)?.into(),
+                right_field,
+                outer,
+            },
+            Self::Limit { source, limit, offset } => {
+                Self::Limit { source: source.transform(before, after)?.into(), limit, offset }
+            },
             Self::NestedLoopJoin { left, left_size, right, predicate, outer } => {
                 Self::NestedLoopJoin {
                     left: left.transform(before, after)?.into(),
@@ -142,17 +343,44 @@ impl Node {
                     outer,
                 }
             },
-            // Self::Offset { source, offset } => {
-            //     Self::Offset { source: source.transform(before, after)?.into(), offset }
-            // },
-            // Self::Order { source, orders } => {
-            //     Self::Order { source: source.transform(before, after)?.into(), orders }
-            // },
+            Self::LateralJoin { left, right, predicate, outer } => Self::LateralJoin {
+                left: left.transform(before, after)?.into(),
+                right: right.transform(before, after)?.into(),
+                predicate,
+                outer,
+            },
+            Self::Order { source, orders } => {
+                Self::Order { source: source.transform(before, after)?.into(), orders }
+            },
             Self::Projection { source, expressions } => {
                 Self::Projection { source: source.transform(before, after)?.into(), expressions }
             },
-            Self::Update { table, source, expressions } => {
-                Self::Update { table, source: source.transform(before, after)?.into(), expressions }
+            Self::Sample { source, probability, seed } => {
+                Self::Sample { source: source.transform(before, after)?.into(), probability, seed }
+            },
+            Self::InsertSelect { table, columns, source, on_conflict, returning } => {
+                Self::InsertSelect {
+                    table,
+                    columns,
+                    source: source.transform(before, after)?.into(),
+                    on_conflict,
+                    returning,
+                }
+            },
+            Self::SelectInto { table, primary_key, datatypes, source } => Self::SelectInto {
+                table,
+                primary_key,
+                datatypes,
+                source: source.transform(before, after)?.into(),
+            },
+            Self::Update { table, source, expressions, returning } => Self::Update {
+                table,
+                source: source.transform(before, after)?.into(),
+                expressions,
+                returning,
+            },
+            Self::Window { source, functions } => {
+                Self::Window { source: source.transform(before, after)?.into(), functions }
             },
         };
         after(self)
@@ -165,37 +393,80 @@ impl Node {
         A: Fn(Expression) -> Result<Expression>,
     {
         Ok(match self {
-            // n @ Self::Aggregation { .. }
+            n @ Self::AddColumn { .. }
+            | n @ Self::AddConstraint { .. }
+            | n @ Self::AddEnumValue { .. }
+            | n @ Self::CreateIndex { .. }
+            | n @ Self::CreateMaterializedView { .. }
             | n @ Self::CreateTable { .. }
-            | n @ Self::Delete { .. }
+            | n @ Self::Distinct { .. }
+            | n @ Self::DropColumn { .. }
+            | n @ Self::DropConstraint { .. }
+            | n @ Self::DropMaterializedView { .. }
             | n @ Self::DropTable { .. }
-            // | n @ Self::HashJoin { .. }
-            // | n @ Self::IndexLookup { .. }
+            | n @ Self::Analyze { .. }
+            | n @ Self::DropExtension { .. }
+            | n @ Self::LoadExtension { .. }
+            | n @ Self::HashJoin { .. }
+            | n @ Self::IndexLookup { .. }
             | n @ Self::KeyLookup { .. }
-            // | n @ Self::Limit { .. }
+            | n @ Self::Limit { .. }
+            | n @ Self::LateralJoin { predicate: None, .. }
+            | n @ Self::MaterializedCte { .. }
             | n @ Self::NestedLoopJoin { predicate: None, .. }
             | n @ Self::Nothing
-            // | n @ Self::Offset { .. }
-            | n @ Self::Scan { filter: None, .. } => n,
+            | n @ Self::RefreshMaterializedView { .. }
+            | n @ Self::ResetSequence { .. }
+            | n @ Self::Sample { .. }
+            | n @ Self::Scan { filter: None, .. }
+            | n @ Self::SelectInto { .. } => n,
 
+            Self::Aggregation { source, group_by, group_labels, aggregates } => Self::Aggregation {
+                source,
+                group_by: group_by
+                    .into_iter()
+                    .map(|e| e.transform(before, after))
+                    .collect::<Result<_>>()?,
+                group_labels,
+                aggregates: aggregates
+                    .into_iter()
+                    .map(|a| a.transform(before, after))
+                    .collect::<Result<_>>()?,
+            },
             Self::Filter { source, predicate } => {
                 Self::Filter { source, predicate: predicate.transform(before, after)? }
             },
-            Self::Insert { table, columns, expression } => Self::Insert {
+            Self::Delete { table, source, returning } => Self::Delete {
+                table,
+                source,
+                returning: Self::transform_returning(returning, before, after)?,
+            },
+            Self::Insert { table, columns, expression, on_conflict, returning } => Self::Insert {
                 table,
                 columns,
+                on_conflict: on_conflict.transform(before, after)?,
                 expression: expression
                     .into_iter()
                     .map(|exprs| exprs.into_iter().map(|e| e.transform(before, after)).collect())
                     .collect::<Result<_>>()?,
+                returning: Self::transform_returning(returning, before, after)?,
+            },
+            Self::InsertSelect { table, columns, source, on_conflict, returning } => {
+                Self::InsertSelect {
+                    table,
+                    columns,
+                    source,
+                    on_conflict: on_conflict.transform(before, after)?,
+                    returning: Self::transform_returning(returning, before, after)?,
+                }
+            },
+            Self::Order { source, orders } => Self::Order {
+                source,
+                orders: orders
+                    .into_iter()
+                    .map(|(e, o)| e.transform(before, after).map(|e| (e, o)))
+                    .collect::<Result<_>>()?,
             },
-            // Self::Order { source, orders } => Self::Order {
-            //     source,
-            //     orders: orders
-            //         .into_iter()
-            //         .map(|(e, o)| e.transform(before, after).map(|e| (e, o)))
-            //         .collect::<Result<_>>()?,
-            // },
             Self::NestedLoopJoin { left, left_size, right, predicate: Some(predicate), outer } => {
                 Self::NestedLoopJoin {
                     left,
@@ -205,6 +476,14 @@ impl Node {
                     outer,
                 }
             },
+            Self::LateralJoin { left, right, predicate: Some(predicate), outer } => {
+                Self::LateralJoin {
+                    left,
+                    right,
+                    predicate: Some(predicate.transform(before, after)?),
+                    outer,
+                }
+            },
             Self::Projection { source, expressions } => Self::Projection {
                 source,
                 expressions: expressions
@@ -215,13 +494,21 @@ impl Node {
             Self::Scan { table, alias, filter: Some(filter) } => {
                 Self::Scan { table, alias, filter: Some(filter.transform(before, after)?) }
             },
-            Self::Update { table, source, expressions } => Self::Update {
+            Self::Update { table, source, expressions, returning } => Self::Update {
                 table,
                 source,
                 expressions: expressions
                     .into_iter()
                     .map(|(i, l, e)| e.transform(before, after).map(|e| (i, l, e)))
                     .collect::<Result<_>>()?,
+                returning: Self::transform_returning(returning, before, after)?,
+            },
+            Self::Window { source, functions } => Self::Window {
+                source,
+                functions: functions
+                    .into_iter()
+                    .map(|f| f.transform(before, after))
+                    .collect::<Result<_>>()?,
             },
         })
     }
@@ -237,63 +524,140 @@ impl Node {
             indent += "   ";
         }
         match self {
-            // Self::Aggregation { source, aggregates } => {
-            //     s += &format!(
-            //         "Aggregation: {}\n",
-            //         aggregates.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")
-            //     );
-            //     s += &source.format(indent, false, true);
-            // }
+            Self::Aggregation { source, group_by, aggregates, .. } => {
+                let mut parts = Vec::new();
+                if !aggregates.is_empty() {
+                    parts.push(
+                        aggregates.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", "),
+                    );
+                }
+                if !group_by.is_empty() {
+                    parts.push(format!(
+                        "group by {}",
+                        group_by.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+                    ));
+                }
+                s += &format!("Aggregation: {}\n", parts.join(" "));
+                s += &source.format(indent, false, true);
+            }
+            Self::Window { source, functions } => {
+                s += &format!(
+                    "Window: {}\n",
+                    functions.iter().map(|f| f.to_string()).collect::<Vec<_>>().join(", ")
+                );
+                s += &source.format(indent, false, true);
+            }
             Self::CreateTable { schema } => {
                 s += &format!("CreateTable: {}\n", schema.name);
             }
-            Self::Delete { source, table } => {
-                s += &format!("Delete: {}\n", table);
+            Self::Delete { source, table, returning } => {
+                s += &format!("Delete: {}{}\n", table, Self::format_returning(returning));
                 s += &source.format(indent, false, true);
             }
             Self::DropTable { table } => {
                 s += &format!("DropTable: {}\n", table);
             }
+            Self::Analyze { table } => {
+                s += &format!("Analyze: {}\n", table);
+            }
+            Self::LoadExtension { path } => {
+                s += &format!("LoadExtension: {}\n", path);
+            }
+            Self::DropExtension { name } => {
+                s += &format!("DropExtension: {}\n", name);
+            }
+            Self::CreateMaterializedView { name, source, .. } => {
+                s += &format!("CreateMaterializedView: {}\n", name);
+                s += &source.format(indent, false, true);
+            }
+            Self::DropMaterializedView { view } => {
+                s += &format!("DropMaterializedView: {}\n", view);
+            }
+            Self::RefreshMaterializedView { view } => {
+                s += &format!("RefreshMaterializedView: {}\n", view);
+            }
+            Self::AddEnumValue { table, column, value } => {
+                s += &format!("AddEnumValue: {}.{} '{}'\n", table, column, value);
+            }
+            Self::ResetSequence { table, column, value } => {
+                s += &format!("ResetSequence: {}.{} {}\n", table, column, value);
+            }
+            Self::AddConstraint { table, name, constraint } => {
+                s += &format!("AddConstraint: {}.{} {}\n", table, name, constraint);
+            }
+            Self::CreateIndex { table, column, predicate } => {
+                s += &format!("CreateIndex: {}.{}", table, column);
+                if let Some(predicate) = predicate {
+                    s += &format!(" where {}", predicate);
+                }
+                s += "\n";
+            }
+            Self::Distinct { source } => {
+                s += "Distinct\n";
+                s += &source.format(indent, false, true);
+            }
+            Self::DropConstraint { table, name } => {
+                s += &format!("DropConstraint: {}.{}\n", table, name);
+            }
+            Self::AddColumn { table, column } => {
+                s += &format!("AddColumn: {}.{}\n", table, column.name);
+            }
+            Self::DropColumn { table, column } => {
+                s += &format!("DropColumn: {}.{}\n", table, column);
+            }
             Self::Filter { source, predicate } => {
                 s += &format!("Filter: {}\n", predicate);
                 s += &source.format(indent, false, true);
             }
-            // Self::HashJoin { left, left_field, right, right_field, outer } => {
-            //     s += &format!(
-            //         "HashJoin: {} on {} = {}\n",
-            //         if *outer { "outer" } else { "inner" },
-            //         match left_field {
-            //             (_, Some((Some(t), n))) => format!("{}.{}", t, n),
-            //             (_, Some((None, n))) => n.clone(),
-            //             (i, None) => format!("left #{}", i),
-            //         },
-            //         match right_field {
-            //             (_, Some((Some(t), n))) => format!("{}.{}", t, n),
-            //             (_, Some((None, n))) => n.clone(),
-            //             (i, None) => format!("right #{}", i),
-            //         },
-            //     );
-            //     s += &left.format(indent.clone(), false, false);
-            //     s += &right.format(indent, false, true);
-            // }
-            // Self::IndexLookup { table, column, alias, values } => {
-            //     s += &format!("IndexLookup: {}", table);
-            //     if let Some(alias) = alias {
-            //         s += &format!(" as {}", alias);
-            //     }
-            //     s += &format!(" column {}", column);
-            //     if !values.is_empty() && values.len() < 10 {
-            //         s += &format!(
-            //             " ({})",
-            //             values.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(", ")
-            //         );
-            //     } else {
-            //         s += &format!(" ({} values)", values.len());
-            //     }
-            //     s += "\n";
-            // }
-            Self::Insert { table, columns: _, expression } => {
-                s += &format!("Insert: {} ({} rows)\n", table, expression.len());
+            Self::HashJoin { left, left_field, right, right_field, outer } => {
+                s += &format!(
+                    "HashJoin: {} on {} = {}\n",
+                    if *outer { "outer" } else { "inner" },
+                    match left_field {
+                        (_, Some((Some(t), n))) => format!("{}.{}", t, n),
+                        (_, Some((None, n))) => n.clone(),
+                        (i, None) => format!("left #{}", i),
+                    },
+                    match right_field {
+                        (_, Some((Some(t), n))) => format!("{}.{}", t, n),
+                        (_, Some((None, n))) => n.clone(),
+                        (i, None) => format!("right #{}", i),
+                    },
+                );
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
+            Self::IndexLookup { table, column, alias, values } => {
+                s += &format!("IndexLookup: {}", table);
+                if let Some(alias) = alias {
+                    s += &format!(" as {}", alias);
+                }
+                s += &format!(" column {}", column);
+                if !values.is_empty() && values.len() < 10 {
+                    s += &format!(
+                        " ({})",
+                        values.iter().map(|k| k.to_string()).collect::<Vec<_>>().join(", ")
+                    );
+                } else {
+                    s += &format!(" ({} values)", values.len());
+                }
+                s += "\n";
+            }
+            Self::Insert { table, columns: _, expression, on_conflict, returning } => {
+                s += &format!("Insert: {} ({} rows)", table, expression.len());
+                s += &Self::format_conflict(on_conflict);
+                s += &Self::format_returning(returning);
+                s += "\n";
+            }
+            Self::InsertSelect { table, columns, source, on_conflict, returning } => {
+                s += &format!("InsertSelect: {}", table);
+                if !columns.is_empty() {
+                    s += &format!(" ({})", columns.join(", "));
+                }
+                s += &Self::format_conflict(on_conflict);
+                s += &Self::format_returning(returning);
+                s += "\n";
+                s += &source.format(indent, false, true);
             }
             Self::KeyLookup { table, alias, keys } => {
                 s += &format!("KeyLookup: {}", table);
@@ -310,10 +674,21 @@ impl Node {
                 }
                 s += "\n";
             }
-            // Self::Limit { source, limit } => {
-            //     s += &format!("Limit: {}\n", limit);
-            //     s += &source.format(indent, false, true);
-            // }
+            Self::Limit { source, limit, offset } => {
+                s += "Limit:";
+                if let Some(limit) = limit {
+                    s += &format!(" {}", limit);
+                }
+                if *offset > 0 {
+                    s += &format!(" offset {}", offset);
+                }
+                s += "\n";
+                s += &source.format(indent, false, true);
+            }
+            Self::MaterializedCte { name, source } => {
+                s += &format!("MaterializedCte: {}\n", name);
+                s += &source.format(indent, false, true);
+            }
             Self::NestedLoopJoin { left, left_size: _, right, predicate, outer } => {
                 s += &format!("NestedLoopJoin: {}", if *outer { "outer" } else { "inner" });
                 if let Some(expr) = predicate {
@@ -323,24 +698,29 @@ impl Node {
                 s += &left.format(indent.clone(), false, false);
                 s += &right.format(indent, false, true);
             }
+            Self::LateralJoin { left, right, predicate, outer } => {
+                s += &format!("LateralJoin: {}", if *outer { "outer" } else { "inner" });
+                if let Some(expr) = predicate {
+                    s += &format!(" on {}", expr);
+                }
+                s += "\n";
+                s += &left.format(indent.clone(), false, false);
+                s += &right.format(indent, false, true);
+            }
             Self::Nothing {} => {
                 s += "Nothing\n";
             }
-            // Self::Offset { source, offset } => {
-            //     s += &format!("Offset: {}\n", offset);
-            //     s += &source.format(indent, false, true);
-            // }
-            // Self::Order { source, orders } => {
-            //     s += &format!(
-            //         "Order: {}\n",
-            //         orders
-            //             .iter()
-            //             .map(|(expr, dir)| format!("{} {}", expr, dir))
-            //             .collect::<Vec<_>>()
-            //             .join(", ")
-            //     );
-            //     s += &source.format(indent, false, true);
-            // }
+            Self::Order { source, orders } => {
+                s += &format!(
+                    "Order: {}\n",
+                    orders
+                        .iter()
+                        .map(|(expr, dir)| format!("{} {}", expr, dir))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                s += &source.format(indent, false, true);
+            }
             Self::Projection { source, expressions } => {
                 s += &format!(
                     "Projection: {}\n",
@@ -352,6 +732,14 @@ impl Node {
                 );
                 s += &source.format(indent, false, true);
             }
+            Self::Sample { source, probability, seed } => {
+                s += &format!("Sample: bernoulli {}%", probability * 100.0);
+                if let Some(seed) = seed {
+                    s += &format!(" repeatable {}", seed);
+                }
+                s += "\n";
+                s += &source.format(indent, false, true);
+            }
             Self::Scan { table, alias, filter } => {
                 s += &format!("Scan: {}", table);
                 if let Some(alias) = alias {
@@ -362,9 +750,13 @@ impl Node {
                 }
                 s += "\n";
             }
-            Self::Update { source, table, expressions } => {
+            Self::SelectInto { table, source, .. } => {
+                s += &format!("SelectInto: {}\n", table);
+                s += &source.format(indent, false, true);
+            }
+            Self::Update { source, table, expressions, returning } => {
                 s += &format!(
-                    "Update: {} ({})\n",
+                    "Update: {} ({}){}\n",
                     table,
                     expressions
                         .iter()
@@ -374,7 +766,8 @@ impl Node {
                             e
                         ))
                         .collect::<Vec<_>>()
-                        .join(",")
+                        .join(","),
+                    Self::format_returning(returning),
                 );
                 s += &source.format(indent, false, true);
             }
@@ -384,6 +777,49 @@ impl Node {
         }
         s
     }
+
+    /// Formats an INSERT conflict action, if any, as a suffix of the node.
+    fn format_conflict(on_conflict: &InsertConflictAction) -> String {
+        match on_conflict {
+            InsertConflictAction::Error => String::new(),
+            InsertConflictAction::DoNothing => " on conflict do nothing".into(),
+            InsertConflictAction::DoUpdate(set) => format!(
+                " on conflict do update ({})",
+                set.iter()
+                    .map(|(i, l, e)| {
+                        format!("{}={}", l.clone().unwrap_or_else(|| format!("#{}", i)), e)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",")
+            ),
+        }
+    }
+
+    /// Transforms the expressions of a RETURNING clause, see Expression::transform().
+    fn transform_returning<B, A>(
+        returning: Option<Returning>,
+        before: &B,
+        after: &A,
+    ) -> Result<Option<Returning>>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        returning
+            .map(|r| r.into_iter().map(|(e, l)| Ok((e.transform(before, after)?, l))).collect())
+            .transpose()
+    }
+
+    /// Formats a RETURNING clause, if any, as a suffix of the node.
+    fn format_returning(returning: &Option<Returning>) -> String {
+        match returning {
+            Some(returning) => format!(
+                " returning {}",
+                returning.iter().map(|(e, _)| e.to_string()).collect::<Vec<_>>().join(", ")
+            ),
+            None => String::new(),
+        }
+    }
 }
 
 impl Display for Node {
@@ -392,6 +828,22 @@ impl Display for Node {
     }
 }
 
+/// A sort order. Nulls sort last in ascending order and first in descending order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Asc => "asc",
+            Self::Desc => "desc",
+        })
+    }
+}
+
 /// An aggregate operation
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Aggregate {
@@ -400,4 +852,179 @@ pub enum Aggregate {
     Max,
     Min,
     Sum,
-}
\ No newline at end of file
+}
+
+impl Aggregate {
+    /// Returns the aggregate with the given function name, if any.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "avg" => Some(Self::Average),
+            "count" => Some(Self::Count),
+            "max" => Some(Self::Max),
+            "min" => Some(Self::Min),
+            "sum" => Some(Self::Sum),
+            _ => None,
+        }
+    }
+}
+
+/// An aggregate function applied to an expression. Null values are skipped, so COUNT(*) is
+/// represented as the count of a non-null constant.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AggregateExpr {
+    Count(Box<Expression>),
+    Sum(Box<Expression>),
+    Min(Box<Expression>),
+    Max(Box<Expression>),
+    Avg(Box<Expression>),
+}
+
+impl AggregateExpr {
+    /// Creates an aggregate expression for an aggregate operation.
+    pub fn new(aggregate: Aggregate, expr: Expression) -> Self {
+        let expr = Box::new(expr);
+        match aggregate {
+            Aggregate::Average => Self::Avg(expr),
+            Aggregate::Count => Self::Count(expr),
+            Aggregate::Max => Self::Max(expr),
+            Aggregate::Min => Self::Min(expr),
+            Aggregate::Sum => Self::Sum(expr),
+        }
+    }
+
+    /// Returns the name of the aggregate function, which also names its output column.
+    pub fn function_name(&self) -> &'static str {
+        match self {
+            Self::Count(_) => "count",
+            Self::Sum(_) => "sum",
+            Self::Min(_) => "min",
+            Self::Max(_) => "max",
+            Self::Avg(_) => "avg",
+        }
+    }
+
+    /// Transforms the aggregated expression, see Expression::transform().
+    pub fn transform<B, A>(self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        Ok(match self {
+            Self::Count(expr) => Self::Count(expr.transform(before, after)?.into()),
+            Self::Sum(expr) => Self::Sum(expr.transform(before, after)?.into()),
+            Self::Min(expr) => Self::Min(expr.transform(before, after)?.into()),
+            Self::Max(expr) => Self::Max(expr.transform(before, after)?.into()),
+            Self::Avg(expr) => Self::Avg(expr.transform(before, after)?.into()),
+        })
+    }
+}
+
+impl Display for AggregateExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count(expr)
+            | Self::Sum(expr)
+            | Self::Min(expr)
+            | Self::Max(expr)
+            | Self::Avg(expr) => write!(f, "{}({})", self.function_name(), expr),
+        }
+    }
+}
+
+/// A window function
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WindowFunc {
+    /// The 1-based position of the row in its partition.
+    RowNumber,
+    /// The position of the first row with the same order values, leaving gaps after ties.
+    Rank,
+    /// The number of distinct order values up to and including the row, without gaps.
+    DenseRank,
+    /// The value of the expression the given number of rows earlier in the partition, or NULL
+    /// if there is no such row.
+    Lag(Box<Expression>, i64),
+    /// The value of the expression the given number of rows later in the partition, or NULL
+    /// if there is no such row.
+    Lead(Box<Expression>, i64),
+}
+
+impl WindowFunc {
+    /// Returns the name of the window function, which also names its output column.
+    pub fn function_name(&self) -> &'static str {
+        match self {
+            Self::RowNumber => "row_number",
+            Self::Rank => "rank",
+            Self::DenseRank => "dense_rank",
+            Self::Lag(..) => "lag",
+            Self::Lead(..) => "lead",
+        }
+    }
+}
+
+/// A window function computed over the rows of each partition, sorted in the given order.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowFunctionExpr {
+    pub func: WindowFunc,
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<(Expression, SortOrder)>,
+}
+
+impl WindowFunctionExpr {
+    /// Transforms the function's expressions, see Expression::transform().
+    pub fn transform<B, A>(self, before: &B, after: &A) -> Result<Self>
+    where
+        B: Fn(Expression) -> Result<Expression>,
+        A: Fn(Expression) -> Result<Expression>,
+    {
+        Ok(Self {
+            func: match self.func {
+                WindowFunc::Lag(expr, offset) => {
+                    WindowFunc::Lag(expr.transform(before, after)?.into(), offset)
+                }
+                WindowFunc::Lead(expr, offset) => {
+                    WindowFunc::Lead(expr.transform(before, after)?.into(), offset)
+                }
+                func => func,
+            },
+            partition_by: self
+                .partition_by
+                .into_iter()
+                .map(|e| e.transform(before, after))
+                .collect::<Result<_>>()?,
+            order_by: self
+                .order_by
+                .into_iter()
+                .map(|(e, o)| e.transform(before, after).map(|e| (e, o)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
+impl Display for WindowFunctionExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.func {
+            WindowFunc::Lag(expr, offset) | WindowFunc::Lead(expr, offset) => {
+                write!(f, "{}({}, {})", self.func.function_name(), expr, offset)?
+            }
+            func => write!(f, "{}()", func.function_name())?,
+        }
+        let mut parts = Vec::new();
+        if !self.partition_by.is_empty() {
+            parts.push(format!(
+                "partition by {}",
+                self.partition_by.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ")
+            ));
+        }
+        if !self.order_by.is_empty() {
+            parts.push(format!(
+                "order by {}",
+                self.order_by
+                    .iter()
+                    .map(|(expr, order)| format!("{} {}", expr, order))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        write!(f, " over ({})", parts.join(" "))
+    }
+}
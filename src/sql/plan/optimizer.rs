@@ -1,4 +1,7 @@
+use std::collections::HashMap;
+
 use crate::error::Result;
+use crate::sql::schema::Catalog;
 use crate::sql::types::{Expression, Value};
 use super::Node;
 
@@ -9,26 +12,15 @@ pub trait Optimizer {
 }
 
 /// A constant folding optimizer, which replaces constant expressions with their evaluated value,
-/// to prevent it from being re-evaluated over and over again during plan execution.
+/// to prevent it from being re-evaluated over and over again during plan execution. See
+/// Expression::fold_constants().
 pub struct ConstantFolder;
 
 impl Optimizer for ConstantFolder {
     fn optimize(&self, node: Node) -> Result<Node> {
-        node.transform(
-            &|n| Ok(n),
-            &|n| {
-                n.transform_expressions(
-                    &|expr| {
-                        if !expr.contains(&|e| matches!(e, Expression::Field(_, _))) {
-                            Ok(Expression::Constant(expr.evaluate(None)?))
-                        } else {
-                            Ok(expr)
-                        }
-                    },
-                    &|expr| Ok(expr),
-                )
-            },
-        )
+        // Folded expressions are left as is by further folding, so descending into them after
+        // folding the root expression is a no-op.
+        node.transform(&|n| Ok(n), &|n| n.transform_expressions(&|e| e.fold_constants(), &Ok))
     }
 }
 
@@ -43,7 +35,15 @@ impl Optimizer for FilterPushdown {
                     // We don't replace the filter node here, since doing so would cause transform()
                     // to skip the source as it won't reapply the transform to the "same" node.
                     // We leave a noop filter node instead, which will be cleaned up by NoopCleaner.
-                    if let Some(remainder) = self.pushdown(predicate, &mut *source) {
+                    // Subqueries are only evaluated by filter nodes, so they're never pushed down.
+                    let (keep, push): (Vec<_>, Vec<_>) = conjuncts(predicate)
+                        .into_iter()
+                        .partition(Expression::contains_subquery);
+                    let remainder = match conjoin(push) {
+                        Some(push) => self.pushdown(push, &mut source)?,
+                        None => None,
+                    };
+                    if let Some(remainder) = conjoin(keep.into_iter().chain(remainder).collect()) {
                         Ok(Node::Filter { source, predicate: remainder })
                     } else {
                         Ok(Node::Filter {
@@ -59,7 +59,8 @@ impl Optimizer for FilterPushdown {
                     predicate: Some(predicate),
                     outer,
                 } => {
-                    let predicate = self.pushdown_join(predicate, &mut left, &mut right, left_size);
+                    let predicate =
+                        self.pushdown_join(predicate, &mut left, &mut right, left_size, outer)?;
                     Ok(Node::NestedLoopJoin { left, left_size, right, predicate, outer })
                 }
                 n => Ok(n),
@@ -71,19 +72,433 @@ impl Optimizer for FilterPushdown {
 
 impl FilterPushdown {
     /// Attempts to push an expression down into a target node, returns any remaining expression.
-    fn pushdown(&self, mut expression: Expression, target: &mut Node) -> Option<Expression> {
-        todo!()
+    fn pushdown(&self, expression: Expression, target: &mut Node) -> Result<Option<Expression>> {
+        Ok(match target {
+            Node::Scan { filter, .. } => {
+                *filter = conjoin(filter.take().into_iter().chain([expression]).collect());
+                None
+            }
+            // Inner join predicates are split up between the join sources by pushdown_join().
+            Node::NestedLoopJoin { predicate, outer: false, .. } => {
+                *predicate = conjoin(predicate.take().into_iter().chain([expression]).collect());
+                None
+            }
+            // Rows of the left source of an outer join are emitted even without a match, so
+            // only the parts referencing nothing but left fields can be pushed into it.
+            Node::NestedLoopJoin { left, left_size, outer: true, .. } => {
+                let (push, keep): (Vec<_>, Vec<_>) =
+                    conjuncts(expression).into_iter().partition(|e| references(e, ..*left_size));
+                let remainder = match conjoin(push) {
+                    Some(push) => self.pushdown(push, left)?,
+                    None => None,
+                };
+                conjoin(keep.into_iter().chain(remainder).collect())
+            }
+            _ => Some(expression),
+        })
     }
 
     /// Attempts to partition a join predicate and push parts of it down into either source,
-    /// returning any remaining expression.
+    /// returning any remaining expression. For outer joins, only the parts referencing nothing
+    /// but right fields can be pushed, since left rows are emitted even without a match.
     fn pushdown_join(
         &self,
         predicate: Expression,
         left: &mut Node,
         right: &mut Node,
         boundary: usize,
-    ) -> Option<Expression> {
-        todo!()
+        outer: bool,
+    ) -> Result<Option<Expression>> {
+        // Right fields are shifted to the right source's own column indexes and back.
+        let shift = |expr: Expression, back: bool| {
+            expr.transform(&Ok, &|e| match e {
+                Expression::Field(i, label) if back => Ok(Expression::Field(i + boundary, label)),
+                Expression::Field(i, label) => Ok(Expression::Field(i - boundary, label)),
+                e => Ok(e),
+            })
+        };
+        let mut remainder = Vec::new();
+        for conjunct in conjuncts(predicate) {
+            if !outer && references(&conjunct, ..boundary) {
+                remainder.extend(self.pushdown(conjunct, left)?);
+            } else if references(&conjunct, boundary..) {
+                if let Some(expr) = self.pushdown(shift(conjunct, false)?, right)? {
+                    remainder.push(shift(expr, true)?);
+                }
+            } else {
+                remainder.push(conjunct);
+            }
+        }
+        Ok(conjoin(remainder))
+    }
+}
+
+/// A noop cleaner, which removes filters and predicates that are always true, e.g. as left
+/// behind by FilterPushdown.
+pub struct NoopCleaner;
+
+impl Optimizer for NoopCleaner {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        let is_noop = |expr: &Option<Expression>| {
+            matches!(expr, Some(Expression::Constant(Value::Boolean(true))))
+        };
+        node.transform(&Ok, &|n| match n {
+            Node::Filter { source, predicate: Expression::Constant(Value::Boolean(true)) } => {
+                Ok(*source)
+            }
+            Node::Scan { table, alias, filter } if is_noop(&filter) => {
+                Ok(Node::Scan { table, alias, filter: None })
+            }
+            Node::NestedLoopJoin { left, left_size, right, predicate, outer }
+                if is_noop(&predicate) =>
+            {
+                Ok(Node::NestedLoopJoin { left, left_size, right, predicate: None, outer })
+            }
+            n => Ok(n),
+        })
+    }
+}
+
+/// A join type optimizer, which replaces nested loop joins on an equality of a left and a right
+/// field with hash joins.
+pub struct JoinType;
+
+impl Optimizer for JoinType {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(&Ok, &|n| match n {
+            Node::NestedLoopJoin {
+                left,
+                left_size,
+                right,
+                predicate: Some(Expression::Equal(lhs, rhs)),
+                outer,
+            } => match (*lhs, *rhs) {
+                (Expression::Field(a, a_label), Expression::Field(b, b_label))
+                    if (a < left_size) != (b < left_size) =>
+                {
+                    let (left_field, right_field) = if a < left_size {
+                        ((a, a_label), (b - left_size, b_label))
+                    } else {
+                        ((b, b_label), (a - left_size, a_label))
+                    };
+                    Ok(Node::HashJoin { left, left_field, right, right_field, outer })
+                }
+                (lhs, rhs) => Ok(Node::NestedLoopJoin {
+                    left,
+                    left_size,
+                    right,
+                    predicate: Some(Expression::Equal(lhs.into(), rhs.into())),
+                    outer,
+                }),
+            },
+            n => Ok(n),
+        })
+    }
+}
+
+/// Splits an expression into its AND-ed conjuncts.
+fn conjuncts(expr: Expression) -> Vec<Expression> {
+    match expr {
+        Expression::And(lhs, rhs) => {
+            let mut exprs = conjuncts(*lhs);
+            exprs.extend(conjuncts(*rhs));
+            exprs
+        }
+        expr => vec![expr],
+    }
+}
+
+/// Joins conjuncts with AND, or returns None if there are none.
+fn conjoin(conjuncts: Vec<Expression>) -> Option<Expression> {
+    conjuncts.into_iter().reduce(|lhs, rhs| Expression::And(lhs.into(), rhs.into()))
+}
+
+/// Returns whether an expression references fields, all of which are in the given range.
+fn references(expr: &Expression, range: impl std::ops::RangeBounds<usize>) -> bool {
+    expr.contains(&|e| matches!(e, Expression::Field(..)))
+        && !expr.contains(&|e| matches!(e, Expression::Field(i, _) if !range.contains(i)))
+}
+
+/// An index selection optimizer, which replaces a filtered table scan with a secondary index
+/// lookup when the filter looks up an indexed column by value. A partial index is only used when
+/// the filter implies its predicate, since it is missing all other rows. If the table has been
+/// analyzed, an index is only used when the lookup is estimated to be cheaper than the scan, and
+/// the cheapest of several usable indexes is chosen. The number of rows looked up is estimated
+/// from the column's value statistics.
+pub struct IndexSelection<'a, C: Catalog> {
+    catalog: &'a C,
+}
+
+impl<'a, C: Catalog> IndexSelection<'a, C> {
+    /// The cost of reading a row via an index lookup, relative to reading it during a table
+    /// scan, since each looked up row is a separate random read.
+    const INDEX_ROW_COST: f64 = 2.0;
+
+    pub fn new(catalog: &'a C) -> Self {
+        Self { catalog }
     }
-}
\ No newline at end of file
+}
+
+impl<'a, C: Catalog> Optimizer for IndexSelection<'a, C> {
+    fn optimize(&self, node: Node) -> Result<Node> {
+        node.transform(
+            &|n| Ok(n),
+            &|n| match n {
+                Node::Scan { table, alias, filter: Some(filter) } => {
+                    match self.select(&table, &filter)? {
+                        Some((column, value)) => Ok(Node::Filter {
+                            source: Box::new(Node::IndexLookup {
+                                table,
+                                alias,
+                                column,
+                                values: vec![value],
+                            }),
+                            predicate: filter,
+                        }),
+                        None => Ok(Node::Scan { table, alias, filter: Some(filter) }),
+                    }
+                }
+                n => Ok(n),
+            },
+        )
+    }
+}
+
+impl<'a, C: Catalog> IndexSelection<'a, C> {
+    /// Selects an index to look up for a table filter, returning the column and value.
+    fn select(&self, table: &str, filter: &Expression) -> Result<Option<(String, Value)>> {
+        let table = self.catalog.assert_read_table(table)?;
+        let conjuncts = Self::conjuncts(filter)
+            .into_iter()
+            .map(Self::unlabeled)
+            .collect::<Result<Vec<_>>>()?;
+        let mut candidates = Vec::new();
+        for (field, value) in conjuncts.iter().filter_map(Self::binding) {
+            let column = match table.columns.get(field) {
+                Some(column) if column.is_indexed => column,
+                _ => continue,
+            };
+            if let Some(predicate) = &column.index_predicate {
+                if !Self::implies(&conjuncts, predicate)? {
+                    continue;
+                }
+            }
+            // Index entries hold stored values, e.g. decimals rather than float literals.
+            if let Ok(value) = column.datatype.coerce(value) {
+                candidates.push((column.name.clone(), value));
+            }
+        }
+        let stats = match self.catalog.get_stats(&table.name)? {
+            Some(stats) => stats,
+            None => return Ok(candidates.into_iter().next()),
+        };
+        let cost = |column: &str, value: &Value| {
+            let index = stats.indexes.get(column);
+            let column = table.get_column_index(column).ok().and_then(|i| stats.columns.get(i));
+            let rows = match (column, index) {
+                // A partial index can't return more rows than it holds.
+                (Some(c), index) => {
+                    let rows = c.selectivity(value) * stats.rows as f64;
+                    index.map_or(rows, |index| rows.min(index.entries as f64))
+                }
+                (None, Some(index)) => index.rows_per_value(),
+                (None, None) => return f64::INFINITY,
+            };
+            rows * Self::INDEX_ROW_COST
+        };
+        Ok(candidates
+            .into_iter()
+            .map(|(column, value)| (cost(&column, &value), column, value))
+            .filter(|(cost, _, _)| *cost < stats.rows as f64)
+            .min_by(|(a, _, _), (b, _, _)| a.total_cmp(b))
+            .map(|(_, column, value)| (column, value)))
+    }
+
+    /// Returns whether the filter conjuncts imply a predicate. Each conjunct of the predicate
+    /// must either be one of the filter conjuncts (ignoring field labels), or only reference
+    /// fields that the filter binds to constants and evaluate to true for those constants.
+    fn implies(filter: &[Expression], predicate: &Expression) -> Result<bool> {
+        let bindings: HashMap<usize, Value> = filter.iter().filter_map(Self::binding).collect();
+        for conjunct in Self::conjuncts(predicate) {
+            let conjunct = Self::unlabeled(conjunct)?;
+            if filter.contains(&conjunct) {
+                continue;
+            }
+            let unbound = conjunct.contains(&|e| match e {
+                Expression::Field(i, _) => !bindings.contains_key(i),
+                Expression::Outer(_, _) => true,
+                _ => false,
+            });
+            if unbound {
+                return Ok(false);
+            }
+            let conjunct = conjunct.transform(&|e| Ok(e), &|e| match e {
+                Expression::Field(i, _) => Ok(Expression::Constant(bindings[&i].clone())),
+                e => Ok(e),
+            })?;
+            if !matches!(conjunct.evaluate(None), Ok(Value::Boolean(true))) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Splits an expression into its AND-ed conjuncts.
+    fn conjuncts(expr: &Expression) -> Vec<&Expression> {
+        match expr {
+            Expression::And(lhs, rhs) => {
+                let mut conjuncts = Self::conjuncts(lhs);
+                conjuncts.extend(Self::conjuncts(rhs));
+                conjuncts
+            }
+            expr => vec![expr],
+        }
+    }
+
+    /// Strips field labels, so that expressions compare equal regardless of table aliases.
+    fn unlabeled(expr: &Expression) -> Result<Expression> {
+        expr.clone().transform(&|e| Ok(e), &|e| match e {
+            Expression::Field(i, _) => Ok(Expression::Field(i, None)),
+            e => Ok(e),
+        })
+    }
+
+    /// Returns the field and constant value of a field = constant expression.
+    fn binding(expr: &Expression) -> Option<(usize, Value)> {
+        match expr {
+            Expression::Equal(lhs, rhs) => match (&**lhs, &**rhs) {
+                (Expression::Field(i, _), Expression::Constant(v))
+                | (Expression::Constant(v), Expression::Field(i, _)) => Some((*i, v.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::concurrency::{Mode, MVCC};
+    use crate::sql::engine::{KvSqlEngine, SqlEngine as _, SqlTxn};
+    use crate::sql::execution::ResultSet;
+    use crate::sql::parser::Parser;
+    use crate::sql::plan::Plan;
+    use crate::sql::schema::IndexStats;
+    use crate::storage::kv::StdBPlusTree;
+
+    fn plan<T: SqlTxn>(txn: &mut T, query: &str) -> Result<Plan> {
+        Plan::build(Parser::new(query).parse()?, txn)?.optimize(txn)
+    }
+
+    #[test]
+    fn pushdown_scan_filter() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        for query in [
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER INDEX, flag BOOLEAN)",
+            "INSERT INTO t VALUES (1, 1, TRUE), (2, 1, FALSE), (3, 2, TRUE), (4, NULL, TRUE)",
+        ] {
+            plan(&mut txn, query)?.execute(&mut txn)?;
+        }
+
+        // The predicate is evaluated during the scan, which only yields matching rows.
+        let filter = match plan(&mut txn, "SELECT * FROM t WHERE flag AND id > 1")?.0 {
+            Node::Scan { filter: Some(filter), .. } => filter,
+            node => panic!("expected filtered scan, got {:?}", node),
+        };
+        let rows = txn.scan("t", Some(filter))?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            vec![
+                vec![Value::Integer(3), Value::Integer(2), Value::Boolean(true)],
+                vec![Value::Integer(4), Value::Null, Value::Boolean(true)],
+            ],
+            rows
+        );
+
+        // Subqueries are only evaluated by filter nodes, so they're kept out of the scan.
+        match plan(&mut txn, "SELECT * FROM t WHERE flag AND id IN (SELECT value FROM t)")?.0 {
+            Node::Filter { source, predicate } => {
+                assert!(predicate.contains_subquery());
+                assert!(matches!(*source, Node::Scan { filter: Some(f), .. }
+                    if !f.contains_subquery()));
+            }
+            node => panic!("expected filter, got {:?}", node),
+        }
+
+        // An equality on an indexed column becomes an index lookup of the value.
+        match plan(&mut txn, "SELECT * FROM t WHERE value = 2")?.0 {
+            Node::Filter { source, .. } => match *source {
+                Node::IndexLookup { column, values, .. } => {
+                    assert_eq!("value", column);
+                    assert_eq!(vec![Value::Integer(2)], values);
+                }
+                node => panic!("expected index lookup, got {:?}", node),
+            },
+            node => panic!("expected filter, got {:?}", node),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn analyze_index_selection() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        let mut txn = engine.begin(Mode::ReadWrite)?;
+        let rows = (0..100)
+            .map(|i| match i % 10 {
+                0 => format!("({}, {}, NULL)", i, i < 95),
+                _ => format!("({}, {}, {})", i, i < 95, i % 50),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        for query in [
+            "CREATE TABLE t (id INTEGER PRIMARY KEY, flag BOOLEAN INDEX, code INTEGER INDEX)",
+            &format!("INSERT INTO t VALUES {}", rows),
+        ] {
+            plan(&mut txn, query)?.execute(&mut txn)?;
+        }
+        let lookup = |txn: &mut _, filter: &str| -> Result<Option<String>> {
+            Ok(match plan(txn, &format!("SELECT * FROM t WHERE {}", filter))?.0 {
+                Node::Filter { source, .. } => match *source {
+                    Node::IndexLookup { column, .. } => Some(column),
+                    node => panic!("expected index lookup, got {:?}", node),
+                },
+                Node::Scan { .. } => None,
+                node => panic!("expected filter or scan, got {:?}", node),
+            })
+        };
+
+        // Without statistics, the first usable index is always used, and rows are counted.
+        assert_eq!(None, txn.get_stats("t")?);
+        assert_eq!(100, txn.row_count("t")?);
+        assert_eq!(Some("flag".into()), lookup(&mut txn, "flag = TRUE")?);
+        assert_eq!(Some("flag".into()), lookup(&mut txn, "flag = TRUE AND code = 7")?);
+
+        let stats = plan(&mut txn, "ANALYZE t")?.execute(&mut txn)?;
+        assert_eq!(ResultSet::Analyze { name: "t".into(), rows: 100 }, stats);
+        let stats = txn.get_stats("t")?.expect("no stats");
+        assert_eq!(100, stats.rows);
+        plan(&mut txn, "INSERT INTO t VALUES (100, TRUE, NULL)")?.execute(&mut txn)?;
+        assert_eq!(100, txn.row_count("t")?);
+        plan(&mut txn, "DELETE FROM t WHERE id = 100")?.execute(&mut txn)?;
+        assert_eq!(100, stats.columns[0].distinct);
+        assert_eq!(
+            vec![(Value::Boolean(true), 0.95), (Value::Boolean(false), 0.05)],
+            stats.columns[1].most_common_values
+        );
+        assert_eq!(0.1, stats.columns[2].null_fraction);
+        assert_eq!(45, stats.columns[2].distinct);
+        assert_eq!(IndexStats { entries: 100, distinct: 2 }, stats.indexes["flag"]);
+
+        // With statistics, only selective lookups use an index, and the most selective one wins.
+        assert_eq!(None, lookup(&mut txn, "flag = TRUE")?);
+        assert_eq!(Some("flag".into()), lookup(&mut txn, "flag = FALSE")?);
+        assert_eq!(Some("code".into()), lookup(&mut txn, "flag = TRUE AND code = 7")?);
+        assert_eq!(Some("code".into()), lookup(&mut txn, "flag = FALSE AND code = 49")?);
+
+        // Dropping a column discards the statistics.
+        plan(&mut txn, "ALTER TABLE t DROP COLUMN code")?.execute(&mut txn)?;
+        assert_eq!(None, txn.get_stats("t")?);
+        Ok(())
+    }
+}
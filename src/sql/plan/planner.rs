@@ -1,27 +1,43 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 
-use crate::sql::types::{Value, Expression};
+use crate::sql::types::{DataType, Value, Expression};
 use crate::error::{Error, Result};
 use crate::sql::schema::Table;
-use crate::sql::schema::{Catalog, Column};
+use crate::sql::schema::{Catalog, Column, Constraint, FkAction};
 use crate::sql::parser::ast;
+use crate::sql::extension::FunctionRegistry;
 
-use super::{Plan, Node, Aggregate};
+use super::{Plan, Node, Aggregate, AggregateExpr, SortOrder, WindowFunc, WindowFunctionExpr};
+use crate::sql::execution::{InsertConflictAction, Returning};
 
 /// A query plan builder.
 pub struct Planner<'a, C: Catalog> {
     catalog: &'a mut C,
+    /// Common table expressions currently in scope, by name.
+    ctes: RefCell<HashMap<String, Cte>>,
+}
+
+/// A planned common table expression.
+#[derive(Clone)]
+struct Cte {
+    /// The plan node producing the CTE rows.
+    node: Node,
+    /// The CTE's column labels.
+    columns: Vec<Option<String>>,
+    /// Whether the CTE is referenced more than once, and should be materialized.
+    materialize: bool,
 }
 
 impl<'a, C: Catalog> Planner<'a, C> {
     /// Creates a new planner.
     pub fn new(catalog: &'a mut C) -> Self {
-        Self { catalog }
+        Self { catalog, ctes: RefCell::new(HashMap::new()) }
     }
 
     /// Builds a plan for a AST statement.
     pub fn build(&mut self, statement: ast::Statement) -> Result<Plan> {
-        Ok(Plan(self.build_statement(statement)?))
+        Ok(Plan(self.insert_casts(self.build_statement(statement)?)?))
     }
 
     /// Builds a plan node for a AST statement.
@@ -34,143 +50,156 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     statement
                 )))
             },
+            ast::Statement::Explain { .. } => {
+                return Err(Error::Internal("Unexpected explain statement".into()))
+            },
 
             // DDL statements (schema changes).
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable {
-                schema: Table::new(
+            ast::Statement::CreateTable { name, columns, constraints } => {
+                Node::CreateTable { schema: self.build_table(name, columns, constraints)? }
+            }
+            ast::Statement::DropTable(table) => Node::DropTable { table },
+            ast::Statement::Analyze(table) => Node::Analyze { table },
+            ast::Statement::LoadExtension(path) => Node::LoadExtension { path },
+            ast::Statement::DropExtension(name) => Node::DropExtension { name },
+            ast::Statement::CreateMaterializedView { name, query, sql } => {
+                let source = self.insert_casts(self.build_query(&mut Environment::new(), *query)?)?;
+                Node::CreateMaterializedView {
                     name,
-                    columns
-                        .into_iter()
-                        .map(|c| {
-                            let is_nullable = c.is_nullable.unwrap_or(!c.is_primary_key);
-                            let default = match c.default {
-                                Some(expr) => Some(self.evaluate_constant(expr)?),
-                                None if is_nullable => Some(Value::Null),
-                                None => None,
-                            };
-                            Ok(Column {
-                                name: c.name,
-                                datatype: c.datatype,
-                                is_primary_key: c.is_primary_key,
-                                is_nullable,
-                                default,
-                                is_unique: c.is_unique || c.is_primary_key,
-                                is_indexed: c.is_indexed && !c.is_primary_key,
-                                references: c.references,
-                            })
-                        })
-                        .collect::<Result<_>>()?,
+                    datatypes: self.infer_datatypes(&source)?,
+                    source: Box::new(source),
+                    query: sql,
+                }
+            },
+            ast::Statement::DropMaterializedView(view) => Node::DropMaterializedView { view },
+            ast::Statement::RefreshMaterializedView(view) => {
+                Node::RefreshMaterializedView { view }
+            },
+            ast::Statement::AddEnumValue { table, column, value } => {
+                Node::AddEnumValue { table, column, value }
+            }
+            ast::Statement::ResetSequence { table, column, value } => {
+                match self.evaluate_constant(value)? {
+                    Value::Integer(value) => Node::ResetSequence { table, column, value },
+                    value => {
+                        return Err(Error::Value(format!(
+                            "Sequence value must be an integer, got {}",
+                            value.to_sql_literal()
+                        )))
+                    }
+                }
+            }
+            ast::Statement::CreateIndex { table, column, predicate } => {
+                let predicate = match predicate {
+                    Some(expr) => {
+                        let schema = self.catalog.assert_read_table(&table)?;
+                        Some(self.build_expression(&mut Environment::from_table(schema)?, expr)?)
+                    }
+                    None => None,
+                };
+                Node::CreateIndex { table, column, predicate }
+            }
+            ast::Statement::AddConstraint { table, name, constraint } => Node::AddConstraint {
+                constraint: self.build_constraint(
+                    &self.catalog.assert_read_table(&table)?,
+                    constraint,
                 )?,
+                table,
+                name,
             },
-            ast::Statement::DropTable(table) => Node::DropTable { table },
+            ast::Statement::DropConstraint { table, name } => Node::DropConstraint { table, name },
+            ast::Statement::AddColumn { table, column } => {
+                if !column.checks.is_empty() {
+                    return Err(Error::Value(format!(
+                        "Can't add column {} with a check constraint, use ADD CONSTRAINT",
+                        column.name
+                    )));
+                }
+                let actions = (column.on_delete, column.on_update);
+                if actions != (FkAction::NoAction, FkAction::NoAction) {
+                    return Err(Error::Value(format!(
+                        "Can't add column {} with foreign key actions, use ADD CONSTRAINT",
+                        column.name
+                    )));
+                }
+                Node::AddColumn { table, column: self.build_column(column)? }
+            }
+            ast::Statement::DropColumn { table, column } => Node::DropColumn { table, column },
 
             // DML statements (mutations).
-            ast::Statement::Insert { table, columns, values } => Node::Insert {
-                table,
-                columns: columns.unwrap_or_else(Vec::new),
-                expression: values
-                    .into_iter()
-                    .map(|exprs| {
-                        exprs
-                            .into_iter()
-                            .map(|expr| {
-                                self.build_expression(&mut Environment::constant(), expr)
-                            })
-                            .collect::<Result<_>>()
-                    })
-                    .collect::<Result<_>>()?,
-            },
+            ast::Statement::Insert { table, columns, values, on_conflict, returning } => {
+                Node::Insert {
+                    on_conflict: self.build_conflict_action(&table, on_conflict)?,
+                    returning: self.build_returning(&table, returning)?,
+                    table,
+                    columns: columns.unwrap_or_else(Vec::new),
+                    expression: values
+                        .into_iter()
+                        .map(|exprs| {
+                            exprs
+                                .into_iter()
+                                .map(|expr| {
+                                    self.build_expression(&mut Environment::constant(), expr)
+                                })
+                                .collect::<Result<_>>()
+                        })
+                        .collect::<Result<_>>()?,
+                }
+            }
+            ast::Statement::InsertSelect { table, columns, query, on_conflict, returning } => {
+                let source = self.build_statement(*query)?;
+                let columns = columns.unwrap_or_default();
+                self.validate_insert_source(&table, &columns, &source)?;
+                Node::InsertSelect {
+                    on_conflict: self.build_conflict_action(&table, on_conflict)?,
+                    returning: self.build_returning(&table, returning)?,
+                    table,
+                    columns,
+                    source: Box::new(source),
+                }
+            }
             // TODO: Read.
-            ast::Statement::Select {
-                mut select,
-                from,
-                r#where,
-                group_by,
-                mut having,
-                mut order,
-                offset,
-                limit,
-            } => {
-                let environment = &mut Environment::new();
-
-                // Build the FROM clause.
-                let mut node = match (from.is_empty(), select.is_empty()) {
-                    (false, _) => self.build_from_clause(environment, from)?,
-                    (true, false) => Node::Nothing,
-                    (true, true) => return Err(Error::Value("Can't select * without a table".into())),
-                };
-
-                // Build the WHERE clause.
-                if let Some(expr) = r#where {
-                    node = Node::Filter {
-                        source: Box::new(node),
-                        predicate: self.build_expression(environment, expr)?,
-                    };
+            statement @ ast::Statement::Select { .. } => {
+                self.build_query(&mut Environment::new(), statement)?
+            },
+            ast::Statement::SelectInto { table, primary_key, query } => {
+                let source = self.build_query(&mut Environment::new(), *query)?;
+                Node::SelectInto {
+                    table,
+                    primary_key,
+                    datatypes: self.infer_datatypes(&source)?,
+                    source: Box::new(source),
                 }
-
-                // Build the SELECT clause.
-                let mut hidden = 0;
-                if !select.is_empty() {
-                    // Inject hidden SELECT columns for fields and aggregates used in ORDER BY and
-                    // HAVING expressions but not present in existing SELECT output. These will be
-                    // removed again by a later projection.
-                    
-                    // if let Some(ref mut expr) = having {
-                    //     hidden += self.inject_hidden(expr, &mut select)?;
-                    // }
-                    // for (expr, _) in order.iter_mut() {
-                    //     hidden += self.inject_hidden(expr, &mut select)?;
-                    // }
-
-                    // Extract any aggregate functions and GROUP BY expressions, replacing them with
-                    // Column placeholders. Aggregations are handled by evaluating group expressions
-                    // and aggregate function arguments in a pre-projection, passing the results
-                    // to an aggregation node, and then evaluating the final SELECT expressions
-                    // in the post-projection. For example:
-                    //
-                    // SELECT (MAX(rating * 100) - MIN(rating * 100)) / 100
-                    // FROM movies
-                    // GROUP BY released - 2000
-                    //
-                    // Results in the following nodes:
-                    //
-                    // - Projection: rating * 100, rating * 100, released - 2000
-                    // - Aggregation: max(#0), min(#1) group by #2
-                    // - Projection: (#0 - #1) / 100
-
-                    // let aggregates = self.extract_aggregates(&mut select)?;
-                    // let groups = self.extract_groups(&mut select, group_by, aggregates.len())?;
-                    // if !aggregates.is_empty() || !groups.is_empty() {
-                    //     node = self.build_aggregation(environment, node, groups, aggregates)?;
-                    // }
-
-                    // Build the remaining non-aggregate projection.
-
-                    // let expressions: Vec<(Expression, Option<String>)> = select
-                    //     .into_iter()
-                    //     .map(|(e, l)| Ok((self.build_expression(environment, e)?, l)))
-                    //     .collect::<Result<_>>()?;
-                    // environment.project(&expressions)?;
-                    // node = Node::Projection { source: Box::new(node), expressions };
-                };
-
-                // TODO: Build HAVING clause.
-
-                // TODO: Build ORDER clause.
-
-                // TODO: Build OFFSET clause.
-
-                // TODO: Build LIMIT clause.
-
-                // TODO: Remove any hidden columns.
-
-                node
             },
-            ast::Statement::Update { table, set, r#where } => {
+            ast::Statement::With { ctes, query } => {
+                // CTEs may be referenced by the main query and by any later CTE. The CTEs are
+                // scoped to this statement, so restore the outer scope once done.
+                let mut counts = CteRefCounts::default();
+                counts.count(&query);
+                for (_, cte) in ctes.iter() {
+                    counts.count(cte);
+                }
+                let outer = self.ctes.borrow().clone();
+                let result = ctes
+                    .into_iter()
+                    .try_for_each(|(name, cte)| {
+                        let mut environment = Environment::new();
+                        let node = self.build_query(&mut environment, cte)?;
+                        let columns = environment.columns.into_iter().map(|(_, l)| l).collect();
+                        let materialize = counts.get(&name) > 1;
+                        self.ctes.borrow_mut().insert(name, Cte { node, columns, materialize });
+                        Ok(())
+                    })
+                    .and_then(|_| self.build_query(&mut Environment::new(), *query));
+                *self.ctes.borrow_mut() = outer;
+                result?
+            },
+            ast::Statement::Update { table, set, r#where, returning } => {
                 let environment = &mut Environment::from_table(
                     self.catalog.assert_read_table(&table)?
                 )?;
                 Node::Update {
+                    returning: self.build_returning(&table, returning)?,
                     table: table.clone(),
                     source: Box::new(Node::Scan {
                         table,
@@ -191,11 +220,12 @@ impl<'a, C: Catalog> Planner<'a, C> {
                         .collect::<Result<_>>()?,
                 }
             },
-            ast::Statement::Delete { table, r#where } => {
+            ast::Statement::Delete { table, r#where, returning } => {
                 let environment = &mut Environment::from_table(
                     self.catalog.assert_read_table(&table)?
                 )?;
                 Node::Delete {
+                    returning: self.build_returning(&table, returning)?,
                     table: table.clone(),
                     source: Box::new(Node::Scan {
                         table,
@@ -209,6 +239,247 @@ impl<'a, C: Catalog> Planner<'a, C> {
         })
     }
 
+    /// Builds a plan node for a SELECT query, populating the environment with its output columns.
+    fn build_query(&self, environment: &mut Environment, statement: ast::Statement) -> Result<Node> {
+        let (distinct, mut select, from, r#where, group_by, mut having, mut order, offset, limit) =
+            match statement {
+                ast::Statement::Select {
+                    distinct, select, from, r#where, group_by, having, order, offset, limit,
+                } => (distinct, select, from, r#where, group_by, having, order, offset, limit),
+                statement => {
+                    return Err(Error::Internal(format!("Unexpected query statement {:?}", statement)))
+                }
+            };
+
+        // Build the FROM clause.
+        let mut node = match (from.is_empty(), select.is_empty()) {
+            (false, _) => self.build_from_clause(environment, from)?,
+            (true, false) => Node::Nothing,
+            (true, true) => return Err(Error::Value("Can't select * without a table".into())),
+        };
+
+        // Build the WHERE clause.
+        if let Some(expr) = r#where {
+            node = Node::Filter {
+                predicate: self.build_expression(environment, expr)?,
+                source: Box::new(node),
+            };
+        }
+
+        // Build the SELECT clause.
+        let mut hidden = 0;
+        if !select.is_empty() {
+            // Inject hidden SELECT columns for fields and aggregates used in ORDER BY and
+            // HAVING expressions but not present in existing SELECT output. These will be
+            // removed again by a later projection.
+            if let Some(ref mut expr) = having {
+                if Self::is_window(expr) {
+                    return Err(Error::Value("Window functions are not allowed in HAVING".into()));
+                }
+                hidden += self.inject_hidden(expr, &mut select)?;
+            }
+            let having_hidden = hidden;
+            for (expr, _) in order.iter_mut() {
+                hidden += self.inject_hidden(expr, &mut select)?;
+            }
+            if distinct && hidden > having_hidden {
+                return Err(Error::Value(
+                    "ORDER BY expressions must be in the SELECT list for SELECT DISTINCT".into(),
+                ));
+            }
+
+            // Extract any aggregate functions and GROUP BY expressions, replacing them with
+            // Column placeholders. The aggregation node evaluates the group expressions and
+            // aggregate function arguments on the source rows, and emits the aggregates followed
+            // by the group values, on which the final SELECT expressions are evaluated by a
+            // projection. For example:
+            //
+            // SELECT (MAX(rating * 100) - MIN(rating * 100)) / 100
+            // FROM movies
+            // GROUP BY released - 2000
+            //
+            // Results in the following nodes:
+            //
+            // - Aggregation: max(rating * 100), min(rating * 100) group by released - 2000
+            // - Projection: (#0 - #1) / 100
+            let aggregates = self.extract_aggregates(&mut select)?;
+            let groups = self.extract_groups(&mut select, group_by, aggregates.len())?;
+            if !aggregates.is_empty() || !groups.is_empty() {
+                let source = environment.clone();
+                node = self.build_aggregation(environment, node, groups, aggregates)?;
+                for (expr, _) in &select {
+                    self.assert_aggregated(&source, environment, expr)?;
+                }
+            } else if having.is_some() {
+                return Err(Error::Value("HAVING requires GROUP BY or aggregate functions".into()));
+            }
+
+            // Extract any window functions, which are computed on the (aggregated) rows by a
+            // window node and replaced with Column placeholders for the window columns it
+            // appends to the rows.
+            let windows = self.extract_windows(&mut select, environment.len())?;
+            if !windows.is_empty() {
+                node = self.build_window(environment, node, windows)?;
+            }
+
+            // Build the remaining non-aggregate projection.
+            let expressions: Vec<(Expression, Option<String>)> = select
+                .into_iter()
+                .map(|(e, l)| Ok((self.build_expression(environment, e)?, l)))
+                .collect::<Result<_>>()?;
+            environment.project(&expressions)?;
+            let expressions = self.label_projection(&node, expressions)?;
+            node = Node::Projection { source: Box::new(node), expressions };
+        } else if !group_by.is_empty() || having.is_some() {
+            return Err(Error::Value("Can't select * with GROUP BY or HAVING".into()));
+        }
+
+        // Build the HAVING clause, which filters the aggregated rows.
+        if let Some(expr) = having {
+            node = Node::Filter {
+                predicate: self.build_expression(environment, expr)?,
+                source: Box::new(node),
+            };
+        }
+
+        // Build the DISTINCT clause, removing any hidden HAVING columns first since they would
+        // otherwise make rows distinct.
+        if distinct {
+            if hidden > 0 {
+                node = self.remove_hidden(environment, node, hidden)?;
+                hidden = 0;
+            }
+            node = Node::Distinct { source: Box::new(node) };
+        }
+
+        // Build the ORDER clause.
+        if !order.is_empty() {
+            node = Node::Order {
+                source: Box::new(node),
+                orders: order
+                    .into_iter()
+                    .map(|(e, o)| {
+                        let order = match o {
+                            ast::Order::Ascending => SortOrder::Asc,
+                            ast::Order::Descending => SortOrder::Desc,
+                        };
+                        Ok((self.build_expression(environment, e)?, order))
+                    })
+                    .collect::<Result<_>>()?,
+            };
+        }
+
+        // Build the OFFSET and LIMIT clauses.
+        if offset.is_some() || limit.is_some() {
+            let evaluate_count = |clause: &str, expr: ast::Expression| {
+                match self.evaluate_constant(expr)? {
+                    Value::Integer(i) if i >= 0 => Ok(i as u64),
+                    value => {
+                        Err(Error::Value(format!("Invalid {} {}", clause, value.to_sql_literal())))
+                    }
+                }
+            };
+            let offset = offset.map(|e| evaluate_count("offset", e)).transpose()?;
+            let limit = limit.map(|e| evaluate_count("limit", e)).transpose()?;
+            node = Node::Limit { source: Box::new(node), limit, offset: offset.unwrap_or(0) };
+        }
+
+        // Remove any hidden columns.
+        if hidden > 0 {
+            node = self.remove_hidden(environment, node, hidden)?;
+        }
+
+        self.qualify_duplicate_columns(environment, node)
+    }
+
+    /// Labels unlabeled computed expressions of a projection over the given source node with
+    /// their column names, see Expression::to_column_name(). These are rendered here from the
+    /// expressions as written, since the optimizer may later fold them, e.g. 1 + 2 into 3.
+    /// Unlabeled field references in them are named by the source columns, e.g. aggregates.
+    fn label_projection(
+        &self,
+        source: &Node,
+        expressions: Vec<(Expression, Option<String>)>,
+    ) -> Result<Vec<(Expression, Option<String>)>> {
+        let names = self.infer_column_names(source)?;
+        expressions
+            .into_iter()
+            .map(|(expr, label)| match (expr, label) {
+                (expr, Some(label)) => Ok((expr, Some(label))),
+                (expr @ Expression::Field(..), None) => Ok((expr, None)),
+                (expr, None) => {
+                    let name = expr
+                        .clone()
+                        .transform(&Ok, &|e| match e {
+                            Expression::Field(i, None) => match names.get(i) {
+                                Some(Some(name)) => {
+                                    Ok(Expression::Field(i, Some((None, name.clone()))))
+                                }
+                                _ => Ok(Expression::Field(i, None)),
+                            },
+                            e => Ok(e),
+                        })?
+                        .to_column_name();
+                    Ok((expr, Some(name)))
+                }
+            })
+            .collect()
+    }
+
+    /// Qualifies output columns whose names would otherwise be ambiguous with their table
+    /// name or alias, e.g. a.id and b.id for SELECT * FROM p a JOIN p b. Columns without a
+    /// table are left as is.
+    fn qualify_duplicate_columns(&self, environment: &Environment, node: Node) -> Result<Node> {
+        let names = self.infer_column_names(&node)?;
+        let mut labels = Vec::with_capacity(names.len());
+        for (i, name) in names.iter().enumerate() {
+            labels.push(match (environment.get_column(i)?, name) {
+                ((Some(table), _), Some(name))
+                    if names.iter().filter(|n| n.as_ref() == Some(name)).count() > 1 =>
+                {
+                    Some(format!("{}.{}", table, name))
+                }
+                _ => None,
+            });
+        }
+        if labels.iter().all(|l| l.is_none()) {
+            return Ok(node);
+        }
+        let node = match node {
+            Node::Projection { source, expressions } => Node::Projection {
+                source,
+                expressions: expressions
+                    .into_iter()
+                    .zip(labels)
+                    .map(|((expr, label), qualified)| (expr, qualified.or(label)))
+                    .collect(),
+            },
+            node => Node::Projection {
+                source: Box::new(node),
+                expressions: labels
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, label)| Ok((Expression::Field(i, environment.get_label(i)?), label)))
+                    .collect::<Result<_>>()?,
+            },
+        };
+        Ok(node)
+    }
+
+    /// Removes the given number of trailing hidden columns with a projection.
+    fn remove_hidden(
+        &self,
+        environment: &mut Environment,
+        node: Node,
+        hidden: usize,
+    ) -> Result<Node> {
+        let expressions = (0..environment.len() - hidden)
+            .map(|i| Ok((Expression::Field(i, environment.get_label(i)?), None)))
+            .collect::<Result<Vec<_>>>()?;
+        environment.project(&expressions)?;
+        Ok(Node::Projection { source: Box::new(node), expressions })
+    }
+
     /// Builds a FROM clause consisting of several items. Each item is either a single table or a
     /// join of an arbitrary number of tables. All of the items are joined, since e.g. 'SELECT * FROM
     /// a, b' is an implicit join of a and b.
@@ -240,6 +511,15 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// only be referenced by the query name (so if alias is given, cannot reference by name).
     fn build_from_item(&self, environment: &mut Environment, item: ast::FromItem) -> Result<Node> {
         Ok(match item {
+            ast::FromItem::Table { name, alias } if self.ctes.borrow().contains_key(&name) => {
+                let Cte { node, columns, materialize } = self.ctes.borrow()[&name].clone();
+                environment.add_derived(alias.unwrap_or_else(|| name.clone()), columns)?;
+                match materialize {
+                    true => Node::MaterializedCte { name, source: Box::new(node) },
+                    false => node,
+                }
+            }
+
             ast::FromItem::Table { name, alias } => {
                 environment.add_table(
                     alias.clone().unwrap_or_else(|| name.clone()),
@@ -248,6 +528,34 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 Node::Scan { table: name, alias, filter: None }
             }
 
+            ast::FromItem::Subquery { query, alias } => {
+                let mut scope = Environment::new();
+                let node = self.build_query(&mut scope, *query)?;
+                environment.add_derived(alias, scope.columns.into_iter().map(|(_, l)| l).collect())?;
+                node
+            }
+
+            ast::FromItem::Join { left, right, r#type, predicate }
+                if matches!(r#type, ast::JoinType::Lateral | ast::JoinType::LeftLateral) =>
+            {
+                // The subquery is planned in a scope whose enclosing scope is the left-hand side,
+                // such that references to left-hand columns resolve to outer fields.
+                let (query, alias) = match *right {
+                    ast::FromItem::Subquery { query, alias } => (query, alias),
+                    item => return Err(Error::Value(format!("LATERAL join requires a subquery, got {:?}", item))),
+                };
+                if environment.outer.is_some() {
+                    return Err(Error::Value("Nested LATERAL joins are not supported".into()));
+                }
+                let left = Box::new(self.build_from_item(environment, *left)?);
+                let mut scope = Environment::lateral(environment.clone());
+                let right = Box::new(self.build_query(&mut scope, *query)?);
+                environment.add_derived(alias, scope.columns.into_iter().map(|(_, l)| l).collect())?;
+                let predicate = predicate.map(|e| self.build_expression(environment, e)).transpose()?;
+                let outer = matches!(r#type, ast::JoinType::LeftLateral);
+                Node::LateralJoin { left, right, predicate, outer }
+            }
+
             ast::FromItem::Join { left, right, r#type, predicate } => {
                 // Right outer joins are built as a left outer join with an additional projection
                 // to swap the resulting columns.
@@ -262,6 +570,9 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 let outer = match r#type {
                     ast::JoinType::Cross | ast::JoinType::Inner => false,
                     ast::JoinType::Left | ast::JoinType::Right => true,
+                    ast::JoinType::Lateral | ast::JoinType::LeftLateral => {
+                        return Err(Error::Internal("Unexpected lateral join".into()))
+                    }
                 };
                 let mut node = Node::NestedLoopJoin { left, left_size, right, predicate, outer };
                 if matches!(r#type, ast::JoinType::Right) {
@@ -274,6 +585,31 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 }
                 node
             }
+
+            ast::FromItem::Sample { source, percent, seed } => {
+                let probability = match self.evaluate_constant(percent)? {
+                    Value::Integer(i) if (0..=100).contains(&i) => i as f64 / 100.0,
+                    Value::Float(f) if (0.0..=100.0).contains(&f) => f / 100.0,
+                    value => {
+                        return Err(Error::Value(format!(
+                            "Sample percentage must be between 0 and 100, got {}",
+                            value
+                        )))
+                    }
+                };
+                let seed = match seed.map(|e| self.evaluate_constant(e)).transpose()? {
+                    Some(Value::Integer(i)) => Some(i as u64),
+                    Some(value) => {
+                        return Err(Error::Value(format!(
+                            "Sample seed must be an integer, got {}",
+                            value.to_sql_literal()
+                        )))
+                    }
+                    None => None,
+                };
+                let source = Box::new(self.build_from_item(environment, *source)?);
+                Node::Sample { source, probability, seed }
+            }
         })
     }
 
@@ -287,7 +623,37 @@ impl<'a, C: Catalog> Planner<'a, C> {
         expr: &mut ast::Expression,
         select: &mut Vec<(ast::Expression, Option<String>)>,
     ) -> Result<usize> {
-        todo!()
+        let len = select.len();
+        let e = std::mem::replace(expr, ast::Literal::Null.into());
+        *expr = e.transform(
+            &mut |e| {
+                // Replace expressions that are SELECT expressions or reference their labels.
+                for (i, (sexpr, label)) in select.iter().enumerate() {
+                    match (&e, label) {
+                        (e, _) if e == sexpr => return Ok(ast::Expression::Column(i)),
+                        (ast::Expression::Field(None, name), Some(label)) if name == label => {
+                            return Ok(ast::Expression::Column(i))
+                        }
+                        _ => {}
+                    }
+                }
+                // Any other aggregates and fields are added as hidden columns.
+                match &e {
+                    ast::Expression::Function(name, _) if Aggregate::from_name(name).is_none() => {
+                        Ok(e)
+                    }
+                    ast::Expression::Function(..)
+                    | ast::Expression::Window(_)
+                    | ast::Expression::Field(..) => {
+                        select.push((e, None));
+                        Ok(ast::Expression::Column(select.len() - 1))
+                    }
+                    _ => Ok(e),
+                }
+            },
+            &mut Ok,
+        )?;
+        Ok(select.len() - len)
     }
 
     /// Extracts aggregate functions from an AST expression tree. This finds the aggregate
@@ -297,7 +663,36 @@ impl<'a, C: Catalog> Planner<'a, C> {
         &self,
         exprs: &mut [(ast::Expression, Option<String>)],
     ) -> Result<Vec<(Aggregate, ast::Expression)>> {
-        todo!()
+        let mut aggregates = Vec::new();
+        for (expr, _) in exprs.iter_mut() {
+            let e = std::mem::replace(expr, ast::Literal::Null.into());
+            *expr = e.transform(
+                &mut |e| match e {
+                    ast::Expression::Function(name, mut args) => {
+                        let aggregate = match Aggregate::from_name(&name) {
+                            Some(aggregate) => aggregate,
+                            None => return Ok(ast::Expression::Function(name, args)),
+                        };
+                        if args.len() != 1 {
+                            return Err(Error::Value(format!(
+                                "Aggregate function {} takes 1 argument, given {}",
+                                name,
+                                args.len()
+                            )));
+                        }
+                        let arg = args.remove(0);
+                        if Self::is_aggregate(&arg) {
+                            return Err(Error::Value("Aggregate functions can't be nested".into()));
+                        }
+                        aggregates.push((aggregate, arg));
+                        Ok(ast::Expression::Column(aggregates.len() - 1))
+                    }
+                    e => Ok(e),
+                },
+                &mut Ok,
+            )?;
+        }
+        Ok(aggregates)
     }
 
     /// Extracts group by expressions, and replaces them with column references with the given
@@ -309,16 +704,38 @@ impl<'a, C: Catalog> Planner<'a, C> {
     /// SELECT COUNT(*) FROM movies GROUP BY released / 100
     fn extract_groups(
         &self,
-        exprs: &mut Vec<(ast::Expression, Option<String>)>,
+        exprs: &mut [(ast::Expression, Option<String>)],
         group_by: Vec<ast::Expression>,
         offset: usize,
     ) -> Result<Vec<(ast::Expression, Option<String>)>> {
-        todo!()
+        let mut groups = Vec::new();
+        for group in group_by {
+            let column = ast::Expression::Column(offset + groups.len());
+            let position = match &group {
+                ast::Expression::Field(None, name) => {
+                    exprs.iter().position(|(_, label)| label.as_deref() == Some(name))
+                }
+                _ => None,
+            }
+            .or_else(|| exprs.iter().position(|(expr, _)| expr == &group));
+            let group = match position {
+                Some(i) => (std::mem::replace(&mut exprs[i].0, column), exprs[i].1.clone()),
+                None => (group, None),
+            };
+            // SELECT expressions have had their aggregates replaced by Column references.
+            if group.0.contains(&|e| matches!(e, ast::Expression::Column(_)))
+                || Self::is_aggregate(&group.0)
+            {
+                return Err(Error::Value("Group expression can't contain aggregates".into()));
+            }
+            groups.push(group);
+        }
+        Ok(groups)
     }
 
-    /// Builds an aggregation node. All aggregate parameters and GROUP BY expressions are evaluated
-    /// in a pre-projection, whose results are fed into an Aggregate node. This node computes the
-    /// aggregates for the given groups, passing the group values through directly.
+    /// Builds an aggregation node, which evaluates the GROUP BY expressions and aggregate
+    /// arguments on the source rows and computes the aggregates for each group. Its output
+    /// columns are the aggregates followed by the group values.
     fn build_aggregation(
         &self,
         environment: &mut Environment,
@@ -326,7 +743,310 @@ impl<'a, C: Catalog> Planner<'a, C> {
         groups: Vec<(ast::Expression, Option<String>)>,
         aggregations: Vec<(Aggregate, ast::Expression)>,
     ) -> Result<Node> {
-        todo!()
+        let aggregates = aggregations
+            .into_iter()
+            .map(|(a, e)| Ok(AggregateExpr::new(a, self.build_expression(environment, e)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let groups = groups
+            .into_iter()
+            .map(|(e, label)| Ok((self.build_expression(environment, e)?, label)))
+            .collect::<Result<Vec<_>>>()?;
+        let columns = aggregates
+            .iter()
+            .map(|_| (Expression::Constant(Value::Null), None))
+            .chain(groups.iter().cloned())
+            .collect::<Vec<_>>();
+        environment.project(&columns)?;
+        Ok(Node::Aggregation {
+            source: Box::new(source),
+            group_labels: groups.iter().map(|(e, _)| e.to_column_name()).collect(),
+            group_by: groups.into_iter().map(|(e, _)| e).collect(),
+            aggregates,
+        })
+    }
+
+    /// Extracts window function calls from SELECT expressions, replacing them with Column
+    /// references to the window columns following the given number of source columns.
+    /// Identical calls share a column.
+    fn extract_windows(
+        &self,
+        exprs: &mut [(ast::Expression, Option<String>)],
+        offset: usize,
+    ) -> Result<Vec<ast::Expression>> {
+        let mut windows: Vec<ast::Expression> = Vec::new();
+        for (expr, _) in exprs.iter_mut() {
+            let e = std::mem::replace(expr, ast::Literal::Null.into());
+            *expr = e.transform(
+                &mut |e| match e {
+                    ast::Expression::Window(ref window) => {
+                        if window.children().any(Self::is_window) {
+                            return Err(Error::Value("Window functions can't be nested".into()));
+                        }
+                        let i = match windows.iter().position(|w| w == &e) {
+                            Some(i) => i,
+                            None => {
+                                windows.push(e);
+                                windows.len() - 1
+                            }
+                        };
+                        Ok(ast::Expression::Column(offset + i))
+                    }
+                    e => Ok(e),
+                },
+                &mut Ok,
+            )?;
+        }
+        Ok(windows)
+    }
+
+    /// Builds a window node, which computes the window functions on the source rows and
+    /// appends their values to the rows.
+    fn build_window(
+        &self,
+        environment: &mut Environment,
+        source: Node,
+        windows: Vec<ast::Expression>,
+    ) -> Result<Node> {
+        let functions = windows
+            .into_iter()
+            .map(|window| self.build_window_function(environment, window))
+            .collect::<Result<Vec<_>>>()?;
+        for _ in &functions {
+            environment.add_column(None, None);
+        }
+        Ok(Node::Window { source: Box::new(source), functions })
+    }
+
+    /// Builds a window function from an AST window function call. LAG and LEAD take an
+    /// optional constant offset, which defaults to 1.
+    fn build_window_function(
+        &self,
+        environment: &mut Environment,
+        window: ast::Expression,
+    ) -> Result<WindowFunctionExpr> {
+        let ast::Window { function, mut args, partition_by, order } = match window {
+            ast::Expression::Window(window) => *window,
+            expr => return Err(Error::Internal(format!("Unexpected window {:?}", expr))),
+        };
+        let func = match (function.as_str(), args.len()) {
+            ("row_number", 0) => WindowFunc::RowNumber,
+            ("rank", 0) => WindowFunc::Rank,
+            ("dense_rank", 0) => WindowFunc::DenseRank,
+            ("lag" | "lead", 1 | 2) => {
+                let offset = match args.len() {
+                    2 => match self.evaluate_constant(args.remove(1))? {
+                        Value::Integer(i) => i,
+                        value => {
+                            return Err(Error::Value(format!(
+                                "Invalid {} offset {}",
+                                function, value
+                            )))
+                        }
+                    },
+                    _ => 1,
+                };
+                let expr = Box::new(self.build_expression(environment, args.remove(0))?);
+                match function.as_str() {
+                    "lag" => WindowFunc::Lag(expr, offset),
+                    _ => WindowFunc::Lead(expr, offset),
+                }
+            }
+            ("row_number" | "rank" | "dense_rank", n) => {
+                return Err(Error::Value(format!(
+                    "Window function {} takes no arguments, given {}",
+                    function, n
+                )))
+            }
+            ("lag" | "lead", n) => {
+                return Err(Error::Value(format!(
+                    "Window function {} takes 1 or 2 arguments, given {}",
+                    function, n
+                )))
+            }
+            _ => return Err(Error::Value(format!("Unknown window function {}", function))),
+        };
+        Ok(WindowFunctionExpr {
+            func,
+            partition_by: partition_by
+                .into_iter()
+                .map(|e| self.build_expression(environment, e))
+                .collect::<Result<_>>()?,
+            order_by: order
+                .into_iter()
+                .map(|(e, o)| {
+                    let order = match o {
+                        ast::Order::Ascending => SortOrder::Asc,
+                        ast::Order::Descending => SortOrder::Desc,
+                    };
+                    Ok((self.build_expression(environment, e)?, order))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Checks that an expression evaluated on aggregated rows only references grouped fields,
+    /// given the environments of the aggregation's source and output.
+    fn assert_aggregated(
+        &self,
+        source: &Environment,
+        environment: &Environment,
+        expr: &ast::Expression,
+    ) -> Result<()> {
+        expr.clone().transform(
+            &mut |e| {
+                if let ast::Expression::Field(table, name) = &e {
+                    let table = table.as_deref();
+                    if environment.resolve(table, name).is_err()
+                        && environment.resolve_outer(table, name).is_none()
+                    {
+                        source.resolve(table, name)?;
+                        return Err(Error::Value(format!(
+                            "Field {} must be used in GROUP BY or an aggregate function",
+                            match table {
+                                Some(table) => format!("{}.{}", table, name),
+                                None => name.clone(),
+                            }
+                        )));
+                    }
+                }
+                Ok(e)
+            },
+            &mut Ok,
+        )?;
+        Ok(())
+    }
+
+    /// Returns whether an AST expression contains a window function call.
+    fn is_window(expr: &ast::Expression) -> bool {
+        expr.contains(&|e| matches!(e, ast::Expression::Window(_)))
+    }
+
+    /// Returns whether an AST expression contains an aggregate function call.
+    fn is_aggregate(expr: &ast::Expression) -> bool {
+        expr.contains(&|e| {
+            matches!(e, ast::Expression::Function(name, _) if Aggregate::from_name(name).is_some())
+        })
+    }
+
+    /// Builds a column schema from an AST column. Nullable columns default to NULL, primary keys
+    /// are unique and not separately indexed, and primary keys and SERIAL columns aren't
+    /// nullable unless specified.
+    fn build_column(&self, c: ast::Column) -> Result<Column> {
+        let is_serial = c.datatype == DataType::Serial;
+        let is_nullable = c.is_nullable.unwrap_or(!c.is_primary_key && !is_serial);
+        let default = match c.default {
+            Some(expr) => Some(c.datatype.coerce(self.evaluate_constant(expr)?)?),
+            None if is_nullable => Some(Value::Null),
+            None => None,
+        };
+        Ok(Column {
+            name: c.name,
+            datatype: c.datatype,
+            is_primary_key: c.is_primary_key,
+            is_nullable,
+            default,
+            is_unique: c.is_unique || c.is_primary_key,
+            is_indexed: c.is_indexed && !c.is_primary_key,
+            index_predicate: None,
+            references: c.references,
+        })
+    }
+
+    /// Builds a table schema from CREATE TABLE columns and constraints. Column check constraints,
+    /// and column references with ON DELETE or ON UPDATE actions, become table constraints.
+    /// Unnamed constraints are named after the table, their column
+    /// and their kind, e.g. test_value_check, with a number appended if the name is taken.
+    fn build_table(
+        &self,
+        name: String,
+        columns: Vec<ast::Column>,
+        constraints: Vec<(Option<String>, ast::Constraint)>,
+    ) -> Result<Table> {
+        let mut column_constraints = Vec::new();
+        let columns = columns
+            .into_iter()
+            .map(|mut c| {
+                column_constraints.extend(
+                    c.checks.drain(..).map(|expr| (c.name.clone(), ast::Constraint::Check(expr))),
+                );
+                if (c.on_delete, c.on_update) != (FkAction::NoAction, FkAction::NoAction) {
+                    if let Some(references) = c.references.take() {
+                        let constraint = ast::Constraint::ForeignKey {
+                            column: c.name.clone(),
+                            references,
+                            referenced_column: None,
+                            on_delete: c.on_delete,
+                            on_update: c.on_update,
+                        };
+                        column_constraints.push((c.name.clone(), constraint));
+                    }
+                }
+                self.build_column(c)
+            })
+            .collect::<Result<_>>()?;
+        let mut table = Table::new(name, columns)?;
+        let constraints = column_constraints
+            .into_iter()
+            .map(|(column, constraint)| (None, Some(column), constraint))
+            .chain(constraints.into_iter().map(|(name, constraint)| (name, None, constraint)));
+        for (name, column, constraint) in constraints {
+            let constraint = self.build_constraint(&table, constraint)?;
+            let name = match name {
+                Some(name) => name,
+                None => {
+                    let base = match (&constraint, column) {
+                        (Constraint::Check(_), Some(column)) => {
+                            format!("{}_{}_check", table.name, column)
+                        }
+                        (Constraint::Check(_), None) => format!("{}_check", table.name),
+                        (Constraint::Unique(column), _) => format!("{}_{}_key", table.name, column),
+                        (Constraint::ForeignKey { column, .. }, _) => {
+                            format!("{}_{}_fkey", table.name, column)
+                        }
+                    };
+                    let taken = |name: &str| table.named_constraints.iter().any(|(n, _)| n == name);
+                    (1..)
+                        .map(|i| if i == 1 { base.clone() } else { format!("{}{}", base, i) })
+                        .find(|name| !taken(name))
+                        .expect("unbounded range")
+                }
+            };
+            table.named_constraints.push((name, constraint));
+        }
+        Ok(table)
+    }
+
+    /// Builds a named table constraint from an AST constraint. Foreign keys may only reference
+    /// the primary key of the target table.
+    fn build_constraint(&self, table: &Table, constraint: ast::Constraint) -> Result<Constraint> {
+        Ok(match constraint {
+            ast::Constraint::Check(expr) => {
+                let mut environment = Environment::from_table(table.clone())?;
+                Constraint::Check(self.build_expression(&mut environment, expr)?)
+            }
+            ast::Constraint::Unique(column) => Constraint::Unique(column),
+            ast::Constraint::ForeignKey {
+                column,
+                references,
+                referenced_column,
+                on_delete,
+                on_update,
+            } => {
+                if let Some(referenced_column) = referenced_column {
+                    let target = match references == table.name {
+                        true => table.clone(),
+                        false => self.catalog.assert_read_table(&references)?,
+                    };
+                    if !target.get_column(&referenced_column)?.is_primary_key {
+                        return Err(Error::Value(format!(
+                            "Foreign key must reference the primary key of table {}",
+                            references
+                        )));
+                    }
+                }
+                Constraint::ForeignKey { column, references, on_delete, on_update }
+            }
+        })
     }
 
     /// Builds an expression from an AST expression. TODO: Read.
@@ -341,11 +1061,56 @@ impl<'a, C: Catalog> Planner<'a, C> {
                 ast::Literal::String(s) => Value::String(s),
             }),
             ast::Expression::Column(i) => Field(i, environment.get_label(i)?),
+            ast::Expression::Parameter(n) => Parameter(n),
+            ast::Expression::Cast(expr, target) => {
+                Cast { value: self.build_expression(environment, *expr)?.into(), target }
+            }
             ast::Expression::Field(table, name) => {
-                Field(environment.resolve(table.as_deref(), &name)?, Some((table, name)))
+                match environment.resolve_outer(table.as_deref(), &name) {
+                    Some(index) => Outer(index, Some((table, name))),
+                    None => Field(environment.resolve(table.as_deref(), &name)?, Some((table, name))),
+                }
+            }
+            ast::Expression::Function(name, args) => {
+                let arity = match name.as_str() {
+                    "now" => 0,
+                    "date_trunc" | "extract" => 2,
+                    _ => match FunctionRegistry::global().read().get(&name) {
+                        Some(function) => function.arg_types.len(),
+                        None => return Err(Error::Value(format!("Unknown function {}", name))),
+                    },
+                };
+                if args.len() != arity {
+                    return Err(Error::Value(format!(
+                        "Function {} takes {} arguments, given {}",
+                        name,
+                        arity,
+                        args.len()
+                    )));
+                }
+                let mut args = args
+                    .into_iter()
+                    .map(|arg| self.build_expression(environment, arg).map(Box::new))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter();
+                match name.as_str() {
+                    "date_trunc" => DateTrunc(args.next().unwrap(), args.next().unwrap()),
+                    "extract" => Extract(args.next().unwrap(), args.next().unwrap()),
+                    "now" => Now,
+                    _ => Function(name, args.map(|arg| *arg).collect()),
+                }
+            }
+            ast::Expression::Window(window) => {
+                return Err(Error::Value(format!(
+                    "Window function {} is only allowed in SELECT and ORDER BY",
+                    window.function
+                )))
+            }
+            ast::Expression::Subquery(query) => {
+                ScalarSubquery(self.build_subquery(environment, *query, true)?.into())
             }
-            ast::Expression::Function(name, _) => {
-                return Err(Error::Value(format!("Unknown function {}", name,)))
+            ast::Expression::Exists(query) => {
+                ExistsSubquery(self.build_subquery(environment, *query, false)?.into())
             }
             ast::Expression::Operation(op) => match op {
                 // Logical operators
@@ -397,10 +1162,23 @@ impl<'a, C: Catalog> Planner<'a, C> {
                     )
                     .into(),
                 ),
-                ast::Operation::Like(lhs, rhs) => Like(
+                ast::Operation::Concat(lhs, rhs) => Concat(
                     self.build_expression(environment, *lhs)?.into(),
                     self.build_expression(environment, *rhs)?.into(),
                 ),
+                ast::Operation::Like { value, pattern, escape, case_insensitive } => Like {
+                    value: self.build_expression(environment, *value)?.into(),
+                    pattern: self.build_expression(environment, *pattern)?.into(),
+                    escape: escape
+                        .map(|e| self.build_expression(environment, *e).map(Box::new))
+                        .transpose()?,
+                    case_insensitive,
+                },
+                ast::Operation::In { value, subquery, negated } => InSubquery {
+                    value: self.build_expression(environment, *value)?.into(),
+                    subquery: self.build_subquery(environment, *subquery, true)?.into(),
+                    negated,
+                },
                 ast::Operation::NotEqual(lhs, rhs) => Not(Equal(
                     self.build_expression(environment, *lhs)?.into(),
                     self.build_expression(environment, *rhs)?.into(),
@@ -441,19 +1219,356 @@ impl<'a, C: Catalog> Planner<'a, C> {
         })
     }
 
+    /// Builds a subquery plan, in a scope enclosed by the given one such that references to its
+    /// columns resolve to outer fields. Optionally requires the subquery to return one column.
+    fn build_subquery(
+        &self,
+        environment: &Environment,
+        query: ast::Statement,
+        single_column: bool,
+    ) -> Result<Node> {
+        let mut scope = Environment::lateral(environment.clone());
+        let node = self.insert_casts(self.build_query(&mut scope, query)?)?;
+        if single_column && scope.len() != 1 {
+            return Err(Error::Value(format!(
+                "Subquery must return a single column, got {}",
+                scope.len()
+            )));
+        }
+        Ok(node)
+    }
+
+    /// Inserts casts into the expressions of the plan's nodes, given the datatypes of their
+    /// input columns, see Expression::insert_casts(). Sort keys and MIN/MAX arguments are also
+    /// cast to their enum types, such that enums sort by declaration order. Subqueries in
+    /// expressions are handled when built, see build_subquery().
+    fn insert_casts(&self, node: Node) -> Result<Node> {
+        node.transform(&Ok, &|node| {
+            let fields = match &node {
+                Node::Aggregation { source, .. }
+                | Node::Delete { source, .. }
+                | Node::Filter { source, .. }
+                | Node::Order { source, .. }
+                | Node::Projection { source, .. }
+                | Node::Update { source, .. }
+                | Node::Window { source, .. } => self.infer_datatypes(source)?,
+                Node::NestedLoopJoin { left, right, .. }
+                | Node::LateralJoin { left, right, .. } => {
+                    let mut fields = self.infer_datatypes(left)?;
+                    fields.extend(self.infer_datatypes(right)?);
+                    fields
+                }
+                _ => return Ok(node),
+            };
+            Ok(match node.transform_expressions(&Ok, &|e| Ok(e.insert_cast(&fields)))? {
+                Node::Aggregation { source, group_by, group_labels, aggregates } => {
+                    Node::Aggregation {
+                        source,
+                        group_by,
+                        group_labels,
+                        aggregates: aggregates
+                            .into_iter()
+                            .map(|aggregate| match aggregate {
+                                AggregateExpr::Min(e) => {
+                                    AggregateExpr::Min(e.cast_enum(&fields).into())
+                                }
+                                AggregateExpr::Max(e) => {
+                                    AggregateExpr::Max(e.cast_enum(&fields).into())
+                                }
+                                aggregate => aggregate,
+                            })
+                            .collect(),
+                    }
+                }
+                Node::Order { source, orders } => Node::Order {
+                    source,
+                    orders: orders.into_iter().map(|(e, o)| (e.cast_enum(&fields), o)).collect(),
+                },
+                Node::Window { source, functions } => Node::Window {
+                    source,
+                    functions: functions
+                        .into_iter()
+                        .map(|mut function| {
+                            function.order_by = function
+                                .order_by
+                                .into_iter()
+                                .map(|(e, o)| (e.cast_enum(&fields), o))
+                                .collect();
+                            function
+                        })
+                        .collect(),
+                },
+                node => node,
+            })
+        })
+    }
+
+    /// Infers the datatypes of a query node's output columns, where possible.
+    fn infer_datatypes(&self, node: &Node) -> Result<Vec<Option<DataType>>> {
+        Ok(match node {
+            Node::Scan { table, .. }
+            | Node::KeyLookup { table, .. }
+            | Node::IndexLookup { table, .. } => self
+                .catalog
+                .assert_read_table(table)?
+                .columns
+                .into_iter()
+                .map(|c| Some(c.datatype.value_type().clone()))
+                .collect(),
+            Node::Distinct { source }
+            | Node::Filter { source, .. }
+            | Node::Limit { source, .. }
+            | Node::MaterializedCte { source, .. }
+            | Node::Order { source, .. }
+            | Node::Sample { source, .. } => self.infer_datatypes(source)?,
+            Node::Projection { source, expressions } => {
+                let fields = self.infer_datatypes(source)?;
+                expressions.iter().map(|(expr, _)| expr.infer_type(&fields)).collect()
+            }
+            Node::Aggregation { source, group_by, aggregates, .. } => {
+                let fields = self.infer_datatypes(source)?;
+                let aggregates = aggregates.iter().map(|aggregate| match aggregate {
+                    AggregateExpr::Count(_) => Some(DataType::Integer),
+                    AggregateExpr::Avg(expr) => match expr.infer_type(&fields) {
+                        Some(DataType::Integer | DataType::Float) => Some(DataType::Float),
+                        _ => None,
+                    },
+                    // Decimal sums may need a higher precision than the summands.
+                    AggregateExpr::Sum(expr) => match expr.infer_type(&fields) {
+                        Some(DataType::Decimal(..)) => None,
+                        datatype => datatype,
+                    },
+                    AggregateExpr::Min(expr) | AggregateExpr::Max(expr) => expr.infer_type(&fields),
+                });
+                aggregates.chain(group_by.iter().map(|expr| expr.infer_type(&fields))).collect()
+            }
+            Node::Window { source, functions } => {
+                let fields = self.infer_datatypes(source)?;
+                let windows = functions.iter().map(|function| match &function.func {
+                    WindowFunc::RowNumber | WindowFunc::Rank | WindowFunc::DenseRank => {
+                        Some(DataType::Integer)
+                    }
+                    WindowFunc::Lag(expr, _) | WindowFunc::Lead(expr, _) => {
+                        expr.infer_type(&fields)
+                    }
+                });
+                let mut datatypes = fields.clone();
+                datatypes.extend(windows);
+                datatypes
+            }
+            Node::NestedLoopJoin { left, right, .. }
+            | Node::HashJoin { left, right, .. }
+            | Node::LateralJoin { left, right, .. } => {
+                let mut datatypes = self.infer_datatypes(left)?;
+                datatypes.extend(self.infer_datatypes(right)?);
+                datatypes
+            }
+            Node::Nothing => Vec::new(),
+            node => return Err(Error::Internal(format!("Unexpected query node {:?}", node))),
+        })
+    }
+
+    /// Infers the names of a query node's output columns, as emitted by its executor.
+    fn infer_column_names(&self, node: &Node) -> Result<Vec<Option<String>>> {
+        Ok(match node {
+            Node::Scan { table, .. }
+            | Node::KeyLookup { table, .. }
+            | Node::IndexLookup { table, .. } => self
+                .catalog
+                .assert_read_table(table)?
+                .columns
+                .into_iter()
+                .map(|c| Some(c.name))
+                .collect(),
+            Node::Distinct { source }
+            | Node::Filter { source, .. }
+            | Node::Limit { source, .. }
+            | Node::MaterializedCte { source, .. }
+            | Node::Order { source, .. }
+            | Node::Sample { source, .. } => self.infer_column_names(source)?,
+            Node::Projection { source, expressions } => {
+                let names = self.infer_column_names(source)?;
+                expressions
+                    .iter()
+                    .map(|(expr, label)| match (expr, label) {
+                        (_, Some(label)) => Some(label.clone()),
+                        (Expression::Field(i, _), None) => names.get(*i).cloned().flatten(),
+                        (_, None) => None,
+                    })
+                    .collect()
+            }
+            Node::Aggregation { group_labels, aggregates, .. } => aggregates
+                .iter()
+                .map(|aggregate| Some(aggregate.function_name().to_string()))
+                .chain(group_labels.iter().map(|label| Some(label.clone())))
+                .collect(),
+            Node::Window { source, functions } => {
+                let mut names = self.infer_column_names(source)?;
+                names.extend(
+                    functions.iter().map(|function| Some(function.func.function_name().into())),
+                );
+                names
+            }
+            Node::NestedLoopJoin { left, right, .. }
+            | Node::HashJoin { left, right, .. }
+            | Node::LateralJoin { left, right, .. } => {
+                let mut names = self.infer_column_names(left)?;
+                names.extend(self.infer_column_names(right)?);
+                names
+            }
+            Node::Nothing => Vec::new(),
+            node => return Err(Error::Internal(format!("Unexpected query node {:?}", node))),
+        })
+    }
+
+    /// Builds the action to take on conflicting rows of an INSERT into a table. DO UPDATE
+    /// expressions are evaluated against the existing row followed by the EXCLUDED row.
+    fn build_conflict_action(
+        &self,
+        table: &str,
+        on_conflict: Option<ast::OnConflict>,
+    ) -> Result<InsertConflictAction> {
+        Ok(match on_conflict {
+            Some(ast::OnConflict::DoNothing) => InsertConflictAction::DoNothing,
+            Some(ast::OnConflict::DoUpdate(set)) => {
+                let table = self.catalog.assert_read_table(table)?;
+                let mut environment = Environment::from_table(table.clone())?;
+                environment.add_qualified_table("excluded".into(), table)?;
+                InsertConflictAction::DoUpdate(
+                    set.into_iter()
+                        .map(|(column, expr)| {
+                            Ok((
+                                environment.resolve(None, &column)?,
+                                Some(column),
+                                self.build_expression(&mut environment, expr)?,
+                            ))
+                        })
+                        .collect::<Result<_>>()?,
+                )
+            }
+            None => InsertConflictAction::Error,
+        })
+    }
+
+    /// Builds the RETURNING clause of a mutation of a table, if any. Its expressions may refer to
+    /// the affected row by the table's name or unqualified, and to the row before and after the
+    /// mutation as OLD and NEW. RETURNING * returns the affected row.
+    fn build_returning(
+        &self,
+        table: &str,
+        returning: Option<ast::Returning>,
+    ) -> Result<Option<Returning>> {
+        let Some(returning) = returning else { return Ok(None) };
+        let table = self.catalog.assert_read_table(table)?;
+        let mut environment = Environment::from_table(table.clone())?;
+        if returning.is_empty() {
+            return (0..table.columns.len())
+                .map(|i| Ok((Expression::Field(i, environment.get_label(i)?), None)))
+                .collect::<Result<_>>()
+                .map(Some);
+        }
+        environment.add_qualified_table("old".into(), table.clone())?;
+        environment.add_qualified_table("new".into(), table)?;
+        returning
+            .into_iter()
+            .map(|(expr, label)| Ok((self.build_expression(&mut environment, expr)?, label)))
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+
+    /// Checks that the rows of a query can be inserted into the given columns of a table, or
+    /// into its leading columns if none are given. The query must have a column for each target
+    /// column, and their datatypes must be coercible to the target columns' datatypes where
+    /// they can be inferred.
+    fn validate_insert_source(&self, table: &str, columns: &[String], source: &Node) -> Result<()> {
+        let table = self.catalog.assert_read_table(table)?;
+        let datatypes = self.infer_datatypes(source)?;
+        let targets = match columns.is_empty() {
+            true => table.columns.iter().take(datatypes.len()).collect(),
+            false => columns.iter().map(|c| table.get_column(c)).collect::<Result<Vec<_>>>()?,
+        };
+        if columns.is_empty() && datatypes.len() > table.columns.len() {
+            return Err(Error::Value(format!(
+                "Query returns {} columns, but table {} has {}",
+                datatypes.len(),
+                table.name,
+                table.columns.len()
+            )));
+        } else if !columns.is_empty() && datatypes.len() != columns.len() {
+            return Err(Error::Value(format!(
+                "Query returns {} columns, expected {}",
+                datatypes.len(),
+                columns.len()
+            )));
+        }
+        let mut names = HashSet::new();
+        for (column, datatype) in targets.into_iter().zip(datatypes) {
+            if !names.insert(&column.name) {
+                return Err(Error::Value(format!("Column {} given multiple times", column.name)));
+            }
+            match datatype {
+                Some(datatype) if !column.datatype.coercible_from(&datatype) => {
+                    return Err(Error::Value(format!(
+                        "Can't insert {} into column {} of type {}",
+                        datatype, column.name, column.datatype
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     /// Builds and evaluates a constant AST expression.
     fn evaluate_constant(&self, expr: ast::Expression) -> Result<Value> {
         self.build_expression(&mut Environment::constant(), expr)?.evaluate(None)
     }
 }
 
+/// Counts the FROM references to each table name in a statement, used to decide which common
+/// table expressions should be materialized.
+#[derive(Default)]
+struct CteRefCounts(HashMap<String, usize>);
+
+impl CteRefCounts {
+    /// Counts the references in a statement, adding them to the existing counts.
+    fn count(&mut self, statement: &ast::Statement) {
+        match statement {
+            ast::Statement::Select { from, .. } => from.iter().for_each(|item| self.count_item(item)),
+            ast::Statement::With { ctes, query } => {
+                ctes.iter().for_each(|(_, cte)| self.count(cte));
+                self.count(query);
+            }
+            _ => {}
+        }
+    }
+
+    /// Counts the references in a FROM item.
+    fn count_item(&mut self, item: &ast::FromItem) {
+        match item {
+            ast::FromItem::Table { name, .. } => *self.0.entry(name.clone()).or_insert(0) += 1,
+            ast::FromItem::Join { left, right, .. } => {
+                self.count_item(left);
+                self.count_item(right);
+            }
+            ast::FromItem::Subquery { query, .. } => self.count(query),
+            ast::FromItem::Sample { source, .. } => self.count_item(source),
+        }
+    }
+
+    /// Returns the number of references to a name.
+    fn get(&self, name: &str) -> usize {
+        self.0.get(name).copied().unwrap_or(0)
+    }
+}
+
 /// Manages names available to expressions and executors, and maps them onto columns/fields.
 #[derive(Clone, Debug)]
 pub struct Environment {
     // If true, the environment is constant and cannot contain any variables.
     is_constant: bool,
     // Currently visible tables, by query name (i.e. alias or actual name).
-    tables: HashMap<String, Table>,
+    tables: HashSet<String>,
     // Column labels, if any (qualified by table name when available)
     columns: Vec<(Option<String>, Option<String>)>,
     // Qualified names to column indexes.
@@ -462,6 +1577,8 @@ pub struct Environment {
     unqualified: HashMap<String, usize>,
     // Unqialified ambiguous names.
     ambiguous: HashSet<String>,
+    // The enclosing scope of a lateral subquery, if any.
+    outer: Option<Box<Environment>>,
 }
 
 impl Environment {
@@ -469,11 +1586,12 @@ impl Environment {
     fn new() -> Self {
         Self {
             is_constant: false,
-            tables: HashMap::new(),
+            tables: HashSet::new(),
             columns: Vec::new(),
             qualified: HashMap::new(),
             unqualified: HashMap::new(),
             ambiguous: HashSet::new(),
+            outer: None,
         }
     }
 
@@ -484,6 +1602,13 @@ impl Environment {
         environment
     }
 
+    /// Creates an environment for a lateral subquery, enclosed by the given scope.
+    fn lateral(outer: Environment) -> Self {
+        let mut environment = Self::new();
+        environment.outer = Some(Box::new(outer));
+        environment
+    }
+
     /// Creates an environment from a table.
     fn from_table(table: Table) -> Result<Self> {
         let mut environment = Self::new();
@@ -515,13 +1640,45 @@ impl Environment {
         if self.is_constant {
             return Err(Error::Internal("Can't modify constant environment".into()));
         }
-        if self.tables.contains_key(&label) {
+        if self.tables.contains(&label) {
             return Err(Error::Value(format!("Duplicate table name {}", label)));
         }
         for column in &table.columns {
             self.add_column(Some(label.clone()), Some(column.name.clone()));
         }
-        self.tables.insert(label, table);
+        self.tables.insert(label);
+        Ok(())
+    }
+
+    /// Adds a table whose columns can only be referenced by qualified names, such as the
+    /// EXCLUDED row of an INSERT conflict.
+    fn add_qualified_table(&mut self, label: String, table: Table) -> Result<()> {
+        if self.is_constant {
+            return Err(Error::Internal("Can't modify constant environment".into()));
+        }
+        if self.tables.contains(&label) {
+            return Err(Error::Value(format!("Duplicate table name {}", label)));
+        }
+        for column in table.columns {
+            self.qualified.insert((label.clone(), column.name.clone()), self.columns.len());
+            self.columns.push((Some(label.clone()), Some(column.name)));
+        }
+        self.tables.insert(label);
+        Ok(())
+    }
+
+    /// Adds a derived table, such as a common table expression, with the given column labels.
+    fn add_derived(&mut self, label: String, columns: Vec<Option<String>>) -> Result<()> {
+        if self.is_constant {
+            return Err(Error::Internal("Can't modify constant environment".into()));
+        }
+        if self.tables.contains(&label) {
+            return Err(Error::Value(format!("Duplicate table name {}", label)));
+        }
+        for column in columns {
+            self.add_column(Some(label.clone()), column);
+        }
+        self.tables.insert(label);
         Ok(())
     }
 
@@ -530,11 +1687,11 @@ impl Environment {
         if self.is_constant {
             return Err(Error::Internal("Can't modify constant scope".into()));
         }
-        for (label, table) in scope.tables {
-            if self.tables.contains_key(&label) {
+        for label in scope.tables {
+            if self.tables.contains(&label) {
                 return Err(Error::Value(format!("Duplicate table name {}", label)));
             }
-            self.tables.insert(label, table);
+            self.tables.insert(label);
         }
         for (table, label) in scope.columns {
             self.add_column(table, label);
@@ -551,7 +1708,7 @@ impl Environment {
             )));
         }
         if let Some(table) = table {
-            if !self.tables.contains_key(table) {
+            if !self.tables.contains(table) {
                 return Err(Error::Value(format!("Unknown table {}", table)));
             }
             self.qualified
@@ -568,6 +1725,18 @@ impl Environment {
         }
     }
 
+    /// Resolves a name in the enclosing scope, if it is not visible in the current scope.
+    fn resolve_outer(&self, table: Option<&str>, name: &str) -> Option<usize> {
+        let visible = match table {
+            Some(table) => self.tables.contains(table),
+            None => self.unqualified.contains_key(name) || self.ambiguous.contains(name),
+        };
+        match visible {
+            true => None,
+            false => self.outer.as_ref()?.resolve(table, name).ok(),
+        }
+    }
+
     /// Fetches a column from the scope by index. TODO: Read.
     fn get_column(&self, index: usize) -> Result<(Option<String>, Option<String>)> {
         if self.is_constant {
@@ -598,6 +1767,24 @@ impl Environment {
     /// Projects the scope. This takes a set of expressions and labels in the current scope,
     /// and returns a new scope for the projection.
     fn project(&mut self, projection: &[(Expression, Option<String>)]) -> Result<()> {
-        todo!()
+        if self.is_constant {
+            return Err(Error::Internal("Can't modify constant scope".into()));
+        }
+        let mut new = Self::new();
+        new.tables = self.tables.clone();
+        new.outer = self.outer.take();
+        for (expr, label) in projection {
+            match (expr, label) {
+                (_, Some(label)) => new.add_column(None, Some(label.clone())),
+                // Fields keep their table qualification, so they can still be referenced by it.
+                (Expression::Field(i, _), None) => {
+                    let (table, label) = self.get_column(*i)?;
+                    new.add_column(table, label)
+                }
+                (_, None) => new.add_column(None, None),
+            }
+        }
+        *self = new;
+        Ok(())
     }
 }
\ No newline at end of file
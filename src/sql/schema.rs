@@ -2,6 +2,8 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Display};
 
 use serde_derive::{Deserialize, Serialize};
@@ -9,7 +11,7 @@ use serde_derive::{Deserialize, Serialize};
 use crate::error::{Error, Result};
 use super::engine::SqlTxn;
 use super::parser::format_ident;
-use super::types::{DataType, Value};
+use super::types::{DataType, Expression, Row, Value};
 
 /// The catalog stores schema information
 pub trait Catalog {
@@ -17,10 +19,21 @@ pub trait Catalog {
     fn create_table(&mut self, table: Table) -> Result<()>;
     /// Deletes an existing table, or errors if it does not exist.
     fn delete_table(&mut self, table: &str) -> Result<()>;
+    /// Replaces the schema of an existing table, or errors if it does not exist.
+    fn update_table(&mut self, table: Table) -> Result<()>;
     /// Reads a table, or returns None if it does not exist.
     fn read_table(&self, table: &str) -> Result<Option<Table>>;
     /// Iterates over all tables.
     fn scan_tables(&self) -> Result<Tables>;
+    /// Computes statistics about a table's rows and indexes by scanning the whole table, and
+    /// stores them for get_stats().
+    fn analyze(&mut self, table: &str) -> Result<TableStats>;
+    /// Returns the statistics stored by the last analyze() of a table, for estimating query plan
+    /// costs, or None if the table hasn't been analyzed since its columns last changed.
+    fn get_stats(&self, table: &str) -> Result<Option<TableStats>>;
+    /// Returns the number of rows in a table, as of its last analyze(), or by counting them if
+    /// the table hasn't been analyzed.
+    fn row_count(&self, table: &str) -> Result<u64>;
 
     /// Reads a table, and errors if it does not exist.
     fn assert_read_table(&self, table: &str) -> Result<Table> {
@@ -29,8 +42,13 @@ pub trait Catalog {
         )
     }
 
-    /// Returns all references to a table, as (table,column) pairs.
-    fn table_references(&self, table: &str, include_self: bool) -> Result<Vec<(String, Vec<String>)>> {
+    /// Returns all references to a table, as (table,columns) pairs. Each referencing column is
+    /// given with its ON DELETE and ON UPDATE actions, which are NO ACTION for column references.
+    fn table_references(
+        &self,
+        table: &str,
+        include_self: bool,
+    ) -> Result<Vec<(String, Vec<Reference>)>> {
         Ok(self
             .scan_tables()?
             .filter(|t| include_self || t.name != table)
@@ -38,7 +56,15 @@ pub trait Catalog {
                 let references = t.columns
                     .iter()
                     .filter(|column| column.references.as_deref() == Some(table))
-                    .map(|column| column.name.clone())
+                    .map(|column| (column.name.clone(), FkAction::NoAction, FkAction::NoAction))
+                    .chain(t.named_constraints.iter().filter_map(|(_, c)| match c {
+                        Constraint::ForeignKey { column, references, on_delete, on_update }
+                            if references == table =>
+                        {
+                            Some((column.clone(), *on_delete, *on_update))
+                        }
+                        _ => None,
+                    }))
                     .collect::<Vec<_>>();
                 (t.name, references)
             })
@@ -48,17 +74,44 @@ pub trait Catalog {
     }
 }
 
+/// A column referencing a table, with its ON DELETE and ON UPDATE actions.
+pub type Reference = (String, FkAction, FkAction);
+
 /// A table schema
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    /// The defining SELECT query, if the table stores a materialized view. It's stored as SQL
+    /// and planned again on refresh, since plans refer to columns by position.
+    pub view: Option<String>,
+    /// Constraints added after table creation, by name
+    pub named_constraints: Vec<(String, Constraint)>,
+    /// The schema version, incremented when columns are added or dropped
+    pub schema_version: u32,
+    /// The positions of dropped columns in stored rows, in ascending order, see decode_row()
+    pub dropped_columns: Vec<usize>,
 }
 
 impl Table {
+    /// The maximum number of violations reported when adding a constraint.
+    const MAX_VIOLATIONS: usize = 10;
+
     /// Creates a new table schema.
     pub fn new(name: String, columns: Vec<Column>) -> Result<Self> {
-        Ok(Self { name, columns })
+        Ok(Self {
+            name,
+            columns,
+            view: None,
+            named_constraints: Vec::new(),
+            schema_version: 0,
+            dropped_columns: Vec::new(),
+        })
+    }
+
+    /// Returns true if the table stores a materialized view.
+    pub fn is_view(&self) -> bool {
+        self.view.is_some()
     }
 
     /// Fetches a column by name.
@@ -107,10 +160,213 @@ impl Table {
         for column in &self.columns {
             column.validate(self, txn)?;
         }
+        for (i, (name, constraint)) in self.named_constraints.iter().enumerate() {
+            if self.named_constraints[..i].iter().any(|(n, _)| n == name) {
+                return Err(Error::Value(format!(
+                    "Constraint {} already exists in table {}",
+                    name, self.name
+                )));
+            }
+            constraint.validate(name, self, txn)?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the existing rows satisfy a named constraint, erroring with up to
+    /// MAX_VIOLATIONS examples of violating values or rows if they don't.
+    pub fn check_constraint(&self, name: &str, constraint: &Constraint, txn: &mut dyn SqlTxn) -> Result<()> {
+        let mut violations = Vec::new();
+        let mut seen = HashSet::new();
+        let mut scan = txn.scan(&self.name, None)?;
+        while let Some(row) = scan.next().transpose()? {
+            let violation = match constraint {
+                Constraint::Check(expr) => match expr.evaluate(Some(&row))? {
+                    Value::Boolean(false) => Some(self.get_row_key(&row)?),
+                    _ => None,
+                },
+                Constraint::Unique(column) => {
+                    let value = &row[self.get_column_index(column)?];
+                    match value {
+                        Value::Null => None,
+                        v if !seen.insert(v.clone()) && !violations.contains(v) => Some(v.clone()),
+                        _ => None,
+                    }
+                }
+                Constraint::ForeignKey { column, references, .. } => {
+                    match &row[self.get_column_index(column)?] {
+                        Value::Null => None,
+                        v if references == &self.name && v == &self.get_row_key(&row)? => None,
+                        v if txn.read(references, v)?.is_none() && !violations.contains(v) => {
+                            Some(v.clone())
+                        }
+                        _ => None,
+                    }
+                }
+            };
+            if let Some(violation) = violation {
+                violations.push(violation);
+                if violations.len() > Self::MAX_VIOLATIONS {
+                    break;
+                }
+            }
+        }
+        if violations.is_empty() {
+            return Ok(());
+        }
+        let mut examples = violations
+            .iter()
+            .take(Self::MAX_VIOLATIONS)
+            .map(|v| v.to_sql_literal())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if violations.len() > Self::MAX_VIOLATIONS {
+            examples += ", ...";
+        }
+        Err(Error::Value(match constraint {
+            Constraint::Check(_) => format!(
+                "Can't add constraint {}, existing rows fail check: {}",
+                name, examples
+            ),
+            Constraint::Unique(column) => format!(
+                "Can't add constraint {}, column {} has duplicate values: {}",
+                name, column, examples
+            ),
+            Constraint::ForeignKey { column, references, .. } => format!(
+                "Can't add constraint {}, column {} has values missing from table {}: {}",
+                name, column, references, examples
+            ),
+        }))
+    }
+
+    /// Adds a column at the end of the table. Existing rows are not rewritten, but take the
+    /// column's default value when read, see decode_row(), so the column must have one.
+    pub fn add_column(&mut self, column: Column) -> Result<()> {
+        if self.columns.iter().any(|c| c.name == column.name) {
+            return Err(Error::Value(format!(
+                "Column {} already exists in table {}",
+                column.name, self.name
+            )));
+        }
+        if column.is_primary_key || column.is_unique {
+            return Err(Error::Value(format!(
+                "Can't add primary key or unique column {}",
+                column.name
+            )));
+        }
+        if column.default.is_none() {
+            return Err(Error::Value(format!("Added column {} needs a default value", column.name)));
+        }
+        self.columns.push(column);
+        self.schema_version += 1;
         Ok(())
     }
 
+    /// Drops a column. Its values are not removed from stored rows, but skipped when read, see
+    /// decode_row(). Columns used by constraints or index predicates can't be dropped, and field
+    /// references to later columns in check constraints and index predicates are shifted.
+    pub fn drop_column(&mut self, name: &str) -> Result<()> {
+        let index = self.get_column_index(name)?;
+        if self.columns[index].is_primary_key {
+            return Err(Error::Value(format!("Can't drop primary key column {}", name)));
+        }
+        let uses_field = |expr: &Expression| {
+            expr.contains(&|e| matches!(e, Expression::Field(i, _) if *i == index))
+        };
+        let used_by = self.named_constraints.iter().find(|(_, constraint)| match constraint {
+            Constraint::Check(expr) => uses_field(expr),
+            Constraint::Unique(column) | Constraint::ForeignKey { column, .. } => column == name,
+        });
+        if let Some((constraint, _)) = used_by {
+            return Err(Error::Value(format!(
+                "Can't drop column {}, it is used by constraint {}",
+                name, constraint
+            )));
+        }
+        if let Some(column) = self.columns.iter().find(|c| {
+            c.name != name && c.index_predicate.as_ref().is_some_and(uses_field)
+        }) {
+            return Err(Error::Value(format!(
+                "Can't drop column {}, it is used by the index predicate of column {}",
+                name, column.name
+            )));
+        }
+
+        let shift = |expr: Expression| {
+            expr.transform(&Ok, &|e| match e {
+                Expression::Field(i, label) if i > index => Ok(Expression::Field(i - 1, label)),
+                e => Ok(e),
+            })
+        };
+        for (_, constraint) in self.named_constraints.iter_mut() {
+            if let Constraint::Check(expr) = constraint {
+                *expr = shift(expr.clone())?;
+            }
+        }
+        for column in self.columns.iter_mut() {
+            column.index_predicate = column.index_predicate.take().map(shift).transpose()?;
+        }
+        self.dropped_columns.push(self.stored_index(index));
+        self.dropped_columns.sort_unstable();
+        self.columns.remove(index);
+        self.schema_version += 1;
+        Ok(())
+    }
+
+    /// Returns the position in stored rows of the column at the given index.
+    fn stored_index(&self, index: usize) -> usize {
+        let mut position = index;
+        for dropped in &self.dropped_columns {
+            if *dropped <= position {
+                position += 1;
+            }
+        }
+        position
+    }
+
+    /// Converts a row to its stored form, with NULL values in the positions of dropped columns.
+    pub fn encode_row(&self, mut row: Row) -> Row {
+        for position in &self.dropped_columns {
+            if *position <= row.len() {
+                row.insert(*position, Value::Null);
+            }
+        }
+        row
+    }
+
+    /// Converts a stored row to the table's current columns: values of dropped columns are
+    /// removed, and columns added after the row was stored take their default value.
+    pub fn decode_row(&self, mut row: Row) -> Row {
+        for position in self.dropped_columns.iter().rev() {
+            if *position < row.len() {
+                row.remove(*position);
+            }
+        }
+        let len = row.len().min(self.columns.len());
+        row.extend(self.columns[len..].iter().map(|c| c.default.clone().unwrap_or(Value::Null)));
+        row
+    }
+
     /// Validates a row.
+    /// Converts a row's values for storage in the table's columns, see DataType::coerce().
+    /// Rows of the wrong length are returned as is, for validate_row() to reject.
+    pub fn coerce_row(&self, row: Row) -> Result<Row> {
+        if row.len() != self.columns.len() {
+            return Ok(row);
+        }
+        self.columns.iter().zip(row).map(|(column, value)| column.datatype.coerce(value)).collect()
+    }
+
+    /// Assigns the next values of their sequences to SERIAL columns which are NULL in the row,
+    /// see Column::omitted_value().
+    pub fn assign_serials(&self, row: &mut Row, txn: &mut dyn SqlTxn) -> Result<()> {
+        for (column, value) in self.columns.iter().zip(row.iter_mut()) {
+            if column.datatype == DataType::Serial && *value == Value::Null {
+                *value = Value::Integer(txn.next_serial(&self.name, &column.name)?);
+            }
+        }
+        Ok(())
+    }
+
     pub fn validate_row(&self, row: &[Value], txn: &mut dyn SqlTxn) -> Result<()> {
         if row.len() != self.columns.len() {
             return Err(Error::Value(format!("Row has {} columns, expected {}", row.len(), self.columns.len())));
@@ -119,6 +375,47 @@ impl Table {
         for (column, value) in self.columns.iter().zip(row.iter()) {
             column.validate_value(value, &primary_key, self, txn)?;
         }
+        for (name, constraint) in &self.named_constraints {
+            constraint.validate_row(name, row, &primary_key, self, txn)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the table's foreign keys, as (column, referenced table) pairs, from both column
+    /// references and named constraints.
+    pub fn foreign_keys(&self) -> Vec<(&str, &str)> {
+        self.columns
+            .iter()
+            .filter_map(|c| Some((c.name.as_str(), c.references.as_deref()?)))
+            .chain(self.named_constraints.iter().filter_map(|(_, c)| match c {
+                Constraint::ForeignKey { column, references, .. } => {
+                    Some((column.as_str(), references.as_str()))
+                }
+                _ => None,
+            }))
+            .collect()
+    }
+
+    /// Validates that a row's foreign keys reference existing rows. This isn't part of
+    /// validate_row(), since references are checked when the transaction commits, allowing rows
+    /// to reference rows inserted later in the transaction, e.g. for circular references.
+    pub fn validate_references(&self, row: &[Value], txn: &mut dyn SqlTxn) -> Result<()> {
+        let primary_key = self.get_row_key(row)?;
+        for (column, target) in self.foreign_keys() {
+            match &row[self.get_column_index(column)?] {
+                Value::Null => {}
+                Value::Float(f) if f.is_nan() => {}
+                v if target == self.name && v == &primary_key => {}
+                v if txn.read(target, v)?.is_none() => {
+                    return Err(Error::ConstraintViolation(format!(
+                        "Referenced primary key {} in table {} does not exist",
+                        v.to_sql_literal(),
+                        target,
+                    )))
+                }
+                _ => {}
+            }
+        }
         Ok(())
     }
 }
@@ -127,17 +424,357 @@ impl Display for Table {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "CREATE TABLE {} (\n{}\n)",
+            "CREATE {} {} (\n{}\n)",
+            if self.is_view() { "MATERIALIZED VIEW" } else { "TABLE" },
             format_ident(&self.name),
-            self.columns.iter().map(|c| format!("  {}", c)).collect::<Vec<String>>().join(",\n")
+            self.columns
+                .iter()
+                .map(|c| format!("  {}", c))
+                .chain(self.named_constraints.iter().map(|(name, constraint)| {
+                    format!("  CONSTRAINT {} {}", format_ident(name), constraint)
+                }))
+                .collect::<Vec<String>>()
+                .join(",\n")
         )
     }
 }
 
+/// A step in migrating a table schema to a new version, see migration_plan().
+#[derive(Clone, Debug, PartialEq)]
+pub enum MigrationStep {
+    /// Adds a column, which existing rows take the default value of.
+    AddColumn(Column),
+    /// Drops a column, by name.
+    DropColumn(String),
+}
+
+/// Returns the steps migrating a table schema to a new version, matching columns by name.
+/// Dropped columns come first, in their order in the old schema, followed by added columns in
+/// their order in the new schema.
+pub fn migration_plan(old: &Table, new: &Table) -> Vec<MigrationStep> {
+    let dropped = old
+        .columns
+        .iter()
+        .filter(|c| !new.columns.iter().any(|n| n.name == c.name))
+        .map(|c| MigrationStep::DropColumn(c.name.clone()));
+    let added = new
+        .columns
+        .iter()
+        .filter(|c| !old.columns.iter().any(|o| o.name == c.name))
+        .map(|c| MigrationStep::AddColumn(c.clone()));
+    dropped.chain(added).collect()
+}
+
+/// A named table constraint, added with ALTER TABLE ... ADD CONSTRAINT
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Constraint {
+    /// Rows must not evaluate the expression to false.
+    Check(Expression),
+    /// Non-null values of the column must be unique.
+    Unique(String),
+    /// Non-null values of the column must be primary keys of the referenced table. The actions
+    /// are applied to referencing rows when the referenced row is deleted or its key updated.
+    ForeignKey { column: String, references: String, on_delete: FkAction, on_update: FkAction },
+}
+
+/// The action taken on the rows referencing a primary key when it is deleted or updated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FkAction {
+    /// Errors if the key is still referenced when the transaction commits, such that the
+    /// referencing rows can be changed later in the transaction.
+    #[default]
+    NoAction,
+    /// Errors immediately if the key is referenced.
+    Restrict,
+    /// Deletes the referencing rows, or updates them to the new key.
+    Cascade,
+    /// Sets the referencing column to NULL.
+    SetNull,
+    /// Sets the referencing column to its default value.
+    SetDefault,
+}
+
+impl Display for FkAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::NoAction => "NO ACTION",
+            Self::Restrict => "RESTRICT",
+            Self::Cascade => "CASCADE",
+            Self::SetNull => "SET NULL",
+            Self::SetDefault => "SET DEFAULT",
+        })
+    }
+}
+
+impl Constraint {
+    /// Validates a constraint schema. Check expressions are type-checked against the table's
+    /// columns: they must be boolean, and mustn't compare or combine incompatible datatypes.
+    pub fn validate(&self, name: &str, table: &Table, txn: &mut dyn SqlTxn) -> Result<()> {
+        match self {
+            Self::Check(expr) => {
+                let fields = table
+                    .columns
+                    .iter()
+                    .map(|c| Some(c.datatype.value_type().clone()))
+                    .collect::<Vec<_>>();
+                // Comparisons also accept decimals with floats, and strings with temporal
+                // values, which are parsed during evaluation.
+                let comparable = |l: &DataType, r: &DataType| {
+                    use DataType::*;
+                    l.compatible_with(r)
+                        || matches!(
+                            (l, r),
+                            (Decimal(..), Float)
+                                | (Float, Decimal(..))
+                                | (String, Timestamp | Date)
+                                | (Timestamp | Date, String)
+                        )
+                };
+                let mismatch = std::cell::RefCell::new(None);
+                let check = |lhs: &Expression, rhs: &Expression, compare: bool| {
+                    match (lhs.infer_type(&fields), rhs.infer_type(&fields)) {
+                        (Some(l), Some(r))
+                            if !(compare && comparable(&l, &r) || l.compatible_with(&r)) =>
+                        {
+                            mismatch.replace(Some((l, r)));
+                            true
+                        }
+                        _ => false,
+                    }
+                };
+                expr.contains(&|e| match e {
+                    Expression::Equal(lhs, rhs)
+                    | Expression::GreaterThan(lhs, rhs)
+                    | Expression::LessThan(lhs, rhs) => check(lhs, rhs, true),
+                    Expression::Add(lhs, rhs)
+                    | Expression::Subtract(lhs, rhs)
+                    | Expression::Multiply(lhs, rhs)
+                    | Expression::Divide(lhs, rhs)
+                    | Expression::Modulo(lhs, rhs) => check(lhs, rhs, false),
+                    _ => false,
+                });
+                if let Some((lhs, rhs)) = mismatch.into_inner() {
+                    return Err(Error::Value(format!(
+                        "Check constraint {} combines incompatible datatypes {} and {}",
+                        name, lhs, rhs
+                    )));
+                }
+                match expr.infer_type(&fields) {
+                    Some(DataType::Boolean) | None => Ok(()),
+                    Some(datatype) => Err(Error::Value(format!(
+                        "Check constraint {} has datatype {}, expected BOOLEAN",
+                        name, datatype
+                    ))),
+                }
+            }
+            Self::Unique(column) => table.get_column(column).map(|_| ()),
+            Self::ForeignKey { column, references, on_delete, on_update } => {
+                let column = table.get_column(column)?;
+                for action in [on_delete, on_update] {
+                    match action {
+                        FkAction::SetNull if !column.is_nullable => {
+                            return Err(Error::Value(format!(
+                                "Can't use SET NULL for constraint {}, column {} is not nullable",
+                                name, column.name
+                            )))
+                        }
+                        FkAction::SetDefault if column.default.is_none() => {
+                            return Err(Error::Value(format!(
+                                "Can't use SET DEFAULT for constraint {}, column {} has no default",
+                                name, column.name
+                            )))
+                        }
+                        _ => {}
+                    }
+                }
+                let target = if references == &table.name {
+                    table.clone()
+                } else if let Some(target) = txn.read_table(references)? {
+                    target
+                } else {
+                    return Err(Error::Value(format!(
+                        "Table {} referenced by constraint {} does not exist",
+                        references, name
+                    )));
+                };
+                let key_type = target.get_primary_key()?.datatype.value_type();
+                if column.datatype.value_type() != key_type {
+                    return Err(Error::Value(format!(
+                        "Can't reference {} primary key of table {} from {} column {}",
+                        target.get_primary_key()?.datatype,
+                        target.name,
+                        column.datatype,
+                        column.name
+                    )));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Validates a row against the constraint.
+    pub fn validate_row(
+        &self,
+        name: &str,
+        row: &[Value],
+        primary_key: &Value,
+        table: &Table,
+        txn: &mut dyn SqlTxn,
+    ) -> Result<()> {
+        match self {
+            Self::Check(expr) => match expr.evaluate(Some(&row.to_vec()))? {
+                Value::Boolean(true) | Value::Null => Ok(()),
+                Value::Boolean(false) => Err(Error::ConstraintViolation(format!(
+                    "Row {} violates check constraint {}",
+                    primary_key.to_sql_literal(),
+                    name
+                ))),
+                value => Err(Error::Value(format!(
+                    "Check constraint {} returned {}, expected boolean",
+                    name,
+                    value.to_sql_literal()
+                ))),
+            },
+            Self::Unique(column) => {
+                let index = table.get_column_index(column)?;
+                let value = &row[index];
+                if value == &Value::Null {
+                    return Ok(());
+                }
+                let mut scan = txn.scan(&table.name, None)?;
+                while let Some(existing) = scan.next().transpose()? {
+                    if &existing[index] == value && &table.get_row_key(&existing)? != primary_key {
+                        return Err(Error::Value(format!(
+                            "Unique value {} already exists for constraint {}",
+                            value.to_sql_literal(),
+                            name
+                        )));
+                    }
+                }
+                Ok(())
+            }
+            // Checked when the transaction commits, see Table::validate_references().
+            Self::ForeignKey { .. } => Ok(()),
+        }
+    }
+}
+
+impl Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Check(expr) => write!(f, "CHECK ({})", expr),
+            Self::Unique(column) => write!(f, "UNIQUE ({})", format_ident(column)),
+            Self::ForeignKey { column, references, on_delete, on_update } => {
+                write!(
+                    f,
+                    "FOREIGN KEY ({}) REFERENCES {}",
+                    format_ident(column),
+                    format_ident(references)
+                )?;
+                if on_delete != &FkAction::NoAction {
+                    write!(f, " ON DELETE {}", on_delete)?;
+                }
+                if on_update != &FkAction::NoAction {
+                    write!(f, " ON UPDATE {}", on_update)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 
 /// A table scan iterator
 pub type Tables = Box<dyn DoubleEndedIterator<Item = Table> + Send>;
 
+/// Table statistics, used by the optimizer to estimate the number of rows read by a plan.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct TableStats {
+    /// The number of rows in the table.
+    pub rows: u64,
+    /// The average size of a stored row, in bytes.
+    pub avg_row_bytes: u64,
+    /// The statistics of each column, in table column order.
+    pub columns: Vec<ColumnStats>,
+    /// The statistics of each secondary index, by column name.
+    pub indexes: HashMap<String, IndexStats>,
+}
+
+/// Column value statistics.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ColumnStats {
+    /// The fraction of rows where the column is NULL.
+    pub null_fraction: f64,
+    /// The number of distinct non-NULL values.
+    pub distinct: u64,
+    /// The most common non-NULL values held by more than one row, with the fraction of rows
+    /// holding each, most common first.
+    pub most_common_values: Vec<(Value, f64)>,
+}
+
+impl ColumnStats {
+    /// The maximum number of most common values to keep.
+    pub const MAX_MOST_COMMON_VALUES: usize = 10;
+
+    /// Computes statistics from the number of rows holding each value, out of the given number
+    /// of rows.
+    pub fn from_counts(mut counts: HashMap<Value, u64>, rows: u64) -> Self {
+        if rows == 0 {
+            return Self::default();
+        }
+        let nulls = counts.remove(&Value::Null).unwrap_or(0);
+        let distinct = counts.len() as u64;
+        let mut common: Vec<_> = counts.into_iter().filter(|(_, count)| *count > 1).collect();
+        common.sort_by(|(a, a_count), (b, b_count)| {
+            b_count.cmp(a_count).then_with(|| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        });
+        common.truncate(Self::MAX_MOST_COMMON_VALUES);
+        Self {
+            null_fraction: nulls as f64 / rows as f64,
+            distinct,
+            most_common_values: common
+                .into_iter()
+                .map(|(value, count)| (value, count as f64 / rows as f64))
+                .collect(),
+        }
+    }
+
+    /// Estimates the fraction of rows where the column equals a value. Values other than the
+    /// most common ones are assumed to be uniformly distributed over the remaining rows.
+    pub fn selectivity(&self, value: &Value) -> f64 {
+        if *value == Value::Null {
+            return self.null_fraction;
+        }
+        if let Some((_, fraction)) = self.most_common_values.iter().find(|(v, _)| v == value) {
+            return *fraction;
+        }
+        let common: f64 = self.most_common_values.iter().map(|(_, fraction)| fraction).sum();
+        match self.distinct - self.most_common_values.len() as u64 {
+            0 => 0.0,
+            others => (1.0 - self.null_fraction - common).max(0.0) / others as f64,
+        }
+    }
+}
+
+/// Secondary index statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct IndexStats {
+    /// The number of indexed rows, excluding rows that don't match a partial index predicate.
+    pub entries: u64,
+    /// The number of distinct indexed values.
+    pub distinct: u64,
+}
+
+impl IndexStats {
+    /// Estimates the number of rows looked up by a single value, assuming a uniform distribution.
+    pub fn rows_per_value(&self) -> f64 {
+        match self.distinct {
+            0 => 0.0,
+            distinct => self.entries as f64 / distinct as f64,
+        }
+    }
+}
+
 /// A table column schema
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Column {
@@ -157,6 +794,8 @@ pub struct Column {
     pub references: Option<String>,
     /// Whether the column should be indexed
     pub is_indexed: bool,
+    /// The predicate rows must satisfy to be indexed, if the index is partial
+    pub index_predicate: Option<Expression>,
 }
 
 impl Column {
@@ -170,10 +809,41 @@ impl Column {
             return Err(Error::Value(format!("Primary key {} must be unique", self.name)));
         }
 
+        // Validate index
+        if self.index_predicate.is_some() && !self.is_indexed {
+            return Err(Error::Value(format!(
+                "Index predicate given for non-indexed column {}",
+                self.name
+            )));
+        }
+
+        // Validate enum variants
+        if let DataType::Enum(variants) = &self.datatype {
+            if variants.is_empty() {
+                return Err(Error::Value(format!("Enum column {} has no variants", self.name)));
+            }
+            for (i, variant) in variants.iter().enumerate() {
+                if variants[..i].contains(variant) {
+                    return Err(Error::Value(format!(
+                        "Duplicate variant '{}' for enum column {}",
+                        variant, self.name
+                    )));
+                }
+            }
+        }
+
+        // Validate serial columns, whose values are assigned rather than defaulted
+        if self.datatype == DataType::Serial && (self.default.is_some() || self.is_nullable) {
+            return Err(Error::Value(format!(
+                "Serial column {} can't be nullable or have a default value",
+                self.name
+            )));
+        }
+
         // Validate default value
         if let Some(default) = &self.default {
             if let Some(datatype) = default.datatype() {
-                if datatype != self.datatype {
+                if !self.datatype.accepts(default) {
                     return Err(Error::Value(format!(
                         "Default value for column {} has datatype {}, must be {}",
                         self.name, datatype, self.datatype
@@ -204,7 +874,7 @@ impl Column {
                     reference, self.name
                 )));
             };
-            if self.datatype != target.get_primary_key()?.datatype {
+            if self.datatype.value_type() != target.get_primary_key()?.datatype.value_type() {
                 return Err(Error::Value(format!(
                     "Can't reference {} primary key of table {} from {} column {}",
                     target.get_primary_key()?.datatype,
@@ -227,12 +897,20 @@ impl Column {
         txn: &mut dyn SqlTxn
     ) -> Result<()> {
         // Validate datatype
-        match value.datatype() {
-            None if self.is_nullable => Ok(()),
-            None => Err(Error::Value(format!("NULL value not allowed for column {}", self.name))),
-            Some(ref datatype) if datatype != &self.datatype => Err(Error::Value(format!(
+        match (value.datatype(), &self.datatype) {
+            (None, _) if self.is_nullable => Ok(()),
+            (None, _) => Err(Error::Value(format!("NULL value not allowed for column {}", self.name))),
+            (Some(DataType::String), DataType::Enum(variants)) if !self.datatype.accepts(value) => {
+                Err(Error::Value(format!(
+                    "Invalid value {} for enum column {}, expected one of {}",
+                    value.to_sql_literal(),
+                    self.name,
+                    DataType::format_variants(variants)
+                )))
+            }
+            (Some(_), _) if !self.datatype.accepts(value) => Err(Error::Value(format!(
                 "Invalid datatype {} for {} column {}",
-                datatype, self.datatype, self.name
+                value.datatype().unwrap(), self.datatype, self.name
             ))),
             _ => Ok(()),
         }?;
@@ -245,20 +923,6 @@ impl Column {
             _ => Ok(()),
         }?;
 
-        // Validate outgoing references
-        if let Some(target) = &self.references {
-            match value {
-                Value::Null => Ok(()),
-                Value::Float(f) if f.is_nan() => Ok(()),
-                v if target == &table.name && v == primary_key => Ok(()),
-                v if txn.read(target, v)?.is_none() => Err(Error::Value(format!(
-                    "Referenced primary key {} in table {} does not exist",
-                    v, target,
-                ))),
-                _ => Ok(()),
-            }?;
-        }
-
         // Validate uniqueness constraints
         if self.is_unique && !self.is_primary_key && value != &Value::Null {
             let index = table.get_column_index(&self.name)?;
@@ -269,7 +933,8 @@ impl Column {
                 {
                     return Err(Error::Value(format!(
                         "Unique value {} already exists for column {}",
-                        value, self.name
+                        value.to_sql_literal(),
+                        self.name
                     )));
                 }
             }
@@ -279,6 +944,34 @@ impl Column {
     }
 }
 
+impl Column {
+    /// Returns whether a row belongs in the column's index, i.e. whether the column is indexed
+    /// and the row satisfies the index predicate, if any.
+    pub fn indexes(&self, row: &[Value]) -> Result<bool> {
+        if !self.is_indexed {
+            return Ok(false);
+        }
+        match &self.index_predicate {
+            Some(predicate) => Ok(predicate.evaluate(Some(&row.to_vec()))? == Value::Boolean(true)),
+            None => Ok(true),
+        }
+    }
+
+    /// Returns the value of the column when it isn't given on insert, i.e. its default value,
+    /// or NULL for SERIAL columns, which is replaced by the next value of the column's sequence
+    /// when the row is created. Errors if the column has neither.
+    pub fn omitted_value(&self) -> Result<Value> {
+        match &self.default {
+            Some(default) => Ok(default.clone()),
+            None if self.datatype == DataType::Serial => Ok(Value::Null),
+            None => Err(Error::Value(format!(
+                "Column {} not given and has no default value",
+                self.name
+            ))),
+        }
+    }
+}
+
 impl Display for Column {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut sql = format_ident(&self.name);
@@ -301,6 +994,9 @@ impl Display for Column {
         if self.is_indexed {
             sql += " INDEX";
         }
+        if let Some(predicate) = &self.index_predicate {
+            sql += &format!(" WHERE {}", predicate);
+        }
         write!(f, "{}", sql)
     }
 }
\ No newline at end of file
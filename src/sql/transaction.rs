@@ -0,0 +1,159 @@
+use crate::error::{Error, Result};
+use super::engine::{Mode, SqlEngine, SqlSession, SqlTxn};
+use super::execution::ResultSet;
+use super::parser::{ast, Parser};
+use super::plan::Plan;
+
+/// An explicit SQL transaction, which executes queries in a single MVCC transaction of the
+/// underlying engine. Writes are only visible to other transactions once committed: the local
+/// engine commits them atomically in the MVCC store, and the Raft engine with a single commit
+/// entry in the Raft log. Conflicting writes by concurrent transactions fail with
+/// Error::Serialization, and the transaction should then be rolled back and retried.
+pub struct Transaction<E: SqlEngine> {
+    txn: E::EngineTxn,
+}
+
+impl<E: SqlEngine + 'static> Transaction<E> {
+    /// Begins a read-write transaction.
+    pub fn begin(engine: &E) -> Result<Self> {
+        Ok(Self { txn: engine.begin(Mode::ReadWrite)? })
+    }
+
+    /// Begins a read-only transaction, which sees a snapshot of the data as of its start and
+    /// fails with Error::ReadOnly on writes.
+    pub fn read_only(engine: &E) -> Result<Self> {
+        Ok(Self { txn: engine.begin(Mode::ReadOnly)? })
+    }
+
+    /// The transaction ID.
+    pub fn id(&self) -> u64 {
+        self.txn.id()
+    }
+
+    /// The transaction mode.
+    pub fn mode(&self) -> Mode {
+        self.txn.mode()
+    }
+
+    /// Parses, plans and executes a query in the transaction. Transaction control statements are
+    /// not allowed, use commit() and rollback() instead.
+    pub fn execute(&mut self, query: &str) -> Result<ResultSet> {
+        match Parser::new(query).parse()? {
+            ast::Statement::Begin { .. } | ast::Statement::Commit | ast::Statement::Rollback => {
+                Err(Error::Value("Transaction control is not allowed in a transaction".into()))
+            }
+            ast::Statement::Explain { statement, analyze: false } => {
+                Ok(Plan::build(*statement, &mut self.txn)?.optimize(&mut self.txn)?.explain())
+            }
+            statement => SqlSession::<E>::execute_statement(statement, &mut self.txn),
+        }
+    }
+
+    /// Commits the transaction, making its writes visible to others.
+    pub fn commit(self) -> Result<()> {
+        self.txn.commit()
+    }
+
+    /// Rolls back the transaction, discarding its writes.
+    pub fn rollback(self) -> Result<()> {
+        self.txn.rollback()
+    }
+}
+
+/// Runs a closure in a new read-write transaction, which is committed if the closure succeeds
+/// and rolled back if it fails.
+pub fn with_transaction<E, T, F>(engine: &E, f: F) -> Result<T>
+where
+    E: SqlEngine + 'static,
+    F: FnOnce(&mut Transaction<E>) -> Result<T>,
+{
+    let mut txn = Transaction::begin(engine)?;
+    match f(&mut txn) {
+        Ok(result) => {
+            txn.commit()?;
+            Ok(result)
+        }
+        Err(err) => {
+            txn.rollback()?;
+            Err(err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::concurrency::MVCC;
+    use crate::sql::engine::KvSqlEngine;
+    use crate::sql::types::Value;
+    use crate::storage::kv::StdBPlusTree;
+    use super::*;
+
+    fn count(engine: &KvSqlEngine) -> Result<Value> {
+        Transaction::read_only(engine)?.execute("SELECT COUNT(*) FROM t")?.into_value()
+    }
+
+    #[test]
+    fn transaction() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        with_transaction(&engine, |txn| {
+            txn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER)")?;
+            txn.execute("INSERT INTO t VALUES (1, 10), (2, 20)")
+        })?;
+        assert_eq!(Value::Integer(2), count(&engine)?);
+
+        // Writes are only visible once committed, and are discarded on rollback.
+        let mut txn = Transaction::begin(&engine)?;
+        txn.execute("INSERT INTO t VALUES (3, 30)")?;
+        assert_eq!(Value::Integer(3), txn.execute("SELECT COUNT(*) FROM t")?.into_value()?);
+        assert_eq!(Value::Integer(2), count(&engine)?);
+        txn.rollback()?;
+        assert_eq!(Value::Integer(2), count(&engine)?);
+
+        // with_transaction() rolls back when the closure fails.
+        let result = with_transaction(&engine, |txn| {
+            txn.execute("INSERT INTO t VALUES (3, 30)")?;
+            txn.execute("INSERT INTO t VALUES (1, 10)")
+        });
+        assert!(result.is_err());
+        assert_eq!(Value::Integer(2), count(&engine)?);
+
+        // Read-only transactions reject writes, and transactions reject transaction control.
+        let mut txn = Transaction::read_only(&engine)?;
+        assert_eq!(Some(Error::ReadOnly), txn.execute("DELETE FROM t").err());
+        assert!(txn.execute("COMMIT").is_err());
+        txn.rollback()?;
+        Ok(())
+    }
+
+    #[test]
+    fn conflicting_writes() -> Result<()> {
+        let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+        with_transaction(&engine, |txn| {
+            txn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, value INTEGER)")?;
+            txn.execute("INSERT INTO t VALUES (1, 10)")
+        })?;
+
+        // A write to a row written by a concurrent transaction fails, even after it commits.
+        let mut a = Transaction::begin(&engine)?;
+        let mut b = Transaction::begin(&engine)?;
+        a.execute("UPDATE t SET value = 11 WHERE id = 1")?;
+        assert_eq!(
+            Some(Error::Serialization),
+            b.execute("UPDATE t SET value = 12 WHERE id = 1").err()
+        );
+        a.commit()?;
+        assert_eq!(
+            Some(Error::Serialization),
+            b.execute("UPDATE t SET value = 12 WHERE id = 1").err()
+        );
+        b.rollback()?;
+
+        // A retry in a new transaction sees the committed write.
+        let value = with_transaction(&engine, |txn| {
+            txn.execute("UPDATE t SET value = value + 1 WHERE id = 1")?;
+            txn.execute("SELECT value FROM t WHERE id = 1")?.into_value()
+        })?;
+        assert_eq!(Value::Integer(12), value);
+        Ok(())
+    }
+}
@@ -0,0 +1,406 @@
+//! Date and time handling for the TIMESTAMP and DATE datatypes. Timestamps are stored as
+//! microseconds since the Unix epoch in UTC, and dates as days since the Unix epoch. Time zones
+//! are only used when parsing: a timestamp with a UTC offset is converted to UTC, so the same
+//! instant is stored identically regardless of the offset (e.g. across DST transitions).
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+pub const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+pub const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+/// The largest number of days from the epoch whose midnight is a representable timestamp.
+pub const MAX_DAYS: i64 = i64::MAX / MICROS_PER_DAY;
+
+/// Returns the current time as a timestamp.
+pub fn now() -> Result<i64> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| Error::Internal(err.to_string()))?;
+    Ok(elapsed.as_micros() as i64)
+}
+
+/// Adds microseconds to a timestamp.
+pub fn add_micros(micros: i64, delta: i64) -> Result<i64> {
+    micros.checked_add(delta).ok_or_else(|| Error::Value("Timestamp out of range".into()))
+}
+
+/// Adds days to a date. The result must be convertible to a timestamp, see MAX_DAYS.
+pub fn add_days(days: i32, delta: i64) -> Result<i32> {
+    (days as i64)
+        .checked_add(delta)
+        .filter(|days| (-MAX_DAYS..=MAX_DAYS).contains(days))
+        .and_then(|days| i32::try_from(days).ok())
+        .ok_or_else(|| Error::Value("Date out of range".into()))
+}
+
+/// Converts a date to the timestamp at its midnight UTC.
+pub fn date_to_timestamp(days: i32) -> Result<i64> {
+    (days as i64)
+        .checked_mul(MICROS_PER_DAY)
+        .ok_or_else(|| Error::Value("Timestamp out of range".into()))
+}
+
+/// Parses an ISO 8601 date, i.e. YYYY-MM-DD, into days since the epoch.
+pub fn parse_date(s: &str) -> Result<i32> {
+    let mut parser = Parser::new(s);
+    let days = parser.date()?;
+    parser.end()?;
+    Ok(days as i32)
+}
+
+/// Parses an ISO 8601 timestamp into microseconds since the epoch. The date may be followed by
+/// a time, separated by T or a space, as HH:MM[:SS[.ffffff]], optionally followed by Z or a UTC
+/// offset as ±HH[:MM]. Timestamps without an offset are taken to be UTC. A leap second (:60) is
+/// folded into the first second of the next minute, since the epoch count ignores leap seconds.
+pub fn parse_timestamp(s: &str) -> Result<i64> {
+    let mut parser = Parser::new(s);
+    let mut micros = parser.date()? * MICROS_PER_DAY;
+    if parser.next_if(|c| c == 'T' || c == 't' || c == ' ') {
+        micros += parser.time()?;
+        micros -= parser.offset()?;
+    }
+    parser.end()?;
+    Ok(micros)
+}
+
+/// Formats days since the epoch as YYYY-MM-DD.
+pub fn format_date(days: i32) -> String {
+    let (year, month, day) = civil_from_days(days as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Formats a timestamp as YYYY-MM-DDTHH:MM:SS[.ffffff]Z.
+pub fn format_timestamp(micros: i64) -> String {
+    let (days, time) = (micros.div_euclid(MICROS_PER_DAY), micros.rem_euclid(MICROS_PER_DAY));
+    let (year, month, day) = civil_from_days(days);
+    let fraction = match time % MICROS_PER_SECOND {
+        0 => String::new(),
+        f => format!(".{:06}", f),
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        time / MICROS_PER_HOUR,
+        time % MICROS_PER_HOUR / MICROS_PER_MINUTE,
+        time % MICROS_PER_MINUTE / MICROS_PER_SECOND,
+        fraction
+    )
+}
+
+/// Truncates a timestamp to the start of the given unit: second, minute, hour, day, week
+/// (starting on Monday), month, quarter, or year.
+pub fn truncate(unit: &str, micros: i64) -> Result<i64> {
+    let truncate_to = |unit: i64| micros - micros.rem_euclid(unit);
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let (year, month, _) = civil_from_days(days);
+    Ok(match unit.to_lowercase().as_str() {
+        "second" => truncate_to(MICROS_PER_SECOND),
+        "minute" => truncate_to(MICROS_PER_MINUTE),
+        "hour" => truncate_to(MICROS_PER_HOUR),
+        "day" => truncate_to(MICROS_PER_DAY),
+        // The epoch was a Thursday, so Monday is 3 days before.
+        "week" => (days - (days + 3).rem_euclid(7)) * MICROS_PER_DAY,
+        "month" => days_from_civil(year, month, 1) * MICROS_PER_DAY,
+        "quarter" => days_from_civil(year, month - (month - 1) % 3, 1) * MICROS_PER_DAY,
+        "year" => days_from_civil(year, 1, 1) * MICROS_PER_DAY,
+        _ => return Err(Error::Value(format!("Unknown date_trunc unit {}", unit))),
+    })
+}
+
+/// Extracts a field from a timestamp: year, quarter, month, day, hour, minute, second,
+/// microsecond (within the second), dow (day of week, 0 for Sunday), doy (day of year, from
+/// 1), or epoch (seconds since the epoch).
+pub fn extract(field: &str, micros: i64) -> Result<i64> {
+    let days = micros.div_euclid(MICROS_PER_DAY);
+    let time = micros.rem_euclid(MICROS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    Ok(match field.to_lowercase().as_str() {
+        "year" => year,
+        "quarter" => (month - 1) / 3 + 1,
+        "month" => month,
+        "day" => day,
+        "hour" => time / MICROS_PER_HOUR,
+        "minute" => time % MICROS_PER_HOUR / MICROS_PER_MINUTE,
+        "second" => time % MICROS_PER_MINUTE / MICROS_PER_SECOND,
+        "microsecond" => time % MICROS_PER_SECOND,
+        "dow" => (days + 4).rem_euclid(7),
+        "doy" => days - days_from_civil(year, 1, 1) + 1,
+        "epoch" => micros.div_euclid(MICROS_PER_SECOND),
+        _ => return Err(Error::Value(format!("Unknown extract field {}", field))),
+    })
+}
+
+/// Returns the number of days since the epoch for a proleptic Gregorian calendar date. See
+/// http://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the proleptic Gregorian calendar date (year, month, day) for a number of days since
+/// the epoch. The inverse of days_from_civil().
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Returns the number of days in a month.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A parser for ISO 8601 dates and times.
+struct Parser<'a> {
+    input: &'a str,
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self { input, chars: input.chars().peekable() }
+    }
+
+    fn error(&self) -> Error {
+        Error::Value(format!("Invalid date or time {}", self.input))
+    }
+
+    /// Consumes the next character if it satisfies the predicate.
+    fn next_if<F: Fn(char) -> bool>(&mut self, predicate: F) -> bool {
+        self.chars.next_if(|c| predicate(*c)).is_some()
+    }
+
+    /// Consumes the given character, or errors.
+    fn expect(&mut self, c: char) -> Result<()> {
+        match self.next_if(|n| n == c) {
+            true => Ok(()),
+            false => Err(self.error()),
+        }
+    }
+
+    /// Consumes a fixed number of digits as an integer.
+    fn digits(&mut self, count: usize) -> Result<i64> {
+        let mut n = 0;
+        for _ in 0..count {
+            match self.chars.next().and_then(|c| c.to_digit(10)) {
+                Some(digit) => n = n * 10 + digit as i64,
+                None => return Err(self.error()),
+            }
+        }
+        Ok(n)
+    }
+
+    /// Checks that the whole input has been consumed.
+    fn end(&mut self) -> Result<()> {
+        match self.chars.peek() {
+            Some(_) => Err(self.error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Parses YYYY-MM-DD as days since the epoch.
+    fn date(&mut self) -> Result<i64> {
+        let year = self.digits(4)?;
+        self.expect('-')?;
+        let month = self.digits(2)?;
+        self.expect('-')?;
+        let day = self.digits(2)?;
+        if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+            return Err(Error::Value(format!("Date out of range: {}", self.input)));
+        }
+        Ok(days_from_civil(year, month, day))
+    }
+
+    /// Parses HH:MM[:SS[.ffffff]] as microseconds since midnight.
+    fn time(&mut self) -> Result<i64> {
+        let hour = self.digits(2)?;
+        self.expect(':')?;
+        let minute = self.digits(2)?;
+        let (mut second, mut fraction) = (0, 0);
+        if self.next_if(|c| c == ':') {
+            second = self.digits(2)?;
+            if self.next_if(|c| c == '.') {
+                let mut scale = MICROS_PER_SECOND;
+                while let Some(digit) = self.chars.next_if(char::is_ascii_digit) {
+                    if scale == 1 {
+                        return Err(Error::Value(format!(
+                            "Timestamp {} has more than 6 fractional digits",
+                            self.input
+                        )));
+                    }
+                    scale /= 10;
+                    fraction += digit.to_digit(10).unwrap() as i64 * scale;
+                }
+                if scale == MICROS_PER_SECOND {
+                    return Err(self.error());
+                }
+            }
+        }
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(Error::Value(format!("Time out of range: {}", self.input)));
+        }
+        Ok(hour * MICROS_PER_HOUR + minute * MICROS_PER_MINUTE + second * MICROS_PER_SECOND
+            + fraction)
+    }
+
+    /// Parses an optional Z or ±HH[:MM] UTC offset as microseconds ahead of UTC.
+    fn offset(&mut self) -> Result<i64> {
+        if self.next_if(|c| c == 'Z' || c == 'z') {
+            return Ok(0);
+        }
+        let sign = match self.chars.next_if(|c| *c == '+' || *c == '-') {
+            Some('-') => -1,
+            Some(_) => 1,
+            None => return Ok(0),
+        };
+        let hours = self.digits(2)?;
+        let minutes = match self.next_if(|c| c == ':') {
+            true => self.digits(2)?,
+            false if self.chars.peek().is_some() => self.digits(2)?,
+            false => 0,
+        };
+        if hours > 15 || minutes > 59 {
+            return Err(Error::Value(format!("UTC offset out of range: {}", self.input)));
+        }
+        Ok(sign * (hours * MICROS_PER_HOUR + minutes * MICROS_PER_MINUTE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> i64 {
+        parse_timestamp(s).unwrap()
+    }
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(0, ts("1970-01-01"));
+        assert_eq!(0, parse_date("1970-01-01").unwrap());
+        assert_eq!(-1, parse_date("1969-12-31").unwrap());
+        assert_eq!("2000-02-29", format_date(parse_date("2000-02-29").unwrap()));
+        assert_eq!("2024-07-01T12:34:56Z", format_timestamp(ts("2024-07-01 12:34:56")));
+        assert_eq!("2024-07-01T12:34:00Z", format_timestamp(ts("2024-07-01T12:34")));
+        assert_eq!("1969-12-31T23:59:59.500000Z", format_timestamp(ts("1969-12-31T23:59:59.5Z")));
+        assert_eq!("2024-07-01T10:04:56Z", format_timestamp(ts("2024-07-01T12:34:56+02:30")));
+        assert_eq!("2024-07-01T17:34:56Z", format_timestamp(ts("2024-07-01T12:34:56-0500")));
+
+        for invalid in [
+            "2024-7-01",
+            "2024-07-01X",
+            "2024-07-01T12",
+            "2024-07-01T12:34:56.",
+            "2024-07-01T12:34:56.1234567",
+            "2024-07-01T12:34:56+2",
+            "2023-02-29",
+            "1900-02-29",
+            "2024-13-01",
+            "2024-04-31",
+            "2024-07-01T24:00",
+            "2024-07-01T12:34:61",
+            "2024-07-01T12:34:56+16:00",
+        ] {
+            assert!(parse_timestamp(invalid).is_err(), "{} should be invalid", invalid);
+        }
+        assert!(parse_date("2024-07-01T00:00").is_err());
+    }
+
+    #[test]
+    fn date_range() {
+        let max = MAX_DAYS as i32;
+        assert_eq!(max, add_days(0, MAX_DAYS).unwrap());
+        assert_eq!(-max, add_days(0, -MAX_DAYS).unwrap());
+        assert!(add_days(max, 1).is_err());
+        assert!(add_days(-max, -1).is_err());
+        assert!(add_days(0, 2_000_000_000).is_err());
+        assert_eq!(MAX_DAYS * MICROS_PER_DAY, date_to_timestamp(max).unwrap());
+        assert!(date_to_timestamp(max + 1).is_err());
+        assert!(date_to_timestamp(i32::MIN).is_err());
+    }
+
+    #[test]
+    fn dst_transitions() {
+        // US Eastern springs forward from 01:59:59 EST to 03:00:00 EDT on 2024-03-10.
+        assert_eq!(
+            ts("2024-03-10T01:59:59-05:00") + MICROS_PER_SECOND,
+            ts("2024-03-10T03:00:00-04:00")
+        );
+        // And falls back on 2024-11-03, repeating 01:30 an hour apart under different offsets.
+        assert_eq!(
+            ts("2024-11-03T01:30:00-04:00") + MICROS_PER_HOUR,
+            ts("2024-11-03T01:30:00-05:00")
+        );
+        assert_eq!(ts("2024-11-03T05:30:00Z"), ts("2024-11-03T01:30:00-04:00"));
+        // Truncating to the day happens in UTC, independently of the original offset.
+        assert_eq!(ts("2024-03-10"), truncate("day", ts("2024-03-10T03:00:00-04:00")).unwrap());
+    }
+
+    #[test]
+    fn leap_seconds() {
+        assert_eq!(ts("2017-01-01T00:00:00Z"), ts("2016-12-31T23:59:60Z"));
+        assert_eq!(ts("2017-01-01T00:00:00.25Z"), ts("2016-12-31T23:59:60.25Z"));
+        assert_eq!(2017, extract("year", ts("2016-12-31T23:59:60Z")).unwrap());
+        // The epoch count ignores leap seconds, so a day spanning one is still 86400 seconds.
+        assert_eq!(86400, (ts("2017-01-01") - ts("2016-12-31")) / MICROS_PER_SECOND);
+    }
+
+    #[test]
+    fn truncate_and_extract() {
+        let t = ts("2024-05-15T13:45:30.123456Z");
+        for (unit, expect) in [
+            ("second", "2024-05-15T13:45:30Z"),
+            ("minute", "2024-05-15T13:45:00Z"),
+            ("hour", "2024-05-15T13:00:00Z"),
+            ("DAY", "2024-05-15T00:00:00Z"),
+            ("week", "2024-05-13T00:00:00Z"),
+            ("month", "2024-05-01T00:00:00Z"),
+            ("quarter", "2024-04-01T00:00:00Z"),
+            ("year", "2024-01-01T00:00:00Z"),
+        ] {
+            assert_eq!(expect, format_timestamp(truncate(unit, t).unwrap()), "{}", unit);
+        }
+        assert!(truncate("fortnight", t).is_err());
+
+        for (field, expect) in [
+            ("year", 2024),
+            ("quarter", 2),
+            ("month", 5),
+            ("day", 15),
+            ("hour", 13),
+            ("minute", 45),
+            ("second", 30),
+            ("microsecond", 123456),
+            ("dow", 3),
+            ("doy", 136),
+            ("epoch", 1715780730),
+        ] {
+            assert_eq!(expect, extract(field, t).unwrap(), "{}", field);
+        }
+        assert_eq!(-1, extract("epoch", ts("1969-12-31T23:59:59.5Z")).unwrap());
+        assert!(extract("century", t).is_err());
+        assert!(now().unwrap() > ts("2020-01-01"));
+    }
+}
@@ -0,0 +1,373 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// An exact fixed-point decimal number, stored as an integer mantissa scaled by 10^-scale. Values
+/// compare and hash by their numeric value, so 1.5 and 1.50 are equal.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Decimal {
+    mantissa: i128,
+    scale: u32,
+}
+
+impl Decimal {
+    /// The maximum number of significant digits, bounded by the i128 mantissa.
+    pub const MAX_PRECISION: u32 = 38;
+    /// The minimum scale of division results, which are rounded rather than exact.
+    pub const DIVISION_SCALE: u32 = 6;
+
+    /// Creates a decimal from a mantissa and scale, i.e. mantissa * 10^-scale. Errors if the
+    /// mantissa has more than MAX_PRECISION digits.
+    pub fn new(mantissa: i128, scale: u32) -> Result<Self> {
+        if scale > Self::MAX_PRECISION {
+            return Err(Error::Value(format!(
+                "Decimal scale {} exceeds maximum {}",
+                scale,
+                Self::MAX_PRECISION
+            )));
+        }
+        if mantissa.unsigned_abs() >= Self::pow10(Self::MAX_PRECISION)?.unsigned_abs() {
+            return Err(Self::overflow());
+        }
+        Ok(Self { mantissa, scale })
+    }
+
+    /// Converts a float to the decimal with the shortest representation that round-trips to it.
+    pub fn from_f64(f: f64) -> Result<Self> {
+        if !f.is_finite() {
+            return Err(Error::Value(format!("Can't convert {} to decimal", f)));
+        }
+        f.to_string().parse()
+    }
+
+    /// Returns the mantissa, i.e. the value scaled by 10^scale.
+    pub fn mantissa(&self) -> i128 {
+        self.mantissa
+    }
+
+    /// Returns the scale, i.e. the number of fractional digits.
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Returns the precision, i.e. the number of digits needed to represent the value with its
+    /// current scale.
+    pub fn precision(&self) -> u32 {
+        let mut digits = 1;
+        let mut m = self.mantissa.unsigned_abs();
+        while m >= 10 {
+            m /= 10;
+            digits += 1;
+        }
+        digits.max(self.scale)
+    }
+
+    /// Returns the decimal with the given scale, rounding half away from zero if the scale is
+    /// reduced.
+    pub fn rescale(&self, scale: u32) -> Result<Self> {
+        let mantissa = match scale.cmp(&self.scale) {
+            Ordering::Equal => self.mantissa,
+            Ordering::Greater => self
+                .mantissa
+                .checked_mul(Self::pow10(scale - self.scale)?)
+                .ok_or_else(Self::overflow)?,
+            Ordering::Less => Self::div_round(self.mantissa, Self::pow10(self.scale - scale)?),
+        };
+        Self::new(mantissa, scale)
+    }
+
+    /// Rescales the decimal to the given scale, and checks that it fits in the given precision.
+    pub fn fit(&self, precision: u32, scale: u32) -> Result<Self> {
+        let decimal = self.rescale(scale)?;
+        if decimal.mantissa.unsigned_abs() >= Self::pow10(precision)?.unsigned_abs() {
+            return Err(Error::Value(format!(
+                "Decimal {} does not fit in DECIMAL({}, {})",
+                self, precision, scale
+            )));
+        }
+        Ok(decimal)
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<Self> {
+        let (lhs, rhs, scale) = self.align(other)?;
+        Self::new(lhs.checked_add(rhs).ok_or_else(Self::overflow)?, scale)
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<Self> {
+        let (lhs, rhs, scale) = self.align(other)?;
+        Self::new(lhs.checked_sub(rhs).ok_or_else(Self::overflow)?, scale)
+    }
+
+    /// Multiplies the decimals. Products with more than MAX_PRECISION fractional digits are
+    /// rounded. If the exact product has too many digits, the operands' fractional digits are
+    /// rounded away first, starting with the longer operand.
+    pub fn checked_mul(&self, other: &Self) -> Result<Self> {
+        let scale = self.scale + other.scale;
+        let Some(mantissa) = self.mantissa.checked_mul(other.mantissa) else {
+            let digits = |d: &Self| d.mantissa.unsigned_abs().checked_ilog10().map_or(1, |n| n + 1);
+            let (lhs_digits, rhs_digits) = (digits(self), digits(other));
+            let (mut lhs_cut, mut rhs_cut) = (0, 0);
+            while lhs_digits - lhs_cut + rhs_digits - rhs_cut > Self::MAX_PRECISION {
+                let lhs_longer = lhs_digits - lhs_cut >= rhs_digits - rhs_cut;
+                if lhs_cut < self.scale && (lhs_longer || rhs_cut == other.scale) {
+                    lhs_cut += 1;
+                } else if rhs_cut < other.scale {
+                    rhs_cut += 1;
+                } else {
+                    return Err(Self::overflow());
+                }
+            }
+            let lhs = self.rescale(self.scale - lhs_cut)?;
+            return lhs.checked_mul(&other.rescale(other.scale - rhs_cut)?);
+        };
+        match scale > Self::MAX_PRECISION {
+            true => Self { mantissa, scale }.rescale(Self::MAX_PRECISION),
+            false => Self::new(mantissa, scale),
+        }
+    }
+
+    pub fn checked_neg(&self) -> Result<Self> {
+        Self::new(self.mantissa.checked_neg().ok_or_else(Self::overflow)?, self.scale)
+    }
+
+    /// Divides the decimals, rounding the result to the larger of their scales and
+    /// DIVISION_SCALE.
+    pub fn checked_div(&self, other: &Self) -> Result<Self> {
+        if other.mantissa == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        let scale = self.scale.max(other.scale).max(Self::DIVISION_SCALE);
+        let lhs = self
+            .mantissa
+            .checked_mul(Self::pow10(scale + other.scale - self.scale)?)
+            .ok_or_else(Self::overflow)?;
+        Self::new(Self::div_round(lhs, other.mantissa), scale)
+    }
+
+    /// Takes the remainder of the decimals, with the sign of the dividend like integers.
+    pub fn checked_rem(&self, other: &Self) -> Result<Self> {
+        if other.mantissa == 0 {
+            return Err(Error::DivisionByZero);
+        }
+        let (lhs, rhs, scale) = self.align(other)?;
+        Self::new(lhs % rhs, scale)
+    }
+
+    /// Raises the decimal to a non-negative integer power, by repeated squaring.
+    pub fn checked_pow(&self, exp: u32) -> Result<Self> {
+        let one = Self::pow10(self.scale)?;
+        match (self.mantissa, exp) {
+            (_, 0) => return Ok(Self { mantissa: 1, scale: 0 }),
+            (0, _) => return Ok(*self),
+            (m, _) if m == one || m == -one && exp & 1 == 1 => return Ok(*self),
+            (m, _) if m == -one => return self.checked_neg(),
+            _ => {}
+        }
+        let (mut result, mut base, mut exp) = (Self { mantissa: 1, scale: 0 }, *self, exp);
+        loop {
+            if exp & 1 == 1 {
+                result = result.checked_mul(&base)?;
+            }
+            exp >>= 1;
+            if exp == 0 {
+                return Ok(result);
+            }
+            base = base.checked_mul(&base)?;
+        }
+    }
+
+    /// Returns the mantissas of both decimals at their common scale.
+    fn align(&self, other: &Self) -> Result<(i128, i128, u32)> {
+        let scale = self.scale.max(other.scale);
+        Ok((self.rescale(scale)?.mantissa, other.rescale(scale)?.mantissa, scale))
+    }
+
+    /// Divides integers, rounding half away from zero.
+    fn div_round(lhs: i128, rhs: i128) -> i128 {
+        let (quotient, remainder) = (lhs / rhs, lhs % rhs);
+        if remainder.unsigned_abs() >= rhs.unsigned_abs() - remainder.unsigned_abs() {
+            quotient + if (lhs < 0) == (rhs < 0) { 1 } else { -1 }
+        } else {
+            quotient
+        }
+    }
+
+    /// Returns 10^exp, erroring on overflow.
+    fn pow10(exp: u32) -> Result<i128> {
+        10_i128.checked_pow(exp).ok_or_else(Self::overflow)
+    }
+
+    fn overflow() -> Error {
+        Error::Value("Decimal overflow".into())
+    }
+
+    /// Returns the decimal with trailing fractional zeros removed.
+    pub(crate) fn normalize(&self) -> Self {
+        let mut decimal = *self;
+        while decimal.scale > 0 && decimal.mantissa % 10 == 0 {
+            decimal.mantissa /= 10;
+            decimal.scale -= 1;
+        }
+        decimal
+    }
+}
+
+impl From<i64> for Decimal {
+    fn from(i: i64) -> Self {
+        Self { mantissa: i as i128, scale: 0 }
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Value(format!("Invalid decimal {}", s));
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if integer.is_empty() && fraction.is_empty()
+            || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        let mut mantissa: i128 = 0;
+        for c in integer.chars().chain(fraction.chars()) {
+            mantissa = mantissa
+                .checked_mul(10)
+                .and_then(|m| m.checked_add(c.to_digit(10).unwrap() as i128))
+                .ok_or_else(Self::overflow)?;
+        }
+        Self::new(if negative { -mantissa } else { mantissa }, fraction.len() as u32)
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.scale as usize + 1;
+        let digits = format!("{:0>width$}", self.mantissa.unsigned_abs(), width = width);
+        let (integer, fraction) = digits.split_at(digits.len() - self.scale as usize);
+        let sign = if self.mantissa < 0 { "-" } else { "" };
+        match fraction.is_empty() {
+            true => write!(f, "{}{}", sign, integer),
+            false => write!(f, "{}{}.{}", sign, integer, fraction),
+        }
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.align(other) {
+            Ok((lhs, rhs, _)) => lhs.cmp(&rhs),
+            // The side with the smaller scale overflowed when scaled up, so its magnitude is
+            // larger than the other's and its sign determines the order.
+            Err(_) if self.scale < other.scale => self.mantissa.cmp(&0),
+            Err(_) => 0.cmp(&other.mantissa),
+        }
+    }
+}
+
+impl Hash for Decimal {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let decimal = self.normalize();
+        decimal.mantissa.hash(state);
+        decimal.scale.hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        for s in ["0", "1", "-1", "1.50", "-0.05", "123456.789", "0.000"] {
+            assert_eq!(s, d(s).to_string());
+        }
+        assert_eq!("0.5", d(".5").to_string());
+        assert_eq!("2", d("+2.").to_string());
+        assert!("".parse::<Decimal>().is_err());
+        assert!(".".parse::<Decimal>().is_err());
+        assert!("1e3".parse::<Decimal>().is_err());
+        assert!("1.2.3".parse::<Decimal>().is_err());
+        assert!("1".repeat(40).parse::<Decimal>().is_err());
+        assert_eq!(d("0.1"), Decimal::from_f64(0.1).unwrap());
+        assert!(Decimal::from_f64(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!("0.3", d("0.1").checked_add(&d("0.2")).unwrap().to_string());
+        assert_eq!("-1.05", d("1.2").checked_sub(&d("2.25")).unwrap().to_string());
+        assert_eq!("0.0050", d("0.05").checked_mul(&d("0.10")).unwrap().to_string());
+        assert_eq!("0.333333", d("1").checked_div(&d("3")).unwrap().to_string());
+        assert_eq!("-0.666667", d("-2").checked_div(&d("3")).unwrap().to_string());
+        assert_eq!("-0.5", d("-5.5").checked_rem(&d("1")).unwrap().to_string());
+        assert_eq!("1.21", d("1.1").checked_pow(2).unwrap().to_string());
+        assert_eq!("1.61051", d("1.1").checked_pow(5).unwrap().to_string());
+        assert_eq!("1", d("0.5").checked_pow(0).unwrap().to_string());
+        assert_eq!(d("0"), d("0.0").checked_pow(u32::MAX).unwrap());
+        assert_eq!(d("1"), d("1.00").checked_pow(u32::MAX).unwrap());
+        assert_eq!(d("-1"), d("-1").checked_pow(u32::MAX).unwrap());
+        assert_eq!(d("1"), d("-1.0").checked_pow(u32::MAX - 1).unwrap());
+        assert!(d("2").checked_pow(u32::MAX).is_err());
+        let pow = d("0.9").checked_pow(100).unwrap();
+        assert_eq!("0.00002656139888758748", pow.rescale(20).unwrap().to_string());
+        assert!(d("1").checked_div(&d("0.00")).is_err());
+        assert!(d(&"9".repeat(38)).checked_add(&d("1")).is_err());
+    }
+
+    #[test]
+    fn rescale_and_fit() {
+        assert_eq!("1.25", d("1.245").rescale(2).unwrap().to_string());
+        assert_eq!("-1.25", d("-1.245").rescale(2).unwrap().to_string());
+        assert_eq!("1.2400", d("1.24").rescale(4).unwrap().to_string());
+        assert_eq!(3, d("1.25").precision());
+        assert_eq!(2, d("0.05").precision());
+        assert_eq!("999.99", d("999.99").fit(5, 2).unwrap().to_string());
+        assert!(d("999.995").fit(5, 2).is_err());
+        assert!(d("1000").fit(5, 2).is_err());
+    }
+
+    #[test]
+    fn compare_and_hash() {
+        use std::collections::hash_map::DefaultHasher;
+        let hash = |d: Decimal| {
+            let mut hasher = DefaultHasher::new();
+            d.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(d("1.5"), d("1.500"));
+        assert_eq!(hash(d("1.5")), hash(d("1.500")));
+        assert!(d("-0.01") < d("0"));
+        assert!(d("10") > d("9.99"));
+        let huge = d(&"9".repeat(38));
+        assert!(huge > d("0.5"));
+        assert!(d("0.5") < huge);
+    }
+}
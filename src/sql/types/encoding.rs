@@ -0,0 +1,113 @@
+//! Order-preserving encodings of values and rows, for storing them in the key-value store.
+//!
+//! Value: The type-prefixed encoding of crate::encoding::encode_value(). Integers are big-endian
+//!        with the sign bit flipped, and strings have 0x00 escaped as 0x00 0xff and are
+//!        terminated by 0x00 0x00, such that values of the same type sort like the values
+//!        themselves.
+//! Row:   The encoded values concatenated, such that rows sort by their first column, then their
+//!        second, etc, and a row sorts before the rows it is a prefix of.
+
+use super::{Row, Value};
+use crate::encoding;
+use crate::error::{Error, Result};
+
+/// Encodes a value.
+pub fn encode_value(value: &Value) -> Vec<u8> {
+    encoding::encode_value(value)
+}
+
+/// Decodes a value from the start of a byte slice, returning it with the remaining bytes.
+pub fn decode_value(mut bytes: &[u8]) -> Result<(Value, &[u8])> {
+    let value = encoding::take_value(&mut bytes)?;
+    Ok((value, bytes))
+}
+
+/// Encodes a row.
+pub fn encode_row(row: &Row) -> Vec<u8> {
+    row.iter().flat_map(encode_value).collect()
+}
+
+/// Decodes a row, which must span the entire byte slice.
+pub fn decode_row(mut bytes: &[u8]) -> Result<Row> {
+    let mut row = Row::new();
+    while !bytes.is_empty() {
+        let (value, rest) = decode_value(bytes)
+            .map_err(|err| Error::Internal(format!("Invalid row encoding: {}", err)))?;
+        row.push(value);
+        bytes = rest;
+    }
+    Ok(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// Generates a random integer, string, or null value.
+    fn random_value(rng: &mut StdRng) -> Value {
+        match rng.gen_range(0..4) {
+            0 => Value::Null,
+            1 => Value::Integer(rng.gen_range(-3..3)),
+            2 => Value::Integer(rng.gen()),
+            _ => {
+                let chars = ['\0', 'a', 'b', 'ÿ'];
+                let len = rng.gen_range(0..4);
+                Value::String((0..len).map(|_| chars[rng.gen_range(0..4)]).collect())
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let row: Row = (0..rng.gen_range(0..5)).map(|_| random_value(&mut rng)).collect();
+            assert_eq!(row, decode_row(&encode_row(&row))?);
+        }
+
+        let row = vec![
+            Value::Boolean(true),
+            Value::Float(-1.5),
+            Value::Decimal("3.14".parse()?),
+            Value::Timestamp(-1),
+            Value::Date(7),
+        ];
+        assert_eq!(row, decode_row(&encode_row(&row))?);
+
+        let bytes = [encode_value(&Value::Integer(7)), vec![0xaa]].concat();
+        assert_eq!((Value::Integer(7), &[0xaa][..]), decode_value(&bytes)?);
+        assert!(decode_row(&bytes).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sort_order() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let (a, b) = (random_value(&mut rng), random_value(&mut rng));
+            if let Some(ordering) = a.partial_cmp(&b) {
+                assert_eq!(ordering, encode_value(&a).cmp(&encode_value(&b)), "{} vs {}", a, b);
+            }
+        }
+
+        // Rows sort column by column, with 0x00 bytes and prefixes ordering correctly.
+        let rows = vec![
+            vec![Value::Integer(i64::MIN)],
+            vec![Value::Integer(-1), Value::String("b".into())],
+            vec![Value::Integer(0)],
+            vec![Value::Integer(0), Value::String("".into())],
+            vec![Value::Integer(0), Value::String("\0".into())],
+            vec![Value::Integer(0), Value::String("\0\0".into())],
+            vec![Value::Integer(0), Value::String("a".into())],
+            vec![Value::Integer(0), Value::String("a\0".into())],
+            vec![Value::Integer(0), Value::String("ab".into())],
+            vec![Value::Integer(i64::MAX), Value::String("a".into())],
+        ];
+        for pair in rows.windows(2) {
+            assert!(encode_row(&pair[0]) < encode_row(&pair[1]), "{:?} < {:?}", pair[0], pair[1]);
+        }
+    }
+}
@@ -1,10 +1,12 @@
+use std::cmp::Ordering;
 use std::fmt::{Display, self};
 
-use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
-use super::{Row, Value};
+use crate::sql::extension::FunctionRegistry;
+use crate::sql::plan::Node;
+use super::{datetime, DataType, Row, Value};
 
 /// An expression, made up of constants and operations
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -12,6 +14,10 @@ pub enum Expression {
     // Values
     Constant(Value),
     Field(usize, Option<(Option<String>, String)>),
+    /// A field of the left-hand row in a lateral join, bound to a constant before execution.
+    Outer(usize, Option<(Option<String>, String)>),
+    /// A numbered prepared statement parameter, bound to a constant before execution.
+    Parameter(u64),
 
     // Logical operations
     And(Box<Expression>, Box<Expression>),
@@ -36,7 +42,52 @@ pub enum Expression {
     Subtract(Box<Expression>, Box<Expression>),
 
     // String operations
-    Like(Box<Expression>, Box<Expression>),
+    /// Concatenates two values as strings, see Value::sql_concat().
+    Concat(Box<Expression>, Box<Expression>),
+    /// Matches a string against a pattern, see like_match(). The escape character defaults to
+    /// a backslash, and an empty escape string disables escaping.
+    Like {
+        value: Box<Expression>,
+        pattern: Box<Expression>,
+        escape: Option<Box<Expression>>,
+        case_insensitive: bool,
+    },
+
+    // Type conversions
+    /// Converts a value to the target datatype, see DataType::cast().
+    Cast { value: Box<Expression>, target: DataType },
+
+    // Conditional operations
+    /// Returns the result of the first branch whose condition is true, or else_expr (or NULL)
+    /// if none match. With an operand, the branch conditions are values compared to it with SQL
+    /// equality, so a NULL operand or value never matches.
+    Case {
+        operand: Option<Box<Expression>>,
+        branches: Vec<(Expression, Expression)>,
+        else_expr: Option<Box<Expression>>,
+    },
+
+    // Date and time operations
+    /// Truncates a timestamp to the given unit, e.g. 'day'.
+    DateTrunc(Box<Expression>, Box<Expression>),
+    /// Extracts the given field, e.g. 'year', from a timestamp.
+    Extract(Box<Expression>, Box<Expression>),
+    Now,
+
+    // Extension functions
+    /// Calls a function registered by a native extension, see sql::extension.
+    Function(String, Vec<Expression>),
+
+    // Subqueries
+    /// A subquery returning a single column and at most one row, or NULL if it returns none.
+    /// Outer fields in the subquery plan refer to the row the expression is evaluated for, and
+    /// are bound to its values before executing it, like the right side of a lateral join.
+    ScalarSubquery(Box<Node>),
+    /// Whether the value equals any row of a single-column subquery, with the NULL semantics of
+    /// a chain of ORed equalities.
+    InSubquery { value: Box<Expression>, subquery: Box<Node>, negated: bool },
+    /// Whether a subquery returns any rows.
+    ExistsSubquery(Box<Node>),
 }
 
 impl Expression {
@@ -47,118 +98,56 @@ impl Expression {
             // Constant values
             Self::Constant(c) => c.clone(),
             Self::Field(i, _) => row.and_then(|row| row.get(*i).cloned()).unwrap_or(Null),
+            Self::Outer(i, _) => {
+                return Err(Error::Internal(format!("Unbound outer field #{}", i)))
+            }
+            Self::Parameter(n) => return Err(Error::Value(format!("Unbound parameter ${}", n))),
+            // Subqueries are executed and replaced by their results before evaluation, where
+            // supported, see execution::subquery.
+            Self::ScalarSubquery(_) | Self::InSubquery { .. } | Self::ExistsSubquery(_) => {
+                return Err(Error::Value(
+                    "Subqueries are only supported in SELECT, WHERE and HAVING clauses".into(),
+                ))
+            }
 
             // Logical operations
-            Self::And(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Boolean(lhs), Boolean(rhs)) => Boolean(lhs && rhs),
-                (Boolean(lhs), Null) if !lhs => Boolean(false),
-                (Boolean(_), Null) => Null,
-                (Null, Boolean(rhs)) if !rhs => Boolean(false),
-                (Null, Boolean(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => return Err(Error::Value(format!("Can't and {} and {}", lhs, rhs))),
-            },
-            Self::Not(expr) => match expr.evaluate(row)? {
-                Boolean(b) => Boolean(!b),
-                Null => Null,
-                value => return Err(Error::Value(format!("Can't negate {}", value))),
-            },
-            Self::Or(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Boolean(lhs), Boolean(rhs)) => Boolean(lhs || rhs),
-                (Boolean(lhs), Null) if lhs => Boolean(true),
-                (Boolean(_), Null) => Null,
-                (Null, Boolean(rhs)) if rhs => Boolean(true),
-                (Null, Boolean(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => return Err(Error::Value(format!("Can't or {} and {}", lhs, rhs))),
-            },
+            Self::And(lhs, rhs) => lhs.evaluate(row)?.sql_and(&rhs.evaluate(row)?)?,
+            Self::Not(expr) => expr.evaluate(row)?.sql_not()?,
+            Self::Or(lhs, rhs) => lhs.evaluate(row)?.sql_or(&rhs.evaluate(row)?)?,
 
-            // Comparison operations
-            #[allow(clippy::float_cmp)] // Up to the user if they want to compare or not
-            Self::Equal(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Boolean(lhs), Boolean(rhs)) => Boolean(lhs == rhs),
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs == rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 == rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs == rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs == rhs),
-                (String(lhs), String(rhs)) => Boolean(lhs == rhs),
-                (Null, _) | (_, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            Self::GreaterThan(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                #[allow(clippy::bool_comparison)]
-                (Boolean(lhs), Boolean(rhs)) => Boolean(lhs > rhs),
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs > rhs),
-                (Integer(lhs), Float(rhs)) => Boolean(lhs as f64 > rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs > rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs > rhs),
-                (String(lhs), String(rhs)) => Boolean(lhs > rhs),
-                (Null, _) | (_, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
-            Self::LessThan(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                #[allow(clippy::bool_comparison)]
-                (Boolean(lhs), Boolean(rhs)) => Boolean(lhs < rhs),
-                (Integer(lhs), Integer(rhs)) => Boolean(lhs < rhs),
-                (Integer(lhs), Float(rhs)) => Boolean((lhs as f64) < rhs),
-                (Float(lhs), Integer(rhs)) => Boolean(lhs < rhs as f64),
-                (Float(lhs), Float(rhs)) => Boolean(lhs < rhs),
-                (String(lhs), String(rhs)) => Boolean(lhs < rhs),
-                (Null, _) | (_, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't compare {} and {}", lhs, rhs)))
-                }
-            },
+            // Comparison operations, after coercing temporal values to a common type
+            Self::Equal(lhs, rhs) => {
+                let (lhs, rhs) = Self::evaluate_temporal(lhs, rhs, row)?;
+                lhs.sql_eq(&rhs)?.map(Boolean).unwrap_or(Null)
+            }
+            Self::GreaterThan(lhs, rhs) => {
+                Self::evaluate_comparison(lhs, rhs, row, Ordering::Greater)?
+            }
+            Self::LessThan(lhs, rhs) => Self::evaluate_comparison(lhs, rhs, row, Ordering::Less)?,
             Self::IsNull(expr) => match expr.evaluate(row)? {
                 Null => Boolean(true),
                 _ => Boolean(false),
             },
 
-            // Mathematical operations
-            Self::Add(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Integer(lhs), Integer(rhs)) => Integer(
-                    lhs.checked_add(rhs).ok_or_else(|| Error::Value("Integer overflow".into()))?,
-                ),
-                (Integer(lhs), Float(rhs)) => Float(lhs as f64 + rhs),
-                (Integer(_), Null) => Null,
-                (Float(lhs), Float(rhs)) => Float(lhs + rhs),
-                (Float(lhs), Integer(rhs)) => Float(lhs + rhs as f64),
-                (Float(_), Null) => Null,
-                (Null, Float(_)) => Null,
-                (Null, Integer(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => return Err(Error::Value(format!("Can't add {} and {}", lhs, rhs))),
-            },
+            // Mathematical operations, after coercing operands to a common type
+            Self::Add(lhs, rhs) => {
+                let (lhs, rhs) = Self::coerce_operands(lhs.evaluate(row)?, rhs.evaluate(row)?)?;
+                (lhs + rhs)?
+            }
             Self::Assert(expr) => match expr.evaluate(row)? {
                 Float(f) => Float(f),
+                Decimal(d) => Decimal(d),
                 Integer(i) => Integer(i),
                 Null => Null,
                 expr => return Err(Error::Value(format!("Can't take the positive of {}", expr))),
             },
-            Self::Divide(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Integer(_), Integer(rhs)) if rhs == 0 => {
-                    return Err(Error::Value("Can't divide by zero".into()))
-                }
-                (Integer(lhs), Integer(rhs)) => Integer(lhs / rhs),
-                (Integer(lhs), Float(rhs)) => Float(lhs as f64 / rhs),
-                (Integer(_), Null) => Null,
-                (Float(lhs), Integer(rhs)) => Float(lhs / rhs as f64),
-                (Float(lhs), Float(rhs)) => Float(lhs / rhs),
-                (Float(_), Null) => Null,
-                (Null, Float(_)) => Null,
-                (Null, Integer(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't divide {} and {}", lhs, rhs)))
-                }
-            },
+            Self::Divide(lhs, rhs) => {
+                let (lhs, rhs) = Self::coerce_operands(lhs.evaluate(row)?, rhs.evaluate(row)?)?;
+                (lhs / rhs)?
+            }
             Self::Exponentiate(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
                 (Integer(lhs), Integer(rhs)) if rhs >= 0 => Integer(
-                    lhs.checked_pow(rhs as u32)
+                    lhs.checked_pow(Self::exponent(rhs)?)
                         .ok_or_else(|| Error::Value("Integer overflow".into()))?,
                 ),
                 (Integer(lhs), Integer(rhs)) => Float((lhs as f64).powf(rhs as f64)),
@@ -166,12 +155,20 @@ impl Expression {
                 (Integer(_), Null) => Null,
                 (Float(lhs), Integer(rhs)) => Float((lhs).powi(rhs as i32)),
                 (Float(lhs), Float(rhs)) => Float((lhs).powf(rhs)),
+                (Decimal(lhs), Integer(rhs)) if rhs >= 0 => {
+                    Decimal(lhs.checked_pow(Self::exponent(rhs)?)?)
+                }
+                (Decimal(_), Null) | (Null, Decimal(_)) => Null,
                 (Float(_), Null) => Null,
                 (Null, Float(_)) => Null,
                 (Null, Integer(_)) => Null,
                 (Null, Null) => Null,
                 (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't exponentiate {} and {}", lhs, rhs)))
+                    return Err(Error::Value(format!(
+                        "Can't exponentiate {} and {}",
+                        lhs.to_sql_literal(),
+                        rhs.to_sql_literal()
+                    )))
                 }
             },
             Self::Factorial(expr) => match expr.evaluate(row)? {
@@ -180,91 +177,525 @@ impl Expression {
                 }
                 Integer(i) => Integer((1..=i).fold(1, |a, b| a * b as i64)),
                 Null => Null,
-                value => return Err(Error::Value(format!("Can't take factorial of {}", value))),
+                value => {
+                    return Err(Error::Value(format!(
+                        "Can't take factorial of {}",
+                        value.to_sql_literal()
+                    )))
+                }
             },
-            Self::Modulo(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                // This uses remainder semantics, like Postgres.
-                (Integer(_), Integer(rhs)) if rhs == 0 => {
-                    return Err(Error::Value("Can't divide by zero".into()))
+            // This uses remainder semantics, like Postgres.
+            Self::Modulo(lhs, rhs) => {
+                let (lhs, rhs) = Self::coerce_operands(lhs.evaluate(row)?, rhs.evaluate(row)?)?;
+                (lhs % rhs)?
+            }
+            Self::Multiply(lhs, rhs) => {
+                let (lhs, rhs) = Self::coerce_operands(lhs.evaluate(row)?, rhs.evaluate(row)?)?;
+                (lhs * rhs)?
+            }
+            Self::Negate(expr) => (-expr.evaluate(row)?)?,
+            Self::Subtract(lhs, rhs) => {
+                let (lhs, rhs) = Self::evaluate_temporal(lhs, rhs, row)?;
+                let (lhs, rhs) = Self::coerce_operands(lhs, rhs)?;
+                (lhs - rhs)?
+            }
+
+            // String operations
+            Self::Concat(lhs, rhs) => lhs.evaluate(row)?.sql_concat(&rhs.evaluate(row)?)?,
+            Self::Like { value, pattern, escape, case_insensitive } => {
+                let escape = match escape.as_ref().map(|e| e.evaluate(row)).transpose()? {
+                    None => String("\\".into()),
+                    Some(escape) => escape,
+                };
+                match (value.evaluate(row)?, pattern.evaluate(row)?, escape) {
+                    (String(value), String(pattern), String(escape)) => {
+                        let mut chars = escape.chars();
+                        let escape = match (chars.next(), chars.next()) {
+                            (escape, None) => escape,
+                            _ => {
+                                return Err(Error::Value(format!(
+                                    "Invalid escape string '{}', must be one character or empty",
+                                    escape
+                                )))
+                            }
+                        };
+                        Boolean(like_match(&value, &pattern, escape, *case_insensitive)?)
+                    }
+                    (String(_) | Null, String(_) | Null, String(_) | Null) => Null,
+                    (value, pattern, escape) => {
+                        return Err(Error::Value(format!(
+                            "Can't LIKE {} and {} with escape {}",
+                            value.to_sql_literal(),
+                            pattern.to_sql_literal(),
+                            escape.to_sql_literal()
+                        )))
+                    }
                 }
-                (Integer(lhs), Integer(rhs)) => Integer(lhs % rhs),
-                (Integer(lhs), Float(rhs)) => Float(lhs as f64 % rhs),
-                (Integer(_), Null) => Null,
-                (Float(lhs), Integer(rhs)) => Float(lhs % rhs as f64),
-                (Float(lhs), Float(rhs)) => Float(lhs % rhs),
-                (Float(_), Null) => Null,
-                (Null, Float(_)) => Null,
-                (Null, Integer(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't take modulo of {} and {}", lhs, rhs)))
+            }
+
+            // Type conversions
+            Self::Cast { value, target } => target.cast(value.evaluate(row)?)?,
+
+            // Conditional operations
+            Self::Case { operand, branches, else_expr } => {
+                let operand = operand.as_ref().map(|expr| expr.evaluate(row)).transpose()?;
+                for (condition, result) in branches {
+                    let condition = match &operand {
+                        Some(operand) => Self::Equal(
+                            Box::new(Self::Constant(operand.clone())),
+                            Box::new(Self::Constant(condition.evaluate(row)?)),
+                        )
+                        .evaluate(None)?,
+                        None => condition.evaluate(row)?,
+                    };
+                    match condition {
+                        Boolean(true) => return result.evaluate(row),
+                        Boolean(false) | Null => {}
+                        value => {
+                            return Err(Error::Value(format!(
+                                "CASE condition returned {}, expected boolean",
+                                value.to_sql_literal()
+                            )))
+                        }
+                    }
                 }
-            },
-            Self::Multiply(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Integer(lhs), Integer(rhs)) => Integer(
-                    lhs.checked_mul(rhs).ok_or_else(|| Error::Value("Integer overflow".into()))?,
-                ),
-                (Integer(lhs), Float(rhs)) => Float(lhs as f64 * rhs),
-                (Integer(_), Null) => Null,
-                (Float(lhs), Integer(rhs)) => Float(lhs * rhs as f64),
-                (Float(lhs), Float(rhs)) => Float(lhs * rhs),
-                (Float(_), Null) => Null,
-                (Null, Float(_)) => Null,
-                (Null, Integer(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't multiply {} and {}", lhs, rhs)))
+                match else_expr {
+                    Some(expr) => expr.evaluate(row)?,
+                    None => Null,
                 }
-            },
-            Self::Negate(expr) => match expr.evaluate(row)? {
-                Integer(i) => Integer(-i),
-                Float(f) => Float(-f),
-                Null => Null,
-                value => return Err(Error::Value(format!("Can't negate {}", value))),
-            },
-            Self::Subtract(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (Integer(lhs), Integer(rhs)) => Integer(
-                    lhs.checked_sub(rhs).ok_or_else(|| Error::Value("Integer overflow".into()))?,
-                ),
-                (Integer(lhs), Float(rhs)) => Float(lhs as f64 - rhs),
-                (Integer(_), Null) => Null,
-                (Float(lhs), Integer(rhs)) => Float(lhs - rhs as f64),
-                (Float(lhs), Float(rhs)) => Float(lhs - rhs),
-                (Float(_), Null) => Null,
-                (Null, Float(_)) => Null,
-                (Null, Integer(_)) => Null,
-                (Null, Null) => Null,
-                (lhs, rhs) => {
-                    return Err(Error::Value(format!("Can't subtract {} and {}", lhs, rhs)))
+            }
+
+            // Date and time operations
+            Self::DateTrunc(unit, expr) => {
+                match (unit.evaluate(row)?, Self::to_timestamp(expr.evaluate(row)?)?) {
+                    (String(unit), Timestamp(t)) => Timestamp(datetime::truncate(&unit, t)?),
+                    (Null, _) | (_, Null) => Null,
+                    (unit, value) => {
+                        return Err(Error::Value(format!(
+                            "Can't truncate {} to {}",
+                            value.to_sql_literal(),
+                            unit.to_sql_literal()
+                        )))
+                    }
                 }
-            },
+            }
+            Self::Extract(field, expr) => {
+                match (field.evaluate(row)?, Self::to_timestamp(expr.evaluate(row)?)?) {
+                    (String(field), Timestamp(t)) => Integer(datetime::extract(&field, t)?),
+                    (Null, _) | (_, Null) => Null,
+                    (field, value) => {
+                        return Err(Error::Value(format!(
+                            "Can't extract {} from {}",
+                            field.to_sql_literal(),
+                            value.to_sql_literal()
+                        )))
+                    }
+                }
+            }
+            Self::Now => Timestamp(datetime::now()?),
 
-            // String operations
-            Self::Like(lhs, rhs) => match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
-                (String(lhs), String(rhs)) => Boolean(
-                    Regex::new(&format!(
-                        "^{}$",
-                        regex::escape(&rhs)
-                            .replace("%", ".*")
-                            .replace(".*.*", "%")
-                            .replace("_", ".")
-                            .replace("..", "_")
-                    ))?
-                    .is_match(&lhs),
-                ),
-                (String(_), Null) => Null,
-                (Null, String(_)) => Null,
-                (lhs, rhs) => return Err(Error::Value(format!("Can't LIKE {} and {}", lhs, rhs))),
+            // Extension functions
+            Self::Function(name, args) => FunctionRegistry::global().read().call(
+                name,
+                args.iter().map(|arg| arg.evaluate(row)).collect::<Result<_>>()?,
+            )?,
+        })
+    }
+
+    /// Converts a non-negative integer exponent to u32, erroring if it is too large.
+    fn exponent(exp: i64) -> Result<u32> {
+        u32::try_from(exp).map_err(|_| Error::Value(format!("Exponent {} is too large", exp)))
+    }
+
+    /// Converts a value to a timestamp, parsing strings and taking dates at midnight UTC. Nulls
+    /// are returned as is.
+    fn to_timestamp(value: Value) -> Result<Value> {
+        match value {
+            Value::Null | Value::Timestamp(_) => Ok(value),
+            Value::Date(d) => Ok(Value::Timestamp(datetime::date_to_timestamp(d)?)),
+            Value::String(s) => Ok(Value::Timestamp(datetime::parse_timestamp(&s)?)),
+            value => {
+                Err(Error::Value(format!("Can't convert {} to timestamp", value.to_sql_literal())))
+            }
+        }
+    }
+
+    /// Coerces the operands of an arithmetic operation to their common type, e.g. integers to
+    /// floats, see DataType::common_type(). Operands without one are returned as is, for the
+    /// operation to combine or reject, e.g. timestamps and integers.
+    fn coerce_operands(lhs: Value, rhs: Value) -> Result<(Value, Value)> {
+        match (lhs.datatype(), rhs.datatype()) {
+            (Some(a), Some(b)) if a != b => match DataType::common_type(&a, &b) {
+                Ok(common) => Ok((lhs.coerce(&common)?, rhs.coerce(&common)?)),
+                Err(_) => Ok((lhs, rhs)),
             },
+            _ => Ok((lhs, rhs)),
+        }
+    }
+
+    /// Evaluates whether lhs compares to rhs with the given ordering: NULL if either is NULL,
+    /// and false if they are unordered floats. Operands cast to an enum compare by declaration
+    /// order.
+    fn evaluate_comparison(
+        lhs: &Self,
+        rhs: &Self,
+        row: Option<&Row>,
+        ordering: Ordering,
+    ) -> Result<Value> {
+        let datatype = lhs.enum_type().or_else(|| rhs.enum_type());
+        let (lhs, rhs) = Self::evaluate_temporal(lhs, rhs, row)?;
+        if lhs == Value::Null || rhs == Value::Null {
+            return Ok(Value::Null);
+        }
+        let cmp = match datatype {
+            Some(datatype) => datatype.compare(&lhs, &rhs),
+            None => lhs.sql_cmp(&rhs)?,
+        };
+        Ok(Value::Boolean(cmp == Some(ordering)))
+    }
+
+    /// Returns the enum datatype the expression is explicitly cast to, if any. Such expressions
+    /// are compared and sorted by the enum's declaration order rather than lexically.
+    pub fn enum_type(&self) -> Option<&DataType> {
+        match self {
+            Self::Cast { target: target @ DataType::Enum(_), .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    /// Wraps the expression in a cast to its enum datatype, if it has one, given the datatypes
+    /// of the row fields. Enum values are strings at runtime, so the cast records the variant
+    /// order for comparisons and sorting (see enum_type()).
+    pub fn cast_enum(self, fields: &[Option<DataType>]) -> Self {
+        match self.infer_type(fields) {
+            Some(target @ DataType::Enum(_)) if self.enum_type().is_none() => {
+                Self::Cast { value: Box::new(self), target }
+            }
+            _ => self,
+        }
+    }
+
+    /// Evaluates the operands of a comparison or subtraction. Strings combined with dates or
+    /// timestamps are parsed, and dates combined with timestamps are converted to timestamps.
+    /// Other operands are returned as is.
+    fn evaluate_temporal(lhs: &Self, rhs: &Self, row: Option<&Row>) -> Result<(Value, Value)> {
+        use Value::*;
+        Ok(match (lhs.evaluate(row)?, rhs.evaluate(row)?) {
+            (lhs @ Timestamp(_), rhs @ (Date(_) | String(_)))
+            | (lhs @ Date(_), rhs @ (Timestamp(_) | String(_)))
+            | (lhs @ String(_), rhs @ (Timestamp(_) | Date(_))) => {
+                (Self::to_timestamp(lhs)?, Self::to_timestamp(rhs)?)
+            }
+            (lhs, rhs) => (lhs, rhs),
         })
     }
-    
+
+    /// Infers the datatype of the expression's result, given the datatypes of the row fields.
+    /// Returns None if the datatype can't be determined statically, e.g. for NULL constants,
+    /// integer exponentiation (which yields a float for negative exponents) or decimal arithmetic
+    /// (whose result scale depends on the operands).
+    pub fn infer_type(&self, fields: &[Option<DataType>]) -> Option<DataType> {
+        use DataType::*;
+        match self {
+            Self::Constant(c) => c.datatype(),
+            Self::Field(i, _) => fields.get(*i).cloned().flatten(),
+            Self::Outer(..) | Self::Parameter(_) | Self::ScalarSubquery(_) => None,
+
+            Self::And(..)
+            | Self::Not(_)
+            | Self::Or(..)
+            | Self::Equal(..)
+            | Self::GreaterThan(..)
+            | Self::IsNull(_)
+            | Self::LessThan(..)
+            | Self::Like { .. }
+            | Self::InSubquery { .. }
+            | Self::ExistsSubquery(_) => Some(Boolean),
+
+            Self::Add(lhs, rhs)
+            | Self::Divide(lhs, rhs)
+            | Self::Modulo(lhs, rhs)
+            | Self::Multiply(lhs, rhs)
+            | Self::Subtract(lhs, rhs) => {
+                match (lhs.infer_type(fields), rhs.infer_type(fields)) {
+                    (Some(Integer), Some(Integer)) => Some(Integer),
+                    (Some(Integer | Float), Some(Integer | Float)) => Some(Float),
+                    _ => None,
+                }
+            }
+            Self::Exponentiate(lhs, rhs) => {
+                match (lhs.infer_type(fields), rhs.infer_type(fields)) {
+                    (Some(Float), Some(Integer | Float)) | (Some(Integer), Some(Float)) => {
+                        Some(Float)
+                    }
+                    _ => None,
+                }
+            }
+            Self::Assert(expr) | Self::Negate(expr) => match expr.infer_type(fields) {
+                Some(Integer) => Some(Integer),
+                Some(Float) => Some(Float),
+                Some(Decimal(precision, scale)) => Some(Decimal(precision, scale)),
+                _ => None,
+            },
+            Self::Factorial(_) => Some(Integer),
+            Self::Concat(..) => Some(String),
+
+            Self::Cast { target, .. } => Some(target.clone()),
+
+            // The results must all have the same type, except for NULL constants.
+            Self::Case { branches, else_expr, .. } => {
+                let mut datatype = None;
+                for result in branches.iter().map(|(_, r)| r).chain(else_expr.as_deref()) {
+                    match (result.infer_type(fields), &datatype) {
+                        (None, _) if *result == Self::Constant(Value::Null) => {}
+                        (None, _) => return None,
+                        (Some(t), None) => datatype = Some(t),
+                        (Some(t), Some(d)) if t == *d => {}
+                        (Some(_), Some(_)) => return None,
+                    }
+                }
+                datatype
+            }
+
+            Self::DateTrunc(..) | Self::Now => Some(Timestamp),
+            Self::Extract(..) => Some(Integer),
+            Self::Function(name, _) => {
+                FunctionRegistry::global().read().get(name).map(|f| f.return_type.clone())
+            }
+        }
+    }
+
+    /// Inserts explicit casts where arithmetic operators combine an integer and a float operand,
+    /// converting the integer to a float, given the datatypes of the row fields. Evaluation
+    /// promotes such operands anyway, but explicit casts make the conversion visible in plans.
+    /// Exponentiation is left alone, since float bases with integer exponents use powi(). Enum
+    /// operands of ordering comparisons are cast to their enum type, see cast_enum().
+    pub fn insert_casts(self, fields: &[Option<DataType>]) -> Result<Self> {
+        self.transform(&Ok, &|expr| Ok(expr.insert_cast(fields)))
+    }
+
+    /// Inserts casts for the operands of the expression's own operator, but not those of nested
+    /// operators, see insert_casts(). Used when transforming expressions bottom-up.
+    pub fn insert_cast(self, fields: &[Option<DataType>]) -> Self {
+        let cast = |lhs: Box<Self>, rhs: Box<Self>| -> (Box<Self>, Box<Self>) {
+            let float = |expr| Box::new(Self::Cast { value: expr, target: DataType::Float });
+            match (lhs.infer_type(fields), rhs.infer_type(fields)) {
+                (Some(DataType::Integer), Some(DataType::Float)) => (float(lhs), rhs),
+                (Some(DataType::Float), Some(DataType::Integer)) => (lhs, float(rhs)),
+                _ => (lhs, rhs),
+            }
+        };
+        match self {
+            Self::Add(lhs, rhs) => {
+                let (lhs, rhs) = cast(lhs, rhs);
+                Self::Add(lhs, rhs)
+            }
+            Self::Divide(lhs, rhs) => {
+                let (lhs, rhs) = cast(lhs, rhs);
+                Self::Divide(lhs, rhs)
+            }
+            Self::Modulo(lhs, rhs) => {
+                let (lhs, rhs) = cast(lhs, rhs);
+                Self::Modulo(lhs, rhs)
+            }
+            Self::Multiply(lhs, rhs) => {
+                let (lhs, rhs) = cast(lhs, rhs);
+                Self::Multiply(lhs, rhs)
+            }
+            Self::Subtract(lhs, rhs) => {
+                let (lhs, rhs) = cast(lhs, rhs);
+                Self::Subtract(lhs, rhs)
+            }
+            Self::GreaterThan(lhs, rhs) => Self::GreaterThan(
+                Box::new(lhs.cast_enum(fields)),
+                Box::new(rhs.cast_enum(fields)),
+            ),
+            Self::LessThan(lhs, rhs) => Self::LessThan(
+                Box::new(lhs.cast_enum(fields)),
+                Box::new(rhs.cast_enum(fields)),
+            ),
+            expr => expr,
+        }
+    }
+
     /// Walks the expression tree while calling a closure. Returns true as soon as the closure
     /// returns true. This is the inverse of walk().
     pub fn contains<F: Fn(&Expression) -> bool>(&self, visitor: &F) -> bool {
         !self.walk(&|e| !visitor(e))
     }
 
+    /// Returns whether the expression contains a subquery.
+    pub fn contains_subquery(&self) -> bool {
+        self.contains(&|e| {
+            matches!(e, Self::ScalarSubquery(_) | Self::InSubquery { .. } | Self::ExistsSubquery(_))
+        })
+    }
+
+    /// Returns the name of an output column computed by the expression, when it isn't given a
+    /// label: the column name for field references, and otherwise the rendered expression,
+    /// e.g. "rating * 2".
+    pub fn to_column_name(&self) -> String {
+        match self {
+            Self::Field(_, Some((_, name))) | Self::Outer(_, Some((_, name))) => name.clone(),
+            expr => expr.to_sql(),
+        }
+    }
+
+    /// Renders the expression as SQL, with constants as SQL literals and operands parenthesized
+    /// where the parser's operator precedence requires it, such that distinct expressions don't
+    /// render the same. Unlike Display, which is used for plans, it doesn't show field indexes.
+    fn to_sql(&self) -> String {
+        let operand = |expr: &Self, min: u8| match expr.precedence() {
+            precedence if precedence < min => format!("({})", expr.to_sql()),
+            _ => expr.to_sql(),
+        };
+        // Infix operators are left-associative, so a right operand of equal precedence needs
+        // parentheses.
+        let infix = |lhs: &Self, op: &str, rhs: &Self| {
+            let precedence = self.precedence();
+            format!("{} {} {}", operand(lhs, precedence), op, operand(rhs, precedence + 1))
+        };
+        match self {
+            Self::Constant(v) => v.to_sql_literal(),
+            Self::Field(..) | Self::Outer(..) | Self::Now | Self::Parameter(_) => self.to_string(),
+
+            Self::And(lhs, rhs) => infix(lhs, "AND", rhs),
+            Self::Or(lhs, rhs) => infix(lhs, "OR", rhs),
+            Self::Not(expr) => format!("NOT {}", operand(expr, 10)),
+
+            Self::Equal(lhs, rhs) => infix(lhs, "=", rhs),
+            Self::GreaterThan(lhs, rhs) => infix(lhs, ">", rhs),
+            Self::LessThan(lhs, rhs) => infix(lhs, "<", rhs),
+            Self::IsNull(expr) => format!("{} IS NULL", operand(expr, 9)),
+
+            Self::Add(lhs, rhs) => infix(lhs, "+", rhs),
+            Self::Assert(expr) => expr.to_sql(),
+            Self::Divide(lhs, rhs) => infix(lhs, "/", rhs),
+            Self::Exponentiate(lhs, rhs) => format!("{} ^ {}", operand(lhs, 9), operand(rhs, 8)),
+            Self::Factorial(expr) => format!("{}!", operand(expr, 9)),
+            Self::Modulo(lhs, rhs) => infix(lhs, "%", rhs),
+            Self::Multiply(lhs, rhs) => infix(lhs, "*", rhs),
+            // Parenthesize nested negations, since -- starts a comment.
+            Self::Negate(expr) => match operand(expr, 10) {
+                s if s.starts_with('-') => format!("-({})", s),
+                s => format!("-{}", s),
+            },
+            Self::Subtract(lhs, rhs) => infix(lhs, "-", rhs),
+
+            Self::Concat(lhs, rhs) => infix(lhs, "||", rhs),
+            Self::Like { value, pattern, escape, case_insensitive } => {
+                let op = if *case_insensitive { "ILIKE" } else { "LIKE" };
+                let s = infix(value, op, pattern);
+                match escape {
+                    Some(escape) => format!("{} ESCAPE {}", s, operand(escape, 4)),
+                    None => s,
+                }
+            }
+
+            Self::Cast { value, target } => format!("CAST({} AS {})", value.to_sql(), target),
+
+            Self::Case { operand, branches, else_expr } => {
+                let mut s = "CASE".to_string();
+                if let Some(operand) = operand {
+                    s += &format!(" {}", operand.to_sql());
+                }
+                for (cond, result) in branches {
+                    s += &format!(" WHEN {} THEN {}", cond.to_sql(), result.to_sql());
+                }
+                if let Some(expr) = else_expr {
+                    s += &format!(" ELSE {}", expr.to_sql());
+                }
+                s + " END"
+            }
+
+            Self::DateTrunc(unit, expr) => {
+                format!("date_trunc({}, {})", unit.to_sql(), expr.to_sql())
+            }
+            Self::Extract(field, expr) => format!("extract({} FROM {})", field, expr.to_sql()),
+            Self::Function(name, args) => {
+                let args = args.iter().map(|arg| arg.to_sql()).collect::<Vec<_>>();
+                format!("{}({})", name, args.join(", "))
+            }
+
+            Self::ScalarSubquery(_) => "(subquery)".to_string(),
+            Self::InSubquery { value, negated, .. } => {
+                let op = if *negated { "NOT IN" } else { "IN" };
+                format!("{} {} (subquery)", operand(value, 3), op)
+            }
+            Self::ExistsSubquery(_) => "EXISTS (subquery)".to_string(),
+        }
+    }
+
+    /// Returns the precedence of the expression's operator as parsed, see the parser's Operator
+    /// implementations, with atoms binding tightest. Negative constants render as negations.
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Or(..) => 1,
+            Self::And(..) => 2,
+            Self::Equal(..) | Self::Like { .. } | Self::InSubquery { .. } => 3,
+            Self::GreaterThan(..) | Self::LessThan(..) => 4,
+            Self::Concat(..) => 5,
+            Self::Add(..) | Self::Subtract(..) => 6,
+            Self::Multiply(..) | Self::Divide(..) | Self::Modulo(..) => 7,
+            Self::Exponentiate(..) => 8,
+            // A negative constant ranks below a negation, such that (-1)! isn't rendered as -1!,
+            // which parses as -(1!).
+            Self::Constant(v) if v.to_sql_literal().starts_with('-') => 8,
+            Self::IsNull(..) | Self::Factorial(..) => 9,
+            Self::Not(..) | Self::Negate(..) => 10,
+            Self::Assert(expr) => expr.precedence(),
+            _ => 11,
+        }
+    }
+
+    /// Returns whether evaluating the expression does more than compute a value from its
+    /// operands, i.e. executes a subquery. Such expressions can't be evaluated without a
+    /// transaction, and must not be removed by simplification.
+    pub fn has_side_effects(&self) -> bool {
+        self.contains_subquery()
+    }
+
+    /// Returns whether the expression is constant, i.e. doesn't depend on the row it's
+    /// evaluated for, and can be evaluated ahead of time.
+    fn is_constant(&self) -> bool {
+        !self.has_side_effects()
+            && !self.contains(&|e| {
+                matches!(e, Self::Field(_, _) | Self::Outer(_, _) | Self::Parameter(_))
+            })
+    }
+
+    /// Folds constant sub-expressions into their evaluated value, bottom-up, e.g. a + (1 + 2)
+    /// becomes a + 3. Logical operations are also simplified when one operand decides the
+    /// result regardless of the other, e.g. a AND FALSE becomes FALSE, provided the other
+    /// operand has no side effects.
+    pub fn fold_constants(&self) -> Result<Expression> {
+        use Value::*;
+        self.clone().transform(&Ok, &|expr| {
+            if !matches!(expr, Self::Constant(_)) && expr.is_constant() {
+                return Ok(Self::Constant(expr.evaluate(None)?));
+            }
+            Ok(match expr {
+                Self::And(lhs, rhs) => match (*lhs, *rhs) {
+                    (Self::Constant(Boolean(false)), e) | (e, Self::Constant(Boolean(false)))
+                        if !e.has_side_effects() =>
+                    {
+                        Self::Constant(Boolean(false))
+                    }
+                    (lhs, rhs) => Self::And(lhs.into(), rhs.into()),
+                },
+                Self::Or(lhs, rhs) => match (*lhs, *rhs) {
+                    (Self::Constant(Boolean(true)), e) | (e, Self::Constant(Boolean(true)))
+                        if !e.has_side_effects() =>
+                    {
+                        Self::Constant(Boolean(true))
+                    }
+                    (lhs, rhs) => Self::Or(lhs.into(), rhs.into()),
+                },
+                expr => expr,
+            })
+        })
+    }
+
     /// Replaces the expression with result of the closure. Helper function for transform().
     fn replace_with<F: Fn(Self) -> Result<Self>>(&mut self, f: F) -> Result<()> {
         // Temporarily replace expression with a null value, in case closure panics. May consider
@@ -280,24 +711,50 @@ impl Expression {
             && match self {
                 Self::Add(lhs, rhs)
                 | Self::And(lhs, rhs)
+                | Self::Concat(lhs, rhs)
+                | Self::DateTrunc(lhs, rhs)
                 | Self::Divide(lhs, rhs)
                 | Self::Equal(lhs, rhs)
                 | Self::Exponentiate(lhs, rhs)
+                | Self::Extract(lhs, rhs)
                 | Self::GreaterThan(lhs, rhs)
                 | Self::LessThan(lhs, rhs)
-                | Self::Like(lhs, rhs)
                 | Self::Modulo(lhs, rhs)
                 | Self::Multiply(lhs, rhs)
                 | Self::Or(lhs, rhs)
                 | Self::Subtract(lhs, rhs) => lhs.walk(visitor) && rhs.walk(visitor),
 
+                Self::Like { value, pattern, escape, .. } => {
+                    value.walk(visitor)
+                        && pattern.walk(visitor)
+                        && escape.iter().all(|e| e.walk(visitor))
+                }
+
                 Self::Assert(expr)
                 | Self::Factorial(expr)
                 | Self::IsNull(expr)
                 | Self::Negate(expr)
-                | Self::Not(expr) => expr.walk(visitor),
+                | Self::Not(expr)
+                | Self::Cast { value: expr, .. }
+                | Self::InSubquery { value: expr, .. } => expr.walk(visitor),
+
+                Self::Case { operand, branches, else_expr } => {
+                    operand.iter().chain(else_expr).all(|expr| expr.walk(visitor))
+                        && branches
+                            .iter()
+                            .all(|(cond, result)| cond.walk(visitor) && result.walk(visitor))
+                }
 
-                Self::Constant(_) | Self::Field(_, _) => true,
+                Self::Function(_, args) => args.iter().all(|arg| arg.walk(visitor)),
+
+                // Subquery plans have their own fields, and are not walked.
+                Self::Constant(_)
+                | Self::Field(_, _)
+                | Self::Outer(_, _)
+                | Self::Parameter(_)
+                | Self::Now
+                | Self::ScalarSubquery(_)
+                | Self::ExistsSubquery(_) => true,
             }
     }
 
@@ -311,12 +768,14 @@ impl Expression {
         match &mut self {
             Self::Add(lhs, rhs)
             | Self::And(lhs, rhs)
+            | Self::Concat(lhs, rhs)
+            | Self::DateTrunc(lhs, rhs)
             | Self::Divide(lhs, rhs)
             | Self::Equal(lhs, rhs)
             | Self::Exponentiate(lhs, rhs)
+            | Self::Extract(lhs, rhs)
             | Self::GreaterThan(lhs, rhs)
             | Self::LessThan(lhs, rhs)
-            | Self::Like(lhs, rhs)
             | Self::Modulo(lhs, rhs)
             | Self::Multiply(lhs, rhs)
             | Self::Or(lhs, rhs)
@@ -329,9 +788,41 @@ impl Expression {
             | Self::Factorial(expr)
             | Self::IsNull(expr)
             | Self::Negate(expr)
-            | Self::Not(expr) => Self::replace_with(expr, |e| e.transform(before, after))?,
+            | Self::Not(expr)
+            | Self::Cast { value: expr, .. }
+            | Self::InSubquery { value: expr, .. } => {
+                Self::replace_with(expr, |e| e.transform(before, after))?
+            }
+
+            Self::Like { value, pattern, escape, .. } => {
+                for expr in [value, pattern].into_iter().chain(escape.iter_mut()) {
+                    Self::replace_with(expr, |e| e.transform(before, after))?;
+                }
+            }
+
+            Self::Case { operand, branches, else_expr } => {
+                for expr in operand.iter_mut().chain(else_expr.iter_mut()) {
+                    Self::replace_with(expr, |e| e.transform(before, after))?;
+                }
+                for (cond, result) in branches.iter_mut() {
+                    Self::replace_with(cond, |e| e.transform(before, after))?;
+                    Self::replace_with(result, |e| e.transform(before, after))?;
+                }
+            }
+
+            Self::Function(_, args) => {
+                for arg in args.iter_mut() {
+                    Self::replace_with(arg, |e| e.transform(before, after))?;
+                }
+            }
 
-            Self::Constant(_) | Self::Field(_, _) => {}
+            Self::Constant(_)
+            | Self::Field(_, _)
+            | Self::Outer(_, _)
+            | Self::Parameter(_)
+            | Self::Now
+            | Self::ScalarSubquery(_)
+            | Self::ExistsSubquery(_) => {}
         };
         after(self)
     }
@@ -344,6 +835,9 @@ impl Display for Expression {
             Self::Field(i, None) => format!("#{}", i),
             Self::Field(_, Some((None, name))) => name.to_string(),
             Self::Field(_, Some((Some(table), name))) => format!("{}.{}", table, name),
+            Self::Outer(i, None) => format!("outer #{}", i),
+            Self::Outer(_, Some((None, name))) => format!("outer {}", name),
+            Self::Outer(_, Some((Some(table), name))) => format!("outer {}.{}", table, name),
 
             Self::And(lhs, rhs) => format!("{} AND {}", lhs, rhs),
             Self::Or(lhs, rhs) => format!("{} OR {}", lhs, rhs),
@@ -364,8 +858,511 @@ impl Display for Expression {
             Self::Negate(expr) => format!("-{}", expr),
             Self::Subtract(lhs, rhs) => format!("{} - {}", lhs, rhs),
 
-            Self::Like(lhs, rhs) => format!("{} LIKE {}", lhs, rhs),
+            Self::Concat(lhs, rhs) => format!("{} || {}", lhs, rhs),
+            Self::Like { value, pattern, escape, case_insensitive } => {
+                let op = if *case_insensitive { "ILIKE" } else { "LIKE" };
+                match escape {
+                    Some(escape) => format!("{} {} {} ESCAPE {}", value, op, pattern, escape),
+                    None => format!("{} {} {}", value, op, pattern),
+                }
+            }
+
+            Self::Cast { value, target } => format!("CAST({} AS {})", value, target),
+
+            Self::Case { operand, branches, else_expr } => {
+                let mut s = "CASE".to_string();
+                if let Some(operand) = operand {
+                    s += &format!(" {}", operand);
+                }
+                for (cond, result) in branches {
+                    s += &format!(" WHEN {} THEN {}", cond, result);
+                }
+                if let Some(expr) = else_expr {
+                    s += &format!(" ELSE {}", expr);
+                }
+                s + " END"
+            }
+
+            Self::DateTrunc(unit, expr) => format!("date_trunc({}, {})", unit, expr),
+            Self::Extract(field, expr) => format!("extract({} FROM {})", field, expr),
+            Self::Now => "now()".to_string(),
+            Self::Function(name, args) => {
+                let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+                format!("{}({})", name, args.join(", "))
+            }
+            Self::Parameter(n) => format!("${}", n),
+
+            Self::ScalarSubquery(_) => "(subquery)".to_string(),
+            Self::InSubquery { value, negated: false, .. } => format!("{} IN (subquery)", value),
+            Self::InSubquery { value, negated: true, .. } => {
+                format!("{} NOT IN (subquery)", value)
+            }
+            Self::ExistsSubquery(_) => "EXISTS (subquery)".to_string(),
         };
         write!(f, "{}", s)
     }
 }
+
+/// Matches a string against a LIKE pattern, where % matches any number of characters, _
+/// matches a single character, and the escape character matches the following character
+/// literally. Case-insensitive matching compares characters by their lowercase and uppercase
+/// forms. Errors if the pattern ends with the escape character.
+pub fn like_match(
+    value: &str,
+    pattern: &str,
+    escape: Option<char>,
+    case_insensitive: bool,
+) -> Result<bool> {
+    /// A pattern element. Consecutive %s are collapsed, since they match the same strings.
+    #[derive(PartialEq)]
+    enum Element {
+        Any,
+        One,
+        Char(char),
+    }
+
+    let mut elements = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        elements.push(match c {
+            c if Some(c) == escape => match chars.next() {
+                Some(c) => Element::Char(c),
+                None => {
+                    return Err(Error::Value(format!(
+                        "LIKE pattern '{}' must not end with the escape character",
+                        pattern
+                    )))
+                }
+            },
+            '%' if elements.last() == Some(&Element::Any) => continue,
+            '%' => Element::Any,
+            '_' => Element::One,
+            c => Element::Char(c),
+        });
+    }
+    let equal = |a: char, b: char| {
+        a == b
+            || case_insensitive
+                && (a.to_lowercase().eq(b.to_lowercase()) || a.to_uppercase().eq(b.to_uppercase()))
+    };
+
+    // Match greedily, and on a mismatch backtrack to the last % and let it match one more
+    // character. Only the last % needs to be retried, since any match of the elements after it
+    // can also be reached from it, so this takes O(value * pattern) time.
+    let value: Vec<char> = value.chars().collect();
+    let (mut i, mut j) = (0, 0);
+    let mut backtrack = None;
+    while i < value.len() {
+        match elements.get(j) {
+            Some(Element::One) => (i, j) = (i + 1, j + 1),
+            Some(Element::Char(c)) if equal(*c, value[i]) => (i, j) = (i + 1, j + 1),
+            Some(Element::Any) => {
+                backtrack = Some((i, j));
+                j += 1;
+            }
+            _ => match backtrack {
+                Some((bi, bj)) => {
+                    backtrack = Some((bi + 1, bj));
+                    (i, j) = (bi + 1, bj + 1);
+                }
+                None => return Ok(false),
+            },
+        }
+    }
+    Ok(elements[j..].iter().all(|e| *e == Element::Any))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn constant(value: Value) -> Box<Expression> {
+        Box::new(Expression::Constant(value))
+    }
+
+    #[test]
+    fn insert_casts() -> Result<()> {
+        use Expression::*;
+        let fields = [Some(DataType::Integer), Some(DataType::Float), None];
+        let field = |i: usize| Box::new(Field(i, None));
+        let float = |expr| Box::new(Cast { value: expr, target: DataType::Float });
+
+        // Integer operands of arithmetic with floats are cast, including in nested expressions.
+        let expr = GreaterThan(Box::new(Add(field(0), field(1))), constant(Value::Integer(3)));
+        assert_eq!(
+            GreaterThan(Box::new(Add(float(field(0)), field(1))), constant(Value::Integer(3))),
+            expr.insert_casts(&fields)?
+        );
+        let expr = Multiply(Box::new(Subtract(field(1), field(0))), field(0));
+        assert_eq!(
+            Multiply(Box::new(Subtract(field(1), float(field(0)))), float(field(0))),
+            expr.insert_casts(&fields)?
+        );
+        let expr = Divide(constant(Value::Float(1.5)), constant(Value::Integer(2)));
+        assert_eq!(Value::Float(0.75), expr.insert_casts(&fields)?.evaluate(None)?);
+
+        // Other types, unknown types, and exponentiation are left as is.
+        for expr in [
+            Add(field(0), field(0)),
+            Add(field(0), field(2)),
+            Modulo(field(1), field(1)),
+            Exponentiate(field(1), field(0)),
+        ] {
+            assert_eq!(expr.clone(), expr.insert_casts(&fields)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn to_column_name() {
+        use Expression::*;
+        let v = || Box::new(Field(0, Some((None, "v".into()))));
+        let int = |i| constant(Value::Integer(i));
+        let cases = [
+            (*v(), "v"),
+            (Subtract(v(), Box::new(Divide(int(1), int(-1)))), "v - 1 / -1"),
+            (Divide(Box::new(Subtract(v(), int(1))), int(-1)), "(v - 1) / -1"),
+            (Subtract(v(), Box::new(Subtract(v(), int(1)))), "v - (v - 1)"),
+            (Subtract(Box::new(Subtract(v(), int(1))), v()), "v - 1 - v"),
+            (Negate(Box::new(Subtract(v(), int(1)))), "-(v - 1)"),
+            (Negate(Box::new(Negate(v()))), "-(-v)"),
+            (Factorial(int(-1)), "(-1)!"),
+            (Exponentiate(Box::new(Exponentiate(v(), v())), v()), "(v ^ v) ^ v"),
+            (Exponentiate(v(), Box::new(Exponentiate(v(), v()))), "v ^ v ^ v"),
+            (Not(Box::new(Equal(v(), int(1)))), "NOT (v = 1)"),
+            (And(Box::new(Or(v(), v())), v()), "(v OR v) AND v"),
+            (
+                Like {
+                    value: v(),
+                    pattern: constant(Value::String("A'".into())),
+                    escape: None,
+                    case_insensitive: true,
+                },
+                "v ILIKE 'A'''",
+            ),
+            (
+                Cast { value: Box::new(Add(v(), int(1))), target: DataType::Float },
+                "CAST(v + 1 AS FLOAT)",
+            ),
+        ];
+        for (expr, name) in cases {
+            assert_eq!(name, expr.to_column_name());
+        }
+    }
+
+    #[test]
+    fn like() -> Result<()> {
+        let cases = [
+            // Literals and single-character wildcards.
+            ("abc", "abc", true),
+            ("abc", "ab", false),
+            ("abc", "a_c", true),
+            ("abc", "a__", true),
+            ("ab", "a__", false),
+            ("abc", "___", true),
+            ("", "_", false),
+            // Leading, trailing, and multiple %s, including overlapping candidates.
+            ("", "%", true),
+            ("abc", "%", true),
+            ("abc", "a%", true),
+            ("abc", "%c", true),
+            ("abc", "%b%", true),
+            ("abc", "%%%", true),
+            ("abc", "%d%", false),
+            ("aaab", "%a%ab", true),
+            ("abababc", "%ab%abc", true),
+            ("abababc", "%ab%ab%abc", true),
+            ("ababab", "%ab%abc", false),
+            ("mississippi", "m%iss%ppi", true),
+            ("mississippi", "m%issi_pi", true),
+            ("mississippi", "%s_s%s_s%", false),
+            ("abcab", "a%_b", true),
+            ("ab", "a%_b", false),
+            // Escaped metacharacters.
+            ("100%", r"100\%", true),
+            ("100", r"100\%", false),
+            ("a_c", r"a\_c", true),
+            ("abc", r"a\_c", false),
+            (r"a\c", r"a\\c", true),
+            ("a%b_c", r"%\%%\_%", true),
+            ("abc", r"\a\b\c", true),
+            // Special regex characters are literals.
+            ("a.c", "a.c", true),
+            ("abc", "a.c", false),
+            ("a*c", "a*c", true),
+            ("[a]", "[a]", true),
+        ];
+        for (value, pattern, expect) in cases {
+            let actual = like_match(value, pattern, Some('\\'), false)?;
+            assert_eq!(expect, actual, "{} LIKE {}", value, pattern);
+        }
+
+        // Other and no escape characters.
+        assert!(like_match("10%", "10#%", Some('#'), false)?);
+        assert!(!like_match("10", "10#%", Some('#'), false)?);
+        assert!(like_match(r"a\bc", r"a\%", None, false)?);
+        assert!(like_match("a#c", "a##c", Some('#'), false)?);
+        assert!(like_match("a", r"a\", Some('\\'), false).is_err());
+
+        // Case-insensitive matching, including Unicode characters and the final sigma.
+        assert!(!like_match("ABC", "abc", Some('\\'), false)?);
+        assert!(like_match("ABC", "abc", Some('\\'), true)?);
+        assert!(like_match("ÄrGeR", "är%", Some('\\'), true)?);
+        assert!(like_match("Σίσυφος", "ΣΊΣΥΦΟΣ", Some('\\'), true)?);
+        assert!(like_match("ĞÜŞ", "_üş", Some('\\'), true)?);
+        assert!(!like_match("ĞÜŞ", "_us", Some('\\'), true)?);
+        Ok(())
+    }
+
+    #[test]
+    fn like_expression() -> Result<()> {
+        use Value::*;
+        let like = |value: Value, pattern: &str, escape: Option<Value>, case_insensitive| {
+            Expression::Like {
+                value: constant(value),
+                pattern: constant(String(pattern.into())),
+                escape: escape.map(constant),
+                case_insensitive,
+            }
+            .evaluate(None)
+        };
+        assert_eq!(Boolean(true), like(String("a%".into()), r"a\%", None, false)?);
+        let (bang, empty) = (Some(String("!".into())), Some(String("".into())));
+        assert_eq!(Boolean(true), like(String("A%".into()), "a!%", bang, true)?);
+        assert_eq!(Boolean(true), like(String(r"a\b".into()), r"a\%", empty, false)?);
+        assert_eq!(Null, like(Null, "a", None, false)?);
+        assert_eq!(Null, like(String("a".into()), "a", Some(Null), false)?);
+        assert!(like(String("a".into()), "a", Some(String("ab".into())), false).is_err());
+        assert!(like(Integer(1), "1", None, false).is_err());
+        Ok(())
+    }
+
+    /// Generates a random integer (or boolean, if boolean is true) expression, possibly containing
+    /// nested CASE expressions, along with its value under a reference evaluation.
+    fn random_expr(rng: &mut StdRng, depth: usize, boolean: bool) -> (Expression, Value) {
+        if depth == 0 || rng.gen_bool(0.3) {
+            let value = match (rng.gen_range(0..4), boolean) {
+                (0, _) => Value::Null,
+                (_, true) => Value::Boolean(rng.gen()),
+                (_, false) => Value::Integer(rng.gen_range(0..3)),
+            };
+            return (Expression::Constant(value.clone()), value);
+        }
+        if boolean && rng.gen_bool(0.3) {
+            let (lhs, l) = random_expr(rng, depth - 1, false);
+            let (rhs, r) = random_expr(rng, depth - 1, false);
+            let value = match (l, r) {
+                (Value::Null, _) | (_, Value::Null) => Value::Null,
+                (l, r) => Value::Boolean(l == r),
+            };
+            return (Expression::Equal(Box::new(lhs), Box::new(rhs)), value);
+        }
+
+        // Conditions are integers compared to the operand if there is one, else booleans.
+        let operand = rng.gen_bool(0.5).then(|| random_expr(rng, depth - 1, false));
+        let mut branches = Vec::new();
+        let mut value = None;
+        for _ in 0..rng.gen_range(0..4) {
+            let (cond, c) = random_expr(rng, depth - 1, operand.is_none());
+            let (result, r) = random_expr(rng, depth - 1, boolean);
+            let matched = match &operand {
+                Some((_, Value::Null)) => false,
+                Some((_, o)) => c != Value::Null && c == *o,
+                None => c == Value::Boolean(true),
+            };
+            if matched && value.is_none() {
+                value = Some(r);
+            }
+            branches.push((cond, result));
+        }
+        let else_expr = rng.gen_bool(0.5).then(|| random_expr(rng, depth - 1, boolean));
+        let value = value.or_else(|| else_expr.as_ref().map(|(_, v)| v.clone()));
+        let expr = Expression::Case {
+            operand: operand.map(|(e, _)| Box::new(e)),
+            branches,
+            else_expr: else_expr.map(|(e, _)| Box::new(e)),
+        };
+        (expr, value.unwrap_or(Value::Null))
+    }
+
+    #[test]
+    fn case_fuzz() -> Result<()> {
+        for seed in 0..1000 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let boolean = rng.gen();
+            let (expr, expect) = random_expr(&mut rng, 4, boolean);
+            assert_eq!(expect, expr.evaluate(None)?, "seed {}: {}", seed, expr);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn case_semantics() -> Result<()> {
+        use Value::*;
+        let case = |operand: Option<Value>, branches: Vec<(Value, Value)>, else_expr| {
+            Expression::Case {
+                operand: operand.map(constant),
+                branches: branches
+                    .into_iter()
+                    .map(|(c, r)| (Expression::Constant(c), Expression::Constant(r)))
+                    .collect(),
+                else_expr: Option::<Value>::map(else_expr, constant),
+            }
+            .evaluate(None)
+        };
+
+        // Simple form, where NULL never equals NULL.
+        assert_eq!(Integer(2), case(Some(Integer(1)), vec![(Integer(1), Integer(2))], None)?);
+        assert_eq!(Integer(2), case(Some(Float(1.0)), vec![(Integer(1), Integer(2))], None)?);
+        assert_eq!(Null, case(Some(Null), vec![(Null, Integer(2))], None)?);
+        assert_eq!(Integer(3), case(Some(Null), vec![(Null, Integer(2))], Some(Integer(3)))?);
+        assert!(case(Some(Integer(1)), vec![(String("a".into()), Integer(2))], None).is_err());
+
+        // Searched form, where conditions must be booleans and NULL doesn't match.
+        let branches = vec![(Null, Integer(1)), (Boolean(false), Integer(2))];
+        assert_eq!(Null, case(None, branches.clone(), None)?);
+        assert_eq!(Integer(3), case(None, branches, Some(Integer(3)))?);
+        assert_eq!(Integer(1), case(None, vec![(Boolean(true), Integer(1))], None)?);
+        assert!(case(None, vec![(Integer(1), Integer(1))], None).is_err());
+
+        // Branches after the first match are not evaluated.
+        let divide_by_zero = Expression::Divide(constant(Integer(1)), constant(Integer(0)));
+        let expr = Expression::Case {
+            operand: None,
+            branches: vec![
+                (Expression::Constant(Boolean(true)), Expression::Constant(Integer(1))),
+                (divide_by_zero.clone(), divide_by_zero.clone()),
+            ],
+            else_expr: Some(Box::new(divide_by_zero)),
+        };
+        assert_eq!(Integer(1), expr.evaluate(None)?);
+        Ok(())
+    }
+
+    #[test]
+    fn case_nested() -> Result<()> {
+        use Value::*;
+        // Nest CASE expressions in the ELSE branch, each level matching the row's field.
+        let mut expr = Expression::Field(0, None);
+        for i in 0..50 {
+            expr = Expression::Case {
+                operand: Some(Box::new(Expression::Field(0, None))),
+                branches: vec![(*constant(Integer(i)), *constant(Integer(-i)))],
+                else_expr: Some(Box::new(expr)),
+            };
+        }
+        assert_eq!(Integer(-42), expr.evaluate(Some(&vec![Integer(42)]))?);
+        assert_eq!(Integer(50), expr.evaluate(Some(&vec![Integer(50)]))?);
+        assert_eq!(Null, expr.evaluate(Some(&vec![Null]))?);
+        assert_eq!(Some(DataType::Integer), expr.infer_type(&[Some(DataType::Integer)]));
+
+        // Nest searched CASE expressions in the conditions, each level negating the previous
+        // one, except that NULL becomes TRUE at the first level.
+        let mut expr = Expression::Field(0, None);
+        for _ in 0..50 {
+            expr = Expression::Case {
+                operand: None,
+                branches: vec![(expr, Expression::Constant(Boolean(false)))],
+                else_expr: Some(constant(Boolean(true))),
+            };
+        }
+        assert_eq!(Boolean(true), expr.evaluate(Some(&vec![Boolean(true)]))?);
+        assert_eq!(Boolean(false), expr.evaluate(Some(&vec![Boolean(false)]))?);
+        assert_eq!(Boolean(false), expr.evaluate(Some(&vec![Null]))?);
+        Ok(())
+    }
+
+    /// Generates a random expression over the boolean field 0 and integer field 1.
+    fn random_fold_expr(rng: &mut StdRng, depth: usize, boolean: bool) -> Expression {
+        use Expression::*;
+        if depth == 0 || rng.gen_bool(0.2) {
+            return match (rng.gen_range(0..10), boolean) {
+                (0..=2, true) => Field(0, None),
+                (0..=2, false) => Field(1, None),
+                (3, _) => Constant(Value::Null),
+                (_, true) => Constant(Value::Boolean(rng.gen())),
+                (_, false) => Constant(Value::Integer(rng.gen_range(-2..3))),
+            };
+        }
+        let op = rng.gen_range(0..6);
+        let mut gen = |boolean| Box::new(random_fold_expr(rng, depth - 1, boolean));
+        match (op, boolean) {
+            (0, true) => And(gen(true), gen(true)),
+            (1, true) => Or(gen(true), gen(true)),
+            (2, true) => Not(gen(true)),
+            (3, true) => IsNull(gen(false)),
+            (4, true) => Equal(gen(false), gen(false)),
+            (_, true) => LessThan(gen(false), gen(false)),
+            (0 | 1, false) => Add(gen(false), gen(false)),
+            (2 | 3, false) => Multiply(gen(false), gen(false)),
+            (4, false) => Divide(gen(false), gen(false)),
+            (_, false) => Negate(gen(false)),
+        }
+    }
+
+    #[test]
+    fn fold_constants_fuzz() -> Result<()> {
+        let rows: Vec<Row> = [Value::Null, Value::Boolean(false), Value::Boolean(true)]
+            .into_iter()
+            .flat_map(|b| (-2..3).map(Value::Integer).chain([Value::Null]).map(move |i| {
+                vec![b.clone(), i]
+            }))
+            .collect();
+        for seed in 0..1000 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let boolean = rng.gen();
+            let expr = random_fold_expr(&mut rng, 4, boolean);
+            // Folding may only fail if evaluation fails regardless of the row, while
+            // simplification may remove an erroring operand, e.g. 1/0 = 1 AND FALSE.
+            let folded = match expr.fold_constants() {
+                Ok(folded) => folded,
+                Err(_) => {
+                    assert!(rows.iter().all(|row| expr.evaluate(Some(row)).is_err()));
+                    continue;
+                }
+            };
+            for row in &rows {
+                if let Ok(expect) = expr.evaluate(Some(row)) {
+                    let actual = folded.evaluate(Some(row))?;
+                    let msg = format!("seed {}: {} => {} for {:?}", seed, expr, folded, row);
+                    assert_eq!(expect, actual, "{}", msg);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fold_constants() -> Result<()> {
+        use Expression::*;
+        use Value::*;
+        let field = |i| Box::new(Field(i, None));
+
+        // Constant sub-expressions are folded, while field references are kept.
+        let expr = Add(field(0), Box::new(Multiply(constant(Integer(2)), constant(Integer(3)))));
+        assert_eq!(Add(field(0), constant(Integer(6))), expr.fold_constants()?);
+        let expr = Not(Box::new(And(constant(Null), constant(Boolean(false)))));
+        assert_eq!(Constant(Boolean(true)), expr.fold_constants()?);
+
+        // An operand deciding a logical operation removes the other one.
+        let expr = And(field(0), Box::new(Equal(constant(Integer(1)), constant(Integer(2)))));
+        assert_eq!(Constant(Boolean(false)), expr.fold_constants()?);
+        let expr = Or(constant(Boolean(true)), Box::new(IsNull(field(0))));
+        assert_eq!(Constant(Boolean(true)), expr.fold_constants()?);
+        let expr = And(field(0), constant(Boolean(true)));
+        assert_eq!(expr, expr.fold_constants()?);
+
+        // Unless it has side effects.
+        let subquery = Box::new(ExistsSubquery(Box::new(Node::Nothing)));
+        assert!(subquery.has_side_effects());
+        let expr = And(subquery, constant(Boolean(false)));
+        assert_eq!(expr, expr.fold_constants()?);
+
+        // Evaluation errors are returned.
+        assert!(Divide(constant(Integer(1)), constant(Integer(0))).fold_constants().is_err());
+        Ok(())
+    }
+}
@@ -2,9 +2,13 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 
+pub mod datetime;
+mod decimal;
+pub mod encoding;
 mod expression;
 use std::{borrow::Cow, hash::{Hash, Hasher}, cmp::Ordering};
 
+pub use decimal::Decimal;
 pub use expression::Expression;
 
 use serde_derive::{Deserialize, Serialize};
@@ -17,7 +21,19 @@ pub enum DataType {
     Boolean,
     Integer,
     Float,
+    /// An exact fixed-point number with the given precision (total digits) and scale
+    /// (fractional digits).
+    Decimal(u32, u32),
     String,
+    /// A string restricted to the given variants, ordered by declaration.
+    Enum(Vec<String>),
+    /// Microseconds since the Unix epoch, in UTC.
+    Timestamp,
+    /// Days since the Unix epoch.
+    Date,
+    /// An integer which is assigned the next value of the column's sequence on insert, unless
+    /// given explicitly. Values are stored as integers, see value_type().
+    Serial,
 }
 
 impl std::fmt::Display for DataType {
@@ -26,19 +42,250 @@ impl std::fmt::Display for DataType {
             Self::Boolean => "BOOLEAN",
             Self::Integer => "INTEGER",
             Self::Float => "FLOAT",
+            Self::Decimal(precision, scale) => {
+                return write!(f, "DECIMAL({}, {})", precision, scale);
+            }
             Self::String => "STRING",
+            Self::Enum(variants) => {
+                return write!(f, "ENUM({})", DataType::format_variants(variants));
+            }
+            Self::Timestamp => "TIMESTAMP",
+            Self::Date => "DATE",
+            Self::Serial => "SERIAL",
         })
     }
 }
 
-/// A specific value of a data type
+impl DataType {
+    /// Formats enum variants as a list of quoted strings.
+    pub fn format_variants(variants: &[String]) -> String {
+        variants
+            .iter()
+            .map(|v| format!("'{}'", v.replace('\'', "''")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Returns the datatype of the values stored in a column of this datatype, which is INTEGER
+    /// for SERIAL columns and the datatype itself otherwise.
+    pub fn value_type(&self) -> &DataType {
+        match self {
+            Self::Serial => &Self::Integer,
+            datatype => datatype,
+        }
+    }
+
+    /// Returns true if a non-null value can be stored in a column of this datatype.
+    pub fn accepts(&self, value: &Value) -> bool {
+        match (self.value_type(), value) {
+            (Self::Enum(variants), Value::String(s)) => variants.contains(s),
+            (Self::Decimal(precision, scale), Value::Decimal(d)) => {
+                d.scale() == *scale && d.precision() <= *precision
+            }
+            (datatype, value) => value.datatype().as_ref() == Some(datatype),
+        }
+    }
+
+    /// Returns true if values of the two datatypes can be combined arithmetically. Decimals mix
+    /// with integers, but not with floats since the result would not be exact. They can still be
+    /// compared with floats, which are converted to their shortest decimal representation.
+    pub fn compatible_with(&self, other: &DataType) -> bool {
+        match (self.value_type(), other.value_type()) {
+            (Self::Integer | Self::Float, Self::Integer | Self::Float) => true,
+            (Self::Integer | Self::Decimal(..), Self::Integer | Self::Decimal(..)) => true,
+            (Self::String | Self::Enum(_), Self::String | Self::Enum(_)) => true,
+            (Self::Timestamp | Self::Date, Self::Timestamp | Self::Date) => true,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Converts a value for storage in a column of this datatype. Integers and floats are
+    /// converted to decimals of the column's scale, since numeric literals with a fractional
+    /// part are parsed as floats. Strings are parsed as ISO 8601 timestamps and dates, and
+    /// dates are converted to timestamps at midnight UTC. Other values are widened where
+    /// possible, see Value::coerce(), or returned as is for validation to check.
+    pub fn coerce(&self, value: Value) -> Result<Value> {
+        match (self.value_type(), value) {
+            (Self::Decimal(precision, scale), Value::Integer(i)) => {
+                Ok(Value::Decimal(Decimal::from(i).fit(*precision, *scale)?))
+            }
+            (Self::Decimal(precision, scale), Value::Float(f)) => {
+                Ok(Value::Decimal(Decimal::from_f64(f)?.fit(*precision, *scale)?))
+            }
+            (Self::Decimal(precision, scale), Value::Decimal(d)) => {
+                Ok(Value::Decimal(d.fit(*precision, *scale)?))
+            }
+            (Self::Timestamp, Value::String(s)) => {
+                Ok(Value::Timestamp(datetime::parse_timestamp(&s)?))
+            }
+            (Self::Timestamp, Value::Date(d)) => {
+                Ok(Value::Timestamp(datetime::date_to_timestamp(d)?))
+            }
+            (Self::Date, Value::String(s)) => Ok(Value::Date(datetime::parse_date(&s)?)),
+            (_, value) => Ok(value.coerce(self).unwrap_or(value)),
+        }
+    }
+
+    /// Returns the precedence of the datatype in implicit conversions, where values may only be
+    /// widened to datatypes of higher precedence. NULL precedes all datatypes.
+    fn type_precedence(&self) -> u8 {
+        match self {
+            Self::Boolean => 1,
+            Self::Integer | Self::Serial => 2,
+            Self::Float => 3,
+            Self::Decimal(..) => 4,
+            Self::Date => 5,
+            Self::Timestamp => 6,
+            Self::String | Self::Enum(_) => 7,
+        }
+    }
+
+    /// Returns the least upper bound of two datatypes, to which values of both can be widened
+    /// with Value::coerce(). Decimals are widened to hold any integer and both scales, and
+    /// enums are widened to strings. Floats and decimals have no common type, since the result
+    /// would not be exact, and neither do booleans and numbers.
+    pub fn common_type(a: &DataType, b: &DataType) -> Result<DataType> {
+        let (a, b) = (a.value_type(), b.value_type());
+        let (low, high) = match a.type_precedence() <= b.type_precedence() {
+            true => (a, b),
+            false => (b, a),
+        };
+        Ok(match (low, high) {
+            (low, high) if low == high => high.clone(),
+            (Self::Integer, Self::Float) => Self::Float,
+            (Self::Integer, Self::Decimal(precision, scale)) => {
+                let digits = (precision - scale).max(19);
+                Self::Decimal((digits + scale).min(decimal::Decimal::MAX_PRECISION), *scale)
+            }
+            (Self::Decimal(p1, s1), Self::Decimal(p2, s2)) => {
+                let scale = *s1.max(s2);
+                let digits = (p1 - s1).max(p2 - s2);
+                Self::Decimal((digits + scale).min(decimal::Decimal::MAX_PRECISION), scale)
+            }
+            (Self::Date, Self::Timestamp) => Self::Timestamp,
+            (Self::String | Self::Enum(_), Self::String | Self::Enum(_)) => Self::String,
+            (a, b) => return Err(Error::Value(format!("No common type for {} and {}", a, b))),
+        })
+    }
+
+    /// Returns true if values of the other datatype may be stored in a column of this datatype,
+    /// possibly after coerce(). Values can still be rejected, e.g. strings that aren't enum
+    /// variants or decimals that don't fit the column's precision.
+    pub fn coercible_from(&self, other: &DataType) -> bool {
+        match (self.value_type(), other.value_type()) {
+            (Self::Decimal(..), Self::Integer | Self::Float | Self::Decimal(..)) => true,
+            (Self::String | Self::Enum(_), Self::String | Self::Enum(_)) => true,
+            (Self::Timestamp, Self::String | Self::Date) => true,
+            (Self::Date, Self::String) => true,
+            (Self::Float, Self::Integer) => true,
+            (a, b) => a == b,
+        }
+    }
+
+    /// Explicitly converts a value to this datatype, as in CAST(value AS datatype). Floats and
+    /// decimals are truncated when cast to integers, integers are true if non-zero, and
+    /// strings are parsed. NULL remains NULL. Errors if the value can't be represented in the
+    /// datatype, or if there is no conversion between the types.
+    pub fn cast(&self, value: Value) -> Result<Value> {
+        let error = |value: &Value| {
+            Error::Value(format!("Can't cast {} to {}", value.to_sql_literal(), self))
+        };
+        Ok(match (self.value_type(), value) {
+            (_, Value::Null) => Value::Null,
+            (datatype, value) if datatype.accepts(&value) => value,
+
+            (Self::Boolean, Value::Integer(i)) => Value::Boolean(i != 0),
+            (Self::Boolean, Value::String(s)) => match s.trim().to_lowercase().as_str() {
+                "true" | "t" | "1" => Value::Boolean(true),
+                "false" | "f" | "0" => Value::Boolean(false),
+                _ => return Err(error(&Value::String(s))),
+            },
+
+            (Self::Integer, Value::Boolean(b)) => Value::Integer(b as i64),
+            (Self::Integer, value @ Value::Float(f)) => {
+                // Float to integer conversion saturates, so check the range first.
+                let f = f.trunc();
+                if !(-(2f64.powi(63))..2f64.powi(63)).contains(&f) {
+                    return Err(error(&value));
+                }
+                Value::Integer(f as i64)
+            }
+            (Self::Integer, value @ Value::Decimal(d)) => {
+                match i64::try_from(d.mantissa() / 10i128.pow(d.scale())) {
+                    Ok(i) => Value::Integer(i),
+                    Err(_) => return Err(error(&value)),
+                }
+            }
+            (Self::Integer, Value::String(s)) => match s.trim().parse() {
+                Ok(i) => Value::Integer(i),
+                Err(_) => return Err(error(&Value::String(s))),
+            },
+
+            (Self::Float, Value::Integer(i)) => Value::Float(i as f64),
+            (Self::Float, Value::Decimal(d)) => Value::Float(d.to_string().parse()?),
+            (Self::Float, Value::String(s)) => match s.trim().parse() {
+                Ok(f) => Value::Float(f),
+                Err(_) => return Err(error(&Value::String(s))),
+            },
+
+            (Self::Decimal(..), Value::String(s)) => match s.trim().parse::<Decimal>() {
+                Ok(d) => self.coerce(Value::Decimal(d))?,
+                Err(_) => return Err(error(&Value::String(s))),
+            },
+            (
+                Self::Decimal(..),
+                value @ (Value::Integer(_) | Value::Float(_) | Value::Decimal(_)),
+            ) => self.coerce(value)?,
+
+            (Self::String, value @ (Value::Boolean(_) | Value::Integer(_) | Value::Float(_))) => {
+                Value::String(value.to_string())
+            }
+            (Self::String, value @ (Value::Decimal(_) | Value::Timestamp(_) | Value::Date(_))) => {
+                Value::String(value.to_string())
+            }
+
+            (Self::Timestamp, value @ (Value::String(_) | Value::Date(_))) => self.coerce(value)?,
+            (Self::Date, Value::String(s)) => self.coerce(Value::String(s))?,
+            (Self::Date, Value::Timestamp(t)) => {
+                // i64 microseconds span fewer days than i32 can represent.
+                Value::Date(t.div_euclid(datetime::MICROS_PER_DAY) as i32)
+            }
+
+            (_, value) => return Err(error(&value)),
+        })
+    }
+
+    /// Compares two values of this datatype. Enum values are ordered by declaration rather
+    /// than lexically, and values which aren't variants sort after all variants.
+    pub fn compare(&self, a: &Value, b: &Value) -> Option<Ordering> {
+        match (self, a, b) {
+            (Self::Enum(variants), Value::String(a), Value::String(b)) => {
+                let position = |s: &String| variants.iter().position(|v| v == s);
+                match (position(a), position(b)) {
+                    (Some(a), Some(b)) => a.partial_cmp(&b),
+                    (Some(_), None) => Some(Ordering::Less),
+                    (None, Some(_)) => Some(Ordering::Greater),
+                    (None, None) => a.partial_cmp(b),
+                }
+            }
+            (_, a, b) => a.partial_cmp(b),
+        }
+    }
+}
+
+/// A specific value of a data type. Equality, ordering and hashing are structural, such that
+/// NULL equals NULL, as needed for grouping, hashing and sorting values. SQL comparisons, where
+/// NULL is unknown, use sql_eq() and sql_cmp() instead.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Value {
     Null,
     Boolean(bool),
     Integer(i64),
     Float(f64),
+    Decimal(Decimal),
     String(String),
+    Timestamp(i64),
+    Date(i32),
 }
 
 impl std::cmp::Eq for Value {}
@@ -46,13 +293,17 @@ impl std::cmp::Eq for Value {}
 #[allow(clippy::derive_hash_xor_eq)]
 impl Hash for Value {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.datatype().hash(state);
+        // Equal decimals may have different scales, and thus datatypes, so hash the variant.
+        std::mem::discriminant(self).hash(state);
         match self {
-            Value::Null => self.hash(state),
+            Value::Null => {}
             Value::Boolean(v) => v.hash(state),
             Value::Integer(v) => v.hash(state),
             Value::Float(v) => v.to_be_bytes().hash(state),
+            Value::Decimal(v) => v.hash(state),
             Value::String(v) => v.hash(state),
+            Value::Timestamp(v) => v.hash(state),
+            Value::Date(v) => v.hash(state),
         }
     }
 }
@@ -66,7 +317,10 @@ impl std::fmt::Display for Value {
                 Self::Boolean(_) => "FALSE".to_string(),
                 Self::Integer(i) => i.to_string(),
                 Self::Float(f) => f.to_string(),
+                Self::Decimal(d) => d.to_string(),
                 Self::String(s) => s.clone(),
+                Self::Timestamp(t) => datetime::format_timestamp(*t),
+                Self::Date(d) => datetime::format_date(*d),
             }
             .as_ref(),
         )
@@ -94,13 +348,282 @@ impl PartialOrd for Value {
             (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
             (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
             (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Decimal(a), Value::Decimal(b)) => a.partial_cmp(b),
             (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
             (_, _) => None,
         }
     }
 }
 
 impl Value {
+    /// Writes a deterministic byte representation of the value for hashing, which unlike Hash
+    /// is the same across processes, platforms and Rust versions, e.g. for hash partitioning.
+    /// Values are written in the key encoding, see encoding::encode_value(), such that equal
+    /// values write the same bytes: zeroes of either sign are written as 0.0, NaNs as a single
+    /// NaN bit pattern, and decimals without trailing fractional zeros. The encoding is
+    /// self-delimiting, so the values of a row can be written one after another.
+    pub fn hash_stable(&self, state: &mut impl std::io::Write) -> Result<()> {
+        let value = match self {
+            Self::Float(f) if *f == 0.0 => Cow::Owned(Self::Float(0.0)),
+            Self::Float(f) if f.is_nan() => Cow::Owned(Self::Float(f64::NAN)),
+            Self::Decimal(d) => Cow::Owned(Self::Decimal(d.normalize())),
+            value => Cow::Borrowed(value),
+        };
+        state.write_all(&crate::encoding::encode_value(&value))?;
+        Ok(())
+    }
+
+    /// Formats the value as an SQL literal evaluating to the same value, e.g. for error
+    /// messages. Unlike Display, strings are quoted, floats always have a fractional part, and
+    /// values without a literal syntax are cast from a string.
+    pub fn to_sql_literal(&self) -> String {
+        match self {
+            Self::Null => "NULL".into(),
+            Self::Boolean(true) => "TRUE".into(),
+            Self::Boolean(false) => "FALSE".into(),
+            // The lexer can't parse the absolute value of i64::MIN.
+            Self::Integer(i64::MIN) => format!("({} - 1)", i64::MIN + 1),
+            Self::Integer(i) => i.to_string(),
+            Self::Float(f) if f.is_nan() => "NAN".into(),
+            Self::Float(f) if f.is_infinite() && *f > 0.0 => "INFINITY".into(),
+            Self::Float(f) if f.is_infinite() => "-INFINITY".into(),
+            Self::Float(f) => format!("{:?}", f),
+            Self::String(s) => format!("'{}'", s.replace('\'', "''")),
+            value => {
+                let datatype = value.datatype().expect("non-null value has a datatype");
+                format!("CAST('{}' AS {})", value, datatype)
+            }
+        }
+    }
+
+    /// Returns an error for an operation on values of the wrong types.
+    fn operand_error(operation: &str, lhs: &Value, rhs: &Value) -> Error {
+        Error::Value(format!(
+            "Can't {} {} and {}",
+            operation,
+            lhs.to_sql_literal(),
+            rhs.to_sql_literal()
+        ))
+    }
+
+    /// Compares two values as SQL does, returning None if either is NULL, or if they are
+    /// unordered floats (i.e. NaN). Numbers of different types are compared by value. Errors if
+    /// the values can't be compared.
+    pub fn sql_cmp(&self, other: &Value) -> Result<Option<Ordering>> {
+        Ok(match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => None,
+            (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Float(b)) => (*a as f64).partial_cmp(b),
+            (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Decimal(a), Value::Decimal(b)) => a.partial_cmp(b),
+            (Value::Decimal(a), Value::Integer(b)) => a.partial_cmp(&Decimal::from(*b)),
+            (Value::Integer(a), Value::Decimal(b)) => Decimal::from(*a).partial_cmp(b),
+            (Value::Decimal(a), Value::Float(b)) => a.partial_cmp(&Decimal::from_f64(*b)?),
+            (Value::Float(a), Value::Decimal(b)) => Decimal::from_f64(*a)?.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Timestamp(a), Value::Timestamp(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
+            (a, b) => return Err(Self::operand_error("compare", a, b)),
+        })
+    }
+
+    /// Returns whether two values are equal as SQL does, or None if either is NULL. Unordered
+    /// floats (i.e. NaN) are never equal. Errors if the values can't be compared.
+    pub fn sql_eq(&self, other: &Value) -> Result<Option<bool>> {
+        if *self == Value::Null || *other == Value::Null {
+            return Ok(None);
+        }
+        Ok(Some(self.sql_cmp(other)? == Some(Ordering::Equal)))
+    }
+
+    /// Returns the logical AND of two booleans, in SQL's three-valued logic: false if either is
+    /// false, and otherwise NULL if either is NULL.
+    pub fn sql_and(&self, other: &Value) -> Result<Value> {
+        Ok(match (self, other) {
+            (Value::Boolean(false), Value::Boolean(_) | Value::Null)
+            | (Value::Null, Value::Boolean(false)) => Value::Boolean(false),
+            (Value::Boolean(true), Value::Boolean(b)) => Value::Boolean(*b),
+            (Value::Boolean(true) | Value::Null, Value::Null)
+            | (Value::Null, Value::Boolean(true)) => Value::Null,
+            (a, b) => return Err(Self::operand_error("and", a, b)),
+        })
+    }
+
+    /// Returns the logical OR of two booleans, in SQL's three-valued logic: true if either is
+    /// true, and otherwise NULL if either is NULL.
+    pub fn sql_or(&self, other: &Value) -> Result<Value> {
+        Ok(match (self, other) {
+            (Value::Boolean(true), Value::Boolean(_) | Value::Null)
+            | (Value::Null, Value::Boolean(true)) => Value::Boolean(true),
+            (Value::Boolean(false), Value::Boolean(b)) => Value::Boolean(*b),
+            (Value::Boolean(false) | Value::Null, Value::Null)
+            | (Value::Null, Value::Boolean(false)) => Value::Null,
+            (a, b) => return Err(Self::operand_error("or", a, b)),
+        })
+    }
+
+    /// Returns the logical NOT of a boolean, which is NULL for NULL.
+    pub fn sql_not(&self) -> Result<Value> {
+        match self {
+            Value::Boolean(b) => Ok(Value::Boolean(!b)),
+            Value::Null => Ok(Value::Null),
+            value => Err(Error::Value(format!("Can't negate {}", value.to_sql_literal()))),
+        }
+    }
+
+    /// Adds two numbers, or an integer number of microseconds or days to a timestamp or date.
+    /// Integers are promoted to floats or decimals if the other value is one. NULL if either
+    /// value is NULL.
+    pub fn sql_add(&self, other: &Value, overflow: OverflowBehavior) -> Result<Value> {
+        use Value::*;
+        Ok(match (self, other) {
+            (Null, _) | (_, Null) => Null,
+            (Integer(lhs), Integer(rhs)) => {
+                Integer(overflow.apply(*lhs, *rhs, i64::checked_add, i64::wrapping_add)?)
+            }
+            (Integer(lhs), Float(rhs)) => Float(*lhs as f64 + rhs),
+            (Float(lhs), Float(rhs)) => Float(lhs + rhs),
+            (Float(lhs), Integer(rhs)) => Float(lhs + *rhs as f64),
+            (Decimal(lhs), Decimal(rhs)) => Decimal(lhs.checked_add(rhs)?),
+            (Decimal(lhs), Integer(rhs)) => Decimal(lhs.checked_add(&(*rhs).into())?),
+            (Integer(lhs), Decimal(rhs)) => Decimal(decimal::Decimal::from(*lhs).checked_add(rhs)?),
+            (Timestamp(lhs), Integer(rhs)) | (Integer(rhs), Timestamp(lhs)) => {
+                Timestamp(datetime::add_micros(*lhs, *rhs)?)
+            }
+            (Date(lhs), Integer(rhs)) | (Integer(rhs), Date(lhs)) => {
+                Date(datetime::add_days(*lhs, *rhs)?)
+            }
+            (lhs, rhs) => return Err(Self::operand_error("add", lhs, rhs)),
+        })
+    }
+
+    /// Subtracts two numbers, an integer number of microseconds or days from a timestamp or
+    /// date, or two timestamps or dates, giving the microseconds or days between them. NULL if
+    /// either value is NULL.
+    pub fn sql_sub(&self, other: &Value, overflow: OverflowBehavior) -> Result<Value> {
+        use Value::*;
+        let integer_overflow = || Error::Value("Integer overflow".into());
+        Ok(match (self, other) {
+            (Null, _) | (_, Null) => Null,
+            (Integer(lhs), Integer(rhs)) => {
+                Integer(overflow.apply(*lhs, *rhs, i64::checked_sub, i64::wrapping_sub)?)
+            }
+            (Integer(lhs), Float(rhs)) => Float(*lhs as f64 - rhs),
+            (Float(lhs), Integer(rhs)) => Float(lhs - *rhs as f64),
+            (Float(lhs), Float(rhs)) => Float(lhs - rhs),
+            (Decimal(lhs), Decimal(rhs)) => Decimal(lhs.checked_sub(rhs)?),
+            (Decimal(lhs), Integer(rhs)) => Decimal(lhs.checked_sub(&(*rhs).into())?),
+            (Integer(lhs), Decimal(rhs)) => Decimal(decimal::Decimal::from(*lhs).checked_sub(rhs)?),
+            (Timestamp(lhs), Integer(rhs)) => Timestamp(datetime::add_micros(
+                *lhs,
+                rhs.checked_neg().ok_or_else(integer_overflow)?,
+            )?),
+            (Timestamp(lhs), Timestamp(rhs)) => {
+                Integer(lhs.checked_sub(*rhs).ok_or_else(integer_overflow)?)
+            }
+            (Date(lhs), Integer(rhs)) => Date(datetime::add_days(
+                *lhs,
+                rhs.checked_neg().ok_or_else(integer_overflow)?,
+            )?),
+            (Date(lhs), Date(rhs)) => Integer(*lhs as i64 - *rhs as i64),
+            (lhs, rhs) => return Err(Self::operand_error("subtract", lhs, rhs)),
+        })
+    }
+
+    /// Multiplies two numbers. NULL if either value is NULL.
+    pub fn sql_mul(&self, other: &Value, overflow: OverflowBehavior) -> Result<Value> {
+        use Value::*;
+        Ok(match (self, other) {
+            (Null, _) | (_, Null) => Null,
+            (Integer(lhs), Integer(rhs)) => {
+                Integer(overflow.apply(*lhs, *rhs, i64::checked_mul, i64::wrapping_mul)?)
+            }
+            (Integer(lhs), Float(rhs)) => Float(*lhs as f64 * rhs),
+            (Float(lhs), Integer(rhs)) => Float(lhs * *rhs as f64),
+            (Float(lhs), Float(rhs)) => Float(lhs * rhs),
+            (Decimal(lhs), Decimal(rhs)) => Decimal(lhs.checked_mul(rhs)?),
+            (Decimal(lhs), Integer(rhs)) => Decimal(lhs.checked_mul(&(*rhs).into())?),
+            (Integer(lhs), Decimal(rhs)) => Decimal(decimal::Decimal::from(*lhs).checked_mul(rhs)?),
+            (lhs, rhs) => return Err(Self::operand_error("multiply", lhs, rhs)),
+        })
+    }
+
+    /// Divides two numbers. Integer division truncates towards zero. Errors with DivisionByZero
+    /// for a zero divisor, including float and mixed division rather than giving an infinity or
+    /// NaN. NULL if either value is NULL.
+    pub fn sql_div(&self, other: &Value, overflow: OverflowBehavior) -> Result<Value> {
+        use Value::*;
+        Ok(match (self, other) {
+            (Null, _) | (_, Null) => Null,
+            (Integer(_) | Float(_), Integer(0)) => return Err(Error::DivisionByZero),
+            (Integer(_) | Float(_), Float(rhs)) if *rhs == 0.0 => {
+                return Err(Error::DivisionByZero)
+            }
+            (Integer(lhs), Integer(rhs)) => {
+                Integer(overflow.apply(*lhs, *rhs, i64::checked_div, i64::wrapping_div)?)
+            }
+            (Integer(lhs), Float(rhs)) => Float(*lhs as f64 / rhs),
+            (Float(lhs), Integer(rhs)) => Float(lhs / *rhs as f64),
+            (Float(lhs), Float(rhs)) => Float(lhs / rhs),
+            (Decimal(lhs), Decimal(rhs)) => Decimal(lhs.checked_div(rhs)?),
+            (Decimal(lhs), Integer(rhs)) => Decimal(lhs.checked_div(&(*rhs).into())?),
+            (Integer(lhs), Decimal(rhs)) => Decimal(decimal::Decimal::from(*lhs).checked_div(rhs)?),
+            (lhs, rhs) => return Err(Self::operand_error("divide", lhs, rhs)),
+        })
+    }
+
+    /// Takes the remainder of dividing two numbers, with the sign of the dividend like Postgres.
+    /// Errors with DivisionByZero for a zero divisor, like division. NULL if either value is NULL.
+    pub fn sql_rem(&self, other: &Value, overflow: OverflowBehavior) -> Result<Value> {
+        use Value::*;
+        Ok(match (self, other) {
+            (Null, _) | (_, Null) => Null,
+            (Integer(_) | Float(_), Integer(0)) => return Err(Error::DivisionByZero),
+            (Integer(_) | Float(_), Float(rhs)) if *rhs == 0.0 => {
+                return Err(Error::DivisionByZero)
+            }
+            (Integer(lhs), Integer(rhs)) => {
+                Integer(overflow.apply(*lhs, *rhs, i64::checked_rem, i64::wrapping_rem)?)
+            }
+            (Integer(lhs), Float(rhs)) => Float(*lhs as f64 % rhs),
+            (Float(lhs), Integer(rhs)) => Float(lhs % *rhs as f64),
+            (Float(lhs), Float(rhs)) => Float(lhs % rhs),
+            (Decimal(lhs), Decimal(rhs)) => Decimal(lhs.checked_rem(rhs)?),
+            (Decimal(lhs), Integer(rhs)) => Decimal(lhs.checked_rem(&(*rhs).into())?),
+            (Integer(lhs), Decimal(rhs)) => Decimal(decimal::Decimal::from(*lhs).checked_rem(rhs)?),
+            (lhs, rhs) => return Err(Self::operand_error("take modulo of", lhs, rhs)),
+        })
+    }
+
+    /// Concatenates two values as strings, as the || operator does. At least one of them must
+    /// be a string, and the other is formatted as one. NULL if either value is NULL.
+    pub fn sql_concat(&self, other: &Value) -> Result<Value> {
+        Ok(match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (lhs @ Value::String(_), rhs) | (lhs, rhs @ Value::String(_)) => {
+                Value::String(format!("{}{}", lhs, rhs))
+            }
+            (lhs, rhs) => return Err(Self::operand_error("concatenate", lhs, rhs)),
+        })
+    }
+
+    /// Negates a number, erroring on integer overflow. NULL for NULL.
+    pub fn negate(&self) -> Result<Value> {
+        Ok(match self {
+            Value::Null => Value::Null,
+            Value::Integer(i) => Value::Integer(
+                i.checked_neg().ok_or_else(|| Error::Value("Integer overflow".into()))?,
+            ),
+            Value::Float(f) => Value::Float(-f),
+            Value::Decimal(d) => Value::Decimal(d.checked_neg()?),
+            value => return Err(Error::Value(format!("Can't negate {}", value.to_sql_literal()))),
+        })
+    }
+
     /// Returns the value's datatype, or None for null values
     pub fn datatype(&self) -> Option<DataType> {
         match self {
@@ -108,7 +631,83 @@ impl Value {
             Value::Boolean(_) => Some(DataType::Boolean),
             Value::Integer(_) => Some(DataType::Integer),
             Value::Float(_) => Some(DataType::Float),
+            Value::Decimal(d) => Some(DataType::Decimal(d.precision(), d.scale())),
             Value::String(_) => Some(DataType::String),
+            Value::Timestamp(_) => Some(DataType::Timestamp),
+            Value::Date(_) => Some(DataType::Date),
+        }
+    }
+
+    /// Widens the value to a datatype of higher precedence, see DataType::common_type():
+    /// integers to floats and decimals, floats to decimals, dates to timestamps, and strings to
+    /// enums they are a variant of. Decimals are rounded to the target's scale, and error if
+    /// they don't fit its precision. NULL coerces to any datatype. Errors for other
+    /// conversions, which need an explicit CAST.
+    pub fn coerce(&self, target: &DataType) -> Result<Value> {
+        match (self, target) {
+            (Value::Null, _) => Ok(Value::Null),
+            (Value::Integer(_) | Value::Float(_) | Value::Decimal(_), DataType::Decimal(..))
+            | (Value::Date(_), DataType::Timestamp) => target.coerce(self.clone()),
+            (value, target) if target.accepts(value) => Ok(value.clone()),
+            (Value::Integer(i), DataType::Float) => Ok(Value::Float(*i as f64)),
+            (value, target) => {
+                Err(Error::Value(format!("Can't coerce {} to {}", value.to_sql_literal(), target)))
+            }
+        }
+    }
+}
+
+/// Implements an arithmetic operator for values, erroring on integer overflow.
+macro_rules! impl_value_op {
+    ($trait:ident, $fn:ident, $method:ident) => {
+        impl std::ops::$trait for Value {
+            type Output = Result<Value>;
+
+            fn $fn(self, rhs: Value) -> Result<Value> {
+                self.$method(&rhs, OverflowBehavior::Error)
+            }
+        }
+    };
+}
+
+impl_value_op!(Add, add, sql_add);
+impl_value_op!(Sub, sub, sql_sub);
+impl_value_op!(Mul, mul, sql_mul);
+impl_value_op!(Div, div, sql_div);
+impl_value_op!(Rem, rem, sql_rem);
+
+impl std::ops::Neg for Value {
+    type Output = Result<Value>;
+
+    fn neg(self) -> Result<Value> {
+        self.negate()
+    }
+}
+
+/// How integer arithmetic handles overflow.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowBehavior {
+    /// Overflow is an error, as in SQL expressions.
+    #[default]
+    Error,
+    /// Overflow wraps around, in two's complement.
+    Wrap,
+}
+
+impl OverflowBehavior {
+    /// Applies an integer operation, given its checked and wrapping implementations.
+    fn apply(
+        self,
+        lhs: i64,
+        rhs: i64,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+    ) -> Result<i64> {
+        match self {
+            Self::Error => {
+                checked(lhs, rhs).ok_or_else(|| Error::Value("Integer overflow".into()))
+            }
+            Self::Wrap => Ok(wrapping(lhs, rhs)),
         }
     }
 }
@@ -116,6 +715,26 @@ impl Value {
 /// A row of values
 pub type Row = Vec<Value>;
 
+/// Formats a row as a parenthesized list of SQL literals, e.g. (1, 'a', NULL).
+pub fn format_row(row: &[Value]) -> String {
+    format!("({})", row.iter().map(|v| v.to_sql_literal()).collect::<Vec<_>>().join(", "))
+}
+
+/// Hashes a value deterministically across processes, by hashing Value::hash_stable() with
+/// the fixed-seed FxHash used for Bloom filters.
+pub fn stable_hash(value: &Value) -> u64 {
+    stable_hash_row(std::slice::from_ref(value))
+}
+
+/// Hashes a row deterministically across processes, like stable_hash().
+pub fn stable_hash_row(row: &[Value]) -> u64 {
+    let mut bytes = Vec::new();
+    for value in row {
+        value.hash_stable(&mut bytes).expect("writing to a Vec can't fail");
+    }
+    crate::storage::kv::bloom::hash(&bytes)
+}
+
 /// A row iterator
 pub type Rows = Box<dyn Iterator<Item = Result<Row>> + Send>;
 
@@ -127,3 +746,341 @@ pub struct ResColumn {
 
 /// A set of columns
 pub type Columns = Vec<ResColumn>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn three_valued_logic() -> Result<()> {
+        use Value::*;
+        let (t, f) = (Boolean(true), Boolean(false));
+        // (lhs, rhs, lhs AND rhs, lhs OR rhs) for all combinations of TRUE, FALSE and NULL.
+        let cases = [
+            (&t, &t, &t, &t),
+            (&t, &f, &f, &t),
+            (&t, &Null, &Null, &t),
+            (&f, &t, &f, &t),
+            (&f, &f, &f, &f),
+            (&f, &Null, &f, &Null),
+            (&Null, &t, &Null, &t),
+            (&Null, &f, &f, &Null),
+            (&Null, &Null, &Null, &Null),
+        ];
+        for (lhs, rhs, and, or) in cases {
+            assert_eq!(*and, lhs.sql_and(rhs)?, "{} AND {}", lhs, rhs);
+            assert_eq!(*or, lhs.sql_or(rhs)?, "{} OR {}", lhs, rhs);
+        }
+        assert_eq!(f, t.sql_not()?);
+        assert_eq!(t, f.sql_not()?);
+        assert_eq!(Null, Null.sql_not()?);
+
+        assert!(Integer(1).sql_and(&t).is_err());
+        assert!(Null.sql_or(&String("a".into())).is_err());
+        assert!(Integer(1).sql_not().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sql_comparison() -> Result<()> {
+        use Value::*;
+        let decimal = |s: &str| -> Result<Value> { Ok(Decimal(s.parse()?)) };
+
+        // NULL is unknown, even compared to itself, although the values are structurally equal.
+        assert_eq!(Null, Null);
+        assert_eq!(None, Null.sql_eq(&Null)?);
+        assert_eq!(None, Integer(1).sql_eq(&Null)?);
+        assert_eq!(None, Null.sql_cmp(&Integer(1))?);
+
+        // Numbers compare by value across types, while NaN is unordered and never equal.
+        assert_eq!(Some(true), Integer(1).sql_eq(&Float(1.0))?);
+        assert_eq!(Some(true), decimal("1.50")?.sql_eq(&Float(1.5))?);
+        assert_eq!(Some(Ordering::Less), Integer(1).sql_cmp(&decimal("1.1")?)?);
+        assert_eq!(Some(Ordering::Greater), Float(2.5).sql_cmp(&Integer(2))?);
+        assert_eq!(Some(false), Float(f64::NAN).sql_eq(&Float(f64::NAN))?);
+        assert_eq!(None, Float(f64::NAN).sql_cmp(&Float(1.0))?);
+
+        assert_eq!(Some(Ordering::Less), String("a".into()).sql_cmp(&String("b".into()))?);
+        assert_eq!(
+            Err(Error::Value("Can't compare 1 and 'a'".into())),
+            Integer(1).sql_eq(&String("a".into()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cast() -> Result<()> {
+        use Value::*;
+        let targets = [
+            DataType::Boolean,
+            DataType::Integer,
+            DataType::Float,
+            DataType::Decimal(5, 1),
+            DataType::String,
+            DataType::Enum(vec!["12".into(), "x".into()]),
+            DataType::Timestamp,
+            DataType::Date,
+        ];
+        let timestamp = datetime::parse_timestamp("2024-03-10T12:00:00Z")?;
+        let date = datetime::parse_date("2024-03-10")?;
+        let decimal = |s: &str| -> Result<Value> { Ok(Decimal(s.parse()?)) };
+        let string = |s: &str| String(s.into());
+
+        // The expected results of casting each value to each target, or None for errors.
+        let cases = vec![
+            (Null, [Some(Null), Some(Null), Some(Null), Some(Null), Some(Null), Some(Null),
+                Some(Null), Some(Null)]),
+            (Boolean(true), [Some(Boolean(true)), Some(Integer(1)), None, None,
+                Some(string("TRUE")), None, None, None]),
+            (Integer(-3), [Some(Boolean(true)), Some(Integer(-3)), Some(Float(-3.0)),
+                Some(decimal("-3.0")?), Some(string("-3")), None, None, None]),
+            (Float(2.7), [None, Some(Integer(2)), Some(Float(2.7)), Some(decimal("2.7")?),
+                Some(string("2.7")), None, None, None]),
+            (decimal("-2.75")?, [None, Some(Integer(-2)), Some(Float(-2.75)),
+                Some(decimal("-2.8")?), Some(string("-2.75")), None, None, None]),
+            (string("12"), [None, Some(Integer(12)), Some(Float(12.0)), Some(decimal("12.0")?),
+                Some(string("12")), Some(string("12")), None, None]),
+            (string("2024-03-10"), [None, None, None, None, Some(string("2024-03-10")), None,
+                Some(Timestamp(date as i64 * datetime::MICROS_PER_DAY)), Some(Date(date))]),
+            (Timestamp(timestamp), [None, None, None, None,
+                Some(string(&datetime::format_timestamp(timestamp))), None,
+                Some(Timestamp(timestamp)), Some(Date(date))]),
+            (Date(date), [None, None, None, None, Some(string("2024-03-10")), None,
+                Some(Timestamp(date as i64 * datetime::MICROS_PER_DAY)), Some(Date(date))]),
+        ];
+        for (value, expect) in cases {
+            for (target, expect) in targets.iter().zip(expect) {
+                let result = target.cast(value.clone());
+                match expect {
+                    Some(expect) => {
+                        let result = result?;
+                        assert_eq!(expect, result, "{} AS {}", value, target);
+                        assert_eq!(expect.datatype(), result.datatype(), "{} AS {}", value, target);
+                    }
+                    None => assert!(result.is_err(), "{} AS {}: {:?}", value, target, result),
+                }
+            }
+        }
+
+        // Edge cases and invalid values.
+        assert_eq!(
+            Err(Error::Value("Can't cast 'abc' to INTEGER".into())),
+            DataType::Integer.cast(string("abc"))
+        );
+        assert_eq!(Integer(42), DataType::Integer.cast(string(" 42 "))?);
+        assert_eq!(Integer(-2), DataType::Integer.cast(Float(-2.9))?);
+        assert_eq!(Boolean(false), DataType::Boolean.cast(Integer(0))?);
+        assert_eq!(Boolean(true), DataType::Boolean.cast(string("TRUE"))?);
+        assert!(DataType::Integer.cast(Float(1e19)).is_err());
+        assert!(DataType::Integer.cast(Float(f64::NAN)).is_err());
+        assert!(DataType::Integer.cast(decimal("9223372036854775808")?).is_err());
+        assert!(DataType::Float.cast(string("1.5x")).is_err());
+        assert!(DataType::Decimal(3, 1).cast(Integer(1000)).is_err());
+        assert!(DataType::Boolean.cast(string("yes")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn coerce() -> Result<()> {
+        use Value::*;
+        let targets = [
+            DataType::Boolean,
+            DataType::Integer,
+            DataType::Float,
+            DataType::Decimal(5, 1),
+            DataType::String,
+            DataType::Enum(vec!["12".into(), "x".into()]),
+            DataType::Timestamp,
+            DataType::Date,
+        ];
+        let timestamp = datetime::parse_timestamp("2024-03-10T12:00:00Z")?;
+        let date = datetime::parse_date("2024-03-10")?;
+        let decimal = |s: &str| -> Result<Value> { Ok(Decimal(s.parse()?)) };
+        let string = |s: &str| String(s.into());
+
+        // The expected results of coercing each value to each target, or None for errors.
+        // Values are only widened, never narrowed or parsed.
+        let cases = vec![
+            (Null, [Some(Null), Some(Null), Some(Null), Some(Null), Some(Null), Some(Null),
+                Some(Null), Some(Null)]),
+            (Boolean(true), [Some(Boolean(true)), None, None, None, None, None, None, None]),
+            (Integer(-3), [None, Some(Integer(-3)), Some(Float(-3.0)), Some(decimal("-3.0")?),
+                None, None, None, None]),
+            (Float(2.7), [None, None, Some(Float(2.7)), Some(decimal("2.7")?), None, None, None,
+                None]),
+            (decimal("-2.75")?, [None, None, None, Some(decimal("-2.8")?), None, None, None,
+                None]),
+            (string("12"), [None, None, None, None, Some(string("12")), Some(string("12")), None,
+                None]),
+            (string("2024-03-10"), [None, None, None, None, Some(string("2024-03-10")), None,
+                None, None]),
+            (Timestamp(timestamp), [None, None, None, None, None, None,
+                Some(Timestamp(timestamp)), None]),
+            (Date(date), [None, None, None, None, None, None,
+                Some(Timestamp(date as i64 * datetime::MICROS_PER_DAY)), Some(Date(date))]),
+        ];
+        for (value, expect) in cases {
+            for (target, expect) in targets.iter().zip(expect) {
+                let result = value.coerce(target);
+                match expect {
+                    Some(expect) => assert_eq!(expect, result?, "{} to {}", value, target),
+                    None => assert!(result.is_err(), "{} to {}: {:?}", value, target, result),
+                }
+            }
+        }
+        assert_eq!(
+            Err(Error::Value("Can't coerce 1.5 to INTEGER".into())),
+            Float(1.5).coerce(&DataType::Integer)
+        );
+        assert!(Integer(1000).coerce(&DataType::Decimal(3, 1)).is_err());
+
+        // The common type is the least upper bound, and is symmetric.
+        let common = |a: &DataType, b: &DataType| -> Result<DataType> {
+            let common = DataType::common_type(a, b);
+            assert_eq!(common, DataType::common_type(b, a));
+            common
+        };
+        let enum_type = DataType::Enum(vec!["a".into()]);
+        assert_eq!(DataType::Float, common(&DataType::Integer, &DataType::Float)?);
+        assert_eq!(DataType::Integer, common(&DataType::Integer, &DataType::Integer)?);
+        assert_eq!(DataType::Decimal(21, 2), common(&DataType::Integer, &DataType::Decimal(5, 2))?);
+        assert_eq!(
+            DataType::Decimal(8, 3),
+            common(&DataType::Decimal(6, 1), &DataType::Decimal(4, 3))?
+        );
+        assert_eq!(DataType::Timestamp, common(&DataType::Date, &DataType::Timestamp)?);
+        assert_eq!(DataType::String, common(&enum_type, &DataType::String)?);
+        assert!(common(&DataType::Float, &DataType::Decimal(5, 2)).is_err());
+        assert!(common(&DataType::Boolean, &DataType::Integer).is_err());
+        assert!(common(&DataType::Integer, &DataType::String).is_err());
+
+        // Arithmetic on mixed numbers coerces them to their common type.
+        let add = |a: Value, b: Value| -> Result<Value> {
+            Expression::Add(Expression::Constant(a).into(), Expression::Constant(b).into())
+                .evaluate(None)
+        };
+        assert_eq!(Float(2.0), add(Integer(1), Float(1.0))?);
+        assert_eq!(decimal("3.50")?, add(Integer(1), decimal("2.50")?)?);
+        assert!(add(Float(1.0), decimal("2.50")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compare_enum_declaration_order() {
+        let datatype = DataType::Enum(vec!["low".into(), "high".into(), "medium".into()]);
+        let mut values: Vec<Value> = ["medium", "low", "unknown", "high"]
+            .iter()
+            .map(|s| Value::String(s.to_string()))
+            .collect();
+        values.sort_by(|a, b| datatype.compare(a, b).unwrap());
+        assert_eq!(
+            values,
+            vec![
+                Value::String("low".into()),
+                Value::String("high".into()),
+                Value::String("medium".into()),
+                Value::String("unknown".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn arithmetic() -> Result<()> {
+        use Value::*;
+        // Integers are promoted to floats, and NULL propagates through any operand.
+        assert_eq!(Integer(5), (Integer(2) + Integer(3))?);
+        assert_eq!(Float(5.5), (Integer(2) + Float(3.5))?);
+        assert_eq!(Float(-1.5), (Float(2.0) - Float(3.5))?);
+        assert_eq!(Float(7.0), (Float(3.5) * Integer(2))?);
+        assert_eq!(Integer(-2), (Integer(-7) / Integer(3))?);
+        assert_eq!(Integer(-1), (Integer(-7) % Integer(3))?);
+        assert_eq!(Null, (Null + Integer(1))?);
+        assert_eq!(Null, (String("a".into()) * Null)?);
+        assert_eq!(Null, (-Null)?);
+        assert_eq!(Integer(-3), (-Integer(3))?);
+
+        assert_eq!(Err(Error::DivisionByZero), Integer(1) / Integer(0));
+        assert_eq!(Err(Error::DivisionByZero), Integer(1) % Integer(0));
+        assert_eq!(Err(Error::DivisionByZero), Decimal("1.5".parse()?) / Integer(0));
+        assert_eq!(Err(Error::DivisionByZero), Float(1.0) / Integer(0));
+        assert_eq!(Err(Error::DivisionByZero), Integer(1) / Float(-0.0));
+        assert_eq!(Err(Error::DivisionByZero), Float(0.0) / Float(0.0));
+        assert_eq!(Err(Error::DivisionByZero), Float(1.5) % Float(0.0));
+        assert!((Boolean(true) + Integer(1)).is_err());
+        assert!((String("a".into()) - String("b".into())).is_err());
+
+        // Overflow errors by default, or wraps.
+        let wrap = OverflowBehavior::Wrap;
+        assert!((Integer(i64::MAX) + Integer(1)).is_err());
+        assert!((Integer(i64::MIN) / Integer(-1)).is_err());
+        assert!((Integer(i64::MIN) % Integer(-1)).is_err());
+        assert!((-Integer(i64::MIN)).is_err());
+        assert_eq!(Integer(i64::MIN), Integer(i64::MAX).sql_add(&Integer(1), wrap)?);
+        assert_eq!(Integer(i64::MAX), Integer(i64::MIN).sql_sub(&Integer(1), wrap)?);
+        assert_eq!(Integer(-2), Integer(i64::MAX).sql_mul(&Integer(2), wrap)?);
+        assert_eq!(Integer(i64::MIN), Integer(i64::MIN).sql_div(&Integer(-1), wrap)?);
+        assert_eq!(Integer(0), Integer(i64::MIN).sql_rem(&Integer(-1), wrap)?);
+
+        // Concatenation formats non-string operands.
+        let string = |s: &str| String(s.into());
+        assert_eq!(string("ab"), string("a").sql_concat(&string("b"))?);
+        assert_eq!(string("a1"), string("a").sql_concat(&Integer(1))?);
+        assert_eq!(string("TRUEa"), Boolean(true).sql_concat(&string("a"))?);
+        assert_eq!(Null, string("a").sql_concat(&Null)?);
+        assert!(Integer(1).sql_concat(&Integer(2)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn integer_arithmetic_matches_checked() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let edges = [0, 1, -1, i64::MAX, i64::MIN];
+        for _ in 0..10000 {
+            let mut random = || match rng.gen_range(0..3) {
+                0 => edges[rng.gen_range(0..edges.len())],
+                1 => rng.gen_range(-1000..1000),
+                _ => rng.gen(),
+            };
+            let (a, b) = (random(), random());
+            let expect = |result: Option<i64>| result.map(Value::Integer);
+            assert_eq!(expect(a.checked_add(b)), (Value::Integer(a) + Value::Integer(b)).ok());
+            assert_eq!(expect(a.checked_sub(b)), (Value::Integer(a) - Value::Integer(b)).ok());
+            assert_eq!(expect(a.checked_mul(b)), (Value::Integer(a) * Value::Integer(b)).ok());
+            assert_eq!(expect(a.checked_div(b)), (Value::Integer(a) / Value::Integer(b)).ok());
+            assert_eq!(expect(a.checked_rem(b)), (Value::Integer(a) % Value::Integer(b)).ok());
+            assert_eq!(expect(a.checked_neg()), (-Value::Integer(a)).ok());
+        }
+    }
+
+    #[test]
+    fn stable_hash() -> Result<()> {
+        use super::stable_hash;
+        use Value::*;
+        let d = |s: &str| -> Result<Value> { Ok(Decimal(s.parse()?)) };
+
+        // The hashes are the same in every process, so they can be compared to constants.
+        assert_eq!(0x5f96660e5a207d50, stable_hash(&Null));
+        assert_eq!(0xf077e091286db6c8, stable_hash(&Boolean(true)));
+        assert_eq!(0x7155f3db2098488f, stable_hash(&Integer(-1)));
+        assert_eq!(0x8b5d882a827006fa, stable_hash(&Float(1.5)));
+        assert_eq!(0xf36f63bfbf79f530, stable_hash(&Float(f64::NAN)));
+        assert_eq!(0x05cf99ecbb95981c, stable_hash(&d("1.5")?));
+        assert_eq!(0x527aa42cea9a1ee5, stable_hash(&String("abc".into())));
+        assert_eq!(0x01b13241043f6092, stable_hash(&Timestamp(0)));
+        assert_eq!(0xc1786098cd53e35a, stable_hash(&Date(1)));
+
+        // Equal values hash the same, as do all NaNs, but values of different types don't.
+        assert_eq!(stable_hash(&Float(0.0)), stable_hash(&Float(-0.0)));
+        assert_eq!(stable_hash(&Float(f64::NAN)), stable_hash(&Float(-f64::NAN)));
+        assert_eq!(stable_hash(&d("1.5")?), stable_hash(&d("1.500")?));
+        assert_ne!(stable_hash(&Integer(0)), stable_hash(&Timestamp(0)));
+
+        // Rows hash their values one after another, which can't be confused across fields.
+        let row = |a: &str, b: &str| vec![String(a.into()), String(b.into())];
+        assert_ne!(stable_hash_row(&row("a", "bc")), stable_hash_row(&row("ab", "c")));
+        Ok(())
+    }
+}
@@ -0,0 +1,304 @@
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::io::AsyncWriteExt;
+
+use super::kv::{KvScan, KvStore, Range};
+use crate::error::{Error, Result};
+
+/// An asynchronous key-value store, for use from async code without blocking the runtime on
+/// I/O. This is an async counterpart to KvStore, which remains the synchronous interface: the
+/// adapters below convert between the two.
+#[tonic::async_trait]
+pub trait AsyncStore: Display + Send + Sync {
+    /// Sets a value for a key, replacing the existing value if any.
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()>;
+
+    /// Gets a value for a key, if it exists.
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Deletes a key, doing nothing if it does not exist.
+    async fn delete(&self, key: &[u8]) -> Result<()>;
+
+    /// Returns an ordered range of key/value pairs.
+    async fn scan(&self, range: Range) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+
+    /// Flushes any buffered data to the underlying storage medium.
+    async fn flush(&self) -> Result<()>;
+}
+
+/// A key-value store backed by tokio::fs, storing each value in a file named by its hex-encoded
+/// key. Writes go to a temporary file which is synced and then renamed over the key's file, so a
+/// crash never leaves a partially written value behind.
+pub struct TokioFileStore {
+    /// The directory containing the value files.
+    dir: PathBuf,
+}
+
+impl TokioFileStore {
+    /// The file name suffix of values being written.
+    const TEMP_SUFFIX: &'static str = ".tmp";
+
+    /// Opens a store in the given directory, creating it if necessary, and removes any
+    /// temporary files left behind by a crash.
+    pub async fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_name().to_string_lossy().ends_with(Self::TEMP_SUFFIX) {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(Self { dir })
+    }
+
+    /// Returns the file path of a key.
+    fn path(&self, key: &[u8]) -> PathBuf {
+        self.dir.join(key.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+
+    /// Decodes a key from a file name, or None if it isn't a value file.
+    fn decode_key(name: &str) -> Option<Vec<u8>> {
+        if !name.len().is_multiple_of(2) {
+            return None;
+        }
+        (0..name.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+            .collect()
+    }
+}
+
+impl Display for TokioFileStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "tokiofile")
+    }
+}
+
+#[tonic::async_trait]
+impl AsyncStore for TokioFileStore {
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let path = self.path(key);
+        let mut temp_path = path.clone().into_os_string();
+        temp_path.push(Self::TEMP_SUFFIX);
+        let mut file = tokio::fs::File::create(&temp_path).await?;
+        file.write_all(&value).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)).await {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn scan(&self, range: Range) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut keys = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let key = entry.file_name().to_str().and_then(Self::decode_key);
+            if let Some(key) = key.filter(|key| std::ops::RangeBounds::contains(&range, key)) {
+                keys.push(key);
+            }
+        }
+        keys.sort();
+
+        // A key may be deleted concurrently, in which case it is skipped.
+        let mut items = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.get(&key).await? {
+                items.push((key, value));
+            }
+        }
+        Ok(items)
+    }
+
+    async fn flush(&self) -> Result<()> {
+        // Values are synced as they are written.
+        Ok(())
+    }
+}
+
+/// Implements AsyncStore for a synchronous store, by running its operations on tokio's blocking
+/// thread pool via spawn_blocking.
+pub struct SyncAdapter<S: KvStore + 'static> {
+    store: Arc<S>,
+}
+
+impl<S: KvStore + 'static> SyncAdapter<S> {
+    /// Wraps a synchronous store.
+    pub fn new(store: S) -> Self {
+        Self { store: Arc::new(store) }
+    }
+
+    /// Runs a store operation on the blocking thread pool.
+    async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&S) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(&store)).await?
+    }
+}
+
+impl<S: KvStore + 'static> Display for SyncAdapter<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "async({})", self.store)
+    }
+}
+
+#[tonic::async_trait]
+impl<S: KvStore + 'static> AsyncStore for SyncAdapter<S> {
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let key = key.to_vec();
+        self.run(move |store| store.set(&key, value)).await
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let key = key.to_vec();
+        self.run(move |store| store.get(&key)).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        let key = key.to_vec();
+        self.run(move |store| store.delete(&key)).await
+    }
+
+    async fn scan(&self, range: Range) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.run(move |store| store.scan(range)?.collect()).await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.run(|store| store.flush()).await
+    }
+}
+
+/// Implements KvStore for an asynchronous store, by blocking on its operations in a private
+/// single-threaded runtime. It must not be used from within an async context, since tokio
+/// doesn't allow blocking on a runtime from a runtime thread.
+pub struct BlockingAdapter<S: AsyncStore> {
+    store: S,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl<S: AsyncStore> BlockingAdapter<S> {
+    /// Wraps an asynchronous store.
+    pub fn new(store: S) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| Error::Internal(format!("Failed to start runtime: {}", e)))?;
+        Ok(Self { store, runtime })
+    }
+}
+
+impl<S: AsyncStore> Display for BlockingAdapter<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "blocking({})", self.store)
+    }
+}
+
+impl<S: AsyncStore> KvStore for BlockingAdapter<S> {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.runtime.block_on(self.store.set(key, value))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.runtime.block_on(self.store.get(key))
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.runtime.block_on(self.store.delete(key))
+    }
+
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        let items = self.runtime.block_on(self.store.scan(range))?;
+        Ok(Box::new(items.into_iter().map(Ok)))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.runtime.block_on(self.store.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::StdBPlusTree;
+
+    /// Runs basic operations against an async store.
+    async fn check_store(s: &impl AsyncStore) -> Result<()> {
+        s.set(b"b", vec![0x02]).await?;
+        s.set(b"a", vec![0x01]).await?;
+        s.set(b"c", vec![0x03]).await?;
+        s.set(b"a", vec![0x04]).await?;
+        assert_eq!(Some(vec![0x04]), s.get(b"a").await?);
+        assert_eq!(None, s.get(b"d").await?);
+
+        s.delete(b"c").await?;
+        s.delete(b"d").await?;
+        assert_eq!(None, s.get(b"c").await?);
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x04]), (b"b".to_vec(), vec![0x02])],
+            s.scan(Range::from(..)).await?
+        );
+        assert_eq!(vec![(b"b".to_vec(), vec![0x02])], s.scan(Range::from(b"b".to_vec()..)).await?);
+        s.flush().await
+    }
+
+    #[tokio::test]
+    async fn test_tokio_file_store() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let s = TokioFileStore::open(dir.path()).await?;
+        check_store(&s).await?;
+
+        // Binary keys round-trip through file names, and leftover temporary files are removed.
+        s.set(&[0x00, 0xff], vec![]).await?;
+        assert_eq!(Some(vec![]), s.get(&[0x00, 0xff]).await?);
+        tokio::fs::write(dir.path().join("0a.tmp"), b"partial").await?;
+        let s = TokioFileStore::open(dir.path()).await?;
+        assert_eq!(3, s.scan(Range::from(..)).await?.len());
+        assert!(!dir.path().join("0a.tmp").exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sync_adapter() -> Result<()> {
+        check_store(&SyncAdapter::new(StdBPlusTree::new())).await
+    }
+
+    #[test]
+    fn test_blocking_adapter() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        let s = BlockingAdapter::new(runtime.block_on(TokioFileStore::open(dir.path()))?)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.delete(b"a")?;
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x02])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+        s.flush()?;
+
+        // Round-trips through both adapters.
+        let s = BlockingAdapter::new(SyncAdapter::new(StdBPlusTree::new()))?;
+        s.set(b"a", vec![0x01])?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio::time::{Interval, MissedTickBehavior};
+
+use super::kv::{BitcaskStore, KvStore, LsmStorage, MemoryStore, StdBPlusTree, StorageStats};
+use crate::error::{Error, Result};
+
+/// Maintenance tasks of a key-value store, run periodically by a BackgroundWorker. Stores
+/// without a task do nothing for it.
+pub trait Maintenance: KvStore {
+    /// Deletes expired keys, returning the number of deleted keys.
+    fn purge_expired(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Compacts the store, reclaiming the space of overwritten and deleted keys.
+    fn compact(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Maintenance for MemoryStore {}
+
+impl Maintenance for StdBPlusTree {}
+
+impl Maintenance for BitcaskStore {
+    fn compact(&self) -> Result<()> {
+        self.merge()
+    }
+}
+
+impl Maintenance for LsmStorage {
+    /// Compacts each level into the next, from the top.
+    fn compact(&self) -> Result<()> {
+        for level in 0..Self::MAX_LEVELS - 1 {
+            LsmStorage::compact(self, level)?;
+        }
+        Ok(())
+    }
+}
+
+/// Background worker configuration.
+#[derive(Clone, Debug)]
+pub struct BackgroundConfig {
+    /// How often the store is compacted.
+    pub compact_interval: Duration,
+    /// How often expired keys are purged.
+    pub vacuum_interval: Duration,
+    /// How often the storage statistics are refreshed.
+    pub stats_interval: Duration,
+}
+
+impl Default for BackgroundConfig {
+    fn default() -> Self {
+        Self {
+            compact_interval: Duration::from_secs(600),
+            vacuum_interval: Duration::from_secs(60),
+            stats_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A worker which runs a store's maintenance tasks on timers, rather than requiring them to
+/// be triggered manually. Tasks run on Tokio's blocking thread pool, one at a time, and
+/// failures are logged and retried at the next tick. Each task first runs one interval after
+/// the worker starts.
+pub struct BackgroundWorker {
+    store: Arc<dyn Maintenance>,
+    config: BackgroundConfig,
+    /// The most recent storage statistics.
+    stats: Arc<Mutex<Option<StorageStats>>>,
+}
+
+impl BackgroundWorker {
+    /// Starts a worker for a store in the current Tokio runtime.
+    pub fn start(store: Arc<dyn Maintenance>, config: BackgroundConfig) -> BackgroundWorkerHandle {
+        let stats = Arc::new(Mutex::new(None));
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let worker = Self { store, config, stats: stats.clone() };
+        let task = tokio::spawn(worker.run(stop_rx));
+        BackgroundWorkerHandle { stop_tx, task, stats }
+    }
+
+    /// Runs the tasks until stopped, or until the handle is dropped.
+    async fn run(self, mut stop_rx: oneshot::Receiver<()>) {
+        let mut compact = Self::interval(self.config.compact_interval);
+        let mut vacuum = Self::interval(self.config.vacuum_interval);
+        let mut stats = Self::interval(self.config.stats_interval);
+        loop {
+            tokio::select! {
+                _ = &mut stop_rx => return,
+                _ = vacuum.tick() => {
+                    if let Err(err) = self.run_task(|store| store.purge_expired()).await {
+                        ::log::warn!("Failed to purge expired keys in {}: {}", self.store, err);
+                    }
+                }
+                _ = compact.tick() => {
+                    if let Err(err) = self.run_task(|store| store.compact()).await {
+                        ::log::warn!("Failed to compact {}: {}", self.store, err);
+                    }
+                }
+                _ = stats.tick() => match self.run_task(|store| store.stats()).await {
+                    Ok(stats) => *self.stats.lock() = Some(stats),
+                    Err(err) => ::log::warn!("Failed to read stats of {}: {}", self.store, err),
+                },
+            }
+        }
+    }
+
+    /// Returns an interval which first ticks after one period, and delays later ticks rather
+    /// than bursting when a task overruns.
+    fn interval(period: Duration) -> Interval {
+        let period = period.max(Duration::from_millis(1));
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    }
+
+    /// Runs a blocking task against the store.
+    async fn run_task<T, F>(&self, task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Maintenance) -> Result<T> + Send + 'static,
+    {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || task(&*store))
+            .await
+            .map_err(|err| Error::Internal(err.to_string()))?
+    }
+}
+
+/// A handle to a running BackgroundWorker. Dropping the handle also stops the worker, but
+/// without waiting for a running task to finish.
+pub struct BackgroundWorkerHandle {
+    stop_tx: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+    stats: Arc<Mutex<Option<StorageStats>>>,
+}
+
+impl BackgroundWorkerHandle {
+    /// Returns the most recent storage statistics, if they have been computed yet.
+    pub fn stats(&self) -> Option<StorageStats> {
+        *self.stats.lock()
+    }
+
+    /// Stops the worker, waiting for a running task to finish.
+    pub async fn stop(self) -> Result<()> {
+        // The worker may have exited already, in which case the receiver is gone.
+        let _ = self.stop_tx.send(());
+        self.task.await.map_err(|err| Error::Internal(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    use super::super::kv::{Range, TtlStore};
+    use super::*;
+
+    #[tokio::test]
+    async fn purges_expired_keys() -> Result<()> {
+        let now = Arc::new(AtomicU64::new(1000));
+        let clock = now.clone();
+        let clock = move || clock.load(Ordering::SeqCst);
+        let store = Arc::new(TtlStore::with_clock(StdBPlusTree::new(), clock));
+        for i in 0..10_000u32 {
+            store.set_with_ttl(&i.to_be_bytes(), vec![1], Duration::from_millis(10))?;
+        }
+        store.set(b"live", vec![2])?;
+        now.store(2000, Ordering::SeqCst);
+        assert_eq!(1, store.scan(Range::from(..))?.count());
+
+        let config = BackgroundConfig {
+            compact_interval: Duration::from_millis(50),
+            vacuum_interval: Duration::from_millis(50),
+            stats_interval: Duration::from_millis(50),
+        };
+        let worker = BackgroundWorker::start(store.clone(), config);
+        assert_eq!(None, worker.stats());
+
+        // The expired keys are purged without a manual purge_expired() call, so they don't
+        // reappear when turning back the clock.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        now.store(1000, Ordering::SeqCst);
+        assert_eq!(1, store.scan(Range::from(..))?.count());
+        assert_eq!(Some(vec![2]), store.get(b"live")?);
+        assert_eq!(Some(1), worker.stats().map(|stats| stats.key_count));
+        worker.stop().await
+    }
+}
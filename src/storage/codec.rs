@@ -0,0 +1,104 @@
+use std::marker::PhantomData;
+
+use bincode::Options as _;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// A serialization format for values of type T, see TypedStore. Decoding bytes that were
+/// encoded by a different codec or for a different type fails, rather than returning a bogus
+/// value.
+pub trait Codec<T>: Send + Sync {
+    /// Encodes a value as bytes.
+    fn encode(value: &T) -> Result<Vec<u8>>;
+
+    /// Decodes a value from bytes.
+    fn decode(bytes: &[u8]) -> Result<T>;
+}
+
+/// The Bincode format, as used elsewhere in the crate. Trailing bytes are rejected on decode.
+pub struct BincodeCodec<T>(PhantomData<fn() -> T>);
+
+impl<T> BincodeCodec<T> {
+    /// The Bincode options, encoding like bincode::serialize() does.
+    fn options() -> impl bincode::Options {
+        bincode::DefaultOptions::new().with_fixint_encoding().reject_trailing_bytes()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for BincodeCodec<T> {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        Ok(Self::options().serialize(value)?)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        Ok(Self::options().deserialize(bytes)?)
+    }
+}
+
+/// The JSON format, which is larger but human-readable.
+pub struct JsonCodec<T>(PhantomData<fn() -> T>);
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec<T> {
+    fn encode(value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// The identity codec, for raw byte values.
+pub struct RawCodec;
+
+impl Codec<Vec<u8>> for RawCodec {
+    fn encode(value: &Vec<u8>) -> Result<Vec<u8>> {
+        Ok(value.clone())
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        id: u64,
+        name: String,
+    }
+
+    #[test]
+    fn roundtrip() -> Result<()> {
+        let entry = Entry { id: 7, name: "a".into() };
+
+        let bytes = BincodeCodec::encode(&entry)?;
+        assert_eq!(bincode::serialize(&entry)?, bytes);
+        assert_eq!(entry, BincodeCodec::decode(&bytes)?);
+
+        let bytes = JsonCodec::encode(&entry)?;
+        assert_eq!(br#"{"id":7,"name":"a"}"#.to_vec(), bytes);
+        assert_eq!(entry, JsonCodec::decode(&bytes)?);
+
+        assert_eq!(vec![0x01, 0x02], RawCodec::encode(&vec![0x01, 0x02])?);
+        assert_eq!(vec![0x01, 0x02], RawCodec::decode(&[0x01, 0x02])?);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_mismatch() -> Result<()> {
+        let bytes = BincodeCodec::encode(&Entry { id: 7, name: "a".into() })?;
+        assert!(<JsonCodec<Entry>>::decode(&bytes).is_err());
+        assert!(<BincodeCodec<u64>>::decode(&bytes).is_err());
+
+        let bytes = JsonCodec::encode(&Entry { id: 7, name: "a".into() })?;
+        assert!(<BincodeCodec<Entry>>::decode(&bytes).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use parking_lot::Mutex;
+
+use super::wal::crc32;
+use super::{KvScan, KvStore, Range, StorageStats, WriteOp};
+use crate::error::{Error, Result};
+
+/// The value length marking a tombstone, i.e. a deleted key.
+const TOMBSTONE: u32 = u32::MAX;
+
+/// The minimum amount of garbage in the log before it is compacted automatically, in bytes.
+const COMPACT_MIN_GARBAGE: u64 = 4 << 20;
+
+/// A key-value store persisting its writes to a single append-only log file, with an in-memory
+/// index of the file offset of each key's latest value. Values are read from the file on demand.
+/// Overwritten and deleted records remain in the log as garbage until it is compacted, which is
+/// done automatically once at least half the log, and COMPACT_MIN_GARBAGE bytes, are garbage.
+///
+/// Each record is encoded as `[key_len: u32][key][value_len: u32][value][crc32: u32]`, where a
+/// value length of 0xffffffff marks a tombstone without a value, the CRC covers the rest of the
+/// record, and integers are big-endian. On open, the log is replayed to rebuild the index,
+/// stopping at the first truncated or corrupt record, e.g. from a crash during a write, and the
+/// log is truncated there. A batch is appended with a single write, but a crash during the write
+/// may persist only some of its records.
+pub struct AppendLogStore {
+    log: Mutex<Log>,
+}
+
+/// The log file and its index.
+struct Log {
+    path: PathBuf,
+    file: File,
+    /// The offset and length of each live key's value in the file.
+    index: BTreeMap<Vec<u8>, (u64, u32)>,
+    /// The length of the file.
+    len: u64,
+    /// The total size of the records that were overwritten or deleted, and of tombstones.
+    garbage: u64,
+}
+
+impl AppendLogStore {
+    /// Opens or creates a log file at the given path, replaying it to rebuild the index.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self { log: Mutex::new(Log::open(path.as_ref().to_path_buf())?) })
+    }
+
+    /// Rewrites the log with only the live records, returning the number of bytes saved.
+    pub fn compact(&self) -> Result<u64> {
+        self.log.lock().compact()
+    }
+}
+
+impl Log {
+    fn open(path: PathBuf) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut log = Self { path, file, index: BTreeMap::new(), len: 0, garbage: 0 };
+        while let Some((key, value, len)) = Self::decode(&data[log.len as usize..]) {
+            // Value offsets are relative to the start of the record.
+            let value = value.map(|(offset, len)| (log.len + offset as u64, len));
+            log.apply(key, value, len);
+        }
+        if log.len < data.len() as u64 {
+            log.file.set_len(log.len)?;
+        }
+        Ok(log)
+    }
+
+    /// Decodes the record at the start of a buffer, returning its key, the offset and length of
+    /// its value if not a tombstone, and the record length. Returns None if the record is
+    /// truncated or corrupt.
+    #[allow(clippy::type_complexity)]
+    fn decode(data: &[u8]) -> Option<(Vec<u8>, Option<(usize, u32)>, u64)> {
+        let read_u32 =
+            |at: usize| Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?));
+        let key_len = read_u32(0)? as usize;
+        let value_len = read_u32(4 + key_len)?;
+        let value_start = 8 + key_len;
+        let crc_start = match value_len {
+            TOMBSTONE => value_start,
+            len => value_start.checked_add(len as usize)?,
+        };
+        if crc32(data.get(..crc_start)?) != read_u32(crc_start)? {
+            return None;
+        }
+        let key = data[4..4 + key_len].to_vec();
+        let value = (value_len != TOMBSTONE).then_some((value_start, value_len));
+        Some((key, value, crc_start as u64 + 4))
+    }
+
+    /// Encodes a record, with None as the value for a tombstone.
+    fn encode(key: &[u8], value: Option<&[u8]>) -> Result<Vec<u8>> {
+        let len = |bytes: &[u8]| match u32::try_from(bytes.len()) {
+            Ok(len) if len != TOMBSTONE => Ok(len),
+            _ => Err(Error::Value(format!("Key or value too large ({} bytes)", bytes.len()))),
+        };
+        let mut record = Vec::with_capacity(12 + key.len() + value.map_or(0, |v| v.len()));
+        record.extend(len(key)?.to_be_bytes());
+        record.extend(key);
+        match value {
+            Some(value) => {
+                record.extend(len(value)?.to_be_bytes());
+                record.extend(value);
+            }
+            None => record.extend(TOMBSTONE.to_be_bytes()),
+        }
+        record.extend(crc32(&record).to_be_bytes());
+        Ok(record)
+    }
+
+    /// Updates the index for a record of the given length appended at the end of the log.
+    fn apply(&mut self, key: Vec<u8>, value: Option<(u64, u32)>, len: u64) {
+        let replaced = match value {
+            Some(value) => self.index.insert(key.clone(), value),
+            None => {
+                self.garbage += len;
+                self.index.remove(&key)
+            }
+        };
+        if let Some((_, value_len)) = replaced {
+            self.garbage += Self::record_len(&key, Some(value_len));
+        }
+        self.len += len;
+    }
+
+    /// Returns the length of a record.
+    fn record_len(key: &[u8], value_len: Option<u32>) -> u64 {
+        12 + key.len() as u64 + value_len.unwrap_or(0) as u64
+    }
+
+    /// Moves the value at old_key to new_key with a single batch, see KvStore::rename(). If
+    /// overwrite is false, an occupied new_key is left alone and false is returned.
+    fn rename(&mut self, old_key: &[u8], new_key: &[u8], overwrite: bool) -> Result<bool> {
+        super::rename_batch(
+            self,
+            old_key,
+            new_key,
+            overwrite,
+            Self::get,
+            |log, key| Ok(log.index.contains_key(key)),
+            Self::write,
+        )
+    }
+
+    /// Appends a batch of writes with a single write, then updates the index. Deletes of missing
+    /// keys are skipped. Compacts the log if it has accumulated enough garbage.
+    fn write(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+        let mut buf = Vec::new();
+        let mut records = Vec::with_capacity(ops.len());
+        let mut put_keys = HashSet::new();
+        for op in ops {
+            let start = self.len + buf.len() as u64;
+            let (key, value) = match op {
+                WriteOp::Put { key, value } => {
+                    buf.extend(Self::encode(&key, Some(&value))?);
+                    let value = Some((start + 8 + key.len() as u64, value.len() as u32));
+                    put_keys.insert(key.clone());
+                    (key, value)
+                }
+                WriteOp::Delete { key }
+                    if self.index.contains_key(&key) || put_keys.contains(&key) =>
+                {
+                    buf.extend(Self::encode(&key, None)?);
+                    (key, None)
+                }
+                WriteOp::Delete { .. } => continue,
+            };
+            records.push((key, value, self.len + buf.len() as u64 - start));
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.file.write_all(&buf)?;
+        for (key, value, len) in records {
+            self.apply(key, value, len);
+        }
+        if self.garbage >= COMPACT_MIN_GARBAGE && 2 * self.garbage >= self.len {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Reads a value from the file.
+    fn read(&mut self, (offset, len): (u64, u32)) -> Result<Vec<u8>> {
+        let mut value = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut value)?;
+        Ok(value)
+    }
+
+    /// Gets the value of a key, if any.
+    fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.index.get(key).copied() {
+            Some(value) => Ok(Some(self.read(value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Rewrites the log with only the live records, returning the number of bytes saved. The
+    /// new log is written to a temporary file, synced, and then renamed over the old log.
+    fn compact(&mut self) -> Result<u64> {
+        let tmp_path = self.path.with_extension("compact");
+        let mut tmp = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        let mut index = BTreeMap::new();
+        let mut len = 0;
+        for (key, value) in self.index.clone() {
+            let record = Self::encode(&key, Some(&self.read(value)?))?;
+            tmp.write_all(&record)?;
+            index.insert(key.clone(), (len + 8 + key.len() as u64, value.1));
+            len += record.len() as u64;
+        }
+        tmp.sync_all()?;
+        drop(tmp);
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        let saved = self.len - len;
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        self.index = index;
+        self.len = len;
+        self.garbage = 0;
+        Ok(saved)
+    }
+}
+
+impl Display for AppendLogStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "append_log")
+    }
+}
+
+impl KvStore for AppendLogStore {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.log.lock().write(vec![WriteOp::Put { key: key.to_vec(), value }])
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.log.lock().get(key)
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut log = self.log.lock();
+        keys.iter().map(|key| log.get(key)).collect()
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.log.lock().write(vec![WriteOp::Delete { key: key.to_vec() }])
+    }
+
+    /// The range is buffered, reading all its values from the file.
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        if range.is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let mut log = self.log.lock();
+        let entries: Vec<_> = log.index.range(range).map(|(k, v)| (k.clone(), *v)).collect();
+        let items = entries
+            .into_iter()
+            .map(|(key, value)| Ok((key, log.read(value)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(items.into_iter().map(Ok)))
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(self.log.lock().file.sync_all()?)
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        self.log.lock().rename(old_key, new_key, true).map(|_| ())
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        self.log.lock().rename(old_key, new_key, false)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        self.log.lock().write(ops)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let mut log = self.log.lock();
+        if log.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        log.write(vec![WriteOp::Put { key: key.to_vec(), value: new_value }])?;
+        Ok(true)
+    }
+
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        if range.is_empty() {
+            return Ok(0);
+        }
+        let mut log = self.log.lock();
+        let ops: Vec<_> =
+            log.index.range(range).map(|(key, _)| WriteOp::Delete { key: key.clone() }).collect();
+        let count = ops.len() as u64;
+        log.write(ops)?;
+        Ok(count)
+    }
+
+    /// Computed from the index, without reading values.
+    fn entry_count(&self) -> Result<u64> {
+        Ok(self.log.lock().index.len() as u64)
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        let log = self.log.lock();
+        Ok(StorageStats {
+            key_count: log.index.len() as u64,
+            total_bytes_keys: log.index.keys().map(|k| k.len() as u64).sum(),
+            total_bytes_values: log.index.values().map(|(_, len)| *len as u64).sum(),
+            disk_bytes: log.len,
+        })
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<AppendLogStore> for AppendLogStore {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        let dir = tempfile::tempdir()?;
+        Ok((AppendLogStore::open(dir.path().join("kv.log"))?, Some(dir)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        AppendLogStore::test()
+    }
+
+    fn scan(s: &AppendLogStore) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        s.scan(Range::from(..))?.collect()
+    }
+
+    #[test]
+    fn recover() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kv.log");
+        let s = AppendLogStore::open(&path)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![])?;
+        s.set(b"a", vec![0x02])?;
+        s.delete(b"b")?;
+        s.set(b"c", vec![0x03])?;
+        drop(s);
+
+        let s = AppendLogStore::open(&path)?;
+        assert_eq!(vec![(b"a".to_vec(), vec![0x02]), (b"c".to_vec(), vec![0x03])], scan(&s)?);
+        Ok(())
+    }
+
+    #[test]
+    fn recover_truncated() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kv.log");
+        let s = AppendLogStore::open(&path)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        drop(s);
+
+        // Chop off the end of the last record, as if the process crashed mid-write. It is lost,
+        // and removed so later writes can be replayed.
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(file.metadata()?.len() - 2)?;
+        let s = AppendLogStore::open(&path)?;
+        assert_eq!(vec![(b"a".to_vec(), vec![0x01])], scan(&s)?);
+        s.set(b"c", vec![0x03])?;
+        drop(s);
+
+        // A corrupt record fails its checksum, and is dropped along with everything after it.
+        let mut data = std::fs::read(&path)?;
+        data[5] ^= 0xff;
+        std::fs::write(&path, data)?;
+        let s = AppendLogStore::open(&path)?;
+        assert!(scan(&s)?.is_empty());
+        assert_eq!(0, std::fs::metadata(&path)?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn compact() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("kv.log");
+        let s = AppendLogStore::open(&path)?;
+        for i in 0..10_u8 {
+            s.set(b"a", vec![i])?;
+        }
+        s.set(b"b", vec![0x01, 0x02])?;
+        s.set(b"c", vec![0x03])?;
+        s.delete(b"c")?;
+
+        // Nine overwritten values of "a", and "c" with its tombstone, are removed.
+        let len = std::fs::metadata(&path)?.len();
+        assert_eq!(9 * 14 + 14 + 13, s.compact()?);
+        assert_eq!(len - 9 * 14 - 14 - 13, std::fs::metadata(&path)?.len());
+        assert_eq!(0, s.compact()?);
+        let mut expect = vec![(b"a".to_vec(), vec![0x09]), (b"b".to_vec(), vec![0x01, 0x02])];
+        assert_eq!(expect, scan(&s)?);
+
+        // The store keeps working after compaction, also across restarts.
+        s.set(b"d", vec![0x04])?;
+        drop(s);
+        let s = AppendLogStore::open(&path)?;
+        expect.push((b"d".to_vec(), vec![0x04]));
+        assert_eq!(expect, scan(&s)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt::Display;
+
+use parking_lot::Mutex;
+
+use super::{KvScan, KvStore, Range, StorageStats, StoreSnapshot, WriteOp};
+use crate::error::Result;
+
+/// A key-value store wrapper which serializes all writes through a mutex, making
+/// compare_and_swap() and the other read-modify-write operations atomic for any inner store.
+/// Reads are not serialized. This only protects against concurrent writers in the same process
+/// that go through this wrapper, not against other handles to the inner store.
+pub struct AtomicStore<S: KvStore> {
+    /// The inner store.
+    inner: S,
+    /// Held while writing.
+    writes: Mutex<()>,
+}
+
+impl<S: KvStore> AtomicStore<S> {
+    /// Wraps a store.
+    pub fn new(inner: S) -> Self {
+        Self { inner, writes: Mutex::new(()) }
+    }
+}
+
+impl<S: KvStore> Display for AtomicStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "atomic({})", self.inner)
+    }
+}
+
+impl<S: KvStore> KvStore for AtomicStore<S> {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.set(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.get_multi(keys)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.delete(key)
+    }
+
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        self.inner.scan(range)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.rename(old_key, new_key)
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        let _writes = self.writes.lock();
+        self.inner.rename_if_absent(old_key, new_key)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.write_batch(ops)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let _writes = self.writes.lock();
+        self.inner.compare_and_swap(key, expected, new_value)
+    }
+
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        let _writes = self.writes.lock();
+        self.inner.delete_range(range)
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        self.inner.stats()
+    }
+
+    fn entry_count(&self) -> Result<u64> {
+        self.inner.entry_count()
+    }
+
+    /// Writes are blocked while the inner store is snapshotted, so the snapshot is consistent
+    /// even if the inner store's scans aren't.
+    fn snapshot(&self) -> Result<StoreSnapshot> {
+        let _writes = self.writes.lock();
+        self.inner.snapshot()
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<AtomicStore<super::StdBPlusTree>> for AtomicStore<super::StdBPlusTree> {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        Ok((AtomicStore::new(super::StdBPlusTree::new()), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{LsmStorage, StdBPlusTree};
+    use super::*;
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        AtomicStore::<StdBPlusTree>::test()
+    }
+
+    /// Increments a counter from several threads with compare-and-swap retry loops, checking
+    /// that no increment is lost.
+    fn check_counter(s: &impl KvStore) -> Result<()> {
+        const THREADS: u64 = 4;
+        const INCREMENTS: u64 = 250;
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    scope.spawn(|| -> Result<()> {
+                        for _ in 0..INCREMENTS {
+                            loop {
+                                let current = s.get(b"counter")?;
+                                let next = match current.as_deref() {
+                                    Some(bytes) => u64::from_be_bytes(bytes.try_into()?) + 1,
+                                    None => 1,
+                                };
+                                let new_value = next.to_be_bytes().to_vec();
+                                if s.compare_and_swap(b"counter", current.as_deref(), new_value)? {
+                                    break;
+                                }
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            threads.into_iter().try_for_each(|t| t.join().unwrap())
+        })?;
+        let expected = (THREADS * INCREMENTS).to_be_bytes().to_vec();
+        assert_eq!(Some(expected), s.get(b"counter")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_and_swap_contention() -> Result<()> {
+        check_counter(&StdBPlusTree::new())?;
+        check_counter(&AtomicStore::new(StdBPlusTree::new()))?;
+        let dir = tempfile::tempdir()?;
+        check_counter(&AtomicStore::new(LsmStorage::open(dir.path())?))
+    }
+
+    /// Publishes a temporary key per thread to a shared key, once with rename_if_absent(),
+    /// where exactly one thread must win, and once with rename(), where each thread overwrites
+    /// the last and no temporary key may be left behind.
+    fn check_publish(s: &impl KvStore) -> Result<()> {
+        const THREADS: u8 = 8;
+        let temp = |i: u8| vec![b't', i];
+        for i in 0..THREADS {
+            s.set(&temp(i), vec![i])?;
+        }
+        let winners = std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|i| scope.spawn(move || s.rename_if_absent(&temp(i), b"published")))
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect::<Result<Vec<_>>>()
+        })?;
+        assert_eq!(1, winners.iter().filter(|won| **won).count());
+        let winner = winners.iter().position(|won| *won).unwrap() as u8;
+        assert_eq!(Some(vec![winner]), s.get(b"published")?);
+        for i in 0..THREADS {
+            assert_eq!(i != winner, s.get(&temp(i))?.is_some());
+        }
+
+        for i in 0..THREADS {
+            s.set(&temp(i), vec![i])?;
+        }
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..THREADS)
+                .map(|i| scope.spawn(move || s.rename(&temp(i), b"published")))
+                .collect();
+            threads.into_iter().try_for_each(|t| t.join().unwrap())
+        })?;
+        assert_eq!(1, s.scan(Range::from(..))?.count());
+        assert!(s.get(b"published")?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename_contention() -> Result<()> {
+        check_publish(&AtomicStore::new(StdBPlusTree::new()))?;
+        let dir = tempfile::tempdir()?;
+        check_publish(&AtomicStore::new(LsmStorage::open(dir.path())?))
+    }
+}
@@ -0,0 +1,684 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::RwLock;
+
+use super::wal::crc32;
+use super::{KvScan, KvStore, Range, StorageStats, StoreSnapshot, WriteOp};
+use crate::error::{Error, Result};
+
+/// The default size after which the active file is rotated.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 64 << 20;
+
+/// The value length marking a tombstone, i.e. a deleted key.
+const TOMBSTONE: u32 = u32::MAX;
+
+/// The length of a data file record header.
+const HEADER_LEN: usize = 20;
+
+/// The length of a hint file entry header.
+const HINT_HEADER_LEN: usize = 28;
+
+/// A keydir entry, locating the latest value of a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirEntry {
+    /// The data file containing the value.
+    pub file_id: u32,
+    /// The offset of the value in the data file.
+    pub offset: u64,
+    /// The length of the value.
+    pub size: u32,
+    /// The time the value was written, in milliseconds since the Unix epoch.
+    pub tstamp: u64,
+}
+
+/// A key-value store in the style of Bitcask. Writes are appended to the active data file, which
+/// is rotated once it exceeds the maximum file size, and an in-memory keydir locates the latest
+/// value of each key. Values are read from the data files on demand, with positional reads, so
+/// concurrent readers don't contend on the files. Overwritten and deleted records remain in the
+/// data files as garbage until merge() compacts the non-active files.
+///
+/// Data files are named by a monotonically increasing ID, e.g. 000001.data, where higher IDs hold
+/// newer records. Each record is encoded as `[crc32: u32][tstamp: u64][key_len: u32]
+/// [value_len: u32][key][value]`, where a value length of 0xffffffff marks a tombstone without a
+/// value, the CRC covers the rest of the record, and integers are big-endian. On open, the data
+/// files are replayed in order to rebuild the keydir, stopping at the first truncated or corrupt
+/// record in each file, and the file is truncated there.
+///
+/// A merged file has a hint file alongside it, e.g. 000004.hint, which holds the keydir entries
+/// of its records as `[crc32: u32][tstamp: u64][key_len: u32][value_len: u32][offset: u64][key]`.
+/// On open, the keydir entries of a merged file are read from its hint file instead of scanning
+/// the file's values, falling back to the scan if the hint file is corrupt.
+pub struct BitcaskStore {
+    inner: RwLock<Inner>,
+}
+
+/// The data files and keydir.
+struct Inner {
+    /// The directory containing the data files.
+    dir: PathBuf,
+    /// The size after which the active file is rotated.
+    max_file_size: u64,
+    /// The latest value of each live key.
+    keydir: BTreeMap<Vec<u8>, DirEntry>,
+    /// The open data files and their lengths, by ID. The last one is the active file.
+    files: BTreeMap<u32, (File, u64)>,
+}
+
+impl BitcaskStore {
+    /// Opens or creates a store in the given directory with the default maximum file size, see
+    /// `open_with`.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with(dir, DEFAULT_MAX_FILE_SIZE)
+    }
+
+    /// Opens or creates a store in the given directory, replaying its data files to rebuild the
+    /// keydir. Writes are appended to the last data file.
+    pub fn open_with(dir: impl AsRef<Path>, max_file_size: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let (keydir, files) = (BTreeMap::new(), BTreeMap::new());
+        let mut inner = Inner { dir, max_file_size, keydir, files };
+        for id in inner.file_ids()? {
+            inner.load(id)?;
+        }
+        if inner.files.is_empty() {
+            inner.create_file(1)?;
+        }
+        Ok(Self { inner: RwLock::new(inner) })
+    }
+
+    /// Compacts all non-active data files into a single merged file containing only their live
+    /// records, and writes its hint file. Writes are blocked during the merge.
+    pub fn merge(&self) -> Result<()> {
+        self.inner.write().merge()
+    }
+
+    /// Returns the keydir entry of a key, if it exists.
+    pub fn dir_entry(&self, key: &[u8]) -> Option<DirEntry> {
+        self.inner.read().keydir.get(key).copied()
+    }
+}
+
+impl Inner {
+    /// Returns the path of a data file.
+    fn data_path(&self, id: u32) -> PathBuf {
+        self.dir.join(format!("{:06}.data", id))
+    }
+
+    /// Returns the path of a hint file.
+    fn hint_path(&self, id: u32) -> PathBuf {
+        self.dir.join(format!("{:06}.hint", id))
+    }
+
+    /// Lists the IDs of the data files in ascending order, removing temporary files left behind
+    /// by an interrupted merge.
+    fn file_ids(&self) -> Result<Vec<u32>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            if name.ends_with(".tmp") {
+                fs::remove_file(&path)?;
+            } else if let Some(id) = name.strip_suffix(".data").and_then(|id| id.parse().ok()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    /// Returns the ID of the active file.
+    fn active_id(&self) -> u32 {
+        *self.files.keys().next_back().expect("no active file")
+    }
+
+    /// Creates a new, empty active file.
+    fn create_file(&mut self, id: u32) -> Result<()> {
+        let path = self.data_path(id);
+        let file = OpenOptions::new().read(true).append(true).create_new(true).open(path)?;
+        self.files.insert(id, (file, 0));
+        Ok(())
+    }
+
+    /// Loads a data file into the keydir, from its hint file if it has a valid one.
+    fn load(&mut self, id: u32) -> Result<()> {
+        let mut file = OpenOptions::new().read(true).append(true).open(self.data_path(id))?;
+        let len = file.metadata()?.len();
+        if let Some(entries) = self.read_hint(id, len)? {
+            for (key, entry) in entries {
+                self.keydir.insert(key, entry);
+            }
+            self.files.insert(id, (file, len));
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let mut pos = 0;
+        while let Some((key, entry, record_len)) = Self::decode(&data[pos..]) {
+            match entry {
+                Some((offset, size, tstamp)) => {
+                    let offset = (pos + offset) as u64;
+                    self.keydir.insert(key, DirEntry { file_id: id, offset, size, tstamp })
+                }
+                None => self.keydir.remove(&key),
+            };
+            pos += record_len;
+        }
+        if pos < data.len() {
+            file.set_len(pos as u64)?;
+        }
+        self.files.insert(id, (file, pos as u64));
+        Ok(())
+    }
+
+    /// Reads the keydir entries of a data file of the given length from its hint file. Returns
+    /// None if there is no hint file, or if it is corrupt or doesn't cover the data file.
+    #[allow(clippy::type_complexity)]
+    fn read_hint(&self, id: u32, len: u64) -> Result<Option<Vec<(Vec<u8>, DirEntry)>>> {
+        let data = match fs::read(self.hint_path(id)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let mut entries = Vec::new();
+        let (mut pos, mut covered) = (0, 0);
+        while pos < data.len() {
+            let (key, entry, hint_len) = match Self::decode_hint(&data[pos..]) {
+                Some((key, offset, size, tstamp, hint_len)) => {
+                    (key, DirEntry { file_id: id, offset, size, tstamp }, hint_len)
+                }
+                None => return Ok(None),
+            };
+            covered = covered.max(entry.offset + entry.size as u64);
+            entries.push((key, entry));
+            pos += hint_len;
+        }
+        Ok((covered == len).then_some(entries))
+    }
+
+    /// Decodes the record at the start of a buffer, returning its key, the value offset
+    /// relative to the record, length and timestamp if not a tombstone, and the record length.
+    /// Returns None if the record is truncated or corrupt.
+    #[allow(clippy::type_complexity)]
+    fn decode(data: &[u8]) -> Option<(Vec<u8>, Option<(usize, u32, u64)>, usize)> {
+        let header = data.get(..HEADER_LEN)?;
+        let tstamp = u64::from_be_bytes(header[4..12].try_into().ok()?);
+        let key_len = u32::from_be_bytes(header[12..16].try_into().ok()?) as usize;
+        let value_len = u32::from_be_bytes(header[16..20].try_into().ok()?);
+        let value_start = HEADER_LEN.checked_add(key_len)?;
+        let end = match value_len {
+            TOMBSTONE => value_start,
+            len => value_start.checked_add(len as usize)?,
+        };
+        if crc32(data.get(4..end)?) != u32::from_be_bytes(header[..4].try_into().ok()?) {
+            return None;
+        }
+        let key = data[HEADER_LEN..value_start].to_vec();
+        let value = (value_len != TOMBSTONE).then_some((value_start, value_len, tstamp));
+        Some((key, value, end))
+    }
+
+    /// Encodes a record, with None as the value for a tombstone.
+    fn encode(tstamp: u64, key: &[u8], value: Option<&[u8]>) -> Result<Vec<u8>> {
+        let len = |bytes: &[u8]| match u32::try_from(bytes.len()) {
+            Ok(len) if len != TOMBSTONE => Ok(len),
+            _ => Err(Error::Value(format!("Key or value too large ({} bytes)", bytes.len()))),
+        };
+        let mut record = Vec::with_capacity(HEADER_LEN + key.len() + value.map_or(0, |v| v.len()));
+        record.extend([0; 4]);
+        record.extend(tstamp.to_be_bytes());
+        record.extend(len(key)?.to_be_bytes());
+        record.extend(value.map_or(Ok(TOMBSTONE), len)?.to_be_bytes());
+        record.extend(key);
+        record.extend(value.unwrap_or_default());
+        let crc = crc32(&record[4..]);
+        record[..4].copy_from_slice(&crc.to_be_bytes());
+        Ok(record)
+    }
+
+    /// Decodes the hint entry at the start of a buffer, returning its key, value offset, length
+    /// and timestamp, and the entry length. Returns None if the entry is truncated or corrupt.
+    #[allow(clippy::type_complexity)]
+    fn decode_hint(data: &[u8]) -> Option<(Vec<u8>, u64, u32, u64, usize)> {
+        let header = data.get(..HINT_HEADER_LEN)?;
+        let tstamp = u64::from_be_bytes(header[4..12].try_into().ok()?);
+        let key_len = u32::from_be_bytes(header[12..16].try_into().ok()?) as usize;
+        let size = u32::from_be_bytes(header[16..20].try_into().ok()?);
+        let offset = u64::from_be_bytes(header[20..28].try_into().ok()?);
+        let end = HINT_HEADER_LEN.checked_add(key_len)?;
+        if crc32(data.get(4..end)?) != u32::from_be_bytes(header[..4].try_into().ok()?) {
+            return None;
+        }
+        Some((data[HINT_HEADER_LEN..end].to_vec(), offset, size, tstamp, end))
+    }
+
+    /// Encodes a hint entry.
+    fn encode_hint(key: &[u8], entry: &DirEntry) -> Vec<u8> {
+        let mut hint = Vec::with_capacity(HINT_HEADER_LEN + key.len());
+        hint.extend([0; 4]);
+        hint.extend(entry.tstamp.to_be_bytes());
+        hint.extend((key.len() as u32).to_be_bytes());
+        hint.extend(entry.size.to_be_bytes());
+        hint.extend(entry.offset.to_be_bytes());
+        hint.extend(key);
+        let crc = crc32(&hint[4..]);
+        hint[..4].copy_from_slice(&crc.to_be_bytes());
+        hint
+    }
+
+    /// Moves the value at old_key to new_key with a single batch, see KvStore::rename(). If
+    /// overwrite is false, an occupied new_key is left alone and false is returned.
+    fn rename(&mut self, old_key: &[u8], new_key: &[u8], overwrite: bool) -> Result<bool> {
+        super::rename_batch(
+            self,
+            old_key,
+            new_key,
+            overwrite,
+            |inner, key| inner.get(key),
+            |inner, key| Ok(inner.keydir.contains_key(key)),
+            Self::write,
+        )
+    }
+
+    /// Appends a batch of writes to the active file with a single write, then updates the
+    /// keydir. Deletes of missing keys are skipped. The active file is rotated first if full, so
+    /// a batch is never split across files.
+    fn write(&mut self, ops: Vec<WriteOp>) -> Result<()> {
+        let active_id = self.active_id();
+        if self.files[&active_id].1 >= self.max_file_size {
+            self.create_file(active_id + 1)?;
+        }
+        let active_id = self.active_id();
+        let start = self.files[&active_id].1;
+        let tstamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+
+        let mut buf = Vec::new();
+        let mut entries = Vec::with_capacity(ops.len());
+        let mut put_keys = HashSet::new();
+        for op in ops {
+            let offset = start + (buf.len() + HEADER_LEN) as u64;
+            match op {
+                WriteOp::Put { key, value } => {
+                    buf.extend(Self::encode(tstamp, &key, Some(&value))?);
+                    let offset = offset + key.len() as u64;
+                    let size = value.len() as u32;
+                    put_keys.insert(key.clone());
+                    let entry = DirEntry { file_id: active_id, offset, size, tstamp };
+                    entries.push((key, Some(entry)));
+                }
+                WriteOp::Delete { key }
+                    if self.keydir.contains_key(&key) || put_keys.contains(&key) =>
+                {
+                    buf.extend(Self::encode(tstamp, &key, None)?);
+                    entries.push((key, None));
+                }
+                WriteOp::Delete { .. } => {}
+            }
+        }
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let (file, len) = self.files.get_mut(&active_id).expect("no active file");
+        file.write_all(&buf)?;
+        *len += buf.len() as u64;
+        for (key, entry) in entries {
+            match entry {
+                Some(entry) => self.keydir.insert(key, entry),
+                None => self.keydir.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    /// Reads a value from its data file.
+    fn read(&self, entry: &DirEntry) -> Result<Vec<u8>> {
+        let (file, _) = self.files.get(&entry.file_id).ok_or_else(|| {
+            Error::Internal(format!("Bitcask data file {} not found", entry.file_id))
+        })?;
+        let mut value = vec![0; entry.size as usize];
+        file.read_exact_at(&mut value, entry.offset)?;
+        Ok(value)
+    }
+
+    /// Gets the value of a key, if any.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.keydir.get(key).map(|entry| self.read(entry)).transpose()
+    }
+
+    /// Merges the non-active files. A new active file is created first, and the merged file
+    /// takes the ID between it and the old active file, which is sealed. Since the merged file
+    /// only holds keys whose latest records were in the merged files, it shares no keys with the
+    /// old active file, and replaying the files in ID order still yields the latest records.
+    ///
+    /// The merged data and hint files are written to temporary files, synced, and renamed into
+    /// place, before the merged files are removed in ascending ID order. If interrupted, any
+    /// merged files left behind are older than the merged file, and since tombstones are always
+    /// in newer files than the records they delete, the remaining ones still shadow any stale
+    /// records they delete.
+    fn merge(&mut self) -> Result<()> {
+        let active_id = self.active_id();
+        let inputs: Vec<u32> = self.files.keys().copied().filter(|id| *id != active_id).collect();
+        if inputs.is_empty() {
+            return Ok(());
+        }
+        let merged_id = active_id + 1;
+        self.create_file(active_id + 2)?;
+
+        let (data_path, hint_path) = (self.data_path(merged_id), self.hint_path(merged_id));
+        let tmp_path = |path: &Path| PathBuf::from(format!("{}.tmp", path.display()));
+        let mut data = BufWriter::new(File::create(tmp_path(&data_path))?);
+        let mut hint = BufWriter::new(File::create(tmp_path(&hint_path))?);
+        let mut entries = Vec::new();
+        let mut len = 0;
+        for (key, entry) in self.keydir.iter().filter(|(_, e)| inputs.contains(&e.file_id)) {
+            let record = Self::encode(entry.tstamp, key, Some(&self.read(entry)?))?;
+            let offset = len + (HEADER_LEN + key.len()) as u64;
+            let entry = DirEntry { file_id: merged_id, offset, ..*entry };
+            data.write_all(&record)?;
+            hint.write_all(&Self::encode_hint(key, &entry))?;
+            entries.push((key.clone(), entry));
+            len += record.len() as u64;
+        }
+        for mut file in [data, hint] {
+            file.flush()?;
+            file.get_ref().sync_all()?;
+        }
+        fs::rename(tmp_path(&data_path), &data_path)?;
+        fs::rename(tmp_path(&hint_path), &hint_path)?;
+
+        let file = OpenOptions::new().read(true).open(&data_path)?;
+        self.files.insert(merged_id, (file, len));
+        for (key, entry) in entries {
+            self.keydir.insert(key, entry);
+        }
+        for id in inputs {
+            self.files.remove(&id);
+            fs::remove_file(self.data_path(id))?;
+            match fs::remove_file(self.hint_path(id)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for BitcaskStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bitcask")
+    }
+}
+
+impl KvStore for BitcaskStore {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.inner.write().write(vec![WriteOp::Put { key: key.to_vec(), value }])
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.read().get(key)
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let inner = self.inner.read();
+        keys.iter().map(|key| inner.get(key)).collect()
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.inner.write().write(vec![WriteOp::Delete { key: key.to_vec() }])
+    }
+
+    /// The range is buffered, reading all its values from the data files.
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        if range.is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let inner = self.inner.read();
+        let items = inner
+            .keydir
+            .range(range)
+            .map(|(key, entry)| Ok((key.clone(), inner.read(entry)?)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Box::new(items.into_iter().map(Ok)))
+    }
+
+    fn flush(&self) -> Result<()> {
+        let inner = self.inner.read();
+        Ok(inner.files[&inner.active_id()].0.sync_all()?)
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        self.inner.write().rename(old_key, new_key, true).map(|_| ())
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        self.inner.write().rename(old_key, new_key, false)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        self.inner.write().write(ops)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let mut inner = self.inner.write();
+        if inner.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        inner.write(vec![WriteOp::Put { key: key.to_vec(), value: new_value }])?;
+        Ok(true)
+    }
+
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        if range.is_empty() {
+            return Ok(0);
+        }
+        let mut inner = self.inner.write();
+        let ops: Vec<_> = inner
+            .keydir
+            .range(range)
+            .map(|(key, _)| WriteOp::Delete { key: key.clone() })
+            .collect();
+        let count = ops.len() as u64;
+        inner.write(ops)?;
+        Ok(count)
+    }
+
+    /// Computed from the keydir, without reading values.
+    fn entry_count(&self) -> Result<u64> {
+        Ok(self.inner.read().keydir.len() as u64)
+    }
+
+    /// Copies the keydir and duplicates the data file handles, without copying any values.
+    /// Since records are only ever appended, the snapshot's entries remain valid as the store
+    /// is written to, and the open handles keep files removed by a merge readable.
+    fn snapshot(&self) -> Result<StoreSnapshot> {
+        let inner = self.inner.read();
+        let files = inner
+            .files
+            .iter()
+            .map(|(id, (file, len))| Ok((*id, (file.try_clone()?, *len))))
+            .collect::<Result<_>>()?;
+        let view = Inner {
+            dir: inner.dir.clone(),
+            max_file_size: inner.max_file_size,
+            keydir: inner.keydir.clone(),
+            files,
+        };
+        Ok(StoreSnapshot::new(BitcaskStore { inner: RwLock::new(view) }))
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        let inner = self.inner.read();
+        Ok(StorageStats {
+            key_count: inner.keydir.len() as u64,
+            total_bytes_keys: inner.keydir.keys().map(|k| k.len() as u64).sum(),
+            total_bytes_values: inner.keydir.values().map(|e| e.size as u64).sum(),
+            disk_bytes: inner.files.values().map(|(_, len)| len).sum(),
+        })
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<BitcaskStore> for BitcaskStore {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        // A small file size exercises rotation.
+        let dir = tempfile::tempdir()?;
+        Ok((BitcaskStore::open_with(dir.path(), 1024)?, Some(dir)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        BitcaskStore::test()
+    }
+
+    fn scan(s: &BitcaskStore) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        s.scan(Range::from(..))?.collect()
+    }
+
+    /// Lists the file names in a directory, in order.
+    fn files(dir: &Path) -> Result<Vec<String>> {
+        let mut names = fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    #[test]
+    fn recover() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        for i in 0..20_u8 {
+            s.set(&[i % 5], vec![i; 10])?;
+        }
+        s.delete(&[2])?;
+        assert!(files(dir.path())?.len() > 1);
+        let expect = scan(&s)?;
+        drop(s);
+
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        assert_eq!(expect, scan(&s)?);
+        assert_eq!(None, s.get(&[2])?);
+
+        // A truncated record at the end of the active file is lost, and removed so later writes
+        // can be replayed.
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        drop(s);
+        let path = dir.path().join(files(dir.path())?.last().unwrap());
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(file.metadata()?.len() - 2)?;
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"b")?);
+        s.set(b"c", vec![0x03])?;
+        drop(s);
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        assert_eq!(Some(vec![0x03]), s.get(b"c")?);
+        Ok(())
+    }
+
+    #[test]
+    fn merge() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        for i in 0..50_u8 {
+            s.set(&[i % 10], vec![i; 10])?;
+        }
+        for i in 0..5_u8 {
+            s.delete(&[i])?;
+        }
+        let expect = scan(&s)?;
+        let disk_bytes = s.stats()?.disk_bytes;
+
+        // The non-active files are replaced by a merged file with a hint file, and a new active
+        // file. The old active file holds the latest deletes, and is kept.
+        let active: u32 = files(dir.path())?.last().unwrap()[..6].parse()?;
+        let name = |id: u32, ext: &str| format!("{:06}.{}", id, ext);
+        s.merge()?;
+        assert_eq!(
+            vec![name(active, "data"), name(active + 1, "data"), name(active + 1, "hint")]
+                .into_iter()
+                .chain([name(active + 2, "data")])
+                .collect::<Vec<_>>(),
+            files(dir.path())?
+        );
+        assert!(s.stats()?.disk_bytes < disk_bytes);
+        assert_eq!(expect, scan(&s)?);
+        assert_eq!(active + 1, s.dir_entry(&[5]).unwrap().file_id);
+        s.set(&[6], vec![0x06])?;
+        s.delete(&[7])?;
+
+        // The keydir is rebuilt from the hint file on open.
+        drop(s);
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        let mut expect: Vec<_> = expect.into_iter().filter(|(k, _)| k != &[7]).collect();
+        expect[1].1 = vec![0x06];
+        assert_eq!(expect, scan(&s)?);
+        let entry = s.dir_entry(&[8]).unwrap();
+        drop(s);
+
+        // A corrupt hint file is ignored, and the data file scanned instead.
+        let hint_path = dir.path().join(name(active + 1, "hint"));
+        let mut hint = fs::read(&hint_path)?;
+        hint[20] ^= 0xff;
+        fs::write(&hint_path, hint)?;
+        let s = BitcaskStore::open_with(dir.path(), 64)?;
+        assert_eq!(expect, scan(&s)?);
+        assert_eq!(Some(entry), s.dir_entry(&[8]));
+
+        // Merging again compacts the previous merged and active files, leaving the sealed
+        // active file, the merged file and its hint, and the new active file.
+        s.merge()?;
+        s.merge()?;
+        assert_eq!(expect, scan(&s)?);
+        assert_eq!(4, files(dir.path())?.len());
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_reads() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let s = BitcaskStore::open_with(dir.path(), 256)?;
+        for i in 0..100_u8 {
+            s.set(&[i], vec![i; 16])?;
+        }
+        std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| -> Result<()> {
+                        for i in 0..100_u8 {
+                            assert_eq!(Some(vec![i; 16]), s.get(&[i])?);
+                        }
+                        Ok(())
+                    })
+                })
+                .collect();
+            for i in 100..200_u8 {
+                s.set(&[i], vec![i; 16])?;
+            }
+            s.merge()?;
+            readers.into_iter().try_for_each(|reader| reader.join().unwrap())
+        })?;
+        assert_eq!(200, scan(&s)?.len());
+        Ok(())
+    }
+}
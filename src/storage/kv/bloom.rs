@@ -0,0 +1,182 @@
+use bytes::{Buf, BufMut};
+
+use crate::error::{Error, Result};
+
+/// The default false positive rate of Bloom filters built for on-disk files.
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// A Bloom filter, i.e. a compact set of keys which may report false positives but never false
+/// negatives. Keys are hashed once, and the k bit positions are derived from two halves of the
+/// hash using double hashing: `h1 + i * h2` for i in 0..k.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BloomFilter {
+    /// The bit array.
+    bits: Vec<u8>,
+    /// The number of bits in the bit array.
+    num_bits: u64,
+    /// The number of hash functions.
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for the given number of keys and false positive rate.
+    pub fn new(expected_keys: usize, false_positive_rate: f64) -> Self {
+        let (num_bits, num_hashes) = Self::optimal_size(expected_keys, false_positive_rate);
+        Self { bits: vec![0; num_bits.div_ceil(8) as usize], num_bits, num_hashes }
+    }
+
+    /// Returns a builder which collects keys and sizes the filter for them once built.
+    pub fn builder(false_positive_rate: f64) -> BloomFilterBuilder {
+        BloomFilterBuilder { false_positive_rate, hashes: Vec::new() }
+    }
+
+    /// Computes the optimal number of bits, m = -n ln(p) / ln(2)^2, and of hash functions,
+    /// k = m/n ln(2), for n keys and a false positive rate p.
+    fn optimal_size(expected_keys: usize, false_positive_rate: f64) -> (u64, u32) {
+        let n = expected_keys.max(1) as f64;
+        let p = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let num_bits = (-n * p.ln() / std::f64::consts::LN_2.powi(2)).ceil().max(8.0);
+        let num_hashes = (num_bits / n * std::f64::consts::LN_2).round().clamp(1.0, 30.0);
+        (num_bits as u64, num_hashes as u32)
+    }
+
+    /// Adds a key to the filter.
+    pub fn insert(&mut self, key: &[u8]) {
+        self.insert_hash(hash(key))
+    }
+
+    /// Adds a key hash to the filter.
+    fn insert_hash(&mut self, hash: u64) {
+        for bit in Self::bit_positions(hash, self.num_bits, self.num_hashes) {
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns false if the key is definitely not in the filter, or true if it may be.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        Self::bit_positions(hash(key), self.num_bits, self.num_hashes)
+            .all(|bit| self.bits[(bit / 8) as usize] & 1 << (bit % 8) != 0)
+    }
+
+    /// Returns the bit positions for a key hash.
+    fn bit_positions(hash: u64, num_bits: u64, num_hashes: u32) -> impl Iterator<Item = u64> {
+        let (h1, h2) = (hash & 0xffff_ffff, hash >> 32 | 1);
+        (0..num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    /// Encodes the filter to bytes.
+    ///
+    /// Data alignment:
+    ///
+    /// ```text
+    /// | num_bits (u64) | num_hashes (u32) | bits |
+    /// ```
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(12 + self.bits.len());
+        buffer.put_u64(self.num_bits);
+        buffer.put_u32(self.num_hashes);
+        buffer.put_slice(&self.bits);
+        buffer
+    }
+
+    /// Decodes a filter from bytes.
+    pub fn decode(mut buffer: &[u8]) -> Result<Self> {
+        if buffer.len() < 12 {
+            return Err(Error::Internal("Bloom filter is truncated".into()));
+        }
+        let num_bits = buffer.get_u64();
+        let num_hashes = buffer.get_u32();
+        if num_bits == 0 || buffer.len() as u64 != num_bits.div_ceil(8) {
+            return Err(Error::Internal(format!(
+                "Bloom filter has {} bytes for {} bits",
+                buffer.len(),
+                num_bits
+            )));
+        }
+        Ok(Self { bits: buffer.to_vec(), num_bits, num_hashes })
+    }
+}
+
+/// Builds a Bloom filter sized for the keys added to it.
+pub struct BloomFilterBuilder {
+    /// The target false positive rate.
+    false_positive_rate: f64,
+    /// The hashes of the added keys.
+    hashes: Vec<u64>,
+}
+
+impl BloomFilterBuilder {
+    /// Adds a key to the filter.
+    pub fn add(&mut self, key: &[u8]) {
+        self.hashes.push(hash(key));
+    }
+
+    /// Builds the filter, with the optimal size for the added keys.
+    pub fn build(self) -> BloomFilter {
+        let mut filter = BloomFilter::new(self.hashes.len(), self.false_positive_rate);
+        for hash in self.hashes {
+            filter.insert_hash(hash);
+        }
+        filter
+    }
+}
+
+/// Hashes a key. The hash is persisted along with the filter, so it must be stable: this is the
+/// FxHash word-at-a-time mix, followed by a SplitMix64 finalizer to spread its bits evenly.
+pub(crate) fn hash(key: &[u8]) -> u64 {
+    const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+    let mut hash = key.len() as u64;
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(chunk.try_into().unwrap());
+        hash = (hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+    for &byte in chunks.remainder() {
+        hash = (hash.rotate_left(5) ^ byte as u64).wrapping_mul(SEED);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_false_negatives() -> Result<()> {
+        let mut builder = BloomFilter::builder(0.01);
+        for i in 0..1000_u32 {
+            builder.add(&i.to_be_bytes());
+        }
+        let filter = builder.build();
+        assert!((0..1000_u32).all(|i| filter.may_contain(&i.to_be_bytes())));
+
+        let decoded = BloomFilter::decode(&filter.encode())?;
+        assert_eq!(filter, decoded);
+        assert!((0..1000_u32).all(|i| decoded.may_contain(&i.to_be_bytes())));
+        assert!(BloomFilter::decode(&filter.encode()[..20]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_false_positive_rate() {
+        for rate in [0.1, 0.01, 0.001] {
+            let mut filter = BloomFilter::new(10_000, rate);
+            for i in 0..10_000 {
+                filter.insert(format!("key{}", i).as_bytes());
+            }
+            let queries = 100_000;
+            let false_positives = (0..queries)
+                .filter(|i| filter.may_contain(format!("missing{}", i).as_bytes()))
+                .count();
+            let measured = false_positives as f64 / queries as f64;
+            assert!(
+                (measured - rate).abs() <= 0.2 * rate,
+                "measured false positive rate {} for configured rate {}",
+                measured,
+                rate
+            );
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use parking_lot::{Mutex, RwLock};
+
+use super::lsm_tree::block::Block;
+
+/// The number of recent cache hits buffered before they are applied to the eviction order.
+const READ_BUFFER_SIZE: usize = 64;
+
+/// A node in the LRU cache's eviction list.
+struct Node<K, V> {
+    key: K,
+    value: V,
+    /// The next more recently used node, if any.
+    prev: Option<usize>,
+    /// The next less recently used node, if any.
+    next: Option<usize>,
+}
+
+/// A least-recently-used cache with a fixed capacity. Entries are kept in a slab of nodes linked
+/// in order of use, from the most recently used at the head to the least at the tail, with a map
+/// from keys to their slab position.
+pub struct LruCache<K, V> {
+    /// The maximum number of entries.
+    capacity: usize,
+    /// The slab position of each key.
+    map: HashMap<K, usize>,
+    /// The nodes, where removed ones are None and reused via `free`.
+    nodes: Vec<Option<Node<K, V>>>,
+    /// Free slab positions.
+    free: Vec<usize>,
+    /// The most recently used node.
+    head: Option<usize>,
+    /// The least recently used node.
+    tail: Option<usize>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Creates an empty cache holding at most `capacity` entries, which must be at least 1.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "cache capacity must be positive");
+        Self {
+            capacity,
+            map: HashMap::new(),
+            nodes: Vec::new(),
+            free: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// Returns the number of entries.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Gets a value without marking it as used.
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.map.get(key).map(|&i| &self.node(i).value)
+    }
+
+    /// Gets a value, marking it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        let i = *self.map.get(key)?;
+        self.unlink(i);
+        self.push_front(i);
+        Some(&self.node(i).value)
+    }
+
+    /// Marks a key as the most recently used, if it exists.
+    pub fn touch(&mut self, key: &K) {
+        self.get(key);
+    }
+
+    /// Inserts a value as the most recently used, replacing any existing value for the key.
+    /// Returns the least recently used entry if it was evicted to make room.
+    pub fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if let Some(&i) = self.map.get(&key) {
+            self.nodes[i].as_mut().unwrap().value = value;
+            self.unlink(i);
+            self.push_front(i);
+            return None;
+        }
+        let evicted = match self.map.len() >= self.capacity {
+            true => self.tail.map(|tail| self.remove_node(tail)),
+            false => None,
+        };
+        let node = Node { key: key.clone(), value, prev: None, next: None };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = Some(node);
+                i
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        self.map.insert(key, i);
+        self.push_front(i);
+        evicted
+    }
+
+    /// Removes a key, returning its value if it existed.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = *self.map.get(key)?;
+        Some(self.remove_node(i).1)
+    }
+
+    /// Returns a node, which must exist.
+    fn node(&self, i: usize) -> &Node<K, V> {
+        self.nodes[i].as_ref().unwrap()
+    }
+
+    /// Removes a node from the slab and the eviction list.
+    fn remove_node(&mut self, i: usize) -> (K, V) {
+        self.unlink(i);
+        let node = self.nodes[i].take().unwrap();
+        self.map.remove(&node.key);
+        self.free.push(i);
+        (node.key, node.value)
+    }
+
+    /// Unlinks a node from the eviction list.
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = {
+            let node = self.node(i);
+            (node.prev, node.next)
+        };
+        match prev {
+            Some(prev) => self.nodes[prev].as_mut().unwrap().next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].as_mut().unwrap().prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Links a node in as the most recently used.
+    fn push_front(&mut self, i: usize) {
+        let head = self.head;
+        {
+            let node = self.nodes[i].as_mut().unwrap();
+            node.prev = None;
+            node.next = head;
+        }
+        match head {
+            Some(head) => self.nodes[head].as_mut().unwrap().prev = Some(i),
+            None => self.tail = Some(i),
+        }
+        self.head = Some(i);
+    }
+}
+
+/// Block cache statistics.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// Lookups that found the block in the cache.
+    pub hits: u64,
+    /// Lookups that didn't.
+    pub misses: u64,
+    /// Blocks evicted to make room for others.
+    pub evictions: u64,
+}
+
+/// A cache of decoded storage blocks by page ID, evicting the least recently used ones.
+///
+/// Lookups only take a read lock on the cache, so concurrent readers don't block each other.
+/// Since marking an entry as used needs the write lock, hits are recorded in a small read buffer
+/// instead, which is applied on the next insert, or by a reader once it fills up if the write
+/// lock is free. Hits dropped from a full buffer only make the eviction order approximate.
+#[derive(Clone)]
+pub struct BlockCache {
+    cache: Arc<RwLock<LruCache<u64, Arc<Block>>>>,
+    /// Recent hits not yet applied to the eviction order.
+    read_buffer: Arc<Mutex<Vec<u64>>>,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+    evictions: Arc<AtomicU64>,
+}
+
+impl BlockCache {
+    /// Creates an empty cache holding at most `capacity` blocks.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(LruCache::new(capacity))),
+            read_buffer: Arc::new(Mutex::new(Vec::with_capacity(READ_BUFFER_SIZE))),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+            evictions: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Gets a cached block.
+    pub fn get(&self, page_id: u64) -> Option<Arc<Block>> {
+        let block = self.cache.read().peek(&page_id).cloned();
+        let Some(block) = block else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        let mut read_buffer = self.read_buffer.lock();
+        if read_buffer.len() < READ_BUFFER_SIZE {
+            read_buffer.push(page_id);
+        }
+        if read_buffer.len() >= READ_BUFFER_SIZE {
+            if let Some(mut cache) = self.cache.try_write() {
+                read_buffer.drain(..).for_each(|page_id| cache.touch(&page_id));
+            }
+        }
+        Some(block)
+    }
+
+    /// Caches a block, evicting the least recently used one if the cache is full.
+    pub fn insert(&self, page_id: u64, block: Arc<Block>) {
+        let mut cache = self.cache.write();
+        self.read_buffer.lock().drain(..).for_each(|page_id| cache.touch(&page_id));
+        if cache.insert(page_id, block).is_some() {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Gets a cached block, or reads and caches it.
+    pub fn get_or_insert_with<E>(
+        &self,
+        page_id: u64,
+        read: impl FnOnce() -> std::result::Result<Arc<Block>, E>,
+    ) -> std::result::Result<Arc<Block>, E> {
+        if let Some(block) = self.get(page_id) {
+            return Ok(block);
+        }
+        let block = read()?;
+        self.insert(page_id, block.clone());
+        Ok(block)
+    }
+
+    /// Returns the number of cached blocks.
+    pub fn len(&self) -> usize {
+        self.cache.read().len()
+    }
+
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.cache.read().is_empty()
+    }
+
+    /// Returns the cache statistics.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::lsm_tree::block::BlockBuilder;
+
+    #[test]
+    fn test_lru_eviction_order() {
+        let mut cache = LruCache::new(3);
+        assert_eq!(None, cache.insert(1, "a"));
+        assert_eq!(None, cache.insert(2, "b"));
+        assert_eq!(None, cache.insert(3, "c"));
+        assert_eq!(Some(&"a"), cache.get(&1));
+        assert_eq!(Some((2, "b")), cache.insert(4, "d"));
+        assert_eq!(None, cache.peek(&2));
+
+        // Replacing a value marks it as used, and peeking doesn't.
+        assert_eq!(None, cache.insert(3, "C"));
+        assert_eq!(Some(&"d"), cache.peek(&4));
+        assert_eq!(Some((1, "a")), cache.insert(5, "e"));
+        assert_eq!(Some((4, "d")), cache.insert(6, "f"));
+        assert_eq!(3, cache.len());
+
+        assert_eq!(Some("C"), cache.remove(&3));
+        assert_eq!(None, cache.remove(&3));
+        assert_eq!(None, cache.insert(7, "g"));
+        assert_eq!(Some((5, "e")), cache.insert(8, "h"));
+        let values: Vec<_> = [6, 7, 8].iter().filter_map(|k| cache.peek(k)).collect();
+        assert_eq!(vec![&"f", &"g", &"h"], values);
+    }
+
+    fn block(key: &[u8]) -> Arc<Block> {
+        let mut builder = BlockBuilder::new(64);
+        assert!(builder.add(key, b"value"));
+        Arc::new(builder.build())
+    }
+
+    #[test]
+    fn test_block_cache_stats() {
+        let cache = BlockCache::new(2);
+        assert!(cache.get(1).is_none());
+        cache.insert(1, block(b"a"));
+        cache.insert(2, block(b"b"));
+        assert!(cache.get(1).is_some());
+
+        // The hit on block 1 is applied before inserting block 3, so block 2 is evicted.
+        cache.insert(3, block(b"c"));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert_eq!(CacheStats { hits: 2, misses: 2, evictions: 1 }, cache.stats());
+
+        let read = cache.get_or_insert_with(4, || Ok::<_, ()>(block(b"d"))).unwrap();
+        assert!(Arc::ptr_eq(&read, &cache.get(4).unwrap()));
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn test_block_cache_concurrent_reads() {
+        let cache = BlockCache::new(16);
+        for page_id in 0..16 {
+            cache.insert(page_id, block(&page_id.to_be_bytes()));
+        }
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..1000 {
+                        assert!(cache.get(i % 16).is_some());
+                    }
+                });
+            }
+        });
+        assert_eq!(CacheStats { hits: 4000, misses: 0, evictions: 0 }, cache.stats());
+    }
+}
@@ -0,0 +1,240 @@
+use std::fmt::Display;
+use std::marker::PhantomData;
+
+use super::{KvScan, KvStore, Range, WriteOp};
+use crate::error::{Error, Result};
+
+/// The length of the checksum header prepended to each value.
+const HEADER_LEN: usize = 4;
+
+/// A checksum algorithm used by ChecksumStore.
+pub trait Hasher: Send + Sync {
+    /// Computes the checksum of some bytes.
+    fn checksum(bytes: &[u8]) -> u32;
+}
+
+/// The CRC-32C (Castagnoli) checksum.
+pub struct Crc32c;
+
+impl Hasher for Crc32c {
+    fn checksum(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+}
+
+/// The CRC-32 (IEEE) checksum, as used by the WAL.
+pub struct Crc32;
+
+impl Hasher for Crc32 {
+    fn checksum(bytes: &[u8]) -> u32 {
+        super::wal::crc32(bytes)
+    }
+}
+
+/// A key-value store wrapper which detects corrupted values. Each value in the inner store is
+/// prefixed with its checksum as `[checksum: u32]`, in big-endian, and reads verify it, failing
+/// with Error::Corruption on a mismatch. The checksum algorithm is given by the Hasher, CRC-32C
+/// by default.
+pub struct ChecksumStore<S: KvStore, H: Hasher = Crc32c> {
+    /// The inner store.
+    inner: S,
+    hasher: PhantomData<H>,
+}
+
+impl<S: KvStore, H: Hasher> ChecksumStore<S, H> {
+    /// Wraps a store.
+    pub fn new(inner: S) -> Self {
+        Self { inner, hasher: PhantomData }
+    }
+
+    /// Verifies the checksums of all values, returning the number of verified entries. Fails
+    /// with Error::Corruption at the first corrupted value.
+    pub fn verify_all(&self) -> Result<u64> {
+        let mut count = 0;
+        for item in self.scan(Range::from(..))? {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Gets and verifies a value from the inner store, if it exists.
+    fn get_verified(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key)? {
+            Some(bytes) => Self::decode(key, bytes),
+            None => Ok(None),
+        }
+    }
+
+    /// Encodes a value with its checksum.
+    fn encode(value: Vec<u8>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + value.len());
+        bytes.extend(H::checksum(&value).to_be_bytes());
+        bytes.extend(value);
+        bytes
+    }
+
+    /// Verifies and decodes a value. Tombstones (empty values), which some backends expose, are
+    /// returned as None.
+    fn decode(key: &[u8], mut bytes: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+        if bytes.len() < HEADER_LEN
+            || bytes[..HEADER_LEN] != H::checksum(&bytes[HEADER_LEN..]).to_be_bytes()
+        {
+            return Err(Error::Corruption(format!("CRC mismatch for key: {:?}", key)));
+        }
+        bytes.drain(..HEADER_LEN);
+        Ok(Some(bytes))
+    }
+}
+
+impl<S: KvStore, H: Hasher> Display for ChecksumStore<S, H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "checksum({})", self.inner)
+    }
+}
+
+impl<S: KvStore, H: Hasher> KvStore for ChecksumStore<S, H> {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.inner.set(key, Self::encode(value))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_verified(key)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        Ok(Box::new(self.inner.scan(range)?.filter_map(|item| {
+            let (key, value) = match item {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err)),
+            };
+            match Self::decode(&key, value) {
+                Ok(Some(value)) => Some(Ok((key, value))),
+                Ok(None) => None,
+                Err(err) => Some(Err(err)),
+            }
+        })))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    /// The checksum only covers the value, so the inner store can move it as is.
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        self.inner.rename(old_key, new_key)
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        self.inner.rename_if_absent(old_key, new_key)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let expected = expected.map(|value| Self::encode(value.to_vec()));
+        self.inner.compare_and_swap(key, expected.as_deref(), Self::encode(new_value))
+    }
+
+    /// Deletes the range in the inner store, without verifying the deleted values.
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        self.inner.delete_range(range)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        self.inner.write_batch(
+            ops.into_iter()
+                .map(|op| match op {
+                    WriteOp::Put { key, value } => WriteOp::Put { key, value: Self::encode(value) },
+                    op => op,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<ChecksumStore<super::StdBPlusTree>> for ChecksumStore<super::StdBPlusTree> {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        Ok((ChecksumStore::new(super::StdBPlusTree::new()), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::StdBPlusTree;
+    use super::*;
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        ChecksumStore::<StdBPlusTree>::test()
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(0, Crc32c::checksum(b""));
+        assert_eq!(0xe306_9283, Crc32c::checksum(b"123456789"));
+        assert_eq!(0xcbf4_3926, Crc32::checksum(b"123456789"));
+    }
+
+    #[test]
+    fn test_corruption() -> Result<()> {
+        let s: ChecksumStore<StdBPlusTree> = ChecksumStore::new(StdBPlusTree::new());
+        for i in 0..10_u8 {
+            s.set(&[i], vec![i; 8])?;
+        }
+        assert_eq!(10, s.verify_all()?);
+
+        // Flip a byte in a stored value, which fails its checksum on reads.
+        let mut bytes = s.inner.get(&[5])?.unwrap();
+        assert_eq!(Crc32c::checksum(&[5; 8]).to_be_bytes(), bytes[..HEADER_LEN]);
+        bytes[6] ^= 0x01;
+        s.inner.set(&[5], bytes)?;
+
+        assert!(matches!(s.get(&[5]), Err(Error::Corruption(_))));
+        assert_eq!(Some(vec![4; 8]), s.get(&[4])?);
+        assert!(matches!(s.verify_all(), Err(Error::Corruption(_))));
+        let scan = s.scan(Range::from(..))?.collect::<Vec<_>>();
+        assert_eq!(10, scan.len());
+        assert!(matches!(scan[5], Err(Error::Corruption(_))));
+
+        // A value too short to hold a checksum is corrupt too.
+        s.inner.set(&[6], vec![0x01])?;
+        assert!(matches!(s.get(&[6]), Err(Error::Corruption(_))));
+
+        // Rewriting or deleting a corrupted key repairs it.
+        s.set(&[5], vec![5])?;
+        s.delete(&[6])?;
+        assert_eq!(9, s.verify_all()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_hasher() -> Result<()> {
+        let s: ChecksumStore<StdBPlusTree, Crc32> = ChecksumStore::new(StdBPlusTree::new());
+        s.set(b"a", vec![0x01, 0x02])?;
+        assert_eq!(Crc32::checksum(&[0x01, 0x02]).to_be_bytes(), s.inner.get(b"a")?.unwrap()[..4]);
+        assert_eq!(Some(vec![0x01, 0x02]), s.get(b"a")?);
+        assert!(s.compare_and_swap(b"a", Some(&[0x01, 0x02]), vec![0x03])?);
+        assert_eq!(Some(vec![0x03]), s.get(b"a")?);
+        Ok(())
+    }
+}
@@ -1,18 +1,20 @@
 use std::fmt::Display;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use parking_lot::{RwLock, Mutex};
+use serde_derive::{Deserialize, Serialize};
 
-use crate::error::Result;
-use super::super::{KvStore, Range, KvScan};
-use super::block::Block;
-use super::iterators::{MergeIter, TwoMergeIter};
+use crate::error::{Error, Result};
+use super::super::{KvStore, Range, KvScan, StorageStats, WriteOp};
+use super::super::cache::{BlockCache, CacheStats};
+use super::iterators::{MergeIter, StorageIter, TwoMergeIter};
 use super::lsm_iterator::LsmIter;
 use super::memtable::MemTable;
-use super::sstable::{SsTable, SsTableBuilder, SsTableIter};
-
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
+use super::sstable::{FileObject, SsTable, SsTableBuilder, SsTableIter};
+use super::wal::WalLog;
 
 #[derive(Clone)]
 pub struct LsmStorageInner {
@@ -23,7 +25,6 @@ pub struct LsmStorageInner {
     /// L0 SsTables, from earliest to latest.
     l0_sstables: Vec<Arc<SsTable>>,
     /// L1 - L6 SsTables, sorted by key range.
-    #[allow(dead_code)]
     levels: Vec<Vec<Arc<SsTable>>>,
     /// The next SSTable ID.
     next_sst_id: usize,
@@ -39,6 +40,76 @@ impl LsmStorageInner {
             next_sst_id: 1,
         }
     }
+
+    /// Returns all SsTables from newest to oldest: L0 in reverse order, then each level in turn.
+    fn sstables(&self) -> impl Iterator<Item = &Arc<SsTable>> {
+        self.l0_sstables.iter().rev().chain(self.levels.iter().flatten())
+    }
+
+    /// Gets a value for a key, searching the memtables and then the SsTables.
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        // Search in the current memtable.
+        if let Some(value) = self.memtable.get(key) {
+            match value.is_empty() {
+                true => return Ok(None),
+                false => return Ok(Some(value)),
+            }
+        }
+
+        // Search in immutable memtables.
+        for memtable in self.imm_memtables.iter().rev() {
+            if let Some(value) = memtable.get(key) {
+                match value.is_empty() {
+                    true => return Ok(None),
+                    false => return Ok(Some(value)),
+                }
+            }
+        }
+
+        // Search in SsTables, from newest to oldest, skipping those whose Bloom filter rules out
+        // the key.
+        let mut sstable_iters = vec![];
+        for sstable in self.sstables().filter(|sstable| sstable.may_contain(key)) {
+            sstable_iters.push(Box::new(
+                SsTableIter::create_and_seek_to_key(sstable.clone(), key, true)?
+            ));
+        }
+        let mut merge_iter = MergeIter::create(sstable_iters)?;
+        match merge_iter.next().transpose()? {
+            // An empty value is a deletion tombstone.
+            Some((result_key, value)) if result_key == key => {
+                if value.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(value))
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The SsTable IDs of each level, persisted in the MANIFEST file whenever the tables change.
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    /// L0 SsTable IDs, from earliest to latest.
+    l0_sstables: Vec<usize>,
+    /// L1 - L6 SsTable IDs, sorted by key range.
+    levels: Vec<Vec<usize>>,
+}
+
+impl Manifest {
+    /// Builds a manifest of the given storage state's SsTables.
+    fn from_inner(inner: &LsmStorageInner) -> Self {
+        Self {
+            l0_sstables: inner.l0_sstables.iter().map(|sstable| sstable.id()).collect(),
+            levels: inner
+                .levels
+                .iter()
+                .map(|level| level.iter().map(|sstable| sstable.id()).collect())
+                .collect(),
+        }
+    }
 }
 
 /// The storage interface of the LSM tree.
@@ -47,16 +118,85 @@ pub struct LsmStorage {
     flush_lock: Mutex<()>,
     path: PathBuf,
     block_cache: Arc<BlockCache>,
+    /// The write-ahead log of writes not yet flushed to SsTables.
+    wal: Mutex<WalLog>,
 }
 
 impl LsmStorage {
+    /// The number of levels below L0.
+    pub const MAX_LEVELS: usize = 6;
+    /// The size at which compaction starts a new SsTable in the output level.
+    const TARGET_SSTABLE_SIZE: usize = 1 << 20;
+    /// The number of SsTable blocks kept in the block cache, i.e. 4 GB of 4 KB blocks.
+    const BLOCK_CACHE_CAPACITY: usize = 1 << 20;
+
+    /// Opens the storage in the given directory, loading the SsTables listed in the manifest and
+    /// replaying unflushed writes from the WAL into the memtable.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        Ok(Self {
+        let path = path.as_ref().to_path_buf();
+        std::fs::create_dir_all(&path)?;
+        let mut storage = Self {
             inner: Arc::new(RwLock::new(Arc::new(LsmStorageInner::create()))),
             flush_lock: Mutex::new(()),
-            path: path.as_ref().to_path_buf(),
-            block_cache: Arc::new(BlockCache::new(1 << 20)), // 4GB block cache
-        })
+            wal: Mutex::new(WalLog::open(&path)?),
+            path,
+            block_cache: Arc::new(BlockCache::new(Self::BLOCK_CACHE_CAPACITY)),
+        };
+        let mut inner = LsmStorageInner::create();
+
+        // Load the SsTables, and remove any left behind by an interrupted flush or compaction.
+        let manifest = storage.read_manifest()?;
+        let open = |id| -> Result<Arc<SsTable>> {
+            let file = FileObject::open(&storage.sstable_path(id))?;
+            Ok(Arc::new(SsTable::open(id, Some(storage.block_cache.clone()), file)?))
+        };
+        inner.l0_sstables = manifest.l0_sstables.iter().copied().map(open).collect::<Result<_>>()?;
+        inner.levels = manifest
+            .levels
+            .iter()
+            .map(|level| level.iter().copied().map(open).collect::<Result<_>>())
+            .collect::<Result<_>>()?;
+        inner.next_sst_id = inner.sstables().map(|sstable| sstable.id() + 1).max().unwrap_or(1);
+        for entry in std::fs::read_dir(&storage.path)? {
+            let path = entry?.path();
+            let id = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".sst"))
+                .and_then(|id| id.parse::<usize>().ok());
+            if id.is_some_and(|id| inner.sstables().all(|sstable| sstable.id() != id)) {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        // Recover unflushed writes into the memtable.
+        for (key, value) in WalLog::replay(&storage.path)? {
+            inner.memtable.set(&key, value);
+        }
+
+        storage.inner = Arc::new(RwLock::new(Arc::new(inner)));
+        Ok(storage)
+    }
+
+    /// Reads the manifest, or returns an empty one if there is none.
+    fn read_manifest(&self) -> Result<Manifest> {
+        match std::fs::read(self.path.join("MANIFEST")) {
+            Ok(data) => Ok(bincode::deserialize(&data)?),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Manifest::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Atomically replaces the manifest with the given storage state's SsTables. Must be called
+    /// before removing SsTable files or the WAL segments of flushed memtables.
+    fn write_manifest(&self, inner: &LsmStorageInner) -> Result<()> {
+        let tmp_path = self.path.join("MANIFEST.tmp");
+        let mut tmp = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        tmp.write_all(&bincode::serialize(&Manifest::from_inner(inner))?)?;
+        tmp.sync_all()?;
+        drop(tmp);
+        std::fs::rename(&tmp_path, self.path.join("MANIFEST"))?;
+        Ok(())
     }
 }
 
@@ -66,6 +206,7 @@ impl KvStore for LsmStorage {
         assert!(!value.is_empty(), "value cannot be empty");
 
         let session = self.inner.read();
+        self.wal.lock().append(key, &value)?;
         session.memtable.set(key, value);
 
         Ok(())
@@ -76,58 +217,86 @@ impl KvStore for LsmStorage {
             let session = self.inner.read();
             Arc::clone(&session)
         };
+        snapshot.get(key)
+    }
 
-        // Search in the current memtable.
-        if let Some(value) = snapshot.memtable.get(key) {
-            match value.is_empty() {
-                true => return Ok(None),
-                false => return Ok(Some(value)),
-            }
-        }
-
-        // Search in immutable memtables.
-        for memtable in snapshot.imm_memtables.iter().rev() {
-            if let Some(value) = memtable.get(key) {
-                match value.is_empty() {
-                    true => return Ok(None),
-                    false => return Ok(Some(value)),
-                }
-            }
-        }
-
-        // Search in SsTables.
-        let mut sstable_iters = vec![];
-        sstable_iters.reserve(snapshot.l0_sstables.len());
-        for sstable in snapshot.l0_sstables.iter().rev() {
-            sstable_iters.push(Box::new(
-                SsTableIter::create_and_seek_to_key(sstable.clone(), key, true)?
-            ));
-        }
-        let mut merge_iter = MergeIter::create(sstable_iters)?;
-        match merge_iter.next().transpose()? {
-            None => Ok(None),
-            Some((result_key, value)) => {
-                match key == result_key {
-                    true => match value.is_empty() {
-                        true => return Ok(None),
-                        false => return Ok(Some(value)),
-                    },
-                    false => Ok(None),
-                }
-            },
+    /// Looks up all keys in the same snapshot, in key order for locality.
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let snapshot = {
+            let session = self.inner.read();
+            Arc::clone(&session)
+        };
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|i| keys[*i]);
+        let mut values = vec![None; keys.len()];
+        for i in order {
+            values[i] = snapshot.get(keys[i])?;
         }
+        Ok(values)
     }
 
     fn delete(&self, key: &[u8]) -> Result<()> {
         assert!(!key.is_empty(), "key cannot be empty");
         
         let session = self.inner.read();
+        self.wal.lock().append(key, &[])?;
         session.memtable.set(key, vec![]);
 
         Ok(())
     }
 
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        // Deletes are written as empty tombstone values, like delete().
+        let entries = ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Put { key, value } => {
+                    assert!(!value.is_empty(), "value cannot be empty");
+                    (key, value)
+                }
+                WriteOp::Delete { key } => (key, vec![]),
+            })
+            .inspect(|(key, _)| assert!(!key.is_empty(), "key cannot be empty"))
+            .collect::<Vec<_>>();
+
+        let session = self.inner.read();
+        self.wal.lock().append_batch(
+            &entries.iter().map(|(key, value)| (&key[..], &value[..])).collect::<Vec<_>>(),
+        )?;
+        session.memtable.set_batch(entries);
+
+        Ok(())
+    }
+
+    /// Writes tombstones for the range's keys as a single batch, so readers observe either none
+    /// or all of the deletes. Keys written concurrently with the scan may survive.
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        let ops = self
+            .scan(range)?
+            .map(|r| r.map(|(key, _)| WriteOp::Delete { key }))
+            .collect::<Result<Vec<_>>>()?;
+        let count = ops.len() as u64;
+        self.write_batch(ops)?;
+        Ok(count)
+    }
+
+    /// Scans the store for key and value statistics, and sums the sizes of the SsTable and WAL
+    /// files in the storage directory.
+    fn stats(&self) -> Result<StorageStats> {
+        let mut stats = StorageStats::from_scan(self.scan(Range::from(..))?)?;
+        for entry in std::fs::read_dir(&self.path)? {
+            let metadata = entry?.metadata()?;
+            if metadata.is_file() {
+                stats.disk_bytes += metadata.len();
+            }
+        }
+        Ok(stats)
+    }
+
     fn scan(&self, range: Range) -> Result<KvScan> {
+        if range.is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
         let snapshot = {
             let session = self.inner.read();
             Arc::clone(&session)
@@ -142,8 +311,7 @@ impl KvStore for LsmStorage {
         let memtable_merge_iter = MergeIter::create(memtable_iters)?;
 
         let mut sstable_iters = vec![];
-        sstable_iters.reserve(snapshot.l0_sstables.len());
-        for sstable in snapshot.l0_sstables.iter().rev() {
+        for sstable in snapshot.sstables() {
             sstable_iters.push(Box::new(SsTableIter::create(sstable.clone(), range.clone())?));
         }
         let sstable_merge_iter = MergeIter::create(sstable_iters)?;
@@ -160,6 +328,7 @@ impl KvStore for LsmStorage {
 
         let memtable_to_flush;
         let sstable_id;
+        let checkpoint;
 
         // Move mutable memtable to immutable memtables.
         {
@@ -174,6 +343,9 @@ impl KvStore for LsmStorage {
             memtable_to_flush = memtable.clone();
             sstable_id = snapshot.next_sst_id;
 
+            // Writes to the new memtable go to a new WAL segment.
+            checkpoint = self.wal.lock().rotate()?;
+
             // Add the memtable to the immutable memtables.
             snapshot.imm_memtables.push(memtable);
 
@@ -187,11 +359,7 @@ impl KvStore for LsmStorage {
 
         let mut sstable_builder = SsTableBuilder::new(4096);
         memtable_to_flush.flush(&mut sstable_builder)?;
-        let sstable = Arc::new(sstable_builder.build(
-            sstable_id, 
-            Some(self.block_cache.clone()), 
-            self.path.join(format!("{:05}.sst", sstable_id)),
-        )?);
+        let sstable = self.build_sstable(sstable_builder, sstable_id)?;
 
         // Add the flushed L0 table to the list.
         {
@@ -203,16 +371,133 @@ impl KvStore for LsmStorage {
             snapshot.l0_sstables.push(sstable);
             // Update SST ID
             snapshot.next_sst_id += 1;
+            // Record the new table, so it is loaded on restart.
+            self.write_manifest(&snapshot)?;
             // Update the snapshot.
             *session = Arc::new(snapshot);
         }
 
+        // The flushed memtable's WAL segments are no longer needed, now that its SsTable is in the
+        // manifest.
+        self.wal.lock().checkpoint(checkpoint)?;
+
+        Ok(())
+    }
+}
+
+impl LsmStorage {
+    /// Compacts the given level into the next one. Level 0 merges all (possibly overlapping) L0
+    /// SsTables into L1, and level n merges Ln into Ln+1. The output is rewritten as a sorted run
+    /// of non-overlapping SsTables, keeping only the newest value of each key. Tombstones are
+    /// dropped when there are no older levels left that they could shadow.
+    pub fn compact(&self, level: usize) -> Result<()> {
+        if level >= Self::MAX_LEVELS {
+            return Err(Error::Value(format!(
+                "Can't compact level {}, max is {}",
+                level,
+                Self::MAX_LEVELS - 1
+            )));
+        }
+        // Flushes add L0 SsTables, so hold them off until the compacted tables are swapped in.
+        let _flush_guard = self.flush_lock.lock();
+        let snapshot = {
+            let session = self.inner.read();
+            Arc::clone(&session)
+        };
+
+        // Input tables, from newest to oldest. Levels are zero-indexed from L1.
+        let upper: Vec<Arc<SsTable>> = match level {
+            0 => snapshot.l0_sstables.iter().rev().cloned().collect(),
+            _ => snapshot.levels.get(level - 1).cloned().unwrap_or_default(),
+        };
+        let lower = snapshot.levels.get(level).cloned().unwrap_or_default();
+        if upper.is_empty() {
+            return Ok(());
+        }
+        let bottom = snapshot.levels.iter().skip(level + 1).all(|tables| tables.is_empty());
+
+        let mut iters = vec![];
+        for sstable in upper.iter().chain(lower.iter()) {
+            iters.push(Box::new(SsTableIter::create(sstable.clone(), Range::from(..))?));
+        }
+        let mut merge_iter = MergeIter::create(iters)?;
+        let mut next_sst_id = snapshot.next_sst_id;
+        let mut output = vec![];
+        let mut builder = SsTableBuilder::new(4096);
+        let mut is_empty = true;
+        while let Some((key, value)) = merge_iter.try_next()? {
+            if bottom && value.is_empty() {
+                continue;
+            }
+            builder.add(&key, &value);
+            is_empty = false;
+            if builder.estimated_size() >= Self::TARGET_SSTABLE_SIZE {
+                output.push(self.build_sstable(builder, next_sst_id)?);
+                next_sst_id += 1;
+                builder = SsTableBuilder::new(4096);
+                is_empty = true;
+            }
+        }
+        if !is_empty {
+            output.push(self.build_sstable(builder, next_sst_id)?);
+            next_sst_id += 1;
+        }
+
+        // Swap in the compacted level.
+        {
+            let mut session = self.inner.write();
+            let mut snapshot = session.as_ref().clone();
+            if snapshot.levels.len() <= level {
+                snapshot.levels.resize(level + 1, vec![]);
+            }
+            match level {
+                0 => snapshot.l0_sstables.clear(),
+                _ => snapshot.levels[level - 1].clear(),
+            }
+            snapshot.levels[level] = output;
+            snapshot.next_sst_id = next_sst_id;
+            self.write_manifest(&snapshot)?;
+            *session = Arc::new(snapshot);
+        }
+
+        // Readers may still hold the old tables, but their open files remain readable.
+        for sstable in upper.iter().chain(lower.iter()) {
+            std::fs::remove_file(self.sstable_path(sstable.id()))?;
+        }
         Ok(())
     }
+
+    /// Returns the block cache statistics.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.block_cache.stats()
+    }
+
+    /// Builds an SsTable with the given ID in the storage directory.
+    fn build_sstable(&self, builder: SsTableBuilder, id: usize) -> Result<Arc<SsTable>> {
+        Ok(Arc::new(builder.build(id, Some(self.block_cache.clone()), self.sstable_path(id))?))
+    }
+
+    /// Returns the file path of the SsTable with the given ID.
+    fn sstable_path(&self, id: usize) -> PathBuf {
+        self.path.join(format!("{:05}.sst", id))
+    }
 }
 
 impl Display for LsmStorage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "LsmStorage")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl super::super::TestSuite<LsmStorage> for LsmStorage {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        let dir = tempfile::tempdir()?;
+        Ok((LsmStorage::open(dir.path())?, Some(dir)))
+    }
+}
+
+#[test]
+fn tests() -> Result<()> {
+    use super::super::TestSuite;
+    LsmStorage::test()
+}
@@ -1,137 +1,149 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
 use std::sync::Arc;
 
-use crossbeam_skiplist::SkipMap;
-use crossbeam_skiplist::map::Entry;
-use ouroboros::self_referencing;
+use parking_lot::RwLock;
 
 use crate::error::Result;
 use crate::storage::kv::Range;
 use super::iterators::StorageIter;
 use super::sstable::SsTableBuilder;
 
-/// A basic mem-table based on crossbeam-skiplist
+/// A basic mem-table based on a B-tree behind a read-write lock, so that any number of readers
+/// can proceed concurrently while writers take exclusive access. The B-tree is shared
+/// copy-on-write with open scans, which iterate over it as of when they were created.
 pub struct MemTable {
-    map: Arc<SkipMap<Vec<u8>, Vec<u8>>>,
+    map: RwLock<Arc<BTreeMap<Vec<u8>, Vec<u8>>>>,
 }
 
 impl MemTable {
     /// Create a new mem-table.
     pub fn create() -> Self {
-        Self { map: Arc::new(SkipMap::new()) }
+        Self { map: RwLock::new(Arc::new(BTreeMap::new())) }
     }
 
     /// Get a value by key.
     pub fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.map.get(key).map(|entry| entry.value().clone())
+        self.map.read().get(key).cloned()
     }
 
-    /// Put a key-value pair into the mem-table.
+    /// Put a key-value pair into the mem-table. If scans are open, the B-tree is copied first.
     pub fn set(&self, key: &[u8], value: Vec<u8>) {
-        self.map.insert(key.to_vec(), value);
+        Arc::make_mut(&mut self.map.write()).insert(key.to_vec(), value);
     }
 
-    /// Get an iterator over a range of keys.
+    /// Put a batch of key-value pairs into the mem-table, under a single write lock.
+    pub fn set_batch(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) {
+        Arc::make_mut(&mut self.map.write()).extend(entries);
+    }
+
+    /// Get an iterator over a range of keys, as of now.
     pub fn scan(&self, bound: Range) -> MemTableIter {
-        MemTableIter::create(self.map.clone(), bound)
+        MemTableIter::create(self.map.read().clone(), bound)
     }
 
     /// Flush the mem-table to SSTable.
     pub fn flush(&self, builder: &mut SsTableBuilder) -> Result<()> {
-        for entry in self.map.iter() {
-            builder.add(&entry.key()[..], &entry.value()[..]);
+        // Build from a snapshot, so readers and writers aren't held off while building.
+        let map = self.map.read().clone();
+        for (key, value) in map.iter() {
+            builder.add(&key[..], &value[..]);
         }
         Ok(())
     }
 }
 
-type SkipMapRangeIter<'a> =
-    crossbeam_skiplist::map::Range<'a, Vec<u8>, Range, Vec<u8>, Vec<u8>>;
-
-/// An iterator over a range of `SkipMap`.
-#[self_referencing]
+/// An iterator over a range of a mem-table snapshot. Holding the read lock for the lifetime of
+/// the iterator would block writers until the scan is dropped, so it holds the B-tree instead,
+/// and fetches entries as it advances by narrowing the remaining range past those returned at
+/// either end.
+#[derive(Clone)]
 pub struct MemTableIter {
-    map: Arc<SkipMap<Vec<u8>, Vec<u8>>>,
-    #[borrows(map)]
-    #[not_covariant]
-    iter: SkipMapRangeIter<'this>,
-    bound: Range,
+    map: Arc<BTreeMap<Vec<u8>, Vec<u8>>>,
+    start: Bound<Vec<u8>>,
+    end: Bound<Vec<u8>>,
     front_entry: Option<(Vec<u8>, Vec<u8>)>,
     back_entry: Option<(Vec<u8>, Vec<u8>)>,
     is_valid: bool,
 }
 
-impl Clone for MemTableIter {
-    fn clone(&self) -> Self {
-        let mut other = Self::create( 
-            self.borrow_map().clone(), self.borrow_bound().clone()
-        );
-        while other.front_entry() != self.front_entry() {
-            other.try_next().unwrap();
-        }
-        while other.back_entry() != self.back_entry() {
-            other.try_next_back().unwrap();
-        }
-        other
-    }
-
-    fn clone_from(&mut self, _source: &Self) {
-        unimplemented!()
-    }
-}
-
 impl MemTableIter {
-    fn create(map: Arc<SkipMap<Vec<u8>, Vec<u8>>>, bound: Range) -> Self {
-        let mut mem_table_iter = MemTableIterBuilder {
-            map: map.clone(),
-            iter_builder: |map| map.range(bound.clone()),
-            bound: bound.clone(),
+    fn create(map: Arc<BTreeMap<Vec<u8>, Vec<u8>>>, bound: Range) -> Self {
+        let mut iter = Self {
+            map,
+            start: bound.start_bound().cloned(),
+            end: bound.end_bound().cloned(),
             front_entry: None,
             back_entry: None,
             is_valid: false,
-        }.build();
-        mem_table_iter.with_mut(|this| *this.is_valid = map.range(bound).next().is_some());
-        mem_table_iter
+        };
+        iter.is_valid = !iter.is_exhausted() && iter.map.range(iter.bounds()).next().is_some();
+        iter
+    }
+
+    /// The remaining range.
+    fn bounds(&self) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+        (self.start.clone(), self.end.clone())
+    }
+
+    /// Returns true if the remaining range is empty, which BTreeMap::range() panics on when the
+    /// bounds cross or are equal and excluded.
+    fn is_exhausted(&self) -> bool {
+        match (&self.start, &self.end) {
+            (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Included(end))
+            | (Bound::Included(start), Bound::Excluded(end)) => start > end,
+            _ => false,
+        }
     }
 
-    fn entry_to_item(entry: Option<Entry<'_, Vec<u8>, Vec<u8>>>) -> Option<(Vec<u8>, Vec<u8>)> {
+    /// Fetches the next entry from the front or back of the remaining range, and excludes it
+    /// from the range.
+    fn next_entry(&mut self, back: bool) -> Option<(Vec<u8>, Vec<u8>)> {
+        if self.is_exhausted() {
+            return None;
+        }
+        let mut range = self.map.range(self.bounds());
+        let entry = if back { range.next_back() } else { range.next() };
+        let entry = entry.map(|(k, v)| (k.clone(), v.clone()));
+        if let Some((key, _)) = &entry {
+            match back {
+                true => self.end = Bound::Excluded(key.clone()),
+                false => self.start = Bound::Excluded(key.clone()),
+            }
+        }
         entry
-            .map(|e| Some((e.key().clone(), e.value().clone())))
-            .unwrap_or_else(|| None)
     }
 }
 
 impl StorageIter for MemTableIter {
     fn front_entry(&self) -> Option<(Vec<u8>, Vec<u8>)> {
-        self.borrow_front_entry().clone()
+        self.front_entry.clone()
     }
 
     fn back_entry(&self) -> Option<(Vec<u8>, Vec<u8>)> {
-        self.borrow_back_entry().clone()
+        self.back_entry.clone()
     }
 
     // Sematic change: contains key.
     fn is_valid(&self) -> bool {
-        self.borrow_is_valid().clone()
+        self.is_valid
     }
 
     fn try_next(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        let entry = self.with_iter_mut(
-            |iter| MemTableIter::entry_to_item(iter.next())
-        );
-        self.with_mut(|this| *this.front_entry = entry.clone());
+        let entry = self.next_entry(false);
+        self.front_entry = entry.clone();
         if entry.is_none() {
-            self.with_mut(|this| *this.is_valid = false);
+            self.is_valid = false;
         }
         Ok(entry)
     }
 
     fn try_next_back(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        let entry = self.with_iter_mut(
-            |iter| MemTableIter::entry_to_item(iter.next_back())
-        );
-        self.with_mut(|this| *this.back_entry = entry.clone());
+        let entry = self.next_entry(true);
+        self.back_entry = entry.clone();
         if entry.is_none() {
-            self.with_mut(|this| *this.is_valid = false);
+            self.is_valid = false;
         }
         Ok(entry)
     }
@@ -253,4 +265,52 @@ fn test_memtable_iter() {
         iter.next();
         assert!(!iter.is_valid());
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_memtable_concurrent_scan() {
+    let memtable = Arc::new(MemTable::create());
+    for i in 0..100_u32 {
+        memtable.set(&i.to_be_bytes(), i.to_be_bytes().to_vec());
+    }
+
+    // Keep scans open on several threads while a writer inserts, which must not block on them.
+    let scans = (0..8)
+        .map(|_| {
+            let memtable = memtable.clone();
+            std::thread::spawn(move || {
+                let mut iter = memtable.scan(Range::from(..));
+                let first = iter.next().unwrap().unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                let rest = iter.collect::<Result<Vec<_>>>().unwrap();
+                (first, rest.len())
+            })
+        })
+        .collect::<Vec<_>>();
+    for i in 100..200_u32 {
+        memtable.set(&i.to_be_bytes(), i.to_be_bytes().to_vec());
+    }
+    for scan in scans {
+        let (first, rest) = scan.join().unwrap();
+        assert_eq!(first.0, 0_u32.to_be_bytes().to_vec());
+        assert!(rest >= 99);
+    }
+    assert_eq!(memtable.scan(Range::from(..)).count(), 200);
+}
+
+#[test]
+fn test_memtable_scan_snapshot() {
+    let memtable = MemTable::create();
+    memtable.set(b"key1", b"value1".to_vec());
+    memtable.set(b"key3", b"value3".to_vec());
+
+    // Writes during a scan aren't seen by it, but are by later scans.
+    let mut iter = memtable.scan(Range::from(..));
+    assert_eq!(iter.next().unwrap().unwrap().0, b"key1");
+    memtable.set(b"key2", b"value2".to_vec());
+    memtable.set(b"key4", b"value4".to_vec());
+    assert_eq!(iter.next_back().unwrap().unwrap().0, b"key3");
+    assert!(iter.next().is_none());
+    assert!(!iter.is_valid());
+    assert_eq!(memtable.scan(Range::from(..)).count(), 4);
+}
@@ -4,4 +4,5 @@ pub mod lsm_storage;
 pub mod lsm_iterator;
 pub mod iterators;
 pub mod memtable;
+pub mod wal;
 pub mod tests;
\ No newline at end of file
@@ -5,11 +5,12 @@ use std::sync::Arc;
 
 use bytes::{Buf, Bytes, BufMut};
 
-use crate::error::{Result, Error};
+use crate::error::Result;
 use crate::storage::kv::Range;
+use crate::storage::kv::bloom::{BloomFilter, BloomFilterBuilder, DEFAULT_FALSE_POSITIVE_RATE};
 use super::block::{Block, BlockBuilder, BlockIter};
 use super::iterators::StorageIter;
-use super::lsm_storage::BlockCache;
+use crate::storage::kv::cache::BlockCache;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
@@ -76,8 +77,11 @@ impl FileObject {
         Ok(data)
     }
 
-    pub fn open(_path: &Path) -> Result<Self> {
-        unimplemented!()
+    /// Opens an existing file for reading.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::options().read(true).write(false).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(FileObject(file, size))
     }
 
     pub fn size(&self) -> u64 {
@@ -91,6 +95,8 @@ pub struct SsTable {
     block_metas: Vec<BlockMeta>,
     block_meta_offset: usize,
     block_cache: Option<Arc<BlockCache>>,
+    /// A Bloom filter of the table's keys, to skip reading it for missing keys.
+    bloom: BloomFilter,
 }
 
 impl SsTable {
@@ -103,23 +109,34 @@ impl SsTable {
     /// 
     /// Data alignment: 
     /// 
-    ///     | data block | data block | ... | data block | meta block | meta block offset (u32) |
-    /// 
+    /// ```text
+    /// | data block | ... | data block | meta block | bloom filter | meta block offset (u32) |
+    /// | bloom filter offset (u32) |
+    /// ```
     pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
         let file_len = file.size();
-        let meta_offset_raw = file.read(file_len - 4, 4)?;
-        let block_meta_offset = (&meta_offset_raw[..]).get_u32() as u64;
-        let meta_raw = file.read(block_meta_offset, file_len - 4 - block_meta_offset)?;
+        let offsets_raw = file.read(file_len - 8, 8)?;
+        let mut offsets = &offsets_raw[..];
+        let block_meta_offset = offsets.get_u32() as u64;
+        let bloom_offset = offsets.get_u32() as u64;
+        let meta_raw = file.read(block_meta_offset, bloom_offset - block_meta_offset)?;
         let block_metas = BlockMeta::decode_block_meta(&meta_raw[..]);
+        let bloom = BloomFilter::decode(&file.read(bloom_offset, file_len - 8 - bloom_offset)?)?;
         Ok(Self {
             id,
             file,
             block_metas,
             block_meta_offset: block_meta_offset as usize,
             block_cache,
+            bloom,
         })
     }
 
+    /// Get the SSTable ID.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
     /// Read a block from the disk.
     pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
         let block_offset = self.block_metas[block_idx].offset;
@@ -135,10 +152,8 @@ impl SsTable {
     /// Read a block from disk, with block cache. (Day 4)
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
         if let Some(ref block_cache) = self.block_cache {
-            let blk = block_cache
-                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
-                .map_err(|e| Error::Internal(e.to_string()))?;
-            Ok(blk)
+            let page_id = (self.id as u64) << 32 | block_idx as u64;
+            block_cache.get_or_insert_with(page_id, || self.read_block(block_idx))
         } else {
             self.read_block(block_idx)
         }
@@ -162,6 +177,11 @@ impl SsTable {
     pub fn num_of_blocks(&self) -> usize {
         self.block_metas.len()
     }
+
+    /// Returns false if the table definitely doesn't contain the key, or true if it may.
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bloom.may_contain(key)
+    }
 }
 
 /// Builds an SSTable from key-value pairs.
@@ -171,17 +191,24 @@ pub struct SsTableBuilder {
     cur_block_first_key: Vec<u8>,
     block_builder: BlockBuilder,
     block_size: usize,
+    bloom: BloomFilterBuilder,
 }
 
 impl SsTableBuilder {
     /// Create a builder based on target block size.
     pub fn new(block_size: usize) -> Self {
+        Self::with_false_positive_rate(block_size, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    /// Create a builder based on target block size and Bloom filter false positive rate.
+    pub fn with_false_positive_rate(block_size: usize, false_positive_rate: f64) -> Self {
         Self {
             meta: Vec::new(),
             data: Vec::new(),
             cur_block_first_key: Vec::new(),
             block_builder: BlockBuilder::new(block_size),
             block_size,
+            bloom: BloomFilter::builder(false_positive_rate),
         }
     }
 
@@ -190,6 +217,7 @@ impl SsTableBuilder {
         if self.cur_block_first_key.is_empty() {
             self.cur_block_first_key = key.into();
         }
+        self.bloom.add(key);
         if !self.block_builder.add(key, value) {
             self.finalize_block();
             assert!(self.block_builder.add(key, value));
@@ -225,7 +253,11 @@ impl SsTableBuilder {
         let mut sst_data = self.data;
         let block_meta_offset = sst_data.len();
         BlockMeta::encode_block_meta(&self.meta, &mut sst_data);
+        let bloom_offset = sst_data.len();
+        let bloom = self.bloom.build();
+        sst_data.extend(bloom.encode());
         sst_data.put_u32(block_meta_offset as u32);
+        sst_data.put_u32(bloom_offset as u32);
         let file = FileObject::create(path.as_ref(), sst_data)?;
         Ok(SsTable {
             id,
@@ -233,6 +265,7 @@ impl SsTableBuilder {
             block_metas: self.meta,
             block_meta_offset,
             block_cache,
+            bloom,
         })
     }
 
@@ -542,8 +575,20 @@ fn test_sst_build_all() {
 fn test_sst_decode() {
     let (_dir, sst) = generate_sst();
     let meta = sst.block_metas.clone();
+    let bloom = sst.bloom.clone();
     let new_sst = SsTable::open_for_test(sst.file).unwrap();
     assert_eq!(new_sst.block_metas, meta);
+    assert_eq!(new_sst.bloom, bloom);
+}
+
+#[test]
+fn test_sst_may_contain() {
+    let (_dir, sst) = generate_sst();
+    assert!((0..num_of_keys()).all(|idx| sst.may_contain(&key_of(idx))));
+    let false_positives = (0..1000)
+        .filter(|idx| sst.may_contain(format!("missing_{}", idx).as_bytes()))
+        .count();
+    assert!(false_positives < 50, "{} false positives", false_positives);
 }
 
 #[cfg(test)]
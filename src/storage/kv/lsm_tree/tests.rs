@@ -327,4 +327,133 @@ fn test_storage_concurrent_operation() {
     for i in 0..1000 {
         assert_eq!(&storage.get(&key_of(i)).unwrap().unwrap(), &value_of(i));
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_storage_recover_from_wal() {
+    use super::lsm_storage::LsmStorage;
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.set(b"1", b"233".to_vec()).unwrap();
+    storage.set(b"2", b"2333".to_vec()).unwrap();
+    storage.delete(b"1").unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+
+    // Writes after a flush are recovered from the new WAL segment.
+    storage.flush().unwrap();
+    storage.set(b"3", b"23333".to_vec()).unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+
+    // Flushed writes are recovered from the SsTables, after their WAL segments are removed.
+    storage.flush().unwrap();
+    storage.set(b"4", b"233333".to_vec()).unwrap();
+    storage.flush().unwrap();
+    storage.compact(0).unwrap();
+    storage.set(b"5", b"2333333".to_vec()).unwrap();
+    storage.flush().unwrap();
+    storage.delete(b"2").unwrap();
+    drop(storage);
+
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert!(storage.get(b"2").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+    assert_eq!(&storage.get(b"4").unwrap().unwrap()[..], b"233333");
+    assert_eq!(&storage.get(b"5").unwrap().unwrap()[..], b"2333333");
+
+    // New SsTables don't reuse the IDs of loaded ones.
+    storage.set(b"6", b"23333333".to_vec()).unwrap();
+    storage.flush().unwrap();
+    drop(storage);
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert_eq!(&storage.get(b"5").unwrap().unwrap()[..], b"2333333");
+    assert_eq!(&storage.get(b"6").unwrap().unwrap()[..], b"23333333");
+}
+
+#[test]
+fn test_storage_write_batch() {
+    use super::lsm_storage::LsmStorage;
+    use crate::storage::kv::WriteOp;
+    let dir = tempdir().unwrap();
+    let storage = LsmStorage::open(&dir).unwrap();
+    storage.set(b"1", b"233".to_vec()).unwrap();
+    storage.flush().unwrap();
+
+    // Batched deletes shadow flushed values, and batched puts are interleaved with reads.
+    storage.write_batch(vec![
+        WriteOp::Delete { key: b"1".to_vec() },
+        WriteOp::Put { key: b"2".to_vec(), value: b"2333".to_vec() },
+    ]).unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert_eq!(&storage.get(b"2").unwrap().unwrap()[..], b"2333");
+    storage.write_batch(vec![
+        WriteOp::Put { key: b"3".to_vec(), value: b"23333".to_vec() },
+    ]).unwrap();
+    check_iter_result(
+        storage.scan(Range::from(..)).unwrap(),
+        vec![
+            (Bytes::from("2"), Bytes::from("2333")),
+            (Bytes::from("3"), Bytes::from("23333")),
+        ],
+    );
+
+    // Batches are recovered from the WAL.
+    drop(storage);
+    let storage = LsmStorage::open(&dir).unwrap();
+    assert!(storage.get(b"1").unwrap().is_none());
+    assert_eq!(&storage.get(b"3").unwrap().unwrap()[..], b"23333");
+}
+
+#[test]
+fn test_storage_compact() {
+    use super::lsm_storage::LsmStorage;
+    let dir = tempdir().unwrap();
+    let sstables = || {
+        std::fs::read_dir(&dir).unwrap()
+            .filter(|e| e.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "sst"))
+            .count()
+    };
+    let storage = LsmStorage::open(&dir).unwrap();
+
+    // Build overlapping L0 tables, overwriting and deleting keys from earlier tables.
+    for i in 0..3 {
+        for j in 0..100 {
+            storage.set(&key_of(j), value_of(i * 100 + j)).unwrap();
+        }
+        storage.delete(&key_of(i)).unwrap();
+        storage.flush().unwrap();
+    }
+    storage.set(&key_of(1), b"memtable".to_vec()).unwrap();
+    assert_eq!(sstables(), 3);
+
+    storage.compact(0).unwrap();
+    assert_eq!(sstables(), 1);
+    storage.compact(0).unwrap();
+    assert_eq!(sstables(), 1);
+
+    // L1 is the bottom level, so the deleted key is gone, while newer writes still shadow it.
+    assert_eq!(storage.get(&key_of(0)).unwrap(), Some(value_of(200)));
+    assert_eq!(storage.get(&key_of(1)).unwrap(), Some(b"memtable".to_vec()));
+    assert!(storage.get(&key_of(2)).unwrap().is_none());
+    assert_eq!(storage.get(&key_of(3)).unwrap(), Some(value_of(203)));
+    assert_eq!(storage.scan(Range::from(..)).unwrap().count(), 99);
+
+    // A deletion flushed to L0 shadows L1 until it is compacted away.
+    storage.delete(&key_of(3)).unwrap();
+    storage.flush().unwrap();
+    assert!(storage.get(&key_of(3)).unwrap().is_none());
+    storage.compact(0).unwrap();
+    storage.compact(1).unwrap();
+    assert_eq!(sstables(), 1);
+    assert!(storage.get(&key_of(3)).unwrap().is_none());
+    assert_eq!(storage.get(&key_of(99)).unwrap(), Some(value_of(299)));
+    assert_eq!(storage.scan(Range::from(..)).unwrap().rev().count(), 98);
+
+    assert!(storage.compact(LsmStorage::MAX_LEVELS).is_err());
+}
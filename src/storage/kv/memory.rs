@@ -0,0 +1,206 @@
+use parking_lot::RwLock;
+
+use super::{KvScan, KvStore, Range, StoreSnapshot, WriteOp};
+use crate::error::{Error, Result};
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::Read;
+
+/// A simple in-memory key-value store, serving as the reference implementation that other
+/// stores are checked against, see TestSuite::test_model(). Unlike StdBPlusTree, which shares its
+/// data between clones, cloning a MemoryStore copies its data, so tests can snapshot a store.
+pub struct MemoryStore {
+    data: RwLock<BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl MemoryStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self { data: RwLock::new(BTreeMap::new()) }
+    }
+
+    /// Restores a store from a StoreSnapshot::export() stream.
+    pub fn import(mut reader: impl Read) -> Result<MemoryStore> {
+        /// Reads exactly len bytes, or None at the end of the stream.
+        fn read(reader: &mut impl Read, len: u64) -> Result<Option<Vec<u8>>> {
+            let mut bytes = Vec::new();
+            match reader.take(len).read_to_end(&mut bytes)? as u64 {
+                0 if len > 0 => Ok(None),
+                n if n == len => Ok(Some(bytes)),
+                _ => Err(Error::Corruption("Truncated snapshot export".into())),
+            }
+        }
+
+        /// Reads a length-prefixed byte string, or None at the end of the stream.
+        fn read_bytes(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+            let Some(len) = read(reader, 4)? else { return Ok(None) };
+            let len = u32::from_be_bytes(len.try_into().unwrap()) as u64;
+            let bytes = read(reader, len)?;
+            Ok(Some(bytes.ok_or_else(|| Error::Corruption("Truncated snapshot export".into()))?))
+        }
+
+        let mut data = BTreeMap::new();
+        while let Some(key) = read_bytes(&mut reader)? {
+            let value = read_bytes(&mut reader)?
+                .ok_or_else(|| Error::Corruption("Truncated snapshot export".into()))?;
+            data.insert(key, value);
+        }
+        Ok(Self { data: RwLock::new(data) })
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for MemoryStore {
+    fn clone(&self) -> Self {
+        Self { data: RwLock::new(self.data.read().clone()) }
+    }
+}
+
+impl FromIterator<(Vec<u8>, Vec<u8>)> for MemoryStore {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(iter: I) -> Self {
+        Self { data: RwLock::new(iter.into_iter().collect()) }
+    }
+}
+
+impl Display for MemoryStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "memory ({} keys)", self.data.read().len())
+    }
+}
+
+impl KvStore for MemoryStore {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.data.write().insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.data.read().get(key).cloned())
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let data = self.data.read();
+        Ok(keys.iter().map(|key| data.get(*key).cloned()).collect())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        self.data.write().remove(key);
+        Ok(())
+    }
+
+    /// The range is buffered, so the scan doesn't hold the read lock.
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        if range.is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
+        let items: Vec<_> =
+            self.data.read().range(range).map(|(k, v)| Ok((k.clone(), v.clone()))).collect();
+        Ok(Box::new(items.into_iter()))
+    }
+
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn entry_count(&self) -> Result<u64> {
+        Ok(self.data.read().len() as u64)
+    }
+
+    fn snapshot(&self) -> Result<StoreSnapshot> {
+        Ok(StoreSnapshot::new(self.clone()))
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        let mut data = self.data.write();
+        let value = data
+            .remove(old_key)
+            .ok_or_else(|| Error::NotFound(format!("Key {:?} not found", old_key)))?;
+        data.insert(new_key.to_vec(), value);
+        Ok(())
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        let mut data = self.data.write();
+        if !data.contains_key(old_key) {
+            return Err(Error::NotFound(format!("Key {:?} not found", old_key)));
+        }
+        if data.contains_key(new_key) {
+            return Ok(false);
+        }
+        let value = data.remove(old_key).unwrap();
+        data.insert(new_key.to_vec(), value);
+        Ok(true)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let mut data = self.data.write();
+        for op in ops {
+            match op {
+                WriteOp::Put { key, value } => data.insert(key, value),
+                WriteOp::Delete { key } => data.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let mut data = self.data.write();
+        if data.get(key).map(|v| v.as_slice()) != expected {
+            return Ok(false);
+        }
+        data.insert(key.to_vec(), new_value);
+        Ok(true)
+    }
+
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        if range.is_empty() {
+            return Ok(0);
+        }
+        let mut data = self.data.write();
+        let keys: Vec<Vec<u8>> = data.range(range).map(|(k, _)| k.clone()).collect();
+        for key in keys.iter() {
+            data.remove(key);
+        }
+        Ok(keys.len() as u64)
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<MemoryStore> for MemoryStore {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        Ok((MemoryStore::new(), None))
+    }
+}
+
+#[test]
+fn tests() -> Result<()> {
+    use super::TestSuite;
+    MemoryStore::test()
+}
+
+#[test]
+fn clone_snapshot() -> Result<()> {
+    let s = MemoryStore::from_iter(vec![(b"a".to_vec(), vec![0x01]), (b"b".to_vec(), vec![0x02])]);
+    assert_eq!("memory (2 keys)", s.to_string());
+
+    // Writes after a clone don't affect the clone.
+    let snapshot = s.clone();
+    s.set(b"a", vec![0x03])?;
+    s.delete(b"b")?;
+    assert_eq!(Some(vec![0x01]), snapshot.get(b"a")?);
+    assert_eq!(Some(vec![0x02]), snapshot.get(b"b")?);
+    assert_eq!(Some(vec![0x03]), s.get(b"a")?);
+    assert_eq!("memory (1 keys)", s.to_string());
+    Ok(())
+}
@@ -1,13 +1,76 @@
+pub mod append_log;
+pub mod atomic;
+pub mod bitcask;
+pub mod bloom;
+pub mod cache;
+pub mod checksum;
 pub mod lsm_tree;
+pub mod memory;
+pub mod replicated;
+pub mod snapshot;
+pub mod stats;
 pub mod std_b_plus_tree;
+pub mod ttl;
+pub mod typed;
+pub mod wal;
 
 use std::fmt::Display;
 use std::ops::{Bound, RangeBounds};
 
-use crate::error::Result;
+use serde_derive::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
+
+pub use append_log::AppendLogStore;
+pub use atomic::AtomicStore;
+pub use bitcask::BitcaskStore;
+pub use checksum::ChecksumStore;
 pub use lsm_tree::lsm_storage::LsmStorage;
+pub use memory::MemoryStore;
+pub use replicated::{ReadConsistency, ReplicatedStore};
+pub use snapshot::StoreSnapshot;
+pub use stats::StatsStore;
 pub use std_b_plus_tree::StdBPlusTree;
+pub use ttl::TtlStore;
+pub use typed::TypedStore;
+pub use wal::WalStore;
+
+/// The well-known key holding the store format version, see KvStore::store_version().
+pub const STORE_VERSION_KEY: &[u8] = b"__meta__\x00format_version";
+
+/// A single write in a batch, see KvStore::write_batch().
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WriteOp {
+    Put { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+}
+
+/// Storage statistics, see KvStore::stats().
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StorageStats {
+    /// The number of live keys.
+    pub key_count: u64,
+    /// The total size of the live keys, in bytes.
+    pub total_bytes_keys: u64,
+    /// The total size of the live values, in bytes.
+    pub total_bytes_values: u64,
+    /// The size of the store's files on disk, in bytes, or 0 for in-memory stores.
+    pub disk_bytes: u64,
+}
+
+impl StorageStats {
+    /// Computes the key and value statistics of a scan, with no disk usage.
+    pub fn from_scan(scan: KvScan) -> Result<Self> {
+        let mut stats = Self::default();
+        for item in scan {
+            let (key, value) = item?;
+            stats.key_count += 1;
+            stats.total_bytes_keys += key.len() as u64;
+            stats.total_bytes_values += value.len() as u64;
+        }
+        Ok(stats)
+    }
+}
 
 pub trait KvStore: Display + Send + Sync {
     /// Sets a value for a key, replacing the existing value if any.
@@ -16,14 +79,169 @@ pub trait KvStore: Display + Send + Sync {
     /// Gets a value for a key, if it exists.
     fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
+    /// Gets the values of several keys, in the order of the keys. The default implementation
+    /// gets the keys one by one; backends that can look them up in one pass should override it.
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+
+    /// Gets the values of several keys, in the order of the keys, falling back to the given
+    /// default value for keys that don't exist.
+    fn get_or_multi(&self, keys: &[(&[u8], Vec<u8>)]) -> Result<Vec<Vec<u8>>> {
+        let values = self.get_multi(&keys.iter().map(|(key, _)| *key).collect::<Vec<_>>())?;
+        Ok(values
+            .into_iter()
+            .zip(keys)
+            .map(|(value, (_, default))| value.unwrap_or_else(|| default.clone()))
+            .collect())
+    }
+
     /// Deletes a key, doing nothing if it does not exist.
     fn delete(&self, key: &[u8]) -> Result<()>;
 
-    /// Iterates over an ordered range of key/value pairs.
+    /// Iterates over an ordered range of key/value pairs. An empty range, see Range::is_empty(),
+    /// yields no pairs.
     fn scan(&self, range: Range) -> Result<KvScan>;
 
+    /// Iterates over all key/value pairs whose key starts with the given prefix.
+    fn prefix_scan(&self, prefix: &[u8]) -> Result<KvScan> {
+        self.scan(Range::from_prefix(prefix))
+    }
+
     /// Flushes any buffered data to the underlying storage medium.
     fn flush(&self) -> Result<()>;
+
+    /// Moves the value at old_key to new_key, overwriting any value at new_key. Fails with
+    /// NotFound if old_key does not exist. The default implementation is not atomic; backends
+    /// that can rename atomically should override it.
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        let value = self
+            .get(old_key)?
+            .ok_or_else(|| Error::NotFound(format!("Key {:?} not found", old_key)))?;
+        if old_key != new_key {
+            self.set(new_key, value)?;
+            self.delete(old_key)?;
+        }
+        Ok(())
+    }
+
+    /// Like rename(), but leaves an occupied new_key alone and returns false instead, e.g. to
+    /// publish a value only once. The default implementation is not atomic; backends that can
+    /// rename atomically should override it.
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        if self.get(old_key)?.is_none() {
+            return Err(Error::NotFound(format!("Key {:?} not found", old_key)));
+        }
+        if self.get(new_key)?.is_some() {
+            return Ok(false);
+        }
+        self.rename(old_key, new_key)?;
+        Ok(true)
+    }
+
+    /// Applies a batch of writes in order, such that readers observe either none or all of them.
+    /// The default implementation is not atomic; backends should override it.
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        for op in ops {
+            match op {
+                WriteOp::Put { key, value } => self.set(&key, value)?,
+                WriteOp::Delete { key } => self.delete(&key)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a key to new_value if its current value is expected, where None means the key must
+    /// not exist, returning whether the value was set. The default implementation is not
+    /// linearizable under concurrent writers, since another write may land between the read and
+    /// the write; backends that can compare and swap atomically should override it, and
+    /// AtomicStore provides it for any store.
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        if self.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        self.set(key, new_value)?;
+        Ok(true)
+    }
+
+    /// Deletes all keys in a range, returning the number of deleted keys. The default
+    /// implementation scans the range and deletes the keys one by one, which is not atomic;
+    /// backends that can delete a range natively should override it.
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        let keys = self.scan(range)?.map(|r| r.map(|(k, _)| k)).collect::<Result<Vec<_>>>()?;
+        for key in keys.iter() {
+            self.delete(key)?;
+        }
+        Ok(keys.len() as u64)
+    }
+
+    /// Returns storage statistics. The default implementation scans the entire store, and
+    /// reports no disk usage; backends that can compute them cheaply should override it.
+    fn stats(&self) -> Result<StorageStats> {
+        StorageStats::from_scan(self.scan(Range::from(..))?)
+    }
+
+    /// Returns the number of live keys. The default implementation scans the entire store;
+    /// backends that keep a count should override it.
+    fn entry_count(&self) -> Result<u64> {
+        let mut count = 0;
+        for item in self.scan(Range::from(..))? {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns true if the store has no live keys.
+    fn is_empty(&self) -> Result<bool> {
+        Ok(self.entry_count()? == 0)
+    }
+
+    /// Returns the size of the store in bytes, as a lower bound of the live key and value
+    /// bytes and an upper bound including storage overhead, if known. The default
+    /// implementation derives both from stats(), adding the size of the store's files for the
+    /// upper bound, and returns (0, None) if they can't be computed.
+    fn size_hint(&self) -> (u64, Option<u64>) {
+        match self.stats() {
+            Ok(stats) => {
+                let live = stats.total_bytes_keys + stats.total_bytes_values;
+                (live, (stats.disk_bytes > 0).then(|| live + stats.disk_bytes))
+            }
+            Err(_) => (0, None),
+        }
+    }
+
+    /// Takes a consistent, read-only point-in-time view of the store, e.g. for online backups.
+    /// The default implementation copies a scan of the entire store into memory, which is only
+    /// consistent if the scan is; backends that can share their data should override it.
+    fn snapshot(&self) -> Result<StoreSnapshot> {
+        Ok(StoreSnapshot::new(self.scan(Range::from(..))?.collect::<Result<MemoryStore>>()?))
+    }
+
+    /// Returns the (major, minor) format version the store was written with, or (0, 0) if the
+    /// store is uninitialized.
+    fn store_version(&self) -> Result<(u32, u32)> {
+        match self.get(STORE_VERSION_KEY)? {
+            Some(bytes) if bytes.len() == 8 => Ok((
+                u32::from_be_bytes(bytes[..4].try_into().unwrap()),
+                u32::from_be_bytes(bytes[4..].try_into().unwrap()),
+            )),
+            Some(bytes) => Err(Error::Internal(format!("Invalid store version {:?}", bytes))),
+            None => Ok((0, 0)),
+        }
+    }
+
+    /// Records the (major, minor) format version the store is written with.
+    fn write_store_version(&self, major: u32, minor: u32) -> Result<()> {
+        let mut bytes = major.to_be_bytes().to_vec();
+        bytes.extend(minor.to_be_bytes());
+        self.set(STORE_VERSION_KEY, bytes)
+    }
 }
 
 #[derive(Clone)]
@@ -49,6 +267,52 @@ impl Range {
             },
         }
     }
+
+    /// Creates a range covering all keys that start with the given prefix. The end bound is the
+    /// prefix with its last non-0xff byte incremented and any trailing 0xff bytes removed, or
+    /// unbounded if there is no such byte.
+    pub fn from_prefix(prefix: &[u8]) -> Self {
+        let end = match prefix.iter().rposition(|b| *b != 0xff) {
+            Some(i) => {
+                let mut end = prefix[..=i].to_vec();
+                end[i] += 1;
+                Bound::Excluded(end)
+            }
+            None => Bound::Unbounded,
+        };
+        Self { start: Bound::Included(prefix.to_vec()), end }
+    }
+
+    /// Returns whether the range contains a key, comparing keys bytewise as stores order them.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        let after_start = match &self.start {
+            Bound::Included(start) => key >= start.as_slice(),
+            Bound::Excluded(start) => key > start.as_slice(),
+            Bound::Unbounded => true,
+        };
+        after_start && self.before_end(key)
+    }
+
+    /// Returns whether a key satisfies the end bound.
+    fn before_end(&self, key: &[u8]) -> bool {
+        match &self.end {
+            Bound::Included(end) => key <= end.as_slice(),
+            Bound::Excluded(end) => key < end.as_slice(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Returns whether the range contains no keys at all, e.g. when its start is after its end.
+    /// Scanning such a range yields no items rather than an error.
+    pub fn is_empty(&self) -> bool {
+        // The range is empty iff the smallest key after the start bound is past the end bound.
+        let first = match &self.start {
+            Bound::Included(start) => start.clone(),
+            Bound::Excluded(start) => [start.as_slice(), &[0x00]].concat(),
+            Bound::Unbounded => Vec::new(),
+        };
+        !self.before_end(&first)
+    }
 }
 
 impl RangeBounds<Vec<u8>> for Range {
@@ -72,21 +336,201 @@ impl RangeBounds<Vec<u8>> for Range {
 /// Iterator over a key/value range.
 pub type KvScan = Box<dyn DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> + Send>;
 
+/// A key/value predicate, see FilteredScan.
+pub type ScanPredicate = Box<dyn Fn(&[u8], &[u8]) -> bool + Send>;
+
+/// A scan that only yields the key/value pairs of an underlying scan satisfying a predicate,
+/// evaluated lazily as the scan is iterated. Errors are passed through.
+pub struct FilteredScan {
+    scan: KvScan,
+    predicate: ScanPredicate,
+}
+
+impl FilteredScan {
+    pub fn new<F>(scan: KvScan, predicate: F) -> Self
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + 'static,
+    {
+        Self { scan, predicate: Box::new(predicate) }
+    }
+
+    fn matches(&self, item: &Result<(Vec<u8>, Vec<u8>)>) -> bool {
+        match item {
+            Ok((key, value)) => (self.predicate)(key, value),
+            Err(_) => true,
+        }
+    }
+}
+
+impl Iterator for FilteredScan {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(item) = self.scan.next() {
+            if self.matches(&item) {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+impl DoubleEndedIterator for FilteredScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some(item) = self.scan.next_back() {
+            if self.matches(&item) {
+                return Some(item);
+            }
+        }
+        None
+    }
+}
+
+/// Filters a scan by a key/value predicate, see FilteredScan.
+pub fn filtered<F>(scan: KvScan, predicate: F) -> KvScan
+where
+    F: Fn(&[u8], &[u8]) -> bool + Send + 'static,
+{
+    Box::new(FilteredScan::new(scan, predicate))
+}
+
+/// Renames a key as a batch of a Put of its value at new_key and a Delete of old_key, for
+/// backends that implement KvStore::rename() and rename_if_absent() atomically by holding their
+/// lock across the call. The closures read a key, check whether a key is occupied, and write a
+/// batch, given the locked state. If overwrite is false, an occupied new_key is left alone and
+/// false is returned.
+pub(crate) fn rename_batch<S>(
+    state: &mut S,
+    old_key: &[u8],
+    new_key: &[u8],
+    overwrite: bool,
+    get: impl FnOnce(&mut S, &[u8]) -> Result<Option<Vec<u8>>>,
+    contains: impl FnOnce(&mut S, &[u8]) -> Result<bool>,
+    write: impl FnOnce(&mut S, Vec<WriteOp>) -> Result<()>,
+) -> Result<bool> {
+    let value = get(state, old_key)?
+        .ok_or_else(|| Error::NotFound(format!("Key {:?} not found", old_key)))?;
+    // A key renamed onto itself is kept, but counts as occupied.
+    if old_key == new_key {
+        return Ok(overwrite);
+    }
+    if !overwrite && contains(state, new_key)? {
+        return Ok(false);
+    }
+    write(
+        state,
+        vec![
+            WriteOp::Put { key: new_key.to_vec(), value },
+            WriteOp::Delete { key: old_key.to_vec() },
+        ],
+    )?;
+    Ok(true)
+}
+
 #[cfg(test)]
 trait TestSuite<S: KvStore> {
-    fn setup() -> Result<S>;
+    /// Creates an empty store, along with the temporary directory holding its files if it has
+    /// any. The directory is removed when dropped, so it must outlive the store.
+    fn setup() -> Result<(S, Option<tempfile::TempDir>)>;
 
     fn test() -> Result<()> {
+        Self::test_compare_and_swap()?;
         Self::test_delete()?;
+        Self::test_delete_range()?;
         Self::test_get()?;
+        Self::test_get_multi()?;
+        Self::test_prefix_scan()?;
+        Self::test_rename()?;
         Self::test_scan()?;
         Self::test_set()?;
+        Self::test_stats()?;
+        Self::test_entry_count()?;
+        Self::test_snapshot()?;
+        Self::test_store_version()?;
+        Self::test_write_batch()?;
+        Self::test_write_batch_concurrent()?;
         Self::test_random()?;
+        Self::test_model()?;
+        Ok(())
+    }
+
+    fn test_store_version() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        assert_eq!((0, 0), s.store_version()?);
+        s.write_store_version(1, 2)?;
+        assert_eq!((1, 2), s.store_version()?);
+        s.write_store_version(3, 0)?;
+        assert_eq!((3, 0), s.store_version()?);
+        Ok(())
+    }
+
+    fn test_write_batch() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+
+        s.write_batch(vec![
+            WriteOp::Put { key: b"c".to_vec(), value: vec![0x03] },
+            WriteOp::Delete { key: b"a".to_vec() },
+            WriteOp::Put { key: b"b".to_vec(), value: vec![0x04] },
+        ])?;
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(Some(vec![0x04]), s.get(b"b")?);
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x04]), (b"c".to_vec(), vec![0x03])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+
+        // Later ops in a batch override earlier ones on the same key
+        s.write_batch(vec![
+            WriteOp::Delete { key: b"b".to_vec() },
+            WriteOp::Put { key: b"b".to_vec(), value: vec![0x05] },
+            WriteOp::Put { key: b"d".to_vec(), value: vec![0x06] },
+            WriteOp::Delete { key: b"d".to_vec() },
+        ])?;
+        assert_eq!(Some(vec![0x05]), s.get(b"b")?);
+        assert_eq!(None, s.get(b"d")?);
+
+        // An empty batch is a noop
+        s.write_batch(vec![])?;
+        assert_eq!(
+            vec![(b"b".to_vec(), vec![0x05]), (b"c".to_vec(), vec![0x03])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    fn test_write_batch_concurrent() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        let batch = |i: u64| {
+            vec![
+                WriteOp::Put { key: b"a".to_vec(), value: i.to_be_bytes().to_vec() },
+                WriteOp::Put { key: b"b".to_vec(), value: i.to_be_bytes().to_vec() },
+            ]
+        };
+        s.write_batch(batch(0))?;
+
+        // Scans interleaved with batches must never observe a partially applied batch
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| -> Result<()> {
+                for _ in 0..1000 {
+                    let items = s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?;
+                    assert_eq!(2, items.len());
+                    assert_eq!(items[0].1, items[1].1);
+                }
+                Ok(())
+            });
+            for i in 1..=1000 {
+                s.write_batch(batch(i))?;
+            }
+            reader.join().unwrap()
+        })?;
+        assert_eq!(Some(1000_u64.to_be_bytes().to_vec()), s.get(b"a")?);
         Ok(())
     }
 
     fn test_get() -> Result<()> {
-        let s = Self::setup()?;
+        let (s, _dir) = Self::setup()?;
         s.set(b"a", vec![0x01])?;
         assert_eq!(Some(vec![0x01]), s.get(b"a")?);
         assert_eq!(None, s.get(b"b")?);
@@ -94,7 +538,7 @@ trait TestSuite<S: KvStore> {
     }
 
     fn test_delete() -> Result<()> {
-        let s = Self::setup()?;
+        let (s, _dir) = Self::setup()?;
         s.set(b"a", vec![0x01])?;
         assert_eq!(Some(vec![0x01]), s.get(b"a")?);
         s.delete(b"a")?;
@@ -103,9 +547,102 @@ trait TestSuite<S: KvStore> {
         Ok(())
     }
 
+    fn test_compare_and_swap() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        assert!(!s.compare_and_swap(b"a", Some(&[0x01]), vec![0x02])?);
+        assert_eq!(None, s.get(b"a")?);
+        assert!(s.compare_and_swap(b"a", None, vec![0x01])?);
+        assert!(!s.compare_and_swap(b"a", None, vec![0x02])?);
+        assert!(!s.compare_and_swap(b"a", Some(&[0x02]), vec![0x03])?);
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert!(s.compare_and_swap(b"a", Some(&[0x01]), vec![0x03])?);
+        assert_eq!(Some(vec![0x03]), s.get(b"a")?);
+        Ok(())
+    }
+
+    fn test_delete_range() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        for key in [&b"a"[..], b"b", b"ba", b"bb", b"c"] {
+            s.set(key, vec![0x01])?;
+        }
+        let scan = |range: Range| -> Result<Vec<Vec<u8>>> {
+            s.scan(range)?.map(|r| r.map(|(k, _)| k)).collect()
+        };
+
+        assert_eq!(3, s.delete_range(Range::from_prefix(b"b"))?);
+        assert!(scan(Range::from_prefix(b"b"))?.is_empty());
+        assert_eq!(vec![b"a".to_vec(), b"c".to_vec()], scan(Range::from(..))?);
+        assert_eq!(None, s.get(b"ba")?);
+
+        // Empty ranges delete nothing, and deleted keys can be written again
+        assert_eq!(0, s.delete_range(Range::from(b"b".to_vec()..b"bz".to_vec()))?);
+        s.set(b"bb", vec![0x02])?;
+        assert_eq!(Some(vec![0x02]), s.get(b"bb")?);
+        assert_eq!(3, s.delete_range(Range::from(..))?);
+        assert!(scan(Range::from(..))?.is_empty());
+        Ok(())
+    }
+
+    fn test_stats() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        assert_eq!(0, s.stats()?.key_count);
+        s.set(b"a", vec![0x01, 0x02])?;
+        s.set(b"bc", vec![0x03])?;
+        s.set(b"a", vec![0x04])?;
+        s.set(b"def", vec![0x05, 0x06, 0x07])?;
+        s.delete(b"bc")?;
+        let stats = s.stats()?;
+        assert_eq!((2, 4, 4), (stats.key_count, stats.total_bytes_keys, stats.total_bytes_values));
+        Ok(())
+    }
+
+    fn test_entry_count() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        assert!(s.is_empty()?);
+        assert_eq!(0, s.entry_count()?);
+        for i in 0..100u8 {
+            s.set(&[i % 40], vec![i; 1 + i as usize % 7])?;
+            if i % 3 == 0 {
+                s.delete(&[i % 11])?;
+            }
+        }
+        s.rename(&[1], &[200])?;
+        s.delete_range(Range::from(vec![30]..vec![35]))?;
+
+        // Any override must agree with the scan of the default implementation.
+        let scanned = s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?;
+        assert_eq!(scanned.len() as u64, s.entry_count()?);
+        assert!(!s.is_empty()?);
+        let live = scanned.iter().map(|(k, v)| (k.len() + v.len()) as u64).sum::<u64>();
+        let (lower, upper) = s.size_hint();
+        assert_eq!(live, lower);
+        assert!(upper.is_none_or(|upper| upper >= lower));
+
+        s.delete_range(Range::from(..))?;
+        assert!(s.is_empty()?);
+        assert_eq!((0, 0), (s.entry_count()?, s.size_hint().0));
+        Ok(())
+    }
+
+    fn test_snapshot() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        for i in 0..50u8 {
+            s.set(&[i], vec![i; 3])?;
+        }
+        let expect = s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?;
+        let snapshot = s.snapshot()?;
+        s.set(&[0], vec![9])?;
+        s.delete(&[1])?;
+        s.set(&[100], vec![1])?;
+        assert_eq!(expect, snapshot.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?);
+        assert_eq!(Some(vec![0; 3]), snapshot.get(&[0])?);
+        assert_eq!(Err(Error::ReadOnly), snapshot.set(&[0], vec![1]));
+        Ok(())
+    }
+
     fn test_random() -> Result<()> {
         use rand::Rng;
-        let s = Self::setup()?;
+        let (s, _dir) = Self::setup()?;
         let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(397_427_893);
 
         // Create a bunch of random items and insert them
@@ -137,8 +674,144 @@ trait TestSuite<S: KvStore> {
         Ok(())
     }
 
+    /// Applies random operations both to the store and to the reference MemoryStore, checking
+    /// that they return the same results.
+    fn test_model() -> Result<()> {
+        use rand::Rng;
+        let (s, _dir) = Self::setup()?;
+        let model = MemoryStore::new();
+        let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(397_427_893);
+        // Keys and values are short and non-empty, drawn from a small alphabet so that
+        // operations often hit existing and adjacent keys.
+        let bytes = |rng: &mut rand::rngs::StdRng| -> Vec<u8> {
+            (0..rng.gen_range(1..4)).map(|_| [0x00, 0x01, 0xff][rng.gen_range(0..3)]).collect()
+        };
+        let bound = |rng: &mut rand::rngs::StdRng| match rng.gen_range(0..3) {
+            0 => Bound::Included(bytes(rng)),
+            1 => Bound::Excluded(bytes(rng)),
+            _ => Bound::Unbounded,
+        };
+        let scan = |s: &dyn KvStore, range: Range| s.scan(range)?.collect::<Result<Vec<_>>>();
+
+        for _ in 0..1000 {
+            let (key, value) = (bytes(&mut rng), bytes(&mut rng));
+            match rng.gen_range(0..6) {
+                0 | 1 => {
+                    s.set(&key, value.clone())?;
+                    model.set(&key, value)?;
+                }
+                2 => {
+                    s.delete(&key)?;
+                    model.delete(&key)?;
+                }
+                3 => {
+                    let ops = vec![
+                        WriteOp::Put { key: key.clone(), value },
+                        WriteOp::Delete { key: bytes(&mut rng) },
+                    ];
+                    s.write_batch(ops.clone())?;
+                    model.write_batch(ops)?;
+                }
+                4 => {
+                    let expected = model.get(&bytes(&mut rng))?;
+                    assert_eq!(
+                        model.compare_and_swap(&key, expected.as_deref(), value.clone())?,
+                        s.compare_and_swap(&key, expected.as_deref(), value)?,
+                    );
+                }
+                _ => {
+                    let range = Range::from((bound(&mut rng), bound(&mut rng)));
+                    assert_eq!(model.delete_range(range.clone())?, s.delete_range(range)?);
+                }
+            }
+            let range = Range::from((bound(&mut rng), bound(&mut rng)));
+            assert_eq!(scan(&model, range.clone())?, scan(&s, range)?);
+            assert_eq!(model.get(&key)?, s.get(&key)?);
+        }
+        assert_eq!(scan(&model, Range::from(..))?, scan(&s, Range::from(..))?);
+        Ok(())
+    }
+
+    fn test_get_multi() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        s.set(b"b", vec![0x02])?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"d", vec![0x04])?;
+        s.delete(b"d")?;
+
+        // Missing and present keys are interleaved, in no particular order and with duplicates.
+        let keys: Vec<&[u8]> = vec![b"c", b"b", b"d", b"a", b"b", b"\xff"];
+        assert_eq!(
+            vec![None, Some(vec![0x02]), None, Some(vec![0x01]), Some(vec![0x02]), None],
+            s.get_multi(&keys)?
+        );
+        assert!(s.get_multi(&[])?.is_empty());
+        assert_eq!(
+            vec![vec![0x00], vec![0x02], vec![0x01]],
+            s.get_or_multi(&[(b"c", vec![0x00]), (b"b", vec![0x00]), (b"a", vec![])])?
+        );
+        Ok(())
+    }
+
+    fn test_prefix_scan() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        let keys: Vec<&[u8]> = vec![
+            b"\x00", b"a", b"a\x00", b"ab", b"a\xff", b"b", b"\xff", b"\xff\x00", b"\xff\xff",
+        ];
+        for key in keys.iter() {
+            s.set(key, vec![0x01])?;
+        }
+        let scan = |prefix: &[u8]| -> Result<Vec<Vec<u8>>> {
+            s.prefix_scan(prefix)?.map(|r| r.map(|(k, _)| k)).collect()
+        };
+
+        // Empty prefix scans everything
+        assert_eq!(keys, scan(b"")?);
+
+        // Single-byte prefixes, including ones with a trailing 0xff key
+        assert_eq!(vec![b"a".to_vec(), b"a\x00".to_vec(), b"ab".to_vec(), b"a\xff".to_vec()], scan(b"a")?);
+        assert_eq!(vec![b"a\xff".to_vec()], scan(b"a\xff")?);
+        assert_eq!(vec![b"\x00".to_vec()], scan(b"\x00")?);
+        assert!(scan(b"c")?.is_empty());
+
+        // All-0xff prefixes are unbounded
+        assert_eq!(vec![b"\xff".to_vec(), b"\xff\x00".to_vec(), b"\xff\xff".to_vec()], scan(b"\xff")?);
+        assert_eq!(vec![b"\xff\xff".to_vec()], scan(b"\xff\xff")?);
+        Ok(())
+    }
+
+    fn test_rename() -> Result<()> {
+        let (s, _dir) = Self::setup()?;
+        s.set(b"a", vec![0x01])?;
+        s.rename(b"a", b"b")?;
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(Some(vec![0x01]), s.get(b"b")?);
+
+        // Missing source
+        assert!(matches!(s.rename(b"a", b"c"), Err(Error::NotFound(_))));
+        assert!(matches!(s.rename_if_absent(b"a", b"c"), Err(Error::NotFound(_))));
+
+        // Occupied target, which rename_if_absent() leaves alone and rename() overwrites
+        s.set(b"c", vec![0x03])?;
+        assert!(!s.rename_if_absent(b"b", b"c")?);
+        assert_eq!(Some(vec![0x01]), s.get(b"b")?);
+        assert_eq!(Some(vec![0x03]), s.get(b"c")?);
+        s.rename(b"b", b"c")?;
+        assert_eq!(None, s.get(b"b")?);
+        assert_eq!(Some(vec![0x01]), s.get(b"c")?);
+        assert!(s.rename_if_absent(b"c", b"d")?);
+        assert_eq!(None, s.get(b"c")?);
+        assert_eq!(Some(vec![0x01]), s.get(b"d")?);
+
+        // Renaming a key onto itself keeps it, though it's occupied for rename_if_absent()
+        s.rename(b"d", b"d")?;
+        assert!(!s.rename_if_absent(b"d", b"d")?);
+        assert_eq!(Some(vec![0x01]), s.get(b"d")?);
+        Ok(())
+    }
+
     fn test_scan() -> Result<()> {
-        let s = Self::setup()?;
+        let (s, _dir) = Self::setup()?;
         s.set(b"a", vec![0x01])?;
         s.set(b"b", vec![0x02])?;
         s.set(b"ba", vec![0x02, 0x01])?;
@@ -187,6 +860,11 @@ trait TestSuite<S: KvStore> {
             s.scan(Range::from(..=b"b".to_vec()))?.collect::<Result<Vec<_>>>()?
         );
 
+        // Empty ranges
+        assert!(s.scan(Range::from(b"c".to_vec()..b"a".to_vec()))?.next().is_none());
+        let range = Range::from((Bound::Excluded(b"b".to_vec()), Bound::Excluded(b"b".to_vec())));
+        assert!(s.scan(range)?.next().is_none());
+
         // Full range
         assert_eq!(
             vec![
@@ -202,7 +880,7 @@ trait TestSuite<S: KvStore> {
     }
 
     fn test_set() -> Result<()> {
-        let s = Self::setup()?;
+        let (s, _dir) = Self::setup()?;
         s.set(b"a", vec![0x01])?;
         assert_eq!(Some(vec![0x01]), s.get(b"a")?);
         s.set(b"a", vec![0x02])?;
@@ -210,3 +888,67 @@ trait TestSuite<S: KvStore> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    /// Generates a short random key from a small alphabet, to exercise adjacent keys.
+    fn random_key(rng: &mut impl Rng) -> Vec<u8> {
+        (0..rng.gen_range(0..4)).map(|_| [0x00, 0x01, 0xff][rng.gen_range(0..3)]).collect()
+    }
+
+    fn random_bound(rng: &mut impl Rng) -> Bound<Vec<u8>> {
+        match rng.gen_range(0..3) {
+            0 => Bound::Included(random_key(rng)),
+            1 => Bound::Excluded(random_key(rng)),
+            _ => Bound::Unbounded,
+        }
+    }
+
+    #[test]
+    fn range_contains_is_empty() {
+        let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(397_427_893);
+        for _ in 0..10_000 {
+            let range = Range::from((random_bound(&mut rng), random_bound(&mut rng)));
+            let key = random_key(&mut rng);
+            assert_eq!(RangeBounds::contains(&range, &key), range.contains(&key));
+            if range.is_empty() {
+                assert!(!range.contains(&key));
+                continue;
+            }
+            // A non-empty range contains the smallest key after its start.
+            let first = match range.start_bound() {
+                Bound::Included(start) => start.clone(),
+                Bound::Excluded(start) => [start.as_slice(), &[0x00]].concat(),
+                Bound::Unbounded => Vec::new(),
+            };
+            assert!(range.contains(&first), "{:?} not in non-empty range", first);
+        }
+    }
+
+    #[test]
+    fn filtered_scan() -> Result<()> {
+        let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(397_427_893);
+        let store = StdBPlusTree::new();
+        for _ in 0..100 {
+            store.set(&random_key(&mut rng), random_key(&mut rng))?;
+        }
+        let all = store.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?;
+        for _ in 0..1_000 {
+            let range = Range::from((random_bound(&mut rng), random_bound(&mut rng)));
+            let expect: Vec<_> = all.iter().filter(|(k, _)| range.contains(k)).cloned().collect();
+            assert_eq!(expect, store.scan(range.clone())?.collect::<Result<Vec<_>>>()?);
+
+            let predicate = move |key: &[u8], _: &[u8]| range.contains(key);
+            let scan = filtered(store.scan(Range::from(..))?, predicate.clone());
+            assert_eq!(expect, scan.collect::<Result<Vec<_>>>()?);
+            let scan = filtered(store.scan(Range::from(..))?, predicate);
+            let mut reverse = scan.rev().collect::<Result<Vec<_>>>()?;
+            reverse.reverse();
+            assert_eq!(expect, reverse);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt::Display;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+
+use super::{KvStore, Range, WriteOp};
+use crate::error::Result;
+use crate::raft::{Client, APPLIED_INDEX_KEY};
+use crate::storage::async_store::{AsyncStore, SyncAdapter};
+
+/// The consistency of reads from a ReplicatedStore.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadConsistency {
+    /// Reads are served by the local replica as is, and may miss recent writes, including the
+    /// store's own writes if the replica lags behind the leader.
+    #[default]
+    Eventual,
+    /// Reads first obtain a read index from the leader (see Node::read), and wait for the local
+    /// replica to apply it, such that they see all writes committed before the read began.
+    Strong,
+}
+
+/// A key-value store replicated through Raft. Writes are proposed to the cluster through a Raft
+/// client, as mutations of a KvState state machine, and return once they have been committed
+/// and applied. Reads are served by the local replica, i.e. the store of the node's KvState,
+/// with the given read consistency. The state machine's reserved applied index key is hidden.
+pub struct ReplicatedStore<S: KvStore + 'static> {
+    /// The local replica, which must be the store the local node's KvState applies writes to.
+    local: SyncAdapter<S>,
+    client: Mutex<Client>,
+    read_consistency: ReadConsistency,
+}
+
+impl<S: KvStore + 'static> ReplicatedStore<S> {
+    /// How often the local replica is checked while waiting for it to catch up.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Creates a replicated store, reading from the local replica and writing through the client.
+    pub fn new(local: S, client: Client, read_consistency: ReadConsistency) -> Self {
+        Self { local: SyncAdapter::new(local), client: Mutex::new(client), read_consistency }
+    }
+
+    /// Proposes a batch of writes, returning once it has been committed and applied. The batch
+    /// is applied atomically on all replicas.
+    pub async fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        self.client.lock().await.mutate(bincode::serialize(&ops)?).await?;
+        Ok(())
+    }
+
+    /// Waits until the local replica can serve a read with the configured consistency.
+    async fn read_barrier(&self) -> Result<()> {
+        if self.read_consistency == ReadConsistency::Eventual {
+            return Ok(());
+        }
+        // The query runs once the leader has applied its read index, so the leader's applied
+        // index is at least the commit index when the read began.
+        let query = bincode::serialize(&APPLIED_INDEX_KEY.to_vec())?;
+        let response = self.client.lock().await.query(query).await?;
+        let read_index = Self::decode_index(bincode::deserialize(&response)?)?;
+        while Self::decode_index(self.local.get(APPLIED_INDEX_KEY).await?)? < read_index {
+            tokio::time::sleep(Self::POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Decodes a stored applied index, which is 0 if nothing has been applied yet.
+    fn decode_index(value: Option<Vec<u8>>) -> Result<u64> {
+        match value {
+            Some(bytes) => Ok(bincode::deserialize(&bytes)?),
+            None => Ok(0),
+        }
+    }
+}
+
+impl<S: KvStore + 'static> Display for ReplicatedStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "replicated({})", self.local)
+    }
+}
+
+#[tonic::async_trait]
+impl<S: KvStore + 'static> AsyncStore for ReplicatedStore<S> {
+    async fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.write_batch(vec![WriteOp::Put { key: key.to_vec(), value }]).await
+    }
+
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if key == APPLIED_INDEX_KEY {
+            return Ok(None);
+        }
+        self.read_barrier().await?;
+        self.local.get(key).await
+    }
+
+    async fn delete(&self, key: &[u8]) -> Result<()> {
+        self.write_batch(vec![WriteOp::Delete { key: key.to_vec() }]).await
+    }
+
+    async fn scan(&self, range: Range) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.read_barrier().await?;
+        let mut items = self.local.scan(range).await?;
+        items.retain(|(key, _)| key != APPLIED_INDEX_KEY);
+        Ok(items)
+    }
+
+    /// Proposes an empty batch as a no-op, and waits for it to commit. Since entries commit in
+    /// log order, all writes proposed before it have then been committed too.
+    async fn flush(&self) -> Result<()> {
+        self.write_batch(Vec::new()).await
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::Arc;
+
+use super::{KvScan, KvStore, Range, StorageStats, WriteOp};
+use crate::error::{Error, Result};
+
+/// A consistent, read-only point-in-time view of a key-value store, see KvStore::snapshot().
+/// Writes to the store after the snapshot was taken are not visible in it, so it can be
+/// exported as an online backup without stopping writes. Writes to the snapshot itself fail
+/// with Error::ReadOnly. Cloning a snapshot shares its data.
+#[derive(Clone)]
+pub struct StoreSnapshot {
+    /// The frozen view, which is never written to.
+    view: Arc<dyn KvStore>,
+}
+
+impl StoreSnapshot {
+    /// Creates a snapshot from a frozen view of a store. The view must not change afterwards.
+    pub fn new(view: impl KvStore + 'static) -> Self {
+        Self { view: Arc::new(view) }
+    }
+
+    /// Writes all key-value pairs to the writer in key order, returning the number of pairs.
+    /// Each pair is encoded as `[key_len: u32][key][value_len: u32][value]`, with big-endian
+    /// lengths, and can be restored with MemoryStore::import().
+    pub fn export(&self, mut writer: impl Write) -> Result<u64> {
+        let mut count = 0;
+        for item in self.view.scan(Range::from(..))? {
+            let (key, value) = item?;
+            for bytes in [&key, &value] {
+                let len = u32::try_from(bytes.len()).map_err(|_| {
+                    Error::Value(format!("Key or value too large ({} bytes)", bytes.len()))
+                })?;
+                writer.write_all(&len.to_be_bytes())?;
+                writer.write_all(bytes)?;
+            }
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+}
+
+impl Display for StoreSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "snapshot({})", self.view)
+    }
+}
+
+impl KvStore for StoreSnapshot {
+    fn set(&self, _: &[u8], _: Vec<u8>) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.view.get(key)
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.view.get_multi(keys)
+    }
+
+    fn delete(&self, _: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        self.view.scan(range)
+    }
+
+    /// There is nothing to flush, since the snapshot is never written to.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn rename(&self, _: &[u8], _: &[u8]) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn rename_if_absent(&self, _: &[u8], _: &[u8]) -> Result<bool> {
+        Err(Error::ReadOnly)
+    }
+
+    fn write_batch(&self, _: Vec<WriteOp>) -> Result<()> {
+        Err(Error::ReadOnly)
+    }
+
+    fn compare_and_swap(&self, _: &[u8], _: Option<&[u8]>, _: Vec<u8>) -> Result<bool> {
+        Err(Error::ReadOnly)
+    }
+
+    fn delete_range(&self, _: Range) -> Result<u64> {
+        Err(Error::ReadOnly)
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        self.view.stats()
+    }
+
+    fn entry_count(&self) -> Result<u64> {
+        self.view.entry_count()
+    }
+
+    /// Shares the frozen view.
+    fn snapshot(&self) -> Result<StoreSnapshot> {
+        Ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{AtomicStore, BitcaskStore, MemoryStore, StdBPlusTree};
+    use super::*;
+
+    fn scan(s: &dyn KvStore) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        s.scan(Range::from(..))?.collect()
+    }
+
+    #[test]
+    fn read_only() -> Result<()> {
+        let snapshot = MemoryStore::from_iter(vec![(b"a".to_vec(), vec![1])]).snapshot()?;
+        assert_eq!("snapshot(memory (1 keys))", snapshot.to_string());
+        assert_eq!(Err(Error::ReadOnly), snapshot.set(b"b", vec![2]));
+        assert_eq!(Err(Error::ReadOnly), snapshot.delete(b"a"));
+        assert_eq!(Err(Error::ReadOnly), snapshot.rename(b"a", b"b"));
+        assert_eq!(Err(Error::ReadOnly), snapshot.compare_and_swap(b"a", Some(&[1]), vec![2]));
+        assert_eq!(Err(Error::ReadOnly), snapshot.delete_range(Range::from(..)));
+        assert_eq!(Err(Error::ReadOnly), snapshot.write_store_version(1, 0));
+        assert_eq!(vec![(b"a".to_vec(), vec![1])], scan(&snapshot)?);
+        assert_eq!(vec![(b"a".to_vec(), vec![1])], scan(&snapshot.snapshot()?)?);
+        Ok(())
+    }
+
+    /// Checks that writes to a store after a snapshot aren't visible in it, calling modified()
+    /// after the writes.
+    fn check_isolation(s: &dyn KvStore, modified: impl Fn() -> Result<()>) -> Result<()> {
+        for i in 0..100u8 {
+            s.set(&[i], vec![i; 10])?;
+        }
+        let expect = scan(s)?;
+        let snapshot = s.snapshot()?;
+        for i in 0..100u8 {
+            s.set(&[i], vec![i + 1; 20])?;
+            s.delete(&[i / 2])?;
+        }
+        s.set(b"new", vec![1])?;
+        modified()?;
+        assert_eq!(expect, scan(&snapshot)?, "{}", s);
+        assert_eq!(None, snapshot.get(b"new")?);
+        assert_eq!(100, snapshot.entry_count()?);
+        assert_ne!(expect, scan(s)?);
+        Ok(())
+    }
+
+    #[test]
+    fn isolation() -> Result<()> {
+        check_isolation(&MemoryStore::new(), || Ok(()))?;
+        check_isolation(&AtomicStore::new(MemoryStore::new()), || Ok(()))?;
+        check_isolation(&StdBPlusTree::new(), || Ok(()))?;
+
+        // The writes rotate Bitcask files, and merging them removes the snapshot's files.
+        let dir = tempfile::tempdir()?;
+        let bitcask = BitcaskStore::open_with(dir.path(), 256)?;
+        check_isolation(&bitcask, || bitcask.merge())
+    }
+
+    #[test]
+    fn export_import() -> Result<()> {
+        let s = MemoryStore::from_iter(vec![
+            (b"".to_vec(), b"empty key".to_vec()),
+            (b"a".to_vec(), vec![]),
+            (vec![0xff; 300], vec![0x00; 70_000]),
+        ]);
+        let mut buffer = Vec::new();
+        assert_eq!(3, s.snapshot()?.export(&mut buffer)?);
+        assert_eq!(scan(&s)?, scan(&MemoryStore::import(buffer.as_slice())?)?);
+        assert!(MemoryStore::import(&[][..])?.is_empty()?);
+
+        // Truncated streams are rejected.
+        for len in [1, 4, 10, buffer.len() - 1] {
+            assert!(matches!(MemoryStore::import(&buffer[..len]), Err(Error::Corruption(_))));
+        }
+        Ok(())
+    }
+
+    /// Measures snapshot and export throughput on a 1M-key store. Run with `cargo test
+    /// --release snapshot_throughput -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn snapshot_throughput() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let stores: Vec<Box<dyn KvStore>> =
+            vec![Box::new(MemoryStore::new()), Box::new(BitcaskStore::open(dir.path())?)];
+        for s in stores {
+            let put = |i: u32| WriteOp::Put { key: i.to_be_bytes().to_vec(), value: vec![0; 100] };
+            for chunk in 0..100 {
+                s.write_batch((chunk * 10_000..(chunk + 1) * 10_000).map(put).collect())?;
+            }
+            let start = std::time::Instant::now();
+            let snapshot = s.snapshot()?;
+            let snapshot_elapsed = start.elapsed();
+            let start = std::time::Instant::now();
+            let count = snapshot.export(std::io::sink())?;
+            println!(
+                "{}: snapshot in {:?}, export at {:.0} keys/s",
+                s,
+                snapshot_elapsed,
+                count as f64 / start.elapsed().as_secs_f64(),
+            );
+        }
+        Ok(())
+    }
+}
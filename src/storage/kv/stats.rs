@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use parking_lot::Mutex;
+
+use super::{KvScan, KvStore, Range, StorageStats, WriteOp};
+use crate::error::Result;
+
+/// A key-value store wrapper which maintains storage statistics as running counters, making
+/// stats() O(1). The counters are initialized from the inner store's stats() when opened, and
+/// each write looks up the previous value to account for replaced and deleted keys. The disk
+/// usage is refreshed from the inner store on open and flush.
+pub struct StatsStore<S: KvStore> {
+    /// The inner store.
+    inner: S,
+    key_count: AtomicU64,
+    total_bytes_keys: AtomicU64,
+    total_bytes_values: AtomicU64,
+    disk_bytes: AtomicU64,
+    /// Held while writing, so the previous value looked up by a write is the one it replaces.
+    writes: Mutex<()>,
+}
+
+impl<S: KvStore> StatsStore<S> {
+    /// Wraps a store, computing its initial statistics.
+    pub fn new(inner: S) -> Result<Self> {
+        let stats = inner.stats()?;
+        Ok(Self {
+            inner,
+            key_count: AtomicU64::new(stats.key_count),
+            total_bytes_keys: AtomicU64::new(stats.total_bytes_keys),
+            total_bytes_values: AtomicU64::new(stats.total_bytes_values),
+            disk_bytes: AtomicU64::new(stats.disk_bytes),
+            writes: Mutex::new(()),
+        })
+    }
+
+    /// Updates the counters for a key whose value changed from `old` to `new` bytes, where None
+    /// means the key didn't or doesn't exist.
+    fn record(&self, key: &[u8], old: Option<usize>, new: Option<usize>) {
+        let key_len = key.len() as u64;
+        if let Some(old) = old {
+            self.key_count.fetch_sub(1, Ordering::Relaxed);
+            self.total_bytes_keys.fetch_sub(key_len, Ordering::Relaxed);
+            self.total_bytes_values.fetch_sub(old as u64, Ordering::Relaxed);
+        }
+        if let Some(new) = new {
+            self.key_count.fetch_add(1, Ordering::Relaxed);
+            self.total_bytes_keys.fetch_add(key_len, Ordering::Relaxed);
+            self.total_bytes_values.fetch_add(new as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the size of a key's current value, if it exists.
+    fn value_len(&self, key: &[u8]) -> Result<Option<usize>> {
+        Ok(self.inner.get(key)?.map(|value| value.len()))
+    }
+}
+
+impl<S: KvStore> Display for StatsStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stats({})", self.inner)
+    }
+}
+
+impl<S: KvStore> KvStore for StatsStore<S> {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let _writes = self.writes.lock();
+        let (old, new) = (self.value_len(key)?, value.len());
+        self.inner.set(key, value)?;
+        self.record(key, old, Some(new));
+        Ok(())
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.get_multi(keys)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        let _writes = self.writes.lock();
+        let old = self.value_len(key)?;
+        self.inner.delete(key)?;
+        self.record(key, old, None);
+        Ok(())
+    }
+
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        self.inner.scan(range)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.flush()?;
+        self.disk_bytes.store(self.inner.stats()?.disk_bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        let _writes = self.writes.lock();
+        let (len, replaced) = (self.value_len(old_key)?, self.value_len(new_key)?);
+        self.inner.rename(old_key, new_key)?;
+        if old_key != new_key {
+            self.record(old_key, len, None);
+            self.record(new_key, replaced, len);
+        }
+        Ok(())
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        let _writes = self.writes.lock();
+        let len = self.value_len(old_key)?;
+        if !self.inner.rename_if_absent(old_key, new_key)? {
+            return Ok(false);
+        }
+        self.record(old_key, len, None);
+        self.record(new_key, None, len);
+        Ok(true)
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let _writes = self.writes.lock();
+        let current = self.inner.get(key)?;
+        if current.as_deref() != expected {
+            return Ok(false);
+        }
+        let new = new_value.len();
+        self.inner.set(key, new_value)?;
+        self.record(key, current.map(|value| value.len()), Some(new));
+        Ok(true)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let _writes = self.writes.lock();
+        // Track the value sizes written by the batch, since a key may be written repeatedly.
+        let mut changes: HashMap<Vec<u8>, (Option<usize>, Option<usize>)> = HashMap::new();
+        for op in ops.iter() {
+            let (key, new) = match op {
+                WriteOp::Put { key, value } => (key, Some(value.len())),
+                WriteOp::Delete { key } => (key, None),
+            };
+            match changes.get_mut(key) {
+                Some(change) => change.1 = new,
+                None => {
+                    changes.insert(key.clone(), (self.value_len(key)?, new));
+                }
+            }
+        }
+        self.inner.write_batch(ops)?;
+        for (key, (old, new)) in changes {
+            self.record(&key, old, new);
+        }
+        Ok(())
+    }
+
+    fn stats(&self) -> Result<StorageStats> {
+        Ok(StorageStats {
+            key_count: self.key_count.load(Ordering::Relaxed),
+            total_bytes_keys: self.total_bytes_keys.load(Ordering::Relaxed),
+            total_bytes_values: self.total_bytes_values.load(Ordering::Relaxed),
+            disk_bytes: self.disk_bytes.load(Ordering::Relaxed),
+        })
+    }
+
+    fn entry_count(&self) -> Result<u64> {
+        Ok(self.key_count.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<StatsStore<super::StdBPlusTree>> for StatsStore<super::StdBPlusTree> {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        Ok((StatsStore::new(super::StdBPlusTree::new())?, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::super::{LsmStorage, StdBPlusTree};
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        StatsStore::<StdBPlusTree>::test()
+    }
+
+    #[test]
+    fn test_counters_match_scan() -> Result<()> {
+        let inner = StdBPlusTree::new();
+        inner.set(b"existing", vec![0x01; 10])?;
+        let s = StatsStore::new(inner)?;
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let key = vec![rng.gen_range(0..32_u8); rng.gen_range(1..4)];
+            match rng.gen_range(0..6) {
+                0 => s.delete(&key)?,
+                1 => s.write_batch(vec![
+                    WriteOp::Put { key: key.clone(), value: vec![0x02; rng.gen_range(1..8)] },
+                    WriteOp::Delete { key: vec![rng.gen_range(0..32_u8)] },
+                    WriteOp::Put { key: key.clone(), value: vec![0x03; rng.gen_range(1..8)] },
+                ])?,
+                2 => match s.rename(&key, &[rng.gen_range(0..32_u8)]) {
+                    Ok(()) | Err(Error::NotFound(_)) => {}
+                    Err(err) => return Err(err),
+                },
+                3 => match s.rename_if_absent(&key, &[rng.gen_range(0..32_u8)]) {
+                    Ok(_) | Err(Error::NotFound(_)) => {}
+                    Err(err) => return Err(err),
+                },
+                _ => s.set(&key, vec![0x04; rng.gen_range(0..16)])?,
+            }
+        }
+        s.delete_range(Range::from(vec![8]..vec![16]))?;
+        assert_eq!(StorageStats::from_scan(s.scan(Range::from(..))?)?, s.stats()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_bytes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let s = StatsStore::new(LsmStorage::open(dir.path())?)?;
+        for i in 0..100_u32 {
+            s.set(&i.to_be_bytes(), vec![0x01; 100])?;
+        }
+        assert_eq!(0, s.stats()?.disk_bytes);
+        s.flush()?;
+        let stats = s.stats()?;
+        assert_eq!(100, stats.key_count);
+        assert_eq!((400, 10_000), (stats.total_bytes_keys, stats.total_bytes_values));
+        assert!(stats.disk_bytes > 10_000, "disk_bytes {}", stats.disk_bytes);
+        Ok(())
+    }
+}
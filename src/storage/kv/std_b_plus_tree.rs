@@ -1,13 +1,15 @@
 use parking_lot::RwLock;
 
-use super::{Range, KvScan, KvStore};
-use crate::error::Result;
+use super::{Range, KvScan, KvStore, WriteOp};
+use crate::error::{Error, Result};
 
 use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::sync::Arc;
 
-/// In-memory key-value store using the Rust standard library B-tree implementation.
+/// In-memory key-value store using the Rust standard library B-tree implementation. Clones
+/// share their data.
+#[derive(Clone)]
 pub struct StdBPlusTree {
     data: Arc<RwLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
 }
@@ -35,6 +37,12 @@ impl KvStore for StdBPlusTree {
         Ok(self.data.read().get(key).cloned())
     }
 
+    /// Looks up all keys under a single read lock.
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let data = self.data.read();
+        Ok(keys.iter().map(|key| data.get(*key).cloned()).collect())
+    }
+
     fn delete(&self, key: &[u8]) -> Result<()> {
         self.data.write().remove(key);
         Ok(())
@@ -45,6 +53,9 @@ impl KvStore for StdBPlusTree {
         // the duration of the iteration. This is too coarse, so we buffer the entire iteration
         // here. An iterator with an arc-mutex should be used instead, which is able to resume
         // iteration by grabbing the lock again.
+        if range.is_empty() {
+            return Ok(Box::new(std::iter::empty()));
+        }
         Ok(Box::new(
             self.data
                 .read()
@@ -58,12 +69,77 @@ impl KvStore for StdBPlusTree {
     fn flush(&self) -> Result<()> {
         Ok(())
     }
+
+    fn entry_count(&self) -> Result<u64> {
+        Ok(self.data.read().len() as u64)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let mut data = self.data.write();
+        for op in ops {
+            match op {
+                WriteOp::Put { key, value } => data.insert(key, value),
+                WriteOp::Delete { key } => data.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let mut data = self.data.write();
+        if data.get(key).map(|v| v.as_slice()) != expected {
+            return Ok(false);
+        }
+        data.insert(key.to_vec(), new_value);
+        Ok(true)
+    }
+
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        if range.is_empty() {
+            return Ok(0);
+        }
+        let mut data = self.data.write();
+        let keys: Vec<Vec<u8>> = data.range(range).map(|(k, _)| k.clone()).collect();
+        for key in keys.iter() {
+            data.remove(key);
+        }
+        Ok(keys.len() as u64)
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        // Holding the write lock across the removal and insertion means readers never observe
+        // both keys or neither.
+        let mut data = self.data.write();
+        let value = data
+            .remove(old_key)
+            .ok_or_else(|| Error::NotFound(format!("Key {:?} not found", old_key)))?;
+        data.insert(new_key.to_vec(), value);
+        Ok(())
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        let mut data = self.data.write();
+        if !data.contains_key(old_key) {
+            return Err(Error::NotFound(format!("Key {:?} not found", old_key)));
+        }
+        if data.contains_key(new_key) {
+            return Ok(false);
+        }
+        let value = data.remove(old_key).unwrap();
+        data.insert(new_key.to_vec(), value);
+        Ok(true)
+    }
 }
 
 #[cfg(test)]
 impl super::TestSuite<StdBPlusTree> for StdBPlusTree {
-    fn setup() -> Result<Self> {
-        Ok(StdBPlusTree::new())
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        Ok((StdBPlusTree::new(), None))
     }
 }
 
@@ -72,3 +148,25 @@ fn tests() -> Result<()> {
     use super::TestSuite;
     StdBPlusTree::test()
 }
+
+#[test]
+fn rename_atomic() -> Result<()> {
+    let s = Arc::new(StdBPlusTree::new());
+    s.set(b"a", vec![0x01])?;
+
+    let reader = {
+        let s = s.clone();
+        std::thread::spawn(move || -> Result<()> {
+            for _ in 0..10_000 {
+                let keys = s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?;
+                assert_eq!(1, keys.len());
+            }
+            Ok(())
+        })
+    };
+    for _ in 0..1000 {
+        s.rename(b"a", b"b")?;
+        s.rename(b"b", b"a")?;
+    }
+    reader.join().unwrap()
+}
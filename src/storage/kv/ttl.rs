@@ -0,0 +1,302 @@
+use std::fmt::Display;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+
+use super::{KvScan, KvStore, Range, WriteOp};
+use crate::storage::background::Maintenance;
+use crate::error::{Error, Result};
+
+/// The length of the expiry header prepended to each value.
+const HEADER_LEN: usize = 8;
+
+/// A clock returning the current time in milliseconds since the Unix epoch.
+type Clock = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// A key-value store wrapper which supports keys with a time-to-live. Expired keys are never
+/// returned by reads, but remain in the inner store until they are overwritten, deleted, or
+/// removed by purge_expired().
+///
+/// Each value in the inner store is prefixed with its expiry time as `[expires_at: u64]`, in
+/// big-endian milliseconds since the Unix epoch, where 0 means the key never expires.
+pub struct TtlStore<S: KvStore> {
+    /// The inner store.
+    inner: S,
+    /// The clock used to determine expiry.
+    clock: Clock,
+    /// Held while writing, so purge_expired() never deletes a key that was just rewritten.
+    writes: Mutex<()>,
+}
+
+impl<S: KvStore> TtlStore<S> {
+    /// Wraps a store, using the system clock.
+    pub fn new(inner: S) -> Self {
+        Self::with_clock(inner, || {
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+        })
+    }
+
+    /// Wraps a store, using the given clock in milliseconds since the Unix epoch.
+    pub fn with_clock(inner: S, clock: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        Self { inner, clock: Box::new(clock), writes: Mutex::new(()) }
+    }
+
+    /// Sets a value for a key which expires after the given time-to-live.
+    pub fn set_with_ttl(&self, key: &[u8], value: Vec<u8>, ttl: Duration) -> Result<()> {
+        let expires_at = (self.clock)().saturating_add(ttl.as_millis() as u64).max(1);
+        let _writes = self.writes.lock();
+        self.inner.set(key, encode(expires_at, value))
+    }
+
+    /// Deletes all expired keys, returning the number of deleted keys.
+    pub fn purge_expired(&self) -> Result<u64> {
+        let _writes = self.writes.lock();
+        let now = (self.clock)();
+        let mut ops = Vec::new();
+        for item in self.inner.scan(Range::from(..))? {
+            let (key, value) = item?;
+            if matches!(decode(value)?, Some((expires_at, _)) if is_expired(expires_at, now)) {
+                ops.push(WriteOp::Delete { key });
+            }
+        }
+        let count = ops.len() as u64;
+        self.inner.write_batch(ops)?;
+        Ok(count)
+    }
+
+    /// Gets a value from the inner store, if it exists and hasn't expired.
+    fn get_live(&self, key: &[u8], now: u64) -> Result<Option<Vec<u8>>> {
+        match self.inner.get(key)?.map(decode).transpose()?.flatten() {
+            Some((expires_at, value)) if !is_expired(expires_at, now) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Returns whether an expiry time has passed, where 0 never expires.
+fn is_expired(expires_at: u64, now: u64) -> bool {
+    expires_at != 0 && expires_at <= now
+}
+
+/// Encodes a value with its expiry time.
+fn encode(expires_at: u64, value: Vec<u8>) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + value.len());
+    bytes.extend(expires_at.to_be_bytes());
+    bytes.extend(value);
+    bytes
+}
+
+/// Decodes a value and its expiry time. Values without a valid header are errors, except for
+/// tombstones (empty values) which some backends expose, and which are returned as None.
+fn decode(mut bytes: Vec<u8>) -> Result<Option<(u64, Vec<u8>)>> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::Internal(format!("Invalid TTL value {:?}", bytes)));
+    }
+    let expires_at = u64::from_be_bytes(bytes[..HEADER_LEN].try_into()?);
+    bytes.drain(..HEADER_LEN);
+    Ok(Some((expires_at, bytes)))
+}
+
+impl<S: KvStore> Display for TtlStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ttl({})", self.inner)
+    }
+}
+
+impl<S: KvStore> KvStore for TtlStore<S> {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.set(key, encode(0, value))
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.get_live(key, (self.clock)())
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        let _writes = self.writes.lock();
+        self.inner.delete(key)
+    }
+
+    /// Expired keys are skipped lazily, as of the time the scan started.
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        let now = (self.clock)();
+        Ok(Box::new(self.inner.scan(range)?.filter_map(move |item| {
+            let (key, value) = match item {
+                Ok(item) => item,
+                Err(err) => return Some(Err(err)),
+            };
+            match decode(value) {
+                Ok(Some((expires_at, value))) if !is_expired(expires_at, now) => {
+                    Some(Ok((key, value)))
+                }
+                Ok(_) => None,
+                Err(err) => Some(Err(err)),
+            }
+        })))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+
+    /// Moves the value along with its expiry time. Expired keys count as nonexistent.
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        let _writes = self.writes.lock();
+        if self.get_live(old_key, (self.clock)())?.is_none() {
+            return Err(Error::NotFound(format!("Key {:?} not found", old_key)));
+        }
+        self.inner.rename(old_key, new_key)
+    }
+
+    /// Expired keys count as nonexistent, so they can be renamed over.
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        let _writes = self.writes.lock();
+        let now = (self.clock)();
+        if self.get_live(old_key, now)?.is_none() {
+            return Err(Error::NotFound(format!("Key {:?} not found", old_key)));
+        }
+        if self.get_live(new_key, now)?.is_some() {
+            return Ok(false);
+        }
+        self.inner.rename(old_key, new_key)?;
+        Ok(true)
+    }
+
+    /// Expired keys count as nonexistent, and a swapped value never expires.
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let _writes = self.writes.lock();
+        if self.get_live(key, (self.clock)())?.as_deref() != expected {
+            return Ok(false);
+        }
+        self.inner.set(key, encode(0, new_value))?;
+        Ok(true)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let ops = ops
+            .into_iter()
+            .map(|op| match op {
+                WriteOp::Put { key, value } => WriteOp::Put { key, value: encode(0, value) },
+                op => op,
+            })
+            .collect();
+        let _writes = self.writes.lock();
+        self.inner.write_batch(ops)
+    }
+}
+
+impl<S: Maintenance> Maintenance for TtlStore<S> {
+    fn purge_expired(&self) -> Result<u64> {
+        TtlStore::purge_expired(self)
+    }
+
+    fn compact(&self) -> Result<()> {
+        self.inner.compact()
+    }
+}
+
+#[cfg(test)]
+impl super::TestSuite<TtlStore<super::StdBPlusTree>> for TtlStore<super::StdBPlusTree> {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        Ok((TtlStore::new(super::StdBPlusTree::new()), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::super::StdBPlusTree;
+    use super::*;
+
+    /// Creates a store with a manual clock, starting at 1000 ms.
+    fn setup() -> (TtlStore<StdBPlusTree>, Arc<AtomicU64>) {
+        let now = Arc::new(AtomicU64::new(1000));
+        let clock = now.clone();
+        (TtlStore::with_clock(StdBPlusTree::new(), move || clock.load(Ordering::SeqCst)), now)
+    }
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        TtlStore::<StdBPlusTree>::test()
+    }
+
+    #[test]
+    fn test_expire_get() -> Result<()> {
+        let (s, now) = setup();
+        s.set_with_ttl(b"a", vec![0x01], Duration::from_millis(100))?;
+        s.set(b"b", vec![0x02])?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+
+        now.store(1099, Ordering::SeqCst);
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        now.store(1100, Ordering::SeqCst);
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(Some(vec![0x02]), s.get(b"b")?);
+
+        // Expired keys can't be renamed, but can be renamed over, and a plain set clears the TTL.
+        assert!(matches!(s.rename(b"a", b"c"), Err(Error::NotFound(_))));
+        s.rename(b"b", b"a")?;
+        assert_eq!(Some(vec![0x02]), s.get(b"a")?);
+        now.store(u64::MAX, Ordering::SeqCst);
+        assert_eq!(Some(vec![0x02]), s.get(b"a")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expire_scan() -> Result<()> {
+        let (s, now) = setup();
+        s.set_with_ttl(b"a", vec![0x01], Duration::from_millis(10))?;
+        s.set_with_ttl(b"b", vec![0x02], Duration::from_millis(20))?;
+        s.set(b"c", vec![0x03])?;
+        s.set_with_ttl(b"d", vec![0x04], Duration::from_millis(30))?;
+
+        now.store(1015, Ordering::SeqCst);
+        let scan = |range| -> Result<Vec<Vec<u8>>> {
+            s.scan(range)?.map(|r| r.map(|(k, _)| k)).collect()
+        };
+        assert_eq!(vec![b"b".to_vec(), b"c".to_vec(), b"d".to_vec()], scan(Range::from(..))?);
+        assert_eq!(
+            vec![(b"d".to_vec(), vec![0x04]), (b"c".to_vec(), vec![0x03])],
+            s.scan(Range::from(b"c".to_vec()..))?.rev().collect::<Result<Vec<_>>>()?
+        );
+        now.store(1025, Ordering::SeqCst);
+        assert_eq!(vec![b"c".to_vec(), b"d".to_vec()], scan(Range::from(..))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_purge_expired() -> Result<()> {
+        let (s, now) = setup();
+        for i in 0..10_u8 {
+            s.set_with_ttl(&[i], vec![i], Duration::from_millis(10 * (i as u64 + 1)))?;
+        }
+        s.set(b"z", vec![0xff])?;
+        assert_eq!(0, s.purge_expired()?);
+
+        now.store(1035, Ordering::SeqCst);
+        assert_eq!(3, s.purge_expired()?);
+        assert_eq!(0, s.purge_expired()?);
+        assert_eq!(None, s.inner.get(&[0])?);
+        assert!(s.inner.get(&[3])?.is_some());
+
+        now.store(u64::MAX, Ordering::SeqCst);
+        assert_eq!(7, s.purge_expired()?);
+        assert_eq!(
+            vec![(b"z".to_vec(), vec![0xff])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+}
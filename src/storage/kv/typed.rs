@@ -0,0 +1,84 @@
+use std::marker::PhantomData;
+
+use super::KvStore;
+use crate::error::Result;
+use crate::storage::codec::Codec;
+
+/// A key-value store wrapper which stores values of type T, serialized by the codec C. Reading
+/// a value that was written with a different codec or type fails with an error.
+pub struct TypedStore<T, C: Codec<T>, S: KvStore> {
+    /// The inner store.
+    inner: S,
+    codec: PhantomData<fn() -> (T, C)>,
+}
+
+impl<T, C: Codec<T>, S: KvStore> TypedStore<T, C, S> {
+    /// Wraps a store.
+    pub fn new(inner: S) -> Self {
+        Self { inner, codec: PhantomData }
+    }
+
+    /// Returns the inner store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Gets and decodes a value for a key, if it exists.
+    pub fn get(&self, key: &[u8]) -> Result<Option<T>> {
+        self.inner.get(key)?.map(|bytes| C::decode(&bytes)).transpose()
+    }
+
+    /// Encodes and sets a value for a key, replacing the existing value if any.
+    pub fn set_or_insert(&self, key: &[u8], value: &T) -> Result<()> {
+        self.inner.set(key, C::encode(value)?)
+    }
+
+    /// Deletes a key, or does nothing if it does not exist.
+    pub fn delete(&self, key: &[u8]) -> Result<()> {
+        self.inner.delete(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::StdBPlusTree;
+    use super::*;
+    use crate::storage::codec::{BincodeCodec, JsonCodec, RawCodec};
+
+    #[test]
+    fn get_set() -> Result<()> {
+        let s: TypedStore<(u64, String), BincodeCodec<_>, _> = TypedStore::new(StdBPlusTree::new());
+        assert_eq!(None, s.get(b"a")?);
+        s.set_or_insert(b"a", &(1, "x".into()))?;
+        s.set_or_insert(b"b", &(2, "y".into()))?;
+        assert_eq!(Some((1, "x".into())), s.get(b"a")?);
+        s.set_or_insert(b"a", &(3, "z".into()))?;
+        assert_eq!(Some((3, "z".into())), s.get(b"a")?);
+        s.delete(b"a")?;
+        assert_eq!(None, s.get(b"a")?);
+        assert_eq!(Some(bincode::serialize(&(2_u64, "y"))?), s.inner().get(b"b")?);
+
+        let s: TypedStore<Vec<u8>, RawCodec, _> = TypedStore::new(StdBPlusTree::new());
+        s.set_or_insert(b"a", &vec![0x01])?;
+        assert_eq!(Some(vec![0x01]), s.inner().get(b"a")?);
+        Ok(())
+    }
+
+    #[test]
+    fn switch_codec() -> Result<()> {
+        let s: TypedStore<Vec<String>, JsonCodec<_>, _> = TypedStore::new(StdBPlusTree::new());
+        s.set_or_insert(b"a", &vec!["x".into(), "y".into()])?;
+
+        // Reading the JSON values with Bincode fails, as does reading them as another type.
+        let s: TypedStore<Vec<String>, BincodeCodec<_>, _> = TypedStore::new(s.inner);
+        assert!(s.get(b"a").is_err());
+        let s: TypedStore<u64, JsonCodec<_>, _> = TypedStore::new(s.inner);
+        assert!(s.get(b"a").is_err());
+
+        // Once rewritten with the new codec, the value reads fine.
+        let s: TypedStore<Vec<String>, BincodeCodec<_>, _> = TypedStore::new(s.inner);
+        s.set_or_insert(b"a", &vec!["z".into()])?;
+        assert_eq!(Some(vec!["z".into()]), s.get(b"a")?);
+        Ok(())
+    }
+}
@@ -0,0 +1,380 @@
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use parking_lot::Mutex;
+use serde_derive::{Deserialize, Serialize};
+
+use super::{KvScan, KvStore, Range, StorageStats, StoreSnapshot, WriteOp};
+use crate::error::{Error, Result};
+
+/// A record in the write-ahead log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Record {
+    /// A single set or delete.
+    Write(WriteOp),
+    /// A batch of writes, which is replayed either completely or not at all.
+    Batch(Vec<WriteOp>),
+    /// All preceding writes have been flushed to the inner store.
+    Checkpoint,
+}
+
+/// A key-value store wrapper which appends every write to a write-ahead log file before applying
+/// it to the inner store, such that writes that were not flushed by the inner store are recovered
+/// after a crash by replaying them.
+///
+/// Each record is encoded as `[crc32: u32][len: u32][record]`, where the CRC covers the
+/// bincode-serialized record and integers are big-endian. Replay stops at the first truncated or
+/// corrupt record, e.g. from a crash during a write, and the log is truncated there.
+pub struct WalStore<S: KvStore> {
+    /// The inner store.
+    inner: S,
+    /// The log file. Held locked while applying a write, so the log order is the apply order.
+    file: Mutex<File>,
+}
+
+impl<S: KvStore> WalStore<S> {
+    /// Opens or creates a log file at the given path, replaying its writes into the inner store.
+    pub fn open(inner: S, path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let store = Self { inner, file: Mutex::new(file) };
+        store.recover()?;
+        Ok(store)
+    }
+
+    /// Replays the writes after the last checkpoint into the inner store, returning the number of
+    /// replayed records. A truncated or corrupt tail is removed from the log.
+    pub fn recover(&self) -> Result<u64> {
+        let mut file = self.file.lock();
+        file.seek(SeekFrom::Start(0))?;
+        let mut uncommitted = Vec::new();
+        let file_len = file.metadata()?.len();
+        let mut valid_len = 0;
+        while let Some((record, len)) = Self::read_record(&mut *file, file_len - valid_len)? {
+            match record {
+                Record::Checkpoint => uncommitted.clear(),
+                record => uncommitted.push(record),
+            }
+            valid_len += len;
+        }
+        if valid_len < file_len {
+            file.set_len(valid_len)?;
+        }
+
+        let count = uncommitted.len() as u64;
+        for record in uncommitted {
+            match record {
+                Record::Write(op) => self.inner.write_batch(vec![op])?,
+                Record::Batch(ops) => self.inner.write_batch(ops)?,
+                Record::Checkpoint => {}
+            }
+        }
+        Ok(count)
+    }
+
+    /// Reads the next record and its encoded length, or None at the end of the log or at a
+    /// truncated or corrupt record. The header's length is untrusted until the checksum passes,
+    /// so records claiming more than the remaining bytes are rejected before allocating.
+    fn read_record(reader: &mut impl Read, remaining: u64) -> Result<Option<(Record, u64)>> {
+        let mut read_exact = |buf: &mut [u8]| match reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(err) => Err(Error::from(err)),
+        };
+        let mut header = [0; 8];
+        if !read_exact(&mut header)? {
+            return Ok(None);
+        }
+        let crc = u32::from_be_bytes(header[..4].try_into()?);
+        let len = u32::from_be_bytes(header[4..].try_into()?) as u64;
+        if len > remaining.saturating_sub(8) {
+            return Ok(None);
+        }
+        let mut data = vec![0; len as usize];
+        if !read_exact(&mut data)? || crc32(&data) != crc {
+            return Ok(None);
+        }
+        match bincode::deserialize(&data) {
+            Ok(record) => Ok(Some((record, 8 + data.len() as u64))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Appends a record to the log.
+    fn append(file: &mut File, record: &Record) -> Result<()> {
+        let data = bincode::serialize(record)?;
+        let len = u32::try_from(data.len())
+            .map_err(|_| Error::Value(format!("WAL record too large ({} bytes)", data.len())))?;
+        let mut buf = Vec::with_capacity(8 + data.len());
+        buf.extend(crc32(&data).to_be_bytes());
+        buf.extend(len.to_be_bytes());
+        buf.extend(data);
+        file.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Logs and applies a rename, see KvStore::rename(). If overwrite is false, an occupied
+    /// new_key is left alone and false is returned.
+    fn rename_key(&self, old_key: &[u8], new_key: &[u8], overwrite: bool) -> Result<bool> {
+        super::rename_batch(
+            &mut *self.file.lock(),
+            old_key,
+            new_key,
+            overwrite,
+            |_, key| self.inner.get(key),
+            |_, key| Ok(self.inner.get(key)?.is_some()),
+            |file, ops| {
+                Self::append(file, &Record::Batch(ops))?;
+                self.inner.rename(old_key, new_key)
+            },
+        )
+    }
+}
+
+impl<S: KvStore> Display for WalStore<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "wal({})", self.inner)
+    }
+}
+
+impl<S: KvStore> KvStore for WalStore<S> {
+    fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        let mut file = self.file.lock();
+        let op = WriteOp::Put { key: key.to_vec(), value: value.clone() };
+        Self::append(&mut file, &Record::Write(op))?;
+        self.inner.set(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key)
+    }
+
+    fn get_multi(&self, keys: &[&[u8]]) -> Result<Vec<Option<Vec<u8>>>> {
+        self.inner.get_multi(keys)
+    }
+
+    fn delete(&self, key: &[u8]) -> Result<()> {
+        let mut file = self.file.lock();
+        Self::append(&mut file, &Record::Write(WriteOp::Delete { key: key.to_vec() }))?;
+        self.inner.delete(key)
+    }
+
+    fn scan(&self, range: Range) -> Result<KvScan> {
+        self.inner.scan(range)
+    }
+
+    /// Flushes the inner store, then checkpoints and truncates the log. The checkpoint is synced
+    /// before truncating, so a crash in between never replays already flushed writes.
+    fn flush(&self) -> Result<()> {
+        let mut file = self.file.lock();
+        self.inner.flush()?;
+        Self::append(&mut file, &Record::Checkpoint)?;
+        file.sync_all()?;
+        file.set_len(0)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn rename(&self, old_key: &[u8], new_key: &[u8]) -> Result<()> {
+        self.rename_key(old_key, new_key, true).map(|_| ())
+    }
+
+    fn rename_if_absent(&self, old_key: &[u8], new_key: &[u8]) -> Result<bool> {
+        self.rename_key(old_key, new_key, false)
+    }
+
+    /// Atomic with respect to other writes through this store, since the log is held locked.
+    fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new_value: Vec<u8>,
+    ) -> Result<bool> {
+        let mut file = self.file.lock();
+        if self.inner.get(key)?.as_deref() != expected {
+            return Ok(false);
+        }
+        let op = WriteOp::Put { key: key.to_vec(), value: new_value.clone() };
+        Self::append(&mut file, &Record::Write(op))?;
+        self.inner.set(key, new_value)?;
+        Ok(true)
+    }
+
+    /// Logs the deletes as a single batch, so they are replayed atomically.
+    fn delete_range(&self, range: Range) -> Result<u64> {
+        let mut file = self.file.lock();
+        let ops = self
+            .inner
+            .scan(range)?
+            .map(|r| r.map(|(key, _)| WriteOp::Delete { key }))
+            .collect::<Result<Vec<_>>>()?;
+        let count = ops.len() as u64;
+        if count > 0 {
+            Self::append(&mut file, &Record::Batch(ops.clone()))?;
+            self.inner.write_batch(ops)?;
+        }
+        Ok(count)
+    }
+
+    fn entry_count(&self) -> Result<u64> {
+        self.inner.entry_count()
+    }
+
+    /// Includes the log file in the inner store's disk usage.
+    fn stats(&self) -> Result<StorageStats> {
+        let mut stats = self.inner.stats()?;
+        stats.disk_bytes += self.file.lock().metadata()?.len();
+        Ok(stats)
+    }
+
+    fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let mut file = self.file.lock();
+        Self::append(&mut file, &Record::Batch(ops.clone()))?;
+        self.inner.write_batch(ops)
+    }
+
+    /// Writes are blocked while the inner store is snapshotted, since the log is held locked.
+    fn snapshot(&self) -> Result<StoreSnapshot> {
+        let _file = self.file.lock();
+        self.inner.snapshot()
+    }
+}
+
+/// Computes the CRC-32 (IEEE) checksum of some bytes.
+pub(super) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+impl super::TestSuite<WalStore<super::StdBPlusTree>> for WalStore<super::StdBPlusTree> {
+    fn setup() -> Result<(Self, Option<tempfile::TempDir>)> {
+        let dir = tempfile::tempdir()?;
+        let store = WalStore::open(super::StdBPlusTree::new(), dir.path().join("wal.log"))?;
+        Ok((store, Some(dir)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::StdBPlusTree;
+    use super::*;
+
+    #[test]
+    fn tests() -> Result<()> {
+        use super::super::TestSuite;
+        WalStore::<StdBPlusTree>::test()
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32(b""));
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_recover() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal.log");
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        s.flush()?;
+        s.delete(b"a")?;
+        s.write_batch(vec![
+            WriteOp::Put { key: b"c".to_vec(), value: vec![0x03] },
+            WriteOp::Put { key: b"d".to_vec(), value: vec![0x04] },
+        ])?;
+        s.rename(b"d", b"e")?;
+        drop(s);
+
+        // Only writes after the checkpoint are replayed, since the inner store has no data.
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        assert_eq!(3, s.recover()?);
+        assert_eq!(
+            vec![(b"c".to_vec(), vec![0x03]), (b"e".to_vec(), vec![0x04])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_truncated() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal.log");
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        s.set(b"a", vec![0x01])?;
+        s.write_batch(vec![
+            WriteOp::Put { key: b"b".to_vec(), value: vec![0x02] },
+            WriteOp::Put { key: b"c".to_vec(), value: vec![0x03] },
+        ])?;
+        drop(s);
+
+        // Chop off the end of the batch, as if the process crashed mid-write. None of the batch
+        // is recovered, and the partial record is removed so later writes can be replayed.
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(file.metadata()?.len() - 3)?;
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+        s.set(b"d", vec![0x04])?;
+        drop(s);
+
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        assert_eq!(2, s.recover()?);
+        assert_eq!(
+            vec![(b"a".to_vec(), vec![0x01]), (b"d".to_vec(), vec![0x04])],
+            s.scan(Range::from(..))?.collect::<Result<Vec<_>>>()?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_corrupt() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal.log");
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        s.set(b"a", vec![0x01])?;
+        s.set(b"b", vec![0x02])?;
+        drop(s);
+
+        // Flip a byte in the last record's payload, which fails its checksum.
+        let mut data = std::fs::read(&path)?;
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        std::fs::write(&path, data)?;
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(None, s.get(b"b")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_recover_oversized_length() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("wal.log");
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        s.set(b"a", vec![0x01])?;
+        drop(s);
+
+        // Append a header claiming a 4 GB record. It is dropped as a corrupt tail without
+        // allocating its length.
+        let mut data = std::fs::read(&path)?;
+        let valid_len = data.len() as u64;
+        data.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0x01]);
+        std::fs::write(&path, data)?;
+        let s = WalStore::open(StdBPlusTree::new(), &path)?;
+        assert_eq!(Some(vec![0x01]), s.get(b"a")?);
+        assert_eq!(valid_len, std::fs::metadata(&path)?.len());
+        Ok(())
+    }
+}
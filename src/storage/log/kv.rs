@@ -0,0 +1,258 @@
+use std::fmt::Display;
+use std::ops::Bound;
+
+use crate::error::{Error, Result};
+use crate::storage::kv::{self, KvStore, WriteOp};
+use super::{LogScan, LogStore, Range};
+
+/// A log store backed by a key-value store, such that the log survives restarts when the
+/// key-value store does. Entries are written through to the key-value store immediately, and
+/// are stored at keys `raft:log:{index:016x}`, whose bytewise order is the index order.
+/// The commit index, compaction offset and metadata are stored at separate `raft:` keys.
+pub struct KvLog {
+    store: Box<dyn KvStore>,
+    /// The index of the last entry, including any compacted entries.
+    last_index: u64,
+    /// The committed index.
+    commit_index: u64,
+    /// The number of compacted entries preceding the log.
+    offset: u64,
+}
+
+impl KvLog {
+    /// The key prefix of log entries.
+    const ENTRY_PREFIX: &'static [u8] = b"raft:log:";
+    /// The key prefix of metadata values.
+    const METADATA_PREFIX: &'static [u8] = b"raft:meta:";
+    /// The key of the committed index.
+    const COMMIT_KEY: &'static [u8] = b"raft:commit";
+    /// The key of the compaction offset.
+    const OFFSET_KEY: &'static [u8] = b"raft:offset";
+
+    /// Opens a log in a key-value store, restoring the last and committed indexes from any
+    /// entries already in it.
+    pub fn open(store: Box<dyn KvStore>) -> Result<Self> {
+        let offset = Self::get_u64(&*store, Self::OFFSET_KEY)?;
+        let commit_index = Self::get_u64(&*store, Self::COMMIT_KEY)?;
+        let last_index = match store.prefix_scan(Self::ENTRY_PREFIX)?.next_back().transpose()? {
+            Some((key, _)) => Self::decode_key(&key)?,
+            None => offset,
+        };
+        Ok(Self { store, last_index, commit_index, offset })
+    }
+
+    /// Returns the key of the entry at an index.
+    fn entry_key(index: u64) -> Vec<u8> {
+        [Self::ENTRY_PREFIX, format!("{:016x}", index).as_bytes()].concat()
+    }
+
+    /// Decodes the index of an entry key.
+    fn decode_key(key: &[u8]) -> Result<u64> {
+        key.strip_prefix(Self::ENTRY_PREFIX)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| Error::Corruption(format!("Invalid log entry key {:?}", key)))
+    }
+
+    /// Reads a big-endian integer, or 0 if the key does not exist.
+    fn get_u64(store: &dyn KvStore, key: &[u8]) -> Result<u64> {
+        match store.get(key)? {
+            Some(bytes) => Ok(u64::from_be_bytes(bytes.as_slice().try_into()?)),
+            None => Ok(0),
+        }
+    }
+
+    /// Returns a write op storing an integer.
+    fn encode_u64(key: &[u8], value: u64) -> WriteOp {
+        WriteOp::Put { key: key.to_vec(), value: value.to_be_bytes().to_vec() }
+    }
+
+    /// Returns the key range of the entries between two indexes, inclusive.
+    fn entry_range(start: u64, end: u64) -> kv::Range {
+        kv::Range::from(Self::entry_key(start)..=Self::entry_key(end))
+    }
+}
+
+impl Display for KvLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kv log on {}", self.store)
+    }
+}
+
+impl LogStore for KvLog {
+    fn append(&mut self, entry: Vec<u8>) -> Result<u64> {
+        self.store.set(&Self::entry_key(self.last_index + 1), entry)?;
+        self.last_index += 1;
+        Ok(self.last_index)
+    }
+
+    fn commit(&mut self, index: u64) -> Result<()> {
+        if index > self.len() {
+            return Err(Error::Internal(format!("Cannot commit non-existant index {}", index)));
+        }
+        if index < self.commit_index {
+            return Err(Error::Internal(format!(
+                "Cannot commit below current index {}",
+                self.commit_index
+            )));
+        }
+        self.store.set(Self::COMMIT_KEY, index.to_be_bytes().to_vec())?;
+        self.commit_index = index;
+        Ok(())
+    }
+
+    fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
+        match index {
+            i if i <= self.offset || i > self.last_index => Ok(None),
+            i => self.store.get(&Self::entry_key(i)),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.last_index
+    }
+
+    fn scan(&self, range: Range) -> LogScan<'_> {
+        let start = match range.start {
+            Bound::Included(n) => n,
+            Bound::Excluded(n) => n.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end {
+            Bound::Included(n) => n,
+            Bound::Excluded(0) => return Box::new(std::iter::empty()),
+            Bound::Excluded(n) => n - 1,
+            Bound::Unbounded => u64::MAX,
+        };
+        let (start, end) = (start.max(self.offset + 1), end.min(self.last_index));
+        if start > end {
+            return Box::new(std::iter::empty());
+        }
+        match self.store.scan(Self::entry_range(start, end)) {
+            Ok(scan) => Box::new(scan.map(|r| r.map(|(_, entry)| entry))),
+            Err(err) => Box::new(std::iter::once(Err(err))),
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.scan(Range::from(..)).filter_map(|r| r.ok()).map(|v| v.len() as u64).sum()
+    }
+
+    fn truncate(&mut self, index: u64) -> Result<u64> {
+        if index < self.commit_index {
+            return Err(Error::Internal(format!(
+                "Cannot truncate below commit_index index {}",
+                self.commit_index
+            )));
+        }
+        if index < self.last_index {
+            self.store.delete_range(Self::entry_range(index + 1, self.last_index))?;
+            self.last_index = index;
+        }
+        Ok(self.len())
+    }
+
+    fn compact(&mut self, index: u64) -> Result<()> {
+        if index <= self.offset {
+            return Ok(());
+        }
+        // The offset is recorded first, so a crash before the entries are deleted leaves
+        // unreachable entries rather than a gap.
+        let commit_index = self.commit_index.max(index);
+        self.store.write_batch(vec![
+            Self::encode_u64(Self::OFFSET_KEY, index),
+            Self::encode_u64(Self::COMMIT_KEY, commit_index),
+        ])?;
+        self.store.delete_range(Self::entry_range(self.offset + 1, index))?;
+        self.offset = index;
+        self.commit_index = commit_index;
+        self.last_index = self.last_index.max(index);
+        Ok(())
+    }
+
+    fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.store.get(&[Self::METADATA_PREFIX, key].concat())
+    }
+
+    fn set_metadata(&mut self, key: &[u8], value: Vec<u8>) -> Result<()> {
+        self.store.set(&[Self::METADATA_PREFIX, key].concat(), value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::{BitcaskStore, MemoryStore};
+
+    fn entries(log: &KvLog, range: Range) -> Result<Vec<Vec<u8>>> {
+        log.scan(range).collect()
+    }
+
+    #[test]
+    fn log() -> Result<()> {
+        let mut log = KvLog::open(Box::new(MemoryStore::new()))?;
+        for entry in [b"a", b"b", b"c", b"d"] {
+            log.append(entry.to_vec())?;
+        }
+        assert_eq!(4, log.len());
+        assert_eq!(Some(b"b".to_vec()), log.get(2)?);
+        assert_eq!(None, log.get(5)?);
+        assert_eq!(vec![b"b".to_vec(), b"c".to_vec()], entries(&log, Range::from(2..4))?);
+        assert_eq!(4, log.size());
+
+        // Committed entries can't be truncated.
+        log.commit(2)?;
+        assert!(log.truncate(1).is_err());
+        assert_eq!(3, log.truncate(3)?);
+        assert_eq!(None, log.get(4)?);
+        assert_eq!(4, log.append(b"e".to_vec())?);
+        assert_eq!(Some(b"e".to_vec()), log.get(4)?);
+
+        // Compaction discards entries but keeps the indexes of later ones.
+        log.compact(2)?;
+        assert_eq!(None, log.get(2)?);
+        assert_eq!(vec![b"c".to_vec(), b"e".to_vec()], entries(&log, Range::from(..))?);
+        log.compact(6)?;
+        assert_eq!((6, 6), (log.len(), log.commit_index()));
+        assert!(entries(&log, Range::from(..))?.is_empty());
+        assert_eq!(7, log.append(b"f".to_vec())?);
+
+        log.set_metadata(b"key", b"value".to_vec())?;
+        assert_eq!(Some(b"value".to_vec()), log.get_metadata(b"key")?);
+        assert_eq!(None, log.get_metadata(b"other")?);
+        Ok(())
+    }
+
+    #[test]
+    fn crash_recovery() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut log = KvLog::open(Box::new(BitcaskStore::open(dir.path())?))?;
+        for entry in [b"a", b"b", b"c", b"d"] {
+            log.append(entry.to_vec())?;
+        }
+        log.commit(3)?;
+        log.truncate(3)?;
+        log.compact(1)?;
+        log.set_metadata(b"key", b"value".to_vec())?;
+
+        // The log is dropped without flushing, and reopened with its state intact.
+        drop(log);
+        let mut log = KvLog::open(Box::new(BitcaskStore::open(dir.path())?))?;
+        assert_eq!((3, 3), (log.len(), log.commit_index()));
+        assert_eq!(None, log.get(1)?);
+        assert_eq!(vec![b"b".to_vec(), b"c".to_vec()], entries(&log, Range::from(..))?);
+        assert_eq!(Some(b"value".to_vec()), log.get_metadata(b"key")?);
+
+        // Appends continue after the last entry, also across restarts.
+        assert_eq!(4, log.append(b"e".to_vec())?);
+        drop(log);
+        let log = KvLog::open(Box::new(BitcaskStore::open(dir.path())?))?;
+        assert_eq!(4, log.len());
+        assert_eq!(Some(b"e".to_vec()), log.get(4)?);
+        Ok(())
+    }
+}
@@ -60,6 +60,10 @@ impl LogStore for LogDemo {
         self.store.write()?.truncate(index)
     }
 
+    fn compact(&mut self, index: u64) -> Result<()> {
+        self.store.write()?.compact(index)
+    }
+
     fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.store.read()?.get_metadata(key)
     }
@@ -73,6 +77,8 @@ impl LogStore for LogDemo {
 // An in-memory log store.
 pub struct Memory {
     log: Vec<Vec<u8>>,
+    /// The number of compacted entries preceding the log.
+    offset: u64,
     commit_index: u64,
     metadata: HashMap<Vec<u8>, Vec<u8>>,
 }
@@ -80,7 +86,7 @@ pub struct Memory {
 impl Memory {
     /// Creates a new in-memory log.
     pub fn new() -> Self {
-        Self { log: Vec::new(), commit_index: 0, metadata: HashMap::new() }
+        Self { log: Vec::new(), offset: 0, commit_index: 0, metadata: HashMap::new() }
     }
 }
 
@@ -93,7 +99,7 @@ impl Display for Memory {
 impl LogStore for Memory {
     fn append(&mut self, entry: Vec<u8>) -> Result<u64> {
         self.log.push(entry);
-        Ok(self.log.len() as u64)
+        Ok(self.len())
     }
 
     fn commit(&mut self, index: u64) -> Result<()> {
@@ -116,13 +122,13 @@ impl LogStore for Memory {
 
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>> {
         match index {
-            0 => Ok(None),
-            i => Ok(self.log.get(i as usize - 1).cloned()),
+            i if i <= self.offset => Ok(None),
+            i => Ok(self.log.get((i - self.offset) as usize - 1).cloned()),
         }
     }
 
     fn len(&self) -> u64 {
-        self.log.len() as u64
+        self.offset + self.log.len() as u64
     }
 
     fn scan(&self, range: Range) -> LogScan {
@@ -130,15 +136,13 @@ impl LogStore for Memory {
             self.log
                 .iter()
                 .take(match range.end {
-                    Bound::Included(n) => n as usize,
-                    Bound::Excluded(0) => 0,
-                    Bound::Excluded(n) => n as usize - 1,
+                    Bound::Included(n) => n.saturating_sub(self.offset) as usize,
+                    Bound::Excluded(n) => n.saturating_sub(self.offset + 1) as usize,
                     Bound::Unbounded => std::usize::MAX,
                 })
                 .skip(match range.start {
-                    Bound::Included(0) => 0,
-                    Bound::Included(n) => n as usize - 1,
-                    Bound::Excluded(n) => n as usize,
+                    Bound::Included(n) => n.saturating_sub(self.offset + 1) as usize,
+                    Bound::Excluded(n) => n.saturating_sub(self.offset) as usize,
                     Bound::Unbounded => 0,
                 })
                 .cloned()
@@ -157,8 +161,19 @@ impl LogStore for Memory {
                 self.commit_index
             )));
         }
-        self.log.truncate(index as usize);
-        Ok(self.log.len() as u64)
+        self.log.truncate((index - self.offset) as usize);
+        Ok(self.len())
+    }
+
+    fn compact(&mut self, index: u64) -> Result<()> {
+        if index <= self.offset {
+            return Ok(());
+        }
+        let count = std::cmp::min(index - self.offset, self.log.len() as u64);
+        self.log.drain(..count as usize);
+        self.offset = index;
+        self.commit_index = std::cmp::max(self.commit_index, index);
+        Ok(())
     }
 
     fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
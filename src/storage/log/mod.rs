@@ -1,9 +1,11 @@
+pub mod kv;
 pub mod memory;
 
 use std::fmt::Display;
 use std::ops::{Bound, RangeBounds};
 
 use crate::error::Result;
+pub use kv::KvLog;
 pub use memory::{LogDemo, Memory};
 
 /// A log store. Entry indexes are 1-based, to match Raft semantics.
@@ -20,7 +22,7 @@ pub trait LogStore: Display + Sync + Send {
     /// Fetches a log entry, if it exists.
     fn get(&self, index: u64) -> Result<Option<Vec<u8>>>;
 
-    /// Returns the number of entries in the log.
+    /// Returns the index of the last entry in the log, including any compacted entries.
     fn len(&self) -> u64;
 
     /// Scans the log between the given indexes.
@@ -33,6 +35,11 @@ pub trait LogStore: Display + Sync + Send {
     /// highest index. Errors if asked to truncate any committed entries.
     fn truncate(&mut self, index: u64) -> Result<u64>;
 
+    /// Discards entries up to and including the given index, e.g. once they are included in a
+    /// snapshot, and commits them. Later entries keep their indexes. If the index is past the
+    /// last entry, the log is emptied and the next entry is appended at index + 1.
+    fn compact(&mut self, index: u64) -> Result<()>;
+
     /// Gets a metadata value.
     fn get_metadata(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
 
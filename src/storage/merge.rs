@@ -0,0 +1,305 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::kv::KvScan;
+use crate::error::Result;
+
+/// A key/value pair.
+type Item = (Vec<u8>, Vec<u8>);
+
+/// A merge input, with the next items to be yielded from either end. An item is only ever held
+/// in one of the slots: once the iterator is exhausted, each end takes the other's item.
+struct Source {
+    iter: KvScan,
+    front: Option<Item>,
+    back: Option<Item>,
+}
+
+impl Source {
+    /// Fills the front slot if empty, returning its key.
+    fn fill_front(&mut self) -> Result<Option<&Vec<u8>>> {
+        if self.front.is_none() {
+            self.front = match self.iter.next().transpose()? {
+                Some(item) => Some(item),
+                None => self.back.take(),
+            };
+        }
+        Ok(self.front.as_ref().map(|(key, _)| key))
+    }
+
+    /// Fills the back slot if empty, returning its key.
+    fn fill_back(&mut self) -> Result<Option<&Vec<u8>>> {
+        if self.back.is_none() {
+            self.back = match self.iter.next_back().transpose()? {
+                Some(item) => Some(item),
+                None => self.front.take(),
+            };
+        }
+        Ok(self.back.as_ref().map(|(key, _)| key))
+    }
+}
+
+/// Merges sorted scans into a single sorted scan. Where several scans contain the same key, the
+/// item from the earliest scan in the input takes precedence and the others are skipped, e.g. to
+/// merge MVCC versions by passing the newest scan first.
+///
+/// Each end keeps a heap with one entry per scan, keyed by the scan's next item at that end.
+/// Since an end may take the other end's item once a scan is exhausted, heap entries can be
+/// stale; they are validated when popped, and the scan is refilled if its item was taken.
+///
+/// The first error from any scan is returned, after which the scans are dropped and the
+/// iterator yields no more items.
+pub struct SortedMergeIterator {
+    sources: Vec<Source>,
+    front_heap: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    back_heap: BinaryHeap<(Vec<u8>, Reverse<usize>)>,
+    /// Scans to refill and push onto each heap before its next item. Refills are deferred until
+    /// then, so that an error from a scan isn't returned before the preceding item.
+    front_refill: Vec<usize>,
+    back_refill: Vec<usize>,
+    /// The last key yielded from the back, at or after which the front is done, and vice versa.
+    front_end: Option<Vec<u8>>,
+    back_end: Option<Vec<u8>>,
+}
+
+impl SortedMergeIterator {
+    /// Creates a merge iterator over the given scans, in decreasing order of precedence.
+    pub fn new(iterators: Vec<KvScan>) -> Self {
+        Self {
+            front_refill: (0..iterators.len()).collect(),
+            back_refill: (0..iterators.len()).collect(),
+            sources: iterators
+                .into_iter()
+                .map(|iter| Source { iter, front: None, back: None })
+                .collect(),
+            front_heap: BinaryHeap::new(),
+            back_heap: BinaryHeap::new(),
+            front_end: None,
+            back_end: None,
+        }
+    }
+
+    /// Fills a scan's front slot and pushes it onto the front heap.
+    fn push_front(&mut self, idx: usize) -> Result<()> {
+        if let Some(key) = self.sources[idx].fill_front()? {
+            self.front_heap.push(Reverse((key.clone(), idx)));
+        }
+        Ok(())
+    }
+
+    /// Fills a scan's back slot and pushes it onto the back heap.
+    fn push_back(&mut self, idx: usize) -> Result<()> {
+        if let Some(key) = self.sources[idx].fill_back()? {
+            self.back_heap.push((key.clone(), Reverse(idx)));
+        }
+        Ok(())
+    }
+
+    /// Pops the front heap's smallest valid entry, taking the scan's item. If only is
+    /// given, stops at entries with a different key.
+    fn pop_front(&mut self, only: Option<&Vec<u8>>) -> Result<Option<Item>> {
+        while let Some(Reverse((key, _))) = self.front_heap.peek() {
+            if only.is_some_and(|only| key != only) {
+                break;
+            }
+            let Reverse((key, idx)) = self.front_heap.pop().unwrap();
+            match self.sources[idx].front.take_if(|(k, _)| *k == key) {
+                Some(item) => {
+                    self.front_refill.push(idx);
+                    return Ok(Some(item));
+                }
+                // The back took the item, so refill the front.
+                None => self.push_front(idx)?,
+            }
+        }
+        Ok(None)
+    }
+
+    /// Pops the back heap's largest valid entry, taking the scan's item. If only is
+    /// given, stops at entries with a different key.
+    fn pop_back(&mut self, only: Option<&Vec<u8>>) -> Result<Option<Item>> {
+        while let Some((key, _)) = self.back_heap.peek() {
+            if only.is_some_and(|only| key != only) {
+                break;
+            }
+            let (key, Reverse(idx)) = self.back_heap.pop().unwrap();
+            match self.sources[idx].back.take_if(|(k, _)| *k == key) {
+                Some(item) => {
+                    self.back_refill.push(idx);
+                    return Ok(Some(item));
+                }
+                // The front took the item, so refill the back.
+                None => self.push_back(idx)?,
+            }
+        }
+        Ok(None)
+    }
+
+    fn try_next(&mut self) -> Result<Option<Item>> {
+        while let Some(idx) = self.front_refill.pop() {
+            self.push_front(idx)?;
+        }
+        let Some((key, value)) = self.pop_front(None)? else { return Ok(None) };
+        if self.front_end.as_ref().is_some_and(|end| key >= *end) {
+            self.front_heap.clear();
+            return Ok(None);
+        }
+        // Skip the key in lower-precedence scans.
+        while self.pop_front(Some(&key))?.is_some() {}
+        self.back_end = Some(key.clone());
+        Ok(Some((key, value)))
+    }
+
+    fn try_next_back(&mut self) -> Result<Option<Item>> {
+        while let Some(idx) = self.back_refill.pop() {
+            self.push_back(idx)?;
+        }
+        let Some((key, value)) = self.pop_back(None)? else { return Ok(None) };
+        if self.back_end.as_ref().is_some_and(|end| key <= *end) {
+            self.back_heap.clear();
+            return Ok(None);
+        }
+        // Skip the key in lower-precedence scans.
+        while self.pop_back(Some(&key))?.is_some() {}
+        self.front_end = Some(key.clone());
+        Ok(Some((key, value)))
+    }
+
+    /// Drops the scans after an error, so no more items are yielded.
+    fn fail(&mut self) {
+        self.sources.clear();
+        self.front_heap.clear();
+        self.back_heap.clear();
+        self.front_refill.clear();
+        self.back_refill.clear();
+    }
+}
+
+impl Iterator for SortedMergeIterator {
+    type Item = Result<Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.try_next().transpose();
+        if let Some(Err(_)) = result {
+            self.fail();
+        }
+        result
+    }
+}
+
+impl DoubleEndedIterator for SortedMergeIterator {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let result = self.try_next_back().transpose();
+        if let Some(Err(_)) = result {
+            self.fail();
+        }
+        result
+    }
+}
+
+/// Merges sorted scans into a single sorted scan, see SortedMergeIterator.
+pub fn merge(iterators: Vec<KvScan>) -> KvScan {
+    Box::new(SortedMergeIterator::new(iterators))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::error::Error;
+
+    fn scan(items: Vec<Item>) -> KvScan {
+        Box::new(items.into_iter().map(Ok))
+    }
+
+    fn item(key: u8, value: u8) -> Item {
+        (vec![key], vec![value])
+    }
+
+    #[test]
+    fn test_merge_precedence() -> Result<()> {
+        let scans = || {
+            vec![
+                scan(vec![item(1, 0), item(3, 0)]),
+                scan(vec![]),
+                scan(vec![item(2, 2), item(3, 2), item(5, 2)]),
+                scan(vec![item(1, 3), item(5, 3), item(6, 3)]),
+            ]
+        };
+        let expect = vec![item(1, 0), item(2, 2), item(3, 0), item(5, 2), item(6, 3)];
+        assert_eq!(expect, merge(scans()).collect::<Result<Vec<_>>>()?);
+        let reversed: Vec<_> = expect.iter().rev().cloned().collect();
+        assert_eq!(reversed, merge(scans()).rev().collect::<Result<Vec<_>>>()?);
+
+        // Meeting in the middle yields each key once.
+        let mut iter = merge(scans());
+        assert_eq!(Some(item(1, 0)), iter.next().transpose()?);
+        assert_eq!(Some(item(6, 3)), iter.next_back().transpose()?);
+        assert_eq!(Some(item(5, 2)), iter.next_back().transpose()?);
+        assert_eq!(Some(item(2, 2)), iter.next().transpose()?);
+        assert_eq!(Some(item(3, 0)), iter.next_back().transpose()?);
+        assert_eq!(None, iter.next().transpose()?);
+        assert_eq!(None, iter.next_back().transpose()?);
+        assert!(merge(vec![]).next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_error() {
+        let failing: KvScan = Box::new(
+            vec![Ok(item(2, 1)), Err(Error::Internal("boom".into())), Ok(item(4, 1))].into_iter(),
+        );
+        let mut iter = merge(vec![scan(vec![item(1, 0), item(3, 0)]), failing]);
+        assert_eq!(Some(item(1, 0)), iter.next().transpose().unwrap());
+        assert_eq!(Some(item(2, 1)), iter.next().transpose().unwrap());
+        assert!(matches!(iter.next(), Some(Err(Error::Internal(_)))));
+        assert!(iter.next().is_none());
+        assert!(iter.next_back().is_none());
+    }
+
+    #[test]
+    fn test_merge_fuzz() -> Result<()> {
+        for seed in 0..200 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let num_scans = rng.gen_range(0..6);
+            let mut inputs = Vec::new();
+            let mut expect = BTreeMap::new();
+            for idx in 0..num_scans {
+                let len = rng.gen_range(0..20);
+                let keys: BTreeMap<u8, u8> =
+                    (0..len).map(|_| (rng.gen_range(0..32), idx)).collect();
+                for (&key, &value) in keys.iter() {
+                    expect.entry(vec![key]).or_insert(vec![value]);
+                }
+                inputs.push(keys.into_iter().map(|(k, v)| item(k, v)).collect::<Vec<_>>());
+            }
+            let expect: Vec<Item> = expect.into_iter().collect();
+            let scans = || inputs.iter().cloned().map(scan).collect::<Vec<_>>();
+
+            assert_eq!(expect, merge(scans()).collect::<Result<Vec<_>>>()?, "seed {}", seed);
+            let mut reversed = merge(scans()).rev().collect::<Result<Vec<_>>>()?;
+            reversed.reverse();
+            assert_eq!(expect, reversed, "seed {}", seed);
+
+            // Randomly interleave both ends.
+            let mut iter = merge(scans());
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+            loop {
+                let next = match rng.gen_bool(0.5) {
+                    true => iter.next().transpose()?.map(|item| front.push(item)),
+                    false => iter.next_back().transpose()?.map(|item| back.push(item)),
+                };
+                if next.is_none() {
+                    break;
+                }
+            }
+            front.extend(back.into_iter().rev());
+            assert_eq!(expect, front, "seed {}", seed);
+        }
+        Ok(())
+    }
+}
@@ -1,2 +1,8 @@
+pub mod async_store;
+pub mod background;
+pub mod codec;
 pub mod kv;
-pub mod log;
\ No newline at end of file
+pub mod log;
+pub mod merge;
+pub mod scan_ext;
+pub mod table;
\ No newline at end of file
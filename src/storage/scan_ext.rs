@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+
+use super::kv::{FilteredScan, KvScan};
+use crate::error::Result;
+
+/// A key/value pair.
+type Item = (Vec<u8>, Vec<u8>);
+
+/// Adapters for key/value scans which return a KvScan, so they can be chained and iterated from
+/// either end. Errors from the underlying scan are always yielded, never skipped or counted.
+pub trait ScanExt: DoubleEndedIterator<Item = Result<Item>> + Send + Sized + 'static {
+    /// Transforms each key/value pair. An error from the function is yielded in its place.
+    fn scan_map<F>(self, f: F) -> KvScan
+    where
+        F: FnMut(Item) -> Result<Item> + Send + 'static,
+    {
+        let mut f = f;
+        Box::new(self.map(move |item| item.and_then(&mut f)))
+    }
+
+    /// Only yields the key/value pairs satisfying a predicate.
+    fn scan_filter<F>(self, predicate: F) -> KvScan
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Send + 'static,
+    {
+        Box::new(FilteredScan::new(Box::new(self), predicate))
+    }
+
+    /// Yields at most the first n key/value pairs.
+    fn scan_take(self, n: usize) -> KvScan {
+        Box::new(TakeScan { scan: Box::new(self), remaining: n, buffer: None })
+    }
+
+    /// Skips the first n key/value pairs.
+    fn scan_skip(self, n: usize) -> KvScan {
+        Box::new(SkipScan { scan: Box::new(self), skip: n, errors: VecDeque::new() })
+    }
+
+    /// Collects the key/value pairs, failing at the first error.
+    fn scan_collect(self) -> Result<Vec<Item>> {
+        self.collect()
+    }
+}
+
+impl<I> ScanExt for I where I: DoubleEndedIterator<Item = Result<Item>> + Send + 'static {}
+
+/// A scan yielding at most n key/value pairs from the front. Iterating from the back needs to
+/// know where the first n pairs end, so it buffers them.
+struct TakeScan {
+    scan: KvScan,
+    /// The number of pairs left to take from the scan.
+    remaining: usize,
+    /// The buffered items, once iterated from the back.
+    buffer: Option<VecDeque<Result<Item>>>,
+}
+
+impl Iterator for TakeScan {
+    type Item = Result<Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(buffer) = &mut self.buffer {
+            return buffer.pop_front();
+        }
+        if self.remaining == 0 {
+            return None;
+        }
+        let item = self.scan.next()?;
+        if item.is_ok() {
+            self.remaining -= 1;
+        }
+        Some(item)
+    }
+}
+
+impl DoubleEndedIterator for TakeScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.buffer.is_none() {
+            self.buffer = Some(self.by_ref().collect());
+        }
+        self.buffer.as_mut()?.pop_back()
+    }
+}
+
+/// A scan skipping the first n key/value pairs. Errors met while skipping are yielded first.
+struct SkipScan {
+    scan: KvScan,
+    /// The number of pairs left to skip.
+    skip: usize,
+    /// Errors met while skipping, yet to be yielded.
+    errors: VecDeque<Result<Item>>,
+}
+
+impl SkipScan {
+    /// Skips the remaining pairs from the front of the scan.
+    fn skip(&mut self) {
+        while self.skip > 0 {
+            match self.scan.next() {
+                Some(Ok(_)) => self.skip -= 1,
+                Some(Err(err)) => self.errors.push_back(Err(err)),
+                None => self.skip = 0,
+            }
+        }
+    }
+}
+
+impl Iterator for SkipScan {
+    type Item = Result<Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.skip();
+        self.errors.pop_front().or_else(|| self.scan.next())
+    }
+}
+
+impl DoubleEndedIterator for SkipScan {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.skip();
+        self.errors.pop_front().or_else(|| self.scan.next_back())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    /// Returns a scan over the pairs a=1, b=2, ..., with an error in place of the given keys.
+    fn scan(keys: &str, errors: &str) -> KvScan {
+        let items: Vec<Result<Item>> = keys
+            .bytes()
+            .map(|k| match errors.as_bytes().contains(&k) {
+                true => Err(Error::Internal(format!("bad {}", k as char))),
+                false => Ok((vec![k], vec![k - b'a' + 1])),
+            })
+            .collect();
+        Box::new(items.into_iter())
+    }
+
+    fn keys(scan: impl Iterator<Item = Result<Item>>) -> Vec<String> {
+        scan.map(|item| match item {
+            Ok((key, _)) => String::from_utf8(key).unwrap(),
+            Err(err) => format!("{}", err),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn chain() -> Result<()> {
+        let odd = |_: &[u8], value: &[u8]| value[0] % 2 == 1;
+        let upper = |(key, value): Item| Ok((key.to_ascii_uppercase(), value));
+        assert_eq!(
+            vec!["C", "E", "G"],
+            keys(scan("abcdefghij", "").scan_filter(odd).scan_skip(1).scan_take(3).scan_map(upper))
+        );
+        assert_eq!(
+            vec!["G", "E", "C"],
+            keys(
+                scan("abcdefghij", "")
+                    .scan_filter(odd)
+                    .scan_skip(1)
+                    .scan_take(3)
+                    .scan_map(upper)
+                    .rev()
+            )
+        );
+
+        // Iterating from both ends meets in the middle.
+        let mut s = scan("abcdefghij", "").scan_skip(2).scan_take(5);
+        assert_eq!(b"c".to_vec(), s.next().unwrap()?.0);
+        assert_eq!(b"g".to_vec(), s.next_back().unwrap()?.0);
+        assert_eq!(vec!["d", "e", "f"], keys(s));
+
+        let last = scan("abcdefghij", "").scan_skip(9).scan_collect()?;
+        assert_eq!(vec![(b"j".to_vec(), vec![10])], last);
+        assert!(scan("abc", "").scan_skip(5).scan_collect()?.is_empty());
+        assert!(scan("abc", "").scan_take(0).scan_collect()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        // Errors are yielded rather than skipped, and don't count towards take or skip.
+        assert_eq!(
+            vec!["bad b", "c", "bad d", "e"],
+            keys(scan("abcdefg", "bd").scan_skip(1).scan_take(2))
+        );
+        assert_eq!(vec!["bad b", "bad d", "g"], keys(scan("abcdefg", "bd").scan_skip(4).rev()));
+        assert_eq!(vec!["c", "bad b", "a"], keys(scan("abcde", "b").scan_take(2).rev()));
+
+        // Mapping errors replace the pair, and collecting fails at the first error.
+        let fail_on_b = |(key, value): Item| match key.as_slice() {
+            b"b" => Err(Error::Value("no b".into())),
+            _ => Ok((key, value)),
+        };
+        assert_eq!(vec!["a", "no b", "c"], keys(scan("abc", "").scan_map(fail_on_b)));
+        let result = scan("abc", "").scan_map(fail_on_b).scan_collect();
+        assert_eq!(Err(Error::Value("no b".into())), result);
+        assert_eq!(
+            Err(Error::Internal("bad c".into())),
+            scan("abcd", "c").scan_filter(|_, _| true).scan_collect()
+        );
+    }
+}
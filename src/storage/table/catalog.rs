@@ -0,0 +1,384 @@
+use std::collections::HashSet;
+
+use serde_derive::{Deserialize, Serialize};
+
+use super::Index;
+use crate::encoding::{encode_string, encode_value};
+use crate::error::{Error, Result};
+use crate::sql::types::encoding::{decode_row, encode_row};
+use crate::sql::types::{DataType, Row, Value};
+use crate::storage::kv::{KvStore, Range, WriteOp};
+
+/// The key prefix of table schemas, followed by the table name.
+const SCHEMA_PREFIX: &[u8] = b"\x00catalog\x00";
+/// The key prefix of table rows, followed by the encoded table name.
+const ROW_PREFIX: &[u8] = b"\x00table\x00";
+
+/// A column definition.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ColumnDef {
+    pub name: String,
+    pub datatype: DataType,
+    pub nullable: bool,
+    /// The value of the column when none is given.
+    pub default: Option<Value>,
+    /// Whether the column has a secondary index, see Index.
+    pub indexed: bool,
+}
+
+/// A table schema.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Schema {
+    pub name: String,
+    pub columns: Vec<ColumnDef>,
+    /// The index of the primary key column.
+    pub primary_key: usize,
+}
+
+impl Schema {
+    /// Validates the schema.
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(Error::Value("Table name cannot be empty".into()));
+        }
+        if self.columns.is_empty() {
+            return Err(Error::Value(format!("Table {} has no columns", self.name)));
+        }
+        let Some(primary_key) = self.columns.get(self.primary_key) else {
+            return Err(Error::Value(format!("No primary key in table {}", self.name)));
+        };
+        if primary_key.nullable {
+            return Err(Error::Value(format!(
+                "Primary key {} cannot be nullable",
+                primary_key.name
+            )));
+        }
+        let mut names = HashSet::new();
+        for column in self.columns.iter() {
+            if !names.insert(column.name.as_str()) {
+                return Err(Error::Value(format!("Duplicate column {}", column.name)));
+            }
+            match &column.default {
+                Some(Value::Null) if !column.nullable => {
+                    return Err(Error::Value(format!(
+                        "Column {} cannot have null default",
+                        column.name
+                    )))
+                }
+                Some(value) if *value != Value::Null && !column.datatype.accepts(value) => {
+                    return Err(Error::Value(format!(
+                        "Default value {} for column {} is not {}",
+                        value, column.name, column.datatype
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A catalog of table schemas, persisted in a key-value store along with the tables' rows.
+/// Schemas are stored under `\x00catalog\x00{name}`, and rows under the prefix returned by
+/// row_prefix().
+pub struct Catalog<S: KvStore> {
+    store: S,
+}
+
+impl<S: KvStore> Catalog<S> {
+    /// Opens a catalog in a store.
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    /// Returns the underlying store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the key prefix of a table's rows. The table name is encoded such that no table's
+    /// prefix is a prefix of another's.
+    pub fn row_prefix(table: &str) -> Vec<u8> {
+        [ROW_PREFIX, &encode_string(table)].concat()
+    }
+
+    /// Returns the key of a row.
+    fn row_key(table: &str, pk: &Value) -> Vec<u8> {
+        [Self::row_prefix(table), encode_value(pk)].concat()
+    }
+
+    /// Returns the key of a table's schema.
+    fn schema_key(table: &str) -> Vec<u8> {
+        [SCHEMA_PREFIX, table.as_bytes()].concat()
+    }
+
+    /// Creates a table, failing with AlreadyExists if a table of the same name exists.
+    pub fn create_table(&self, schema: Schema) -> Result<()> {
+        schema.validate()?;
+        let key = Self::schema_key(&schema.name);
+        if !self.store.compare_and_swap(&key, None, bincode::serialize(&schema)?)? {
+            return Err(Error::AlreadyExists(format!("Table {} already exists", schema.name)));
+        }
+        Ok(())
+    }
+
+    /// Drops a table and deletes its rows and index entries, failing with NotFound if it does
+    /// not exist. The data is deleted first, so an interrupted drop leaves the table in place to
+    /// be dropped again.
+    pub fn drop_table(&self, table: &str) -> Result<()> {
+        self.must_get_table(table)?;
+        self.store.delete_range(Range::from_prefix(&Self::row_prefix(table)))?;
+        self.index().delete_table(table)?;
+        self.store.delete(&Self::schema_key(table))
+    }
+
+    /// Gets a table schema, if the table exists.
+    pub fn get_table(&self, table: &str) -> Result<Option<Schema>> {
+        match self.store.get(&Self::schema_key(table))? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets a table schema, failing with NotFound if the table does not exist.
+    fn must_get_table(&self, table: &str) -> Result<Schema> {
+        self.get_table(table)?
+            .ok_or_else(|| Error::NotFound(format!("Table {} does not exist", table)))
+    }
+
+    /// Returns the secondary indexes.
+    pub fn index(&self) -> Index<'_, S> {
+        Index::new(&self.store)
+    }
+
+    /// Inserts a row, replacing any row with the same primary key, and updates the indexes of
+    /// the table's indexed columns in the same batch. Concurrent writes to the same row must be
+    /// serialized by the caller, e.g. with AtomicStore, since the replaced row is read first.
+    pub fn insert_row(&self, table: &str, row: Row) -> Result<()> {
+        let schema = self.must_get_table(table)?;
+        if row.len() != schema.columns.len() {
+            return Err(Error::Value(format!(
+                "Row has {} columns, expected {}",
+                row.len(),
+                schema.columns.len()
+            )));
+        }
+        let pk = &row[schema.primary_key];
+        if *pk == Value::Null {
+            return Err(Error::Value(format!("Primary key in table {} cannot be null", table)));
+        }
+        let key = Self::row_key(table, pk);
+        let mut ops = Vec::new();
+        if let Some(old) = self.store.get(&key)? {
+            ops.extend(Self::index_ops(&schema, &decode_row(&old)?, Index::<S>::delete_op));
+        }
+        ops.extend(Self::index_ops(&schema, &row, Index::<S>::insert_op));
+        ops.push(WriteOp::Put { key, value: encode_row(&row) });
+        self.store.write_batch(ops)
+    }
+
+    /// Gets a row by primary key, if it exists.
+    pub fn get_row(&self, table: &str, pk: &Value) -> Result<Option<Row>> {
+        self.store.get(&Self::row_key(table, pk))?.map(|bytes| decode_row(&bytes)).transpose()
+    }
+
+    /// Deletes a row by primary key along with its index entries, returning whether it existed.
+    pub fn delete_row(&self, table: &str, pk: &Value) -> Result<bool> {
+        let schema = self.must_get_table(table)?;
+        let Some(row) = self.get_row(table, pk)? else { return Ok(false) };
+        let mut ops = Self::index_ops(&schema, &row, Index::<S>::delete_op);
+        ops.push(WriteOp::Delete { key: Self::row_key(table, pk) });
+        self.store.write_batch(ops)?;
+        Ok(true)
+    }
+
+    /// Returns the index writes for a row's indexed columns.
+    fn index_ops(
+        schema: &Schema,
+        row: &Row,
+        op: fn(&str, &str, &Value, &Value) -> WriteOp,
+    ) -> Vec<WriteOp> {
+        let pk = &row[schema.primary_key];
+        (schema.columns.iter().zip(row.iter()))
+            .filter(|(column, _)| column.indexed)
+            .map(|(column, value)| op(&schema.name, &column.name, value, pk))
+            .collect()
+    }
+
+    /// Lists the table names, in order.
+    pub fn list_tables(&self) -> Result<Vec<String>> {
+        self.store
+            .prefix_scan(SCHEMA_PREFIX)?
+            .map(|item| Ok(String::from_utf8(item?.0[SCHEMA_PREFIX.len()..].to_vec())?))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::kv::{KvScan, LsmStorage, StdBPlusTree};
+
+    fn schema(name: &str) -> Schema {
+        Schema {
+            name: name.into(),
+            columns: vec![
+                ColumnDef {
+                    name: "id".into(),
+                    datatype: DataType::Integer,
+                    nullable: false,
+                    default: None,
+                    indexed: false,
+                },
+                ColumnDef {
+                    name: "name".into(),
+                    datatype: DataType::String,
+                    nullable: true,
+                    default: Some(Value::String("unknown".into())),
+                    indexed: true,
+                },
+            ],
+            primary_key: 0,
+        }
+    }
+
+    fn row_key(table: &str, id: i64) -> Vec<u8> {
+        [Catalog::<LsmStorage>::row_prefix(table), encode_value(&Value::Integer(id))].concat()
+    }
+
+    #[test]
+    fn lifecycle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let catalog = Catalog::new(LsmStorage::open(dir.path())?);
+        catalog.create_table(schema("b"))?;
+        catalog.create_table(schema("a"))?;
+        catalog.create_table(schema("ab"))?;
+        assert!(matches!(catalog.create_table(schema("a")), Err(Error::AlreadyExists(_))));
+        assert_eq!(vec!["a", "ab", "b"], catalog.list_tables()?);
+        assert_eq!(Some(schema("a")), catalog.get_table("a")?);
+        assert_eq!(None, catalog.get_table("c")?);
+
+        for id in 1..=3 {
+            catalog.store().set(&row_key("a", id), vec![0x01])?;
+            catalog.store().set(&row_key("ab", id), vec![0x02])?;
+        }
+        drop(catalog);
+
+        // Schemas and rows persist across restarts, recovered from the LSM write-ahead log.
+        let catalog = Catalog::new(LsmStorage::open(dir.path())?);
+        assert_eq!(vec!["a", "ab", "b"], catalog.list_tables()?);
+        assert_eq!(Some(schema("ab")), catalog.get_table("ab")?);
+
+        // Dropping a table deletes its rows, but not those of a table sharing its name prefix.
+        catalog.drop_table("a")?;
+        assert!(matches!(catalog.drop_table("a"), Err(Error::NotFound(_))));
+        assert_eq!(vec!["ab", "b"], catalog.list_tables()?);
+        let rows = |table| -> Result<usize> {
+            let prefix = Catalog::<LsmStorage>::row_prefix(table);
+            Ok(catalog.store().prefix_scan(&prefix)?.count())
+        };
+        assert_eq!((0, 3), (rows("a")?, rows("ab")?));
+
+        // The name can be reused, without any of the old rows.
+        catalog.create_table(schema("a"))?;
+        assert_eq!(0, rows("a")?);
+        Ok(())
+    }
+
+    /// A store which counts the items read by scans.
+    struct CountingStore {
+        inner: StdBPlusTree,
+        scanned: Arc<AtomicU64>,
+    }
+
+    impl std::fmt::Display for CountingStore {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "counting({})", self.inner)
+        }
+    }
+
+    impl KvStore for CountingStore {
+        fn set(&self, key: &[u8], value: Vec<u8>) -> Result<()> {
+            self.inner.set(key, value)
+        }
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn delete(&self, key: &[u8]) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn scan(&self, range: Range) -> Result<KvScan> {
+            let scanned = self.scanned.clone();
+            Ok(Box::new(self.inner.scan(range)?.inspect(move |_| {
+                scanned.fetch_add(1, Ordering::Relaxed);
+            })))
+        }
+
+        fn flush(&self) -> Result<()> {
+            self.inner.flush()
+        }
+
+        fn write_batch(&self, ops: Vec<WriteOp>) -> Result<()> {
+            self.inner.write_batch(ops)
+        }
+    }
+
+    #[test]
+    fn indexed_rows() -> Result<()> {
+        let scanned = Arc::new(AtomicU64::new(0));
+        let store = CountingStore { inner: StdBPlusTree::new(), scanned: scanned.clone() };
+        let catalog = Catalog::new(store);
+        catalog.create_table(schema("t"))?;
+        let name = |id: i64| Value::String(format!("name{}", id % 100));
+        for id in 0..1000 {
+            catalog.insert_row("t", vec![Value::Integer(id), name(id)])?;
+        }
+
+        // Index lookups only read the matching entries, not the entire table.
+        scanned.store(0, Ordering::Relaxed);
+        let pks = catalog.index().index_scan("t", "name", &name(7))?;
+        assert_eq!((7..1000).step_by(100).map(Value::Integer).collect::<Vec<_>>(), pks);
+        assert_eq!(10, scanned.load(Ordering::Relaxed));
+
+        // Replacing and deleting rows keeps the index consistent.
+        catalog.insert_row("t", vec![Value::Integer(7), Value::String("other".into())])?;
+        assert!(catalog.delete_row("t", &Value::Integer(107))?);
+        assert!(!catalog.delete_row("t", &Value::Integer(107))?);
+        assert_eq!(8, catalog.index().index_scan("t", "name", &name(7))?.len());
+        let other = catalog.index().index_scan("t", "name", &Value::String("other".into()))?;
+        assert_eq!(vec![Value::Integer(7)], other);
+        assert_eq!(
+            Some(vec![Value::Integer(8), name(8)]),
+            catalog.get_row("t", &Value::Integer(8))?
+        );
+        assert!(catalog.insert_row("t", vec![Value::Null, name(0)]).is_err());
+        assert!(catalog.insert_row("t", vec![Value::Integer(1)]).is_err());
+
+        catalog.drop_table("t")?;
+        assert_eq!(0, catalog.store().inner.scan(Range::from(..))?.count());
+        Ok(())
+    }
+
+    #[test]
+    fn validate() {
+        let invalid = |f: fn(&mut Schema)| {
+            let mut schema = schema("t");
+            f(&mut schema);
+            schema.validate().is_err()
+        };
+        assert!(schema("t").validate().is_ok());
+        assert!(invalid(|s| s.name.clear()));
+        assert!(invalid(|s| s.columns.clear()));
+        assert!(invalid(|s| s.primary_key = 2));
+        assert!(invalid(|s| s.columns[0].nullable = true));
+        assert!(invalid(|s| s.columns[1].name = "id".into()));
+        assert!(invalid(|s| s.columns[1].default = Some(Value::Integer(1))));
+        assert!(invalid(|s| s.columns[0].default = Some(Value::Null)));
+    }
+}
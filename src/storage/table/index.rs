@@ -0,0 +1,107 @@
+use crate::encoding::{encode_string, encode_value};
+use crate::error::Result;
+use crate::sql::types::encoding::decode_value;
+use crate::sql::types::Value;
+use crate::storage::kv::{KvStore, Range, WriteOp};
+
+/// The key prefix of index entries.
+const INDEX_PREFIX: &[u8] = b"\x00index\x00";
+
+/// Secondary indexes, mapping a column value to the primary keys of the rows containing it.
+///
+/// Each (table, column, value, primary key) is stored as a separate entry under the key
+/// `\x00index\x00{table}{column}{value}{pk}`, with the names and values in the order-preserving
+/// encoding of crate::encoding, and the encoded primary key as the value. Since the encodings
+/// are self-delimiting, all entries of a column, or of a value in a column, share a key prefix
+/// and are found with a prefix scan, and updating the index never has to read it.
+pub struct Index<'a, S: KvStore> {
+    store: &'a S,
+}
+
+impl<'a, S: KvStore> Index<'a, S> {
+    /// Accesses the indexes in a store.
+    pub fn new(store: &'a S) -> Self {
+        Self { store }
+    }
+
+    /// Returns the key prefix of a table's index entries.
+    fn table_prefix(table: &str) -> Vec<u8> {
+        [INDEX_PREFIX, &encode_string(table)].concat()
+    }
+
+    /// Returns the key prefix of a column's index entries.
+    pub fn column_prefix(table: &str, column: &str) -> Vec<u8> {
+        [Self::table_prefix(table), encode_string(column)].concat()
+    }
+
+    /// Returns the key prefix of a column value's index entries.
+    fn value_prefix(table: &str, column: &str, value: &Value) -> Vec<u8> {
+        [Self::column_prefix(table, column), encode_value(value)].concat()
+    }
+
+    /// Returns the write adding an index entry, for batching with a row write.
+    pub fn insert_op(table: &str, column: &str, value: &Value, pk: &Value) -> WriteOp {
+        let pk = encode_value(pk);
+        let key = [Self::value_prefix(table, column, value), pk.clone()].concat();
+        WriteOp::Put { key, value: pk }
+    }
+
+    /// Returns the write removing an index entry, for batching with a row write.
+    pub fn delete_op(table: &str, column: &str, value: &Value, pk: &Value) -> WriteOp {
+        let key = [Self::value_prefix(table, column, value), encode_value(pk)].concat();
+        WriteOp::Delete { key }
+    }
+
+    /// Adds a row's primary key to the index entries of a column value.
+    pub fn index_insert(&self, table: &str, column: &str, value: &Value, pk: &Value) -> Result<()> {
+        self.store.write_batch(vec![Self::insert_op(table, column, value, pk)])
+    }
+
+    /// Removes a row's primary key from the index entries of a column value.
+    pub fn index_delete(&self, table: &str, column: &str, value: &Value, pk: &Value) -> Result<()> {
+        self.store.write_batch(vec![Self::delete_op(table, column, value, pk)])
+    }
+
+    /// Returns the primary keys of the rows with the given column value, in primary key order.
+    pub fn index_scan(&self, table: &str, column: &str, value: &Value) -> Result<Vec<Value>> {
+        self.store
+            .prefix_scan(&Self::value_prefix(table, column, value))?
+            .map(|item| Ok(decode_value(&item?.1)?.0))
+            .collect()
+    }
+
+    /// Deletes all index entries of a table, returning the number of deleted entries.
+    pub fn delete_table(&self, table: &str) -> Result<u64> {
+        self.store.delete_range(Range::from_prefix(&Self::table_prefix(table)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::kv::StdBPlusTree;
+
+    #[test]
+    fn insert_scan_delete() -> Result<()> {
+        let store = StdBPlusTree::new();
+        let index = Index::new(&store);
+        let (a, b) = (Value::String("a".into()), Value::String("a\0".into()));
+        index.index_insert("t", "c", &a, &Value::Integer(2))?;
+        index.index_insert("t", "c", &a, &Value::Integer(-1))?;
+        index.index_insert("t", "c", &b, &Value::Integer(3))?;
+        index.index_insert("t", "cc", &a, &Value::Integer(4))?;
+        index.index_insert("tc", "", &a, &Value::Integer(5))?;
+
+        // Entries of other values, columns, and tables sharing a name prefix aren't returned.
+        assert_eq!(vec![Value::Integer(-1), Value::Integer(2)], index.index_scan("t", "c", &a)?);
+        assert_eq!(vec![Value::Integer(3)], index.index_scan("t", "c", &b)?);
+        assert_eq!(vec![Value::Integer(5)], index.index_scan("tc", "", &a)?);
+
+        index.index_delete("t", "c", &a, &Value::Integer(2))?;
+        assert_eq!(vec![Value::Integer(-1)], index.index_scan("t", "c", &a)?);
+        assert_eq!(3, index.delete_table("t")?);
+        assert!(index.index_scan("t", "cc", &a)?.is_empty());
+        assert_eq!(vec![Value::Integer(5)], index.index_scan("tc", "", &a)?);
+        Ok(())
+    }
+}
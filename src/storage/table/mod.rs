@@ -0,0 +1,5 @@
+pub mod catalog;
+pub mod index;
+
+pub use catalog::{Catalog, ColumnDef, Schema};
+pub use index::Index;
@@ -0,0 +1,76 @@
+//! A test extension providing DOUBLE(n INTEGER) -> INTEGER, built as a cdylib by the
+//! sql::extension tests. It declares the extension interface types itself, as any plugin
+//! written against the C ABI would.
+
+use std::ffi::c_char;
+
+#[repr(C)]
+pub struct PluginMeta {
+    name: *const c_char,
+    version: *const c_char,
+    functions: *const PluginFunction,
+    functions_len: usize,
+}
+
+#[repr(C)]
+pub struct PluginFunction {
+    name: *const c_char,
+    arg_types: *const PluginType,
+    arg_types_len: usize,
+    return_type: PluginType,
+    function: unsafe extern "C" fn(*const PluginValue, usize) -> PluginValue,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+pub enum PluginType {
+    Boolean,
+    Integer,
+    Float,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginValue {
+    is_null: bool,
+    boolean: bool,
+    integer: i64,
+    float: f64,
+}
+
+struct Meta(PluginMeta);
+
+// The metadata only points to static data.
+unsafe impl Sync for Meta {}
+
+#[repr(transparent)]
+struct Function(PluginFunction);
+
+unsafe impl Sync for Function {}
+
+static ARG_TYPES: [PluginType; 1] = [PluginType::Integer];
+
+static FUNCTIONS: [Function; 1] = [Function(PluginFunction {
+    name: c"double".as_ptr(),
+    arg_types: ARG_TYPES.as_ptr(),
+    arg_types_len: 1,
+    return_type: PluginType::Integer,
+    function: double,
+})];
+
+static META: Meta = Meta(PluginMeta {
+    name: c"double_ext".as_ptr(),
+    version: c"0.1.0".as_ptr(),
+    functions: &FUNCTIONS[0].0,
+    functions_len: 1,
+});
+
+unsafe extern "C" fn double(args: *const PluginValue, len: usize) -> PluginValue {
+    let arg = std::slice::from_raw_parts(args, len)[0];
+    PluginValue { integer: arg.integer * 2, ..arg }
+}
+
+#[no_mangle]
+pub extern "C" fn gymxdb_plugin_init() -> *const PluginMeta {
+    &META.0
+}
@@ -1,5 +1,6 @@
 mod leader_election;
 mod log_replication;
+mod replicated_store;
 
 use std::collections::HashMap;
 use std::time::Duration;
@@ -0,0 +1,134 @@
+use std::future::Future;
+use std::time::Duration;
+
+use featherdb::error::{Error, Result};
+use featherdb::proto::featherkv::FeatherKvServer;
+use featherdb::raft::{Client, FeatherKV, KvState, RaftConfig};
+use featherdb::storage;
+use featherdb::storage::async_store::AsyncStore;
+use featherdb::storage::kv::{Range, ReadConsistency, ReplicatedStore, StdBPlusTree};
+use tokio::sync::oneshot;
+use tonic::transport::Server;
+
+const PEERS: [&str; 3] = ["127.0.0.1:50157", "127.0.0.1:50158", "127.0.0.1:50159"];
+const CLIENT_ADDRS: [&str; 3] = ["127.0.0.1:50167", "127.0.0.1:50168", "127.0.0.1:50169"];
+
+/// A FeatherKV node serving a KvState, on its own thread and runtime such that it can fail.
+struct KvNode {
+    /// The store of the node's state machine.
+    replica: StdBPlusTree,
+    stop_tx: oneshot::Sender<()>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl KvNode {
+    fn start(id: u64) -> Self {
+        let replica = StdBPlusTree::new();
+        let state = Box::new(KvState::new(Box::new(replica.clone())).unwrap());
+        let (stop_tx, stop_rx) = oneshot::channel();
+        let thread = std::thread::spawn(move || {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(async move {
+                let server = FeatherKV::with_config(
+                    id,
+                    PEERS.iter().map(|addr| addr.to_string()).collect(),
+                    CLIENT_ADDRS.iter().map(|addr| addr.to_string()).collect(),
+                    state,
+                    Box::new(storage::log::Memory::new()),
+                    RaftConfig::default(),
+                )
+                .await
+                .unwrap();
+                let serve = Server::builder()
+                    .add_service(FeatherKvServer::new(server))
+                    .serve(CLIENT_ADDRS[id as usize].parse().unwrap());
+                tokio::select! {
+                    result = serve => result.unwrap(),
+                    _ = stop_rx => {},
+                }
+            });
+            // Dropping the runtime aborts the node's tasks and closes its connections.
+        });
+        Self { replica, stop_tx, thread }
+    }
+
+    /// Fails the node, waiting for it to shut down.
+    fn fail(self) {
+        self.stop_tx.send(()).unwrap();
+        self.thread.join().unwrap();
+    }
+}
+
+/// Runs a future, failing if it doesn't complete within 10 seconds.
+async fn within<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(Duration::from_secs(10), future)
+        .await
+        .map_err(|_| Error::Internal("Timed out".into()))?
+}
+
+/// Waits until an eventually consistent read of a key returns the expected value.
+async fn await_value(s: &impl AsyncStore, key: &[u8], expect: Option<Vec<u8>>) -> Result<()> {
+    within(async {
+        while s.get(key).await? != expect {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replicated_store() -> Result<()> {
+    let mut nodes: Vec<KvNode> = (0..3).map(KvNode::start).collect();
+    for addr in CLIENT_ADDRS {
+        within(async {
+            while tokio::net::TcpStream::connect(addr).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            Ok(())
+        })
+        .await?;
+    }
+    let client = || Client::new(CLIENT_ADDRS.iter().map(|addr| addr.to_string()).collect());
+    let strong =
+        ReplicatedStore::new(nodes[1].replica.clone(), client().await?, ReadConsistency::Strong);
+    let eventual =
+        ReplicatedStore::new(nodes[2].replica.clone(), client().await?, ReadConsistency::Eventual);
+    assert_eq!("replicated(async(stdmemory))", strong.to_string());
+
+    // Strong reads see the preceding writes, and the state machine's applied index is hidden.
+    within(strong.set(b"a", vec![1])).await?;
+    within(strong.set(b"b", vec![2])).await?;
+    within(strong.set(b"c", vec![3])).await?;
+    within(strong.delete(b"b")).await?;
+    assert_eq!(Some(vec![1]), within(strong.get(b"a")).await?);
+    assert_eq!(None, within(strong.get(b"b")).await?);
+    assert_eq!(
+        vec![(b"a".to_vec(), vec![1]), (b"c".to_vec(), vec![3])],
+        within(strong.scan(Range::from(..))).await?
+    );
+
+    // Writes through either store are replicated to the other's replica.
+    within(eventual.set(b"d", vec![4])).await?;
+    within(eventual.flush()).await?;
+    assert_eq!(Some(vec![4]), within(strong.get(b"d")).await?);
+    await_value(&eventual, b"a", Some(vec![1])).await?;
+
+    // The remaining majority keeps committing writes when a node fails, even if it was the
+    // leader that the clients sent their requests to.
+    nodes.remove(0).fail();
+    within(strong.set(b"e", vec![5])).await?;
+    within(eventual.delete(b"a")).await?;
+    within(strong.flush()).await?;
+    assert_eq!(
+        vec![(b"c".to_vec(), vec![3]), (b"d".to_vec(), vec![4]), (b"e".to_vec(), vec![5])],
+        within(strong.scan(Range::from(..))).await?
+    );
+    await_value(&eventual, b"e", Some(vec![5])).await?;
+    await_value(&eventual, b"a", None).await?;
+
+    for node in nodes {
+        node.fail();
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use featherdb::client::{Client, ConnectionPool, PoolConfig};
+use featherdb::concurrency::MVCC;
+use featherdb::error::{Error, Result};
+use featherdb::server::Server;
+use featherdb::sql::engine::KvSqlEngine;
+use featherdb::sql::execution::ResultSet;
+use featherdb::sql::types::{ResColumn, Value};
+use featherdb::storage::kv::StdBPlusTree;
+
+/// Starts a server for an empty in-memory engine on a free port, returning its address.
+async fn setup() -> Result<SocketAddr> {
+    let engine = KvSqlEngine::new(MVCC::new(Box::new(StdBPlusTree::new()), false));
+    let server = Server::bind(engine, "127.0.0.1:0").await?;
+    let addr = server.local_addr()?;
+    tokio::spawn(server.serve());
+    Ok(addr)
+}
+
+/// Returns the rows of a query result.
+fn rows(result: ResultSet) -> Result<Vec<Vec<Value>>> {
+    match result {
+        ResultSet::Query { buffered_rows, .. } => buffered_rows,
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_insert_select() -> Result<()> {
+    let mut client = Client::connect(setup().await?).await?;
+    assert_eq!(
+        ResultSet::CreateTable { name: "movies".into() },
+        client.execute("CREATE TABLE movies (id INTEGER PRIMARY KEY, title STRING)").await?
+    );
+    assert_eq!(
+        ResultSet::Create { count: 2 },
+        client.execute("INSERT INTO movies VALUES (1, 'Sicario'), (2, 'Stalker')").await?
+    );
+    match client.execute("SELECT * FROM movies WHERE id > 1").await? {
+        ResultSet::Query { columns, buffered_rows } => {
+            assert_eq!(
+                vec![ResColumn { name: Some("id".into()) }, ResColumn { name: Some("title".into()) }],
+                columns
+            );
+            assert_eq!(
+                vec![vec![Value::Integer(2), Value::String("Stalker".into())]],
+                buffered_rows?
+            );
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+
+    // Errors are returned to the client, which can keep using the connection.
+    assert!(matches!(client.execute("SELECT * FROM missing").await, Err(Error::Value(_))));
+    assert!(client.execute("SELECT 1").await.is_ok());
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transactions() -> Result<()> {
+    let addr = setup().await?;
+    let mut client = Client::connect(addr).await?;
+    let mut other = Client::connect(addr).await?;
+    client.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").await?;
+
+    // A rolled back transaction leaves no trace.
+    assert!(matches!(client.begin().await?, ResultSet::Begin { .. }));
+    client.execute("INSERT INTO t VALUES (1)").await?;
+    assert!(matches!(client.rollback().await?, ResultSet::Rollback { .. }));
+    assert_eq!(Err(Error::Value("Not in a transaction".into())), client.commit().await);
+
+    // Each connection has its own session, so other clients only see committed writes.
+    client.begin().await?;
+    client.execute("INSERT INTO t VALUES (2)").await?;
+    assert_eq!(vec![vec![Value::Integer(2)]], rows(client.execute("SELECT id FROM t").await?)?);
+    assert!(rows(other.execute("SELECT id FROM t").await?)?.is_empty());
+    assert!(matches!(client.commit().await?, ResultSet::Commit { .. }));
+    assert_eq!(vec![vec![Value::Integer(2)]], rows(other.execute("SELECT id FROM t").await?)?);
+    Ok(())
+}
+
+/// Returns a pool configuration with the given limits and timeouts in milliseconds.
+fn pool_config(max: usize, min_idle: usize, connection: u64, idle: u64) -> PoolConfig {
+    PoolConfig {
+        max_connections: max,
+        min_idle,
+        connection_timeout: Duration::from_millis(connection),
+        idle_timeout: Duration::from_millis(idle),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pool_concurrent_requests() -> Result<()> {
+    let pool = Arc::new(ConnectionPool::new(setup().await?, pool_config(5, 0, 10_000, 60_000))?);
+    pool.acquire().await?.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)").await?;
+
+    // 50 concurrent requests share at most 5 connections.
+    let tasks: Vec<_> = (0..50)
+        .map(|i| {
+            let pool = pool.clone();
+            tokio::spawn(async move {
+                let mut conn = pool.acquire().await?;
+                assert!(pool.size() <= 5);
+                conn.execute(&format!("INSERT INTO t VALUES ({})", i)).await
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.map_err(|err| Error::Internal(err.to_string()))??;
+    }
+    assert!(pool.size() <= 5);
+    assert_eq!(pool.size(), pool.idle());
+    let count = rows(pool.acquire().await?.execute("SELECT COUNT(*) FROM t").await?)?;
+    assert_eq!(vec![vec![Value::Integer(50)]], count);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pool_invalidate_and_timeout() -> Result<()> {
+    let pool = ConnectionPool::new(setup().await?, pool_config(1, 0, 100, 60_000))?;
+    assert!(ConnectionPool::new(setup().await?, pool_config(1, 2, 100, 60_000)).is_err());
+
+    // Acquiring waits for a connection until the timeout.
+    let conn = pool.acquire().await?;
+    assert_eq!(1, pool.size());
+    assert!(pool.acquire().await.is_err());
+
+    // An invalidated connection is closed, rather than returned to the pool.
+    conn.invalidate();
+    assert_eq!(0, pool.size());
+    let mut conn = pool.acquire().await?;
+    conn.ping().await?;
+    drop(conn);
+    assert_eq!((1, 1), (pool.size(), pool.idle()));
+    pool.acquire().await?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pool_health_check() -> Result<()> {
+    // Idle connections beyond min_idle are closed once they time out.
+    let pool = ConnectionPool::new(setup().await?, pool_config(5, 2, 1_000, 200))?;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(2, pool.idle());
+    let conns = vec![pool.acquire().await?, pool.acquire().await?, pool.acquire().await?];
+    assert_eq!(3, pool.size());
+    drop(conns);
+    assert_eq!(3, pool.idle());
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(2, pool.idle());
+    pool.acquire().await?.ping().await?;
+
+    // Connections closed by the server are removed, here by a listener that closes them.
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((socket, _)) = listener.accept().await {
+            drop(socket);
+        }
+    });
+    let pool = ConnectionPool::new(addr, pool_config(5, 0, 1_000, 200))?;
+    drop(vec![pool.acquire().await?, pool.acquire().await?]);
+    assert_eq!(2, pool.idle());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(0, pool.size());
+    Ok(())
+}
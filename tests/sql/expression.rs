@@ -33,5 +33,60 @@ use Value::*;
 test_expr! {
     // Constants and literals
     const_case: "TrUe" => Ok(Boolean(true)),
-    lit_integer_overflow: "9223372036854775808" => Err(Error::Parse("number too large to fit in target type".into())),
-}
\ No newline at end of file
+    lit_integer_overflow: "9223372036854775808" => Err(Error::Parse("number too large to fit in target type at byte 7".into())),
+
+    // Operators
+    op_add_promote: "1 + 2.5" => Ok(Float(3.5)),
+    op_add_null: "NULL + 'a'" => Ok(Null),
+    op_add_overflow: "9223372036854775807 + 1" => Err(Error::Value("Integer overflow".into())),
+    op_divide_zero: "1 / 0" => Err(Error::DivisionByZero),
+    op_modulo_zero: "1 % 0" => Err(Error::DivisionByZero),
+    op_divide_zero_float: "1.0 / 0" => Err(Error::DivisionByZero),
+    op_divide_zero_mixed: "1 / 0.0" => Err(Error::DivisionByZero),
+    op_modulo_zero_float: "1.5 % 0.0" => Err(Error::DivisionByZero),
+    op_negate_overflow: "-(-9223372036854775807 - 1)" => Err(Error::Value("Integer overflow".into())),
+    op_exponent: "2 ^ 10" => Ok(Integer(1024)),
+    op_exponent_overflow: "2 ^ 64" => Err(Error::Value("Integer overflow".into())),
+    op_exponent_too_large: "1 ^ 4294967296" => Err(Error::Value("Exponent 4294967296 is too large".into())),
+    op_exponent_decimal_too_large: "CAST(1.5 AS DECIMAL(2, 1)) ^ 4294967297" => Err(Error::Value("Exponent 4294967297 is too large".into())),
+    op_concat: "'a' || 'b' || 1" => Ok(String("ab1".into())),
+    op_concat_null: "'a' || NULL" => Ok(Null),
+    op_concat_precedence: "'a' || 1 + 2 = 'a3'" => Ok(Boolean(true)),
+    op_concat_invalid: "1 || 2" => Err(Error::Value("Can't concatenate 1 and 2".into())),
+}
+#[test]
+fn to_sql_literal_roundtrip() -> Result<()> {
+    let values = vec![
+        Boolean(true),
+        Boolean(false),
+        Integer(0),
+        Integer(-42),
+        Integer(i64::MAX),
+        Integer(i64::MIN),
+        Float(3.0),
+        Float(-0.5),
+        Float(2.25),
+        Float(1e300),
+        Float(1.5e-7),
+        Float(f64::INFINITY),
+        Float(f64::NEG_INFINITY),
+        Decimal("1.50".parse()?),
+        Decimal("-123456.789".parse()?),
+        String("".into()),
+        String("hello".into()),
+        String("it's \"quoted\"".into()),
+        Timestamp(1_700_000_000_123_456),
+        Timestamp(-1),
+        Date(19_000),
+        Date(-1),
+    ];
+    for value in values {
+        let literal = value.to_sql_literal();
+        let parsed = eval_expr(&literal)?;
+        assert_eq!(value, parsed, "{}", literal);
+        assert_eq!(value.datatype(), parsed.datatype(), "{}", literal);
+    }
+    assert!(matches!(eval_expr(&Float(f64::NAN).to_sql_literal())?, Float(f) if f.is_nan()));
+    assert_eq!("NULL", Null.to_sql_literal());
+    Ok(())
+}
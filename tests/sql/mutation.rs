@@ -1,7 +1,9 @@
 ///! Mutation tests, using an in-memory database against golden files in tests/sql/mutation/
 use featherdb::error::Result;
 use featherdb::sql::engine::{SqlEngine as _, Mode, SqlTxn as _};
+use featherdb::sql::execution::ResultSet;
 use featherdb::sql::schema::Catalog as _;
+use featherdb::sql::types::Value;
 
 use goldenfile::Mint;
 use std::io::Write;
@@ -67,4 +69,161 @@ test_mutation! { with [
     ];
 
     delete_all: "DELETE FROM test",
-}
\ No newline at end of file
+    explain_delete: "EXPLAIN DELETE FROM test WHERE name = 'b'",
+    explain_select: "EXPLAIN SELECT * FROM test t JOIN other o ON t.id = o.id WHERE o.id > 1",
+    explain_begin: "EXPLAIN BEGIN",
+    explain_nested: "EXPLAIN EXPLAIN SELECT 1",
+}
+test_mutation! { with [
+        "CREATE TABLE test (
+            id INTEGER PRIMARY KEY,
+            name STRING NOT NULL UNIQUE
+        )",
+        "INSERT INTO test VALUES (1, 'a'), (2, 'b'), (3, 'c')",
+    ];
+
+    insert_conflict: "INSERT INTO test VALUES (4, 'd'), (1, 'x')",
+    insert_conflict_do_nothing: "INSERT INTO test VALUES (1, 'x'), (4, 'd'), (2, 'y'), (5, 'e'), (3, 'z') ON CONFLICT DO NOTHING",
+    insert_conflict_do_nothing_unique: "INSERT INTO test VALUES (4, 'a'), (5, 'e') ON CONFLICT DO NOTHING",
+    insert_conflict_do_nothing_not_null: "INSERT INTO test VALUES (1, 'x'), (4, NULL) ON CONFLICT DO NOTHING",
+    insert_conflict_do_update: "INSERT INTO test VALUES (1, 'x'), (4, 'd') ON CONFLICT DO UPDATE SET name = excluded.name",
+    insert_conflict_do_update_unique: "INSERT INTO test VALUES (9, 'a') ON CONFLICT DO UPDATE SET name = name || excluded.name",
+    insert_conflict_do_update_key: "INSERT INTO test VALUES (9, 'b') ON CONFLICT DO UPDATE SET id = excluded.id",
+    insert_conflict_do_update_violation: "INSERT INTO test VALUES (1, 'x') ON CONFLICT DO UPDATE SET name = 'b'",
+    insert_conflict_do_update_unknown: "INSERT INTO test VALUES (1, 'x') ON CONFLICT DO UPDATE SET missing = excluded.name",
+    insert_conflict_do_update_excluded: "INSERT INTO test VALUES (1, 'x') ON CONFLICT DO UPDATE SET name = excluded.missing",
+    explain_insert_conflict_do_update: "EXPLAIN INSERT INTO test VALUES (1, 'x') ON CONFLICT DO UPDATE SET name = name || excluded.name",
+}
+test_mutation! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING, value INTEGER)",
+        "INSERT INTO test VALUES (1, 'a', 1), (2, 'b', 2), (3, 'a', 3), (4, 'b', 4)",
+        "CREATE TABLE other (id INTEGER PRIMARY KEY, label STRING, amount DECIMAL(6, 2))",
+        "CREATE TABLE totals (name STRING PRIMARY KEY, total INTEGER NOT NULL, count INTEGER DEFAULT 0)",
+    ];
+
+    insert_select: "INSERT INTO other SELECT id, name, value FROM test WHERE id <= 2",
+    insert_select_columns: "INSERT INTO other (label, id) SELECT name, id * 10 FROM test",
+    insert_select_aggregate: "INSERT INTO totals SELECT name, SUM(value) FROM test GROUP BY name",
+    insert_select_self: "INSERT INTO test SELECT id + 10, name, value FROM test WHERE id > 2",
+    insert_select_with: "INSERT INTO other (id) WITH big AS (SELECT id FROM test WHERE value > 2) SELECT id FROM big",
+    insert_select_conflict: "INSERT INTO test SELECT id, name, value FROM test",
+    insert_select_conflict_do_nothing: "INSERT INTO test SELECT id + 3, name, value FROM test ON CONFLICT DO NOTHING",
+    insert_select_conflict_do_update: "INSERT INTO test SELECT id + 2, name, value FROM test ON CONFLICT DO UPDATE SET value = test.value * 10 + excluded.value",
+    insert_select_column_count: "INSERT INTO other (id) SELECT id, name FROM test",
+    insert_select_too_many_columns: "INSERT INTO other SELECT id, name, value, value FROM test",
+    insert_select_type_mismatch: "INSERT INTO totals SELECT id, name FROM test",
+    insert_select_unknown_column: "INSERT INTO other (id, missing) SELECT id, name FROM test",
+    explain_insert_select: "EXPLAIN INSERT INTO other (id) SELECT id FROM test WHERE value > 1 ON CONFLICT DO NOTHING",
+}
+test_mutation! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, status ENUM('open', 'closed') NOT NULL)",
+        "INSERT INTO test VALUES (1, 'open'), (2, 'closed')",
+    ];
+
+    insert_enum: "INSERT INTO test VALUES (3, 'closed')",
+    insert_enum_invalid: "INSERT INTO test VALUES (3, 'Open')",
+    update_enum: "UPDATE test SET status = 'closed' WHERE id = 1",
+    update_enum_invalid: "UPDATE test SET status = 'pending' WHERE id = 1",
+}
+test_mutation! { with [
+        "CREATE TABLE accounts (id INTEGER PRIMARY KEY, balance DECIMAL(6, 2) NOT NULL INDEX)",
+        "INSERT INTO accounts VALUES (1, 10.10), (2, 0.2)",
+    ];
+
+    insert_decimal: "INSERT INTO accounts VALUES (3, 5), (4, 1.005), (5, -0.1)",
+    insert_decimal_overflow: "INSERT INTO accounts VALUES (3, 10000)",
+    update_decimal: "UPDATE accounts SET balance = balance + balance * 2 WHERE id = 2",
+    update_decimal_integer: "UPDATE accounts SET balance = balance * 3 - 1",
+    update_decimal_float: "UPDATE accounts SET balance = balance * 1.5",
+}
+test_mutation! { with [
+        "CREATE TABLE readings (id INTEGER PRIMARY KEY, value FLOAT NOT NULL INDEX)",
+        "INSERT INTO readings VALUES (1, 1.5)",
+    ];
+
+    insert_float_integer: "INSERT INTO readings VALUES (2, 3), (3, 2 + 0.5)",
+    insert_float_boolean: "INSERT INTO readings VALUES (2, TRUE)",
+    update_float_integer: "UPDATE readings SET value = id * 10",
+}
+test_mutation! { with [
+        "CREATE TABLE events (id INTEGER PRIMARY KEY, at TIMESTAMP NOT NULL INDEX, day DATE)",
+        "INSERT INTO events VALUES (1, '2024-03-10T01:59:59-05:00', '2024-03-10'), (2, '2024-03-10T03:00:00-04:00', NULL)",
+    ];
+
+    insert_datetime: "INSERT INTO events VALUES (3, '2016-12-31T23:59:60Z', '2016-12-31'), (4, '2024-07-01 12:34:56.789', '2024-07-01')",
+    insert_datetime_invalid: "INSERT INTO events VALUES (3, '2024-07-01T25:00:00', NULL)",
+    insert_datetime_date: "INSERT INTO events (id, at) VALUES (3, '2024-07-01')",
+    update_datetime: "UPDATE events SET at = at + 1000000, day = day + 1 WHERE id = 1",
+}
+test_mutation! { with [
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, status STRING NOT NULL)",
+        "INSERT INTO orders VALUES (1, 'pending'), (2, 'completed'), (3, 'shipped')",
+        "CREATE INDEX open_orders ON orders (status) WHERE status != 'completed'",
+    ];
+
+    insert_partial_index: "INSERT INTO orders VALUES (4, 'pending')",
+    insert_partial_index_excluded: "INSERT INTO orders VALUES (4, 'completed')",
+    update_partial_index_excluded: "UPDATE orders SET status = 'completed' WHERE id = 1",
+    update_partial_index_included: "UPDATE orders SET status = 'pending' WHERE id = 2",
+    update_partial_index_lookup: "UPDATE orders SET status = 'shipped' WHERE status = 'pending'",
+    delete_partial_index: "DELETE FROM orders WHERE status != 'shipped'",
+}
+test_mutation! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL UNIQUE, x INTEGER DEFAULT 0)",
+        "INSERT INTO test VALUES (1, 'a', 1), (2, 'b', 2), (3, 'c', 3)",
+    ];
+
+    insert_returning: "INSERT INTO test (id, name) VALUES (4, 'd'), (5, 'e') RETURNING id",
+    insert_returning_all: "INSERT INTO test VALUES (4, 'd', 4) RETURNING *",
+    insert_returning_conflict: "INSERT INTO test VALUES (1, 'x', 0), (4, 'd', 4) ON CONFLICT DO UPDATE SET x = test.x + 10 RETURNING id, old.x, new.x",
+    insert_select_returning: "INSERT INTO test SELECT id + 10, name || '2', x FROM test RETURNING id, name AS label",
+    update_returning: "UPDATE test SET x = x + 1 WHERE id > 1 RETURNING id, old.x AS old_x, new.x AS new_x",
+    delete_returning: "DELETE FROM test WHERE id != 2 RETURNING *",
+    delete_returning_old_new: "DELETE FROM test WHERE id = 2 RETURNING x, old.x, new.x",
+    returning_unknown: "UPDATE test SET x = 1 RETURNING missing",
+    explain_update_returning: "EXPLAIN UPDATE test SET x = x + 1 RETURNING id, old.x, new.x",
+}
+test_mutation! { with [
+        "CREATE TABLE test (id SERIAL PRIMARY KEY, name STRING NOT NULL)",
+        "INSERT INTO test (name) VALUES ('a'), ('b')",
+    ];
+
+    insert_serial: "INSERT INTO test (name) VALUES ('c'), ('d') RETURNING id",
+    insert_serial_explicit: "INSERT INTO test VALUES (10, 'c'), (NULL, 'd')",
+    insert_serial_duplicate: "INSERT INTO test VALUES (2, 'c')",
+}
+test_mutation! { with [
+        "CREATE TABLE test (id SERIAL PRIMARY KEY, name STRING NOT NULL)",
+        "INSERT INTO test (name) VALUES ('a'), ('b')",
+        "ALTER TABLE test ALTER COLUMN id RESET SEQUENCE TO 100",
+    ];
+
+    insert_serial_reset: "INSERT INTO test (name) VALUES ('c')",
+}
+
+/// Upserts 1000 rows into a table holding half of them, and checks the resulting row counts.
+#[test]
+fn upsert_bulk() -> Result<()> {
+    let existing = (0..500).map(|i| format!("({}, 10)", i * 2)).collect::<Vec<_>>().join(", ");
+    let upsert = (0..1000).map(|i| format!("({}, 1)", i)).collect::<Vec<_>>().join(", ");
+    let count = |engine: &featherdb::sql::engine::KvSqlEngine| -> Result<Vec<Value>> {
+        match engine.session()?.execute("SELECT COUNT(*), SUM(value) FROM test")? {
+            ResultSet::Query { buffered_rows, .. } => Ok(buffered_rows?.remove(0)),
+            r => panic!("unexpected result {:?}", r),
+        }
+    };
+
+    for (action, affected, rows, sum) in [
+        ("DO NOTHING", 500, 1000, 500 * 10 + 500),
+        ("DO UPDATE SET value = test.value + excluded.value", 1000, 1000, 500 * 11 + 500),
+    ] {
+        let engine = super::setup(vec![
+            "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER NOT NULL)",
+            &format!("INSERT INTO test VALUES {}", existing),
+        ])?;
+        let query = format!("INSERT INTO test VALUES {} ON CONFLICT {}", upsert, action);
+        assert_eq!(ResultSet::Create { count: affected }, engine.session()?.execute(&query)?);
+        assert_eq!(vec![Value::Integer(rows), Value::Integer(sum)], count(&engine)?);
+    }
+    Ok(())
+}
@@ -81,7 +81,7 @@ macro_rules! test_query {
             // First, just try to generate a plan and execute it
             let result = Parser::new($query).parse()
                 .and_then(|ast| Plan::build(ast, &mut txn))
-                // .and_then(|plan| plan.optimize(&mut txn))
+                .and_then(|plan| plan.optimize(&mut txn))
                 .and_then(|plan| {
                     write!(f, "Explain:\n{}\n\n", plan)?;
                     plan.execute(&mut txn)
@@ -160,4 +160,232 @@ test_query! {
     bare: "SELECT",
     trailing_comma: "SELECT 1,",
     lowercase: "select 1",
-}
\ No newline at end of file
+
+    like_pattern: "SELECT * FROM movies WHERE title LIKE 'S%r%'",
+    like_ilike: "SELECT * FROM movies WHERE title ILIKE '%IN%'",
+    like_escape: "SELECT * FROM movies WHERE title LIKE '%!_%' ESCAPE '!' OR title LIKE 'G_a%'",
+    like_escape_invalid: "SELECT * FROM movies WHERE title LIKE '%' ESCAPE '!!'",
+    cast_explicit: "SELECT * FROM movies WHERE CAST(rating AS INTEGER) = 8",
+    cast_implicit: "SELECT * FROM movies WHERE released + rating > 2014",
+    cast_invalid: "SELECT * FROM movies WHERE CAST(title AS INTEGER) = 1",
+
+    cte: "WITH g AS (SELECT * FROM genres WHERE id > 1) SELECT * FROM g",
+    cte_alias: "WITH g AS (SELECT * FROM genres) SELECT * FROM g AS x WHERE x.id = 2",
+    cte_chained: "WITH a AS (SELECT * FROM genres WHERE id > 1), b AS (SELECT * FROM a WHERE id < 3) SELECT * FROM b",
+    cte_materialized: "WITH g AS (SELECT * FROM genres WHERE id < 3) SELECT * FROM g, g AS h",
+    cte_missing_select: "WITH g AS (SELECT * FROM genres)",
+
+    tablesample_all: "SELECT * FROM genres TABLESAMPLE BERNOULLI (100)",
+    tablesample_none: "SELECT * FROM genres TABLESAMPLE BERNOULLI (0)",
+    tablesample_repeatable: "SELECT * FROM genres AS g TABLESAMPLE BERNOULLI (50.0) REPEATABLE (7) WHERE g.id > 1",
+    tablesample_out_of_range: "SELECT * FROM genres TABLESAMPLE BERNOULLI (101)",
+    tablesample_missing_method: "SELECT * FROM genres TABLESAMPLE (50)",
+
+    subquery: "SELECT * FROM (SELECT * FROM genres WHERE id > 1) AS g WHERE g.id < 3",
+    subquery_missing_alias: "SELECT * FROM (SELECT * FROM genres)",
+    subquery_uncorrelated: "SELECT * FROM genres g JOIN (SELECT * FROM movies m WHERE m.genre_id = g.id) m ON TRUE",
+    lateral: "SELECT * FROM genres g JOIN LATERAL (SELECT * FROM movies m WHERE m.genre_id = g.id AND m.rating > 8.0) top",
+    lateral_on: "SELECT * FROM genres g CROSS JOIN LATERAL (SELECT * FROM movies m WHERE m.genre_id = g.id) m ON m.released > 2012",
+    lateral_left: "SELECT * FROM genres g LEFT JOIN LATERAL (SELECT * FROM movies m WHERE m.genre_id = g.id AND m.released < 2000) old",
+    lateral_left_empty: "SELECT * FROM genres g LEFT JOIN LATERAL (SELECT * FROM movies m WHERE m.genre_id = g.id) m WHERE g.id > 3",
+    lateral_requires_subquery: "SELECT * FROM genres g JOIN LATERAL movies ON TRUE",
+
+    project_expression: "SELECT title, rating * 10 AS score, released > 2000 FROM movies WHERE id < 4",
+    project_qualified: "SELECT m.title, g.name AS genre FROM movies m JOIN genres g ON m.genre_id = g.id WHERE m.id = 2",
+    project_order_label: "SELECT title, released AS year FROM movies ORDER BY year DESC LIMIT 2",
+    project_constant: "SELECT 1 + 2 AS sum, 'a'",
+    distinct: "SELECT DISTINCT genre_id FROM movies",
+    distinct_expression: "SELECT DISTINCT released / 10 * 10 AS decade, ultrahd FROM movies ORDER BY decade LIMIT 4",
+    distinct_all: "SELECT DISTINCT * FROM genres",
+
+    parse_error_keyword: "SELECT * FORM movies",
+    parse_error_end: "SELECT * FROM movies WHERE",
+    parse_error_string: "SELECT * FROM movies WHERE title = 'Heat",
+    parse_error_character: "SELECT * FROM movies WHERE id = #1",
+    parse_error_distinct: "SELECT DISTINCT FROM movies",
+
+    agg_const: "SELECT MIN(3), MAX(3), SUM(3), COUNT(3), AVG(3)",
+    agg_const_from: "SELECT MIN(3), MAX(3), SUM(3), COUNT(3), AVG(3) FROM genres",
+    agg_count_star: "SELECT COUNT(*) FROM movies",
+    agg_expr: "SELECT SUM(rating * 10) / COUNT(*) FROM movies",
+    agg_nested: "SELECT MAX(MIN(rating)) FROM movies",
+    agg_norows: "SELECT MIN(id), MAX(id), SUM(id), COUNT(id), AVG(id) FROM movies WHERE FALSE",
+    agg_norows_group: "SELECT MIN(id), MAX(id), SUM(id), COUNT(id), AVG(id) FROM movies WHERE FALSE GROUP BY id",
+    agg_ungrouped: "SELECT studio_id, COUNT(*) FROM movies",
+
+    group_expr: "SELECT MAX(rating) AS rating FROM movies GROUP BY studio_id * 2 ORDER BY rating",
+    group_expr_aggr: "SELECT studio_id, SUM(rating * 10) / COUNT(*) FROM movies GROUP BY studio_id ORDER BY studio_id",
+    group_expr_aggr_nogroupref: "SELECT studio_id, SUM(rating * 10) / COUNT(*) + id FROM movies GROUP BY studio_id ORDER BY studio_id",
+    group_expr_aggr_selfref: "SELECT studio_id, SUM(rating * 10) / COUNT(*) + studio_id FROM movies GROUP BY studio_id ORDER BY studio_id",
+    group_expr_aliased: "SELECT studio_id * 2 AS twice, MAX(rating) FROM movies GROUP BY twice ORDER BY twice",
+    group_expr_both: "SELECT studio_id * 2, MAX(rating) AS rating FROM movies GROUP BY studio_id * 2 ORDER BY rating",
+    group_expr_extended: "SELECT studio_id * 2 + 1, MAX(rating) AS rating FROM movies GROUP BY studio_id * 2 ORDER BY rating",
+    group_expr_multigroup: "SELECT studio_id + genre_id AS multi, MAX(rating) AS rating FROM movies GROUP BY studio_id, genre_id ORDER BY rating, multi",
+    group_expr_select: "SELECT studio_id * 2, MAX(rating) AS rating FROM movies GROUP BY studio_id ORDER BY rating",
+    group_join: "SELECT s.name, COUNT(*) FROM movies m JOIN studios s ON m.studio_id = s.id GROUP BY s.name ORDER BY s.name ASC",
+    group_noaggregate: "SELECT title FROM movies GROUP BY title ORDER BY title ASC",
+    group_noselect: "SELECT MAX(rating) AS best FROM movies GROUP BY studio_id ORDER BY best DESC",
+    group_simple: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY studio_id",
+    group_star: "SELECT * FROM movies GROUP BY studio_id",
+    group_unknown: "SELECT COUNT(*) FROM movies GROUP BY unknown",
+
+    having: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING rating > 8 ORDER BY studio_id",
+    having_aggr: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id HAVING MIN(rating) > 7 ORDER BY studio_id",
+    having_aggr_expr: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id HAVING MAX(rating) - MIN(rating) < 1 ORDER BY studio_id",
+    having_aggr_nested: "SELECT studio_id, MAX(rating) AS best FROM movies GROUP BY studio_id HAVING MIN(best) > 7 ORDER BY studio_id",
+    having_count: "SELECT genre_id, COUNT(*) FROM movies GROUP BY genre_id HAVING COUNT(*) > 2",
+    having_count_label: "SELECT genre_id, COUNT(*) AS n FROM movies GROUP BY genre_id HAVING n > 2",
+    having_order_label: "SELECT genre_id, COUNT(*) AS n, MAX(rating) FROM movies GROUP BY genre_id HAVING n > 2 ORDER BY n DESC",
+    having_distinct: "SELECT DISTINCT MAX(rating) FROM movies GROUP BY genre_id HAVING COUNT(*) > 1",
+    having_noaggr: "SELECT studio_id, MAX(rating) AS rating FROM movies GROUP BY studio_id HAVING studio_id >= 3 ORDER BY studio_id",
+    having_nogroup: "SELECT id, rating FROM movies HAVING rating > 8 ORDER BY id",
+    having_noselect: "SELECT studio_id FROM movies GROUP BY studio_id HAVING MAX(rating) > 8 ORDER BY studio_id",
+    having_single_group: "SELECT COUNT(*) FROM movies HAVING AVG(rating) > 7",
+    having_sum_avg: "SELECT studio_id, SUM(rating), AVG(rating) FROM movies GROUP BY studio_id HAVING SUM(rating) < AVG(rating) * 2",
+    having_ungrouped: "SELECT studio_id FROM movies GROUP BY studio_id HAVING title = 'Heat'",
+    having_unknown: "SELECT studio_id FROM movies GROUP BY studio_id HAVING COUNT(*) > unknown",
+
+    order_aggregate: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY MAX(rating)",
+    order_aggregate_noselect: "SELECT studio_id, MAX(rating) FROM movies GROUP BY studio_id ORDER BY MIN(rating)",
+    order_group_by_noselect: "SELECT MAX(rating) FROM movies GROUP BY studio_id ORDER BY studio_id",
+    order_noselect: "SELECT title FROM movies ORDER BY released DESC LIMIT 3",
+    order_noselect_distinct: "SELECT DISTINCT genre_id FROM movies ORDER BY released",
+
+    window_row_number: "SELECT title, genre_id, ROW_NUMBER() OVER (PARTITION BY genre_id ORDER BY rating DESC) AS n FROM movies ORDER BY genre_id, n",
+    window_rank: "SELECT released, RANK() OVER (ORDER BY released / 10), DENSE_RANK() OVER (ORDER BY released / 10) FROM movies ORDER BY released",
+    window_lag_lead: "SELECT id, LAG(title) OVER (ORDER BY id), LEAD(title, 2) OVER (ORDER BY id) FROM movies",
+    window_aggregate: "SELECT studio_id, COUNT(*), RANK() OVER (ORDER BY COUNT(*) DESC) FROM movies GROUP BY studio_id ORDER BY studio_id",
+    window_order_noselect: "SELECT title FROM movies ORDER BY ROW_NUMBER() OVER (ORDER BY rating DESC) LIMIT 3",
+    window_where: "SELECT * FROM movies WHERE ROW_NUMBER() OVER () > 1",
+    window_having: "SELECT studio_id FROM movies GROUP BY studio_id HAVING RANK() OVER () > 1",
+    window_nested: "SELECT LAG(ROW_NUMBER() OVER ()) OVER () FROM movies",
+    window_unknown: "SELECT SUM(id) OVER () FROM movies",
+    window_lag_offset: "SELECT LAG(id, 'a') OVER () FROM movies",
+
+    pushdown_join_inner: "SELECT * FROM movies m JOIN genres g ON m.genre_id = g.id WHERE g.id = 1 AND m.rating > 8",
+    pushdown_join_left: "SELECT * FROM genres g LEFT JOIN movies m ON m.genre_id = g.id AND m.rating > 8 WHERE g.id < 3",
+    pushdown_join_left_where: "SELECT * FROM genres g LEFT JOIN movies m ON m.genre_id = g.id AND m.rating > 8 WHERE m.id IS NULL",
+    pushdown_join_implicit: "SELECT * FROM genres g, studios s WHERE g.id = s.id AND s.name LIKE '%a%'",
+}
+test_query! { with [
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, status STRING NOT NULL)",
+        "INSERT INTO orders VALUES (1, 'pending'), (2, 'completed'), (3, 'shipped'), (4, 'completed'), (5, 'pending')",
+        "CREATE INDEX open_orders ON orders (status) WHERE status != 'completed'",
+    ];
+
+    index_partial: "SELECT * FROM orders WHERE status = 'pending'",
+    index_partial_conjunct: "SELECT * FROM orders o WHERE o.id > 1 AND o.status = 'pending'",
+    index_partial_not_implied: "SELECT * FROM orders WHERE status = 'completed'",
+}
+test_query! { with [
+        "CREATE TABLE flags (id INTEGER PRIMARY KEY, active BOOLEAN INDEX, kind STRING INDEX)",
+        "INSERT INTO flags VALUES (1, TRUE, 'a'), (2, TRUE, 'b'), (3, TRUE, 'c'), (4, FALSE, 'd')",
+        "ANALYZE flags",
+    ];
+
+    index_cost_scan: "SELECT * FROM flags WHERE active = TRUE",
+    index_cost_cheapest: "SELECT * FROM flags WHERE active = TRUE AND kind = 'b'",
+}
+test_query! { with [
+        "CREATE TABLE prices (id INTEGER PRIMARY KEY, amount DECIMAL(10, 2) INDEX, rate FLOAT)",
+        "INSERT INTO prices VALUES (1, 0.1, 0.1), (2, 0.2, 0.2), (3, 0.3, 0.3), (4, 100, 1.0)",
+    ];
+
+    decimal_arithmetic: "SELECT * FROM prices WHERE amount + amount + amount = 0.9",
+    decimal_arithmetic_float: "SELECT * FROM prices WHERE rate + rate + rate = 0.9",
+    decimal_divide: "SELECT * FROM prices WHERE amount / 3 = 33.333333 AND amount % 7 = 2 AND -amount ^ 2 = 10000",
+    decimal_compare: "SELECT * FROM prices WHERE amount > 0.15 AND amount < 100",
+    decimal_float_arithmetic: "SELECT * FROM prices WHERE amount + rate > 0",
+    decimal_index: "SELECT * FROM prices WHERE amount = 0.3",
+    decimal_index_rounded: "SELECT * FROM prices WHERE amount = 0.301",
+}
+test_query! { with [
+        "CREATE TABLE events (id INTEGER PRIMARY KEY, at TIMESTAMP INDEX, day DATE)",
+        "INSERT INTO events VALUES (1, '2024-03-10T01:59:59-05:00', '2024-03-10'), (2, '2024-03-10T03:00:00-04:00', '2024-03-10'), (3, '2024-11-03T01:30:00-04:00', '2024-11-03'), (4, '2024-11-03T01:30:00-05:00', '2024-11-03')",
+    ];
+
+    datetime_compare: "SELECT * FROM events WHERE at >= '2024-03-10T07:00:00Z' AND at < '2024-11-03'",
+    datetime_compare_date: "SELECT * FROM events WHERE day = '2024-03-10' AND at > day",
+    datetime_index: "SELECT * FROM events WHERE at = '2024-11-03T01:30:00-04:00'",
+    datetime_subtract: "SELECT * FROM events WHERE at - '2024-03-10T06:59:59Z' = 1000000",
+    datetime_date_trunc: "SELECT * FROM events WHERE date_trunc('hour', at) = '2024-11-03T06:00:00Z'",
+    datetime_date_trunc_invalid: "SELECT * FROM events WHERE date_trunc('fortnight', at) = at",
+    datetime_extract: "SELECT * FROM events WHERE extract(month FROM at) = 11 AND extract(hour FROM at) = 5",
+    datetime_extract_date: "SELECT * FROM events WHERE extract(dow FROM day) = 0 AND extract(doy FROM day) > 300",
+    datetime_extract_date_max: "SELECT extract(year FROM day + 106700000) FROM events",
+    datetime_extract_date_overflow: "SELECT extract(year FROM day + 2000000000) FROM events",
+}
+test_query! { with [
+        "CREATE TABLE scores (id INTEGER PRIMARY KEY, team STRING, points INTEGER)",
+        "INSERT INTO scores VALUES (1, 'b', 3), (2, 'a', NULL), (3, 'b', 5), (4, NULL, 1), (5, 'a', 2), (6, 'b', 3)",
+    ];
+
+    sort_asc_desc: "SELECT * FROM scores ORDER BY team ASC, points DESC",
+    sort_desc_asc: "SELECT * FROM scores ORDER BY team DESC, points",
+    sort_expression: "SELECT * FROM scores ORDER BY points % 2, id DESC",
+    sort_limit_offset: "SELECT * FROM scores ORDER BY points DESC, id LIMIT 3 OFFSET 1",
+    sort_offset: "SELECT * FROM scores ORDER BY id OFFSET 4",
+    sort_limit_zero: "SELECT * FROM scores LIMIT 0",
+    sort_limit_negative: "SELECT * FROM scores LIMIT -1",
+    sort_distinct: "SELECT DISTINCT team, points FROM scores ORDER BY team, points",
+}
+test_query! { with [
+        "CREATE TABLE vals (id INTEGER PRIMARY KEY, v INTEGER, g STRING)",
+        "INSERT INTO vals VALUES (1, 3, 'a')",
+    ];
+
+    project_expr_name_parens: "SELECT v - (1 / -1), (v - 1) / -1, -(v - 1), -v - 1, (v - 1)! FROM vals",
+    project_expr_name_literal: "SELECT g ILIKE 'A', g || 'b''c', v > 1.5, g IS NULL FROM vals",
+    project_expr_name_constant: "SELECT 1 + 2, 1 = NULL, 'abc' LIKE 'a%', CAST('1.25' AS DECIMAL(5,2)) + 1",
+    project_expr_name_aggregate: "SELECT MIN(v), MAX(v) + 1, v * 2 FROM vals GROUP BY v * 2",
+    project_join_self: "SELECT * FROM vals a JOIN vals b ON a.id = b.id",
+    project_join_self_fields: "SELECT a.id, b.v, a.g FROM vals a JOIN vals b ON a.id = b.id",
+}
+
+test_query! { with [
+        "CREATE TABLE tasks (id INTEGER PRIMARY KEY, priority ENUM('low', 'high', 'medium'))",
+        "INSERT INTO tasks VALUES (1, 'medium'), (2, 'low'), (3, NULL), (4, 'high'), (5, 'low')",
+    ];
+
+    sort_enum: "SELECT * FROM tasks ORDER BY priority, id",
+    sort_enum_desc: "SELECT * FROM tasks ORDER BY priority DESC, id",
+    enum_compare: "SELECT * FROM tasks WHERE priority > 'low' AND priority < 'medium'",
+    enum_compare_project: "SELECT id, priority < 'high' FROM tasks",
+    enum_compare_join: "SELECT a.id, b.id FROM tasks a JOIN tasks b ON a.priority < b.priority ORDER BY a.id, b.id",
+    enum_compare_having: "SELECT priority, COUNT(*) FROM tasks GROUP BY priority HAVING priority > 'low'",
+    enum_compare_having_max: "SELECT id % 2, MAX(priority) FROM tasks GROUP BY id % 2 HAVING MAX(priority) < 'medium'",
+    enum_min_max: "SELECT MIN(priority), MAX(priority) FROM tasks",
+}
+
+test_query! { with [
+        "CREATE TABLE scores (id INTEGER PRIMARY KEY, team STRING, points INTEGER)",
+        "INSERT INTO scores VALUES (1, 'b', 3), (2, 'a', NULL), (3, 'b', 5), (4, NULL, 1), (5, 'a', 2)",
+        "CREATE TABLE teams (name STRING PRIMARY KEY, rank FLOAT)",
+        "INSERT INTO teams VALUES ('a', 1.0), ('c', 2.0)",
+    ];
+
+    hash_join_inner: "SELECT * FROM scores s JOIN teams t ON s.team = t.name",
+    hash_join_inner_swapped: "SELECT * FROM teams t JOIN scores s ON s.team = t.name",
+    hash_join_left: "SELECT * FROM scores s LEFT JOIN teams t ON t.name = s.team",
+    hash_join_right: "SELECT * FROM scores s RIGHT JOIN teams t ON s.team = t.name",
+    hash_join_mixed_types: "SELECT * FROM scores s JOIN teams t ON s.points = t.rank",
+    hash_join_cross: "SELECT * FROM scores s CROSS JOIN teams t WHERE s.id < 3",
+}
+test_query! {
+    subquery_scalar: "SELECT title, rating FROM movies WHERE rating > (SELECT AVG(rating) FROM movies)",
+    subquery_scalar_select: "SELECT id, (SELECT name FROM genres WHERE id = 3) AS genre FROM movies WHERE id < 3",
+    subquery_scalar_correlated: "SELECT m.title, (SELECT g.name FROM genres g WHERE g.id = m.genre_id) AS genre FROM movies m WHERE m.id < 4",
+    subquery_scalar_correlated_filter: "SELECT title, rating FROM movies m WHERE rating = (SELECT MAX(rating) FROM movies WHERE genre_id = m.genre_id)",
+    subquery_scalar_empty: "SELECT id, (SELECT id FROM genres WHERE id > 10) FROM movies WHERE id = 1",
+    subquery_scalar_multiple_rows: "SELECT id, (SELECT id FROM genres) FROM movies",
+    subquery_scalar_multiple_columns: "SELECT id, (SELECT id, name FROM genres WHERE id = 1) FROM movies",
+    subquery_in: "SELECT title FROM movies WHERE studio_id IN (SELECT id FROM studios WHERE country_id = 'us')",
+    subquery_in_correlated: "SELECT s.name FROM studios s WHERE 2015 IN (SELECT released FROM movies WHERE studio_id = s.id)",
+    subquery_not_in: "SELECT name FROM genres WHERE id NOT IN (SELECT genre_id FROM movies WHERE released > 2010)",
+    subquery_not_in_null: "SELECT id, id IN (SELECT 1), id NOT IN (SELECT NULL) FROM genres",
+    subquery_in_multiple_columns: "SELECT title FROM movies WHERE id IN (SELECT id, name FROM genres)",
+    subquery_in_list: "SELECT title FROM movies WHERE id IN (1, 2)",
+    subquery_exists: "SELECT name FROM genres WHERE EXISTS (SELECT * FROM movies WHERE released < 1975)",
+    subquery_exists_correlated: "SELECT name FROM studios s WHERE NOT EXISTS (SELECT * FROM movies m WHERE m.studio_id = s.id AND m.rating < 7.5)",
+    subquery_nested: "SELECT name FROM countries WHERE id IN (SELECT country_id FROM studios WHERE id IN (SELECT studio_id FROM movies WHERE rating > 8.5))",
+}
@@ -110,7 +110,7 @@ test_schema! {
     create_table_default_null_not: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING NOT NULL DEFAULT NULL)",
     create_table_default_expr: "CREATE TABLE name (id INTEGER PRIMARY KEY, value INTEGER DEFAULT 1 + 2 * 3)",
     create_table_default_conflict: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING DEFAULT 7)",
-    create_table_default_conflict_float_integer: "CREATE TABLE name (id INTEGER PRIMARY KEY, value FLOAT DEFAULT 7)",
+    create_table_default_float_integer: "CREATE TABLE name (id INTEGER PRIMARY KEY, value FLOAT DEFAULT 7)",
     create_table_default_conflict_integer_float: "CREATE TABLE name (id INTEGER PRIMARY KEY, value INTEGER DEFAULT 3.14)",
 
     create_table_index: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING INDEX)",
@@ -120,4 +120,258 @@ test_schema! {
     create_table_unique_null: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING NULL UNIQUE)",
     create_table_unique_not_null: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING NOT NULL UNIQUE)",
     create_table_unique_default: "CREATE TABLE name (id INTEGER PRIMARY KEY, value STRING DEFAULT 'foo' UNIQUE)",
-}
\ No newline at end of file
+
+    create_table_enum: "CREATE TABLE name (id INTEGER PRIMARY KEY, status ENUM('open', 'closed') DEFAULT 'open')",
+    create_table_enum_default_invalid: "CREATE TABLE name (id INTEGER PRIMARY KEY, status ENUM('open', 'closed') DEFAULT 'pending')",
+    create_table_enum_duplicate: "CREATE TABLE name (id INTEGER PRIMARY KEY, status ENUM('open', 'open'))",
+
+    create_table_decimal: "CREATE TABLE name (id INTEGER PRIMARY KEY, price DECIMAL(10, 2) DEFAULT 1.5, total NUMERIC)",
+    create_table_decimal_precision: "CREATE TABLE name (id INTEGER PRIMARY KEY, price DECIMAL(5))",
+    create_table_decimal_precision_zero: "CREATE TABLE name (id INTEGER PRIMARY KEY, price DECIMAL(0, 0))",
+    create_table_decimal_precision_large: "CREATE TABLE name (id INTEGER PRIMARY KEY, price DECIMAL(39, 2))",
+    create_table_decimal_scale_large: "CREATE TABLE name (id INTEGER PRIMARY KEY, price DECIMAL(4, 5))",
+    create_table_decimal_default_overflow: "CREATE TABLE name (id INTEGER PRIMARY KEY, price DECIMAL(3, 2) DEFAULT 10)",
+    create_table_datetime: "CREATE TABLE name (id INTEGER PRIMARY KEY, created TIMESTAMP DEFAULT '2024-01-01T00:00:00+01:00', due DATE DEFAULT '2024-12-31')",
+    create_table_datetime_default_invalid: "CREATE TABLE name (id INTEGER PRIMARY KEY, due DATE DEFAULT '2023-02-29')",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, status ENUM('open', 'closed') NOT NULL, name STRING)",
+        "INSERT INTO test VALUES (1, 'open', 'a'), (2, 'closed', 'b')",
+    ];
+
+    alter_table_enum_add: "ALTER TABLE test ALTER COLUMN status ADD 'pending'",
+    alter_table_enum_add_existing: "ALTER TABLE test ALTER COLUMN status ADD 'open'",
+    alter_table_enum_add_not_enum: "ALTER TABLE test ALTER COLUMN name ADD 'c'",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value FLOAT)",
+        "INSERT INTO test VALUES (1, 'a', 1.5), (2, 'b', NULL), (3, 'c', 3.5)",
+    ];
+
+    select_into: "SELECT * INTO copy FROM test WHERE id > 1",
+    select_into_empty: "SELECT * INTO copy FROM test WHERE id > 3",
+    select_into_exists: "SELECT * INTO test FROM test",
+    select_into_primary_key: "SELECT value, name INTO copy PRIMARY KEY name FROM test",
+    select_into_primary_key_missing: "SELECT value INTO copy PRIMARY KEY name FROM test",
+    analyze: "ANALYZE test",
+    analyze_missing: "ANALYZE missing",
+    drop_extension_missing: "DROP EXTENSION missing",
+    load_extension_identifier: "LOAD EXTENSION missing",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value FLOAT)",
+        "INSERT INTO test VALUES (1, 'a', 1.5), (2, 'b', NULL)",
+        "SELECT * INTO copy FROM test",
+    ];
+
+    select_into_insert: "INSERT INTO copy VALUES (3, 'c', 3.5)",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value FLOAT)",
+        "INSERT INTO test VALUES (1, 'a', 1.5), (2, 'b', NULL)",
+    ];
+
+    create_materialized_view: "CREATE MATERIALIZED VIEW recent AS SELECT * FROM test WHERE id > 1",
+    create_materialized_view_exists: "CREATE MATERIALIZED VIEW test AS SELECT * FROM test",
+    refresh_materialized_view_table: "REFRESH MATERIALIZED VIEW test",
+    drop_materialized_view_table: "DROP MATERIALIZED VIEW test",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value FLOAT)",
+        "INSERT INTO test VALUES (1, 'a', 1.5), (2, 'b', NULL)",
+        "CREATE MATERIALIZED VIEW recent AS SELECT * FROM test WHERE id > 1",
+        "INSERT INTO test VALUES (3, 'c', 3.5)",
+        "UPDATE test SET name = 'B' WHERE id = 2",
+    ];
+
+    refresh_materialized_view: "REFRESH MATERIALIZED VIEW recent",
+    refresh_materialized_view_missing: "REFRESH MATERIALIZED VIEW missing",
+    drop_materialized_view: "DROP MATERIALIZED VIEW recent",
+    drop_table_materialized_view: "DROP TABLE recent",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value FLOAT)",
+        "INSERT INTO test VALUES (1, 'a', 1.5), (2, 'b', NULL)",
+        "CREATE MATERIALIZED VIEW names AS SELECT id, name FROM test WHERE id > 1;",
+        "ALTER TABLE test DROP COLUMN value",
+        "INSERT INTO test VALUES (3, 'c')",
+    ];
+
+    refresh_materialized_view_dropped_column: "REFRESH MATERIALIZED VIEW names",
+}
+
+test_schema! { with [
+        "CREATE TABLE other (id INTEGER PRIMARY KEY)",
+        "INSERT INTO other VALUES (1), (2)",
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER, other_id INTEGER)",
+        "INSERT INTO test VALUES (1, 1, 1), (2, 2, 3), (3, 2, NULL), (4, 3, 4), (5, 3, 4), (6, -1, 2)",
+    ];
+
+    add_constraint_check: "ALTER TABLE test ADD CONSTRAINT positive CHECK (value > -2)",
+    add_constraint_check_violated: "ALTER TABLE test ADD CONSTRAINT positive CHECK (value > 0)",
+    add_constraint_unique_duplicates: "ALTER TABLE test ADD CONSTRAINT unique_value UNIQUE (value)",
+    add_constraint_unique_missing_column: "ALTER TABLE test ADD CONSTRAINT unique_value UNIQUE (missing)",
+    add_constraint_foreign_key_violated: "ALTER TABLE test ADD CONSTRAINT fk FOREIGN KEY (other_id) REFERENCES other(id)",
+    add_constraint_foreign_key_not_primary: "ALTER TABLE test ADD CONSTRAINT fk FOREIGN KEY (other_id) REFERENCES test(value)",
+    drop_constraint_missing: "ALTER TABLE test DROP CONSTRAINT missing",
+}
+
+test_schema! { with [
+        "CREATE TABLE other (id INTEGER PRIMARY KEY)",
+        "INSERT INTO other VALUES (1), (2)",
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER, other_id INTEGER)",
+        "INSERT INTO test VALUES (1, 1, 1), (2, 2, 2), (3, 2, NULL), (4, 3, 2)",
+        "DELETE FROM test WHERE id = 3",
+        "ALTER TABLE test ADD CONSTRAINT unique_value UNIQUE (value)",
+        "ALTER TABLE test ADD CONSTRAINT positive CHECK (value > 0)",
+        "ALTER TABLE test ADD CONSTRAINT fk FOREIGN KEY (other_id) REFERENCES other",
+    ];
+
+    add_constraint_exists: "ALTER TABLE test ADD CONSTRAINT positive CHECK (value > 1)",
+    add_constraint_unique_insert: "INSERT INTO test VALUES (5, 1, NULL)",
+    add_constraint_check_insert: "INSERT INTO test VALUES (5, 0, NULL)",
+    add_constraint_foreign_key_insert: "INSERT INTO test VALUES (5, 5, 3)",
+    add_constraint_foreign_key_delete: "DELETE FROM other WHERE id = 2",
+    drop_constraint: "ALTER TABLE test DROP CONSTRAINT unique_value",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL INDEX, value INTEGER)",
+        "INSERT INTO test VALUES (1, 'a', 1), (2, 'b', 2)",
+        "ALTER TABLE test ADD CONSTRAINT positive CHECK (value > 0)",
+        "ALTER TABLE test ADD COLUMN flag BOOLEAN DEFAULT FALSE",
+        "INSERT INTO test VALUES (3, 'c', 3, TRUE)",
+    ];
+
+    add_column: "ALTER TABLE test ADD COLUMN score FLOAT DEFAULT 1.5",
+    add_column_exists: "ALTER TABLE test ADD COLUMN name STRING DEFAULT 'x'",
+    add_column_no_default: "ALTER TABLE test ADD COLUMN score FLOAT NOT NULL",
+    add_column_unique: "ALTER TABLE test ADD COLUMN score FLOAT UNIQUE DEFAULT NULL",
+    drop_column: "ALTER TABLE test DROP COLUMN name",
+    drop_column_added: "ALTER TABLE test DROP flag",
+    drop_column_constraint: "ALTER TABLE test DROP COLUMN value",
+    drop_column_missing: "ALTER TABLE test DROP COLUMN missing",
+    drop_column_primary_key: "ALTER TABLE test DROP COLUMN id",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING NOT NULL, value INTEGER)",
+        "INSERT INTO test VALUES (1, 'a', 1), (2, 'b', 2)",
+        "ALTER TABLE test DROP COLUMN name",
+        "INSERT INTO test VALUES (3, 3)",
+        "ALTER TABLE test ADD COLUMN name STRING DEFAULT 'x'",
+    ];
+
+    drop_column_readd_insert: "INSERT INTO test VALUES (4, 4, 'd')",
+    drop_column_readd_update: "UPDATE test SET name = 'y' WHERE id = 1",
+    drop_column_check: "ALTER TABLE test ADD CONSTRAINT positive CHECK (value > 0)",
+}
+
+test_schema! { with [
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, status STRING NOT NULL)",
+        "INSERT INTO orders VALUES (1, 'pending'), (2, 'completed'), (3, 'shipped'), (4, 'completed'), (5, 'pending')",
+    ];
+
+    create_index: "CREATE INDEX ON orders (status)",
+    create_index_partial: "CREATE INDEX open_orders ON orders (status) WHERE status != 'completed'",
+    create_index_primary_key: "CREATE INDEX ON orders (id)",
+    create_index_missing_column: "CREATE INDEX ON orders (missing)",
+    create_index_predicate_missing_column: "CREATE INDEX ON orders (status) WHERE missing = 1",
+}
+
+test_schema! {
+    create_table_serial: "CREATE TABLE test (id SERIAL PRIMARY KEY, n INTEGER AUTO_INCREMENT)",
+    create_table_serial_default: "CREATE TABLE test (id SERIAL PRIMARY KEY DEFAULT 1)",
+    create_table_serial_nullable: "CREATE TABLE test (id INTEGER PRIMARY KEY, n SERIAL NULL)",
+    create_table_auto_increment_string: "CREATE TABLE test (id STRING PRIMARY KEY AUTO_INCREMENT)",
+}
+
+test_schema! { with [
+        "CREATE TABLE test (id SERIAL PRIMARY KEY, name STRING NOT NULL)",
+        "INSERT INTO test (name) VALUES ('a'), ('b')",
+    ];
+
+    reset_sequence: "ALTER TABLE test ALTER COLUMN id RESET SEQUENCE TO 10",
+    reset_sequence_not_serial: "ALTER TABLE test ALTER name RESET SEQUENCE TO 10",
+    reset_sequence_string: "ALTER TABLE test ALTER id RESET SEQUENCE TO 'a'",
+    reset_sequence_missing_column: "ALTER TABLE test ALTER missing RESET SEQUENCE TO 10",
+}
+
+test_schema! {
+    create_table_check: "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER CHECK (value > 0) CHECK (value < 10), CHECK (value != id))",
+    create_table_check_named: "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER, CONSTRAINT small CHECK (value < 10), UNIQUE (value))",
+    create_table_check_missing_column: "CREATE TABLE test (id INTEGER PRIMARY KEY, CHECK (missing > 0))",
+    create_table_check_not_boolean: "CREATE TABLE test (id INTEGER PRIMARY KEY, value INTEGER CHECK (value + 1))",
+    create_table_check_mismatch: "CREATE TABLE test (id INTEGER PRIMARY KEY, name STRING CHECK (name > 1))",
+    create_table_check_decimal_float: "CREATE TABLE test (id INTEGER PRIMARY KEY, price DECIMAL(10, 2) CHECK (price < 1.5))",
+    create_table_check_date_string: "CREATE TABLE test (id INTEGER PRIMARY KEY, day DATE CHECK (day >= '2000-01-01'))",
+}
+
+test_schema! { with [
+        "CREATE TABLE people (id INTEGER PRIMARY KEY, name STRING CHECK (name IS NOT NULL), age INTEGER CHECK (age > 0 AND age < 150), start_date DATE, end_date DATE, CHECK (end_date > start_date))",
+        "INSERT INTO people VALUES (1, 'a', 30, '2020-01-01', '2021-01-01')",
+    ];
+
+    check_insert: "INSERT INTO people VALUES (2, 'b', 40, '2020-01-01', '2020-06-01')",
+    check_insert_not_null: "INSERT INTO people VALUES (2, NULL, 40, NULL, NULL)",
+    check_insert_range: "INSERT INTO people VALUES (2, 'b', 150, NULL, NULL)",
+    check_insert_dates: "INSERT INTO people VALUES (2, 'b', 40, '2020-01-01', '2019-01-01')",
+    check_insert_null: "INSERT INTO people VALUES (2, 'b', NULL, '2020-01-01', NULL)",
+    check_update: "UPDATE people SET age = 0 WHERE id = 1",
+    check_drop_constraint: "ALTER TABLE people DROP CONSTRAINT people_age_check",
+}
+
+test_schema! { with [
+        "CREATE TABLE orders (id INTEGER PRIMARY KEY, status STRING NOT NULL INDEX)",
+    ];
+
+    create_index_exists: "CREATE INDEX ON orders (status)",
+}
+
+test_schema! { with [
+        "CREATE TABLE parent (id INTEGER PRIMARY KEY, name STRING)",
+        "INSERT INTO parent VALUES (0, 'default'), (1, 'a'), (2, 'b'), (3, 'c')",
+        "CREATE TABLE restricted (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES parent ON DELETE RESTRICT ON UPDATE RESTRICT)",
+        "CREATE TABLE cascaded (id INTEGER PRIMARY KEY, parent_id INTEGER, FOREIGN KEY (parent_id) REFERENCES parent ON DELETE CASCADE ON UPDATE CASCADE)",
+        "CREATE TABLE nulled (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES parent ON UPDATE SET NULL ON DELETE SET NULL)",
+        "CREATE TABLE defaulted (id INTEGER PRIMARY KEY, parent_id INTEGER DEFAULT 0 REFERENCES parent ON DELETE SET DEFAULT ON UPDATE SET DEFAULT)",
+        "INSERT INTO restricted VALUES (1, 1)",
+        "INSERT INTO cascaded VALUES (1, 2), (2, 2), (3, 3)",
+        "INSERT INTO nulled VALUES (1, 2), (2, 3)",
+        "INSERT INTO defaulted VALUES (1, 2), (2, 3)",
+    ];
+
+    foreign_key_delete_restrict: "DELETE FROM parent WHERE id = 1",
+    foreign_key_delete_actions: "DELETE FROM parent WHERE id = 2",
+    foreign_key_delete_default_missing: "DELETE FROM parent WHERE id = 0 OR id = 2",
+    foreign_key_update_restrict: "UPDATE parent SET id = 5 WHERE id = 1",
+    foreign_key_update_actions: "UPDATE parent SET id = 5 WHERE id = 2",
+    foreign_key_update_unchanged: "UPDATE parent SET name = 'x' WHERE id = 2",
+    foreign_key_insert_missing: "INSERT INTO cascaded VALUES (4, 9)",
+    foreign_key_add_constraint: "ALTER TABLE restricted ADD CONSTRAINT fk FOREIGN KEY (id) REFERENCES parent (id) ON DELETE CASCADE",
+    foreign_key_set_null_not_nullable: "CREATE TABLE test (id INTEGER PRIMARY KEY, parent_id INTEGER NOT NULL REFERENCES parent ON DELETE SET NULL)",
+    foreign_key_set_default_missing: "CREATE TABLE test (id INTEGER PRIMARY KEY, parent_id INTEGER NOT NULL REFERENCES parent ON UPDATE SET DEFAULT)",
+    foreign_key_add_column_actions: "ALTER TABLE parent ADD COLUMN other_id INTEGER REFERENCES parent ON DELETE CASCADE",
+}
+
+test_schema! { with [
+        "CREATE TABLE a (id INTEGER PRIMARY KEY, b_id INTEGER NOT NULL)",
+        "CREATE TABLE b (id INTEGER PRIMARY KEY, a_id INTEGER NOT NULL REFERENCES a ON DELETE CASCADE ON UPDATE CASCADE)",
+        "ALTER TABLE a ADD CONSTRAINT a_b_fkey FOREIGN KEY (b_id) REFERENCES b ON DELETE CASCADE ON UPDATE CASCADE",
+        "INSERT INTO a VALUES (1, 1), (2, 2)",
+        "INSERT INTO b VALUES (1, 1), (2, 2)",
+        "CREATE TABLE tree (id INTEGER PRIMARY KEY, parent_id INTEGER REFERENCES tree ON DELETE CASCADE)",
+        "INSERT INTO tree VALUES (1, NULL), (2, 1), (3, 2), (4, 1), (5, NULL)",
+    ];
+
+    foreign_key_circular_delete: "DELETE FROM a WHERE id = 1",
+    foreign_key_circular_insert_missing: "INSERT INTO a VALUES (3, 3)",
+    foreign_key_circular_update: "UPDATE b SET id = 3 WHERE id = 1",
+    foreign_key_self_delete: "DELETE FROM tree WHERE id = 1",
+}
@@ -1,2 +1,3 @@
 mod sql;
-mod raft;
\ No newline at end of file
+mod raft;
+mod server;
\ No newline at end of file